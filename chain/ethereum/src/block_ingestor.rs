@@ -1,5 +1,8 @@
 use lazy_static;
-use std::time::Duration;
+use std::collections::HashMap;
+use std::ops::Range;
+use std::str::FromStr;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 use graph::prelude::*;
 use web3::types::*;
@@ -11,6 +14,146 @@ lazy_static! {
         .ok()
         .map(|s| s.eq_ignore_ascii_case("true"))
         .unwrap_or(false);
+
+    /// Which blocks to keep when `GRAPH_ETHEREUM_CLEANUP_BLOCKS` is set
+    static ref BLOCK_RETENTION: RetentionPolicy = std::env::var("GRAPH_ETHEREUM_BLOCK_RETENTION")
+        .ok()
+        .map(|s| s.parse().expect("invalid GRAPH_ETHEREUM_BLOCK_RETENTION"))
+        .unwrap_or(RetentionPolicy::Referenced);
+
+    /// How many confirmations make a block final on each network. Blocks
+    /// that are not on the main chain are purged once their height is final
+    static ref FINALITY_DEPTHS: FinalityDepths = std::env::var("GRAPH_ETHEREUM_FINALITY_DEPTH")
+        .ok()
+        .map(|s| s.parse().expect("invalid GRAPH_ETHEREUM_FINALITY_DEPTH"))
+        .unwrap_or_default();
+
+    /// Store gas statistics for each ingested block so that mappings can
+    /// look them up with `ethereum.feeHistory`
+    pub static ref FEE_HISTORY: bool = std::env::var("GRAPH_ETHEREUM_FEE_HISTORY")
+        .ok()
+        .map(|s| s.eq_ignore_ascii_case("true"))
+        .unwrap_or(false);
+
+    /// How many blocks we fetch from the Ethereum node at the same time
+    /// when the chain head in the store is behind the node
+    static ref BLOCK_INGESTOR_CONCURRENCY: usize =
+        std::env::var("GRAPH_ETHEREUM_BLOCK_INGESTOR_CONCURRENCY")
+            .ok()
+            .map(|s| s.parse().expect("invalid GRAPH_ETHEREUM_BLOCK_INGESTOR_CONCURRENCY"))
+            .unwrap_or(10);
+
+    /// Remove calls from the `eth_call` cache that were last used more
+    /// than this many days ago
+    static ref CALL_CACHE_MAX_AGE_DAYS: Option<u32> =
+        std::env::var("GRAPH_ETH_CALL_CACHE_MAX_AGE_DAYS")
+            .ok()
+            .map(|s| s.parse().expect("invalid GRAPH_ETH_CALL_CACHE_MAX_AGE_DAYS"));
+
+    /// Keep at most this many calls in the `eth_call` cache, removing the
+    /// ones that were used the longest time ago
+    static ref CALL_CACHE_MAX_ENTRIES: Option<u64> =
+        std::env::var("GRAPH_ETH_CALL_CACHE_MAX_ENTRIES")
+            .ok()
+            .map(|s| s.parse().expect("invalid GRAPH_ETH_CALL_CACHE_MAX_ENTRIES"));
+}
+
+/// How often we remove calls from the `eth_call` cache when it is limited
+/// by `GRAPH_ETH_CALL_CACHE_MAX_AGE_DAYS` or `GRAPH_ETH_CALL_CACHE_MAX_ENTRIES`
+const CALL_CACHE_CLEANUP_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// How `GRAPH_ETHEREUM_BLOCK_RETENTION` says to prune the block cache
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum RetentionPolicy {
+    /// `referenced`: keep the blocks that deployments still need
+    Referenced,
+    /// `last:<count>`: keep the `count` latest blocks
+    Last(u64),
+    /// `max_age:<seconds>`: keep blocks that are at most `seconds` old
+    MaxAge(u64),
+}
+
+impl RetentionPolicy {
+    fn retention(&self) -> BlockRetention {
+        match self {
+            RetentionPolicy::Referenced => BlockRetention::KeepReferenced,
+            RetentionPolicy::Last(count) => BlockRetention::KeepLast(*count),
+            RetentionPolicy::MaxAge(seconds) => {
+                let now = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map(|now| now.as_secs())
+                    .unwrap_or(0);
+                BlockRetention::KeepSince(now.saturating_sub(*seconds))
+            }
+        }
+    }
+}
+
+impl FromStr for RetentionPolicy {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.trim().splitn(2, ':');
+        let kind = parts.next().unwrap_or("");
+        let arg = parts.next().map(|arg| arg.trim().parse::<u64>());
+        match (kind, arg) {
+            ("referenced", None) => Ok(RetentionPolicy::Referenced),
+            ("last", Some(Ok(count))) => Ok(RetentionPolicy::Last(count)),
+            ("max_age", Some(Ok(seconds))) => Ok(RetentionPolicy::MaxAge(seconds)),
+            _ => Err(anyhow!(
+                "expected `referenced`, `last:<count>` or `max_age:<seconds>` but got `{}`",
+                s
+            )),
+        }
+    }
+}
+
+/// The confirmation depths from `GRAPH_ETHEREUM_FINALITY_DEPTH`, a
+/// comma-separated list of `<network>:<depth>` entries and optionally a
+/// plain `<depth>` for all other networks
+#[derive(Clone, Debug, Default, PartialEq)]
+struct FinalityDepths {
+    default: Option<u64>,
+    networks: HashMap<String, u64>,
+}
+
+impl FinalityDepths {
+    fn depth(&self, network: &str) -> Option<u64> {
+        self.networks.get(network).cloned().or(self.default)
+    }
+}
+
+impl FromStr for FinalityDepths {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut depths = FinalityDepths::default();
+        for entry in s
+            .split(',')
+            .map(str::trim)
+            .filter(|entry| !entry.is_empty())
+        {
+            let invalid = || {
+                anyhow!(
+                    "expected `<depth>` or `<network>:<depth>` but got `{}`",
+                    entry
+                )
+            };
+            let mut parts = entry.rsplitn(2, ':');
+            let depth = parts
+                .next()
+                .and_then(|depth| depth.trim().parse::<u64>().ok())
+                .ok_or_else(invalid)?;
+            match parts.next().map(str::trim) {
+                Some("") => return Err(invalid()),
+                Some(network) => {
+                    depths.networks.insert(network.to_owned(), depth);
+                }
+                None => depths.default = Some(depth),
+            }
+        }
+        Ok(depths)
+    }
 }
 
 pub struct BlockIngestorMetrics {
@@ -44,7 +187,7 @@ where
     chain_store: Arc<S>,
     eth_adapter: Arc<dyn EthereumAdapter>,
     ancestor_count: u64,
-    _network_name: String,
+    network_name: String,
     logger: Logger,
     polling_interval: Duration,
 }
@@ -76,13 +219,14 @@ where
             chain_store,
             eth_adapter,
             ancestor_count,
-            _network_name: network_name,
+            network_name,
             logger,
             polling_interval,
         })
     }
 
     pub async fn into_polling_stream(self) {
+        let mut last_call_cache_cleanup: Option<Instant> = None;
         loop {
             match self.do_poll().await {
                 // Some polls will fail due to transient issues
@@ -102,16 +246,47 @@ where
                 Ok(()) => (),
             }
 
+            if let Some(depth) = FINALITY_DEPTHS.depth(&self.network_name) {
+                self.finalize_blocks(depth)
+            }
+
             if *CLEANUP_BLOCKS {
                 self.cleanup_cached_blocks()
             }
 
+            if (CALL_CACHE_MAX_AGE_DAYS.is_some() || CALL_CACHE_MAX_ENTRIES.is_some())
+                && last_call_cache_cleanup
+                    .map_or(true, |last| last.elapsed() >= CALL_CACHE_CLEANUP_INTERVAL)
+            {
+                self.cleanup_call_cache();
+                last_call_cache_cleanup = Some(Instant::now());
+            }
+
             tokio::time::delay_for(self.polling_interval).await;
         }
     }
 
+    fn finalize_blocks(&self, depth: u64) {
+        match self.chain_store.finalize_blocks(depth) {
+            Ok(Some((watermark, purged))) => {
+                if purged > 0 {
+                    info!(
+                        self.logger,
+                        "Purged {} blocks that are not on the main chain", purged;
+                        "finalized_block_number" => watermark
+                    );
+                }
+            }
+            Ok(None) => {}
+            Err(e) => warn!(self.logger, "Failed to finalize blocks: {}", e),
+        }
+    }
+
     fn cleanup_cached_blocks(&self) {
-        match self.chain_store.cleanup_cached_blocks(self.ancestor_count) {
+        match self
+            .chain_store
+            .cleanup_cached_blocks(self.ancestor_count, BLOCK_RETENTION.retention())
+        {
             Ok((min_block, count)) => {
                 if count > 0 {
                     info!(
@@ -130,6 +305,20 @@ where
         }
     }
 
+    fn cleanup_call_cache(&self) {
+        match self
+            .chain_store
+            .cleanup_call_cache(*CALL_CACHE_MAX_AGE_DAYS, *CALL_CACHE_MAX_ENTRIES)
+        {
+            Ok(count) => {
+                if count > 0 {
+                    info!(self.logger, "Removed {} calls from the call cache", count);
+                }
+            }
+            Err(e) => warn!(self.logger, "Failed to clean the call cache: {}", e),
+        }
+    }
+
     async fn do_poll(&self) -> Result<(), EthereumAdapterError> {
         trace!(self.logger, "BlockIngestor::do_poll");
 
@@ -154,13 +343,14 @@ where
         let latest_block = self.eth_adapter.latest_block(&self.logger).compat().await?;
 
         // Compare latest block with head ptr, alert user if far behind
-        match head_block_ptr_opt {
+        let behind = match head_block_ptr_opt {
             None => {
                 info!(
                     self.logger,
                     "Downloading latest blocks from Ethereum. \
                                     This may take a few minutes..."
                 );
+                0..0
             }
             Some(head_block_ptr) => {
                 let latest_number = latest_block.number.unwrap().as_u64() as i64;
@@ -184,14 +374,29 @@ where
                         "code" => code,
                     );
                 }
+                let first_number = latest_number - blocks_needed.max(0) + 1;
+                (first_number.min(latest_number) as u64)..(latest_number as u64)
             }
+        };
+
+        // Fetch the blocks between our chain head and the latest block
+        // concurrently rather than one parent at a time, and write them in
+        // the order of their numbers. If any of them are on a different
+        // branch than the latest block, ingesting the latest block below
+        // fetches the right ones
+        if !behind.is_empty() {
+            let blocks = self.get_blocks_by_number(behind);
+            self.ingest_blocks(blocks).await?;
         }
 
-        let latest_block = self
-            .eth_adapter
-            .load_full_block(&self.logger, latest_block)
-            .compat()
-            .await?;
+        let latest_block = load_full_block(
+            self.logger.clone(),
+            self.eth_adapter.clone(),
+            self.chain_store.clone(),
+            latest_block,
+        )
+        .compat()
+        .await?;
 
         // Store latest block in block store.
         // Might be a no-op if latest block is one that we have seen.
@@ -234,7 +439,23 @@ where
         &self,
         blocks: B,
     ) -> Result<Vec<H256>, EthereumAdapterError> {
-        self.chain_store.upsert_blocks(blocks).compat().await?;
+        if *FEE_HISTORY {
+            let blocks: Vec<EthereumBlock> = blocks.collect().compat().await?;
+            let stats = blocks
+                .iter()
+                .map(|block| BlockFeeStats::from(&block.block))
+                .collect();
+            self.chain_store.upsert_fee_stats(stats).map_err(|e| {
+                error!(self.logger, "failed to store gas statistics");
+                EthereumAdapterError::Unknown(e)
+            })?;
+            self.chain_store
+                .upsert_blocks(stream::iter_ok::<_, EthereumAdapterError>(blocks))
+                .compat()
+                .await?;
+        } else {
+            self.chain_store.upsert_blocks(blocks).compat().await?;
+        }
 
         self.chain_store
             .attempt_chain_head_update(self.ancestor_count)
@@ -244,6 +465,41 @@ where
             })
     }
 
+    /// Request the blocks with the given `numbers` via web3, with up to
+    /// `GRAPH_ETHEREUM_BLOCK_INGESTOR_CONCURRENCY` requests in flight, and
+    /// return them in a stream ordered by block number. Blocks that the
+    /// Ethereum node does not know about are left out
+    fn get_blocks_by_number(
+        &self,
+        numbers: Range<u64>,
+    ) -> Box<dyn Stream<Item = EthereumBlock, Error = EthereumAdapterError> + Send + 'static> {
+        let logger = self.logger.clone();
+        let eth_adapter = self.eth_adapter.clone();
+        let chain_store = self.chain_store.clone();
+
+        let block_futures = stream::iter_ok(numbers).map(move |number| {
+            let logger = logger.clone();
+            let eth_adapter = eth_adapter.clone();
+            let chain_store = chain_store.clone();
+
+            eth_adapter
+                .block_by_number(&logger, number)
+                .from_err()
+                .and_then(move |block_opt| match block_opt {
+                    Some(block) => future::Either::A(
+                        load_full_block(logger, eth_adapter, chain_store, block).map(Some),
+                    ),
+                    None => future::Either::B(future::ok(None)),
+                })
+        });
+
+        Box::new(
+            block_futures
+                .buffered(*BLOCK_INGESTOR_CONCURRENCY)
+                .filter_map(|block| block),
+        )
+    }
+
     /// Requests the specified blocks via web3, returning them in a stream (potentially out of
     /// order).
     fn get_blocks(
@@ -252,10 +508,12 @@ where
     ) -> Box<dyn Stream<Item = EthereumBlock, Error = EthereumAdapterError> + Send + 'static> {
         let logger = self.logger.clone();
         let eth_adapter = self.eth_adapter.clone();
+        let chain_store = self.chain_store.clone();
 
         let block_futures = block_hashes.iter().map(move |&block_hash| {
             let logger = logger.clone();
             let eth_adapter = eth_adapter.clone();
+            let chain_store = chain_store.clone();
 
             eth_adapter
                 .block_by_hash(&logger, block_hash)
@@ -263,9 +521,125 @@ where
                 .and_then(move |block_opt| {
                     block_opt.ok_or_else(|| EthereumAdapterError::BlockUnavailable(block_hash))
                 })
-                .and_then(move |block| eth_adapter.load_full_block(&logger, block))
+                .and_then(move |block| load_full_block(logger, eth_adapter, chain_store, block))
         });
 
         Box::new(stream::futures_unordered(block_futures))
     }
 }
+
+/// Load the transaction receipts for `block`. If `chain_store` already has
+/// all of them, they are taken from there; otherwise, they are requested
+/// from the Ethereum node and added to `chain_store` so that they can be
+/// reused
+fn load_full_block<S: ChainStore>(
+    logger: Logger,
+    eth_adapter: Arc<dyn EthereumAdapter>,
+    chain_store: Arc<S>,
+    block: LightEthereumBlock,
+) -> Box<dyn Future<Item = EthereumBlock, Error = EthereumAdapterError> + Send> {
+    let block_hash = block.hash.expect("block is missing block hash");
+    if !block.transactions.is_empty() {
+        match chain_store.receipts_for_block(block_hash) {
+            Ok(receipts) if receipts.len() == block.transactions.len() => {
+                return Box::new(future::ok(EthereumBlock {
+                    block,
+                    transaction_receipts: receipts,
+                }));
+            }
+            Ok(_) => (),
+            Err(e) => warn!(logger, "Failed to look up stored transaction receipts";
+                            "block_hash" => format!("{:x}", block_hash),
+                            "error" => e.to_string()),
+        }
+    }
+
+    Box::new(
+        eth_adapter
+            .load_full_block(&logger, block)
+            .and_then(move |block| {
+                if !block.transaction_receipts.is_empty() {
+                    chain_store
+                        .upsert_receipts(block.transaction_receipts.clone())
+                        .map_err(EthereumAdapterError::Unknown)?;
+                }
+                Ok(block)
+            }),
+    )
+}
+
+/// Turn the blocks among `block_hashes` that `chain_store` only has as
+/// light blocks into full blocks by loading their transaction receipts and
+/// merging them into the stored blocks. Resolves to the number of blocks
+/// that were turned into full blocks; blocks that are not in the block
+/// cache at all are ignored
+pub fn hydrate_blocks<S: ChainStore>(
+    logger: Logger,
+    eth_adapter: Arc<dyn EthereumAdapter>,
+    chain_store: Arc<S>,
+    block_hashes: Vec<H256>,
+) -> Box<dyn Future<Item = usize, Error = EthereumAdapterError> + Send> {
+    let light_blocks = match chain_store.light_blocks(block_hashes) {
+        Ok(blocks) => blocks,
+        Err(e) => return Box::new(future::err(EthereumAdapterError::Unknown(e))),
+    };
+    if light_blocks.is_empty() {
+        return Box::new(future::ok(0));
+    }
+    debug!(logger, "Hydrating light blocks"; "count" => light_blocks.len());
+
+    let full_blocks = light_blocks
+        .into_iter()
+        .map(|block| eth_adapter.load_full_block(&logger, block));
+    Box::new(
+        stream::futures_unordered(full_blocks)
+            .collect()
+            .and_then(move |blocks| {
+                chain_store
+                    .hydrate_blocks(blocks)
+                    .map_err(EthereumAdapterError::Unknown)
+            }),
+    )
+}
+
+#[test]
+fn retention_policy() {
+    assert_eq!(
+        RetentionPolicy::Referenced,
+        "referenced".parse::<RetentionPolicy>().unwrap()
+    );
+    assert_eq!(
+        RetentionPolicy::Last(1000),
+        "last:1000".parse::<RetentionPolicy>().unwrap()
+    );
+    assert_eq!(
+        RetentionPolicy::MaxAge(86400),
+        "max_age:86400".parse::<RetentionPolicy>().unwrap()
+    );
+    for invalid in &[
+        "",
+        "last",
+        "last:many",
+        "max_age:-1",
+        "referenced:10",
+        "all",
+    ] {
+        assert!(invalid.parse::<RetentionPolicy>().is_err(), "{}", invalid);
+    }
+}
+
+#[test]
+fn finality_depths() {
+    let depths: FinalityDepths = "200, xdai:50,poa-core : 20".parse().unwrap();
+    assert_eq!(Some(200), depths.depth("mainnet"));
+    assert_eq!(Some(50), depths.depth("xdai"));
+    assert_eq!(Some(20), depths.depth("poa-core"));
+
+    let depths: FinalityDepths = "xdai:50".parse().unwrap();
+    assert_eq!(None, depths.depth("mainnet"));
+    assert_eq!(Some(50), depths.depth("xdai"));
+
+    for invalid in &["many", "xdai:", ":50", "xdai:-1"] {
+        assert!(invalid.parse::<FinalityDepths>().is_err(), "{}", invalid);
+    }
+}
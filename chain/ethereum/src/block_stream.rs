@@ -87,6 +87,9 @@ struct BlockStreamContext<S, C> {
     node_id: NodeId,
     subgraph_id: SubgraphDeploymentId,
     reorg_threshold: u64,
+    /// Only process blocks that are at least this many blocks behind the
+    /// chain head
+    confirmations: u64,
     log_filter: EthereumLogFilter,
     call_filter: EthereumCallFilter,
     block_filter: EthereumBlockFilter,
@@ -94,7 +97,9 @@ struct BlockStreamContext<S, C> {
     include_calls_in_blocks: bool,
     logger: Logger,
     metrics: Arc<BlockStreamMetrics>,
+    memory: Arc<DeploymentMemory>,
     previous_triggers_per_block: f64,
+    previous_bytes_per_block: f64,
     previous_block_range_size: u64,
     max_block_range_size: u64,
 }
@@ -108,6 +113,7 @@ impl<S, C> Clone for BlockStreamContext<S, C> {
             node_id: self.node_id.clone(),
             subgraph_id: self.subgraph_id.clone(),
             reorg_threshold: self.reorg_threshold,
+            confirmations: self.confirmations,
             log_filter: self.log_filter.clone(),
             call_filter: self.call_filter.clone(),
             block_filter: self.block_filter.clone(),
@@ -115,7 +121,9 @@ impl<S, C> Clone for BlockStreamContext<S, C> {
             include_calls_in_blocks: self.include_calls_in_blocks,
             logger: self.logger.clone(),
             metrics: self.metrics.clone(),
+            memory: self.memory.clone(),
             previous_triggers_per_block: self.previous_triggers_per_block,
+            previous_bytes_per_block: self.previous_bytes_per_block,
             previous_block_range_size: self.previous_block_range_size,
             max_block_range_size: self.max_block_range_size,
         }
@@ -125,7 +133,13 @@ impl<S, C> Clone for BlockStreamContext<S, C> {
 pub struct BlockStream<S, C> {
     state: BlockStreamState,
     consecutive_err_count: u32,
+    /// The estimated size of the blocks that have been fetched but not
+    /// yielded yet
+    buffered_bytes: usize,
     chain_head_update_stream: ChainHeadUpdateStream,
+    /// The common ancestor of the branches of the last reorg we heard
+    /// about, until the subgraph has been reverted to it
+    reorg_ancestor: Option<EthereumBlockPointer>,
     ctx: BlockStreamContext<S, C>,
 }
 
@@ -156,13 +170,17 @@ where
         start_blocks: Vec<u64>,
         include_calls_in_blocks: bool,
         reorg_threshold: u64,
+        confirmations: u64,
         logger: Logger,
         metrics: Arc<BlockStreamMetrics>,
+        memory: Arc<DeploymentMemory>,
     ) -> Self {
         BlockStream {
             state: BlockStreamState::BeginReconciliation,
             consecutive_err_count: 0,
+            buffered_bytes: 0,
             chain_head_update_stream: chain_store.chain_head_updates(),
+            reorg_ancestor: None,
             ctx: BlockStreamContext {
                 subgraph_store,
                 chain_store,
@@ -170,6 +188,7 @@ where
                 node_id,
                 subgraph_id,
                 reorg_threshold,
+                confirmations,
                 logger,
                 log_filter,
                 call_filter,
@@ -177,9 +196,11 @@ where
                 start_blocks,
                 include_calls_in_blocks,
                 metrics,
+                memory,
 
                 // A high number here forces a slow start, with a range of 1.
                 previous_triggers_per_block: 1_000_000.0,
+                previous_bytes_per_block: 0.0,
                 previous_block_range_size: 1,
                 max_block_range_size: *MAX_BLOCK_RANGE_SIZE,
             },
@@ -221,6 +242,40 @@ where
         }))
     }
 
+    /// Return the block that is `self.confirmations` blocks behind
+    /// `head_ptr`, or `None` if there is no such block or it is not in the
+    /// block cache
+    fn confirmed_head(&self, head_ptr: EthereumBlockPointer) -> Option<EthereumBlockPointer> {
+        if self.confirmations == 0 {
+            return Some(head_ptr);
+        }
+        if head_ptr.number < self.confirmations {
+            return None;
+        }
+        match self
+            .chain_store
+            .ancestor_block(head_ptr, self.confirmations)
+        {
+            Ok(Some(block)) => Some(EthereumBlockPointer::from(&block)),
+            Ok(None) => {
+                warn!(
+                    self.logger,
+                    "The block {} blocks behind the chain head is not in the block cache; \
+                     ETHEREUM_ANCESTOR_COUNT needs to be larger than the number of \
+                     confirmations plus the reorg threshold",
+                    self.confirmations;
+                    "chain_head" => head_ptr.number,
+                );
+                None
+            }
+            Err(e) => {
+                warn!(self.logger, "Failed to look up the confirmed chain head";
+                      "error" => e.to_string());
+                None
+            }
+        }
+    }
+
     /// Determine the next reconciliation step. Does not modify Store or ChainStore.
     fn get_next_step(&self) -> impl Future<Item = ReconciliationStep, Error = Error> + Send {
         let ctx = self.clone();
@@ -241,7 +296,16 @@ where
                 as Box<dyn Future<Item = _, Error = _> + Send>;
         }
 
-        let head_ptr = head_ptr_opt.unwrap();
+        // Pretend that the chain ends `confirmations` blocks before its
+        // actual head so that we never process blocks with fewer
+        // confirmations than the subgraph asked for
+        let head_ptr = match ctx.confirmed_head(head_ptr_opt.unwrap()) {
+            Some(head_ptr) => head_ptr,
+            None => {
+                return Box::new(future::ok(ReconciliationStep::Done))
+                    as Box<dyn Future<Item = _, Error = _> + Send>
+            }
+        };
 
         trace!(
             ctx.logger, "Chain head pointer";
@@ -396,6 +460,26 @@ where
                                     .min(range_size_upper_limit as f64)
                                     as u64
                             };
+                            // Keep the blocks we fetch within the memory
+                            // budget of the deployment, judging by how big
+                            // the blocks in the previous range were
+                            let range_size = match ctx.memory.block_budget() {
+                                Some(budget) if ctx.previous_bytes_per_block > 0.0 => {
+                                    let memory_limit =
+                                        (budget as f64 / ctx.previous_bytes_per_block).max(1.0)
+                                            as u64;
+                                    if memory_limit < range_size {
+                                        debug!(
+                                            ctx.logger,
+                                            "Reducing block range size to stay within memory limit";
+                                            "range_size" => memory_limit,
+                                            "budget_bytes" => budget
+                                        );
+                                    }
+                                    range_size.min(memory_limit)
+                                }
+                                _ => range_size,
+                            };
                             let to = cmp::min(from + range_size - 1, to_limit);
 
                             let section = ctx.metrics.stopwatch.start_section("scan_blocks");
@@ -496,6 +580,7 @@ where
                                     .calls_in_block(
                                         &logger,
                                         ctx.metrics.ethrpc_metrics.clone(),
+                                        ctx.chain_store.clone(),
                                         head_ancestor.block.number.unwrap().as_u64(),
                                         head_ancestor.block.hash.unwrap(),
                                     )
@@ -557,6 +642,29 @@ where
     }
 }
 
+impl<S, C> BlockStream<S, C>
+where
+    S: SubgraphStore,
+    C: ChainStore,
+{
+    /// The block to revert next to get the subgraph back to the common
+    /// ancestor of the last reorg, or `None` if the subgraph is not ahead
+    /// of it. Reverting this way saves looking for the fork point one
+    /// block at a time; a block that the subgraph already processed on
+    /// the new branch is reverted and processed again, which is wasteful
+    /// but harmless
+    fn next_reorg_revert(&mut self) -> Option<EthereumBlockPointer> {
+        let ancestor = self.reorg_ancestor?;
+        match self.ctx.subgraph_store.block_ptr(&self.ctx.subgraph_id) {
+            Ok(Some(ptr)) if ptr.number > ancestor.number => Some(ptr),
+            _ => {
+                self.reorg_ancestor = None;
+                None
+            }
+        }
+    }
+}
+
 impl<S: SubgraphStore, C: ChainStore> BlockStreamTrait for BlockStream<S, C> {}
 
 impl<S: SubgraphStore, C: ChainStore> Stream for BlockStream<S, C> {
@@ -570,6 +678,11 @@ impl<S: SubgraphStore, C: ChainStore> Stream for BlockStream<S, C> {
         let result = loop {
             match state {
                 BlockStreamState::BeginReconciliation => {
+                    if let Some(block) = self.next_reorg_revert() {
+                        state = BlockStreamState::BeginReconciliation;
+                        break Ok(Async::Ready(Some(BlockStreamEvent::Revert(block))));
+                    }
+
                     // Start the reconciliation process by asking for blocks
                     state = BlockStreamState::Reconciliation(self.ctx.next_blocks());
                 }
@@ -603,6 +716,20 @@ impl<S: SubgraphStore, C: ChainStore> Stream for BlockStream<S, C> {
                                 debug!(self.ctx.logger, "Processing {} triggers", total_triggers);
                             }
 
+                            self.buffered_bytes =
+                                next_blocks.iter().map(|b| b.approx_size()).sum::<usize>();
+                            self.ctx.previous_bytes_per_block =
+                                self.buffered_bytes as f64 / block_range_size as f64;
+                            self.ctx.memory.set_block_buffer(self.buffered_bytes);
+                            if self.ctx.memory.over_limit() {
+                                warn!(
+                                    self.ctx.logger,
+                                    "Deployment is over its memory limit, \
+                                     the next block ranges will be smaller";
+                                    "usage_bytes" => self.ctx.memory.usage()
+                                );
+                            }
+
                             // Switch to yielding state until next_blocks is depleted
                             state = BlockStreamState::YieldingBlocks(next_blocks);
 
@@ -656,6 +783,9 @@ impl<S: SubgraphStore, C: ChainStore> Stream for BlockStream<S, C> {
                     match next_blocks.pop_front() {
                         // Yield one block
                         Some(next_block) => {
+                            self.buffered_bytes =
+                                self.buffered_bytes.saturating_sub(next_block.approx_size());
+                            self.ctx.memory.set_block_buffer(self.buffered_bytes);
                             state = BlockStreamState::YieldingBlocks(next_blocks);
                             break Ok(Async::Ready(Some(BlockStreamEvent::Block(next_block))));
                         }
@@ -683,7 +813,20 @@ impl<S: SubgraphStore, C: ChainStore> Stream for BlockStream<S, C> {
                 BlockStreamState::Idle => {
                     match self.chain_head_update_stream.poll() {
                         // Chain head was updated
-                        Ok(Async::Ready(Some(()))) => {
+                        Ok(Async::Ready(Some(update))) => {
+                            if let Some(reorg) = &update.reorg {
+                                debug!(
+                                    self.ctx.logger,
+                                    "Chain head moved to a different branch";
+                                    "previous_head" => reorg.previous_head_block_number,
+                                    "head" => update.head_block_number,
+                                    "common_ancestor" => reorg
+                                        .common_ancestor()
+                                        .map(|ptr| ptr.number.to_string())
+                                        .unwrap_or_else(|| "unknown".to_owned()),
+                                );
+                                self.reorg_ancestor = reorg.common_ancestor();
+                            }
                             state = BlockStreamState::BeginReconciliation;
                         }
 
@@ -786,7 +929,9 @@ where
         call_filter: EthereumCallFilter,
         block_filter: EthereumBlockFilter,
         include_calls_in_blocks: bool,
+        confirmations: u64,
         metrics: Arc<BlockStreamMetrics>,
+        memory: Arc<DeploymentMemory>,
     ) -> Self::Stream {
         let logger = logger.new(o!(
             "component" => "BlockStream",
@@ -827,8 +972,10 @@ where
             start_blocks,
             include_calls_in_blocks,
             self.reorg_threshold,
+            confirmations,
             logger,
             metrics,
+            memory,
         )
     }
 }
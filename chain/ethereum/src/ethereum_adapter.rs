@@ -13,7 +13,7 @@ use graph::prelude::{
     anyhow, debug, error, ethabi,
     futures03::{self, compat::Future01CompatExt, FutureExt, StreamExt, TryStreamExt},
     hex, retry, stream, tiny_keccak, trace, warn, web3, ChainStore, CheapClone, DynTryFuture,
-    Error, EthereumCallCache, Logger, TimeoutError,
+    Error, EthereumCallCache, EthereumCallResult, Logger, RetryTarget, TimeoutError,
 };
 use web3::api::Web3;
 use web3::transports::batch::Batch;
@@ -47,23 +47,6 @@ lazy_static! {
             .parse::<usize>()
             .expect("invalid ETHEREUM_BLOCK_BATCH_SIZE env var");
 
-    /// This should not be too large that it causes requests to timeout without us catching it, nor
-    /// too small that it causes us to timeout requests that would've succeeded. We've seen
-    /// successful `eth_getLogs` requests take over 120 seconds.
-    static ref JSON_RPC_TIMEOUT: u64 = std::env::var("GRAPH_ETHEREUM_JSON_RPC_TIMEOUT")
-            .unwrap_or("180".into())
-            .parse::<u64>()
-            .expect("invalid GRAPH_ETHEREUM_JSON_RPC_TIMEOUT env var");
-
-
-    /// This is used for requests that will not fail the subgraph if the limit is reached, but will
-    /// simply restart the syncing step, so it can be low. This limit guards against scenarios such
-    /// as requesting a block hash that has been reorged.
-    static ref REQUEST_RETRIES: usize = std::env::var("GRAPH_ETHEREUM_REQUEST_RETRIES")
-            .unwrap_or("10".into())
-            .parse::<usize>()
-            .expect("invalid GRAPH_ETHEREUM_REQUEST_RETRIES env var");
-
     /// Log eth_call data and target address at trace level. Turn on for debugging.
     static ref ETH_CALL_FULL_LOG: bool = std::env::var("GRAPH_ETH_CALL_FULL_LOG").is_ok();
 
@@ -132,8 +115,7 @@ where
         let logger = logger.to_owned();
 
         retry("trace_filter RPC call", &logger)
-            .limit(*REQUEST_RETRIES)
-            .timeout_secs(*JSON_RPC_TIMEOUT)
+            .policy(RetryTarget::Ethereum.policy())
             .run(move || {
                 let trace_filter: TraceFilter = match addresses.len() {
                     0 => TraceFilterBuilder::default()
@@ -225,8 +207,7 @@ where
                     .iter()
                     .any(|f| e.to_string().contains(f)),
             })
-            .limit(*REQUEST_RETRIES)
-            .timeout_secs(*JSON_RPC_TIMEOUT)
+            .policy(RetryTarget::Ethereum.policy())
             .run(move || {
                 let start = Instant::now();
                 let subgraph_metrics = subgraph_metrics.clone();
@@ -421,7 +402,7 @@ where
                 Err(_) => true,
             })
             .limit(10)
-            .timeout_secs(*JSON_RPC_TIMEOUT)
+            .policy(RetryTarget::Ethereum.policy())
             .run(move || {
                 let req = CallRequest {
                     from: None,
@@ -431,115 +412,69 @@ where
                     value: None,
                     data: Some(call_data.clone()),
                 };
-                web3.eth().call(req, Some(block_id)).then(|result| {
-                    // Try to check if the call was reverted. The JSON-RPC response for
-                    // reverts is not standardized, the current situation for the tested
-                    // clients is:
-                    //
-                    // - Parity returns a reliable RPC error response for reverts.
-                    // - Ganache also returns a reliable RPC error.
-                    // - Geth now also returns an RPC error. It used to return `0x` on a
-                    //   revert with no reason string, or a Solidity encoded `Error(string)`
-                    //   call from `revert` and `require` calls with a reason string. We
-                    //   still have support for those but that can be removed on the next
-                    //   hard fork (Berlin).
-
-                    // 0xfe is the "designated bad instruction" of the EVM, and Solidity
-                    // uses it for asserts.
-                    const PARITY_BAD_INSTRUCTION_FE: &str = "Bad instruction fe";
-
-                    // 0xfd is REVERT, but on some contracts, and only on older blocks,
-                    // this happens. Makes sense to consider it a revert as well.
-                    const PARITY_BAD_INSTRUCTION_FD: &str = "Bad instruction fd";
-
-                    const PARITY_BAD_JUMP_PREFIX: &str = "Bad jump";
-                    const GANACHE_VM_EXECUTION_ERROR: i64 = -32000;
-                    const GANACHE_REVERT_MESSAGE: &str =
-                        "VM Exception while processing transaction: revert";
-                    const PARITY_VM_EXECUTION_ERROR: i64 = -32015;
-                    const PARITY_REVERT_PREFIX: &str = "Reverted 0x";
-
-                    // Deterministic Geth execution errors. We might need to expand this as
-                    // subgraphs come across other errors. See
-                    // https://github.com/ethereum/go-ethereum/blob/cd57d5cd38ef692de8fbedaa56598b4e9fbfbabc/core/vm/errors.go
-                    const GETH_EXECUTION_ERRORS: &[&str] = &[
-                        "execution reverted",
-                        "invalid jump destination",
-                        "invalid opcode",
-                    ];
-
-                    let as_solidity_revert_with_reason = |bytes: &[u8]| {
-                        let solidity_revert_function_selector =
-                            &tiny_keccak::keccak256(b"Error(string)")[..4];
-
-                        match bytes.len() >= 4 && &bytes[..4] == solidity_revert_function_selector {
-                            false => None,
-                            true => ethabi::decode(&[ParamType::String], &bytes[4..])
-                                .ok()
-                                .and_then(|tokens| tokens[0].clone().to_string()),
-                        }
-                    };
-
-                    match result {
-                        // Check for old Geth revert with reason.
-                        Ok(bytes) => match as_solidity_revert_with_reason(&bytes.0) {
-                            None => Ok(bytes),
-                            Some(reason) => Err(EthereumContractCallError::Revert(reason)),
-                        },
-
-                        // Check for Geth revert.
-                        Err(web3::Error::Rpc(rpc_error))
-                            if GETH_EXECUTION_ERRORS
-                                .iter()
-                                .any(|e| rpc_error.message.contains(e)) =>
-                        {
-                            Err(EthereumContractCallError::Revert(rpc_error.message))
-                        }
+                web3.eth().call(req, Some(block_id)).then(call_result)
+            })
+            .map_err(|e| e.into_inner().unwrap_or(EthereumContractCallError::Timeout))
+    }
 
-                        // Check for Parity revert.
-                        Err(web3::Error::Rpc(ref rpc_error))
-                            if rpc_error.code.code() == PARITY_VM_EXECUTION_ERROR =>
-                        {
-                            match rpc_error.data.as_ref().and_then(|d| d.as_str()) {
-                                Some(data)
-                                    if data.starts_with(PARITY_REVERT_PREFIX)
-                                        || data.starts_with(PARITY_BAD_JUMP_PREFIX)
-                                        || data == PARITY_BAD_INSTRUCTION_FE
-                                        || data == PARITY_BAD_INSTRUCTION_FD =>
-                                {
-                                    let reason = if data == PARITY_BAD_INSTRUCTION_FE {
-                                        PARITY_BAD_INSTRUCTION_FE.to_owned()
-                                    } else {
-                                        let payload = data.trim_start_matches(PARITY_REVERT_PREFIX);
-                                        hex::decode(payload)
-                                            .ok()
-                                            .and_then(|payload| {
-                                                as_solidity_revert_with_reason(&payload)
-                                            })
-                                            .unwrap_or("no reason".to_owned())
-                                    };
-                                    Err(EthereumContractCallError::Revert(reason))
-                                }
+    /// Send several `eth_call`s at the same block to the Ethereum node as
+    /// one batch. The results are in the order of `calls`; the batch is
+    /// retried as long as some of the calls fail for reasons other than a
+    /// revert
+    fn call_batch(
+        &self,
+        logger: Logger,
+        calls: Vec<(Address, Bytes)>,
+        block_ptr: EthereumBlockPointer,
+    ) -> impl Future<
+        Item = Vec<Result<Bytes, EthereumContractCallError>>,
+        Error = EthereumContractCallError,
+    > + Send {
+        let web3 = self.web3.clone();
 
-                                // The VM execution error was not identified as a revert.
-                                _ => Err(EthereumContractCallError::Web3Error(web3::Error::Rpc(
-                                    rpc_error.clone(),
-                                ))),
-                            }
-                        }
+        // Ganache does not support calls by block hash.
+        // See https://github.com/trufflesuite/ganache-cli/issues/745
+        let block_id = if self.is_ganache || *ETH_CALL_BY_NUMBER {
+            BlockId::Number(block_ptr.number.into())
+        } else {
+            BlockId::Hash(block_ptr.hash)
+        };
 
-                        // Check for Ganache revert.
-                        Err(web3::Error::Rpc(ref rpc_error))
-                            if rpc_error.code.code() == GANACHE_VM_EXECUTION_ERROR
-                                && rpc_error.message.starts_with(GANACHE_REVERT_MESSAGE) =>
-                        {
-                            Err(EthereumContractCallError::Revert(rpc_error.message.clone()))
-                        }
+        retry("batch eth_call RPC call", &logger)
+            .when(|result: &Result<Vec<Result<Bytes, _>>, _>| match result {
+                Ok(results) => results.iter().any(|result| match result {
+                    Ok(_) | Err(EthereumContractCallError::Revert(_)) => false,
+                    Err(_) => true,
+                }),
+                Err(_) => true,
+            })
+            .limit(10)
+            .policy(RetryTarget::Ethereum.policy())
+            .run(move || {
+                let batching_web3 = Web3::new(Batch::new(web3.transport().clone()));
+                let call_futures = calls
+                    .iter()
+                    .map(|(address, data)| {
+                        let req = CallRequest {
+                            from: None,
+                            to: *address,
+                            gas: None,
+                            gas_price: None,
+                            value: None,
+                            data: Some(data.clone()),
+                        };
+                        batching_web3
+                            .eth()
+                            .call(req, Some(block_id))
+                            .then(|result| Ok(call_result(result)))
+                    })
+                    .collect::<Vec<_>>();
 
-                        // The error was not identified as a revert.
-                        Err(err) => Err(EthereumContractCallError::Web3Error(err)),
-                    }
-                })
+                batching_web3
+                    .transport()
+                    .submit_batch()
+                    .map_err(EthereumContractCallError::Web3Error)
+                    .and_then(move |_| stream::futures_ordered(call_futures).collect())
             })
             .map_err(|e| e.into_inner().unwrap_or(EthereumContractCallError::Timeout))
     }
@@ -555,8 +490,7 @@ where
         stream::iter_ok::<_, Error>(ids.into_iter().map(move |hash| {
             let web3 = web3.clone();
             retry(format!("load block {}", hash), &logger)
-                .limit(*REQUEST_RETRIES)
-                .timeout_secs(*JSON_RPC_TIMEOUT)
+                .policy(RetryTarget::Ethereum.policy())
                 .run(move || {
                     web3.eth()
                         .block_with_txs(BlockId::Hash(hash))
@@ -586,7 +520,7 @@ where
             let web3 = web3.clone();
             retry(format!("load block ptr {}", block_num), &logger)
                 .no_limit()
-                .timeout_secs(*JSON_RPC_TIMEOUT)
+                .policy(RetryTarget::Ethereum.policy())
                 .run(move || {
                     web3.eth()
                         .block(BlockId::Number(BlockNumber::Number(block_num.into())))
@@ -604,6 +538,168 @@ where
     }
 }
 
+/// Check the arguments of `call` against the function and encode the call
+/// according to the ABI
+fn encode_call(
+    logger: &Logger,
+    call: &EthereumContractCall,
+) -> Result<Vec<u8>, EthereumContractCallError> {
+    // Emit custom error for type mismatches.
+    for (token, kind) in call
+        .args
+        .iter()
+        .zip(call.function.inputs.iter().map(|p| &p.kind))
+    {
+        if !token.type_check(kind) {
+            return Err(EthereumContractCallError::TypeError(
+                token.clone(),
+                kind.clone(),
+            ));
+        }
+    }
+
+    // Encode the call parameters according to the ABI
+    let call_data = call
+        .function
+        .encode_input(&call.args)
+        .map_err(EthereumContractCallError::EncodingError)?;
+
+    if *ETH_CALL_FULL_LOG {
+        trace!(logger, "eth_call";
+            "address" => hex::encode(&call.address),
+            "data" => hex::encode(&call_data)
+        );
+    }
+    Ok(call_data)
+}
+
+/// Decode the return values of `call` according to the ABI
+fn decode_output(
+    call: &EthereumContractCall,
+    output: Vec<u8>,
+) -> Result<Vec<Token>, EthereumContractCallError> {
+    if output.is_empty() {
+        // We got a `0x` response. For old Geth, this can mean a revert. It can also be
+        // that the contract actually returned an empty response. A view call is meant
+        // to return something, so we treat empty responses the same as reverts.
+        Err(EthereumContractCallError::Revert("empty response".into()))
+    } else {
+        // Decode failures are reverts. The reasoning is that if Solidity fails to
+        // decode an argument, that's a revert, so the same goes for the output.
+        call.function.decode_output(&output).map_err(|e| {
+            EthereumContractCallError::Revert(format!("failed to decode output: {}", e))
+        })
+    }
+}
+
+/// Interpret the response of the Ethereum node to an `eth_call`,
+/// recognizing the various ways in which clients report reverts
+fn call_result(result: Result<Bytes, web3::Error>) -> Result<Bytes, EthereumContractCallError> {
+    // Try to check if the call was reverted. The JSON-RPC response for
+    // reverts is not standardized, the current situation for the tested
+    // clients is:
+    //
+    // - Parity returns a reliable RPC error response for reverts.
+    // - Ganache also returns a reliable RPC error.
+    // - Geth now also returns an RPC error. It used to return `0x` on a
+    //   revert with no reason string, or a Solidity encoded `Error(string)`
+    //   call from `revert` and `require` calls with a reason string. We
+    //   still have support for those but that can be removed on the next
+    //   hard fork (Berlin).
+
+    // 0xfe is the "designated bad instruction" of the EVM, and Solidity
+    // uses it for asserts.
+    const PARITY_BAD_INSTRUCTION_FE: &str = "Bad instruction fe";
+
+    // 0xfd is REVERT, but on some contracts, and only on older blocks,
+    // this happens. Makes sense to consider it a revert as well.
+    const PARITY_BAD_INSTRUCTION_FD: &str = "Bad instruction fd";
+
+    const PARITY_BAD_JUMP_PREFIX: &str = "Bad jump";
+    const GANACHE_VM_EXECUTION_ERROR: i64 = -32000;
+    const GANACHE_REVERT_MESSAGE: &str = "VM Exception while processing transaction: revert";
+    const PARITY_VM_EXECUTION_ERROR: i64 = -32015;
+    const PARITY_REVERT_PREFIX: &str = "Reverted 0x";
+
+    // Deterministic Geth execution errors. We might need to expand this as
+    // subgraphs come across other errors. See
+    // https://github.com/ethereum/go-ethereum/blob/cd57d5cd38ef692de8fbedaa56598b4e9fbfbabc/core/vm/errors.go
+    const GETH_EXECUTION_ERRORS: &[&str] = &[
+        "execution reverted",
+        "invalid jump destination",
+        "invalid opcode",
+    ];
+
+    let as_solidity_revert_with_reason = |bytes: &[u8]| {
+        let solidity_revert_function_selector = &tiny_keccak::keccak256(b"Error(string)")[..4];
+
+        match bytes.len() >= 4 && &bytes[..4] == solidity_revert_function_selector {
+            false => None,
+            true => ethabi::decode(&[ParamType::String], &bytes[4..])
+                .ok()
+                .and_then(|tokens| tokens[0].clone().to_string()),
+        }
+    };
+
+    match result {
+        // Check for old Geth revert with reason.
+        Ok(bytes) => match as_solidity_revert_with_reason(&bytes.0) {
+            None => Ok(bytes),
+            Some(reason) => Err(EthereumContractCallError::Revert(reason)),
+        },
+
+        // Check for Geth revert.
+        Err(web3::Error::Rpc(rpc_error))
+            if GETH_EXECUTION_ERRORS
+                .iter()
+                .any(|e| rpc_error.message.contains(e)) =>
+        {
+            Err(EthereumContractCallError::Revert(rpc_error.message))
+        }
+
+        // Check for Parity revert.
+        Err(web3::Error::Rpc(ref rpc_error))
+            if rpc_error.code.code() == PARITY_VM_EXECUTION_ERROR =>
+        {
+            match rpc_error.data.as_ref().and_then(|d| d.as_str()) {
+                Some(data)
+                    if data.starts_with(PARITY_REVERT_PREFIX)
+                        || data.starts_with(PARITY_BAD_JUMP_PREFIX)
+                        || data == PARITY_BAD_INSTRUCTION_FE
+                        || data == PARITY_BAD_INSTRUCTION_FD =>
+                {
+                    let reason = if data == PARITY_BAD_INSTRUCTION_FE {
+                        PARITY_BAD_INSTRUCTION_FE.to_owned()
+                    } else {
+                        let payload = data.trim_start_matches(PARITY_REVERT_PREFIX);
+                        hex::decode(payload)
+                            .ok()
+                            .and_then(|payload| as_solidity_revert_with_reason(&payload))
+                            .unwrap_or("no reason".to_owned())
+                    };
+                    Err(EthereumContractCallError::Revert(reason))
+                }
+
+                // The VM execution error was not identified as a revert.
+                _ => Err(EthereumContractCallError::Web3Error(web3::Error::Rpc(
+                    rpc_error.clone(),
+                ))),
+            }
+        }
+
+        // Check for Ganache revert.
+        Err(web3::Error::Rpc(ref rpc_error))
+            if rpc_error.code.code() == GANACHE_VM_EXECUTION_ERROR
+                && rpc_error.message.starts_with(GANACHE_REVERT_MESSAGE) =>
+        {
+            Err(EthereumContractCallError::Revert(rpc_error.message.clone()))
+        }
+
+        // The error was not identified as a revert.
+        Err(err) => Err(EthereumContractCallError::Web3Error(err)),
+    }
+}
+
 impl<T> EthereumAdapterTrait for EthereumAdapter<T>
 where
     T: web3::BatchTransport + Send + Sync + 'static,
@@ -623,13 +719,13 @@ where
         let web3 = self.web3.clone();
         let net_version_future = retry("net_version RPC call", &logger)
             .no_limit()
-            .timeout_secs(20)
+            .policy(RetryTarget::Ethereum.policy())
             .run(move || web3.net().version().from_err());
 
         let web3 = self.web3.clone();
         let gen_block_hash_future = retry("eth_getBlockByNumber(0, false) RPC call", &logger)
             .no_limit()
-            .timeout_secs(30)
+            .policy(RetryTarget::Ethereum.policy())
             .run(move || {
                 web3.eth()
                     .block(BlockId::Number(BlockNumber::Number(0.into())))
@@ -671,7 +767,7 @@ where
         Box::new(
             retry("eth_getBlockByNumber(latest) no txs RPC call", logger)
                 .no_limit()
-                .timeout_secs(*JSON_RPC_TIMEOUT)
+                .policy(RetryTarget::Ethereum.policy())
                 .run(move || {
                     web3.eth()
                         .block(BlockNumber::Latest.into())
@@ -701,7 +797,7 @@ where
         Box::new(
             retry("eth_getBlockByNumber(latest) with txs RPC call", logger)
                 .no_limit()
-                .timeout_secs(*JSON_RPC_TIMEOUT)
+                .policy(RetryTarget::Ethereum.policy())
                 .run(move || {
                     web3.eth()
                         .block_with_txs(BlockNumber::Latest.into())
@@ -749,8 +845,7 @@ where
 
         Box::new(
             retry("eth_getBlockByHash RPC call", &logger)
-                .limit(*REQUEST_RETRIES)
-                .timeout_secs(*JSON_RPC_TIMEOUT)
+                .policy(RetryTarget::Ethereum.policy())
                 .run(move || {
                     web3.eth()
                         .block_with_txs(BlockId::Hash(block_hash))
@@ -775,7 +870,7 @@ where
         Box::new(
             retry("eth_getBlockByNumber RPC call", &logger)
                 .no_limit()
-                .timeout_secs(*JSON_RPC_TIMEOUT)
+                .policy(RetryTarget::Ethereum.policy())
                 .run(move || {
                     web3.eth()
                         .block_with_txs(BlockId::Number(block_number.into()))
@@ -818,7 +913,7 @@ where
             retry("batch eth_getTransactionReceipt RPC call", &logger)
                 .limit(16)
                 .no_logging()
-                .timeout_secs(*JSON_RPC_TIMEOUT)
+                .policy(RetryTarget::Ethereum.policy())
                 .run(move || {
                     let block = block.clone();
                     let batching_web3 = Web3::new(Batch::new(web3.transport().clone()));
@@ -953,6 +1048,18 @@ where
             Err(e) => return Box::new(future::result(Err(e))),
         };
         let num_hashes = hashes.len();
+
+        // If we store more than one block with that number, the main chain
+        // that the store tracks might tell us which one is the right one
+        let canonical_hash = if num_hashes > 1 {
+            match chain_store.block_by_number(block_number as graph::prelude::BlockNumber) {
+                Ok(block) => block.and_then(|block| block.block.hash),
+                Err(e) => return Box::new(future::result(Err(e))),
+            }
+        } else {
+            None
+        };
+
         let logger1 = logger.clone();
         let confirm_block_hash = move |hash: &Option<H256>| {
             // If there was more than one hash, now that we know what the
@@ -985,11 +1092,14 @@ where
 
         if hashes.len() == 1 {
             Box::new(future::result(Ok(hashes.pop())))
+        } else if canonical_hash.is_some() {
+            confirm_block_hash(&canonical_hash);
+            Box::new(future::result(Ok(canonical_hash)))
         } else {
             Box::new(
                 retry("eth_getBlockByNumber RPC call", &logger)
                     .no_limit()
-                    .timeout_secs(*JSON_RPC_TIMEOUT)
+                    .policy(RetryTarget::Ethereum.policy())
                     .run(move || {
                         web3.eth()
                             .block(BlockId::Number(block_number.into()))
@@ -1034,7 +1144,7 @@ where
 
                 retry("eth_getUncleByBlockHashAndIndex RPC call", &logger)
                     .no_limit()
-                    .timeout_secs(60)
+                    .policy(RetryTarget::Ethereum.policy())
                     .run(move || {
                         web3.eth()
                             .uncle(block_hash.clone().into(), index.into())
@@ -1081,10 +1191,33 @@ where
         &self,
         logger: &Logger,
         subgraph_metrics: Arc<SubgraphEthRpcMetrics>,
+        chain_store: Arc<dyn ChainStore>,
         block_number: u64,
         block_hash: H256,
     ) -> Box<dyn Future<Item = Vec<EthereumCall>, Error = Error> + Send> {
+        fn calls(traces: &[Trace]) -> Vec<EthereumCall> {
+            traces
+                .iter()
+                .filter_map(EthereumCall::try_from_trace)
+                .collect()
+        }
+
+        // Other subgraphs might already have requested the traces of this
+        // block from the Ethereum node
+        match chain_store.traces_for_blocks(vec![block_hash]) {
+            Ok(mut cached) => {
+                if let Some(traces) = cached.remove(&block_hash) {
+                    return Box::new(future::ok(calls(&traces)));
+                }
+            }
+            Err(e) => warn!(logger, "Failed to read traces from the chain store";
+                            "block_number" => block_number,
+                            "block_hash" => format!("{:x}", block_hash),
+                            "error" => e.to_string()),
+        }
+
         let eth = self.clone();
+        let logger = logger.clone();
         let addresses = Vec::new();
         let calls = eth
             .trace_stream(
@@ -1121,10 +1254,16 @@ where
                 future::ok(traces)
             })
             .map(move |traces| {
-                traces
-                    .iter()
-                    .filter_map(EthereumCall::try_from_trace)
-                    .collect()
+                let calls = calls(&traces);
+                // Failing to cache the traces only means that we will have
+                // to request them again
+                if let Err(e) = chain_store.upsert_traces(traces) {
+                    warn!(logger, "Failed to store traces in the chain store";
+                          "block_number" => block_number,
+                          "block_hash" => format!("{:x}", block_hash),
+                          "error" => e.to_string());
+                }
+                calls
             });
         Box::new(calls)
     }
@@ -1192,34 +1331,13 @@ where
         call: EthereumContractCall,
         cache: Arc<dyn EthereumCallCache>,
     ) -> Box<dyn Future<Item = Vec<Token>, Error = EthereumContractCallError> + Send> {
-        // Emit custom error for type mismatches.
-        for (token, kind) in call
-            .args
-            .iter()
-            .zip(call.function.inputs.iter().map(|p| &p.kind))
-        {
-            if !token.type_check(kind) {
-                return Box::new(future::err(EthereumContractCallError::TypeError(
-                    token.clone(),
-                    kind.clone(),
-                )));
-            }
-        }
-
-        // Encode the call parameters according to the ABI
-        let call_data = match call.function.encode_input(&call.args) {
+        let call_data = match encode_call(logger, &call) {
             Ok(data) => data,
-            Err(e) => return Box::new(future::err(EthereumContractCallError::EncodingError(e))),
+            Err(e) => return Box::new(future::err(e)),
         };
 
-        if *ETH_CALL_FULL_LOG {
-            trace!(logger, "eth_call";
-                "address" => hex::encode(&call.address),
-                "data" => hex::encode(&call_data)
-            );
-        }
-
-        // Check if we have it cached, if not do the call and cache.
+        // Check if we have it cached, if not do the call and cache. Calls
+        // that revert are cached, too, since they revert on every attempt
         Box::new(
             match cache
                 .get_call(call.address, &call_data, call.block_ptr)
@@ -1227,9 +1345,12 @@ where
                 .ok()
                 .flatten()
             {
-                Some(result) => {
+                Some(EthereumCallResult::Return(result)) => {
                     Box::new(future::ok(result)) as Box<dyn Future<Item = _, Error = _> + Send>
                 }
+                Some(EthereumCallResult::Revert(reason)) => {
+                    Box::new(future::err(EthereumContractCallError::Revert(reason)))
+                }
                 None => {
                     let cache = cache.clone();
                     let call = call.clone();
@@ -1241,33 +1362,131 @@ where
                             Bytes(call_data.clone()),
                             call.block_ptr,
                         )
-                        .map(move |result| {
-                            let _ = cache
-                                .set_call(call.address, &call_data, call.block_ptr, &result.0)
-                                .map_err(|e| {
-                                    error!(logger, "call cache set error";
-                                                   "error" => e.to_string())
-                                });
-                            result.0
+                        .then(move |result| {
+                            let cached = match &result {
+                                Ok(output) => Some(EthereumCallResult::Return(output.0.clone())),
+                                Err(EthereumContractCallError::Revert(reason)) => {
+                                    Some(EthereumCallResult::Revert(reason.clone()))
+                                }
+                                Err(_) => None,
+                            };
+                            if let Some(cached) = cached {
+                                let _ = cache
+                                    .set_call(call.address, &call_data, call.block_ptr, &cached)
+                                    .map_err(|e| {
+                                        error!(logger, "call cache set error";
+                                                       "error" => e.to_string())
+                                    });
+                            }
+                            result.map(|output| output.0)
                         }),
                     )
                 }
             }
             // Decode the return values according to the ABI
-            .and_then(move |output| {
-                if output.is_empty() {
-                    // We got a `0x` response. For old Geth, this can mean a revert. It can also be
-                    // that the contract actually returned an empty response. A view call is meant
-                    // to return something, so we treat empty responses the same as reverts.
-                    Err(EthereumContractCallError::Revert("empty response".into()))
-                } else {
-                    // Decode failures are reverts. The reasoning is that if Solidity fails to
-                    // decode an argument, that's a revert, so the same goes for the output.
-                    call.function.decode_output(&output).map_err(|e| {
-                        EthereumContractCallError::Revert(format!("failed to decode output: {}", e))
-                    })
+            .and_then(move |output| decode_output(&call, output)),
+        )
+    }
+
+    fn contract_calls(
+        &self,
+        logger: &Logger,
+        calls: Vec<EthereumContractCall>,
+        cache: Arc<dyn EthereumCallCache>,
+    ) -> Box<
+        dyn Future<
+                Item = Vec<Result<Vec<Token>, EthereumContractCallError>>,
+                Error = EthereumContractCallError,
+            > + Send,
+    > {
+        let block_ptr = match calls.first() {
+            Some(call) => call.block_ptr,
+            None => return Box::new(future::ok(vec![])),
+        };
+
+        // The outputs of the calls that are cached or that can not be
+        // encoded; the other calls are looked up in the cache first
+        let mut outputs: Vec<Option<Result<Vec<u8>, EthereumContractCallError>>> =
+            Vec::with_capacity(calls.len());
+        let mut encoded = Vec::new();
+        for call in &calls {
+            match encode_call(logger, call) {
+                Ok(data) => {
+                    encoded.push((outputs.len(), (call.address, data)));
+                    outputs.push(None);
+                }
+                Err(e) => outputs.push(Some(Err(e))),
+            }
+        }
+
+        let keys: Vec<_> = encoded.iter().map(|(_, key)| key.clone()).collect();
+        let cached = cache.get_calls(&keys, block_ptr).unwrap_or_else(|e| {
+            error!(logger, "call cache get error"; "error" => e.to_string());
+            vec![None; keys.len()]
+        });
+        let mut missing = Vec::new();
+        for ((index, key), cached) in encoded.into_iter().zip(cached) {
+            outputs[index] = match cached {
+                Some(EthereumCallResult::Return(output)) => Some(Ok(output)),
+                Some(EthereumCallResult::Revert(reason)) => {
+                    Some(Err(EthereumContractCallError::Revert(reason)))
                 }
-            }),
+                None => {
+                    missing.push((index, key));
+                    None
+                }
+            };
+        }
+
+        let decode = move |outputs: Vec<Option<Result<Vec<u8>, EthereumContractCallError>>>| {
+            calls
+                .iter()
+                .zip(outputs)
+                .map(|(call, output)| {
+                    output
+                        .expect("all calls have an output")
+                        .and_then(|output| decode_output(call, output))
+                })
+                .collect::<Vec<_>>()
+        };
+
+        if missing.is_empty() {
+            return Box::new(future::ok(decode(outputs)));
+        }
+
+        // Send the calls that are not cached as one batch and cache their
+        // results, including reverts
+        let logger = logger.clone();
+        let batch = missing
+            .iter()
+            .map(|(_, (address, data))| (*address, Bytes(data.clone())))
+            .collect();
+        Box::new(
+            self.call_batch(logger.clone(), batch, block_ptr)
+                .map(move |results| {
+                    let mut cacheable = Vec::new();
+                    for ((index, (address, data)), result) in missing.into_iter().zip(results) {
+                        let result = result.map(|output| output.0);
+                        match &result {
+                            Ok(output) => cacheable.push((
+                                address,
+                                data,
+                                EthereumCallResult::Return(output.clone()),
+                            )),
+                            Err(EthereumContractCallError::Revert(reason)) => cacheable.push((
+                                address,
+                                data,
+                                EthereumCallResult::Revert(reason.clone()),
+                            )),
+                            Err(_) => {}
+                        }
+                        outputs[index] = Some(result);
+                    }
+                    if let Err(e) = cache.set_calls(&cacheable, block_ptr) {
+                        error!(logger, "call cache set error"; "error" => e.to_string());
+                    }
+                    decode(outputs)
+                }),
         )
     }
 
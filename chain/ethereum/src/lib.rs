@@ -6,9 +6,13 @@ mod block_stream;
 mod config;
 mod ethereum_adapter;
 pub mod network_indexer;
+mod reorg_alert;
 mod transport;
 
-pub use self::block_ingestor::{BlockIngestor, BlockIngestorMetrics, CLEANUP_BLOCKS};
+pub use self::block_ingestor::{
+    hydrate_blocks, BlockIngestor, BlockIngestorMetrics, CLEANUP_BLOCKS,
+};
 pub use self::block_stream::{BlockStream, BlockStreamBuilder};
 pub use self::ethereum_adapter::EthereumAdapter;
+pub use self::reorg_alert::ReorgAlerter;
 pub use self::transport::{EventLoopHandle, Transport};
@@ -109,14 +109,14 @@ type WriteContextResult = Box<dyn Future<Item = WriteContext, Error = Error> + S
 impl WriteContext {
     /// Updates an entity to a new value (potentially merging it with existing data).
     fn set_entity(mut self, value: impl TryIntoEntity + ToEntityKey) -> WriteContextResult {
-        self.cache.set(
-            value.to_entity_key(self.subgraph_id.clone()),
-            match value.try_into_entity() {
-                Ok(entity) => entity,
-                Err(e) => return Box::new(future::err(e.into())),
-            },
-        );
-        Box::new(future::ok(self))
+        let key = value.to_entity_key(self.subgraph_id.clone());
+        let result = value
+            .try_into_entity()
+            .and_then(|entity| self.cache.set(key, entity));
+        match result {
+            Ok(()) => Box::new(future::ok(self)),
+            Err(e) => Box::new(future::err(e.into())),
+        }
     }
 
     /// Writes a block to the store.
@@ -166,6 +166,7 @@ impl WriteContext {
                                 modifications,
                                 stopwatch,
                                 Vec::new(),
+                                Vec::new(),
                             )
                             .map_err(|e| e.into())
                             .map(move |_| {
@@ -29,6 +29,9 @@ fn create_subgraph(
         data_sources: vec![],
         graft: None,
         templates: vec![],
+        confirmations: 0,
+        canaries: None,
+        limits: Default::default(),
     };
 
     let deployment = SubgraphDeploymentEntity::new(&manifest, false, start_block);
@@ -0,0 +1,191 @@
+//! Alerts about deep chain reorgs. Reorgs that go back further than a few
+//! blocks are rare and often go along with problems in the data of the
+//! subgraphs that index the chain. The `ReorgAlerter` watches the chain
+//! head updates for a network and, for every reorg that is deeper than
+//! `GRAPH_REORG_ALERT_DEPTH`, logs a warning, updates the
+//! `ethereum_deep_reorg_count` and `ethereum_deep_reorg_depth` metrics,
+//! and, if `GRAPH_REORG_ALERT_WEBHOOK` is set, posts a description of the
+//! reorg to that URL
+use std::env;
+use std::time::Duration;
+
+use graph::components::store::StatusStore;
+use graph::data::subgraph::status;
+use graph::prelude::reqwest::{Client, Url};
+use graph::prelude::serde_json::json;
+use graph::prelude::*;
+
+lazy_static! {
+    /// How many blocks a reorg has to go back before we alert about it
+    static ref REORG_ALERT_DEPTH: u64 = env::var("GRAPH_REORG_ALERT_DEPTH")
+        .ok()
+        .map(|s| s.parse().expect("invalid GRAPH_REORG_ALERT_DEPTH"))
+        .unwrap_or(10);
+
+    /// Where to post alerts about deep reorgs
+    static ref REORG_ALERT_WEBHOOK: Option<Url> = env::var("GRAPH_REORG_ALERT_WEBHOOK")
+        .ok()
+        .map(|s| s.parse().expect("invalid GRAPH_REORG_ALERT_WEBHOOK"));
+}
+
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(30);
+
+pub struct ReorgAlerter<S: ChainStore> {
+    logger: Logger,
+    network_name: String,
+    chain_store: Arc<S>,
+    status_store: Arc<dyn StatusStore>,
+    reorg_count: Box<CounterVec>,
+    reorg_depth: Box<GaugeVec>,
+    client: Client,
+}
+
+impl<S: ChainStore> ReorgAlerter<S> {
+    pub fn new(
+        logger: &Logger,
+        network_name: String,
+        chain_store: Arc<S>,
+        status_store: Arc<dyn StatusStore>,
+        registry: Arc<dyn MetricsRegistry>,
+    ) -> Self {
+        let logger = logger.new(o!(
+            "component" => "ReorgAlerter",
+            "network_name" => network_name.clone()
+        ));
+        let reorg_count = registry
+            .new_counter_vec(
+                "ethereum_deep_reorg_count",
+                "The number of reorgs that were deeper than GRAPH_REORG_ALERT_DEPTH",
+                vec![String::from("network")],
+            )
+            .expect("failed to create `ethereum_deep_reorg_count` counter");
+        let reorg_depth = registry
+            .new_gauge_vec(
+                "ethereum_deep_reorg_depth",
+                "The depth of the latest reorg that was deeper than GRAPH_REORG_ALERT_DEPTH",
+                vec![String::from("network")],
+            )
+            .expect("failed to create `ethereum_deep_reorg_depth` gauge");
+        ReorgAlerter {
+            logger,
+            network_name,
+            chain_store,
+            status_store,
+            reorg_count,
+            reorg_depth,
+            client: Client::new(),
+        }
+    }
+
+    /// Watch chain head updates and alert about deep reorgs until the
+    /// stream of updates ends. Updates can get lost when they arrive faster
+    /// than we process them, and we will not alert about the reorgs they
+    /// describe
+    pub async fn run(self) {
+        let mut updates = self.chain_store.chain_head_updates().compat();
+        while let Some(Ok(update)) = updates.next().await {
+            if let Some(reorg) = &update.reorg {
+                self.check_reorg(update.head(), reorg).await;
+            }
+        }
+        debug!(self.logger, "Chain head updates ended");
+    }
+
+    async fn check_reorg(&self, new_head: EthereumBlockPointer, reorg: &ChainReorg) {
+        let previous_head = reorg.previous_head();
+        let common_ancestor = reorg.common_ancestor();
+        let depth = common_ancestor.map(|ancestor| previous_head.number - ancestor.number);
+        if depth.map_or(false, |depth| depth <= *REORG_ALERT_DEPTH) {
+            return;
+        }
+
+        let deployments = match self.affected_deployments(common_ancestor) {
+            Ok(deployments) => deployments,
+            Err(e) => {
+                warn!(self.logger, "Failed to find deployments affected by reorg";
+                      "error" => e.to_string());
+                vec![]
+            }
+        };
+
+        warn!(self.logger, "Deep chain reorg";
+              "depth" => depth.map_or("unknown".to_owned(), |depth| depth.to_string()),
+              "previous_head" => format!("{}", previous_head),
+              "new_head" => format!("{}", new_head),
+              "common_ancestor" => common_ancestor
+                .map_or("unknown".to_owned(), |ptr| format!("{}", ptr)),
+              "deployments" => deployments.join(", "));
+
+        self.reorg_count
+            .with_label_values(&[&self.network_name])
+            .inc();
+        // We do not know how deep reorgs past the block cache go
+        if let Some(depth) = depth {
+            self.reorg_depth
+                .with_label_values(&[&self.network_name])
+                .set(depth as f64);
+        }
+
+        if let Some(url) = REORG_ALERT_WEBHOOK.as_ref() {
+            let block = |ptr: EthereumBlockPointer| json!({ "hash": format!("0x{:x}", ptr.hash), "number": ptr.number });
+            let alert = json!({
+                "network": self.network_name,
+                "previousHead": block(previous_head),
+                "newHead": block(new_head),
+                "commonAncestor": common_ancestor.map(block),
+                "depth": depth,
+                "deployments": deployments,
+            });
+            if let Err(e) = self.post(url, &alert).await {
+                warn!(self.logger, "Failed to post reorg alert";
+                      "error" => e.to_string());
+            }
+        }
+    }
+
+    /// The deployments indexing this network whose latest block is past
+    /// `common_ancestor`, i.e., that will have to revert blocks. If we do
+    /// not know the common ancestor, that is all deployments indexing this
+    /// network
+    fn affected_deployments(
+        &self,
+        common_ancestor: Option<EthereumBlockPointer>,
+    ) -> Result<Vec<String>, StoreError> {
+        let infos = self
+            .status_store
+            .status(status::Filter::Deployments(vec![]))?;
+        Ok(infos
+            .into_iter()
+            .filter(|info| {
+                info.chains.iter().any(|chain| {
+                    chain.network == self.network_name
+                        && match (&chain.latest_block, common_ancestor) {
+                            (Some(latest), Some(ancestor)) => latest.number() > ancestor.number,
+                            (Some(_), None) => true,
+                            (None, _) => false,
+                        }
+                })
+            })
+            .map(|info| info.subgraph)
+            .collect())
+    }
+
+    async fn post(&self, url: &Url, alert: &serde_json::Value) -> Result<(), Error> {
+        let response = self
+            .client
+            .post(url.clone())
+            .timeout(WEBHOOK_TIMEOUT)
+            .header("Content-Type", "application/json")
+            .body(alert.to_string())
+            .send()
+            .await?;
+        if response.status().is_success() {
+            Ok(())
+        } else {
+            Err(anyhow!(
+                "reorg alert webhook answered with status {}",
+                response.status()
+            ))
+        }
+    }
+}
@@ -1,4 +1,5 @@
 use std::env;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
 use std::sync::{Arc, Mutex};
 use std::time::Duration;
@@ -14,6 +15,7 @@ use lru_time_cache::LruCache;
 use serde_json::Value;
 
 use graph::prelude::{LinkResolver as LinkResolverTrait, *};
+use graph::util::futures::RetryConfig;
 
 /// Environment variable for limiting the `ipfs.map` file size limit.
 const MAX_IPFS_MAP_FILE_SIZE_VAR: &'static str = "GRAPH_MAX_IPFS_MAP_FILE_SIZE";
@@ -32,11 +34,6 @@ lazy_static! {
     /// The default size limit for the IPFS cache is 50 items.
     static ref MAX_IPFS_CACHE_SIZE: u64 = read_u64_from_env("GRAPH_MAX_IPFS_CACHE_SIZE")
         .unwrap_or(50);
-
-    /// The timeout for IPFS requests in seconds
-    static ref IPFS_TIMEOUT: Duration = Duration::from_secs(
-        read_u64_from_env("GRAPH_IPFS_TIMEOUT").unwrap_or(60)
-    );
 }
 
 fn read_u64_from_env(name: &str) -> Option<u64> {
@@ -50,6 +47,22 @@ fn read_u64_from_env(name: &str) -> Option<u64> {
     })
 }
 
+/// Retry IPFS calls according to the IPFS retry policy if `do_retry` is
+/// set, and make only one attempt otherwise
+fn retry_config<I, E>(operation_name: &str, logger: &Logger, do_retry: bool) -> RetryConfig<I, E>
+where
+    I: Send,
+    E: Debug + Send + Sync + 'static,
+{
+    let policy = RetryTarget::Ipfs.policy();
+    let config = retry(operation_name, logger).backoff(policy.backoff);
+    match (do_retry, policy.limit) {
+        (true, Some(limit)) => config.limit(limit),
+        (true, None) => config.no_limit(),
+        (false, _) => config.limit(1),
+    }
+}
+
 /// The IPFS APIs don't have a quick "do you have the file" function. Instead, we
 /// just rely on whether an API times out. That makes sense for IPFS, but not for
 /// our application. We want to be able to quickly select from a potential list
@@ -75,12 +88,7 @@ async fn select_fastest_client_with_stat<'a>(
         .iter()
         .enumerate()
         .map(|(i, c)| {
-            let retry_fut = if do_retry {
-                retry("object.stat", logger).no_limit()
-            } else {
-                retry("object.stat", logger).limit(1)
-            }
-            .timeout(timeout);
+            let retry_fut = retry_config("object.stat", logger, do_retry).timeout(timeout);
 
             retry_fut
                 .run(move || c.object_stat(path).map_ok(move |s| (s, i)).boxed().compat())
@@ -124,12 +132,78 @@ fn restrict_file_size(
     Ok(())
 }
 
+/// A subgraph manifest on the local filesystem. Links to the manifest's
+/// deployment id resolve to the manifest itself, and links to files are
+/// resolved relative to the directory containing the manifest. This is
+/// only meant for local development, where it makes it possible to deploy
+/// subgraphs without an IPFS node
+#[derive(Clone, Debug)]
+struct LocalManifest {
+    id: SubgraphDeploymentId,
+    path: PathBuf,
+    dir: PathBuf,
+}
+
+impl LocalManifest {
+    fn new(path: &Path) -> Result<Self, Error> {
+        let path = path
+            .canonicalize()
+            .map_err(|e| anyhow!("can not read manifest `{}`: {}", path.display(), e))?;
+        let dir = path
+            .parent()
+            .map(Path::to_path_buf)
+            .unwrap_or_else(|| PathBuf::from("/"));
+
+        // Derive the id from the path so that deploying the same manifest
+        // always uses the same deployment
+        let hash = tiny_keccak::keccak256(path.to_string_lossy().as_bytes());
+        let hex: String = hash[..20].iter().map(|b| format!("{:02x}", b)).collect();
+        let id = SubgraphDeploymentId::new(format!("dev{}", hex))
+            .expect("`dev` followed by hex digits is a valid deployment id");
+        Ok(LocalManifest { id, path, dir })
+    }
+
+    /// Return the local file that `link` refers to, or `None` if `link`
+    /// should be resolved through IPFS
+    fn resolve(&self, link: &str) -> Option<PathBuf> {
+        if link.trim_start_matches("/ipfs/") == self.id.as_str() {
+            return Some(self.path.clone());
+        }
+        if link.starts_with("/ipfs/") {
+            return None;
+        }
+        Some(self.dir.join(link)).filter(|path| path.is_file())
+    }
+}
+
 #[derive(Clone)]
 pub struct LinkResolver {
     clients: Arc<Vec<IpfsClient>>,
     cache: Arc<Mutex<LruCache<String, Vec<u8>>>>,
     timeout: Duration,
     retry: bool,
+    local: Option<Arc<LocalManifest>>,
+}
+
+impl LinkResolver {
+    /// Serve the subgraph manifest at `path` and the files it references
+    /// from the local filesystem instead of IPFS. Return the deployment id
+    /// under which the manifest can be deployed
+    pub fn with_local_manifest(
+        mut self,
+        path: &Path,
+    ) -> Result<(Self, SubgraphDeploymentId), Error> {
+        let local = LocalManifest::new(path)?;
+        let id = local.id.clone();
+        self.local = Some(Arc::new(local));
+        Ok((self, id))
+    }
+
+    fn local_file(&self, link: &Link) -> Option<PathBuf> {
+        self.local
+            .as_ref()
+            .and_then(|local| local.resolve(&link.link))
+    }
 }
 
 impl From<IpfsClient> for LinkResolver {
@@ -145,8 +219,9 @@ impl From<Vec<IpfsClient>> for LinkResolver {
             cache: Arc::new(Mutex::new(LruCache::with_capacity(
                 *MAX_IPFS_CACHE_SIZE as usize,
             ))),
-            timeout: *IPFS_TIMEOUT,
+            timeout: RetryTarget::Ipfs.policy().timeout,
             retry: false,
+            local: None,
         }
     }
 }
@@ -165,6 +240,12 @@ impl LinkResolverTrait for LinkResolver {
 
     /// Supports links of the form `/ipfs/ipfs_hash` or just `ipfs_hash`.
     async fn cat(&self, logger: &Logger, link: &Link) -> Result<Vec<u8>, Error> {
+        if let Some(file) = self.local_file(link) {
+            trace!(logger, "Reading local file"; "file" => file.display().to_string());
+            return std::fs::read(&file)
+                .map_err(|e| anyhow!("can not read `{}`: {}", file.display(), e));
+        }
+
         // Discard the `/ipfs/` prefix (if present) to get the hash.
         let path = link.link.trim_start_matches("/ipfs/").to_owned();
 
@@ -184,12 +265,7 @@ impl LinkResolverTrait for LinkResolver {
         restrict_file_size(&path, &stat, &max_file_size)?;
 
         let path = path.clone();
-        let retry_fut = if self.retry {
-            retry("ipfs.cat", &logger).no_limit()
-        } else {
-            retry("ipfs.cat", &logger).limit(1)
-        }
-        .timeout(self.timeout);
+        let retry_fut = retry_config("ipfs.cat", &logger, self.retry).timeout(self.timeout);
 
         let data = retry_fut
             .run(move || {
@@ -222,6 +298,25 @@ impl LinkResolverTrait for LinkResolver {
     }
 
     async fn json_stream(&self, logger: &Logger, link: &Link) -> Result<JsonValueStream, Error> {
+        if let Some(file) = self.local_file(link) {
+            let text = std::fs::read_to_string(&file)
+                .map_err(|e| anyhow!("can not read `{}`: {}", file.display(), e))?;
+            let values: Vec<_> = text
+                .lines()
+                .enumerate()
+                .filter(|(_, line)| !line.is_empty())
+                .map(|(idx, line)| {
+                    serde_json::from_str::<Value>(line)
+                        .map(|value| JsonStreamValue {
+                            value,
+                            line: idx + 1,
+                        })
+                        .map_err(|e| anyhow!("{} at line {}: '{}'", e, idx + 1, line))
+                })
+                .collect();
+            return Ok(Box::pin(futures03::stream::iter(values)));
+        }
+
         // Discard the `/ipfs/` prefix (if present) to get the hash.
         let path = link.link.trim_start_matches("/ipfs/");
 
@@ -294,6 +389,20 @@ impl LinkResolverTrait for LinkResolver {
 
         Ok(stream)
     }
+
+    async fn add(&self, logger: &Logger, data: Vec<u8>) -> Result<String, Error> {
+        let client = self
+            .clients
+            .first()
+            .ok_or_else(|| anyhow!("No IPFS clients were supplied to add a file to"))?;
+        let hash = client
+            .add(std::io::Cursor::new(data))
+            .await
+            .map_err(|e| anyhow!("failed to add file to IPFS: {}", e))?
+            .hash;
+        trace!(logger, "Added file to IPFS"; "hash" => &hash);
+        Ok(hash)
+    }
 }
 
 #[cfg(test)]
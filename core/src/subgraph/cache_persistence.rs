@@ -0,0 +1,345 @@
+//! Persist the hot set of a deployment's entity cache to disk so that
+//! indexing does not start with a cold cache after a restart.
+//!
+//! Persistence is turned on by setting `GRAPH_ENTITY_CACHE_PERSIST_DIR`.
+//! While a deployment is indexing, the contents of its entity cache are
+//! written to `<dir>/<deployment>.cache` every
+//! `GRAPH_ENTITY_CACHE_PERSIST_INTERVAL` seconds and when the deployment
+//! is stopped. With `GRAPH_ENTITY_CACHE_PERSIST=keys`, the default, only the
+//! keys of cached entities are written, and the entities are loaded from
+//! the store when the deployment starts. With
+//! `GRAPH_ENTITY_CACHE_PERSIST=entities`, the entities are written, too,
+//! and used as-is if the deployment is still at the block at which they
+//! were written; otherwise, they are loaded from the store just like keys.
+//!
+//! The file starts with a JSON header with the block and the keys of the
+//! cached entities, followed by the entities in the binary encoding from
+//! `graph::data::store::binary`, which is much cheaper to read and write
+//! than JSON for large entities.
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+use std::time::Instant;
+
+use graph::components::store::EntityType;
+use graph::data::store::binary::{Decoder, Encoder};
+use graph::prelude::*;
+use graph::util::lfu_cache::LfuCache;
+
+lazy_static! {
+    /// The directory in which entity caches are persisted. Caches are not
+    /// persisted if this is not set
+    static ref CACHE_DIR: Option<PathBuf> = std::env::var("GRAPH_ENTITY_CACHE_PERSIST_DIR")
+        .ok()
+        .map(PathBuf::from);
+
+    /// Whether to persist the entities in the cache, or only their keys
+    static ref PERSIST_ENTITIES: bool =
+        match std::env::var("GRAPH_ENTITY_CACHE_PERSIST").ok().as_deref() {
+            None | Some("keys") => false,
+            Some("entities") => true,
+            Some(other) => panic!(
+                "invalid GRAPH_ENTITY_CACHE_PERSIST `{}`, must be `keys` or `entities`",
+                other
+            ),
+        };
+
+    /// How often to persist the entity cache while indexing, in seconds
+    static ref PERSIST_INTERVAL: Duration = Duration::from_secs(
+        std::env::var("GRAPH_ENTITY_CACHE_PERSIST_INTERVAL")
+            .unwrap_or("300".into())
+            .parse::<u64>()
+            .expect("invalid GRAPH_ENTITY_CACHE_PERSIST_INTERVAL")
+    );
+}
+
+#[derive(Serialize, Deserialize)]
+struct CacheFile {
+    /// The block the deployment was at when the cache was written
+    block_number: u64,
+    block_hash: String,
+    /// Whether `entries` contain entities or only keys
+    has_entities: bool,
+    entries: Vec<CacheFileEntry>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct CacheFileEntry {
+    entity_type: EntityType,
+    entity_id: String,
+    /// The cached entity; `None` if only keys are persisted or the entity
+    /// does not exist in the store. Entities are written in the binary
+    /// encoding after the header
+    #[serde(skip)]
+    entity: Option<Entity>,
+}
+
+impl CacheFile {
+    fn to_bytes(&self) -> Result<Vec<u8>, Error> {
+        let mut encoder = Encoder::new();
+        encoder.write_bytes(&serde_json::to_vec(self)?);
+        if self.has_entities {
+            for entry in &self.entries {
+                encoder.write_bool(entry.entity.is_some());
+                if let Some(entity) = &entry.entity {
+                    encoder.write_entity(entity);
+                }
+            }
+        }
+        Ok(encoder.finish())
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Result<Self, Error> {
+        let mut decoder = Decoder::new(bytes);
+        let mut file: CacheFile = serde_json::from_slice(decoder.read_bytes()?)?;
+        if file.has_entities {
+            for entry in file.entries.iter_mut() {
+                if decoder.read_bool()? {
+                    entry.entity = Some(decoder.read_entity()?);
+                }
+            }
+        }
+        if !decoder.is_done() {
+            return Err(anyhow!(
+                "unexpected data at the end of the entity cache file"
+            ));
+        }
+        Ok(file)
+    }
+}
+
+/// Writes the entity cache of one deployment to disk periodically and
+/// loads it back when the deployment starts
+pub(crate) struct CachePersistence {
+    path: Option<PathBuf>,
+    last_write: Instant,
+}
+
+impl CachePersistence {
+    pub fn new(deployment: &SubgraphDeploymentId) -> Self {
+        CachePersistence {
+            path: CACHE_DIR
+                .as_ref()
+                .map(|dir| dir.join(format!("{}.cache", deployment))),
+            last_write: Instant::now(),
+        }
+    }
+
+    /// Load the persisted cache for `deployment`. Any problem with reading
+    /// the file is logged and results in an empty cache, since the cache
+    /// only affects performance, not correctness
+    pub fn load<S: SubgraphStore + ?Sized>(
+        &self,
+        logger: &Logger,
+        store: &S,
+        deployment: &SubgraphDeploymentId,
+    ) -> LfuCache<EntityKey, Option<Entity>> {
+        let path = match &self.path {
+            Some(path) if path.exists() => path,
+            _ => return LfuCache::new(),
+        };
+        let start = Instant::now();
+        match Self::read(store, deployment, path) {
+            Ok(cache) => {
+                info!(logger, "Loaded persisted entity cache";
+                      "entities" => cache.len(),
+                      "time_ms" => start.elapsed().as_millis());
+                cache
+            }
+            Err(e) => {
+                warn!(logger, "Failed to load persisted entity cache";
+                      "path" => path.display().to_string(),
+                      "error" => e.to_string());
+                LfuCache::new()
+            }
+        }
+    }
+
+    fn read<S: SubgraphStore + ?Sized>(
+        store: &S,
+        deployment: &SubgraphDeploymentId,
+        path: &PathBuf,
+    ) -> Result<LfuCache<EntityKey, Option<Entity>>, Error> {
+        let file = CacheFile::from_bytes(&fs::read(path)?)?;
+        let mut cache = LfuCache::new();
+
+        // Persisted entities can only be used if nothing has been written
+        // to the deployment since they were persisted
+        let current = store.block_ptr(deployment)?;
+        let fresh = current
+            .map(|ptr| ptr.number == file.block_number && ptr.hash_hex() == file.block_hash)
+            .unwrap_or(false);
+        if file.has_entities && fresh {
+            for entry in file.entries {
+                let key = EntityKey {
+                    subgraph_id: deployment.clone(),
+                    entity_type: entry.entity_type,
+                    entity_id: entry.entity_id,
+                };
+                cache.insert(key, entry.entity);
+            }
+            return Ok(cache);
+        }
+
+        let mut ids_for_type: BTreeMap<&EntityType, Vec<&str>> = BTreeMap::new();
+        for entry in &file.entries {
+            ids_for_type
+                .entry(&entry.entity_type)
+                .or_default()
+                .push(&entry.entity_id);
+        }
+        let mut found = store.get_many(deployment, ids_for_type, BLOCK_NUMBER_MAX)?;
+        for entry in &file.entries {
+            let key = EntityKey {
+                subgraph_id: deployment.clone(),
+                entity_type: entry.entity_type.clone(),
+                entity_id: entry.entity_id.clone(),
+            };
+            cache.insert(key, None);
+        }
+        for (entity_type, entities) in found.iter_mut() {
+            for mut entity in entities.drain(..) {
+                // `__typename` is for queries not for mappings.
+                entity.remove("__typename");
+                let key = EntityKey {
+                    subgraph_id: deployment.clone(),
+                    entity_type: entity_type.clone(),
+                    entity_id: entity.id()?,
+                };
+                cache.insert(key, Some(entity));
+            }
+        }
+        Ok(cache)
+    }
+
+    /// Write `cache` to disk if persistence is turned on and `force` is
+    /// set or the last write was long enough ago. `block_ptr` must be the
+    /// block that the deployment is at. The file is serialized and written
+    /// on a blocking thread so that it does not hold up the executor
+    pub async fn persist(
+        &mut self,
+        logger: &Logger,
+        cache: &LfuCache<EntityKey, Option<Entity>>,
+        block_ptr: &EthereumBlockPointer,
+        force: bool,
+    ) {
+        let path = match &self.path {
+            Some(path) => path.clone(),
+            None => return,
+        };
+        // An empty cache is not worth keeping, and would only overwrite a
+        // more useful one from an earlier write
+        if cache.is_empty() || (!force && self.last_write.elapsed() < *PERSIST_INTERVAL) {
+            return;
+        }
+        self.last_write = Instant::now();
+
+        let file = CacheFile {
+            block_number: block_ptr.number,
+            block_hash: block_ptr.hash_hex(),
+            has_entities: *PERSIST_ENTITIES,
+            entries: cache
+                .iter()
+                .map(|(key, entity)| CacheFileEntry {
+                    entity_type: key.entity_type.clone(),
+                    entity_id: key.entity_id.clone(),
+                    entity: if *PERSIST_ENTITIES {
+                        entity.clone()
+                    } else {
+                        None
+                    },
+                })
+                .collect(),
+        };
+
+        // Write to a temporary file first so that a crash while writing
+        // does not leave a truncated cache file behind
+        let display = path.display().to_string();
+        let res = graph::spawn_blocking_allow_panic(move || {
+            let tmp = path.with_extension("cache.tmp");
+            file.to_bytes()
+                .and_then(|data| fs::write(&tmp, data).map_err(Error::from))
+                .and_then(|()| fs::rename(&tmp, &path).map_err(Error::from))
+        })
+        .await
+        .map_err(Error::from)
+        .and_then(|res| res);
+        if let Err(e) = res {
+            warn!(logger, "Failed to persist entity cache";
+                  "path" => display,
+                  "error" => e.to_string());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_store::*;
+
+    const SCHEMA: &str = "type Thing @entity { id: ID!, name: String! }";
+
+    fn thing(id: &str, name: &str) -> Entity {
+        let mut thing = Entity::new();
+        thing.set("id", id);
+        thing.set("name", name);
+        thing
+    }
+
+    #[test]
+    fn load_persisted_entities() {
+        std::env::set_var("GRAPH_ENTITY_CACHE_PERSIST", "entities");
+        let deployment = SubgraphDeploymentId::new("cachePersistence").unwrap();
+
+        run_test_sequentially(
+            || (),
+            move |store, ()| async move {
+                create_test_subgraph(&deployment, SCHEMA);
+                insert_entities(
+                    deployment.clone(),
+                    vec![(EntityType::data("Thing".to_owned()), thing("t1", "store"))],
+                )
+                .unwrap();
+
+                let key = |id: &str| EntityKey {
+                    subgraph_id: deployment.clone(),
+                    entity_type: EntityType::data("Thing".to_owned()),
+                    entity_id: id.to_owned(),
+                };
+                let mut cache = LfuCache::new();
+                cache.insert(key("t1"), Some(thing("t1", "cached")));
+                cache.insert(key("t2"), None);
+
+                let path = std::env::temp_dir().join(format!("{}.cache", deployment));
+                let mut persistence = CachePersistence {
+                    path: Some(path.clone()),
+                    last_write: Instant::now(),
+                };
+
+                // The deployment is still at the block at which the cache
+                // was written, and the persisted entities are used as-is
+                persistence
+                    .persist(&*LOGGER, &cache, &GENESIS_PTR, true)
+                    .await;
+                let mut loaded = persistence.load(&*LOGGER, store.as_ref(), &deployment);
+                assert_eq!(2, loaded.len());
+                assert_eq!(Some(&Some(thing("t1", "cached"))), loaded.get(&key("t1")));
+                assert_eq!(Some(&None), loaded.get(&key("t2")));
+
+                // The cache was written at a different block, and the
+                // entities are loaded from the store
+                persistence
+                    .persist(&*LOGGER, &cache, &BLOCK_ONE, true)
+                    .await;
+                let mut loaded = persistence.load(&*LOGGER, store.as_ref(), &deployment);
+                assert_eq!(2, loaded.len());
+                assert_eq!(Some(&Some(thing("t1", "store"))), loaded.get(&key("t1")));
+                assert_eq!(Some(&None), loaded.get(&key("t2")));
+
+                fs::remove_file(path).unwrap();
+            },
+        )
+    }
+}
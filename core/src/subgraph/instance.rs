@@ -1,12 +1,14 @@
 use async_trait::async_trait;
 use futures01::sync::mpsc::Sender;
 use lazy_static::lazy_static;
+use semver::Version;
 
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::env;
 use std::str::FromStr;
 
 use graph::components::subgraph::{MappingError, SharedProofOfIndexing};
+use graph::data::subgraph::limits::EntityLimits;
 use graph::prelude::{SubgraphInstance as SubgraphInstanceTrait, *};
 use web3::types::{Log, H256};
 
@@ -22,6 +24,13 @@ pub struct SubgraphInstance<T: RuntimeHostBuilder> {
     network: String,
     host_builder: T,
 
+    /// The context that operators set for the deployment, which mappings
+    /// can read with `deployment.context()`
+    deployment_context: Arc<BTreeMap<String, String>>,
+
+    /// The limits from the manifest on the values that mappings can store
+    entity_limits: EntityLimits,
+
     /// Runtime hosts, one for each data source mapping.
     ///
     /// The runtime hosts are created and added in the same order the
@@ -30,7 +39,7 @@ pub struct SubgraphInstance<T: RuntimeHostBuilder> {
     hosts: Vec<Arc<T::Host>>,
 
     /// Maps the hash of a module to a channel to the thread in which the module is instantiated.
-    module_cache: HashMap<[u8; 32], Sender<T::Req>>,
+    module_cache: HashMap<([u8; 32], Version), Sender<T::Req>>,
 }
 
 impl<T> SubgraphInstance<T>
@@ -42,15 +51,19 @@ where
         manifest: SubgraphManifest,
         host_builder: T,
         host_metrics: Arc<HostMetrics>,
+        deployment_context: Arc<BTreeMap<String, String>>,
     ) -> Result<Self, Error> {
         let subgraph_id = manifest.id.clone();
         let network = manifest.network_name();
         let templates = Arc::new(manifest.templates);
+        let entity_limits = manifest.limits;
 
         let mut this = SubgraphInstance {
             host_builder,
             subgraph_id,
             network,
+            deployment_context,
+            entity_limits,
             hosts: Vec::new(),
             module_cache: HashMap::new(),
         };
@@ -92,17 +105,19 @@ where
     ) -> Result<T::Host, Error> {
         let mapping_request_sender = {
             let module_bytes = data_source.mapping.runtime.as_ref();
-            let module_hash = tiny_keccak::keccak256(module_bytes);
-            if let Some(sender) = self.module_cache.get(&module_hash) {
+            let api_version = Version::parse(&data_source.mapping.api_version)?;
+            let key = (tiny_keccak::keccak256(module_bytes), api_version);
+            if let Some(sender) = self.module_cache.get(&key) {
                 sender.clone()
             } else {
                 let sender = T::spawn_mapping(
                     module_bytes.clone(),
+                    &key.1,
                     logger,
                     self.subgraph_id.clone(),
                     host_metrics.clone(),
                 )?;
-                self.module_cache.insert(module_hash, sender.clone());
+                self.module_cache.insert(key, sender.clone());
                 sender
             }
         };
@@ -111,6 +126,8 @@ where
             self.subgraph_id.clone(),
             data_source,
             templates,
+            self.deployment_context.clone(),
+            self.entity_limits,
             mapping_request_sender,
             host_metrics,
         )
@@ -127,6 +144,15 @@ where
         self.hosts.iter().any(|host| host.matches_log(log))
     }
 
+    fn is_commutative_log(&self, log: &Log) -> bool {
+        let mut hosts = self
+            .hosts
+            .iter()
+            .filter(|host| host.matches_log(log))
+            .peekable();
+        hosts.peek().is_some() && hosts.all(|host| host.is_commutative_log(log))
+    }
+
     async fn process_trigger(
         &self,
         logger: &Logger,
@@ -154,6 +180,18 @@ where
         mut state: BlockState,
         proof_of_indexing: SharedProofOfIndexing,
     ) -> Result<BlockState, MappingError> {
+        // Remember enough about the trigger to identify it in the dead-letter
+        // table should any of its handlers get skipped
+        let errors_before = state.deterministic_errors.len();
+        let (transaction_hash, log_index) = match &trigger {
+            EthereumTrigger::Log(log) => (
+                log.transaction_hash,
+                log.log_index.map(|index| index.as_u64()),
+            ),
+            EthereumTrigger::Call(call) => (call.transaction_hash, None),
+            EthereumTrigger::Block(_, _) => (None, None),
+        };
+
         match trigger {
             EthereumTrigger::Log(log) => {
                 let log = Arc::new(log);
@@ -258,6 +296,7 @@ where
                 }
             }
         }
+        state.record_skipped_triggers(errors_before, transaction_hash, log_index);
         Ok(state)
     }
 
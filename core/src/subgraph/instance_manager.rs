@@ -2,17 +2,20 @@ use atomic_refcell::AtomicRefCell;
 use fail::fail_point;
 use futures01::sync::mpsc::{channel, Receiver, Sender};
 use lazy_static::lazy_static;
-use std::collections::{BTreeSet, HashMap, HashSet};
+use std::collections::{BTreeSet, HashMap, HashSet, VecDeque};
 use std::sync::{Arc, RwLock};
-use std::time::Instant;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 use graph::components::store::{BlockStore, ModificationsAndCache};
-use graph::components::subgraph::{MappingError, ProofOfIndexing, SharedProofOfIndexing};
+use graph::components::subgraph::{
+    MappingError, ProofOfIndexing, ProofOfIndexingVersion, SharedProofOfIndexing,
+};
 use graph::components::{
     ethereum::{triggers_in_block, EthereumNetworks},
     store::EntityType,
 };
 use graph::data::store::scalar::Bytes;
+use graph::data::subgraph::canary::{CanaryAssertion, OnMismatch};
 use graph::data::subgraph::schema::{
     DynamicEthereumContractDataSourceEntity, SubgraphError, POI_OBJECT,
 };
@@ -20,6 +23,7 @@ use graph::data::subgraph::SubgraphFeature;
 use graph::prelude::{SubgraphInstance as SubgraphInstanceTrait, *};
 use graph::util::lfu_cache::LfuCache;
 
+use super::cache_persistence::CachePersistence;
 use super::SubgraphInstance;
 
 lazy_static! {
@@ -31,6 +35,15 @@ lazy_static! {
             .parse::<usize>()
             .expect("invalid GRAPH_ENTITY_CACHE_SIZE");
 
+    /// How often to compare a deployment to the chain head to update the
+    /// `deployment_head_lag_*` gauges, in milliseconds
+    static ref HEAD_LAG_UPDATE_INTERVAL: Duration = Duration::from_millis(
+        std::env::var("GRAPH_HEAD_LAG_UPDATE_INTERVAL")
+            .unwrap_or("1000".into())
+            .parse::<u64>()
+            .expect("invalid GRAPH_HEAD_LAG_UPDATE_INTERVAL")
+    );
+
     // Keep deterministic errors non-fatal even if the subgraph is pending.
     // Used for testing Graph Node itself.
     pub static ref DISABLE_FAIL_FAST: bool =
@@ -42,6 +55,7 @@ type SharedInstanceKeepAliveMap = Arc<RwLock<HashMap<SubgraphDeploymentId, Cance
 struct IndexingInputs<B, S, C> {
     deployment_id: SubgraphDeploymentId,
     features: BTreeSet<SubgraphFeature>,
+    poi_versions: Vec<ProofOfIndexingVersion>,
     network_name: String,
     start_blocks: Vec<u64>,
     store: Arc<S>,
@@ -49,7 +63,11 @@ struct IndexingInputs<B, S, C> {
     eth_adapter: Arc<dyn EthereumAdapter>,
     stream_builder: B,
     include_calls_in_blocks: bool,
+    confirmations: u64,
     templates: Arc<Vec<DataSourceTemplate>>,
+    on_canary_mismatch: OnMismatch,
+    /// The entity types with an `@appendOnly` directive
+    append_only_types: Arc<BTreeSet<EntityType>>,
 }
 
 struct IndexingState<T: RuntimeHostBuilder> {
@@ -60,6 +78,11 @@ struct IndexingState<T: RuntimeHostBuilder> {
     call_filter: EthereumCallFilter,
     block_filter: EthereumBlockFilter,
     entity_lfu_cache: LfuCache<EntityKey, Option<Entity>>,
+    missing_entities: Arc<MissingEntityCache>,
+    cache_persistence: CachePersistence,
+    /// The canary assertions that have not been checked yet, ordered by
+    /// block
+    canaries: VecDeque<CanaryAssertion>,
 }
 
 struct IndexingContext<B, T: RuntimeHostBuilder, S, C> {
@@ -79,6 +102,9 @@ struct IndexingContext<B, T: RuntimeHostBuilder, S, C> {
     pub ethrpc_metrics: Arc<SubgraphEthRpcMetrics>,
 
     pub block_stream_metrics: Arc<BlockStreamMetrics>,
+
+    /// Memory accounting for the indexing pipeline
+    pub memory: Arc<DeploymentMemory>,
 }
 
 pub struct SubgraphInstanceManager {
@@ -123,12 +149,16 @@ struct SubgraphInstanceMetrics {
     pub block_trigger_count: Box<Histogram>,
     pub block_processing_duration: Box<Histogram>,
     pub block_ops_transaction_duration: Box<Histogram>,
+    pub noop_block_count: Box<Counter>,
+    pub unchanged_entity_count: Box<Counter>,
+    pub head_lag_blocks: Box<Gauge>,
+    pub head_lag_seconds: Box<Gauge>,
 
     trigger_processing_duration: Box<HistogramVec>,
 }
 
 impl SubgraphInstanceMetrics {
-    pub fn new(registry: Arc<impl MetricsRegistry>, subgraph_hash: &str) -> Self {
+    pub fn new(registry: Arc<impl MetricsRegistry>, subgraph_hash: &str, network: &str) -> Self {
         let block_trigger_count = registry
             .new_deployment_histogram(
                 "deployment_block_trigger_count",
@@ -162,15 +192,71 @@ impl SubgraphInstanceMetrics {
                 vec![0.01, 0.05, 0.1, 0.3, 0.7, 2.0],
             )
             .expect("failed to create `deployment_transact_block_operations_duration_{}");
+        let noop_block_count = registry
+            .new_deployment_counter(
+                "deployment_noop_block_count",
+                "Counts the blocks that did not change any entities for a subgraph deployment",
+                subgraph_hash,
+            )
+            .expect("failed to create `deployment_noop_block_count` counter");
+        let unchanged_entity_count = registry
+            .new_deployment_counter(
+                "deployment_unchanged_entity_count",
+                "Counts the entity changes that were not written because they left the entity unchanged",
+                subgraph_hash,
+            )
+            .expect("failed to create `deployment_unchanged_entity_count` counter");
+        let head_lag_labels: HashMap<_, _> = vec![
+            (String::from("deployment"), String::from(subgraph_hash)),
+            (String::from("network"), String::from(network)),
+        ]
+        .into_iter()
+        .collect();
+        let head_lag_blocks = registry
+            .new_gauge(
+                "deployment_head_lag_blocks",
+                "The number of blocks between the chain head and the latest block processed by a subgraph deployment",
+                head_lag_labels.clone(),
+            )
+            .expect("failed to create `deployment_head_lag_blocks` gauge");
+        let head_lag_seconds = registry
+            .new_gauge(
+                "deployment_head_lag_seconds",
+                "The number of seconds since the latest block processed by a subgraph deployment was mined",
+                head_lag_labels,
+            )
+            .expect("failed to create `deployment_head_lag_seconds` gauge");
 
         Self {
             block_trigger_count,
             block_processing_duration,
             trigger_processing_duration,
             block_ops_transaction_duration,
+            noop_block_count,
+            unchanged_entity_count,
+            head_lag_blocks,
+            head_lag_seconds,
         }
     }
 
+    /// Update the head lag gauges for a deployment that has just processed
+    /// the block `block_ptr` with timestamp `block_timestamp`
+    pub fn observe_head_lag(
+        &self,
+        head_ptr: &EthereumBlockPointer,
+        block_ptr: &EthereumBlockPointer,
+        block_timestamp: u64,
+    ) {
+        let blocks = head_ptr.number.saturating_sub(block_ptr.number);
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|now| now.as_secs())
+            .unwrap_or(0);
+        self.head_lag_blocks.set(blocks as f64);
+        self.head_lag_seconds
+            .set(now.saturating_sub(block_timestamp) as f64);
+    }
+
     pub fn observe_trigger_processing_duration(&self, duration: f64, trigger: TriggerType) {
         self.trigger_processing_duration
             .with_label_values(vec![trigger.label_value()].as_slice())
@@ -182,6 +268,10 @@ impl SubgraphInstanceMetrics {
         registry.unregister(self.block_trigger_count.clone());
         registry.unregister(self.trigger_processing_duration.clone());
         registry.unregister(self.block_ops_transaction_duration.clone());
+        registry.unregister(self.noop_block_count.clone());
+        registry.unregister(self.unchanged_entity_count.clone());
+        registry.unregister(self.head_lag_blocks.clone());
+        registry.unregister(self.head_lag_seconds.clone());
     }
 }
 
@@ -351,8 +441,35 @@ impl SubgraphInstanceManager {
         // block handlers with call filters; in this case, we need to
         // include calls in all blocks
         let include_calls_in_blocks = manifest.requires_traces();
+        let confirmations = manifest.confirmations;
 
         let templates = Arc::new(manifest.templates.clone());
+        let append_only_types = Arc::new(
+            manifest
+                .schema
+                .append_only_types()
+                .into_iter()
+                .map(EntityType::data)
+                .collect(),
+        );
+
+        // Assertions for blocks that the deployment has already processed
+        // were checked when it processed them
+        let (canaries, on_canary_mismatch) = match &manifest.canaries {
+            Some(canaries) => {
+                let processed = store
+                    .block_ptr(&deployment_id)?
+                    .map(|ptr| ptr.number as BlockNumber);
+                let pending = canaries
+                    .assertions
+                    .iter()
+                    .filter(|assertion| processed.map_or(true, |block| assertion.block > block))
+                    .cloned()
+                    .collect();
+                (pending, canaries.on_mismatch)
+            }
+            None => (VecDeque::new(), OnMismatch::default()),
+        };
 
         // Create a subgraph instance from the manifest; this moves
         // ownership of the manifest and host builder into the new instance
@@ -361,6 +478,7 @@ impl SubgraphInstanceManager {
         let subgraph_metrics = Arc::new(SubgraphInstanceMetrics::new(
             registry.clone(),
             deployment_id.as_str(),
+            &network_name,
         ));
         let subgraph_metrics_unregister = subgraph_metrics.clone();
         let host_metrics = Arc::new(HostMetrics::new(
@@ -375,15 +493,29 @@ impl SubgraphInstanceManager {
             &deployment_id,
             stopwatch_metrics,
         ));
+        let memory = Arc::new(DeploymentMemory::new(registry.clone(), &deployment_id));
+        let cache_persistence = CachePersistence::new(&deployment_id);
+        let entity_lfu_cache = cache_persistence.load(&logger, store.as_ref(), &deployment_id);
+        let missing_entities = Arc::new(MissingEntityCache::new(registry.clone(), &deployment_id));
         let features = manifest.features.clone();
-        let instance =
-            SubgraphInstance::from_manifest(&logger, manifest, host_builder, host_metrics.clone())?;
+        let poi_versions = store.proof_of_indexing_versions(&deployment_id)?;
+        // Changes to the context only reach the mappings when the
+        // deployment is restarted
+        let deployment_context = Arc::new(store.deployment_context(&deployment_id)?);
+        let instance = SubgraphInstance::from_manifest(
+            &logger,
+            manifest,
+            host_builder,
+            host_metrics.clone(),
+            deployment_context,
+        )?;
 
         // The subgraph state tracks the state of the subgraph instance over time
         let ctx = IndexingContext {
             inputs: IndexingInputs {
                 deployment_id: deployment_id.clone(),
                 features,
+                poi_versions,
                 network_name,
                 start_blocks,
                 chain_store,
@@ -391,7 +523,10 @@ impl SubgraphInstanceManager {
                 eth_adapter,
                 stream_builder,
                 include_calls_in_blocks,
+                confirmations,
                 templates,
+                on_canary_mismatch,
+                append_only_types,
             },
             state: IndexingState {
                 logger,
@@ -400,12 +535,16 @@ impl SubgraphInstanceManager {
                 log_filter,
                 call_filter,
                 block_filter,
-                entity_lfu_cache: LfuCache::new(),
+                entity_lfu_cache,
+                missing_entities,
+                cache_persistence,
+                canaries,
             },
             subgraph_metrics,
             host_metrics,
             ethrpc_metrics,
             block_stream_metrics,
+            memory,
         };
 
         // Keep restarting the subgraph until it terminates. The subgraph
@@ -456,6 +595,13 @@ where
     let logger = ctx.state.logger.cheap_clone();
     let id_for_err = ctx.inputs.deployment_id.clone();
 
+    // The block the deployment is at, if it is known to be the block the
+    // entity cache reflects
+    let mut cache_block_ptr: Option<EthereumBlockPointer> = None;
+
+    // When the head lag gauges were last updated
+    let mut head_lag_updated: Option<Instant> = None;
+
     loop {
         debug!(logger, "Starting or restarting subgraph");
 
@@ -473,7 +619,9 @@ where
                 ctx.state.call_filter.clone(),
                 ctx.state.block_filter.clone(),
                 ctx.inputs.include_calls_in_blocks,
+                ctx.inputs.confirmations,
                 ctx.block_stream_metrics.clone(),
+                ctx.memory.clone(),
             )
             .map_err(CancelableError::Error)
             .cancelable(&block_stream_canceler, || CancelableError::Cancel)
@@ -503,7 +651,7 @@ where
 
                     // We would like to revert the DB state to the parent of the current block.
                     // First, load the block in order to get the parent hash.
-                    if let Err(e) = ctx
+                    let parent_ptr = ctx
                         .inputs
                         .eth_adapter
                         .load_blocks(
@@ -516,24 +664,31 @@ where
                         .await
                         .map(|blocks| {
                             assert_eq!(blocks.len(), 1);
-                            blocks.into_iter().next().unwrap()
-                        })
-                        .and_then(|block| {
+                            let block = blocks.into_iter().next().unwrap();
                             // Produce pointer to parent block (using parent hash).
-                            let parent_ptr = block
+                            block
                                 .parent_ptr()
-                                .expect("genesis block cannot be reverted");
-
-                            // Revert entity changes from this block, and update subgraph ptr.
-                            ctx.inputs
-                                .store
-                                .revert_block_operations(
-                                    ctx.inputs.deployment_id.clone(),
-                                    parent_ptr,
-                                )
-                                .map_err(Into::into)
-                        })
-                    {
+                                .expect("genesis block cannot be reverted")
+                        });
+
+                    // Revert entity changes from this block, and update
+                    // subgraph ptr. Like writes, reverts are retried while
+                    // the database fails over and therefore happen on a
+                    // blocking thread
+                    let reverted = match parent_ptr {
+                        Ok(parent_ptr) => {
+                            let store = ctx.inputs.store.cheap_clone();
+                            let id = ctx.inputs.deployment_id.clone();
+                            graph::spawn_blocking_allow_panic(move || {
+                                store.revert_block_operations(id, parent_ptr)
+                            })
+                            .await
+                            .map_err(|e| anyhow!("reverting the block failed: {}", e))
+                            .and_then(|result| result.map_err(Into::into))
+                        }
+                        Err(e) => Err(e),
+                    };
+                    if let Err(e) = reverted {
                         debug!(
                             &logger,
                             "Block stream produced a non-fatal error";
@@ -548,7 +703,8 @@ where
 
                     // Revert the in-memory state:
                     // - Remove hosts for reverted dynamic data sources.
-                    // - Clear the entity cache.
+                    // - Clear the entity cache and forget which entities are missing
+                    //   since reverting may have restored them.
                     //
                     // Note that we do not currently revert the filters, which means the filters
                     // will be broader than necessary. This is not ideal for performance, but is not
@@ -556,11 +712,29 @@ where
                     // match any data sources.
                     ctx.state.instance.revert_data_sources(subgraph_ptr.number);
                     ctx.state.entity_lfu_cache = LfuCache::new();
+                    ctx.state.missing_entities.clear();
+                    cache_block_ptr = None;
                     continue;
                 }
                 // Log and drop the errors from the block_stream
                 // The block stream will continue attempting to produce blocks
                 Some(Err(e)) => {
+                    if block_stream_cancel_handle.is_canceled() {
+                        // The subgraph was stopped; save the entity cache
+                        // for the next time it starts
+                        if let Some(ptr) = &cache_block_ptr {
+                            ctx.state
+                                .cache_persistence
+                                .persist(&logger, &ctx.state.entity_lfu_cache, ptr, true)
+                                .await;
+                        }
+                        debug!(
+                            &logger,
+                            "Subgraph block stream shut down cleanly";
+                            "id" => id_for_err.to_string(),
+                        );
+                        return Err(());
+                    }
                     debug!(
                         &logger,
                         "Block stream produced a non-fatal error";
@@ -572,6 +746,7 @@ where
             };
 
             let block_ptr = EthereumBlockPointer::from(&block.ethereum_block);
+            let block_timestamp = block.ethereum_block.block.timestamp.low_u64();
 
             if block.triggers.len() > 0 {
                 subgraph_metrics
@@ -596,6 +771,29 @@ where
             match res {
                 Ok((c, needs_restart)) => {
                     ctx = c;
+                    if head_lag_updated.map_or(true, |updated| {
+                        updated.elapsed() >= *HEAD_LAG_UPDATE_INTERVAL
+                    }) {
+                        match ctx.inputs.chain_store.chain_head_ptr() {
+                            Ok(Some(head_ptr)) => subgraph_metrics.observe_head_lag(
+                                &head_ptr,
+                                &block_ptr,
+                                block_timestamp,
+                            ),
+                            Ok(None) => {}
+                            Err(e) => debug!(
+                                &logger,
+                                "Failed to load the chain head to update the head lag";
+                                "error" => e.to_string(),
+                            ),
+                        }
+                        head_lag_updated = Some(Instant::now());
+                    }
+                    ctx.state
+                        .cache_persistence
+                        .persist(&logger, &ctx.state.entity_lfu_cache, &block_ptr, false)
+                        .await;
+                    cache_block_ptr = Some(block_ptr);
                     if needs_restart {
                         // Cancel the stream for real
                         ctx.state
@@ -726,6 +924,7 @@ where
     {
         Some(Arc::new(AtomicRefCell::new(ProofOfIndexing::new(
             block_ptr.number,
+            &ctx.inputs.poi_versions,
         ))))
     } else {
         None
@@ -738,7 +937,9 @@ where
         BlockState::new(
             ctx.inputs.store.clone(),
             std::mem::take(&mut ctx.state.entity_lfu_cache),
-        ),
+        )
+        .with_missing_entities(ctx.state.missing_entities.cheap_clone())
+        .with_append_only_types(ctx.inputs.append_only_types.clone()),
         proof_of_indexing.cheap_clone(),
         ctx.subgraph_metrics.clone(),
         &ctx.state.instance,
@@ -895,6 +1096,7 @@ where
     let ModificationsAndCache {
         modifications: mods,
         entity_lfu_cache: mut cache,
+        unchanged,
     } = block_state
         .entity_cache
         .as_modifications(ctx.inputs.store.as_ref())
@@ -905,14 +1107,18 @@ where
         .host_metrics
         .stopwatch
         .start_section("entity_cache_evict");
-    cache.evict(*ENTITY_CACHE_SIZE);
+    cache.evict(ctx.memory.entity_cache_budget(*ENTITY_CACHE_SIZE));
+    ctx.memory.set_entity_cache(cache.total_weight());
     section.end();
 
     // Put the cache back in the ctx, asserting that the placeholder cache was not used.
     assert!(ctx.state.entity_lfu_cache.is_empty());
     ctx.state.entity_lfu_cache = cache;
 
-    if !mods.is_empty() {
+    metrics.unchanged_entity_count.inc_by(unchanged as f64);
+    if mods.is_empty() {
+        metrics.noop_block_count.inc();
+    } else {
         info!(&logger, "Applying {} entity operation(s)", mods.len());
     }
 
@@ -932,13 +1138,29 @@ where
                 .map_err(BlockProcessingError::Unknown)?)
     };
 
-    match ctx.inputs.store.transact_block_operations(
-        subgraph_id.cheap_clone(),
-        block_ptr_after,
-        mods,
-        stopwatch,
-        block_state.deterministic_errors,
-    ) {
+    // The store keeps retrying the write while the database fails over,
+    // which can take a while, and must therefore not happen on one of the
+    // threads that run futures
+    let transacted = {
+        let store = ctx.inputs.store.cheap_clone();
+        let subgraph_id = subgraph_id.cheap_clone();
+        let deterministic_errors = block_state.deterministic_errors;
+        let skipped_triggers = block_state.skipped_triggers;
+        graph::spawn_blocking_allow_panic(move || {
+            store.transact_block_operations(
+                subgraph_id,
+                block_ptr_after,
+                mods,
+                stopwatch,
+                deterministic_errors,
+                skipped_triggers,
+            )
+        })
+        .await
+        .map_err(|e| anyhow!("transacting block {} failed: {}", block_ptr_after.number, e))?
+    };
+
+    match transacted {
         Ok(_) => {
             let elapsed = start.elapsed().as_secs_f64();
             metrics.block_ops_transaction_duration.observe(elapsed);
@@ -955,6 +1177,8 @@ where
                 return Err(BlockProcessingError::Canceled);
             }
 
+            check_canaries(&logger, &mut ctx, block_ptr_after.number as BlockNumber)?;
+
             Ok((ctx, needs_restart))
         }
 
@@ -962,6 +1186,59 @@ where
     }
 }
 
+/// Check the canary assertions for all blocks up to and including `block`.
+/// The block stream skips blocks without triggers, so assertions for such
+/// blocks are checked with the next block that gets processed, by querying
+/// the store at the block of the assertion
+fn check_canaries<B, T: RuntimeHostBuilder, S: SubgraphStore, C>(
+    logger: &Logger,
+    ctx: &mut IndexingContext<B, T, S, C>,
+    block: BlockNumber,
+) -> Result<(), BlockProcessingError> {
+    while ctx
+        .state
+        .canaries
+        .front()
+        .map_or(false, |assertion| assertion.block <= block)
+    {
+        let assertion = ctx.state.canaries.pop_front().unwrap();
+        let query = EntityQuery::new(
+            ctx.inputs.deployment_id.clone(),
+            assertion.block,
+            EntityCollection::All(vec![assertion.entity.clone()]),
+        )
+        .filter(EntityFilter::Equal(
+            "id".to_owned(),
+            Value::String(assertion.id.clone()),
+        ));
+        let entity = ctx
+            .inputs
+            .store
+            .find_one(query)
+            .map_err(|e| BlockProcessingError::Unknown(e.into()))?;
+
+        match assertion.check(entity.as_ref()) {
+            Ok(()) => debug!(logger, "Canary assertion holds";
+                             "canary_block" => assertion.block,
+                             "entity" => &assertion.entity,
+                             "id" => &assertion.id),
+            Err(message) => match ctx.inputs.on_canary_mismatch {
+                OnMismatch::Warn => warn!(logger, "Canary assertion failed: {}", message),
+                OnMismatch::Fail => {
+                    return Err(BlockProcessingError::Deterministic(SubgraphError {
+                        subgraph_id: ctx.inputs.deployment_id.clone(),
+                        message: format!("canary assertion failed: {}", message),
+                        block_ptr: None,
+                        handler: None,
+                        deterministic: true,
+                    }))
+                }
+            },
+        }
+    }
+    Ok(())
+}
+
 /// Transform the proof of indexing changes into entity updates that will be
 /// inserted when as_modifications is called.
 async fn update_proof_of_indexing(
@@ -974,12 +1251,13 @@ async fn update_proof_of_indexing(
 
     let mut proof_of_indexing = proof_of_indexing.take();
 
-    for (causality_region, stream) in proof_of_indexing.drain() {
+    for (digest_id, stream) in proof_of_indexing.drain() {
         // Create the special POI entity key specific to this causality_region
+        // and PoI version
         let entity_key = EntityKey {
             subgraph_id: deployment_id.clone(),
             entity_type: EntityType::data(POI_OBJECT.to_owned()),
-            entity_id: causality_region,
+            entity_id: digest_id,
         };
 
         // Grab the current digest attribute on this entity
@@ -1003,7 +1281,7 @@ async fn update_proof_of_indexing(
             digest: updated_proof_of_indexing,
         };
 
-        entity_cache.set(entity_key, new_poi_entity);
+        entity_cache.set(entity_key, new_poi_entity)?;
     }
 
     Ok(())
@@ -1018,43 +1296,179 @@ async fn process_triggers(
     block: &Arc<LightEthereumBlock>,
     triggers: Vec<EthereumTrigger>,
 ) -> Result<BlockState, MappingError> {
-    for trigger in triggers.into_iter() {
-        let block_ptr = EthereumBlockPointer::from(block.as_ref());
-        let trigger_type = match trigger {
-            EthereumTrigger::Log(_) => TriggerType::Event,
-            EthereumTrigger::Call(_) => TriggerType::Call,
-            EthereumTrigger::Block(..) => TriggerType::Block,
-        };
-        let transaction_id = match &trigger {
-            EthereumTrigger::Log(log) => log.transaction_hash,
-            EthereumTrigger::Call(call) => call.transaction_hash,
-            EthereumTrigger::Block(..) => None,
-        };
-        let start = Instant::now();
-        block_state = instance
-            .process_trigger(
-                &logger,
-                &block,
-                trigger,
+    let is_commutative = |trigger: &EthereumTrigger| match trigger {
+        EthereumTrigger::Log(log) => instance.is_commutative_log(log),
+        EthereumTrigger::Call(_) | EthereumTrigger::Block(..) => false,
+    };
+
+    let mut triggers = triggers.into_iter().peekable();
+    while let Some(trigger) = triggers.next() {
+        // Consecutive events whose handlers are all commutative are
+        // processed together
+        let mut group = vec![trigger];
+        if is_commutative(&group[0]) {
+            while triggers
+                .peek()
+                .map_or(false, |trigger| is_commutative(trigger))
+            {
+                group.push(triggers.next().unwrap());
+            }
+        }
+
+        block_state = if group.len() > 1 {
+            process_commutative_triggers(
+                logger,
                 block_state,
                 proof_of_indexing.cheap_clone(),
+                subgraph_metrics.cheap_clone(),
+                instance,
+                block,
+                group,
             )
-            .await
-            .map_err(move |e| {
-                e.context(match transaction_id {
-                    Some(tx_hash) => format!(
-                        "Failed to process trigger in block {}, transaction {:x}",
-                        block_ptr, tx_hash
-                    ),
-                    None => "Failed to process trigger".to_string(),
-                })
-            })?;
-        let elapsed = start.elapsed().as_secs_f64();
-        subgraph_metrics.observe_trigger_processing_duration(elapsed, trigger_type);
+            .await?
+        } else {
+            process_trigger(
+                logger,
+                block_state,
+                proof_of_indexing.cheap_clone(),
+                subgraph_metrics.cheap_clone(),
+                instance,
+                block,
+                group.pop().unwrap(),
+            )
+            .await?
+        };
     }
     Ok(block_state)
 }
 
+/// Process triggers whose handlers are all declared `commutative`. The
+/// triggers are processed concurrently, each on a fork of `block_state`,
+/// and the forks are merged in the order of the triggers. A trigger that
+/// read an entity that an earlier trigger changed, or whose processing
+/// failed, is processed again on the merged state. That way, the result is
+/// the same as processing the triggers one after the other
+async fn process_commutative_triggers(
+    logger: &Logger,
+    mut block_state: BlockState,
+    proof_of_indexing: SharedProofOfIndexing,
+    subgraph_metrics: Arc<SubgraphInstanceMetrics>,
+    instance: &SubgraphInstance<impl RuntimeHostBuilder>,
+    block: &Arc<LightEthereumBlock>,
+    triggers: Vec<EthereumTrigger>,
+) -> Result<BlockState, MappingError> {
+    let runs = triggers
+        .iter()
+        .cloned()
+        .zip(block_state.forks(triggers.len()))
+        .map(|(trigger, state)| {
+            let proof_of_indexing = proof_of_indexing
+                .as_ref()
+                .map(|poi| Arc::new(AtomicRefCell::new(poi.borrow().fork())));
+            let subgraph_metrics = subgraph_metrics.cheap_clone();
+            async move {
+                let state = process_trigger(
+                    logger,
+                    state,
+                    proof_of_indexing.cheap_clone(),
+                    subgraph_metrics,
+                    instance,
+                    block,
+                    trigger,
+                )
+                .await?;
+                Ok::<_, MappingError>((state, proof_of_indexing))
+            }
+        });
+    let runs = futures03::future::join_all(runs).await;
+
+    let mut reprocessed = 0;
+    block_state.entity_cache.track_access();
+    for (trigger, run) in triggers.into_iter().zip(runs) {
+        let merged = block_state
+            .entity_cache
+            .access()
+            .expect("access to the entity cache is tracked");
+        match run {
+            Ok((state, fork_poi))
+                if state
+                    .entity_cache
+                    .access()
+                    .map_or(false, |access| !access.read_changes_of(merged)) =>
+            {
+                block_state.extend(state);
+                if let (Some(poi), Some(fork_poi)) = (&proof_of_indexing, fork_poi) {
+                    poi.borrow_mut().replay(logger, &fork_poi.borrow());
+                }
+            }
+            _ => {
+                reprocessed += 1;
+                block_state = process_trigger(
+                    logger,
+                    block_state,
+                    proof_of_indexing.cheap_clone(),
+                    subgraph_metrics.cheap_clone(),
+                    instance,
+                    block,
+                    trigger,
+                )
+                .await?;
+            }
+        }
+    }
+    block_state.entity_cache.take_access();
+
+    if reprocessed > 0 {
+        debug!(logger, "Reprocessed commutative triggers one after the other";
+               "triggers" => reprocessed);
+    }
+    Ok(block_state)
+}
+
+async fn process_trigger(
+    logger: &Logger,
+    block_state: BlockState,
+    proof_of_indexing: SharedProofOfIndexing,
+    subgraph_metrics: Arc<SubgraphInstanceMetrics>,
+    instance: &SubgraphInstance<impl RuntimeHostBuilder>,
+    block: &Arc<LightEthereumBlock>,
+    trigger: EthereumTrigger,
+) -> Result<BlockState, MappingError> {
+    let block_ptr = EthereumBlockPointer::from(block.as_ref());
+    let trigger_type = match trigger {
+        EthereumTrigger::Log(_) => TriggerType::Event,
+        EthereumTrigger::Call(_) => TriggerType::Call,
+        EthereumTrigger::Block(..) => TriggerType::Block,
+    };
+    let transaction_id = match &trigger {
+        EthereumTrigger::Log(log) => log.transaction_hash,
+        EthereumTrigger::Call(call) => call.transaction_hash,
+        EthereumTrigger::Block(..) => None,
+    };
+    let start = Instant::now();
+    let block_state = instance
+        .process_trigger(
+            &logger,
+            &block,
+            trigger,
+            block_state,
+            proof_of_indexing.cheap_clone(),
+        )
+        .await
+        .map_err(move |e| {
+            e.context(match transaction_id {
+                Some(tx_hash) => format!(
+                    "Failed to process trigger in block {}, transaction {:x}",
+                    block_ptr, tx_hash
+                ),
+                None => "Failed to process trigger".to_string(),
+            })
+        })?;
+    let elapsed = start.elapsed().as_secs_f64();
+    subgraph_metrics.observe_trigger_processing_duration(elapsed, trigger_type);
+    Ok(block_state)
+}
+
 fn create_dynamic_data_sources<B, T: RuntimeHostBuilder, S, C>(
     logger: Logger,
     ctx: &mut IndexingContext<B, T, S, C>,
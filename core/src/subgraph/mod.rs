@@ -1,11 +1,14 @@
+mod cache_persistence;
 mod instance;
 mod instance_manager;
 mod loader;
 mod provider;
 mod registrar;
+mod shadow;
 
 pub use self::instance::SubgraphInstance;
 pub use self::instance_manager::SubgraphInstanceManager;
 pub use self::loader::DataSourceLoader;
 pub use self::provider::SubgraphAssignmentProvider;
 pub use self::registrar::SubgraphRegistrar;
+pub use self::shadow::ShadowMonitor;
@@ -7,7 +7,7 @@ use lazy_static::lazy_static;
 
 use graph::components::store::SubscriptionManager;
 use graph::components::{ethereum::EthereumNetworks, store::BlockStore};
-use graph::data::subgraph::schema::SubgraphDeploymentEntity;
+use graph::data::subgraph::schema::{SkippedTrigger, SubgraphDeploymentEntity};
 use graph::prelude::{
     CreateSubgraphResult, SubgraphAssignmentProvider as SubgraphAssignmentProviderTrait,
     SubgraphRegistrar as SubgraphRegistrarTrait, *,
@@ -361,6 +361,97 @@ where
 
         Ok(())
     }
+
+    async fn skipped_triggers(
+        &self,
+        hash: SubgraphDeploymentId,
+    ) -> Result<Vec<SkippedTrigger>, SubgraphRegistrarError> {
+        if !self.store.is_deployed(&hash)? {
+            return Err(SubgraphRegistrarError::DeploymentNotFound(hash.to_string()));
+        }
+        Ok(self.store.skipped_triggers(&hash)?)
+    }
+
+    async fn replay_skipped_triggers(
+        &self,
+        name: SubgraphName,
+        hash: SubgraphDeploymentId,
+        node_id: NodeId,
+    ) -> Result<usize, SubgraphRegistrarError> {
+        let logger = self.logger_factory.subgraph_logger(&hash);
+
+        let manifest = SubgraphManifest::resolve(hash.to_ipfs_link(), &*self.resolver, &logger)
+            .await
+            .map_err(SubgraphRegistrarError::ResolveError)?;
+        let graft = manifest.graft.ok_or_else(|| {
+            SubgraphRegistrarError::ReplayError(format!(
+                "deployment {} is not grafted onto another deployment",
+                hash
+            ))
+        })?;
+
+        // Only replay triggers that no other deployment has replayed yet
+        let pending: Vec<_> = self
+            .store
+            .skipped_triggers(&graft.base)?
+            .into_iter()
+            .filter(|trigger| trigger.replayed_by.is_none())
+            .collect();
+        let first = pending.first().ok_or_else(|| {
+            SubgraphRegistrarError::ReplayError(format!(
+                "deployment {} has no skipped triggers that need to be replayed",
+                graft.base
+            ))
+        })?;
+        if first.block_ptr.number <= graft.block as u64 {
+            return Err(SubgraphRegistrarError::ReplayError(format!(
+                "the graft block {} must be before block {} of the first skipped trigger of {}",
+                graft.block, first.block_ptr.number, graft.base
+            )));
+        }
+
+        self.create_subgraph_version(name, hash.clone(), node_id)
+            .await?;
+
+        let count = self
+            .store
+            .mark_skipped_triggers_replayed(&graft.base, graft.block, &hash)?;
+
+        info!(
+            logger,
+            "Replaying skipped triggers";
+            "base" => graft.base.to_string(),
+            "graft_block" => graft.block,
+            "count" => count,
+        );
+
+        Ok(count)
+    }
+
+    async fn set_subscriptions_disabled(
+        &self,
+        hash: SubgraphDeploymentId,
+        disabled: bool,
+    ) -> Result<(), SubgraphRegistrarError> {
+        if !self.store.is_deployed(&hash)? {
+            return Err(SubgraphRegistrarError::DeploymentNotFound(hash.to_string()));
+        }
+        self.store.set_subscriptions_disabled(&hash, disabled)?;
+        Ok(())
+    }
+
+    async fn set_deployment_context(
+        &self,
+        hash: SubgraphDeploymentId,
+        key: String,
+        value: Option<String>,
+    ) -> Result<(), SubgraphRegistrarError> {
+        if !self.store.is_deployed(&hash)? {
+            return Err(SubgraphRegistrarError::DeploymentNotFound(hash.to_string()));
+        }
+        self.store.set_deployment_context(&hash, key, value)?;
+        Ok(())
+    }
 }
 
 async fn handle_assignment_event(
@@ -0,0 +1,186 @@
+//! Compare a new version of a subgraph with the current one while the new
+//! version is indexed alongside it.
+//!
+//! Shadow mode is turned on by listing subgraph names in
+//! `GRAPH_SHADOW_SUBGRAPHS`. For each of these subgraphs, the pending
+//! version is treated as the shadow of the current version. Whenever both
+//! have indexed past a checkpoint, i.e., a block that is a multiple of
+//! `GRAPH_SHADOW_CHECKPOINT_INTERVAL` and outside of the reorg threshold,
+//! the entities of each type in both deployments, including their proof of
+//! indexing, are digested as of that block and compared. Entity types whose
+//! digests differ are reported through the `shadow` field of the indexing
+//! status of the pending version.
+use lazy_static::lazy_static;
+use std::collections::HashSet;
+use std::time::Duration;
+
+use graph::components::store::StatusStore;
+use graph::data::subgraph::status::{
+    self, Info, ShadowComparison, ShadowComparisons, ShadowDivergence,
+};
+use graph::prelude::*;
+
+lazy_static! {
+    /// The names of the subgraphs whose pending version should be compared
+    /// with their current version
+    static ref SHADOW_SUBGRAPHS: Vec<String> = std::env::var("GRAPH_SHADOW_SUBGRAPHS")
+        .unwrap_or_default()
+        .split(',')
+        .map(|name| name.trim().to_owned())
+        .filter(|name| !name.is_empty())
+        .collect();
+
+    /// Compare deployments at every block that is a multiple of this
+    static ref CHECKPOINT_INTERVAL: BlockNumber =
+        std::env::var("GRAPH_SHADOW_CHECKPOINT_INTERVAL")
+            .unwrap_or("1000".into())
+            .parse::<BlockNumber>()
+            .ok()
+            .filter(|interval| *interval > 0)
+            .expect("invalid GRAPH_SHADOW_CHECKPOINT_INTERVAL");
+}
+
+/// How often to check whether shadow deployments passed a new checkpoint
+const POLL_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Periodically compares shadow deployments with the deployments they
+/// shadow and records the results in `ShadowComparisons`
+pub struct ShadowMonitor<S> {
+    logger: Logger,
+    store: Arc<S>,
+    comparisons: Arc<ShadowComparisons>,
+}
+
+impl<S: StatusStore> ShadowMonitor<S> {
+    pub fn new(
+        logger_factory: &LoggerFactory,
+        store: Arc<S>,
+        comparisons: Arc<ShadowComparisons>,
+    ) -> Self {
+        ShadowMonitor {
+            logger: logger_factory.component_logger("ShadowMonitor", None),
+            store,
+            comparisons,
+        }
+    }
+
+    /// Start comparing deployments in the background. Does nothing if no
+    /// subgraphs are indexed in shadow mode
+    pub fn start(self) {
+        if SHADOW_SUBGRAPHS.is_empty() {
+            return;
+        }
+        info!(self.logger, "Comparing shadow deployments";
+              "subgraphs" => SHADOW_SUBGRAPHS.join(","),
+              "checkpoint_interval" => *CHECKPOINT_INTERVAL);
+
+        let logger = self.logger.clone();
+        let monitor = Arc::new(self);
+        graph::spawn(async move {
+            let mut interval = tokio::time::interval(POLL_INTERVAL);
+            loop {
+                interval.tick().await;
+                let monitor = monitor.clone();
+                if let Err(e) = graph::spawn_blocking_allow_panic(move || monitor.check_all()).await
+                {
+                    error!(logger, "Shadow comparison failed"; "error" => e.to_string());
+                }
+            }
+        });
+    }
+
+    fn check_all(&self) {
+        let mut shadows = HashSet::new();
+        for name in SHADOW_SUBGRAPHS.iter() {
+            match self.check(name) {
+                Ok(Some(shadow)) => {
+                    shadows.insert(shadow);
+                }
+                Ok(None) => {}
+                Err(e) => {
+                    warn!(self.logger, "Failed to compare shadow deployment";
+                          "subgraph" => name,
+                          "error" => e.to_string());
+                }
+            }
+        }
+        self.comparisons.retain(|id| shadows.contains(id));
+    }
+
+    /// Compare the pending version of the subgraph `name` with its current
+    /// version if both have passed a checkpoint that they have not been
+    /// compared at yet. Returns the id of the shadow deployment, or `None`
+    /// if the subgraph has no pending version
+    fn check(&self, name: &str) -> Result<Option<SubgraphDeploymentId>, StoreError> {
+        let version = |use_current| -> Result<Option<Info>, StoreError> {
+            Ok(self
+                .store
+                .status(status::Filter::SubgraphVersion(
+                    name.to_owned(),
+                    use_current,
+                ))?
+                .into_iter()
+                .next())
+        };
+        let (current, shadow) = match (version(true)?, version(false)?) {
+            (Some(current), Some(shadow)) if current.subgraph != shadow.subgraph => {
+                (current, shadow)
+            }
+            _ => return Ok(None),
+        };
+        let current_id = SubgraphDeploymentId::new(current.subgraph.clone())
+            .map_err(|id| graph::constraint_violation!("invalid deployment id `{}`", id))?;
+        let shadow_id = SubgraphDeploymentId::new(shadow.subgraph.clone())
+            .map_err(|id| graph::constraint_violation!("invalid deployment id `{}`", id))?;
+
+        let mut comparison = self
+            .comparisons
+            .get(shadow_id.as_str())
+            .filter(|comparison| comparison.current == current_id)
+            .unwrap_or_else(|| ShadowComparison::new(current_id.clone()));
+
+        let checkpoint = match (checkpoint(&current), checkpoint(&shadow)) {
+            (Some(current), Some(shadow)) => current.min(shadow),
+            _ => {
+                self.comparisons.set(shadow_id.clone(), comparison);
+                return Ok(Some(shadow_id));
+            }
+        };
+        if comparison
+            .checkpoint
+            .map_or(false, |last| last >= checkpoint)
+        {
+            self.comparisons.set(shadow_id.clone(), comparison);
+            return Ok(Some(shadow_id));
+        }
+
+        let current_digests = self.store.entity_digests(&current_id, checkpoint)?;
+        let shadow_digests = self.store.entity_digests(&shadow_id, checkpoint)?;
+        let divergences = ShadowDivergence::compare(checkpoint, &current_digests, &shadow_digests);
+        for divergence in &divergences {
+            warn!(self.logger, "Shadow deployment diverges from current deployment";
+                  "subgraph" => name,
+                  "current" => current_id.as_str(),
+                  "shadow" => shadow_id.as_str(),
+                  "block" => checkpoint,
+                  "entity_type" => &divergence.entity_type);
+        }
+        comparison.record(checkpoint, divergences);
+        self.comparisons.set(shadow_id.clone(), comparison);
+        Ok(Some(shadow_id))
+    }
+}
+
+/// The latest checkpoint that the deployment described by `info` has
+/// passed and that can not be affected by reorgs anymore
+fn checkpoint(info: &Info) -> Option<BlockNumber> {
+    let chain = info.chains.first()?;
+    let latest = chain.latest_block.as_ref()?.number();
+    let safe = latest.checked_sub(chain.reorg_threshold)? as BlockNumber;
+    let checkpoint = safe - safe % *CHECKPOINT_INTERVAL;
+    if checkpoint > 0 {
+        Some(checkpoint)
+    } else {
+        None
+    }
+}
@@ -1192,3 +1192,57 @@ fn nested_interface_fragments_overlapping() {
         }
     );
 }
+
+#[test]
+fn interface_pagination() {
+    let subgraph_id = "InterfacePagination";
+    let schema = "interface Legged { legs: Int }
+                  type Animal implements Legged @entity { id: ID!, legs: Int }
+                  type Furniture implements Legged @entity { id: ID!, legs: Int }
+                  ";
+
+    let legged = |id: &str, legs: i32, entity_type| {
+        (
+            Entity::from(vec![("id", Value::from(id)), ("legs", Value::from(legs))]),
+            entity_type,
+        )
+    };
+    let entities = vec![
+        legged("a1", 1, "Animal"),
+        legged("a3", 3, "Animal"),
+        legged("a5", 5, "Animal"),
+        legged("f2", 2, "Furniture"),
+        legged("f4", 4, "Furniture"),
+        legged("f6", 6, "Furniture"),
+    ];
+
+    // Order and range apply to the entities of all types together
+    let query = "query { leggeds(first: 3, skip: 2, orderBy: legs) { id } }";
+    let res = insert_and_query(subgraph_id, schema, entities, query).unwrap();
+    let data = extract_data!(res).unwrap();
+    assert_eq!(
+        data,
+        object! {
+            leggeds: vec![
+                object! { id: "a3" },
+                object! { id: "f4" },
+                object! { id: "a5" },
+            ]
+        }
+    );
+
+    let query = "query {
+        leggeds(first: 2, orderBy: legs, orderDirection: desc, where: { legs_lt: 5 }) { id }
+    }";
+    let res = insert_and_query(subgraph_id, schema, vec![], query).unwrap();
+    let data = extract_data!(res).unwrap();
+    assert_eq!(
+        data,
+        object! {
+            leggeds: vec![
+                object! { id: "f4" },
+                object! { id: "a3" },
+            ]
+        }
+    );
+}
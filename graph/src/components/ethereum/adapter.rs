@@ -730,10 +730,14 @@ pub trait EthereumAdapter: Send + Sync + 'static {
         block_ptr: EthereumBlockPointer,
     ) -> Box<dyn Future<Item = bool, Error = Error> + Send>;
 
+    /// Find the calls in the block `block_hash`. The traces of the block
+    /// are cached in `chain_store` so that they are only requested from
+    /// the Ethereum node once
     fn calls_in_block(
         &self,
         logger: &Logger,
         subgraph_metrics: Arc<SubgraphEthRpcMetrics>,
+        chain_store: Arc<dyn ChainStore>,
         block_number: u64,
         block_hash: H256,
     ) -> Box<dyn Future<Item = Vec<EthereumCall>, Error = Error> + Send>;
@@ -763,6 +767,23 @@ pub trait EthereumAdapter: Send + Sync + 'static {
         call: EthereumContractCall,
         cache: Arc<dyn EthereumCallCache>,
     ) -> Box<dyn Future<Item = Vec<Token>, Error = EthereumContractCallError> + Send>;
+
+    /// Call the functions of smart contracts at the same block. The calls
+    /// are looked up in the cache with one query, and the calls that are
+    /// not cached are sent to the Ethereum node as one batch. The results
+    /// are in the order of `calls`; the future only fails if the batch can
+    /// not be sent
+    fn contract_calls(
+        &self,
+        logger: &Logger,
+        calls: Vec<EthereumContractCall>,
+        cache: Arc<dyn EthereumCallCache>,
+    ) -> Box<
+        dyn Future<
+                Item = Vec<Result<Vec<Token>, EthereumContractCallError>>,
+                Error = EthereumContractCallError,
+            > + Send,
+    >;
 }
 
 fn parse_log_triggers(
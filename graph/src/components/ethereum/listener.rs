@@ -4,6 +4,8 @@ use serde::{Deserialize, Serialize};
 use std::str::FromStr;
 use web3::types::H256;
 
+use super::EthereumBlockPointer;
+
 /// Deserialize an H256 hash (with or without '0x' prefix).
 fn deserialize_h256<'de, D>(deserializer: D) -> Result<H256, D::Error>
 where
@@ -20,13 +22,78 @@ pub struct ChainHeadUpdate {
     #[serde(deserialize_with = "deserialize_h256")]
     pub head_block_hash: H256,
     pub head_block_number: u64,
+    /// Set if the new head is not a descendant of the previous head
+    #[serde(default)]
+    pub reorg: Option<ChainReorg>,
+}
+
+impl ChainHeadUpdate {
+    pub fn head(&self) -> EthereumBlockPointer {
+        EthereumBlockPointer::from((self.head_block_hash, self.head_block_number))
+    }
 }
 
-/// The updates have no payload, receivers should call `Store::chain_head_ptr`
-/// to check what the latest block is.
-pub type ChainHeadUpdateStream = Box<dyn Stream<Item = (), Error = ()> + Send>;
+/// What a chain head update that switched to a different branch of the
+/// chain replaced
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ChainReorg {
+    pub previous_head_block_hash: H256,
+    pub previous_head_block_number: u64,
+    /// The latest block that the previous and the new branch have in
+    /// common, or `None` if it is further back than the block cache goes
+    pub common_ancestor: Option<(H256, u64)>,
+}
+
+impl ChainReorg {
+    pub fn previous_head(&self) -> EthereumBlockPointer {
+        EthereumBlockPointer::from((
+            self.previous_head_block_hash,
+            self.previous_head_block_number,
+        ))
+    }
+
+    pub fn common_ancestor(&self) -> Option<EthereumBlockPointer> {
+        self.common_ancestor.map(EthereumBlockPointer::from)
+    }
+}
+
+/// Receivers of the updates should still call `Store::chain_head_ptr` to
+/// check what the latest block is since updates can get lost when they
+/// arrive faster than receivers process them.
+pub type ChainHeadUpdateStream = Box<dyn Stream<Item = ChainHeadUpdate, Error = ()> + Send>;
 
 pub trait ChainHeadUpdateListener {
     // Subscribe to chain head updates for the given network.
     fn subscribe(&self, network: String) -> ChainHeadUpdateStream;
 }
+
+#[test]
+fn chain_head_update_json() {
+    use serde_json::json;
+
+    let hash = "0x3b4b3fa4fd7b8df05d7bf4ddbea5ad1d6f6127e5dd6ebbb0a4c1e3d8292a48f5";
+
+    // Updates from nodes that do not know about reorgs
+    let update: ChainHeadUpdate = serde_json::from_value(json!({
+        "network_name": "mainnet",
+        "head_block_hash": hash.trim_start_matches("0x"),
+        "head_block_number": 12,
+    }))
+    .unwrap();
+    assert_eq!(12, update.head().number);
+    assert_eq!(None, update.reorg);
+
+    let reorg = ChainReorg {
+        previous_head_block_hash: H256::from_str(hash.trim_start_matches("0x")).unwrap(),
+        previous_head_block_number: 11,
+        common_ancestor: Some((H256::zero(), 9)),
+    };
+    let update: ChainHeadUpdate = serde_json::from_value(json!({
+        "network_name": "mainnet",
+        "head_block_hash": hash,
+        "head_block_number": 12,
+        "reorg": reorg,
+    }))
+    .unwrap();
+    assert_eq!(Some(reorg), update.reorg);
+}
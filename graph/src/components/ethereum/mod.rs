@@ -4,6 +4,30 @@ mod network;
 mod stream;
 mod types;
 
+use lazy_static::lazy_static;
+use std::env;
+
+lazy_static! {
+    /// How many blocks behind the chain head reorgs are handled. Subgraphs
+    /// that are further behind than this index in block ranges and stay
+    /// this many blocks behind the chain head until they catch up; closer
+    /// to the head, they follow it block by block and revert blocks that
+    /// were removed from the chain. Defaults to 50
+    pub static ref REORG_THRESHOLD: u64 = env::var("ETHEREUM_REORG_THRESHOLD")
+        .ok()
+        .map(|s| s.parse::<u64>()
+            .unwrap_or_else(|_| panic!("failed to parse env var ETHEREUM_REORG_THRESHOLD")))
+        .unwrap_or(50);
+
+    /// How many ancestors of the chain head the block ingestor keeps in
+    /// the block cache. Must be at least `REORG_THRESHOLD`. Defaults to 50
+    pub static ref ANCESTOR_COUNT: u64 = env::var("ETHEREUM_ANCESTOR_COUNT")
+        .ok()
+        .map(|s| s.parse::<u64>()
+             .unwrap_or_else(|_| panic!("failed to parse env var ETHEREUM_ANCESTOR_COUNT")))
+        .unwrap_or(50);
+}
+
 pub use self::adapter::{
     blocks_with_triggers, triggers_in_block, BlockStreamMetrics, EthGetLogsFilter, EthereumAdapter,
     EthereumAdapterError, EthereumBlockFilter, EthereumCallFilter, EthereumContractCall,
@@ -11,11 +35,13 @@ pub use self::adapter::{
     EthereumContractStateRequest, EthereumLogFilter, EthereumNetworkIdentifier,
     MockEthereumAdapter, ProviderEthRpcMetrics, SubgraphEthRpcMetrics,
 };
-pub use self::listener::{ChainHeadUpdate, ChainHeadUpdateListener, ChainHeadUpdateStream};
+pub use self::listener::{
+    ChainHeadUpdate, ChainHeadUpdateListener, ChainHeadUpdateStream, ChainReorg,
+};
 pub use self::network::{EthereumNetworkAdapters, EthereumNetworks, NodeCapabilities};
 pub use self::stream::{BlockStream, BlockStreamBuilder, BlockStreamEvent};
 pub use self::types::{
-    BlockFinality, EthereumBlock, EthereumBlockData, EthereumBlockPointer,
+    BlockFeeStats, BlockFinality, EthereumBlock, EthereumBlockData, EthereumBlockPointer,
     EthereumBlockTriggerType, EthereumBlockWithCalls, EthereumBlockWithTriggers, EthereumCall,
     EthereumCallData, EthereumEventData, EthereumTransactionData, EthereumTrigger,
     LightEthereumBlock, LightEthereumBlockExt,
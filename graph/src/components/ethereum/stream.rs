@@ -23,6 +23,8 @@ pub trait BlockStreamBuilder: Clone + Send + Sync + 'static {
         call_filter: EthereumCallFilter,
         block_filter: EthereumBlockFilter,
         include_calls_in_blocks: bool,
+        confirmations: u64,
         ethrpc_metrics: Arc<BlockStreamMetrics>,
+        memory: Arc<DeploymentMemory>,
     ) -> Self::Stream;
 }
@@ -3,7 +3,7 @@ use ethabi::LogParam;
 use serde::{Deserialize, Serialize};
 use stable_hash::prelude::*;
 use stable_hash::utils::AsBytes;
-use std::{cmp::Ordering, convert::TryFrom};
+use std::{cmp::Ordering, convert::TryFrom, mem};
 use std::{fmt, str::FromStr};
 use web3::types::*;
 
@@ -109,6 +109,52 @@ impl EthereumBlockWithTriggers {
             triggers,
         }
     }
+
+    /// An estimate of the memory used by the block and its triggers
+    pub fn approx_size(&self) -> usize {
+        fn log_size(log: &Log) -> usize {
+            mem::size_of::<Log>() + log.data.0.len() + log.topics.len() * mem::size_of::<H256>()
+        }
+        fn call_size(call: &EthereumCall) -> usize {
+            mem::size_of::<EthereumCall>() + call.input.0.len() + call.output.0.len()
+        }
+        fn block_size(block: &LightEthereumBlock) -> usize {
+            mem::size_of::<LightEthereumBlock>()
+                + block.extra_data.0.len()
+                + block
+                    .transactions
+                    .iter()
+                    .map(|tx| mem::size_of::<Transaction>() + tx.input.0.len())
+                    .sum::<usize>()
+        }
+
+        let block = match &self.ethereum_block {
+            BlockFinality::Final(block) => block_size(block),
+            BlockFinality::NonFinal(block) => {
+                block_size(&block.ethereum_block.block)
+                    + block
+                        .ethereum_block
+                        .transaction_receipts
+                        .iter()
+                        .map(|receipt| {
+                            mem::size_of::<TransactionReceipt>()
+                                + receipt.logs.iter().map(log_size).sum::<usize>()
+                        })
+                        .sum::<usize>()
+                    + block.calls.iter().map(call_size).sum::<usize>()
+            }
+        };
+        let triggers = self
+            .triggers
+            .iter()
+            .map(|trigger| match trigger {
+                EthereumTrigger::Block(..) => mem::size_of::<EthereumTrigger>(),
+                EthereumTrigger::Call(call) => call_size(call),
+                EthereumTrigger::Log(log) => log_size(log),
+            })
+            .sum::<usize>();
+        block + triggers
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -135,6 +181,8 @@ pub struct EthereumCall {
     pub block_hash: H256,
     pub transaction_hash: Option<H256>,
     transaction_index: u64,
+    /// The position of the call in the tree of calls of its transaction
+    pub trace_address: Vec<usize>,
 }
 
 impl EthereumCall {
@@ -171,6 +219,7 @@ impl EthereumCall {
             block_hash: trace.block_hash,
             transaction_hash: trace.transaction_hash,
             transaction_index,
+            trace_address: trace.trace_address.clone(),
         })
     }
 }
@@ -411,6 +460,39 @@ impl Clone for EthereumCallData {
     }
 }
 
+/// Gas statistics for one block. Mappings use them through
+/// `ethereum.feeHistory` instead of fetching blocks over RPC
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BlockFeeStats {
+    pub number: u64,
+    pub hash: H256,
+    pub gas_used: U256,
+    pub gas_limit: U256,
+    pub transaction_count: u64,
+    /// The lowest, median, and highest gas price of the transactions in
+    /// the block; all of them are zero for blocks without transactions
+    pub min_gas_price: U256,
+    pub median_gas_price: U256,
+    pub max_gas_price: U256,
+}
+
+impl From<&'_ LightEthereumBlock> for BlockFeeStats {
+    fn from(block: &LightEthereumBlock) -> BlockFeeStats {
+        let mut prices: Vec<U256> = block.transactions.iter().map(|tx| tx.gas_price).collect();
+        prices.sort();
+        BlockFeeStats {
+            number: block.number(),
+            hash: block.hash.unwrap(),
+            gas_used: block.gas_used,
+            gas_limit: block.gas_limit,
+            transaction_count: prices.len() as u64,
+            min_gas_price: prices.first().cloned().unwrap_or_default(),
+            median_gas_price: prices.get(prices.len() / 2).cloned().unwrap_or_default(),
+            max_gas_price: prices.last().cloned().unwrap_or_default(),
+        }
+    }
+}
+
 /// A block hash and block number from a specific Ethereum block.
 ///
 /// Maximum block number supported: 2^63 - 1
@@ -437,14 +519,14 @@ impl EthereumBlockPointer {
         format!("{:x}", self.hash)
     }
 
-    /// Block number to be passed into the store. Panics if it does not fit in an i32.
+    /// Block number to be passed into the store. Panics if it does not fit in an i64.
     pub fn block_number(&self) -> crate::components::store::BlockNumber {
-        if self.number <= std::i32::MAX as u64 {
-            self.number as i32
+        if self.number <= std::i64::MAX as u64 {
+            self.number as i64
         } else {
             panic!(
                 "Block numbers bigger than {} are not supported, but received block number {}",
-                std::i32::MAX,
+                std::i64::MAX,
                 self.number
             )
         }
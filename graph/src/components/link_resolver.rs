@@ -41,4 +41,7 @@ pub trait LinkResolver: Send + Sync + 'static {
     /// as they are used to split the file contents and each line is deserialized
     /// separately.
     async fn json_stream(&self, logger: &Logger, link: &Link) -> Result<JsonValueStream, Error>;
+
+    /// Store `data` and return the hash under which it can be fetched
+    async fn add(&self, logger: &Logger, data: Vec<u8>) -> Result<String, Error>;
 }
@@ -20,6 +20,24 @@ impl Drop for Section {
     }
 }
 
+/// Times one execution of a stage of writing a block. The time is recorded
+/// when the timer is dropped.
+pub struct StageTimer {
+    histogram: Histogram,
+    start: Instant,
+}
+
+impl StageTimer {
+    /// A more readable `drop`.
+    pub fn end(self) {}
+}
+
+impl Drop for StageTimer {
+    fn drop(&mut self) {
+        self.histogram.observe(self.start.elapsed().as_secs_f64())
+    }
+}
+
 /// Usage example:
 /// ```ignore
 /// // Start counting time for the "main_section".
@@ -37,6 +55,10 @@ impl Drop for Section {
 pub struct StopwatchMetrics {
     disabled: Arc<AtomicBool>,
     inner: Arc<Mutex<StopwatchInner>>,
+    // Durations of the stages of writing a block, by stage and entity type.
+    // Unlike sections, stages are timed individually, and their time is
+    // recorded even when the stopwatch is disabled
+    stages: HistogramVec,
 }
 
 impl StopwatchMetrics {
@@ -62,12 +84,36 @@ impl StopwatchMetrics {
             timer: Instant::now(),
         };
 
+        let stages = *registry
+            .new_deployment_histogram_vec(
+                "deployment_transact_stage_secs",
+                "time spent in the stages of writing a block",
+                subgraph_id.as_str(),
+                vec!["stage".to_owned(), "entity_type".to_owned()],
+                vec![0.0001, 0.0005, 0.001, 0.005, 0.01, 0.05, 0.1, 0.5, 1.0, 5.0],
+            )
+            .expect(&format!(
+                "failed to register deployment_transact_stage_secs prometheus histogram for {}",
+                subgraph_id
+            ));
+
         // Start a base section so that all time is accounted for.
         inner.start_section("unknown".to_owned());
 
         StopwatchMetrics {
             disabled: Arc::new(AtomicBool::new(false)),
             inner: Arc::new(Mutex::new(inner)),
+            stages,
+        }
+    }
+
+    /// Start timing one execution of `stage` of writing a block.
+    /// `entity_type` is the entity type the stage works on, or empty if it
+    /// does not work on a specific one.
+    pub fn start_stage(&self, stage: &str, entity_type: &str) -> StageTimer {
+        StageTimer {
+            histogram: self.stages.with_label_values(&[stage, entity_type]),
+            start: Instant::now(),
         }
     }
 
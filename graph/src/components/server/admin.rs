@@ -1,9 +1,32 @@
+use std::collections::BTreeMap;
 use std::io;
 use std::sync::Arc;
 
+use crate::components::link_resolver::LinkResolver;
+use crate::components::store::StatusStore;
 use crate::prelude::Logger;
 use crate::prelude::NodeId;
 
+/// What the JSON-RPC admin server knows about the sender of a request
+#[derive(Clone, Debug, Default)]
+pub struct AdminCredentials {
+    /// The token from an `Authorization: Bearer <token>` header
+    pub token: Option<String>,
+    /// All headers of the request whose values are valid strings. Proxies
+    /// that terminate TLS usually pass the identity from the client
+    /// certificate on in a header
+    pub headers: BTreeMap<String, String>,
+}
+
+/// Decides whether the sender of a request to the JSON-RPC admin server
+/// may call a method. Operators can plug in their own implementation, for
+/// example, to check JWTs or client certificates
+pub trait AdminAuthorizer: Send + Sync + 'static {
+    /// Return `Ok(())` if the sender may call `method`, and the reason why
+    /// not otherwise
+    fn authorize(&self, credentials: &AdminCredentials, method: &str) -> Result<(), String>;
+}
+
 /// Common trait for JSON-RPC admin server implementations.
 pub trait JsonRpcServer<P> {
     type Server;
@@ -13,6 +36,9 @@ pub trait JsonRpcServer<P> {
         http_port: u16,
         ws_port: u16,
         provider: Arc<P>,
+        statuses: Arc<dyn StatusStore>,
+        link_resolver: Arc<dyn LinkResolver>,
+        authorizer: Arc<dyn AdminAuthorizer>,
         node_id: NodeId,
         logger: Logger,
     ) -> Result<Self::Server, io::Error>;
@@ -1,6 +1,7 @@
 use std::sync::Arc;
 
 use futures::prelude::*;
+use serde::Serialize;
 
 use crate::prelude::Schema;
 
@@ -19,6 +20,57 @@ pub struct VersionInfo {
     pub network: String,
 }
 
+/// How the entities of a deployment are stored in the database. Entity
+/// types that are interfaces do not have a table of their own and are
+/// therefore not listed
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StorageLayout {
+    pub deployment: String,
+    pub shard: String,
+    /// The database schema that holds the tables of the deployment
+    pub namespace: String,
+    pub tables: Vec<TableStorage>,
+}
+
+/// The table that stores the entities of one type
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TableStorage {
+    /// The GraphQL name of the entity type
+    pub entity_type: String,
+    /// The name of the table, qualified with the namespace
+    pub table: String,
+    pub columns: Vec<ColumnStorage>,
+    pub indexes: Vec<IndexStorage>,
+    /// The number of rows, i.e., entity versions, in the table as estimated
+    /// by Postgres; `-1` if the table has never been analyzed
+    pub row_estimate: i64,
+    /// The size of the table including its indexes and TOAST data
+    pub total_bytes: i64,
+}
+
+/// The column that stores one attribute of an entity type
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ColumnStorage {
+    /// The GraphQL name of the attribute
+    pub field: String,
+    pub column: String,
+    /// The Postgres type of the column; for lists, the type of the elements
+    pub sql_type: String,
+    pub nullable: bool,
+    pub list: bool,
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct IndexStorage {
+    pub name: String,
+    /// The `create index` statement for the index
+    pub definition: String,
+}
+
 /// Common trait for index node server implementations.
 pub trait IndexNodeServer {
     type ServeError;
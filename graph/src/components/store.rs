@@ -5,7 +5,7 @@ use mockall::predicate::*;
 use mockall::*;
 use serde::{Deserialize, Serialize};
 use stable_hash::prelude::*;
-use std::collections::{BTreeMap, HashMap, HashSet};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::env;
 use std::fmt;
 use std::str::FromStr;
@@ -13,7 +13,7 @@ use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::{Arc, RwLock};
 use std::time::{Duration, Instant};
 use thiserror::Error;
-use web3::types::{Address, H256};
+use web3::types::{Address, Trace, TransactionReceipt, H256};
 
 use crate::data::subgraph::status;
 use crate::data::{query::QueryTarget, subgraph::schema::*};
@@ -21,7 +21,8 @@ use crate::data::{store::*, subgraph::Source};
 use crate::prelude::*;
 use crate::util::lfu_cache::LfuCache;
 
-use crate::components::server::index_node::VersionInfo;
+use crate::components::server::index_node::{StorageLayout, VersionInfo};
+use crate::components::subgraph::ProofOfIndexingVersion;
 
 lazy_static! {
     pub static ref SUBSCRIPTION_THROTTLE_INTERVAL: Duration =
@@ -32,6 +33,15 @@ lazy_static! {
             )))
             .map(Duration::from_millis)
             .unwrap_or_else(|| Duration::from_millis(1000));
+
+    /// Names that entities can not use for their fields since the store
+    /// uses them for its own columns
+    static ref ENTITY_RESERVED_FIELDS: Vec<String> = env::var("GRAPH_ENTITY_RESERVED_FIELDS")
+        .unwrap_or_else(|_| "vid,block_range".to_owned())
+        .split(',')
+        .map(|name| name.trim().to_owned())
+        .filter(|name| !name.is_empty())
+        .collect();
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord, Hash)]
@@ -224,6 +234,53 @@ pub enum EntityFilter {
     NotStartsWith(Attribute, Value),
     EndsWith(Attribute, Value),
     NotEndsWith(Attribute, Value),
+    /// Match against a POSIX regular expression
+    Match(Attribute, Value),
+    NotMatch(Attribute, Value),
+    EqualNoCase(Attribute, Value),
+    NotEqualNoCase(Attribute, Value),
+    ContainsNoCase(Attribute, Value),
+    NotContainsNoCase(Attribute, Value),
+    StartsWithNoCase(Attribute, Value),
+    NotStartsWithNoCase(Attribute, Value),
+    EndsWithNoCase(Attribute, Value),
+    NotEndsWithNoCase(Attribute, Value),
+    MatchNoCase(Attribute, Value),
+    NotMatchNoCase(Attribute, Value),
+    IsNull(Attribute),
+    IsNotNull(Attribute),
+    /// The attribute lies between the two values, both of them inclusive
+    Between(Attribute, Value, Value),
+    /// The list attribute has exactly this many elements
+    LengthEqual(Attribute, u32),
+    /// The list attribute has more than this many elements
+    LengthGreaterThan(Attribute, u32),
+    /// The list attribute has fewer than this many elements
+    LengthLessThan(Attribute, u32),
+    /// The version of the entity that is visible was written at this block
+    /// or later, i.e., the entity was created or last updated there
+    ChangedAtBlockGte(BlockNumber),
+    /// The entity was created at this block or later; it did not exist in
+    /// any earlier block
+    CreatedAtBlockGte(BlockNumber),
+    /// The entity has at least one child of type `child_type` that matches
+    /// `filter`
+    Child {
+        child_type: String,
+        filter: Box<EntityFilter>,
+        link: ChildLink,
+    },
+}
+
+/// How the parent and the children in a `Child` filter are connected
+#[derive(Clone, Debug, PartialEq)]
+pub enum ChildLink {
+    /// This attribute of the parent stores the id of the child, or a list
+    /// of child ids
+    Parent(Attribute),
+    /// This attribute of the child stores the id of the parent, or a list
+    /// of parent ids. This is how fields with `@derivedFrom` are stored
+    Child(Attribute),
 }
 
 // Define some convenience methods
@@ -268,13 +325,161 @@ impl EntityFilter {
     }
 }
 
+impl EntityFilter {
+    /// Evaluate the filter against `entity` in memory the way the store
+    /// would evaluate it. Returns `None` if the filter can only be
+    /// evaluated by the store, which is the case for regular expressions
+    /// and for filters on block numbers and children
+    pub fn matches(&self, entity: &Entity) -> Option<bool> {
+        use std::cmp::Ordering::*;
+        use EntityFilter::*;
+
+        fn equal(a: &Value, b: &Value) -> bool {
+            a == b || a.compare(b) == Some(Equal)
+        }
+
+        fn lower(value: &Value) -> Option<String> {
+            match value {
+                Value::String(s) => Some(s.to_lowercase()),
+                _ => None,
+            }
+        }
+
+        fn contains(value: &Value, part: &Value) -> Option<bool> {
+            match (value, part) {
+                (Value::String(s), Value::String(part)) => Some(s.contains(part.as_str())),
+                (Value::Bytes(b), Value::Bytes(part)) => Some(
+                    part.as_slice().is_empty()
+                        || b.as_slice()
+                            .windows(part.as_slice().len())
+                            .any(|window| window == part.as_slice()),
+                ),
+                (Value::List(values), Value::List(parts)) => Some(
+                    parts
+                        .iter()
+                        .all(|part| values.iter().any(|value| equal(value, part))),
+                ),
+                (Value::Null, _) => Some(false),
+                _ => None,
+            }
+        }
+
+        fn length(value: &Value) -> Option<usize> {
+            match value {
+                Value::List(values) => Some(values.len()),
+                _ => None,
+            }
+        }
+
+        fn all(filters: &[EntityFilter], entity: &Entity) -> Option<Vec<bool>> {
+            filters
+                .iter()
+                .map(|filter| filter.matches(entity))
+                .collect()
+        }
+
+        let null = Value::Null;
+        let get = |attr: &Attribute| entity.get(attr).unwrap_or(&null);
+        let compare = |attr: &Attribute, value: &Value| get(attr).compare(value);
+
+        let matches = match self {
+            And(filters) => all(filters, entity)?.into_iter().all(|m| m),
+            Or(filters) => all(filters, entity)?.into_iter().any(|m| m),
+            Equal(attr, value) => equal(get(attr), value),
+            Not(attr, value) => !get(attr).is_null() && !equal(get(attr), value),
+            GreaterThan(attr, value) => compare(attr, value) == Some(Greater),
+            LessThan(attr, value) => compare(attr, value) == Some(Less),
+            GreaterOrEqual(attr, value) => compare(attr, value).map_or(false, |o| o != Less),
+            LessOrEqual(attr, value) => compare(attr, value).map_or(false, |o| o != Greater),
+            In(attr, values) => values.iter().any(|value| equal(get(attr), value)),
+            NotIn(attr, values) => {
+                !get(attr).is_null() && !values.iter().any(|value| equal(get(attr), value))
+            }
+            Contains(attr, value) => contains(get(attr), value)?,
+            NotContains(attr, value) => !get(attr).is_null() && !contains(get(attr), value)?,
+            StartsWith(attr, value) | NotStartsWith(attr, value) => {
+                let starts = match (get(attr), value) {
+                    (Value::String(s), Value::String(prefix)) => s.starts_with(prefix.as_str()),
+                    (Value::Null, _) => return Some(false),
+                    _ => return None,
+                };
+                starts == matches!(self, StartsWith(_, _))
+            }
+            EndsWith(attr, value) | NotEndsWith(attr, value) => {
+                let ends = match (get(attr), value) {
+                    (Value::String(s), Value::String(suffix)) => s.ends_with(suffix.as_str()),
+                    (Value::Null, _) => return Some(false),
+                    _ => return None,
+                };
+                ends == matches!(self, EndsWith(_, _))
+            }
+            EqualNoCase(attr, value) => lower(get(attr)).map_or(false, |s| Some(s) == lower(value)),
+            NotEqualNoCase(attr, value) => match (lower(get(attr)), lower(value)) {
+                (Some(s), Some(value)) => s != value,
+                _ => false,
+            },
+            ContainsNoCase(attr, value)
+            | NotContainsNoCase(attr, value)
+            | StartsWithNoCase(attr, value)
+            | NotStartsWithNoCase(attr, value)
+            | EndsWithNoCase(attr, value)
+            | NotEndsWithNoCase(attr, value) => {
+                let (s, part) = match (lower(get(attr)), lower(value)) {
+                    (Some(s), Some(part)) => (s, part),
+                    (None, _) if get(attr).is_null() => return Some(false),
+                    _ => return None,
+                };
+                match self {
+                    ContainsNoCase(_, _) => s.contains(&part),
+                    NotContainsNoCase(_, _) => !s.contains(&part),
+                    StartsWithNoCase(_, _) => s.starts_with(&part),
+                    NotStartsWithNoCase(_, _) => !s.starts_with(&part),
+                    EndsWithNoCase(_, _) => s.ends_with(&part),
+                    _ => !s.ends_with(&part),
+                }
+            }
+            IsNull(attr) => get(attr).is_null(),
+            IsNotNull(attr) => !get(attr).is_null(),
+            Between(attr, lower, upper) => {
+                compare(attr, lower).map_or(false, |o| o != Less)
+                    && compare(attr, upper).map_or(false, |o| o != Greater)
+            }
+            LengthEqual(attr, len) => length(get(attr)).map_or(false, |l| l == *len as usize),
+            LengthGreaterThan(attr, len) => length(get(attr)).map_or(false, |l| l > *len as usize),
+            LengthLessThan(attr, len) => length(get(attr)).map_or(false, |l| l < *len as usize),
+            Match(_, _)
+            | NotMatch(_, _)
+            | MatchNoCase(_, _)
+            | NotMatchNoCase(_, _)
+            | ChangedAtBlockGte(_)
+            | CreatedAtBlockGte(_)
+            | Child { .. } => return None,
+        };
+        Some(matches)
+    }
+}
+
+/// Where entities whose sort attribute is `null` go when ordering by it
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NullsPlacement {
+    First,
+    Last,
+}
+
+impl Default for NullsPlacement {
+    /// Nulls go last, regardless of the direction of the order
+    fn default() -> Self {
+        NullsPlacement::Last
+    }
+}
+
 /// The order in which entities should be restored from a store.
 #[derive(Clone, Debug, PartialEq)]
 pub enum EntityOrder {
     /// Order ascending by the given attribute. Use `id` as a tie-breaker
-    Ascending(String, ValueType),
+    Ascending(String, ValueType, NullsPlacement),
     /// Order descending by the given attribute. Use `id` as a tie-breaker
-    Descending(String, ValueType),
+    Descending(String, ValueType, NullsPlacement),
     /// Order by the `id` of the entities
     Default,
     /// Do not order at all. This speeds up queries where we know that
@@ -282,6 +487,51 @@ pub enum EntityOrder {
     Unordered,
 }
 
+impl EntityOrder {
+    /// Sort `entities` in memory in this order, using the `id` as the
+    /// tie-breaker like the store does
+    pub fn sort(&self, entities: &mut Vec<Entity>) {
+        use std::cmp::Ordering;
+
+        fn by_id(a: &Entity, b: &Entity) -> Ordering {
+            a.id().ok().cmp(&b.id().ok())
+        }
+
+        fn by_attr(
+            attr: &str,
+            nulls: NullsPlacement,
+            descending: bool,
+            a: &Entity,
+            b: &Entity,
+        ) -> Ordering {
+            let null = Value::Null;
+            let a_value = a.get(attr).unwrap_or(&null);
+            let b_value = b.get(attr).unwrap_or(&null);
+            let nulls_first = nulls == NullsPlacement::First;
+            let order = match (a_value.is_null(), b_value.is_null()) {
+                (true, true) => Ordering::Equal,
+                (true, false) if nulls_first => return Ordering::Less,
+                (true, false) => return Ordering::Greater,
+                (false, true) if nulls_first => return Ordering::Greater,
+                (false, true) => return Ordering::Less,
+                (false, false) => a_value.compare(b_value).unwrap_or(Ordering::Equal),
+            };
+            let order = if descending { order.reverse() } else { order };
+            order.then_with(|| by_id(a, b))
+        }
+
+        match self {
+            EntityOrder::Ascending(attr, _, nulls) => {
+                entities.sort_by(|a, b| by_attr(attr, *nulls, false, a, b))
+            }
+            EntityOrder::Descending(attr, _, nulls) => {
+                entities.sort_by(|a, b| by_attr(attr, *nulls, true, a, b))
+            }
+            EntityOrder::Default | EntityOrder::Unordered => entities.sort_by(by_id),
+        }
+    }
+}
+
 /// How many entities to return, how many to skip etc.
 #[derive(Clone, Debug, PartialEq)]
 pub struct EntityRange {
@@ -391,11 +641,11 @@ pub enum EntityCollection {
     Window(Vec<EntityWindow>),
 }
 /// The type we use for block numbers. This has to be a signed integer type
-/// since Postgres does not support unsigned integer types. But 2G ought to
-/// be enough for everybody
-pub type BlockNumber = i32;
+/// since Postgres does not support unsigned integer types. Chains with
+/// short block times get past 2G blocks, which is why this is not an `i32`
+pub type BlockNumber = i64;
 
-pub const BLOCK_NUMBER_MAX: BlockNumber = std::i32::MAX;
+pub const BLOCK_NUMBER_MAX: BlockNumber = std::i64::MAX;
 
 /// A query for entities in a store.
 ///
@@ -426,11 +676,24 @@ pub struct EntityQuery {
     /// A range to limit the size of the result.
     pub range: EntityRange,
 
+    /// Return each entity at most once, even if it belongs to the windows
+    /// of several parents. Only affects windowed collections, for which it
+    /// returns each entity with the first of its parents
+    pub distinct: bool,
+
     /// Optional logger for anything related to this query
     pub logger: Option<Logger>,
 
     pub query_id: Option<String>,
 
+    /// How long the SQL statement for the query may run before the
+    /// database cancels it. The store may impose a shorter timeout
+    pub timeout: Option<Duration>,
+
+    /// The attributes that the caller will look at. If this is set, the
+    /// store only returns these attributes of entities, and their `id`
+    pub selected_attributes: Option<BTreeSet<String>>,
+
     _force_use_of_new: (),
 }
 
@@ -447,8 +710,11 @@ impl EntityQuery {
             filter: None,
             order: EntityOrder::Default,
             range: EntityRange::first(100),
+            distinct: false,
             logger: None,
             query_id: None,
+            timeout: None,
+            selected_attributes: None,
             _force_use_of_new: (),
         }
     }
@@ -478,6 +744,21 @@ impl EntityQuery {
         self
     }
 
+    pub fn distinct(mut self) -> Self {
+        self.distinct = true;
+        self
+    }
+
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    pub fn select(mut self, attributes: BTreeSet<String>) -> Self {
+        self.selected_attributes = Some(attributes);
+        self
+    }
+
     pub fn simplify(mut self) -> Self {
         // If there is one window, with one id, in a direct relation to the
         // entities, we can simplify the query by changing the filter and
@@ -506,6 +787,122 @@ impl EntityQuery {
     }
 }
 
+/// The aggregate functions that an `EntityAggregationQuery` can compute
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AggregateFunction {
+    Count,
+    Sum,
+    Min,
+    Max,
+    Avg,
+}
+
+impl AggregateFunction {
+    pub fn as_str(&self) -> &'static str {
+        use AggregateFunction::*;
+        match self {
+            Count => "count",
+            Sum => "sum",
+            Min => "min",
+            Max => "max",
+            Avg => "avg",
+        }
+    }
+}
+
+impl FromStr for AggregateFunction {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        use AggregateFunction::*;
+        match s {
+            "count" => Ok(Count),
+            "sum" => Ok(Sum),
+            "min" => Ok(Min),
+            "max" => Ok(Max),
+            "avg" => Ok(Avg),
+            _ => Err(format!("unknown aggregate function `{}`", s)),
+        }
+    }
+}
+
+/// A query that aggregates the entities of one type in the database
+/// rather than loading them
+#[derive(Clone, Debug, PartialEq)]
+pub struct EntityAggregationQuery {
+    pub subgraph_id: SubgraphDeploymentId,
+
+    /// The block height at which to aggregate
+    pub block: BlockNumber,
+
+    pub entity_type: String,
+
+    pub function: AggregateFunction,
+
+    /// The attribute to aggregate. It can only be omitted for `Count`,
+    /// which then counts entities
+    pub attribute: Option<Attribute>,
+
+    /// Compute the aggregate separately for each value of this attribute
+    pub group_by: Option<Attribute>,
+
+    /// Only aggregate entities that match this filter
+    pub filter: Option<EntityFilter>,
+}
+
+impl EntityAggregationQuery {
+    pub fn new(
+        subgraph_id: SubgraphDeploymentId,
+        block: BlockNumber,
+        entity_type: String,
+        function: AggregateFunction,
+    ) -> Self {
+        EntityAggregationQuery {
+            subgraph_id,
+            block,
+            entity_type,
+            function,
+            attribute: None,
+            group_by: None,
+            filter: None,
+        }
+    }
+
+    pub fn attribute(mut self, attribute: Attribute) -> Self {
+        self.attribute = Some(attribute);
+        self
+    }
+
+    pub fn group_by(mut self, attribute: Attribute) -> Self {
+        self.group_by = Some(attribute);
+        self
+    }
+
+    pub fn filter(mut self, filter: EntityFilter) -> Self {
+        self.filter = Some(filter);
+        self
+    }
+}
+
+/// One row in the result of an `EntityAggregationQuery`. The `group` is
+/// only set if the query groups by an attribute. `Count` produces an
+/// `Int`, `Sum` a `BigInt` or `BigDecimal`, `Avg` a `BigDecimal`, and
+/// `Min` and `Max` a value of the type of the aggregated attribute
+#[derive(Clone, Debug, PartialEq)]
+pub struct AggregateRow {
+    pub group: Option<Value>,
+    pub value: Value,
+}
+
+/// How the database would run an `EntityQuery`: `sql` is the SQL that the
+/// query turns into, including bind variables, and `plan` the plan that the
+/// database chose for it in JSON format
+#[derive(Clone, Debug, PartialEq)]
+pub struct QueryPlan {
+    pub sql: String,
+    pub plan: serde_json::Value,
+}
+
 /// Operation types that lead to entity changes.
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq, Hash)]
 #[serde(rename_all = "lowercase")]
@@ -556,6 +953,30 @@ impl From<MetadataOperation> for EntityChange {
     }
 }
 
+/// The number of changes to the entities of one type in one deployment.
+/// Compacted `StoreEvents` carry these instead of the individual changes
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct EntityChangeSummary {
+    pub subgraph_id: SubgraphDeploymentId,
+    pub entity_type: EntityType,
+    pub count: usize,
+}
+
+/// A change to an entity as recorded in the change log of a deployment.
+/// Unlike `StoreEvent`s, which are lost if nobody listens when they are
+/// sent, logged changes are kept until every consumer of the deployment
+/// has acknowledged them
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LoggedEntityChange {
+    /// The position of the change in the log. Positions increase with
+    /// every change, but are not necessarily consecutive
+    pub seq: i64,
+    /// The block at which the change happened; for changes that undo a
+    /// block during a revert, the block that the deployment was reverted to
+    pub block: BlockNumber,
+    pub change: EntityChange,
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 /// The store emits `StoreEvents` to indicate that some entities have changed.
 /// For block-related data, at most one `StoreEvent` is emitted for each block
@@ -570,6 +991,11 @@ pub struct StoreEvent {
     // logs as they flow through the system
     pub tag: usize,
     pub changes: HashSet<EntityChange>,
+    /// Changes to entities that were compacted into counts per entity
+    /// type. Subscribers that need to know which entities changed have to
+    /// query for all entities of these types
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub summaries: Vec<EntityChangeSummary>,
 }
 
 impl From<Vec<MetadataOperation>> for StoreEvent {
@@ -586,7 +1012,7 @@ impl<'a> FromIterator<&'a EntityModification> for StoreEvent {
             .map(|op| {
                 use self::EntityModification::*;
                 match op {
-                    Insert { key, .. } | Overwrite { key, .. } => {
+                    Insert { key, .. } | Overwrite { key, .. } | Increment { key, .. } => {
                         EntityChange::from_key(key.clone(), EntityChangeOperation::Set)
                     }
                     Remove { key } => {
@@ -605,7 +1031,11 @@ impl StoreEvent {
 
         let tag = NEXT_TAG.fetch_add(1, Ordering::Relaxed);
         let changes = changes.into_iter().collect();
-        StoreEvent { tag, changes }
+        StoreEvent {
+            tag,
+            changes,
+            summaries: vec![],
+        }
     }
 
     /// Extend `ev1` with `ev2`. If `ev1` is `None`, just set it to `ev2`
@@ -614,6 +1044,7 @@ impl StoreEvent {
             trace!(logger, "Adding changes to event";
                            "from" => ev2.tag, "to" => e.tag);
             e.changes.extend(ev2.changes);
+            e.add_summaries(ev2.summaries);
         } else {
             *ev1 = Some(ev2);
         }
@@ -621,8 +1052,70 @@ impl StoreEvent {
 
     pub fn extend(mut self, other: StoreEvent) -> Self {
         self.changes.extend(other.changes);
+        self.add_summaries(other.summaries);
         self
     }
+
+    fn add_summaries(&mut self, summaries: Vec<EntityChangeSummary>) {
+        for summary in summaries {
+            match self.summaries.iter_mut().find(|s| {
+                s.subgraph_id == summary.subgraph_id && s.entity_type == summary.entity_type
+            }) {
+                Some(existing) => existing.count += summary.count,
+                None => self.summaries.push(summary),
+            }
+        }
+    }
+
+    /// If the event has more than `threshold` changes, replace the changes
+    /// to data entities with a count of changes per entity type. Changes
+    /// to metadata are always kept since their consumers need to know the
+    /// ids of the changed entities
+    pub fn compact(mut self, threshold: usize) -> Self {
+        if self.changes.len() <= threshold {
+            return self;
+        }
+
+        let (data, metadata): (Vec<_>, Vec<_>) = self
+            .changes
+            .drain()
+            .partition(|change| change.entity_type.is_data_type());
+        self.changes = metadata.into_iter().collect();
+        let summaries = data
+            .into_iter()
+            .fold(
+                HashMap::<(SubgraphDeploymentId, EntityType), usize>::new(),
+                |mut counts, change| {
+                    *counts
+                        .entry((change.subgraph_id, change.entity_type))
+                        .or_default() += 1;
+                    counts
+                },
+            )
+            .into_iter()
+            .map(|((subgraph_id, entity_type), count)| EntityChangeSummary {
+                subgraph_id,
+                entity_type,
+                count,
+            })
+            .collect();
+        self.add_summaries(summaries);
+        self
+    }
+
+    /// `true` if changes in this event were compacted into summaries
+    pub fn is_compacted(&self) -> bool {
+        !self.summaries.is_empty()
+    }
+
+    /// `true` if the event contains a change that `filter` is interested in
+    pub fn matches(&self, filter: &SubscriptionFilter) -> bool {
+        self.changes.iter().any(|change| filter.matches(change))
+            || self
+                .summaries
+                .iter()
+                .any(|summary| filter.matches_summary(summary))
+    }
 }
 
 impl fmt::Display for StoreEvent {
@@ -632,6 +1125,11 @@ impl fmt::Display for StoreEvent {
             "StoreEvent[{}](changes: {})",
             self.tag,
             self.changes.len()
+                + self
+                    .summaries
+                    .iter()
+                    .map(|summary| summary.count)
+                    .sum::<usize>()
         )
     }
 }
@@ -639,10 +1137,56 @@ impl fmt::Display for StoreEvent {
 impl PartialEq for StoreEvent {
     fn eq(&self, other: &StoreEvent) -> bool {
         // Ignore tag for equality
-        self.changes == other.changes
+        self.changes == other.changes && self.summaries == other.summaries
     }
 }
 
+#[test]
+fn compact_store_event() {
+    let id = SubgraphDeploymentId::new("compact").unwrap();
+    let change = |entity_type: EntityType, entity_id: &str| EntityChange {
+        subgraph_id: id.clone(),
+        entity_type,
+        entity_id: entity_id.to_owned(),
+        operation: EntityChangeOperation::Set,
+    };
+    let band = || EntityType::data("Band".to_owned());
+    let assignment = || EntityType::metadata(MetadataType::SubgraphDeploymentAssignment);
+    let event = StoreEvent::new(vec![
+        change(band(), "1"),
+        change(band(), "2"),
+        change(EntityType::data("Musician".to_owned()), "1"),
+        change(assignment(), "compact"),
+    ]);
+
+    // Small events are left alone
+    let small = event.clone().compact(4);
+    assert!(!small.is_compacted());
+    assert_eq!(event, small);
+
+    // Metadata changes are kept, data changes are counted
+    let compacted = event.compact(3);
+    assert!(compacted.is_compacted());
+    assert_eq!(1, compacted.changes.len());
+    assert!(compacted.matches(&SubscriptionFilter::Assignment));
+    assert!(compacted.matches(&SubscriptionFilter::Entities(id.clone(), band())));
+    assert!(!compacted.matches(&SubscriptionFilter::Entities(
+        id.clone(),
+        EntityType::data("Album".to_owned())
+    )));
+    let count = compacted
+        .summaries
+        .iter()
+        .find(|summary| summary.entity_type == band())
+        .map(|summary| summary.count);
+    assert_eq!(Some(2), count);
+
+    // Summaries of the same entity type add up
+    let combined = compacted.clone().extend(compacted);
+    assert_eq!(2, combined.summaries.len());
+    assert_eq!(6, combined.summaries.iter().map(|s| s.count).sum::<usize>());
+}
+
 /// A `StoreEventStream` produces the `StoreEvents`. Various filters can be applied
 /// to it to reduce which and how many events are delivered by the stream.
 pub struct StoreEventStream<S> {
@@ -653,6 +1197,10 @@ pub struct StoreEventStream<S> {
 pub type StoreEventStreamBox =
     StoreEventStream<Box<dyn Stream<Item = Arc<StoreEvent>, Error = ()> + Send>>;
 
+/// A stream of the entities that match a query, as returned by
+/// `SubgraphStore::find_stream`
+pub type EntityStream = Box<dyn Stream<Item = Entity, Error = QueryExecutionError> + Send>;
+
 impl<S> Stream for StoreEventStream<S>
 where
     S: Stream<Item = Arc<StoreEvent>, Error = ()> + Send,
@@ -678,12 +1226,9 @@ where
     /// at least one change to one of the given (subgraph, entity) combinations
     /// will be delivered by the filtered stream.
     pub fn filter_by_entities(self, filters: Vec<SubscriptionFilter>) -> StoreEventStreamBox {
-        let source = self.source.filter(move |event| {
-            event
-                .changes
-                .iter()
-                .any(|change| filters.iter().any(|filter| filter.matches(change)))
-        });
+        let source = self
+            .source
+            .filter(move |event| filters.iter().any(|filter| event.matches(filter)));
 
         StoreEventStream::new(Box::new(source))
     }
@@ -953,27 +1498,40 @@ pub trait SubgraphStore: Send + Sync + 'static {
         subgraph_id: &'a SubgraphDeploymentId,
     ) -> DynTryFuture<'a, bool>;
 
+    /// The versions of the proof of indexing that are computed while
+    /// indexing the deployment
+    fn proof_of_indexing_versions(
+        &self,
+        subgraph_id: &SubgraphDeploymentId,
+    ) -> Result<Vec<ProofOfIndexingVersion>, StoreError>;
+
     /// A value of None indicates that the table is not available. Re-deploying
     /// the subgraph fixes this. It is undesirable to force everything to
     /// re-sync from scratch, so existing deployments will continue without a
     /// Proof of Indexing. Once all subgraphs have been re-deployed the Option
     /// can be removed.
+    ///
+    /// If `version` is `None`, the PoI is computed with the version that
+    /// the deployment reports by default. A value of None is also returned
+    /// if the deployment does not compute the requested version
     fn get_proof_of_indexing<'a>(
         self: Arc<Self>,
         subgraph_id: &'a SubgraphDeploymentId,
         indexer: &'a Option<Address>,
         block: EthereumBlockPointer,
+        version: Option<ProofOfIndexingVersion>,
     ) -> DynTryFuture<'a, Option<[u8; 32]>>;
 
     /// Looks up an entity using the given store key at the latest block.
     fn get(&self, key: EntityKey) -> Result<Option<Entity>, QueryExecutionError>;
 
-    /// Look up multiple entities as of the latest block. Returns a map of
-    /// entities by type.
+    /// Look up multiple entities as of `block`; use `BLOCK_NUMBER_MAX` for
+    /// the latest block. Returns a map of entities by type.
     fn get_many(
         &self,
         subgraph_id: &SubgraphDeploymentId,
         ids_for_type: BTreeMap<&EntityType, Vec<&str>>,
+        block: BlockNumber,
     ) -> Result<BTreeMap<EntityType, Vec<Entity>>, StoreError>;
 
     /// Queries the store for entities that match the store query.
@@ -982,6 +1540,21 @@ pub trait SubgraphStore: Send + Sync + 'static {
     /// Queries the store for a single entity matching the store query.
     fn find_one(&self, query: EntityQuery) -> Result<Option<Entity>, QueryExecutionError>;
 
+    /// Queries the store for entities that match the store query like
+    /// `find`, but returns them incrementally as they are read from the
+    /// database instead of collecting all of them first
+    fn find_stream(&self, query: EntityQuery) -> Result<EntityStream, QueryExecutionError>;
+
+    /// Count the entities that match the store query, disregarding its
+    /// order and range
+    fn count(&self, query: EntityQuery) -> Result<u64, QueryExecutionError>;
+
+    /// Aggregate the entities that match the query in the database
+    fn aggregate(
+        &self,
+        query: EntityAggregationQuery,
+    ) -> Result<Vec<AggregateRow>, QueryExecutionError>;
+
     /// Find the reverse of keccak256 for `hash` through looking it up in the
     /// rainbow table.
     fn find_ens_name(&self, _hash: &str) -> Result<Option<String>, QueryExecutionError>;
@@ -990,6 +1563,9 @@ pub trait SubgraphStore: Send + Sync + 'static {
     /// subgraph block pointer to `block_ptr_to`.
     ///
     /// `block_ptr_to` must point to a child block of the current subgraph block pointer.
+    ///
+    /// The `skipped_triggers` are recorded in the dead-letter table for the
+    /// subgraph together with the `deterministic_errors` that caused them.
     fn transact_block_operations(
         &self,
         subgraph_id: SubgraphDeploymentId,
@@ -997,6 +1573,7 @@ pub trait SubgraphStore: Send + Sync + 'static {
         mods: Vec<EntityModification>,
         stopwatch: StopwatchMetrics,
         deterministic_errors: Vec<SubgraphError>,
+        skipped_triggers: Vec<SkippedTrigger>,
     ) -> Result<(), StoreError>;
 
     /// Revert the entity changes from a single block atomically in the store, and update the
@@ -1115,6 +1692,87 @@ pub trait SubgraphStore: Send + Sync + 'static {
     /// Return the name of the network that the subgraph is indexing from. The
     /// names returned are things like `mainnet` or `ropsten`
     fn network_name(&self, subgraph_id: &SubgraphDeploymentId) -> Result<String, StoreError>;
+
+    /// Return the triggers that were skipped for the deployment `id` because
+    /// their handlers failed with a deterministic error, ordered by block
+    fn skipped_triggers(
+        &self,
+        id: &SubgraphDeploymentId,
+    ) -> Result<Vec<SkippedTrigger>, StoreError>;
+
+    /// Mark the skipped triggers of `id` after `graft_block` as replayed by
+    /// `replayed_by`, a deployment that is grafted onto `id` at that block.
+    /// Returns the number of triggers that were marked
+    fn mark_skipped_triggers_replayed(
+        &self,
+        id: &SubgraphDeploymentId,
+        graft_block: BlockNumber,
+        replayed_by: &SubgraphDeploymentId,
+    ) -> Result<usize, StoreError>;
+
+    /// Turn away new subscriptions to the deployment `id` if `disabled` is
+    /// `true`, and allow them again otherwise
+    fn set_subscriptions_disabled(
+        &self,
+        id: &SubgraphDeploymentId,
+        disabled: bool,
+    ) -> Result<(), StoreError>;
+
+    /// The key/value pairs that operators set for the deployment `id`.
+    /// Mappings can read them, which makes it possible to configure the
+    /// same subgraph differently for different deployments
+    fn deployment_context(
+        &self,
+        id: &SubgraphDeploymentId,
+    ) -> Result<BTreeMap<String, String>, StoreError>;
+
+    /// Set the context entry `key` of the deployment `id` to `value`, or
+    /// remove it if `value` is `None`
+    fn set_deployment_context(
+        &self,
+        id: &SubgraphDeploymentId,
+        key: String,
+        value: Option<String>,
+    ) -> Result<(), StoreError>;
+
+    /// Register `consumer` as a reader of the change log of the deployment
+    /// `id`. From now on, changes to the entities of the deployment are
+    /// kept in the log until `consumer` acknowledges them. Registering a
+    /// consumer again does nothing. Returns the position up to which
+    /// `consumer` has acknowledged changes
+    fn register_change_log_consumer(
+        &self,
+        id: &SubgraphDeploymentId,
+        consumer: &str,
+    ) -> Result<i64, StoreError>;
+
+    /// Stop keeping changes in the change log of `id` for `consumer`
+    fn remove_change_log_consumer(
+        &self,
+        id: &SubgraphDeploymentId,
+        consumer: &str,
+    ) -> Result<(), StoreError>;
+
+    /// Return up to `limit` changes from the change log of `id` that
+    /// `consumer` has not acknowledged yet, ordered by their position.
+    /// Reading changes does not acknowledge them; a consumer that restarts
+    /// reads them again
+    fn entity_changes(
+        &self,
+        id: &SubgraphDeploymentId,
+        consumer: &str,
+        limit: usize,
+    ) -> Result<Vec<LoggedEntityChange>, StoreError>;
+
+    /// Acknowledge all changes in the change log of `id` up to and
+    /// including position `seq` for `consumer`. Changes that all consumers
+    /// have acknowledged are removed from the log
+    fn ack_entity_changes(
+        &self,
+        id: &SubgraphDeploymentId,
+        consumer: &str,
+        seq: i64,
+    ) -> Result<(), StoreError>;
 }
 
 pub trait QueryStoreManager: Send + Sync + 'static {
@@ -1141,6 +1799,8 @@ mock! {
             _subgraph_id: &SubgraphDeploymentId,
             _ids_for_type: BTreeMap<&'a EntityType, Vec<&'a str>>,
         ) -> Result<BTreeMap<EntityType, Vec<Entity>>, StoreError>;
+
+        fn find_mock(&self, _query: EntityQuery) -> Result<Vec<Entity>, QueryExecutionError>;
     }
 }
 
@@ -1165,11 +1825,19 @@ impl SubgraphStore for MockStore {
         unimplemented!();
     }
 
+    fn proof_of_indexing_versions(
+        &self,
+        _subgraph_id: &SubgraphDeploymentId,
+    ) -> Result<Vec<ProofOfIndexingVersion>, StoreError> {
+        unimplemented!()
+    }
+
     fn get_proof_of_indexing<'a>(
         self: Arc<Self>,
         _subgraph_id: &'a SubgraphDeploymentId,
         _indexer: &'a Option<Address>,
         _block: EthereumBlockPointer,
+        _version: Option<ProofOfIndexingVersion>,
     ) -> DynTryFuture<'a, Option<[u8; 32]>> {
         unimplemented!();
     }
@@ -1182,18 +1850,34 @@ impl SubgraphStore for MockStore {
         &self,
         subgraph_id: &SubgraphDeploymentId,
         ids_for_type: BTreeMap<&EntityType, Vec<&str>>,
+        _block: BlockNumber,
     ) -> Result<BTreeMap<EntityType, Vec<Entity>>, StoreError> {
         self.get_many_mock(subgraph_id, ids_for_type)
     }
 
-    fn find(&self, _query: EntityQuery) -> Result<Vec<Entity>, QueryExecutionError> {
-        unimplemented!()
+    fn find(&self, query: EntityQuery) -> Result<Vec<Entity>, QueryExecutionError> {
+        self.find_mock(query)
     }
 
     fn find_one(&self, _query: EntityQuery) -> Result<Option<Entity>, QueryExecutionError> {
         unimplemented!()
     }
 
+    fn find_stream(&self, _query: EntityQuery) -> Result<EntityStream, QueryExecutionError> {
+        unimplemented!()
+    }
+
+    fn count(&self, _query: EntityQuery) -> Result<u64, QueryExecutionError> {
+        unimplemented!()
+    }
+
+    fn aggregate(
+        &self,
+        _query: EntityAggregationQuery,
+    ) -> Result<Vec<AggregateRow>, QueryExecutionError> {
+        unimplemented!()
+    }
+
     fn find_ens_name(&self, _hash: &str) -> Result<Option<String>, QueryExecutionError> {
         unimplemented!()
     }
@@ -1205,6 +1889,7 @@ impl SubgraphStore for MockStore {
         _mods: Vec<EntityModification>,
         _stopwatch: StopwatchMetrics,
         _deterministic_errors: Vec<SubgraphError>,
+        _skipped_triggers: Vec<SkippedTrigger>,
     ) -> Result<(), StoreError> {
         unimplemented!()
     }
@@ -1310,6 +1995,80 @@ impl SubgraphStore for MockStore {
     fn network_name(&self, _: &SubgraphDeploymentId) -> Result<String, StoreError> {
         unimplemented!()
     }
+
+    fn skipped_triggers(
+        &self,
+        _: &SubgraphDeploymentId,
+    ) -> Result<Vec<SkippedTrigger>, StoreError> {
+        unimplemented!()
+    }
+
+    fn mark_skipped_triggers_replayed(
+        &self,
+        _: &SubgraphDeploymentId,
+        _: BlockNumber,
+        _: &SubgraphDeploymentId,
+    ) -> Result<usize, StoreError> {
+        unimplemented!()
+    }
+
+    fn set_subscriptions_disabled(
+        &self,
+        _: &SubgraphDeploymentId,
+        _: bool,
+    ) -> Result<(), StoreError> {
+        unimplemented!()
+    }
+
+    fn deployment_context(
+        &self,
+        _: &SubgraphDeploymentId,
+    ) -> Result<BTreeMap<String, String>, StoreError> {
+        unimplemented!()
+    }
+
+    fn set_deployment_context(
+        &self,
+        _: &SubgraphDeploymentId,
+        _: String,
+        _: Option<String>,
+    ) -> Result<(), StoreError> {
+        unimplemented!()
+    }
+
+    fn register_change_log_consumer(
+        &self,
+        _: &SubgraphDeploymentId,
+        _: &str,
+    ) -> Result<i64, StoreError> {
+        unimplemented!()
+    }
+
+    fn remove_change_log_consumer(
+        &self,
+        _: &SubgraphDeploymentId,
+        _: &str,
+    ) -> Result<(), StoreError> {
+        unimplemented!()
+    }
+
+    fn entity_changes(
+        &self,
+        _: &SubgraphDeploymentId,
+        _: &str,
+        _: usize,
+    ) -> Result<Vec<LoggedEntityChange>, StoreError> {
+        unimplemented!()
+    }
+
+    fn ack_entity_changes(
+        &self,
+        _: &SubgraphDeploymentId,
+        _: &str,
+        _: i64,
+    ) -> Result<(), StoreError> {
+        unimplemented!()
+    }
 }
 
 pub trait BlockStore: Send + Sync + 'static {
@@ -1324,6 +2083,20 @@ pub trait CallCache: Send + Sync + 'static {
     fn ethereum_call_cache(&self, network: &str) -> Option<Arc<Self::EthereumCallCache>>;
 }
 
+/// Which blocks `ChainStore::cleanup_cached_blocks` keeps in the block
+/// cache
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BlockRetention {
+    /// Keep the given number of blocks closest to the chain head
+    KeepLast(u64),
+    /// Keep blocks with a timestamp at or after the given number of
+    /// seconds since the epoch
+    KeepSince(u64),
+    /// Keep the blocks that active deployments that have not failed still
+    /// need to process
+    KeepReferenced,
+}
+
 /// Common trait for blockchain store implementations.
 #[automock]
 pub trait ChainStore: Send + Sync + 'static {
@@ -1387,12 +2160,31 @@ pub trait ChainStore: Send + Sync + 'static {
         offset: u64,
     ) -> Result<Option<EthereumBlock>, Error>;
 
-    /// Remove old blocks from the cache we maintain in the database and
-    /// return a pair containing the number of the oldest block retained
-    /// and the number of blocks deleted.
+    /// Get the block with number `number` on the main chain. Returns None
+    /// if the store does not know which block with that number is on the
+    /// main chain, or does not have the block
+    fn block_by_number(&self, number: BlockNumber) -> Result<Option<EthereumBlock>, Error>;
+
+    /// Remove the blocks that `retention` does not keep from the cache we
+    /// maintain in the database and return a pair containing the number
+    /// of the oldest block retained and the number of blocks deleted.
     /// We will never remove blocks that are within `ancestor_count` of
     /// the chain head.
-    fn cleanup_cached_blocks(&self, ancestor_count: u64) -> Result<(BlockNumber, usize), Error>;
+    fn cleanup_cached_blocks(
+        &self,
+        ancestor_count: u64,
+        retention: BlockRetention,
+    ) -> Result<(BlockNumber, usize), Error>;
+
+    /// Remove the calls that were last used more than `max_age_days` ago
+    /// from the call cache, and then the calls that were used the longest
+    /// time ago until at most `max_entries` remain. Returns the number of
+    /// calls that were removed
+    fn cleanup_call_cache(
+        &self,
+        max_age_days: Option<u32>,
+        max_entries: Option<u64>,
+    ) -> Result<usize, Error>;
 
     /// Return the hashes of all blocks with the given number
     fn block_hashes_by_block_number(&self, number: u64) -> Result<Vec<H256>, Error>;
@@ -1401,18 +2193,82 @@ pub trait ChainStore: Send + Sync + 'static {
     /// may purge any other blocks with that number
     fn confirm_block_hash(&self, number: u64, hash: &H256) -> Result<usize, Error>;
 
+    /// The finality watermark: the number of the latest block that was
+    /// finalized with `finalize_blocks`, or `None` if that never happened
+    fn finalized_block_number(&self) -> Result<Option<BlockNumber>, Error>;
+
+    /// Consider all blocks that are at least `confirmations` blocks below
+    /// the chain head final, and purge the blocks that are not on the main
+    /// chain at heights between the old and the new finality watermark, as
+    /// if `confirm_block_hash` had been called for each of them. Return the
+    /// new watermark and the number of purged blocks, or `None` if the
+    /// watermark did not move
+    fn finalize_blocks(&self, confirmations: u64) -> Result<Option<(BlockNumber, usize)>, Error>;
+
     /// Find the block with `block_hash` and return the network name and number
     fn block_number(&self, block_hash: H256) -> Result<Option<(String, BlockNumber)>, StoreError>;
+
+    /// Insert gas statistics for blocks, replacing any statistics that are
+    /// already stored for the same block numbers
+    fn upsert_fee_stats(&self, stats: Vec<BlockFeeStats>) -> Result<(), Error>;
+
+    /// Return the gas statistics that are stored for the blocks with
+    /// numbers from `from` to `to`, both inclusive, ordered by block number.
+    /// Blocks for which no statistics are stored are skipped
+    fn fee_history(&self, from: u64, to: u64) -> Result<Vec<BlockFeeStats>, Error>;
+
+    /// Insert transaction receipts, replacing any receipts that are already
+    /// stored for the same transactions in the same blocks
+    fn upsert_receipts(&self, receipts: Vec<TransactionReceipt>) -> Result<(), Error>;
+
+    /// Return the receipts that are stored for the block `block_hash`,
+    /// ordered by their transaction index. The result is empty if no
+    /// receipts are stored for the block, and might be incomplete if only
+    /// some of them are
+    fn receipts_for_block(&self, block_hash: H256) -> Result<Vec<TransactionReceipt>, Error>;
+
+    /// Insert the traces of blocks, replacing any traces that are already
+    /// stored for the same blocks. The traces of a block are stored
+    /// together, and `traces` must therefore contain all traces of each
+    /// block that it has traces for
+    fn upsert_traces(&self, traces: Vec<Trace>) -> Result<(), Error>;
+
+    /// Return the traces that are stored for the blocks `hashes`. Blocks
+    /// for which no traces are stored are not in the result
+    fn traces_for_blocks(&self, hashes: Vec<H256>) -> Result<HashMap<H256, Vec<Trace>>, Error>;
+
+    /// Return the blocks among `hashes` that are in the block cache but
+    /// lack the receipts for some of their transactions, e.g., because
+    /// they were stored with `upsert_light_blocks`. Blocks whose receipts
+    /// were all stored separately with `upsert_receipts` are turned into
+    /// full blocks right away and not returned
+    fn light_blocks(&self, hashes: Vec<H256>) -> Result<Vec<LightEthereumBlock>, Error>;
+
+    /// Merge the transaction receipts of `blocks` into the blocks in the
+    /// block cache that lack them, making them full blocks. Blocks that are
+    /// not in the cache, that are full already, or that are not complete
+    /// in `blocks` are left alone. Returns the number of blocks that were
+    /// turned into full blocks
+    fn hydrate_blocks(&self, blocks: Vec<EthereumBlock>) -> Result<usize, Error>;
+}
+
+/// The outcome of an `eth_call` as it is kept in the call cache
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum EthereumCallResult {
+    /// The raw output of a call that succeeded
+    Return(Vec<u8>),
+    /// The call reverted deterministically for the given reason
+    Revert(String),
 }
 
 pub trait EthereumCallCache: Send + Sync + 'static {
-    /// Cached return value.
+    /// Cached result of the call.
     fn get_call(
         &self,
         contract_address: ethabi::Address,
         encoded_call: &[u8],
         block: EthereumBlockPointer,
-    ) -> Result<Option<Vec<u8>>, Error>;
+    ) -> Result<Option<EthereumCallResult>, Error>;
 
     // Add entry to the cache.
     fn set_call(
@@ -1420,18 +2276,50 @@ pub trait EthereumCallCache: Send + Sync + 'static {
         contract_address: ethabi::Address,
         encoded_call: &[u8],
         block: EthereumBlockPointer,
-        return_value: &[u8],
+        result: &EthereumCallResult,
+    ) -> Result<(), Error>;
+
+    /// Cached results of several calls at the same block, in the order of
+    /// `calls`, looked up with one query
+    fn get_calls(
+        &self,
+        calls: &[(ethabi::Address, Vec<u8>)],
+        block: EthereumBlockPointer,
+    ) -> Result<Vec<Option<EthereumCallResult>>, Error>;
+
+    /// Add the results of several calls at the same block to the cache
+    /// with one query
+    fn set_calls(
+        &self,
+        calls: &[(ethabi::Address, Vec<u8>, EthereumCallResult)],
+        block: EthereumBlockPointer,
     ) -> Result<(), Error>;
 }
 
 /// Store operations used when serving queries for a specific deployment
 #[async_trait]
 pub trait QueryStore: Send + Sync {
+    /// Run `query`. If `attributes` is given, the entities in the result
+    /// only have these attributes, their `id` and their `__typename`;
+    /// otherwise, they have all their attributes
     fn find_query_values(
         &self,
         query: EntityQuery,
+        attributes: Option<BTreeSet<String>>,
     ) -> Result<Vec<BTreeMap<String, q::Value>>, QueryExecutionError>;
 
+    fn count(&self, query: EntityQuery) -> Result<u64, QueryExecutionError>;
+
+    fn aggregate(
+        &self,
+        query: EntityAggregationQuery,
+    ) -> Result<Vec<AggregateRow>, QueryExecutionError>;
+
+    /// Return the SQL for `query` and the plan that the database would use
+    /// to run it, without running it. Only available if
+    /// `GRAPH_ALLOW_QUERY_EXPLAIN` is set
+    fn explain(&self, query: EntityQuery) -> Result<QueryPlan, QueryExecutionError>;
+
     fn is_deployment_synced(&self, id: &SubgraphDeploymentId) -> Result<bool, Error>;
 
     fn block_ptr(
@@ -1439,7 +2327,11 @@ pub trait QueryStore: Send + Sync {
         subgraph_id: SubgraphDeploymentId,
     ) -> Result<Option<EthereumBlockPointer>, Error>;
 
-    fn block_number(&self, block_hash: H256) -> Result<Option<BlockNumber>, StoreError>;
+    /// Find the number of the block with `block_hash`. Returns `None` if
+    /// the block is not known, and an error if the deployment has not
+    /// indexed that block yet or if the block is not on the chain that the
+    /// deployment has indexed, for example, because it was reorged out
+    fn block_number(&self, block_hash: H256) -> Result<Option<BlockNumber>, QueryExecutionError>;
 
     fn wait_stats(&self) -> &PoolWaitStats;
 
@@ -1454,9 +2346,22 @@ pub trait QueryStore: Send + Sync {
     /// return details about it needed for executing queries
     fn deployment_state(&self) -> Result<DeploymentState, QueryExecutionError>;
 
+    /// The current indexing status of the deployment, including the head
+    /// of the chain it indexes
+    fn deployment_status(&self) -> Result<status::Info, QueryExecutionError>;
+
     fn api_schema(&self) -> Result<Arc<ApiSchema>, QueryExecutionError>;
 
     fn network_name(&self) -> &str;
+
+    /// Return a store for the same deployment that treats the block the
+    /// deployment is at right now as its latest block, no matter how far
+    /// the deployment advances while the store is in use. `block_ptr`
+    /// always returns that block, and queries for later blocks, including
+    /// `BLOCK_NUMBER_MAX`, are run at that block. Using such a store for
+    /// all parts of a request guarantees that they see the same snapshot
+    /// of the deployment
+    fn pin_latest_block(&self) -> Result<Arc<dyn QueryStore + Send + Sync>, QueryExecutionError>;
 }
 
 /// A view of the store that can provide information about the indexing status
@@ -1467,6 +2372,13 @@ pub trait StatusStore: Send + Sync + 'static {
     /// Support for the explorer-specific API
     fn version_info(&self, version_id: &str) -> Result<VersionInfo, StoreError>;
 
+    /// Describe the tables, columns and indexes that store the entities of
+    /// the deployment `subgraph_id`
+    fn storage_layout(
+        &self,
+        subgraph_id: &SubgraphDeploymentId,
+    ) -> Result<StorageLayout, StoreError>;
+
     /// Support for the explorer-specific API; note that `subgraph_id` must be
     /// the id of an entry in `subgraphs.subgraph`, not that of a deployment.
     /// The return values are the ids of the `subgraphs.subgraph_version` for
@@ -1486,12 +2398,38 @@ pub trait StatusStore: Send + Sync + 'static {
     /// re-sync from scratch, so existing deployments will continue without a
     /// Proof of Indexing. Once all subgraphs have been re-deployed the Option
     /// can be removed.
+    ///
+    /// If `version` is `None`, the PoI is computed with the version that
+    /// the deployment reports by default. A value of None is also returned
+    /// if the deployment does not compute the requested version
     fn get_proof_of_indexing<'a>(
         self: Arc<Self>,
         subgraph_id: &'a SubgraphDeploymentId,
         indexer: &'a Option<Address>,
         block: EthereumBlockPointer,
+        version: Option<ProofOfIndexingVersion>,
     ) -> DynTryFuture<'a, Option<[u8; 32]>>;
+
+    /// Digests of the entities of each type in the deployment as of
+    /// `block`, keyed by entity type. Two deployments of subgraphs that
+    /// produce the same data have the same digests
+    fn entity_digests(
+        &self,
+        subgraph_id: &SubgraphDeploymentId,
+        block: BlockNumber,
+    ) -> Result<BTreeMap<String, status::EntityDigest>, StoreError>;
+
+    /// Get the block with number `number` on the main chain of `network`.
+    /// Returns None if the network is not known or the block is not in
+    /// the block cache
+    fn block_by_number(
+        &self,
+        network: &str,
+        number: BlockNumber,
+    ) -> Result<Option<EthereumBlock>, StoreError>;
+
+    /// The size of the `eth_call` cache of each network
+    fn call_cache_sizes(&self) -> Result<Vec<status::CallCacheSize>, StoreError>;
 }
 
 /// An entity operation that can be transacted into the store; as opposed to
@@ -1505,13 +2443,21 @@ pub enum EntityModification {
     Overwrite { key: EntityKey, data: Entity },
     /// Remove the entity
     Remove { key: EntityKey },
+    /// Add `deltas` to the fields of the entity without reading it first;
+    /// does nothing if the entity does not exist
+    Increment {
+        key: EntityKey,
+        deltas: BTreeMap<String, Value>,
+    },
 }
 
 impl EntityModification {
     pub fn entity_key(&self) -> &EntityKey {
         use EntityModification::*;
         match self {
-            Insert { key, .. } | Overwrite { key, .. } | Remove { key } => key,
+            Insert { key, .. } | Overwrite { key, .. } | Remove { key } | Increment { key, .. } => {
+                key
+            }
         }
     }
 
@@ -1529,9 +2475,36 @@ enum EntityOp {
     Remove,
     Update(Entity),
     Overwrite(Entity),
+    /// Apply `updates` like `Update`, then add `deltas` to the fields of
+    /// the result. `deltas` never has an entry for a field in `updates`
+    Increment {
+        updates: Entity,
+        deltas: BTreeMap<String, Value>,
+    },
+}
+
+/// Add `delta` to the value of `field` in `entity`
+fn add_delta(entity: &mut Entity, field: &str, delta: &Value) {
+    let value = entity
+        .get(field)
+        .unwrap_or(&Value::Null)
+        .add_number(delta)
+        // Only numbers are ever incremented; if the field somehow holds
+        // something else, the delta replaces it
+        .unwrap_or_else(|| delta.clone());
+    entity.set(field, value);
 }
 
 impl EntityOp {
+    fn increment(field: String, delta: Value) -> EntityOp {
+        let mut deltas = BTreeMap::new();
+        deltas.insert(field, delta);
+        EntityOp::Increment {
+            updates: Entity::new(),
+            deltas,
+        }
+    }
+
     fn apply_to(self, entity: Option<Entity>) -> Option<Entity> {
         use EntityOp::*;
         match (self, entity) {
@@ -1541,25 +2514,95 @@ impl EntityOp {
                 entity.merge_remove_null_fields(updates);
                 Some(entity)
             }
+            // Incrementing an entity that does not exist does not create it
+            (Increment { updates, .. }, None) if updates.is_empty() => None,
+            (Increment { updates, deltas }, entity) => {
+                let mut entity = entity.unwrap_or_default();
+                entity.merge_remove_null_fields(updates);
+                for (field, delta) in &deltas {
+                    add_delta(&mut entity, field, delta);
+                }
+                Some(entity)
+            }
         }
     }
 
     fn accumulate(&mut self, next: EntityOp) {
         use EntityOp::*;
-        let update = match next {
+        let (update, deltas) = match next {
             // Remove and Overwrite ignore the current value.
             Remove | Overwrite(_) => {
                 *self = next;
                 return;
             }
-            Update(update) => update,
+            Update(update) => (update, BTreeMap::new()),
+            Increment { updates, deltas } => (updates, deltas),
         };
 
         // We have an update, apply it.
         match self {
             // This is how `Overwrite` is constructed, by accumulating `Update` onto `Remove`.
-            Remove => *self = Overwrite(update),
+            Remove if deltas.is_empty() || !update.is_empty() => *self = Overwrite(update),
+            // Incrementing a removed entity does nothing
+            Remove => {}
             Update(current) | Overwrite(current) => current.merge(update),
+            Increment {
+                updates: current,
+                deltas: current_deltas,
+            } => {
+                // Setting a field discards earlier increments of it
+                for field in update.keys() {
+                    current_deltas.remove(field);
+                }
+                current.merge(update)
+            }
+        }
+
+        // Apply the deltas. Fields that we know the value of are changed
+        // right away, all others need to be incremented in the store
+        let mut remaining = BTreeMap::new();
+        match self {
+            Remove => {}
+            Overwrite(current) => {
+                for (field, delta) in &deltas {
+                    add_delta(current, field, delta);
+                }
+            }
+            Update(current)
+            | Increment {
+                updates: current, ..
+            } => {
+                for (field, delta) in deltas {
+                    if current.contains_key(&field) {
+                        add_delta(current, &field, &delta);
+                    } else {
+                        remaining.insert(field, delta);
+                    }
+                }
+            }
+        }
+        if remaining.is_empty() {
+            return;
+        }
+        match self {
+            Update(current) => {
+                *self = Increment {
+                    updates: std::mem::take(current),
+                    deltas: remaining,
+                }
+            }
+            Increment {
+                deltas: current, ..
+            } => {
+                for (field, delta) in remaining {
+                    let value = match current.remove(&field) {
+                        Some(value) => value.add_number(&delta).unwrap_or(delta),
+                        None => delta,
+                    };
+                    current.insert(field, value);
+                }
+            }
+            Remove | Overwrite(_) => unreachable!("deltas were applied already"),
         }
     }
 }
@@ -1585,10 +2628,61 @@ pub struct EntityCache {
     // Marks whether updates should go in `handler_updates`.
     in_handler: bool,
 
+    /// For a cache created with `forks`, the changes that the cache it was
+    /// forked from had accumulated at that point
+    base_updates: Arc<HashMap<EntityKey, EntityOp>>,
+
+    /// The entities that were read or changed since `track_access` was
+    /// called; `None` if access is not tracked
+    access: Option<EntityAccess>,
+
+    /// The entities that are known not to exist in the store
+    missing: Option<Arc<MissingEntityCache>>,
+
+    /// The entity types whose entities are written at most once
+    append_only: Arc<BTreeSet<EntityType>>,
+
     /// The store is only used to read entities.
     pub store: Arc<dyn SubgraphStore>,
 }
 
+/// Check that `entity` does not set any of the field names from
+/// `GRAPH_ENTITY_RESERVED_FIELDS`. Since these are configured for each node
+/// and not in the subgraph, the error is not deterministic. The limits on
+/// the size of values are part of the manifest and checked with
+/// `EntityLimits::check`
+pub fn check_reserved_fields(
+    key: &EntityKey,
+    entity: &HashMap<Attribute, Value>,
+) -> Result<(), anyhow::Error> {
+    for field in entity.keys() {
+        if ENTITY_RESERVED_FIELDS.iter().any(|name| name == field) {
+            return Err(anyhow!(
+                "Entity {}[{}]: `{}` is a reserved field name and can not be set",
+                key.entity_type,
+                key.entity_id,
+                field
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// The keys of the entities that an `EntityCache` read and changed
+#[derive(Clone, Debug, Default)]
+pub struct EntityAccess {
+    pub reads: HashSet<EntityKey>,
+    pub writes: HashSet<EntityKey>,
+}
+
+impl EntityAccess {
+    /// Return `true` if anything that was read here was changed in
+    /// `earlier`, i.e., if the reads might have seen outdated entities
+    pub fn read_changes_of(&self, earlier: &EntityAccess) -> bool {
+        self.reads.iter().any(|key| earlier.writes.contains(key))
+    }
+}
+
 impl Debug for EntityCache {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         f.debug_struct("EntityCache")
@@ -1601,6 +2695,9 @@ impl Debug for EntityCache {
 pub struct ModificationsAndCache {
     pub modifications: Vec<EntityModification>,
     pub entity_lfu_cache: LfuCache<EntityKey, Option<Entity>>,
+    /// The number of entity changes that did not result in a modification
+    /// because they left the entity as it is in the store
+    pub unchanged: usize,
 }
 
 impl EntityCache {
@@ -1610,6 +2707,10 @@ impl EntityCache {
             updates: HashMap::new(),
             handler_updates: HashMap::new(),
             in_handler: false,
+            base_updates: Arc::new(HashMap::new()),
+            access: None,
+            missing: None,
+            append_only: Arc::new(BTreeSet::new()),
             store,
         }
     }
@@ -1623,10 +2724,80 @@ impl EntityCache {
             updates: HashMap::new(),
             handler_updates: HashMap::new(),
             in_handler: false,
+            base_updates: Arc::new(HashMap::new()),
+            access: None,
+            missing: None,
+            append_only: Arc::new(BTreeSet::new()),
             store,
         }
     }
 
+    /// Create `count` caches that start out with the same entities as this
+    /// one, so that handlers can run on them concurrently. The caches track
+    /// which entities they access and can be merged back into this cache
+    /// with `BlockState::extend`
+    pub fn forks(&self, count: usize) -> Vec<EntityCache> {
+        use std::collections::hash_map::Entry;
+
+        assert!(!self.in_handler);
+
+        let mut base_updates = self.base_updates.as_ref().clone();
+        for (key, op) in &self.updates {
+            match base_updates.entry(key.clone()) {
+                Entry::Vacant(entry) => {
+                    entry.insert(op.clone());
+                }
+                Entry::Occupied(mut entry) => entry.get_mut().accumulate(op.clone()),
+            }
+        }
+        let base_updates = Arc::new(base_updates);
+
+        (0..count)
+            .map(|_| EntityCache {
+                current: LfuCache::new(),
+                updates: HashMap::new(),
+                handler_updates: HashMap::new(),
+                in_handler: false,
+                base_updates: base_updates.clone(),
+                access: Some(EntityAccess::default()),
+                missing: self.missing.clone(),
+                append_only: self.append_only.clone(),
+                store: self.store.clone(),
+            })
+            .collect()
+    }
+
+    /// Consult `missing` before looking up entities in the store, and keep
+    /// it up to date
+    pub fn with_missing_entities(mut self, missing: Arc<MissingEntityCache>) -> Self {
+        self.missing = Some(missing);
+        self
+    }
+
+    /// Insert entities of the `append_only` types without checking whether
+    /// they exist in the store. Writing such an entity a second time makes
+    /// transacting the block fail
+    pub fn with_append_only_types(mut self, append_only: Arc<BTreeSet<EntityType>>) -> Self {
+        self.append_only = append_only;
+        self
+    }
+
+    /// Start recording which entities are read and changed
+    pub fn track_access(&mut self) {
+        self.access = Some(EntityAccess::default());
+    }
+
+    /// The entities that were read and changed since `track_access` was
+    /// called; stops tracking access
+    pub fn take_access(&mut self) -> EntityAccess {
+        self.access.take().unwrap_or_default()
+    }
+
+    /// The entities that were read and changed so far
+    pub fn access(&self) -> Option<&EntityAccess> {
+        self.access.as_ref()
+    }
+
     pub(crate) fn enter_handler(&mut self) {
         assert!(!self.in_handler);
         self.in_handler = true;
@@ -1650,8 +2821,18 @@ impl EntityCache {
     }
 
     pub fn get(&mut self, key: &EntityKey) -> Result<Option<Entity>, QueryExecutionError> {
-        // Get the current entity, apply any updates from `updates`, then from `handler_updates`.
-        let mut entity = self.current.get_entity(&*self.store, &key)?;
+        if let Some(access) = &mut self.access {
+            access.reads.insert(key.clone());
+        }
+
+        // Get the current entity, apply any updates from `base_updates`
+        // and `updates`, then from `handler_updates`.
+        let mut entity = self
+            .current
+            .get_entity(&*self.store, &key, self.missing.as_deref())?;
+        if let Some(op) = self.base_updates.get(&key).cloned() {
+            entity = op.apply_to(entity)
+        }
         if let Some(op) = self.updates.get(&key).cloned() {
             entity = op.apply_to(entity)
         }
@@ -1661,12 +2842,185 @@ impl EntityCache {
         Ok(entity)
     }
 
+    /// Run `query` against the store and overlay the changes that have
+    /// been made through this cache but not been written yet, so that the
+    /// result reflects entities that were created, changed, or removed
+    /// earlier in the block, including the current handler. Only queries
+    /// for all entities of some types are supported, and if some of them
+    /// have pending changes, the query's filter must be one that can be
+    /// evaluated in memory
+    pub fn find(&mut self, query: EntityQuery) -> Result<Vec<Entity>, QueryExecutionError> {
+        let entity_types = match &query.collection {
+            EntityCollection::All(entity_types) => entity_types.clone(),
+            EntityCollection::Window(_) => {
+                return Err(QueryExecutionError::NotSupported(
+                    "windowed queries can not include pending changes".to_owned(),
+                ))
+            }
+        };
+
+        let changed: BTreeSet<EntityKey> = self
+            .base_updates
+            .keys()
+            .chain(self.updates.keys())
+            .chain(self.handler_updates.keys())
+            .filter(|key| {
+                key.subgraph_id == query.subgraph_id
+                    && entity_types
+                        .iter()
+                        .any(|entity_type| key.entity_type.is_data(entity_type))
+            })
+            .cloned()
+            .collect();
+
+        // Every changed entity can displace at most one entity from the
+        // store's result, so it is enough to get that many more
+        let subgraph_id = query.subgraph_id.clone();
+        let range = query.range.clone();
+        let order = query.order.clone();
+        let filter = query.filter.clone();
+        let store_range = EntityRange {
+            first: range
+                .first
+                .map(|first| first + range.skip + changed.len() as u32),
+            skip: 0,
+        };
+        let store_query = match changed.is_empty() {
+            true => query,
+            false => query.range(store_range),
+        };
+
+        let mut entities = Vec::new();
+        for mut entity in self.store.find(store_query)? {
+            let entity_type = match entity.remove("__typename") {
+                Some(Value::String(entity_type)) => entity_type,
+                _ => match entity_types.as_slice() {
+                    [entity_type] => entity_type.clone(),
+                    _ => {
+                        return Err(QueryExecutionError::NotSupported(
+                            "entities in the result must have a __typename".to_owned(),
+                        ))
+                    }
+                },
+            };
+            let key = EntityKey::data(subgraph_id.clone(), entity_type, entity.id().unwrap());
+            if !changed.contains(&key) {
+                if let Some(access) = &mut self.access {
+                    access.reads.insert(key);
+                }
+                entities.push(entity);
+            }
+        }
+        if changed.is_empty() {
+            return Ok(entities);
+        }
+
+        self.prefetch(changed.iter().cloned().collect())?;
+        for key in changed {
+            if let Some(entity) = self.get(&key)? {
+                let matches = match &filter {
+                    Some(filter) => filter.matches(&entity).ok_or_else(|| {
+                        QueryExecutionError::NotSupported(format!(
+                            "the filter {:?} can not include pending changes",
+                            filter
+                        ))
+                    })?,
+                    None => true,
+                };
+                if matches {
+                    entities.push(entity);
+                }
+            }
+        }
+
+        order.sort(&mut entities);
+        let entities = entities.into_iter().skip(range.skip as usize);
+        Ok(match range.first {
+            Some(first) => entities.take(first as usize).collect(),
+            None => entities.collect(),
+        })
+    }
+
+    /// Load all entities for `keys` that are not cached yet with one
+    /// lookup in the store, so that subsequent calls to `get` for them do
+    /// not go to the store. Entities that do not exist are remembered as
+    /// missing
+    pub fn prefetch(&mut self, keys: Vec<EntityKey>) -> Result<(), QueryExecutionError> {
+        let mut keys: Vec<_> = keys
+            .into_iter()
+            .filter(|key| !self.current.contains_key(key))
+            .collect();
+        keys.sort();
+        keys.dedup();
+
+        if let Some(missing) = &self.missing {
+            let (known_missing, unknown): (Vec<_>, Vec<_>) =
+                keys.into_iter().partition(|key| missing.contains(key));
+            for key in known_missing {
+                self.current.insert(key, None);
+            }
+            keys = unknown;
+        }
+
+        let mut by_subgraph: BTreeMap<_, BTreeMap<&EntityType, Vec<&str>>> = BTreeMap::new();
+        for key in &keys {
+            by_subgraph
+                .entry(&key.subgraph_id)
+                .or_default()
+                .entry(&key.entity_type)
+                .or_default()
+                .push(&key.entity_id);
+        }
+
+        let mut found = HashMap::new();
+        for (subgraph_id, ids_for_type) in by_subgraph {
+            for (entity_type, entities) in
+                self.store
+                    .get_many(subgraph_id, ids_for_type, BLOCK_NUMBER_MAX)?
+            {
+                for mut entity in entities {
+                    // `__typename` is for queries not for mappings.
+                    entity.remove("__typename");
+                    let key = EntityKey {
+                        subgraph_id: subgraph_id.clone(),
+                        entity_type: entity_type.clone(),
+                        entity_id: entity.id().unwrap(),
+                    };
+                    found.insert(key, entity);
+                }
+            }
+        }
+
+        for key in keys {
+            let entity = found.remove(&key);
+            if entity.is_none() {
+                if let Some(missing) = &self.missing {
+                    missing.insert(&key);
+                }
+            }
+            self.current.insert(key, entity);
+        }
+        Ok(())
+    }
+
     pub fn remove(&mut self, key: EntityKey) {
         self.entity_op(key, EntityOp::Remove);
     }
 
-    pub fn set(&mut self, key: EntityKey, entity: Entity) {
-        self.entity_op(key, EntityOp::Update(entity))
+    /// Set the entity for `key` to `entity`. Fails if `entity` uses a
+    /// reserved field name; see `check_reserved_fields`
+    pub fn set(&mut self, key: EntityKey, entity: Entity) -> Result<(), anyhow::Error> {
+        check_reserved_fields(&key, &entity)?;
+        self.entity_op(key, EntityOp::Update(entity));
+        Ok(())
+    }
+
+    /// Add `delta` to `field` of the entity. Unless the entity is read or
+    /// set in the same block, it is never loaded from the store; instead,
+    /// the store adds `delta` to the field when the block is transacted.
+    /// Incrementing an entity that does not exist does nothing
+    pub fn increment(&mut self, key: EntityKey, field: String, delta: Value) {
+        self.entity_op(key, EntityOp::increment(field, delta))
     }
 
     pub fn append(&mut self, operations: Vec<EntityOperation>) {
@@ -1687,6 +3041,10 @@ impl EntityCache {
     fn entity_op(&mut self, key: EntityKey, op: EntityOp) {
         use std::collections::hash_map::Entry;
 
+        if let Some(access) = &mut self.access {
+            access.writes.insert(key.clone());
+        }
+
         let updates = match self.in_handler {
             true => &mut self.handler_updates,
             false => &mut self.updates,
@@ -1720,12 +3078,51 @@ impl EntityCache {
     ) -> Result<ModificationsAndCache, QueryExecutionError> {
         assert!(!self.in_handler);
 
+        // Entities that are only incremented and that we know nothing
+        // about do not need to be loaded; the store increments them
+        let current = &self.current;
+        let (increments, updates): (HashMap<_, _>, HashMap<_, _>) =
+            std::mem::take(&mut self.updates)
+                .into_iter()
+                .partition(|(key, op)| match op {
+                    EntityOp::Increment { updates, .. } => {
+                        updates.is_empty() && !current.contains_key(key)
+                    }
+                    _ => false,
+                });
+
         // The first step is to make sure all entities being set are in `self.current`.
         // For each subgraph, we need a map of entity type to missing entity ids.
-        let missing = self
-            .updates
-            .keys()
-            .filter(|key| !self.current.contains_key(key));
+        // Entities that are known not to exist do not need to be looked up
+        let known_missing: Vec<_> = match &self.missing {
+            Some(missing) => updates
+                .keys()
+                .filter(|key| !self.current.contains_key(key) && missing.contains(key))
+                .cloned()
+                .collect(),
+            None => vec![],
+        };
+        for key in known_missing {
+            self.current.insert(key, None);
+        }
+
+        // Entities of append-only types are written at most once; unless
+        // we already know them, setting them inserts them
+        let append_only: Vec<_> = updates
+            .iter()
+            .filter(|(key, op)| match op {
+                EntityOp::Update(_) => {
+                    self.append_only.contains(&key.entity_type) && !self.current.contains_key(key)
+                }
+                _ => false,
+            })
+            .map(|(key, _)| key.clone())
+            .collect();
+        for key in append_only {
+            self.current.insert(key, None);
+        }
+
+        let missing = updates.keys().filter(|key| !self.current.contains_key(key));
 
         let mut missing_by_subgraph: BTreeMap<_, BTreeMap<&EntityType, Vec<&str>>> =
             BTreeMap::new();
@@ -1739,7 +3136,7 @@ impl EntityCache {
         }
 
         for (subgraph_id, keys) in missing_by_subgraph {
-            for (entity_type, entities) in store.get_many(subgraph_id, keys)? {
+            for (entity_type, entities) in store.get_many(subgraph_id, keys, BLOCK_NUMBER_MAX)? {
                 for entity in entities {
                     let key = EntityKey {
                         subgraph_id: subgraph_id.clone(),
@@ -1752,12 +3149,30 @@ impl EntityCache {
         }
 
         let mut mods = Vec::new();
-        for (key, update) in self.updates {
+        let mut unchanged = 0;
+        for (key, update) in increments {
+            if let EntityOp::Increment { deltas, .. } = update {
+                mods.push(EntityModification::Increment { key, deltas });
+            }
+        }
+        for (key, update) in updates {
             use EntityModification::*;
             let current = self.current.remove(&key).and_then(|entity| entity);
+            let update = match update {
+                // We know the entity now, and can compute its new value
+                EntityOp::Increment { .. } => match (&current, update.apply_to(current.clone())) {
+                    (_, None) => EntityOp::Remove,
+                    (None, Some(data)) => EntityOp::Update(data),
+                    (Some(_), Some(data)) => EntityOp::Overwrite(data),
+                },
+                update => update,
+            };
             let modification = match (current, update) {
                 // Entity was created
                 (None, EntityOp::Update(updates)) | (None, EntityOp::Overwrite(updates)) => {
+                    if let Some(missing) = &self.missing {
+                        missing.remove(&key);
+                    }
                     // Merging with an empty entity removes null fields.
                     let mut data = Entity::new();
                     data.merge_remove_null_fields(updates);
@@ -1791,14 +3206,17 @@ impl EntityCache {
                 }
                 // Entity was deleted, but it doesn't exist in the store
                 (None, EntityOp::Remove) => None,
+                (_, EntityOp::Increment { .. }) => unreachable!("increments were resolved above"),
             };
-            if let Some(modification) = modification {
-                mods.push(modification)
+            match modification {
+                Some(modification) => mods.push(modification),
+                None => unchanged += 1,
             }
         }
         Ok(ModificationsAndCache {
             modifications: mods,
             entity_lfu_cache: self.current,
+            unchanged,
         })
     }
 }
@@ -1809,13 +3227,25 @@ impl LfuCache<EntityKey, Option<Entity>> {
         &mut self,
         store: &(impl SubgraphStore + ?Sized),
         key: &EntityKey,
+        missing: Option<&MissingEntityCache>,
     ) -> Result<Option<Entity>, QueryExecutionError> {
         match self.get(&key) {
             None => {
+                if missing.map_or(false, |missing| missing.contains(key)) {
+                    self.insert(key.clone(), None);
+                    return Ok(None);
+                }
                 let mut entity = store.get(key.clone())?;
-                if let Some(entity) = &mut entity {
+                match &mut entity {
                     // `__typename` is for queries not for mappings.
-                    entity.remove("__typename");
+                    Some(entity) => {
+                        entity.remove("__typename");
+                    }
+                    None => {
+                        if let Some(missing) = missing {
+                            missing.insert(key);
+                        }
+                    }
                 }
                 self.insert(key.clone(), entity.clone());
                 Ok(entity)
@@ -1,4 +1,5 @@
 use std::cmp::PartialEq;
+use std::collections::BTreeMap;
 use std::fmt;
 use std::sync::Arc;
 use std::time::Instant;
@@ -6,9 +7,11 @@ use std::time::Instant;
 use anyhow::Error;
 use async_trait::async_trait;
 use futures::sync::mpsc;
+use semver::Version;
 
 use crate::components::metrics::HistogramVec;
 use crate::components::subgraph::SharedProofOfIndexing;
+use crate::data::subgraph::limits::EntityLimits;
 use crate::prelude::*;
 use web3::types::{Log, Transaction};
 
@@ -41,6 +44,10 @@ pub trait RuntimeHost: Send + Sync + Debug + 'static {
     /// Returns true if the RuntimeHost has a handler for an Ethereum event.
     fn matches_log(&self, log: &Log) -> bool;
 
+    /// Returns true if all handlers of the RuntimeHost for an Ethereum event
+    /// are declared `commutative`.
+    fn is_commutative_log(&self, log: &Log) -> bool;
+
     /// Returns true if the RuntimeHost has a handler for an Ethereum call.
     fn matches_call(&self, call: &EthereumCall) -> bool;
 
@@ -178,14 +185,18 @@ pub trait RuntimeHostBuilder: Clone + Send + Sync + 'static {
         subgraph_id: SubgraphDeploymentId,
         data_source: DataSource,
         top_level_templates: Arc<Vec<DataSourceTemplate>>,
+        deployment_context: Arc<BTreeMap<String, String>>,
+        entity_limits: EntityLimits,
         mapping_request_sender: mpsc::Sender<Self::Req>,
         metrics: Arc<HostMetrics>,
     ) -> Result<Self::Host, Error>;
 
     /// Spawn a mapping and return a channel for mapping requests. The sender should be able to be
-    /// cached and shared among mappings that use the same wasm file.
+    /// cached and shared among mappings that use the same wasm file and API version. The API
+    /// version determines which WASM features the mapping may use.
     fn spawn_mapping(
         raw_module: Vec<u8>,
+        api_version: &Version,
         logger: Logger,
         subgraph_id: SubgraphDeploymentId,
         metrics: Arc<HostMetrics>,
@@ -1,11 +1,13 @@
 use async_trait::async_trait;
-use web3::types::Log;
+use std::collections::BTreeSet;
+use web3::types::{Log, H256};
 
 use crate::prelude::*;
 use crate::util::lfu_cache::LfuCache;
 use crate::{
+    components::store::EntityType,
     components::subgraph::{MappingError, SharedProofOfIndexing},
-    data::subgraph::schema::SubgraphError,
+    data::subgraph::schema::{SkippedTrigger, SubgraphError},
 };
 
 #[derive(Clone, Debug)]
@@ -21,6 +23,7 @@ pub struct DataSourceTemplateInfo {
 pub struct BlockState {
     pub entity_cache: EntityCache,
     pub deterministic_errors: Vec<SubgraphError>,
+    pub skipped_triggers: Vec<SkippedTrigger>,
     created_data_sources: Vec<DataSourceTemplateInfo>,
 
     // Data sources created in the current handler.
@@ -38,18 +41,53 @@ impl BlockState {
         BlockState {
             entity_cache: EntityCache::with_current(store, lfu_cache),
             deterministic_errors: Vec::new(),
+            skipped_triggers: Vec::new(),
             created_data_sources: Vec::new(),
             handler_created_data_sources: Vec::new(),
             in_handler: false,
         }
     }
 
+    /// Consult `missing` before looking up entities in the store, see
+    /// `EntityCache::with_missing_entities`
+    pub fn with_missing_entities(mut self, missing: Arc<MissingEntityCache>) -> Self {
+        self.entity_cache = self.entity_cache.with_missing_entities(missing);
+        self
+    }
+
+    /// Insert entities of the `append_only` types without looking them up,
+    /// see `EntityCache::with_append_only_types`
+    pub fn with_append_only_types(mut self, append_only: Arc<BTreeSet<EntityType>>) -> Self {
+        self.entity_cache = self.entity_cache.with_append_only_types(append_only);
+        self
+    }
+
+    /// Create `count` block states whose entity caches are forks of this
+    /// one's, see `EntityCache::forks`
+    pub fn forks(&self, count: usize) -> Vec<BlockState> {
+        assert!(!self.in_handler);
+
+        self.entity_cache
+            .forks(count)
+            .into_iter()
+            .map(|entity_cache| BlockState {
+                entity_cache,
+                deterministic_errors: Vec::new(),
+                skipped_triggers: Vec::new(),
+                created_data_sources: Vec::new(),
+                handler_created_data_sources: Vec::new(),
+                in_handler: false,
+            })
+            .collect()
+    }
+
     pub fn extend(&mut self, other: BlockState) {
         assert!(!other.in_handler);
 
         let BlockState {
             entity_cache,
             deterministic_errors,
+            skipped_triggers,
             created_data_sources,
             handler_created_data_sources,
             in_handler,
@@ -60,6 +98,7 @@ impl BlockState {
             false => created_data_sources.extend(other.created_data_sources),
         }
         deterministic_errors.extend(other.deterministic_errors);
+        skipped_triggers.extend(other.skipped_triggers);
         entity_cache.extend(other.entity_cache);
     }
 
@@ -67,6 +106,20 @@ impl BlockState {
         !self.deterministic_errors.is_empty()
     }
 
+    /// Record a skipped trigger for every deterministic error that was
+    /// added since there were `errors_before` errors
+    pub fn record_skipped_triggers(
+        &mut self,
+        errors_before: usize,
+        transaction_hash: Option<H256>,
+        log_index: Option<u64>,
+    ) {
+        let skipped = self.deterministic_errors[errors_before..]
+            .iter()
+            .filter_map(|e| SkippedTrigger::new(e, transaction_hash, log_index));
+        self.skipped_triggers.extend(skipped);
+    }
+
     pub fn has_created_data_sources(&self) -> bool {
         assert!(!self.in_handler);
         !self.created_data_sources.is_empty()
@@ -111,6 +164,10 @@ pub trait SubgraphInstance<H: RuntimeHost> {
     /// Returns true if the subgraph has a handler for an Ethereum event.
     fn matches_log(&self, log: &Log) -> bool;
 
+    /// Returns true if the subgraph has a handler for an Ethereum event and
+    /// all its handlers for it are declared `commutative`.
+    fn is_commutative_log(&self, log: &Log) -> bool;
+
     /// Process and Ethereum trigger and return the resulting entity operations as a future.
     async fn process_trigger(
         &self,
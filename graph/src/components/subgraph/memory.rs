@@ -0,0 +1,98 @@
+use lazy_static::lazy_static;
+use std::env;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use crate::prelude::{GaugeVec, MetricsRegistry, SubgraphDeploymentId};
+
+lazy_static! {
+    /// The most memory, in MB, that the indexing pipeline of one deployment
+    /// should use. There is no limit if this is not set
+    static ref MEMORY_LIMIT: Option<usize> = env::var("GRAPH_DEPLOYMENT_MEMORY_LIMIT")
+        .ok()
+        .map(|s| {
+            s.parse::<usize>()
+                .expect("invalid GRAPH_DEPLOYMENT_MEMORY_LIMIT")
+                * 1_000_000
+        });
+}
+
+/// Approximate accounting of the memory used by the indexing pipeline of a
+/// deployment, i.e., its entity cache and the blocks, with their triggers,
+/// that the block stream has fetched but that have not been processed yet.
+///
+/// The numbers are estimates in bytes, based on `CacheWeight` for the entity
+/// cache and `EthereumBlockWithTriggers::approx_size` for blocks. When
+/// `GRAPH_DEPLOYMENT_MEMORY_LIMIT` is set, the block stream uses them to
+/// shrink the block ranges it fetches, and the entity cache is evicted more
+/// aggressively so that the deployment stays within the limit
+pub struct DeploymentMemory {
+    limit: Option<usize>,
+    entity_cache: AtomicUsize,
+    block_buffer: AtomicUsize,
+    usage: Box<GaugeVec>,
+}
+
+impl DeploymentMemory {
+    pub fn new(registry: Arc<impl MetricsRegistry>, deployment_id: &SubgraphDeploymentId) -> Self {
+        let usage = registry
+            .new_deployment_gauge_vec(
+                "deployment_pipeline_memory_bytes",
+                "Estimated memory used by the indexing pipeline of a deployment",
+                deployment_id.as_str(),
+                vec![String::from("component")],
+            )
+            .expect("failed to create `deployment_pipeline_memory_bytes` gauge");
+        DeploymentMemory {
+            limit: *MEMORY_LIMIT,
+            entity_cache: AtomicUsize::new(0),
+            block_buffer: AtomicUsize::new(0),
+            usage,
+        }
+    }
+
+    pub fn set_entity_cache(&self, bytes: usize) {
+        self.entity_cache.store(bytes, Ordering::SeqCst);
+        self.usage
+            .with_label_values(&["entity_cache"])
+            .set(bytes as f64);
+    }
+
+    pub fn set_block_buffer(&self, bytes: usize) {
+        self.block_buffer.store(bytes, Ordering::SeqCst);
+        self.usage
+            .with_label_values(&["block_buffer"])
+            .set(bytes as f64);
+    }
+
+    /// The estimated total memory used by the pipeline
+    pub fn usage(&self) -> usize {
+        self.entity_cache.load(Ordering::SeqCst) + self.block_buffer.load(Ordering::SeqCst)
+    }
+
+    pub fn over_limit(&self) -> bool {
+        self.limit.map_or(false, |limit| self.usage() > limit)
+    }
+
+    /// How much memory the next batch of blocks may use, or `None` if there
+    /// is no limit. The block buffer is always drained before the next
+    /// batch is fetched, so only the entity cache counts against the limit
+    pub fn block_budget(&self) -> Option<usize> {
+        self.limit
+            .map(|limit| limit.saturating_sub(self.entity_cache.load(Ordering::SeqCst)))
+    }
+
+    /// The weight to which the entity cache should be evicted, given that
+    /// it should never be bigger than `max_weight`. The cache always gets
+    /// at least half the limit so that one batch of huge blocks does not
+    /// wipe it out completely
+    pub fn entity_cache_budget(&self, max_weight: usize) -> usize {
+        match self.limit {
+            Some(limit) => {
+                let available = limit.saturating_sub(self.block_buffer.load(Ordering::SeqCst));
+                max_weight.min(available.max(limit / 2))
+            }
+            None => max_weight,
+        }
+    }
+}
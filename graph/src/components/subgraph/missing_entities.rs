@@ -0,0 +1,154 @@
+use lazy_static::lazy_static;
+use std::collections::{HashSet, VecDeque};
+use std::env;
+use std::sync::{Arc, Mutex};
+
+use crate::components::store::{EntityKey, EntityType};
+use crate::prelude::{Counter, Gauge, MetricsRegistry, SubgraphDeploymentId};
+
+lazy_static! {
+    /// The number of entities that the cache of missing entities of one
+    /// deployment remembers. Setting this to 0 turns the cache off
+    static ref MISSING_ENTITY_CACHE_SIZE: usize = env::var("GRAPH_MISSING_ENTITY_CACHE_SIZE")
+        .unwrap_or("10000".into())
+        .parse::<usize>()
+        .expect("invalid GRAPH_MISSING_ENTITY_CACHE_SIZE");
+}
+
+type MissingKey = (EntityType, String);
+
+/// The entities of a deployment that are known not to exist in the store.
+///
+/// The `EntityCache` remembers lookups that found nothing, too, but it
+/// loses them when it evicts entries; handlers that create entities if
+/// they are missing would then look the same entities up in the store over
+/// and over. The `EntityCache` therefore consults this cache before it goes
+/// to the store. Entries are added when a lookup in the store finds
+/// nothing and removed when the entity is inserted; since reverting blocks
+/// can bring entities back, the cache must be cleared on reverts. When the
+/// cache is full, the oldest entries are evicted first
+pub struct MissingEntityCache {
+    capacity: usize,
+    inner: Mutex<Inner>,
+    hits: Box<Counter>,
+    entries: Box<Gauge>,
+}
+
+#[derive(Default)]
+struct Inner {
+    keys: HashSet<MissingKey>,
+    /// The keys in the order in which they were added. Removed keys stay
+    /// in here until they are evicted
+    order: VecDeque<MissingKey>,
+}
+
+impl MissingEntityCache {
+    pub fn new(registry: Arc<impl MetricsRegistry>, deployment_id: &SubgraphDeploymentId) -> Self {
+        let hits = registry
+            .new_deployment_counter(
+                "deployment_missing_entity_cache_hits",
+                "Counts the entity lookups that did not go to the store because the entity is known to be missing",
+                deployment_id.as_str(),
+            )
+            .expect("failed to create `deployment_missing_entity_cache_hits` counter");
+        let entries = registry
+            .new_deployment_gauge(
+                "deployment_missing_entity_cache_entries",
+                "The number of entities that are known to be missing from the store",
+                deployment_id.as_str(),
+            )
+            .expect("failed to create `deployment_missing_entity_cache_entries` gauge");
+        Self::with_capacity(*MISSING_ENTITY_CACHE_SIZE, hits, entries)
+    }
+
+    fn with_capacity(capacity: usize, hits: Box<Counter>, entries: Box<Gauge>) -> Self {
+        MissingEntityCache {
+            capacity,
+            inner: Mutex::new(Inner::default()),
+            hits,
+            entries,
+        }
+    }
+
+    fn key(key: &EntityKey) -> MissingKey {
+        (key.entity_type.clone(), key.entity_id.clone())
+    }
+
+    /// Return `true` if the entity is known not to exist in the store
+    pub fn contains(&self, key: &EntityKey) -> bool {
+        let found = self.inner.lock().unwrap().keys.contains(&Self::key(key));
+        if found {
+            self.hits.inc();
+        }
+        found
+    }
+
+    /// Remember that the entity does not exist in the store
+    pub fn insert(&self, key: &EntityKey) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        let key = Self::key(key);
+        let mut inner = self.inner.lock().unwrap();
+        if inner.keys.insert(key.clone()) {
+            inner.order.push_back(key);
+        }
+        while inner.keys.len() > self.capacity || inner.order.len() > 2 * self.capacity {
+            match inner.order.pop_front() {
+                Some(oldest) => {
+                    inner.keys.remove(&oldest);
+                }
+                None => break,
+            }
+        }
+        self.entries.set(inner.keys.len() as f64);
+    }
+
+    /// Forget about the entity, e.g., because it is about to be inserted
+    pub fn remove(&self, key: &EntityKey) {
+        let mut inner = self.inner.lock().unwrap();
+        if inner.keys.remove(&Self::key(key)) {
+            self.entries.set(inner.keys.len() as f64);
+        }
+    }
+
+    pub fn clear(&self) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.keys.clear();
+        inner.order.clear();
+        self.entries.set(0.0);
+    }
+}
+
+#[test]
+fn missing_entity_cache() {
+    let cache = MissingEntityCache::with_capacity(
+        2,
+        Box::new(Counter::new("hits", "hits").unwrap()),
+        Box::new(Gauge::new("entries", "entries").unwrap()),
+    );
+    let subgraph_id = SubgraphDeploymentId::new("missing").unwrap();
+    let key = |id: &str| EntityKey::data(subgraph_id.clone(), "Band".to_owned(), id.to_owned());
+
+    cache.insert(&key("mogwai"));
+    cache.insert(&key("sigurros"));
+    assert!(cache.contains(&key("mogwai")));
+    assert!(!cache.contains(&key("low")));
+    assert_eq!(1.0, cache.hits.get());
+
+    // The oldest entry is evicted first
+    cache.insert(&key("low"));
+    assert!(!cache.contains(&key("mogwai")));
+    assert!(cache.contains(&key("sigurros")));
+    assert!(cache.contains(&key("low")));
+    assert_eq!(2.0, cache.entries.get());
+
+    cache.remove(&key("low"));
+    assert!(!cache.contains(&key("low")));
+    assert_eq!(1.0, cache.entries.get());
+
+    cache.clear();
+    assert!(!cache.contains(&key("sigurros")));
+    assert_eq!(0.0, cache.entries.get());
+}
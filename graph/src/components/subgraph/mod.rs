@@ -2,6 +2,8 @@ mod host;
 mod instance;
 mod instance_manager;
 mod loader;
+mod memory;
+mod missing_entities;
 mod proof_of_indexing;
 mod provider;
 mod registrar;
@@ -12,9 +14,11 @@ pub use self::host::{HostMetrics, MappingError, RuntimeHost, RuntimeHostBuilder}
 pub use self::instance::{BlockState, DataSourceTemplateInfo, SubgraphInstance};
 pub use self::instance_manager::SubgraphInstanceManager;
 pub use self::loader::DataSourceLoader;
+pub use self::memory::DeploymentMemory;
+pub use self::missing_entities::MissingEntityCache;
 pub use self::proof_of_indexing::{
     BlockEventStream, ProofOfIndexing, ProofOfIndexingEvent, ProofOfIndexingFinisher,
-    SharedProofOfIndexing,
+    ProofOfIndexingVersion, SharedProofOfIndexing, POI_TRANSITION_VERSION, POI_VERSION,
 };
 pub use self::provider::SubgraphAssignmentProvider;
 pub use self::registrar::{SubgraphRegistrar, SubgraphVersionSwitchingMode};
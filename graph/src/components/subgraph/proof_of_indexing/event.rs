@@ -16,6 +16,12 @@ pub enum ProofOfIndexingEvent<'a> {
         id: &'a str,
         data: &'a HashMap<String, Value>,
     },
+    IncrementEntity {
+        entity_type: &'a str,
+        id: &'a str,
+        field: &'a str,
+        delta: &'a Value,
+    },
 }
 
 impl StableHash for ProofOfIndexingEvent<'_> {
@@ -37,6 +43,17 @@ impl StableHash for ProofOfIndexingEvent<'_> {
                 id.stable_hash(sequence_number.next_child(), state);
                 data.stable_hash(sequence_number.next_child(), state);
             }
+            IncrementEntity {
+                entity_type,
+                id,
+                field,
+                delta,
+            } => {
+                entity_type.stable_hash(sequence_number.next_child(), state);
+                id.stable_hash(sequence_number.next_child(), state);
+                field.stable_hash(sequence_number.next_child(), state);
+                delta.stable_hash(sequence_number.next_child(), state);
+            }
         }
     }
 }
@@ -62,6 +79,17 @@ impl fmt::Debug for ProofOfIndexingEvent<'_> {
                 builder.field("id", id);
                 builder.field("data", &data.iter().collect::<BTreeMap<_, _>>());
             }
+            Self::IncrementEntity {
+                entity_type,
+                id,
+                field,
+                delta,
+            } => {
+                builder.field("entity_type", entity_type);
+                builder.field("id", id);
+                builder.field("field", field);
+                builder.field("delta", delta);
+            }
         }
         builder.finish()
     }
@@ -5,9 +5,101 @@ mod reference;
 pub use event::ProofOfIndexingEvent;
 pub use online::{BlockEventStream, ProofOfIndexing, ProofOfIndexingFinisher};
 
+use anyhow::anyhow;
 use atomic_refcell::AtomicRefCell;
+use lazy_static::lazy_static;
+use std::borrow::Cow;
+use std::convert::TryFrom;
+use std::fmt;
 use std::sync::Arc;
 
+lazy_static! {
+    /// The PoI version that new deployments report, see
+    /// `ProofOfIndexingVersion`
+    pub static ref POI_VERSION: ProofOfIndexingVersion = std::env::var("GRAPH_POI_VERSION")
+        .ok()
+        .map(|version| parse_version(&version, "GRAPH_POI_VERSION"))
+        .unwrap_or(ProofOfIndexingVersion::LATEST);
+
+    /// A PoI version that new deployments compute in addition to
+    /// `POI_VERSION`. Setting this makes it possible to roll out new hashing
+    /// rules gradually: indexers compute both variants for a while, and
+    /// switch the version they report once the whole network can produce
+    /// the new one
+    pub static ref POI_TRANSITION_VERSION: Option<ProofOfIndexingVersion> =
+        std::env::var("GRAPH_POI_TRANSITION_VERSION")
+            .ok()
+            .map(|version| parse_version(&version, "GRAPH_POI_TRANSITION_VERSION"))
+            .filter(|version| version != &*POI_VERSION);
+}
+
+fn parse_version(version: &str, var: &str) -> ProofOfIndexingVersion {
+    version
+        .parse::<i32>()
+        .map_err(|e| anyhow!("{}", e))
+        .and_then(ProofOfIndexingVersion::try_from)
+        .unwrap_or_else(|e| panic!("invalid value `{}` for {}: {}", version, var, e))
+}
+
+/// The rules by which a PoI is hashed. Whenever these rules change, a new
+/// version needs to be added here; deployments record which versions they
+/// compute so that the PoI for all versions can be reproduced later
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ProofOfIndexingVersion {
+    /// The rules used since the PoI was introduced
+    Legacy = 1,
+}
+
+impl ProofOfIndexingVersion {
+    pub const LATEST: ProofOfIndexingVersion = ProofOfIndexingVersion::Legacy;
+
+    /// The id under which the intermediate digest for `causality_region`
+    /// is stored. `Legacy` digests use the name of the causality region
+    /// so that existing deployments keep working; later versions append
+    /// the version to it
+    pub fn digest_id<'a>(&self, causality_region: &'a str) -> Cow<'a, str> {
+        match self {
+            ProofOfIndexingVersion::Legacy => Cow::Borrowed(causality_region),
+        }
+    }
+
+    /// The inverse of `digest_id`
+    pub fn from_digest_id(id: &str) -> Result<(Self, &str), anyhow::Error> {
+        match id.rfind("$v") {
+            Some(pos) => {
+                let version = id[pos + 2..]
+                    .parse::<i32>()
+                    .map_err(|_| anyhow!("invalid PoI digest id `{}`", id))?;
+                Ok((Self::try_from(version)?, &id[..pos]))
+            }
+            None => Ok((ProofOfIndexingVersion::Legacy, id)),
+        }
+    }
+}
+
+impl TryFrom<i32> for ProofOfIndexingVersion {
+    type Error = anyhow::Error;
+
+    fn try_from(version: i32) -> Result<Self, Self::Error> {
+        match version {
+            1 => Ok(ProofOfIndexingVersion::Legacy),
+            _ => Err(anyhow!("unknown PoI version {}", version)),
+        }
+    }
+}
+
+impl From<ProofOfIndexingVersion> for i32 {
+    fn from(version: ProofOfIndexingVersion) -> i32 {
+        version as i32
+    }
+}
+
+impl fmt::Display for ProofOfIndexingVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", *self as i32)
+    }
+}
+
 /// This concoction of types is to allow MappingContext to be static, yet still
 /// have shared mutable data for derive_with_empty_block_state. The static
 /// requirement is so that host exports can be static for wasmtime.
@@ -57,7 +149,10 @@ mod tests {
         }
 
         for block_i in 0..block_count {
-            let mut stream = ProofOfIndexing::new(block_i.try_into().unwrap());
+            let mut stream = ProofOfIndexing::new(
+                block_i.try_into().unwrap(),
+                &[ProofOfIndexingVersion::Legacy],
+            );
 
             for (name, region) in reference.causality_regions.iter() {
                 let block = &region.blocks[block_i];
@@ -264,4 +359,75 @@ mod tests {
             }
         }
     }
+
+    #[test]
+    fn digest_ids() {
+        let version = ProofOfIndexingVersion::Legacy;
+        let id = version.digest_id("ethereum/mainnet");
+        assert_eq!("ethereum/mainnet", id);
+        assert_eq!(
+            (version, "ethereum/mainnet"),
+            ProofOfIndexingVersion::from_digest_id(&id).unwrap()
+        );
+
+        assert!(ProofOfIndexingVersion::from_digest_id("ethereum/mainnet$v0").is_err());
+        assert!(ProofOfIndexingVersion::from_digest_id("ethereum/mainnet$vx").is_err());
+    }
+
+    /// Replaying the events that forks recorded results in the same digests
+    /// as writing the events directly
+    #[test]
+    fn fork_and_replay() {
+        let logger = Logger::root(Discard, o!());
+        let data = hashmap! {
+            "val".to_owned() => Value::Int(1)
+        };
+        let events = vec![
+            (
+                "a",
+                ProofOfIndexingEvent::SetEntity {
+                    entity_type: "t",
+                    id: "id1",
+                    data: &data,
+                },
+            ),
+            (
+                "b",
+                ProofOfIndexingEvent::RemoveEntity {
+                    entity_type: "t",
+                    id: "id2",
+                },
+            ),
+            (
+                "a",
+                ProofOfIndexingEvent::RemoveEntity {
+                    entity_type: "t",
+                    id: "id1",
+                },
+            ),
+        ];
+        let digests = |poi: ProofOfIndexing| {
+            poi.take()
+                .into_iter()
+                .map(|(id, stream)| (id, stream.pause(None)))
+                .collect::<HashMap<_, _>>()
+        };
+
+        let mut direct = ProofOfIndexing::new(1, &[ProofOfIndexingVersion::Legacy]);
+        for (region, event) in &events {
+            direct.write(&logger, region, event);
+        }
+
+        let mut replayed = ProofOfIndexing::new(1, &[ProofOfIndexingVersion::Legacy]);
+        let mut first = replayed.fork();
+        let mut second = replayed.fork();
+        for (region, event) in &events[1..] {
+            second.write(&logger, region, event);
+        }
+        first.write(&logger, events[0].0, &events[0].1);
+        replayed.replay(&logger, &first);
+        replayed.replay(&logger, &second);
+
+        assert_eq!(digests(direct), digests(replayed));
+    }
 }
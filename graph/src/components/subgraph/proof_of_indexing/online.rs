@@ -2,8 +2,8 @@
 //! Any hash constructed from here should be the same as if the same data was given
 //! to the reference implementation, but this is updated incrementally
 
-use super::ProofOfIndexingEvent;
-use crate::prelude::{debug, EthereumBlockPointer, Logger, SubgraphDeploymentId};
+use super::{ProofOfIndexingEvent, ProofOfIndexingVersion};
+use crate::prelude::{debug, EthereumBlockPointer, Logger, SubgraphDeploymentId, Value};
 use lazy_static::lazy_static;
 use stable_hash::crypto::{Blake3SeqNo, SetHasher};
 use stable_hash::prelude::*;
@@ -102,11 +102,98 @@ impl BlockEventStream {
 #[derive(Default)]
 pub struct ProofOfIndexing {
     block_number: u64,
+    /// The PoI versions to compute; during a transition between versions,
+    /// there is more than one
+    versions: Vec<ProofOfIndexingVersion>,
     /// The POI is updated for each data source independently. This is necessary because
     /// some data sources (eg: IPFS files) may be unreliable and therefore cannot mix
     /// state with other data sources. This may also give us some freedom to change
-    /// the order of triggers in the future.
+    /// the order of triggers in the future. The key is the digest id for the
+    /// causality region and PoI version
     per_causality_region: HashMap<String, BlockEventStream>,
+    /// For a PoI created with `fork`, the events that were written to it
+    /// together with their causality region, in the order in which they
+    /// were written. They only become part of the digest when they are
+    /// replayed into the PoI the fork was created from
+    recorded: Option<Vec<(String, RecordedEvent)>>,
+}
+
+/// A `ProofOfIndexingEvent` that owns its data
+enum RecordedEvent {
+    RemoveEntity {
+        entity_type: String,
+        id: String,
+    },
+    SetEntity {
+        entity_type: String,
+        id: String,
+        data: HashMap<String, Value>,
+    },
+    IncrementEntity {
+        entity_type: String,
+        id: String,
+        field: String,
+        delta: Value,
+    },
+}
+
+impl RecordedEvent {
+    fn new(event: &ProofOfIndexingEvent<'_>) -> Self {
+        match event {
+            ProofOfIndexingEvent::RemoveEntity { entity_type, id } => RecordedEvent::RemoveEntity {
+                entity_type: entity_type.to_string(),
+                id: id.to_string(),
+            },
+            ProofOfIndexingEvent::SetEntity {
+                entity_type,
+                id,
+                data,
+            } => RecordedEvent::SetEntity {
+                entity_type: entity_type.to_string(),
+                id: id.to_string(),
+                data: (*data).clone(),
+            },
+            ProofOfIndexingEvent::IncrementEntity {
+                entity_type,
+                id,
+                field,
+                delta,
+            } => RecordedEvent::IncrementEntity {
+                entity_type: entity_type.to_string(),
+                id: id.to_string(),
+                field: field.to_string(),
+                delta: (*delta).clone(),
+            },
+        }
+    }
+
+    fn as_event(&self) -> ProofOfIndexingEvent<'_> {
+        match self {
+            RecordedEvent::RemoveEntity { entity_type, id } => {
+                ProofOfIndexingEvent::RemoveEntity { entity_type, id }
+            }
+            RecordedEvent::SetEntity {
+                entity_type,
+                id,
+                data,
+            } => ProofOfIndexingEvent::SetEntity {
+                entity_type,
+                id,
+                data,
+            },
+            RecordedEvent::IncrementEntity {
+                entity_type,
+                id,
+                field,
+                delta,
+            } => ProofOfIndexingEvent::IncrementEntity {
+                entity_type,
+                id,
+                field,
+                delta,
+            },
+        }
+    }
 }
 
 impl fmt::Debug for ProofOfIndexing {
@@ -116,12 +203,35 @@ impl fmt::Debug for ProofOfIndexing {
 }
 
 impl ProofOfIndexing {
-    pub fn new(block_number: u64) -> Self {
+    pub fn new(block_number: u64, versions: &[ProofOfIndexingVersion]) -> Self {
         Self {
             block_number,
+            versions: versions.to_vec(),
+            per_causality_region: HashMap::new(),
+            recorded: None,
+        }
+    }
+
+    /// Create a PoI that records the events written to it instead of
+    /// digesting them, so that handlers can write to it concurrently with
+    /// other handlers. The events are added to this PoI with `replay`
+    pub fn fork(&self) -> Self {
+        Self {
+            block_number: self.block_number,
+            versions: self.versions.clone(),
             per_causality_region: HashMap::new(),
+            recorded: Some(Vec::new()),
+        }
+    }
+
+    /// Write the events recorded by `fork` in the order in which they were
+    /// recorded
+    pub fn replay(&mut self, logger: &Logger, fork: &ProofOfIndexing) {
+        for (causality_region, event) in fork.recorded.iter().flatten() {
+            self.write(logger, causality_region, &event.as_event());
         }
     }
+
     /// Adds an event to the digest of the ProofOfIndexingStream local to the causality region
     pub fn write(
         &mut self,
@@ -129,6 +239,11 @@ impl ProofOfIndexing {
         causality_region: &str,
         event: &ProofOfIndexingEvent<'_>,
     ) {
+        if let Some(recorded) = &mut self.recorded {
+            recorded.push((causality_region.to_owned(), RecordedEvent::new(event)));
+            return;
+        }
+
         if *LOG_EVENTS {
             debug!(
                 logger,
@@ -138,14 +253,17 @@ impl ProofOfIndexing {
             );
         }
 
-        // This may be better with the raw_entry API, once that is stabilized
-        if let Some(causality_region) = self.per_causality_region.get_mut(causality_region) {
-            causality_region.write(event);
-        } else {
-            let mut entry = BlockEventStream::new(self.block_number);
-            entry.write(event);
-            self.per_causality_region
-                .insert(causality_region.to_owned(), entry);
+        for version in &self.versions {
+            let digest_id = version.digest_id(causality_region);
+            // This may be better with the raw_entry API, once that is stabilized
+            if let Some(stream) = self.per_causality_region.get_mut(digest_id.as_ref()) {
+                stream.write(event);
+            } else {
+                let mut entry = BlockEventStream::new(self.block_number);
+                entry.write(event);
+                self.per_causality_region
+                    .insert(digest_id.into_owned(), entry);
+            }
         }
     }
     pub fn take(self) -> HashMap<String, BlockEventStream> {
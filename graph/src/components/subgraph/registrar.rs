@@ -1,5 +1,6 @@
 use async_trait::async_trait;
 
+use crate::data::subgraph::schema::SkippedTrigger;
 use crate::prelude::*;
 
 #[derive(Clone, Copy, Debug)]
@@ -40,4 +41,41 @@ pub trait SubgraphRegistrar: Send + Sync + 'static {
         hash: SubgraphDeploymentId,
         node_id: NodeId,
     ) -> Result<(), SubgraphRegistrarError>;
+
+    /// List the triggers that were skipped for the deployment `hash`
+    /// because their handlers failed with a deterministic error
+    async fn skipped_triggers(
+        &self,
+        hash: SubgraphDeploymentId,
+    ) -> Result<Vec<SkippedTrigger>, SubgraphRegistrarError>;
+
+    /// Deploy `hash` as a new version of `name` to re-run the skipped
+    /// triggers of the deployment it is grafted onto. The graft block must
+    /// be before the first skipped trigger that has not been replayed yet.
+    /// Returns the number of triggers that will be replayed
+    async fn replay_skipped_triggers(
+        &self,
+        name: SubgraphName,
+        hash: SubgraphDeploymentId,
+        node_id: NodeId,
+    ) -> Result<usize, SubgraphRegistrarError>;
+
+    /// Turn away new subscriptions to the deployment `hash` if `disabled`
+    /// is `true`, and allow them again otherwise. Subscriptions that are
+    /// already running are not affected
+    async fn set_subscriptions_disabled(
+        &self,
+        hash: SubgraphDeploymentId,
+        disabled: bool,
+    ) -> Result<(), SubgraphRegistrarError>;
+
+    /// Set the context entry `key` of the deployment `hash` to `value`, or
+    /// remove it if `value` is `None`. Mappings see the change when the
+    /// deployment is restarted
+    async fn set_deployment_context(
+        &self,
+        hash: SubgraphDeploymentId,
+        key: String,
+        value: Option<String>,
+    ) -> Result<(), SubgraphRegistrarError>;
 }
@@ -0,0 +1,260 @@
+//! Expressions for fields with a `@derivedExpression(expression: "..")`
+//! directive. Their values are not stored but computed from the sibling
+//! fields of the entity when it is queried.
+//!
+//! The grammar is deliberately small:
+//!
+//! ```text
+//! expr    := sum ('||' sum)*
+//! sum     := product (('+' | '-') product)*
+//! product := unary (('*' | '/') unary)*
+//! unary   := '-' unary | primary
+//! primary := field | number | string | '(' expr ')'
+//! ```
+//!
+//! where `field` is the name of a sibling field, `number` a decimal number
+//! like `1` or `2.5`, and `string` a string in single quotes, with quotes
+//! inside the string doubled as in SQL
+use anyhow::{anyhow, Error};
+use std::iter::Peekable;
+use std::str::{Chars, FromStr};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BinaryOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Concat,
+}
+
+impl BinaryOp {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            BinaryOp::Add => "+",
+            BinaryOp::Sub => "-",
+            BinaryOp::Mul => "*",
+            BinaryOp::Div => "/",
+            BinaryOp::Concat => "||",
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Expression {
+    /// The value of a sibling field
+    Field(String),
+    /// A decimal number; only consists of digits and at most one `.`
+    Number(String),
+    String(String),
+    Neg(Box<Expression>),
+    Binary(Box<Expression>, BinaryOp, Box<Expression>),
+}
+
+impl Expression {
+    /// The names of all fields that the expression refers to
+    pub fn fields(&self) -> Vec<&str> {
+        match self {
+            Expression::Field(name) => vec![name.as_str()],
+            Expression::Number(_) | Expression::String(_) => vec![],
+            Expression::Neg(expr) => expr.fields(),
+            Expression::Binary(lhs, _, rhs) => {
+                let mut fields = lhs.fields();
+                fields.extend(rhs.fields());
+                fields
+            }
+        }
+    }
+}
+
+impl FromStr for Expression {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parser = Parser {
+            chars: s.chars().peekable(),
+        };
+        let expr = parser.expr()?;
+        parser.skip_whitespace();
+        match parser.chars.next() {
+            None => Ok(expr),
+            Some(c) => Err(anyhow!("unexpected `{}`", c)),
+        }
+    }
+}
+
+struct Parser<'a> {
+    chars: Peekable<Chars<'a>>,
+}
+
+impl Parser<'_> {
+    fn skip_whitespace(&mut self) {
+        while self.chars.peek().map_or(false, |c| c.is_whitespace()) {
+            self.chars.next();
+        }
+    }
+
+    /// Skip whitespace and return the next character without consuming it
+    fn peek(&mut self) -> Option<char> {
+        self.skip_whitespace();
+        self.chars.peek().cloned()
+    }
+
+    fn expr(&mut self) -> Result<Expression, Error> {
+        let mut lhs = self.sum()?;
+        while self.peek() == Some('|') {
+            self.chars.next();
+            if self.chars.next() != Some('|') {
+                return Err(anyhow!("expected `||`"));
+            }
+            let rhs = self.sum()?;
+            lhs = Expression::Binary(Box::new(lhs), BinaryOp::Concat, Box::new(rhs));
+        }
+        Ok(lhs)
+    }
+
+    fn sum(&mut self) -> Result<Expression, Error> {
+        let mut lhs = self.product()?;
+        loop {
+            let op = match self.peek() {
+                Some('+') => BinaryOp::Add,
+                Some('-') => BinaryOp::Sub,
+                _ => return Ok(lhs),
+            };
+            self.chars.next();
+            let rhs = self.product()?;
+            lhs = Expression::Binary(Box::new(lhs), op, Box::new(rhs));
+        }
+    }
+
+    fn product(&mut self) -> Result<Expression, Error> {
+        let mut lhs = self.unary()?;
+        loop {
+            let op = match self.peek() {
+                Some('*') => BinaryOp::Mul,
+                Some('/') => BinaryOp::Div,
+                _ => return Ok(lhs),
+            };
+            self.chars.next();
+            let rhs = self.unary()?;
+            lhs = Expression::Binary(Box::new(lhs), op, Box::new(rhs));
+        }
+    }
+
+    fn unary(&mut self) -> Result<Expression, Error> {
+        if self.peek() == Some('-') {
+            self.chars.next();
+            Ok(Expression::Neg(Box::new(self.unary()?)))
+        } else {
+            self.primary()
+        }
+    }
+
+    fn primary(&mut self) -> Result<Expression, Error> {
+        match self.peek() {
+            Some('(') => {
+                self.chars.next();
+                let expr = self.expr()?;
+                match self.peek() {
+                    Some(')') => {
+                        self.chars.next();
+                        Ok(expr)
+                    }
+                    _ => Err(anyhow!("expected `)`")),
+                }
+            }
+            Some('\'') => {
+                self.chars.next();
+                let mut s = String::new();
+                loop {
+                    match self.chars.next() {
+                        Some('\'') if self.chars.peek() == Some(&'\'') => {
+                            self.chars.next();
+                            s.push('\'');
+                        }
+                        Some('\'') => return Ok(Expression::String(s)),
+                        Some(c) => s.push(c),
+                        None => return Err(anyhow!("unterminated string")),
+                    }
+                }
+            }
+            Some(c) if c.is_ascii_digit() => {
+                let mut number = String::new();
+                while let Some(c) = self.chars.peek().cloned() {
+                    if c.is_ascii_digit() || (c == '.' && !number.contains('.')) {
+                        number.push(c);
+                        self.chars.next();
+                    } else {
+                        break;
+                    }
+                }
+                if number.ends_with('.') {
+                    return Err(anyhow!("invalid number `{}`", number));
+                }
+                Ok(Expression::Number(number))
+            }
+            Some(c) if c.is_ascii_alphabetic() || c == '_' => {
+                let mut name = String::new();
+                while let Some(c) = self.chars.peek().cloned() {
+                    if c.is_ascii_alphanumeric() || c == '_' {
+                        name.push(c);
+                        self.chars.next();
+                    } else {
+                        break;
+                    }
+                }
+                Ok(Expression::Field(name))
+            }
+            Some(c) => Err(anyhow!("unexpected `{}`", c)),
+            None => Err(anyhow!("unexpected end of expression")),
+        }
+    }
+}
+
+#[test]
+fn parse_expressions() {
+    use Expression::*;
+
+    fn field(name: &str) -> Box<Expression> {
+        Box::new(Field(name.to_owned()))
+    }
+
+    fn parse(s: &str) -> Expression {
+        Expression::from_str(s).expect("expression is valid")
+    }
+
+    assert_eq!(
+        Binary(
+            field("price"),
+            BinaryOp::Add,
+            Box::new(Binary(field("fee"), BinaryOp::Mul, field("quantity")))
+        ),
+        parse("price + fee * quantity")
+    );
+    assert_eq!(
+        Binary(
+            Box::new(Binary(field("a"), BinaryOp::Sub, field("b"))),
+            BinaryOp::Div,
+            Box::new(Number("2.5".to_owned()))
+        ),
+        parse("(a - b) / 2.5")
+    );
+    assert_eq!(
+        Binary(
+            Box::new(Binary(
+                field("first"),
+                BinaryOp::Concat,
+                Box::new(String(" o'".to_owned()))
+            )),
+            BinaryOp::Concat,
+            field("last")
+        ),
+        parse("first || ' o''' || last")
+    );
+    assert_eq!(Neg(field("amount")), parse("-amount"));
+    assert_eq!(vec!["a", "b", "c"], parse("a * (b + -c)").fields());
+
+    for invalid in &["", "a +", "a | b", "(a", "'a", "1.", "a; drop", "a b"] {
+        assert!(Expression::from_str(invalid).is_err(), "{}", invalid);
+    }
+}
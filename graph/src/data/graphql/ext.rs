@@ -248,6 +248,20 @@ impl ValueExt for Value {
 
 pub trait DirectiveFinder {
     fn find_directive(&self, name: String) -> Option<&Directive>;
+
+    /// The roles from a `@restricted(roles: [..])` directive, if there is
+    /// one. Only queries made on behalf of one of these roles may select
+    /// what the directive is attached to
+    fn restricted_roles(&self) -> Option<Vec<&String>> {
+        self.find_directive("restricted".to_string())
+            .map(|restricted| {
+                restricted
+                    .argument("roles")
+                    .and_then(|roles| roles.as_list())
+                    .map(|roles| roles.iter().filter_map(|role| role.as_string()).collect())
+                    .unwrap_or_default()
+            })
+    }
 }
 
 impl DirectiveFinder for ObjectType {
@@ -271,3 +285,55 @@ impl DirectiveFinder for Vec<Directive> {
         self.iter().find(|directive| directive.name.eq(&name))
     }
 }
+
+pub trait FieldExt {
+    /// The previous name of this field if it has a `@renamed(from: "..")`
+    /// directive
+    fn renamed_from(&self) -> Option<&String>;
+
+    /// Whether this field has a `@decimals` directive, which adds a
+    /// `<name>Decimal` field to the API schema
+    fn has_decimals(&self) -> bool;
+
+    /// The sibling field that holds the number of decimals if this field
+    /// has a `@decimals(field: "..")` directive
+    fn decimals_field(&self) -> Option<&String>;
+
+    /// The expression from the `@derivedExpression(expression: "..")`
+    /// directive of this field, if it has one
+    fn derived_expression(&self) -> Option<&String>;
+
+    /// The field of the related type that this field is derived from if it
+    /// has a `@derivedFrom(field: "..")` directive
+    fn derived_from(&self) -> Option<&String>;
+}
+
+impl FieldExt for Field {
+    fn renamed_from(&self) -> Option<&String> {
+        self.find_directive("renamed".to_string())
+            .and_then(|renamed| renamed.argument("from"))
+            .and_then(|from| from.as_string())
+    }
+
+    fn has_decimals(&self) -> bool {
+        self.find_directive("decimals".to_string()).is_some()
+    }
+
+    fn decimals_field(&self) -> Option<&String> {
+        self.find_directive("decimals".to_string())
+            .and_then(|decimals| decimals.argument("field"))
+            .and_then(|field| field.as_string())
+    }
+
+    fn derived_expression(&self) -> Option<&String> {
+        self.find_directive("derivedExpression".to_string())
+            .and_then(|derived| derived.argument("expression"))
+            .and_then(|expression| expression.as_string())
+    }
+
+    fn derived_from(&self) -> Option<&String> {
+        self.find_directive("derivedFrom".to_string())
+            .and_then(|derived| derived.argument("field"))
+            .and_then(|field| field.as_string())
+    }
+}
@@ -2,7 +2,7 @@ mod serialization;
 
 /// Traits to navigate the GraphQL AST
 pub mod ext;
-pub use ext::{DirectiveExt, DocumentExt, ObjectTypeExt, TypeExt, ValueExt};
+pub use ext::{DirectiveExt, DocumentExt, FieldExt, ObjectTypeExt, TypeExt, ValueExt};
 
 /// Utilities for working with GraphQL values.
 mod values;
@@ -25,6 +25,9 @@ pub mod shape_hash;
 
 pub mod effort;
 
+/// Expressions for fields with a `@derivedExpression` directive
+pub mod expression;
+
 pub mod object_or_interface;
 pub use object_or_interface::ObjectOrInterface;
 
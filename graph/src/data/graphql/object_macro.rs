@@ -36,6 +36,13 @@ impl IntoValue for i32 {
     }
 }
 
+impl IntoValue for i64 {
+    #[inline]
+    fn into_value(self) -> q::Value {
+        q::Value::String(self.to_string())
+    }
+}
+
 impl IntoValue for u64 {
     #[inline]
     fn into_value(self) -> q::Value {
@@ -22,6 +22,15 @@ impl Clone for CloneableAnyhowError {
     }
 }
 
+impl CloneableAnyhowError {
+    pub fn downcast_ref<E>(&self) -> Option<&E>
+    where
+        E: fmt::Display + fmt::Debug + Send + Sync + 'static,
+    {
+        self.0.downcast_ref::<E>()
+    }
+}
+
 impl From<anyhow::Error> for CloneableAnyhowError {
     fn from(f: anyhow::Error) -> Self {
         Self(Arc::new(f))
@@ -78,6 +87,20 @@ pub enum QueryExecutionError {
     EventStreamError,
     FulltextQueryRequiresFilter,
     DeploymentReverted,
+    AccessDenied(Pos, String),
+}
+
+impl QueryExecutionError {
+    /// Return `true` if `e` is the error with which Postgres cancels a
+    /// statement that ran longer than its `statement_timeout`
+    pub fn is_statement_timeout(e: &diesel::result::Error) -> bool {
+        match e {
+            diesel::result::Error::DatabaseError(_, info) => {
+                info.message() == "canceling statement due to statement timeout"
+            }
+            _ => false,
+        }
+    }
 }
 
 impl Error for QueryExecutionError {
@@ -218,6 +241,7 @@ impl fmt::Display for QueryExecutionError {
             TooExpensive => write!(f, "query is too expensive"),
             Throttled=> write!(f, "service is overloaded and can not run the query right now. Please try again in a few minutes"),
             DeploymentReverted => write!(f, "the chain was reorganized while executing the query"),
+            AccessDenied(_, what) => write!(f, "Access to `{}` is restricted", what),
         }
     }
 }
@@ -246,6 +270,16 @@ impl From<bigdecimal::ParseBigDecimalError> for QueryExecutionError {
     }
 }
 
+impl From<diesel::result::Error> for QueryExecutionError {
+    fn from(e: diesel::result::Error) -> Self {
+        if QueryExecutionError::is_statement_timeout(&e) {
+            QueryExecutionError::Timeout
+        } else {
+            StoreError::from(e).into()
+        }
+    }
+}
+
 impl From<StoreError> for QueryExecutionError {
     fn from(e: StoreError) -> Self {
         QueryExecutionError::StoreError(CloneableAnyhowError(Arc::new(e.into())))
@@ -365,7 +399,8 @@ impl Serialize for QueryError {
             | QueryError::ExecutionError(AmbiguousDerivedFromResult(pos, _, _, _))
             | QueryError::ExecutionError(EnumCoercionError(pos, _, _, _, _))
             | QueryError::ExecutionError(ScalarCoercionError(pos, _, _, _))
-            | QueryError::ExecutionError(UnknownField(pos, _, _)) => {
+            | QueryError::ExecutionError(UnknownField(pos, _, _))
+            | QueryError::ExecutionError(AccessDenied(pos, _)) => {
                 let mut location = HashMap::new();
                 location.insert("line", pos.line);
                 location.insert("column", pos.column);
@@ -1,6 +1,6 @@
 use serde::de::Deserializer;
 use serde::Deserialize;
-use std::collections::{BTreeMap, HashMap};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::ops::{Deref, DerefMut};
 use std::sync::Arc;
 
@@ -131,6 +131,10 @@ pub struct Query {
     pub shape_hash: u64,
     pub query_text: Arc<String>,
     pub variables_text: Arc<String>,
+    /// The roles on behalf of which the query is made. Types and fields
+    /// with a `@restricted` directive can only be queried if one of their
+    /// roles is in this set; anonymous queries have no roles
+    pub roles: BTreeSet<String>,
     _force_use_of_new: (),
 }
 
@@ -155,7 +159,14 @@ impl Query {
             shape_hash,
             query_text: Arc::new(query_text),
             variables_text: Arc::new(variables_text),
+            roles: BTreeSet::new(),
             _force_use_of_new: (),
         }
     }
+
+    /// Make the query on behalf of `roles`
+    pub fn with_roles(mut self, roles: BTreeSet<String>) -> Self {
+        self.roles = roles;
+        self
+    }
 }
@@ -1,4 +1,5 @@
-use crate::components::store::SubgraphStore;
+use crate::components::store::{BlockNumber, SubgraphStore};
+use crate::data::graphql::expression::Expression;
 use crate::data::graphql::ext::{DirectiveExt, DirectiveFinder, DocumentExt, TypeExt, ValueExt};
 use crate::data::store::ValueType;
 use crate::data::subgraph::{SubgraphDeploymentId, SubgraphName};
@@ -10,13 +11,15 @@ use crate::prelude::{
 use anyhow::{Context, Error};
 use graphql_parser::{self, Pos};
 use inflector::Inflector;
+use lazy_static::lazy_static;
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
-use std::collections::{BTreeMap, HashMap, HashSet};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::convert::TryFrom;
 use std::fmt;
-use std::hash::Hash;
+use std::hash::{Hash, Hasher};
 use std::iter::FromIterator;
 use std::str::FromStr;
 use std::sync::Arc;
@@ -28,6 +31,49 @@ pub const META_FIELD_NAME: &str = "_meta";
 
 pub const BLOCK_FIELD_TYPE: &str = "_Block_";
 
+/// The field of `_meta` that holds the indexing status of a deployment
+pub const INDEXING_STATUS_FIELD: &str = "indexing";
+pub const INDEXING_STATUS_TYPE: &str = "_IndexingStatus_";
+pub const INDEXING_ERROR_TYPE: &str = "_IndexingError_";
+
+/// System field holding the block at which an entity was last changed,
+/// i.e., the lower end of its block range
+pub const LAST_UPDATED_BLOCK_FIELD: &str = "_lastUpdatedBlock";
+
+/// Entries in `where` filters that restrict entities to those that were
+/// changed, or created, at or after a block
+pub const CHANGE_BLOCK_FILTER_FIELD: &str = "_change_block";
+pub const CREATE_BLOCK_FILTER_FIELD: &str = "_create_block";
+
+/// The input type for `_change_block` and `_create_block` filters
+pub const BLOCK_CHANGED_FILTER_TYPE: &str = "BlockChangedFilter";
+
+/// Fields and types that make the GraphQL API usable as a subgraph of an
+/// Apollo Federation supergraph
+pub const SERVICE_FIELD_NAME: &str = "_service";
+pub const SERVICE_FIELD_TYPE: &str = "_Service";
+pub const ENTITIES_FIELD_NAME: &str = "_entities";
+pub const ENTITY_UNION_TYPE: &str = "_Entity";
+pub const ANY_SCALAR_TYPE: &str = "_Any";
+pub const KEY_DIRECTIVE: &str = "key";
+
+lazy_static! {
+    /// Whether to expose `_lastUpdatedBlock` on entities in the GraphQL API.
+    /// Set `GRAPH_ENABLE_LAST_UPDATED_BLOCK` to turn it on
+    pub static ref LAST_UPDATED_BLOCK_ENABLED: bool =
+        std::env::var("GRAPH_ENABLE_LAST_UPDATED_BLOCK").is_ok();
+
+    /// Whether to add the `_service` and `_entities` fields that Apollo
+    /// Federation needs to the GraphQL API. Set `GRAPH_ENABLE_FEDERATION` to
+    /// turn it on
+    pub static ref FEDERATION_ENABLED: bool = std::env::var("GRAPH_ENABLE_FEDERATION").is_ok();
+}
+
+/// Fields that `@snapshot` adds to the history entity of a type, in addition
+/// to the fields of the type itself
+pub const SNAPSHOT_OF_FIELD: &str = "snapshotOf";
+pub const SNAPSHOT_BLOCK_FIELD: &str = "snapshotBlock";
+
 #[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Strings(Vec<String>);
 
@@ -101,6 +147,20 @@ pub enum SchemaValidationError {
     FulltextIncludedFieldMissingRequiredProperty,
     #[error("Fulltext entity field, {0}, not found or not a string")]
     FulltextIncludedFieldInvalid(String),
+    #[error("@snapshot on type `{0}` requires a positive `interval` argument")]
+    SnapshotIntervalInvalid(String),
+    #[error("@snapshot on type `{0}` conflicts with the existing type `{1}`")]
+    SnapshotTypeConflict(String, String),
+    #[error("@ttl on type `{0}` requires a positive `blocks` argument")]
+    TtlInvalid(String),
+    #[error("Field `{1}` in type `{0}` has invalid @renamed: {2}")]
+    InvalidRenamed(String, String, String), // (type, field, reason)
+    #[error("Field `{1}` in type `{0}` has invalid @decimals: {2}")]
+    InvalidDecimals(String, String, String), // (type, field, reason)
+    #[error("Field `{1}` in type `{0}` has invalid @derivedExpression: {2}")]
+    InvalidDerivedExpression(String, String, String), // (type, field, reason)
+    #[error("@restricted on `{0}` is invalid: {1}")]
+    InvalidRestricted(String, String), // (type or type.field, reason)
 }
 
 #[derive(Clone, Debug, PartialEq)]
@@ -355,6 +415,10 @@ pub struct ApiSchema {
     // Root types for the api schema.
     pub query_type: Arc<ObjectType>,
     pub subscription_type: Option<Arc<ObjectType>>,
+
+    /// A hash of the schema document that changes whenever the schema
+    /// changes, for caches of things that depend on the schema
+    version: u64,
 }
 
 impl ApiSchema {
@@ -370,14 +434,25 @@ impl ApiSchema {
             .get_root_subscription_type()
             .cloned()
             .map(Arc::new);
+        let version = {
+            let mut hasher = DefaultHasher::new();
+            api_schema.id.hash(&mut hasher);
+            api_schema.document.to_string().hash(&mut hasher);
+            hasher.finish()
+        };
 
         Ok(Self {
             schema: api_schema,
             query_type: Arc::new(query_type),
             subscription_type,
+            version,
         })
     }
 
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+
     pub fn document(&self) -> &s::Document {
         &self.schema.document
     }
@@ -534,6 +609,7 @@ impl Schema {
             interfaces_for_type,
             types_for_interface,
         };
+        schema.add_snapshot_types()?;
         schema.add_subgraph_id_directives(id);
 
         Ok(schema)
@@ -599,6 +675,149 @@ impl Schema {
         self.interfaces_for_type.get(type_name)
     }
 
+    /// The name of the history entity type that `@snapshot` maintains for
+    /// the entity type `type_name`
+    pub fn snapshot_type_name(type_name: &str) -> String {
+        format!("{}Snapshot", type_name)
+    }
+
+    /// Return the snapshot interval in blocks for every entity type with a
+    /// `@snapshot(interval: ...)` directive
+    pub fn snapshot_intervals(&self) -> BTreeMap<String, BlockNumber> {
+        self.document
+            .get_object_type_definitions()
+            .into_iter()
+            .filter_map(|object_type| {
+                Self::snapshot_interval(object_type)
+                    .and_then(|interval| interval.ok())
+                    .map(|interval| (object_type.name.clone(), interval))
+            })
+            .collect()
+    }
+
+    /// Return the names of the entity types with an `@appendOnly`
+    /// directive. Every entity of such a type is written at most once,
+    /// which makes it possible to insert it without checking whether it
+    /// already exists
+    pub fn append_only_types(&self) -> BTreeSet<String> {
+        self.document
+            .get_object_type_definitions()
+            .into_iter()
+            .filter(|object_type| {
+                object_type
+                    .find_directive(String::from("appendOnly"))
+                    .is_some()
+            })
+            .map(|object_type| object_type.name.clone())
+            .collect()
+    }
+
+    /// The number of blocks after their last change that entities of type
+    /// `object_type` expire, if the type has a valid `@ttl(blocks: ...)`
+    /// directive
+    pub fn ttl(object_type: &ObjectType) -> Option<BlockNumber> {
+        Self::ttl_blocks(object_type).and_then(|blocks| blocks.ok())
+    }
+
+    fn ttl_blocks(object_type: &ObjectType) -> Option<Result<BlockNumber, SchemaValidationError>> {
+        object_type
+            .find_directive(String::from("ttl"))
+            .map(|ttl| match ttl.argument("blocks") {
+                Some(Value::Int(blocks)) if blocks.as_i64().map_or(false, |blocks| blocks > 0) => {
+                    Ok(blocks.as_i64().unwrap())
+                }
+                _ => Err(SchemaValidationError::TtlInvalid(object_type.name.clone())),
+            })
+    }
+
+    fn snapshot_interval(
+        object_type: &ObjectType,
+    ) -> Option<Result<BlockNumber, SchemaValidationError>> {
+        object_type
+            .find_directive(String::from("snapshot"))
+            .map(|snapshot| match snapshot.argument("interval") {
+                Some(Value::Int(interval))
+                    if interval.as_i64().map_or(false, |interval| interval > 0) =>
+                {
+                    Ok(interval.as_i64().unwrap())
+                }
+                _ => Err(SchemaValidationError::SnapshotIntervalInvalid(
+                    object_type.name.clone(),
+                )),
+            })
+    }
+
+    /// For every entity type `T` with a `@snapshot` directive, add the
+    /// history entity type `TSnapshot` to the schema. It has all the stored
+    /// fields of `T`, and in addition references the entity it is a copy of
+    /// and records the block at which the copy was taken
+    fn add_snapshot_types(&mut self) -> Result<(), SchemaValidationError> {
+        let mut snapshot_types = Vec::new();
+        for object_type in self.document.get_object_type_definitions() {
+            if Self::snapshot_interval(object_type).transpose()?.is_none() {
+                continue;
+            }
+
+            let name = Self::snapshot_type_name(&object_type.name);
+            if self.document.get_object_type_definition(&name).is_some() {
+                return Err(SchemaValidationError::SnapshotTypeConflict(
+                    object_type.name.clone(),
+                    name,
+                ));
+            }
+
+            let field = |name: &str, field_type: s::Type| s::Field {
+                position: Pos::default(),
+                description: None,
+                name: name.to_owned(),
+                arguments: vec![],
+                field_type,
+                directives: vec![],
+            };
+            let non_null =
+                |name: &str| s::Type::NonNullType(Box::new(s::Type::NamedType(name.to_owned())));
+
+            let mut fields = vec![
+                field("id", non_null("ID")),
+                field(SNAPSHOT_OF_FIELD, non_null(&object_type.name)),
+                field(SNAPSHOT_BLOCK_FIELD, non_null("Int")),
+            ];
+            fields.extend(
+                object_type
+                    .fields
+                    .iter()
+                    .filter(|field| {
+                        field.name != "id"
+                            && field.find_directive(String::from("derivedFrom")).is_none()
+                            && field
+                                .find_directive(String::from("derivedExpression"))
+                                .is_none()
+                    })
+                    .map(|field| s::Field {
+                        directives: vec![],
+                        ..field.clone()
+                    }),
+            );
+
+            snapshot_types.push(Definition::TypeDefinition(TypeDefinition::Object(
+                ObjectType {
+                    position: Pos::default(),
+                    description: None,
+                    name,
+                    implements_interfaces: vec![],
+                    directives: vec![s::Directive {
+                        name: "entity".to_owned(),
+                        position: Pos::default(),
+                        arguments: vec![],
+                    }],
+                    fields,
+                },
+            )));
+        }
+        self.document.definitions.extend(snapshot_types);
+        Ok(())
+    }
+
     // Adds a @subgraphId(id: ...) directive to object/interface/enum types in the schema.
     pub fn add_subgraph_id_directives(&mut self, id: SubgraphDeploymentId) {
         for definition in self.document.definitions.iter_mut() {
@@ -658,6 +877,11 @@ impl Schema {
         errors.append(&mut self.validate_fields());
         errors.append(&mut self.validate_import_directives());
         errors.append(&mut self.validate_fulltext_directives());
+        errors.append(&mut self.validate_renamed_directives());
+        errors.append(&mut self.validate_decimals_directives());
+        errors.append(&mut self.validate_derived_expressions());
+        errors.append(&mut self.validate_restricted_directives());
+        errors.append(&mut self.validate_ttl_directives());
         errors.append(&mut self.validate_imported_types(schemas));
         if errors.is_empty() {
             Ok(())
@@ -1032,6 +1256,238 @@ impl Schema {
             })
     }
 
+    /// Check that `@renamed(from: "..")` names a string that can be used as
+    /// an additional name for the field in the API schema
+    fn validate_renamed_directives(&self) -> Vec<SchemaValidationError> {
+        let mut errors = vec![];
+        for (type_name, fields) in self.document.get_object_and_interface_type_fields() {
+            let mut seen = HashSet::new();
+            for field in fields {
+                let renamed = match field.find_directive("renamed".to_string()) {
+                    Some(renamed) => renamed,
+                    None => continue,
+                };
+                let invalid = |reason: &str| {
+                    SchemaValidationError::InvalidRenamed(
+                        type_name.to_string(),
+                        field.name.to_string(),
+                        reason.to_string(),
+                    )
+                };
+                match renamed.argument("from") {
+                    Some(Value::String(from)) => {
+                        if fields.iter().any(|other| &other.name == from) {
+                            errors.push(invalid("the old name is still used by a field"));
+                        } else if !seen.insert(from) {
+                            errors.push(invalid("another field was renamed from the same name"));
+                        }
+                    }
+                    _ => errors.push(invalid("the `from` argument must be a string")),
+                }
+            }
+        }
+        errors
+    }
+
+    /// Check that `@decimals` is only used on `BigInt` fields, that the
+    /// `<name>Decimal` field it adds does not clash with another field, and
+    /// that `@decimals(field: "..")` names an `Int` field of the same type
+    fn validate_decimals_directives(&self) -> Vec<SchemaValidationError> {
+        fn is_scalar(field_type: &Type, scalar: &str) -> bool {
+            match field_type {
+                Type::NamedType(name) => name == scalar,
+                Type::NonNullType(inner) => is_scalar(inner, scalar),
+                Type::ListType(_) => false,
+            }
+        }
+
+        let mut errors = vec![];
+        for (type_name, fields) in self.document.get_object_and_interface_type_fields() {
+            for field in fields {
+                let directive = match field.find_directive("decimals".to_string()) {
+                    Some(directive) => directive,
+                    None => continue,
+                };
+                let invalid = |reason: &str| {
+                    SchemaValidationError::InvalidDecimals(
+                        type_name.to_string(),
+                        field.name.to_string(),
+                        reason.to_string(),
+                    )
+                };
+                if !is_scalar(&field.field_type, "BigInt") {
+                    errors.push(invalid("the field must be a BigInt"));
+                }
+                let decimal_name = format!("{}Decimal", field.name);
+                if fields.iter().any(|other| other.name == decimal_name) {
+                    errors.push(invalid(&format!(
+                        "the field `{}` already exists",
+                        decimal_name
+                    )));
+                }
+                match directive.argument("field") {
+                    None => {}
+                    Some(Value::String(decimals)) => {
+                        let is_int = fields
+                            .iter()
+                            .find(|other| &other.name == decimals)
+                            .map_or(false, |other| is_scalar(&other.field_type, "Int"));
+                        if !is_int {
+                            errors.push(invalid("the `field` argument must name an Int field"));
+                        }
+                    }
+                    Some(_) => errors.push(invalid("the `field` argument must be a string")),
+                }
+            }
+        }
+        errors
+    }
+
+    /// Check that `@derivedExpression(expression: "..")` is only used on
+    /// fields of type `Int`, `BigInt`, `BigDecimal` or `String`, and that
+    /// the expression parses and only refers to stored fields of one of
+    /// these types
+    fn validate_derived_expressions(&self) -> Vec<SchemaValidationError> {
+        fn is_computable(field: &Field) -> bool {
+            let is_scalar = match &field.field_type {
+                Type::NamedType(name) => Some(name),
+                Type::NonNullType(inner) => match inner.as_ref() {
+                    Type::NamedType(name) => Some(name),
+                    _ => None,
+                },
+                Type::ListType(_) => None,
+            }
+            .map_or(false, |name| {
+                ["Int", "BigInt", "BigDecimal", "String"].contains(&name.as_str())
+            });
+            is_scalar && field.name != "id"
+        }
+
+        let mut errors = vec![];
+        for object_type in self.document.get_object_type_definitions() {
+            for field in &object_type.fields {
+                let directive = match field.find_directive("derivedExpression".to_string()) {
+                    Some(directive) => directive,
+                    None => continue,
+                };
+                let invalid = |reason: String| {
+                    SchemaValidationError::InvalidDerivedExpression(
+                        object_type.name.to_string(),
+                        field.name.to_string(),
+                        reason,
+                    )
+                };
+                if !is_computable(field) {
+                    errors.push(invalid(
+                        "the field must be an Int, BigInt, BigDecimal, or String".to_string(),
+                    ));
+                }
+                let expression = match directive.argument("expression") {
+                    Some(Value::String(expression)) => expression,
+                    _ => {
+                        errors.push(invalid(
+                            "the `expression` argument must be a string".to_string(),
+                        ));
+                        continue;
+                    }
+                };
+                let expression = match Expression::from_str(expression) {
+                    Ok(expression) => expression,
+                    Err(e) => {
+                        errors.push(invalid(format!("the expression is invalid: {}", e)));
+                        continue;
+                    }
+                };
+                for name in expression.fields() {
+                    let is_stored = object_type
+                        .fields
+                        .iter()
+                        .find(|other| other.name == name)
+                        .map_or(false, |other| {
+                            is_computable(other)
+                                && other
+                                    .find_directive("derivedExpression".to_string())
+                                    .is_none()
+                        });
+                    if !is_stored {
+                        errors.push(invalid(format!(
+                            "`{}` is not a stored Int, BigInt, BigDecimal, or String field",
+                            name
+                        )));
+                    }
+                }
+            }
+        }
+        errors
+    }
+
+    /// Check that every `@restricted` directive lists at least one role,
+    /// and that the `id` field is never restricted
+    fn validate_restricted_directives(&self) -> Vec<SchemaValidationError> {
+        fn check(
+            errors: &mut Vec<SchemaValidationError>,
+            target: String,
+            directive: Option<&Directive>,
+        ) {
+            let directive = match directive {
+                Some(directive) => directive,
+                None => return,
+            };
+            let valid = match directive.argument("roles") {
+                Some(Value::List(roles)) => {
+                    !roles.is_empty()
+                        && roles.iter().all(|role| match role {
+                            Value::String(role) => !role.is_empty(),
+                            _ => false,
+                        })
+                }
+                _ => false,
+            };
+            if !valid {
+                errors.push(SchemaValidationError::InvalidRestricted(
+                    target,
+                    "the `roles` argument must be a non-empty list of role names".to_string(),
+                ));
+            }
+        }
+
+        let mut errors = vec![];
+        for object_type in self.document.get_object_type_definitions() {
+            check(
+                &mut errors,
+                object_type.name.to_string(),
+                object_type.find_directive("restricted".to_string()),
+            );
+        }
+        for (type_name, fields) in self.document.get_object_and_interface_type_fields() {
+            for field in fields {
+                let target = format!("{}.{}", type_name, field.name);
+                if field.name == "id" && field.restricted_roles().is_some() {
+                    errors.push(SchemaValidationError::InvalidRestricted(
+                        target,
+                        "the `id` field can not be restricted".to_string(),
+                    ));
+                    continue;
+                }
+                check(
+                    &mut errors,
+                    target,
+                    field.find_directive("restricted".to_string()),
+                );
+            }
+        }
+        errors
+    }
+
+    fn validate_ttl_directives(&self) -> Vec<SchemaValidationError> {
+        self.document
+            .get_object_type_definitions()
+            .into_iter()
+            .filter_map(|object_type| Self::ttl_blocks(object_type))
+            .filter_map(|blocks| blocks.err())
+            .collect()
+    }
+
     fn validate_fields(&self) -> Vec<SchemaValidationError> {
         let local_types = self.document.get_object_and_interface_type_fields();
         let local_enums = self
@@ -1565,3 +2021,284 @@ type Gravatar @entity {
 
     assert_eq!(schema.validate_fulltext_directives(), vec![]);
 }
+
+#[test]
+fn snapshot_types() {
+    let schema = "
+        type Token @entity @snapshot(interval: 100) {
+            id: ID!,
+            supply: BigInt!,
+            holders: [Holder!]! @derivedFrom(field: \"token\")
+        }
+
+        type Holder @entity { id: ID!, token: Token! }
+    ";
+    let schema = Schema::parse(schema, SubgraphDeploymentId::new("dummy").unwrap()).unwrap();
+
+    let snapshot = schema
+        .document
+        .get_object_type_definition("TokenSnapshot")
+        .expect("the snapshot type was added");
+    let fields: Vec<_> = snapshot
+        .fields
+        .iter()
+        .map(|field| field.name.as_str())
+        .collect();
+    assert_eq!(vec!["id", "snapshotOf", "snapshotBlock", "supply"], fields);
+
+    let mut intervals = BTreeMap::new();
+    intervals.insert("Token".to_owned(), 100);
+    assert_eq!(intervals, schema.snapshot_intervals());
+
+    let schema = "type Token @entity @snapshot(interval: 0) { id: ID! }";
+    let res = Schema::parse(schema, SubgraphDeploymentId::new("dummy").unwrap());
+    assert_eq!(
+        res.unwrap_err()
+            .downcast::<SchemaValidationError>()
+            .unwrap(),
+        SchemaValidationError::SnapshotIntervalInvalid("Token".to_owned())
+    );
+}
+
+#[test]
+fn renamed_directive_validation() {
+    fn validate(fields: &str) -> Vec<SchemaValidationError> {
+        let raw = format!("type A @entity {{ id: ID!\n {} }}", fields);
+        let document = graphql_parser::parse_schema(&raw).expect("Failed to parse raw schema");
+        let schema = Schema::new(SubgraphDeploymentId::new("id").unwrap(), document);
+        schema.validate_renamed_directives()
+    }
+
+    fn invalid(field: &str, reason: &str) -> Vec<SchemaValidationError> {
+        vec![SchemaValidationError::InvalidRenamed(
+            "A".to_owned(),
+            field.to_owned(),
+            reason.to_owned(),
+        )]
+    }
+
+    assert_eq!(
+        Vec::<SchemaValidationError>::new(),
+        validate("name: String @renamed(from: \"label\")")
+    );
+    assert_eq!(
+        invalid("name", "the old name is still used by a field"),
+        validate("name: String @renamed(from: \"label\")\n label: String")
+    );
+    assert_eq!(
+        invalid("title", "another field was renamed from the same name"),
+        validate(
+            "name: String @renamed(from: \"label\")\n title: String @renamed(from: \"label\")"
+        )
+    );
+    assert_eq!(
+        invalid("name", "the `from` argument must be a string"),
+        validate("name: String @renamed(from: 1)")
+    );
+}
+
+#[test]
+fn decimals_directive_validation() {
+    fn validate(fields: &str) -> Vec<SchemaValidationError> {
+        let raw = format!("type A @entity {{ id: ID!\n {} }}", fields);
+        let document = graphql_parser::parse_schema(&raw).expect("Failed to parse raw schema");
+        let schema = Schema::new(SubgraphDeploymentId::new("id").unwrap(), document);
+        schema.validate_decimals_directives()
+    }
+
+    fn invalid(field: &str, reason: &str) -> Vec<SchemaValidationError> {
+        vec![SchemaValidationError::InvalidDecimals(
+            "A".to_owned(),
+            field.to_owned(),
+            reason.to_owned(),
+        )]
+    }
+
+    assert_eq!(
+        Vec::<SchemaValidationError>::new(),
+        validate("amount: BigInt! @decimals")
+    );
+    assert_eq!(
+        Vec::<SchemaValidationError>::new(),
+        validate("amount: BigInt @decimals(field: \"decimals\")\n decimals: Int!")
+    );
+    assert_eq!(
+        invalid("amount", "the field must be a BigInt"),
+        validate("amount: [BigInt!]! @decimals")
+    );
+    assert_eq!(
+        invalid("amount", "the field `amountDecimal` already exists"),
+        validate("amount: BigInt! @decimals\n amountDecimal: BigDecimal!")
+    );
+    assert_eq!(
+        invalid("amount", "the `field` argument must name an Int field"),
+        validate("amount: BigInt! @decimals(field: \"decimals\")\n decimals: String!")
+    );
+    assert_eq!(
+        invalid("amount", "the `field` argument must name an Int field"),
+        validate("amount: BigInt! @decimals(field: \"missing\")")
+    );
+    assert_eq!(
+        invalid("amount", "the `field` argument must be a string"),
+        validate("amount: BigInt! @decimals(field: 18)")
+    );
+}
+
+#[test]
+fn derived_expression_validation() {
+    fn validate(fields: &str) -> Vec<SchemaValidationError> {
+        let raw = format!(
+            "type A @entity {{ id: ID!\n price: BigInt!\n quantity: Int!\n name: String\n {} }}",
+            fields
+        );
+        let document = graphql_parser::parse_schema(&raw).expect("Failed to parse raw schema");
+        let schema = Schema::new(SubgraphDeploymentId::new("id").unwrap(), document);
+        schema.validate_derived_expressions()
+    }
+
+    fn invalid(field: &str, reason: &str) -> Vec<SchemaValidationError> {
+        vec![SchemaValidationError::InvalidDerivedExpression(
+            "A".to_owned(),
+            field.to_owned(),
+            reason.to_owned(),
+        )]
+    }
+
+    assert_eq!(
+        Vec::<SchemaValidationError>::new(),
+        validate("total: BigInt @derivedExpression(expression: \"price * quantity\")")
+    );
+    assert_eq!(
+        Vec::<SchemaValidationError>::new(),
+        validate("label: String @derivedExpression(expression: \"name || ' x' || quantity\")")
+    );
+    assert_eq!(
+        invalid(
+            "total",
+            "the field must be an Int, BigInt, BigDecimal, or String"
+        ),
+        validate("total: [BigInt!] @derivedExpression(expression: \"price\")")
+    );
+    assert_eq!(
+        invalid("total", "the `expression` argument must be a string"),
+        validate("total: BigInt @derivedExpression(expression: 1)")
+    );
+    assert_eq!(
+        invalid("total", "the expression is invalid: expected `)`"),
+        validate("total: BigInt @derivedExpression(expression: \"(price\")")
+    );
+    assert_eq!(
+        invalid(
+            "total",
+            "`cost` is not a stored Int, BigInt, BigDecimal, or String field"
+        ),
+        validate("total: BigInt @derivedExpression(expression: \"price + cost\")")
+    );
+    assert_eq!(
+        invalid(
+            "double",
+            "`total` is not a stored Int, BigInt, BigDecimal, or String field"
+        ),
+        validate(
+            "total: BigInt @derivedExpression(expression: \"price * quantity\")\n \
+             double: BigInt @derivedExpression(expression: \"total * 2\")"
+        )
+    );
+}
+
+#[test]
+fn restricted_directive_validation() {
+    fn validate(directive: &str, field_directive: &str) -> Vec<SchemaValidationError> {
+        let raw = format!(
+            "type Wallet @entity {} {{ id: ID!\n balance: BigInt {} }}",
+            directive, field_directive
+        );
+        let document = graphql_parser::parse_schema(&raw).expect("Failed to parse raw schema");
+        let schema = Schema::new(SubgraphDeploymentId::new("id").unwrap(), document);
+        schema.validate_restricted_directives()
+    }
+
+    fn invalid(target: &str) -> Vec<SchemaValidationError> {
+        vec![SchemaValidationError::InvalidRestricted(
+            target.to_owned(),
+            "the `roles` argument must be a non-empty list of role names".to_owned(),
+        )]
+    }
+
+    assert_eq!(
+        Vec::<SchemaValidationError>::new(),
+        validate("@restricted(roles: [\"ops\"])", "")
+    );
+    assert_eq!(
+        Vec::<SchemaValidationError>::new(),
+        validate("", "@restricted(roles: [\"ops\", \"audit\"])")
+    );
+    assert_eq!(invalid("Wallet"), validate("@restricted(roles: [])", ""));
+    assert_eq!(invalid("Wallet"), validate("@restricted", ""));
+    assert_eq!(
+        invalid("Wallet.balance"),
+        validate("", "@restricted(roles: \"ops\")")
+    );
+    assert_eq!(
+        invalid("Wallet.balance"),
+        validate("", "@restricted(roles: [\"\"])")
+    );
+
+    let raw = "type Wallet @entity { id: ID! @restricted(roles: [\"ops\"]) }";
+    let document = graphql_parser::parse_schema(raw).expect("Failed to parse raw schema");
+    let schema = Schema::new(SubgraphDeploymentId::new("id").unwrap(), document);
+    assert_eq!(
+        vec![SchemaValidationError::InvalidRestricted(
+            "Wallet.id".to_owned(),
+            "the `id` field can not be restricted".to_owned(),
+        )],
+        schema.validate_restricted_directives()
+    );
+}
+
+#[test]
+fn append_only_types() {
+    const SCHEMA: &str = "
+      type Transfer @entity @appendOnly { id: ID!, amount: BigInt! }
+      type Account @entity { id: ID!, balance: BigInt! }
+    ";
+    let document = graphql_parser::parse_schema(SCHEMA).expect("Failed to parse raw schema");
+    let schema = Schema::new(SubgraphDeploymentId::new("id").unwrap(), document);
+
+    assert_eq!(
+        vec!["Transfer".to_owned()],
+        schema.append_only_types().into_iter().collect::<Vec<_>>()
+    );
+}
+
+#[test]
+fn ttl_directive_validation() {
+    fn schema(directive: &str) -> Schema {
+        let raw = format!("type Order @entity {} {{ id: ID! }}", directive);
+        let document = graphql_parser::parse_schema(&raw).expect("Failed to parse raw schema");
+        Schema::new(SubgraphDeploymentId::new("id").unwrap(), document)
+    }
+
+    fn ttl(directive: &str) -> Option<BlockNumber> {
+        let schema = schema(directive);
+        let object_type = schema
+            .document
+            .get_object_type_definition("Order")
+            .expect("the type exists");
+        Schema::ttl(object_type)
+    }
+
+    assert_eq!(Some(100), ttl("@ttl(blocks: 100)"));
+    assert_eq!(None, ttl(""));
+    assert_eq!(
+        Vec::<SchemaValidationError>::new(),
+        schema("@ttl(blocks: 100)").validate_ttl_directives()
+    );
+    for invalid in &["@ttl(blocks: 0)", "@ttl(blocks: \"100\")", "@ttl"] {
+        assert_eq!(None, ttl(invalid));
+        assert_eq!(
+            vec![SchemaValidationError::TtlInvalid("Order".to_owned())],
+            schema(invalid).validate_ttl_directives()
+        );
+    }
+}
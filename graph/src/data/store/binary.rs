@@ -0,0 +1,342 @@
+//! A compact binary encoding of entities. Encoding and decoding entities
+//! this way avoids building and parsing the intermediate JSON documents
+//! that `serde_json` needs, which gets expensive for large entities.
+//!
+//! Numbers are encoded as LEB128 varints, and strings and byte arrays are
+//! prefixed with their length. An entity is the number of its fields,
+//! followed by the name of each field and its length-prefixed value, so
+//! that `EntityBytes` can find a field without decoding the values of the
+//! fields before it
+use anyhow::{anyhow, Error};
+use std::ops::Range;
+use std::str::FromStr;
+
+use super::{scalar, Entity, Value};
+
+const TAG_NULL: u8 = 0;
+const TAG_STRING: u8 = 1;
+const TAG_INT: u8 = 2;
+const TAG_BIG_DECIMAL: u8 = 3;
+const TAG_BOOL: u8 = 4;
+const TAG_LIST: u8 = 5;
+const TAG_BYTES: u8 = 6;
+const TAG_BIG_INT: u8 = 7;
+
+/// Writes values in the binary encoding
+#[derive(Default)]
+pub struct Encoder {
+    buf: Vec<u8>,
+}
+
+impl Encoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn write_u64(&mut self, mut n: u64) {
+        loop {
+            let byte = (n & 0x7f) as u8;
+            n >>= 7;
+            if n == 0 {
+                self.buf.push(byte);
+                return;
+            }
+            self.buf.push(byte | 0x80);
+        }
+    }
+
+    pub fn write_bool(&mut self, b: bool) {
+        self.buf.push(b as u8);
+    }
+
+    pub fn write_bytes(&mut self, bytes: &[u8]) {
+        self.write_u64(bytes.len() as u64);
+        self.buf.extend_from_slice(bytes);
+    }
+
+    pub fn write_str(&mut self, s: &str) {
+        self.write_bytes(s.as_bytes());
+    }
+
+    pub fn write_value(&mut self, value: &Value) {
+        match value {
+            Value::Null => self.buf.push(TAG_NULL),
+            Value::String(s) => {
+                self.buf.push(TAG_STRING);
+                self.write_str(s);
+            }
+            Value::Int(i) => {
+                self.buf.push(TAG_INT);
+                self.buf.extend_from_slice(&i.to_le_bytes());
+            }
+            Value::BigDecimal(d) => {
+                self.buf.push(TAG_BIG_DECIMAL);
+                self.write_str(&d.to_string());
+            }
+            Value::Bool(b) => {
+                self.buf.push(TAG_BOOL);
+                self.write_bool(*b);
+            }
+            Value::List(values) => {
+                self.buf.push(TAG_LIST);
+                self.write_u64(values.len() as u64);
+                for value in values {
+                    self.write_value(value);
+                }
+            }
+            Value::Bytes(bytes) => {
+                self.buf.push(TAG_BYTES);
+                self.write_bytes(bytes.as_slice());
+            }
+            Value::BigInt(n) => {
+                self.buf.push(TAG_BIG_INT);
+                self.write_bytes(&n.to_signed_bytes_le());
+            }
+        }
+    }
+
+    pub fn write_entity(&mut self, entity: &Entity) {
+        self.write_u64(entity.len() as u64);
+        let mut value_buf = Encoder::new();
+        for (name, value) in entity.iter() {
+            self.write_str(name);
+            value_buf.buf.clear();
+            value_buf.write_value(value);
+            self.write_bytes(&value_buf.buf);
+        }
+    }
+
+    pub fn finish(self) -> Vec<u8> {
+        self.buf
+    }
+}
+
+/// Reads values in the binary encoding
+pub struct Decoder<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Decoder<'a> {
+    pub fn new(bytes: &'a [u8]) -> Self {
+        Decoder { bytes, pos: 0 }
+    }
+
+    /// Whether all input has been read
+    pub fn is_done(&self) -> bool {
+        self.pos >= self.bytes.len()
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], Error> {
+        let end = self
+            .pos
+            .checked_add(len)
+            .filter(|end| *end <= self.bytes.len())
+            .ok_or_else(|| anyhow!("unexpected end of binary entity data"))?;
+        let bytes = &self.bytes[self.pos..end];
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    fn read_byte(&mut self) -> Result<u8, Error> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub fn read_u64(&mut self) -> Result<u64, Error> {
+        let mut n: u64 = 0;
+        for shift in (0..64).step_by(7) {
+            let byte = self.read_byte()?;
+            n |= ((byte & 0x7f) as u64) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(n);
+            }
+        }
+        Err(anyhow!("invalid varint in binary entity data"))
+    }
+
+    fn read_len(&mut self) -> Result<usize, Error> {
+        let len = self.read_u64()?;
+        if len > (self.bytes.len() - self.pos) as u64 {
+            return Err(anyhow!("unexpected end of binary entity data"));
+        }
+        Ok(len as usize)
+    }
+
+    pub fn read_bool(&mut self) -> Result<bool, Error> {
+        match self.read_byte()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            b => Err(anyhow!("invalid boolean {} in binary entity data", b)),
+        }
+    }
+
+    pub fn read_bytes(&mut self) -> Result<&'a [u8], Error> {
+        let len = self.read_len()?;
+        self.take(len)
+    }
+
+    pub fn read_str(&mut self) -> Result<&'a str, Error> {
+        Ok(std::str::from_utf8(self.read_bytes()?)?)
+    }
+
+    pub fn read_value(&mut self) -> Result<Value, Error> {
+        match self.read_byte()? {
+            TAG_NULL => Ok(Value::Null),
+            TAG_STRING => Ok(Value::String(self.read_str()?.to_owned())),
+            TAG_INT => {
+                let mut bytes = [0u8; 4];
+                bytes.copy_from_slice(self.take(4)?);
+                Ok(Value::Int(i32::from_le_bytes(bytes)))
+            }
+            TAG_BIG_DECIMAL => Ok(Value::BigDecimal(scalar::BigDecimal::from_str(
+                self.read_str()?,
+            )?)),
+            TAG_BOOL => Ok(Value::Bool(self.read_bool()?)),
+            TAG_LIST => {
+                let len = self.read_len()?;
+                let mut values = Vec::with_capacity(len);
+                for _ in 0..len {
+                    values.push(self.read_value()?);
+                }
+                Ok(Value::List(values))
+            }
+            TAG_BYTES => Ok(Value::Bytes(scalar::Bytes::from(self.read_bytes()?))),
+            TAG_BIG_INT => Ok(Value::BigInt(scalar::BigInt::from_signed_bytes_le(
+                self.read_bytes()?,
+            ))),
+            tag => Err(anyhow!("invalid value tag {} in binary entity data", tag)),
+        }
+    }
+
+    pub fn read_entity(&mut self) -> Result<Entity, Error> {
+        let len = self.read_len()?;
+        let mut entity = Entity::new();
+        for _ in 0..len {
+            let name = self.read_str()?.to_owned();
+            let value = Decoder::new(self.read_bytes()?).read_value()?;
+            entity.insert(name, value);
+        }
+        Ok(entity)
+    }
+}
+
+impl Entity {
+    /// Encode the entity in the binary encoding
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut encoder = Encoder::new();
+        encoder.write_entity(self);
+        encoder.finish()
+    }
+
+    /// Decode an entity that was encoded with `to_bytes`
+    pub fn from_bytes(bytes: &[u8]) -> Result<Entity, Error> {
+        Decoder::new(bytes).read_entity()
+    }
+}
+
+/// An entity in the binary encoding whose values are only decoded when
+/// they are accessed
+pub struct EntityBytes {
+    bytes: Vec<u8>,
+    /// The name of each field and where its value is in `bytes`
+    fields: Vec<(String, Range<usize>)>,
+}
+
+impl EntityBytes {
+    /// Find the fields of the entity encoded in `bytes`; this checks the
+    /// structure of the entity, but not the values of its fields
+    pub fn new(bytes: Vec<u8>) -> Result<Self, Error> {
+        let mut decoder = Decoder::new(&bytes);
+        let len = decoder.read_len()?;
+        let mut fields = Vec::with_capacity(len);
+        for _ in 0..len {
+            let name = decoder.read_str()?.to_owned();
+            let value_len = decoder.read_len()?;
+            let start = decoder.pos;
+            decoder.take(value_len)?;
+            fields.push((name, start..decoder.pos));
+        }
+        Ok(EntityBytes { bytes, fields })
+    }
+
+    pub fn len(&self) -> usize {
+        self.fields.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.fields.is_empty()
+    }
+
+    pub fn fields(&self) -> impl Iterator<Item = &str> {
+        self.fields.iter().map(|(name, _)| name.as_str())
+    }
+
+    /// Decode the value of `field`, or return `None` if the entity does
+    /// not have that field
+    pub fn get(&self, field: &str) -> Result<Option<Value>, Error> {
+        self.fields
+            .iter()
+            .find(|(name, _)| name == field)
+            .map(|(_, range)| Decoder::new(&self.bytes[range.clone()]).read_value())
+            .transpose()
+    }
+
+    /// Decode all fields
+    pub fn to_entity(&self) -> Result<Entity, Error> {
+        let mut entity = Entity::new();
+        for (name, range) in &self.fields {
+            let value = Decoder::new(&self.bytes[range.clone()]).read_value()?;
+            entity.insert(name.clone(), value);
+        }
+        Ok(entity)
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+}
+
+#[test]
+fn binary_entities() {
+    let mut entity = Entity::new();
+    entity.set("id", "0xdeadbeef");
+    entity.set("count", 42);
+    entity.set("negative", -7);
+    entity.set("active", true);
+    entity.set("missing", Value::Null);
+    entity.set(
+        "price",
+        Value::BigDecimal(scalar::BigDecimal::from_str("-1234.000056").unwrap()),
+    );
+    entity.set(
+        "supply",
+        Value::BigInt(scalar::BigInt::from_str("-123456789012345678901234567890").unwrap()),
+    );
+    entity.set(
+        "owner",
+        Value::Bytes(scalar::Bytes::from(&[0u8, 1, 2, 255][..])),
+    );
+    entity.set(
+        "tags",
+        Value::List(vec![
+            Value::String("a".to_owned()),
+            Value::List(vec![Value::Int(1), Value::Null]),
+        ]),
+    );
+    entity.set("long", "x".repeat(300));
+
+    let bytes = entity.to_bytes();
+    assert_eq!(entity, Entity::from_bytes(&bytes).unwrap());
+
+    let lazy = EntityBytes::new(bytes.clone()).unwrap();
+    assert_eq!(entity.len(), lazy.len());
+    assert_eq!(Some(Value::Int(42)), lazy.get("count").unwrap());
+    assert_eq!(entity.get("tags").cloned(), lazy.get("tags").unwrap());
+    assert_eq!(None, lazy.get("nope").unwrap());
+    assert_eq!(entity, lazy.to_entity().unwrap());
+
+    // Truncated data is an error, not a panic
+    for len in 0..bytes.len() {
+        assert!(Entity::from_bytes(&bytes[..len]).is_err(), "{}", len);
+    }
+}
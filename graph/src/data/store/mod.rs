@@ -2,7 +2,10 @@ use crate::{
     components::store::EntityType,
     prelude::{q, s, CacheWeight, EntityKey, QueryExecutionError},
 };
-use crate::{data::subgraph::SubgraphDeploymentId, prelude::EntityChange};
+use crate::{
+    data::subgraph::SubgraphDeploymentId,
+    prelude::{EntityChange, EntityChangeSummary},
+};
 use anyhow::{anyhow, Error};
 use serde::de;
 use serde::{Deserialize, Serialize};
@@ -24,6 +27,9 @@ pub mod scalar;
 // Ethereum compatibility.
 pub mod ethereum;
 
+/// Binary encoding of entities.
+pub mod binary;
+
 /// Filter subscriptions
 pub enum SubscriptionFilter {
     /// Receive updates about all entities from the given deployment of the
@@ -44,6 +50,17 @@ impl SubscriptionFilter {
             }
         }
     }
+
+    pub fn matches_summary(&self, summary: &EntityChangeSummary) -> bool {
+        match self {
+            Self::Entities(id, entity_type) => {
+                &summary.subgraph_id == id && &summary.entity_type == entity_type
+            }
+            // Assignments are metadata, and changes to metadata are never
+            // summarized
+            Self::Assignment => false,
+        }
+    }
 }
 
 #[derive(Clone, Debug, PartialEq, Eq, Hash)]
@@ -314,6 +331,54 @@ impl Value {
         }
     }
 
+    pub fn is_null(&self) -> bool {
+        *self == Value::Null
+    }
+
+    /// Return `true` if this is an `Int`, `BigInt` or `BigDecimal`
+    pub fn is_number(&self) -> bool {
+        match self {
+            Value::Int(_) | Value::BigInt(_) | Value::BigDecimal(_) => true,
+            _ => false,
+        }
+    }
+
+    /// Add two numbers, treating `Value::Null` as zero. An `Int` sum that
+    /// overflows becomes a `BigInt`, and mixing `Int` and `BigInt` with
+    /// `BigDecimal` produces a `BigDecimal`. Returns `None` if either value
+    /// is not a number
+    pub fn add_number(&self, other: &Value) -> Option<Value> {
+        match (self, other) {
+            (Value::Null, other) | (other, Value::Null) if other.is_number() => Some(other.clone()),
+            (Value::Int(a), Value::Int(b)) => Some(
+                a.checked_add(*b)
+                    .map(Value::Int)
+                    .unwrap_or_else(|| Value::BigInt(scalar::BigInt::from(*a as i64 + *b as i64))),
+            ),
+            (Value::BigDecimal(_), _) | (_, Value::BigDecimal(_)) => {
+                Some(Value::BigDecimal(big_decimal(self)? + big_decimal(other)?))
+            }
+            _ => Some(Value::BigInt(big_int(self)? + big_int(other)?)),
+        }
+    }
+
+    /// Compare two values the way the store compares them in filters and
+    /// when sorting. Numbers of different types are compared by their
+    /// value. Returns `None` for values that can not be compared, like a
+    /// string and a number, or lists
+    pub fn compare(&self, other: &Value) -> Option<std::cmp::Ordering> {
+        match (self, other) {
+            (Value::String(a), Value::String(b)) => Some(a.cmp(b)),
+            (Value::Bytes(a), Value::Bytes(b)) => Some(a.as_slice().cmp(b.as_slice())),
+            (Value::Bool(a), Value::Bool(b)) => Some(a.cmp(b)),
+            (Value::Int(a), Value::Int(b)) => Some(a.cmp(b)),
+            (Value::BigDecimal(_), _) | (_, Value::BigDecimal(_)) => {
+                Some(big_decimal(self)?.cmp(&big_decimal(other)?))
+            }
+            _ => Some(big_int(self)?.cmp(&big_int(other)?)),
+        }
+    }
+
     /// Return the name of the type of this value for display to the user
     pub fn type_name(&self) -> String {
         match self {
@@ -335,6 +400,21 @@ impl Value {
     }
 }
 
+fn big_int(value: &Value) -> Option<scalar::BigInt> {
+    match value {
+        Value::Int(i) => Some(scalar::BigInt::from(*i)),
+        Value::BigInt(b) => Some(b.clone()),
+        _ => None,
+    }
+}
+
+fn big_decimal(value: &Value) -> Option<scalar::BigDecimal> {
+    match value {
+        Value::BigDecimal(d) => Some(d.clone()),
+        _ => big_int(value).map(|b| scalar::BigDecimal::new(b, 0)),
+    }
+}
+
 impl fmt::Display for Value {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(
@@ -602,6 +682,26 @@ pub trait ToEntityKey {
     fn to_entity_key(&self, subgraph: SubgraphDeploymentId) -> EntityKey;
 }
 
+#[test]
+fn entity_cache_budget() {
+    use crate::util::lfu_cache::LfuCache;
+
+    // An entity weighs at least as much as the data it holds
+    let big = entity! { id: "big", data: "x".repeat(10_000) };
+    assert!(big.weight() >= 10_000);
+
+    let mut cache: LfuCache<String, Entity> = LfuCache::new();
+    for i in 0..100 {
+        let id = format!("e{}", i);
+        cache.insert(id.clone(), entity! { id: id, data: "x".repeat(1_000) });
+    }
+    let budget = 20_000;
+    assert!(cache.total_weight() > budget);
+    cache.evict(budget);
+    assert!(cache.total_weight() <= budget);
+    assert!(!cache.is_empty() && cache.len() < 20);
+}
+
 #[test]
 fn value_bytes() {
     let graphql_value = q::Value::String("0x8f494c66afc1d3f8ac1b45df21f02a46".to_owned());
@@ -628,3 +728,48 @@ fn value_bigint() {
     );
     assert_eq!(q::Value::from(from_query), graphql_value);
 }
+
+#[test]
+fn add_numbers() {
+    let big_int = |i: i64| Value::BigInt(scalar::BigInt::from(i));
+
+    assert_eq!(
+        Some(Value::Int(3)),
+        Value::Int(1).add_number(&Value::Int(2))
+    );
+    assert_eq!(Some(Value::Int(2)), Value::Null.add_number(&Value::Int(2)));
+    assert_eq!(
+        Some(big_int(i32::MAX as i64 + 1)),
+        Value::Int(i32::MAX).add_number(&Value::Int(1))
+    );
+    assert_eq!(Some(big_int(5)), Value::Int(2).add_number(&big_int(3)));
+    assert_eq!(
+        Some(Value::BigDecimal(scalar::BigDecimal::from(3.5))),
+        big_int(1).add_number(&Value::BigDecimal(scalar::BigDecimal::from(2.5)))
+    );
+    assert_eq!(
+        None,
+        Value::String("1".to_owned()).add_number(&Value::Int(1))
+    );
+    assert_eq!(None, Value::Null.add_number(&Value::Null));
+}
+
+#[test]
+fn compare_values() {
+    use std::cmp::Ordering::*;
+
+    let big_int = |i: i64| Value::BigInt(scalar::BigInt::from(i));
+
+    assert_eq!(Some(Less), Value::Int(1).compare(&Value::Int(2)));
+    assert_eq!(Some(Equal), Value::Int(2).compare(&big_int(2)));
+    assert_eq!(
+        Some(Greater),
+        big_int(3).compare(&Value::BigDecimal(scalar::BigDecimal::from(2.5)))
+    );
+    assert_eq!(
+        Some(Less),
+        Value::String("a".to_owned()).compare(&Value::String("b".to_owned()))
+    );
+    assert_eq!(None, Value::String("1".to_owned()).compare(&Value::Int(1)));
+    assert_eq!(None, Value::Null.compare(&Value::Int(1)));
+}
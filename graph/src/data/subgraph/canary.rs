@@ -0,0 +1,150 @@
+//! Canary assertions about the values of entities at given blocks.
+//!
+//! A manifest can point to a JSON file with a list of assertions:
+//!
+//! ```yaml
+//! canaries:
+//!   file:
+//!     /: /ipfs/Qm...
+//!   onMismatch: warn
+//! ```
+//!
+//! Each assertion in the file looks like `{ "block": 1000000, "entity":
+//! "Token", "id": "0x12..", "field": "totalSupply", "value": "21000000" }`
+//! and says that, at `block`, the `field` of the entity must display as
+//! `value`. A `value` of `null` asserts that the field is not set, or that
+//! the entity does not exist. As indexing passes the block of an
+//! assertion, the instance manager checks it and either fails the subgraph
+//! (`onMismatch: fail`, the default) or logs a warning (`onMismatch: warn`)
+//! if the entity has a different value.
+use anyhow::{anyhow, Error};
+use slog::{info, Logger};
+
+use crate::components::link_resolver::LinkResolver;
+use crate::data::store::{Entity, Value};
+use crate::prelude::{BlockNumber, Deserialize};
+
+use super::Link;
+
+/// What to do when an assertion does not hold
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum OnMismatch {
+    Fail,
+    Warn,
+}
+
+impl Default for OnMismatch {
+    fn default() -> Self {
+        OnMismatch::Fail
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct CanaryAssertion {
+    pub block: BlockNumber,
+    pub entity: String,
+    pub id: String,
+    pub field: String,
+    pub value: serde_json::Value,
+}
+
+impl CanaryAssertion {
+    /// Check the assertion against `entity`, the entity as of `self.block`.
+    /// Return a description of the mismatch if it does not hold
+    pub fn check(&self, entity: Option<&Entity>) -> Result<(), String> {
+        let expected = match &self.value {
+            serde_json::Value::Null => None,
+            serde_json::Value::String(s) => Some(s.clone()),
+            value => Some(value.to_string()),
+        };
+        let actual = entity
+            .and_then(|entity| entity.get(&self.field))
+            .filter(|value| !matches!(value, Value::Null))
+            .map(|value| value.to_string());
+        if expected == actual {
+            return Ok(());
+        }
+        let actual = match (entity, actual) {
+            (None, _) => "the entity does not exist".to_owned(),
+            (Some(_), None) => "it is not set".to_owned(),
+            (Some(_), Some(actual)) => format!("it is `{}`", actual),
+        };
+        Err(format!(
+            "expected `{}.{}` of {} `{}` at block {} to be `{}` but {}",
+            self.entity,
+            self.field,
+            self.entity,
+            self.id,
+            self.block,
+            expected.as_deref().unwrap_or("null"),
+            actual
+        ))
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Canaries {
+    /// The JSON file with the assertions
+    pub file: Link,
+    #[serde(default)]
+    pub on_mismatch: OnMismatch,
+    /// The assertions from `file`, ordered by block. They are only
+    /// available once the manifest has been resolved
+    #[serde(skip)]
+    pub assertions: Vec<CanaryAssertion>,
+}
+
+impl Canaries {
+    pub async fn resolve(
+        self,
+        resolver: &impl LinkResolver,
+        logger: &Logger,
+    ) -> Result<Self, Error> {
+        info!(logger, "Resolve canary assertions"; "link" => &self.file.link);
+
+        let bytes = resolver.cat(&logger, &self.file).await?;
+        let mut assertions: Vec<CanaryAssertion> = serde_json::from_slice(&bytes)
+            .map_err(|e| anyhow!("invalid canary assertions in {}: {}", self.file.link, e))?;
+        assertions.sort_by_key(|assertion| assertion.block);
+        Ok(Canaries { assertions, ..self })
+    }
+}
+
+#[test]
+fn check_canary_assertions() {
+    use crate::prelude::BigInt;
+
+    let assertion = |value: serde_json::Value| CanaryAssertion {
+        block: 7,
+        entity: "Token".to_owned(),
+        id: "t1".to_owned(),
+        field: "supply".to_owned(),
+        value,
+    };
+    let mut entity = Entity::new();
+    entity.set("id", "t1");
+    entity.set("supply", BigInt::from(1000));
+    entity.set("name", Value::Null);
+
+    assert!(assertion(serde_json::json!("1000"))
+        .check(Some(&entity))
+        .is_ok());
+    assert!(assertion(serde_json::json!(1000))
+        .check(Some(&entity))
+        .is_ok());
+    assert_eq!(
+        Err(
+            "expected `Token.supply` of Token `t1` at block 7 to be `999` but it is `1000`"
+                .to_owned()
+        ),
+        assertion(serde_json::json!("999")).check(Some(&entity))
+    );
+    assert!(assertion(serde_json::json!("1000")).check(None).is_err());
+    assert!(assertion(serde_json::Value::Null).check(None).is_ok());
+
+    let mut name = assertion(serde_json::Value::Null);
+    name.field = "name".to_owned();
+    assert!(name.check(Some(&entity)).is_ok());
+}
@@ -0,0 +1,82 @@
+//! Limits on the values that mappings can store in entities.
+//!
+//! A manifest can cap the size of `String` and `Bytes` values and the
+//! length of lists:
+//!
+//! ```yaml
+//! limits:
+//!   entityMaxValueSize: 1048576
+//!   entityMaxListLength: 1000
+//! ```
+//!
+//! Since the limits are part of the subgraph, every indexer enforces the
+//! same limits, and a handler that violates them fails with a
+//! deterministic error.
+use anyhow::{anyhow, Error};
+use std::collections::HashMap;
+
+use crate::components::store::EntityKey;
+use crate::data::store::{Attribute, Value};
+use crate::prelude::Deserialize;
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct EntityLimits {
+    /// The largest size, in bytes, of a `String` or `Bytes` value, or of
+    /// an element of a list of them
+    pub entity_max_value_size: Option<usize>,
+    /// The largest number of elements of a list
+    pub entity_max_list_length: Option<usize>,
+}
+
+impl EntityLimits {
+    /// Check the values that a mapping wants to store for `key` against
+    /// the limits
+    pub fn check(&self, key: &EntityKey, entity: &HashMap<Attribute, Value>) -> Result<(), Error> {
+        let check_size = |field: &str, value: &Value| {
+            let size = match value {
+                Value::String(s) => s.len(),
+                Value::Bytes(b) => b.as_slice().len(),
+                _ => return Ok(()),
+            };
+            match self.entity_max_value_size {
+                Some(max) if size > max => Err(anyhow!(
+                    "Entity {}[{}]: the value for field `{}` has {} bytes, but values can have \
+                     at most {} bytes",
+                    key.entity_type,
+                    key.entity_id,
+                    field,
+                    size,
+                    max
+                )),
+                _ => Ok(()),
+            }
+        };
+
+        for (field, value) in entity.iter() {
+            match value {
+                Value::List(values) => {
+                    match self.entity_max_list_length {
+                        Some(max) if values.len() > max => {
+                            return Err(anyhow!(
+                                "Entity {}[{}]: the list for field `{}` has {} elements, but \
+                                 lists can have at most {} elements",
+                                key.entity_type,
+                                key.entity_id,
+                                field,
+                                values.len(),
+                                max
+                            ));
+                        }
+                        _ => {}
+                    }
+                    for value in values {
+                        check_size(field, value)?;
+                    }
+                }
+                value => check_size(field, value)?,
+            }
+        }
+        Ok(())
+    }
+}
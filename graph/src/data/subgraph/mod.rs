@@ -24,6 +24,8 @@ use crate::data::graphql::TryFromValue;
 use crate::data::query::QueryExecutionError;
 use crate::data::schema::{Schema, SchemaImportError, SchemaValidationError};
 use crate::data::store::Entity;
+use crate::data::subgraph::canary::Canaries;
+use crate::data::subgraph::limits::EntityLimits;
 use crate::data::subgraph::schema::{
     EthereumBlockHandlerEntity, EthereumCallHandlerEntity, EthereumContractAbiEntity,
     EthereumContractDataSourceTemplateEntity, EthereumContractDataSourceTemplateSourceEntity,
@@ -62,6 +64,10 @@ pub mod schema;
 
 pub mod status;
 
+pub mod canary;
+
+pub mod limits;
+
 /// Deserialize an Address (with or without '0x' prefix).
 fn deserialize_address<'de, D>(deserializer: D) -> Result<Option<Address>, D::Error>
 where
@@ -219,6 +225,13 @@ impl SubgraphName {
     pub fn as_str(&self) -> &str {
         self.0.as_str()
     }
+
+    /// The namespace of the subgraph, usually an organization or a team,
+    /// which is the part of the name before the first `/`. Names without a
+    /// `/` are not in any namespace
+    pub fn namespace(&self) -> Option<&str> {
+        self.0.find('/').map(|pos| &self.0[..pos])
+    }
 }
 
 impl fmt::Display for SubgraphName {
@@ -277,6 +290,15 @@ fn test_subgraph_name_validation() {
     assert!(SubgraphName::new("this-component-is-longer-than-the-length-limit").is_err());
 }
 
+#[test]
+fn test_subgraph_name_namespace() {
+    let namespace = |s: &str| SubgraphName::new(s).unwrap().namespace().map(str::to_owned);
+
+    assert_eq!(None, namespace("a"));
+    assert_eq!(Some("a".to_owned()), namespace("a/b"));
+    assert_eq!(Some("a".to_owned()), namespace("a/b/c"));
+}
+
 /// Result of a creating a subgraph in the registar.
 #[derive(Serialize)]
 pub struct CreateSubgraphResult {
@@ -308,6 +330,8 @@ pub enum SubgraphRegistrarError {
     ManifestValidationError(Vec<SubgraphManifestValidationError>),
     #[error("subgraph deployment error: {0}")]
     SubgraphDeploymentError(StoreError),
+    #[error("can not replay skipped triggers: {0}")]
+    ReplayError(String),
     #[error("subgraph registrar error: {0}")]
     Unknown(anyhow::Error),
 }
@@ -579,6 +603,11 @@ pub struct MappingEventHandler {
     pub event: String,
     pub topic0: Option<H256>,
     pub handler: String,
+    /// Whether the handler only makes changes that do not depend on the
+    /// order in which events are handled, so that it may run concurrently
+    /// with other such handlers for events in the same block
+    #[serde(default)]
+    pub commutative: bool,
 }
 
 impl MappingEventHandler {
@@ -594,6 +623,7 @@ impl From<EthereumContractEventHandlerEntity> for MappingEventHandler {
             event: entity.event,
             topic0: entity.topic0,
             handler: entity.handler,
+            commutative: false,
         }
     }
 }
@@ -928,6 +958,15 @@ pub struct BaseSubgraphManifest<S, D, T> {
     pub graft: Option<Graft>,
     #[serde(default)]
     pub templates: Vec<T>,
+    /// Only index blocks that are at least this many blocks behind the
+    /// chain head
+    #[serde(default)]
+    pub confirmations: u64,
+    /// Assertions about entity values that are checked while indexing
+    pub canaries: Option<Canaries>,
+    /// Limits on the values that mappings can store in entities
+    #[serde(default)]
+    pub limits: EntityLimits,
 }
 
 /// Consider two subgraphs to be equal if they come from the same IPLD link.
@@ -1183,6 +1222,9 @@ impl UnresolvedSubgraphManifest {
             data_sources,
             graft,
             templates,
+            confirmations,
+            canaries,
+            limits,
         } = self;
 
         match semver::Version::parse(&spec_version) {
@@ -1214,6 +1256,11 @@ impl UnresolvedSubgraphManifest {
         )
         .await?;
 
+        let canaries = match canaries {
+            Some(canaries) => Some(canaries.resolve(resolver, logger).await?),
+            None => None,
+        };
+
         Ok(SubgraphManifest {
             id,
             location,
@@ -1225,6 +1272,9 @@ impl UnresolvedSubgraphManifest {
             data_sources,
             graft,
             templates,
+            confirmations,
+            canaries,
+            limits,
         })
     }
 }
@@ -1247,6 +1297,77 @@ pub struct DeploymentState {
     pub max_reorg_depth: u32,
     /// The number of the last block that the subgraph has processed
     pub latest_ethereum_block_number: BlockNumber,
+    /// The coarser history that the deployment keeps, or `None` if it
+    /// keeps all versions of its entities
+    pub history: Option<HistoryGranularity>,
+    /// The block before which versions of entities that were replaced have
+    /// been deleted, if the history of the deployment has been pruned
+    pub history_pruned_below: Option<BlockNumber>,
+    /// Whether new subscriptions to the deployment are turned away
+    pub subscriptions_disabled: bool,
+}
+
+impl DeploymentState {
+    /// Check that the deployment still has the data needed to run a query
+    /// at `block`
+    pub fn check_block_available(&self, block: BlockNumber) -> Result<(), QueryExecutionError> {
+        if let Some(pruned_below) = self.history_pruned_below {
+            if block < pruned_below {
+                return Err(QueryExecutionError::ValueParseError(
+                    "block.number".to_owned(),
+                    format!(
+                        "subgraph {} only has data starting at block number {} \
+                         and data for block number {} is therefore not available",
+                        self.id, pruned_below, block
+                    ),
+                ));
+            }
+        }
+        match &self.history {
+            Some(history) if !history.has_block(block) => {
+                Err(QueryExecutionError::ValueParseError(
+                    "block.number".to_owned(),
+                    format!(
+                        "subgraph {} only keeps the data for block numbers \
+                         that are multiples of {} before block {} and data for \
+                         block number {} is therefore not available",
+                        self.id, history.granularity, history.coarse_below, block
+                    ),
+                ))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Check that subscriptions to the deployment may be started
+    pub fn check_subscriptions_allowed(&self) -> Result<(), QueryExecutionError> {
+        if self.subscriptions_disabled {
+            Err(QueryExecutionError::NotSupported(format!(
+                "subscriptions to subgraph {} have been disabled by the operator",
+                self.id
+            )))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// The history that a deployment keeps once its history grew too large.
+/// Of the versions of entities that were replaced before block
+/// `coarse_below`, only those that were current at a block that is a
+/// multiple of `granularity` are kept, so that queries at these blocks
+/// still see the data they saw before the history was coarsened
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HistoryGranularity {
+    pub granularity: BlockNumber,
+    pub coarse_below: BlockNumber,
+}
+
+impl HistoryGranularity {
+    /// Return `true` if queries at `block` see complete data
+    pub fn has_block(&self, block: BlockNumber) -> bool {
+        block >= self.coarse_below || block % self.granularity == 0
+    }
 }
 
 #[derive(Debug, Deserialize, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
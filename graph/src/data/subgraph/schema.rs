@@ -25,6 +25,7 @@ use web3::types::*;
 use super::SubgraphDeploymentId;
 use crate::components::ethereum::EthereumBlockPointer;
 use crate::components::store::{EntityOperation, MetadataKey, MetadataOperation};
+use crate::components::subgraph::{ProofOfIndexingVersion, POI_TRANSITION_VERSION, POI_VERSION};
 use crate::data::graphql::{TryFromValue, ValueMap};
 use crate::data::store::{Entity, Value};
 use crate::data::subgraph::SubgraphManifest;
@@ -234,6 +235,8 @@ pub struct SubgraphDeploymentEntity {
     reorg_count: i32,
     current_reorg_depth: i32,
     max_reorg_depth: i32,
+    poi_version: ProofOfIndexingVersion,
+    poi_transition_version: Option<ProofOfIndexingVersion>,
 }
 
 impl TypedEntity for SubgraphDeploymentEntity {
@@ -264,6 +267,8 @@ impl SubgraphDeploymentEntity {
             reorg_count: 0,
             current_reorg_depth: 0,
             max_reorg_depth: 0,
+            poi_version: *POI_VERSION,
+            poi_transition_version: *POI_TRANSITION_VERSION,
         }
     }
 
@@ -300,6 +305,8 @@ impl SubgraphDeploymentEntity {
             reorg_count: _,
             current_reorg_depth: _,
             max_reorg_depth: _,
+            poi_version,
+            poi_transition_version,
         } = self;
 
         // A fresh subgraph will not have any errors.
@@ -325,6 +332,8 @@ impl SubgraphDeploymentEntity {
             graftBase: graft_base.map(|sid| sid.to_string()),
             graftBlockHash: graft_block_hash,
             graftBlockNumber: graft_block_number,
+            poiVersion: i32::from(poi_version),
+            poiTransitionVersion: poi_transition_version.map(i32::from),
         };
 
         ops.push(set_metadata_operation(
@@ -1191,6 +1200,43 @@ impl StableHash for SubgraphError {
     }
 }
 
+/// A trigger whose handler failed with a deterministic error and was
+/// skipped because the subgraph uses the `nonFatalErrors` feature. Skipped
+/// triggers are kept in a dead-letter table so that the gaps they leave in
+/// the data can be audited and later repaired by grafting a fixed version
+/// of the subgraph onto the deployment right before the first of them
+#[derive(Clone, Debug, PartialEq)]
+pub struct SkippedTrigger {
+    pub subgraph_id: SubgraphDeploymentId,
+    pub block_ptr: EthereumBlockPointer,
+    pub transaction_hash: Option<H256>,
+    pub log_index: Option<u64>,
+    pub handler: Option<String>,
+    pub message: String,
+    /// The deployment that was grafted onto this one to re-run the trigger
+    pub replayed_by: Option<SubgraphDeploymentId>,
+}
+
+impl SkippedTrigger {
+    pub fn new(
+        error: &SubgraphError,
+        transaction_hash: Option<H256>,
+        log_index: Option<u64>,
+    ) -> Option<Self> {
+        // Only errors for a specific block can be replayed
+        let block_ptr = error.block_ptr?;
+        Some(SkippedTrigger {
+            subgraph_id: error.subgraph_id.clone(),
+            block_ptr,
+            transaction_hash,
+            log_index,
+            handler: error.handler.clone(),
+            message: error.message.clone(),
+            replayed_by: None,
+        })
+    }
+}
+
 pub fn generate_entity_id() -> String {
     // Fast crypto RNG from operating system
     let mut rng = OsRng::new().unwrap();
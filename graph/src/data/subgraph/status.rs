@@ -1,8 +1,13 @@
 //! Support for the indexing status API
 
+use std::collections::{BTreeMap, HashMap};
+use std::sync::RwLock;
+
 use super::schema::{SubgraphError, SubgraphHealth};
 use crate::data::graphql::{object, IntoValue};
-use crate::prelude::{q, web3::types::H256, EthereumBlockPointer, Value};
+use crate::prelude::{
+    q, web3::types::H256, BlockNumber, EthereumBlockPointer, SubgraphDeploymentId, Value,
+};
 
 pub enum Filter {
     /// Get all versions for the named subgraph
@@ -15,7 +20,7 @@ pub enum Filter {
 }
 
 /// Light wrapper around `EthereumBlockPointer` that is compatible with GraphQL values.
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct EthereumBlock(EthereumBlockPointer);
 
 impl EthereumBlock {
@@ -61,6 +66,10 @@ pub struct ChainInfo {
     pub earliest_block: Option<EthereumBlock>,
     /// The latest block that the subgraph has synced to.
     pub latest_block: Option<EthereumBlock>,
+    /// How many ancestors of the chain head are kept in the block cache.
+    pub ancestor_count: u64,
+    /// How many blocks behind the chain head reorgs are handled.
+    pub reorg_threshold: u64,
 }
 
 impl IntoValue for ChainInfo {
@@ -70,6 +79,8 @@ impl IntoValue for ChainInfo {
             chain_head_block,
             earliest_block,
             latest_block,
+            ancestor_count,
+            reorg_threshold,
         } = self;
         object! {
             // `__typename` is needed for the `ChainIndexingStatus` interface
@@ -79,6 +90,50 @@ impl IntoValue for ChainInfo {
             chainHeadBlock: chain_head_block,
             earliestBlock: earliest_block,
             latestBlock: latest_block,
+            ancestorCount: format!("{}", ancestor_count),
+            reorgThreshold: format!("{}", reorg_threshold),
+        }
+    }
+}
+
+/// How one deployment depends on another
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DependencyKind {
+    /// The deployment was grafted onto the other deployment
+    Graft,
+}
+
+impl DependencyKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DependencyKind::Graft => "graft",
+        }
+    }
+}
+
+/// A dependency between two deployments. Depending on where it is listed,
+/// `deployment` is either the deployment that is depended on or the
+/// deployment that depends on it
+#[derive(Clone, Debug)]
+pub struct Dependency {
+    pub deployment: String,
+    pub kind: DependencyKind,
+    /// The block at which the dependency starts; for grafts, the block up
+    /// to which the data of the base is used
+    pub block: Option<EthereumBlock>,
+    /// Whether the dependent deployment still needs the other deployment.
+    /// Grafts stop being pending once the data of the base has been copied
+    pub pending: bool,
+}
+
+impl IntoValue for Dependency {
+    fn into_value(self) -> q::Value {
+        object! {
+            __typename: "DeploymentDependency",
+            deployment: self.deployment,
+            kind: q::Value::Enum(self.kind.as_str().to_owned()),
+            block: self.block,
+            pending: self.pending,
         }
     }
 }
@@ -101,6 +156,16 @@ pub struct Info {
 
     /// ID of the Graph Node that the subgraph is indexed by.
     pub node: Option<String>,
+
+    /// How the subgraph compares to the deployment it shadows, if it is
+    /// indexed in shadow mode.
+    pub shadow: Option<ShadowComparison>,
+
+    /// The deployments that this deployment depends on
+    pub dependencies: Vec<Dependency>,
+
+    /// The deployments that depend on this deployment
+    pub dependents: Vec<Dependency>,
 }
 
 impl IntoValue for Info {
@@ -114,6 +179,9 @@ impl IntoValue for Info {
             node,
             non_fatal_errors,
             synced,
+            shadow,
+            dependencies,
+            dependents,
         } = self;
 
         fn subgraph_error_to_value(subgraph_error: SubgraphError) -> q::Value {
@@ -155,6 +223,219 @@ impl IntoValue for Info {
             chains: chains.into_iter().map(|chain| chain.into_value()).collect::<Vec<_>>(),
             entityCount: format!("{}", entity_count),
             node: node,
+            shadow: shadow,
+            dependencies: dependencies
+                .into_iter()
+                .map(|dependency| dependency.into_value())
+                .collect::<Vec<_>>(),
+            dependents: dependents
+                .into_iter()
+                .map(|dependency| dependency.into_value())
+                .collect::<Vec<_>>(),
+        }
+    }
+}
+
+/// The number of entities of one type at a block, and a digest of their
+/// contents. Two deployments with the same digest for a type have the
+/// same entities of that type
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct EntityDigest {
+    pub count: u64,
+    pub digest: String,
+}
+
+/// The size of the `eth_call` cache of a network
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CallCacheSize {
+    pub network: String,
+    /// Whether the network keeps its calls in a table that it shares with
+    /// other networks; `entries` and `bytes` are then for all of them
+    pub shared: bool,
+    pub entries: u64,
+    /// The size of the table that holds the calls, including its indexes
+    pub bytes: u64,
+}
+
+/// An entity type whose entities differ between a shadow deployment and
+/// the deployment it shadows
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ShadowDivergence {
+    /// The first checkpoint at which the entities differed
+    pub block: BlockNumber,
+    pub entity_type: String,
+    /// The digests of the entities in the current and the shadow
+    /// deployment; `None` if the deployment has no such entity type
+    pub current: Option<EntityDigest>,
+    pub shadow: Option<EntityDigest>,
+}
+
+impl ShadowDivergence {
+    /// Compare the digests of two deployments at `block` and return a
+    /// divergence for every entity type whose digests differ
+    pub fn compare(
+        block: BlockNumber,
+        current: &BTreeMap<String, EntityDigest>,
+        shadow: &BTreeMap<String, EntityDigest>,
+    ) -> Vec<ShadowDivergence> {
+        let mut entity_types: Vec<_> = current.keys().chain(shadow.keys()).collect();
+        entity_types.sort();
+        entity_types.dedup();
+        entity_types
+            .into_iter()
+            .filter(|entity_type| current.get(*entity_type) != shadow.get(*entity_type))
+            .map(|entity_type| ShadowDivergence {
+                block,
+                entity_type: entity_type.clone(),
+                current: current.get(entity_type).cloned(),
+                shadow: shadow.get(entity_type).cloned(),
+            })
+            .collect()
+    }
+}
+
+impl IntoValue for ShadowDivergence {
+    fn into_value(self) -> q::Value {
+        let count = |digest: Option<EntityDigest>| {
+            digest.map_or(q::Value::Null, |digest| {
+                q::Value::String(format!("{}", digest.count))
+            })
+        };
+        object! {
+            __typename: "ShadowDivergence",
+            block: self.block,
+            entityType: self.entity_type,
+            currentCount: count(self.current),
+            shadowCount: count(self.shadow),
+        }
+    }
+}
+
+/// The result of comparing a deployment that is indexed in shadow mode
+/// with the deployment it shadows
+#[derive(Clone, Debug, PartialEq)]
+pub struct ShadowComparison {
+    /// The deployment that is shadowed
+    pub current: SubgraphDeploymentId,
+    /// The latest checkpoint at which the deployments were compared
+    pub checkpoint: Option<BlockNumber>,
+    /// For each entity type that diverged, the first checkpoint at which it
+    /// did, in the order in which they were found
+    pub divergences: Vec<ShadowDivergence>,
+}
+
+impl ShadowComparison {
+    pub fn new(current: SubgraphDeploymentId) -> Self {
+        ShadowComparison {
+            current,
+            checkpoint: None,
+            divergences: vec![],
         }
     }
+
+    /// Record the divergences found at a checkpoint. Entity types that
+    /// already diverged earlier are not recorded again
+    pub fn record(&mut self, checkpoint: BlockNumber, divergences: Vec<ShadowDivergence>) {
+        for divergence in divergences {
+            if !self
+                .divergences
+                .iter()
+                .any(|known| known.entity_type == divergence.entity_type)
+            {
+                self.divergences.push(divergence);
+            }
+        }
+        self.checkpoint = Some(checkpoint);
+    }
+}
+
+impl IntoValue for ShadowComparison {
+    fn into_value(self) -> q::Value {
+        object! {
+            __typename: "ShadowComparison",
+            current: self.current.to_string(),
+            checkpoint: self.checkpoint,
+            divergences: self
+                .divergences
+                .into_iter()
+                .map(|divergence| divergence.into_value())
+                .collect::<Vec<_>>(),
+        }
+    }
+}
+
+/// The latest shadow comparisons, keyed by the id of the shadow
+/// deployment. The comparisons are updated by whatever performs them and
+/// read by the indexing status API
+#[derive(Debug, Default)]
+pub struct ShadowComparisons(RwLock<HashMap<SubgraphDeploymentId, ShadowComparison>>);
+
+impl ShadowComparisons {
+    pub fn get(&self, shadow: &str) -> Option<ShadowComparison> {
+        self.0
+            .read()
+            .unwrap()
+            .iter()
+            .find(|(id, _)| id.as_str() == shadow)
+            .map(|(_, comparison)| comparison.clone())
+    }
+
+    pub fn set(&self, shadow: SubgraphDeploymentId, comparison: ShadowComparison) {
+        self.0.write().unwrap().insert(shadow, comparison);
+    }
+
+    /// Only keep the comparisons for shadow deployments for which `f`
+    /// returns `true`
+    pub fn retain(&self, f: impl Fn(&SubgraphDeploymentId) -> bool) {
+        self.0.write().unwrap().retain(|id, _| f(id));
+    }
+}
+
+#[test]
+fn shadow_comparison() {
+    let digest = |count, digest: &str| EntityDigest {
+        count,
+        digest: digest.to_owned(),
+    };
+    let digests = |entries: Vec<(&str, EntityDigest)>| {
+        entries
+            .into_iter()
+            .map(|(entity_type, digest)| (entity_type.to_owned(), digest))
+            .collect::<BTreeMap<_, _>>()
+    };
+
+    let current = digests(vec![
+        ("Token", digest(2, "a")),
+        ("Pair", digest(1, "b")),
+        ("Poi$", digest(1, "c")),
+    ]);
+    let shadow = digests(vec![
+        ("Token", digest(2, "a")),
+        ("Pair", digest(1, "x")),
+        ("Swap", digest(4, "d")),
+        ("Poi$", digest(1, "c")),
+    ]);
+    let divergences = ShadowDivergence::compare(10, &current, &shadow);
+    assert_eq!(
+        vec!["Pair", "Swap"],
+        divergences
+            .iter()
+            .map(|divergence| divergence.entity_type.as_str())
+            .collect::<Vec<_>>()
+    );
+    assert_eq!(None, divergences[1].current);
+
+    let mut comparison = ShadowComparison::new(SubgraphDeploymentId::new("QmCurrent").unwrap());
+    comparison.record(10, divergences);
+    comparison.record(20, ShadowDivergence::compare(20, &current, &current));
+    comparison.record(30, ShadowDivergence::compare(30, &shadow, &current));
+    assert_eq!(Some(30), comparison.checkpoint);
+    assert_eq!(
+        vec![10, 10],
+        comparison
+            .divergences
+            .iter()
+            .map(|divergence| divergence.block)
+            .collect::<Vec<_>>()
+    );
 }
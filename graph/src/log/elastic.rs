@@ -6,7 +6,8 @@ use std::sync::{Arc, Mutex};
 use std::time::Duration;
 
 use chrono::prelude::{SecondsFormat, Utc};
-use futures03::TryFutureExt;
+use futures03::compat::Future01CompatExt;
+use futures03::{FutureExt, TryFutureExt};
 use reqwest;
 use reqwest::Client;
 use serde::ser::Serializer as SerdeSerializer;
@@ -15,6 +16,9 @@ use serde_json::json;
 use slog::*;
 use slog_async;
 
+use crate::util::futures::retry;
+use crate::util::retry_policy::RetryTarget;
+
 /// General configuration parameters for Elasticsearch logging.
 #[derive(Clone, Debug)]
 pub struct ElasticLoggingConfig {
@@ -262,20 +266,27 @@ impl ElasticDrain {
                     let client = Client::new();
                     let logger_for_err = flush_logger.clone();
 
-                    let header = match config.general.username {
-                        Some(username) => client
-                            .post(batch_url)
-                            .header("Content-Type", "application/json")
-                            .basic_auth(username, config.general.password.clone()),
-                        None => client
-                            .post(batch_url)
-                            .header("Content-Type", "application/json"),
-                    };
-                    header
-                        .body(batch_body)
-                        .send()
-                        .and_then(|response| async { response.error_for_status() })
-                        .map_ok(|_| ())
+                    retry("send logs to Elasticsearch", &flush_logger)
+                        .policy(RetryTarget::Elasticsearch.policy())
+                        .run(move || {
+                            let header = match &config.general.username {
+                                Some(username) => client
+                                    .post(batch_url.clone())
+                                    .header("Content-Type", "application/json")
+                                    .basic_auth(username, config.general.password.clone()),
+                                None => client
+                                    .post(batch_url.clone())
+                                    .header("Content-Type", "application/json"),
+                            };
+                            header
+                                .body(batch_body.clone())
+                                .send()
+                                .and_then(|response| async { response.error_for_status() })
+                                .map_ok(|_| ())
+                                .boxed()
+                                .compat()
+                        })
+                        .compat()
                         .unwrap_or_else(move |e| {
                             // Log if there was a problem sending the logs
                             error!(
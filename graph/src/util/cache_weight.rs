@@ -1,5 +1,5 @@
 use crate::{
-    components::store::EntityType,
+    components::store::{EntityType, EthereumCallResult},
     prelude::{q, BigDecimal, BigInt, EntityKey, Value},
 };
 use std::mem;
@@ -156,6 +156,15 @@ impl CacheWeight for [u8; 32] {
     }
 }
 
+impl CacheWeight for EthereumCallResult {
+    fn indirect_weight(&self) -> usize {
+        match self {
+            EthereumCallResult::Return(output) => output.capacity(),
+            EthereumCallResult::Revert(reason) => reason.indirect_weight(),
+        }
+    }
+}
+
 #[cfg(test)]
 impl CacheWeight for &'static str {
     fn indirect_weight(&self) -> usize {
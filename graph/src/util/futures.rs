@@ -1,4 +1,5 @@
 use crate::ext::futures::FutureExtension;
+use crate::util::retry_policy::{Backoff, RetryPolicy};
 use futures::prelude::*;
 use slog::{debug, trace, warn, Logger};
 use std::fmt::Debug;
@@ -6,7 +7,6 @@ use std::marker::PhantomData;
 use std::sync::Arc;
 use std::time::Duration;
 use thiserror::Error;
-use tokio_retry::strategy::{jitter, ExponentialBackoff};
 use tokio_retry::Retry;
 
 /// Generic helper function for retrying async operations with built-in logging.
@@ -21,7 +21,9 @@ use tokio_retry::Retry;
 ///    `.no_timeout()`.
 /// 6. Call `.run(...)`.
 ///
-/// All steps are required, except Step 2 and Step 3.
+/// All steps are required, except Step 2 and Step 3. Steps 4 and 5 can be
+/// replaced by calling `.policy(...)` with the `RetryPolicy` of the service
+/// that is being called.
 ///
 /// Example usage:
 /// ```
@@ -53,6 +55,7 @@ pub fn retry<I, E>(operation_name: impl ToString, logger: &Logger) -> RetryConfi
         log_after: 1,
         warn_after: 10,
         limit: RetryConfigProperty::Unknown,
+        backoff: Backoff::default(),
         phantom_item: PhantomData,
         phantom_error: PhantomData,
     }
@@ -65,6 +68,7 @@ pub struct RetryConfig<I, E> {
     log_after: u64,
     warn_after: u64,
     limit: RetryConfigProperty<usize>,
+    backoff: Backoff,
     phantom_item: PhantomData<I>,
     phantom_error: PhantomData<E>,
 }
@@ -137,6 +141,24 @@ where
         }
     }
 
+    /// Wait between attempts according to `backoff`.
+    pub fn backoff(mut self, backoff: Backoff) -> Self {
+        self.backoff = backoff;
+        self
+    }
+
+    /// Use the timeout and backoff of `policy`, and its limit unless a limit was already
+    /// configured with `.limit(...)` or `.no_limit()`.
+    pub fn policy(mut self, policy: &RetryPolicy) -> RetryConfigWithTimeout<I, E> {
+        if self.limit == RetryConfigProperty::Unknown {
+            match policy.limit {
+                Some(limit) => self.limit.set(limit),
+                None => self.limit.clear(),
+            }
+        }
+        self.backoff(policy.backoff).timeout(policy.timeout)
+    }
+
     /// Allow attempts to take as long as they need (or potentially hang forever).
     pub fn no_timeout(self) -> RetryConfigNoTimeout<I, E> {
         RetryConfigNoTimeout { inner: self }
@@ -167,6 +189,7 @@ where
         let log_after = self.inner.log_after;
         let warn_after = self.inner.warn_after;
         let limit_opt = self.inner.limit.unwrap(&operation_name, "limit");
+        let backoff = self.inner.backoff;
         let timeout = self.timeout;
 
         trace!(logger, "Run with retry: {}", operation_name);
@@ -178,6 +201,7 @@ where
             log_after,
             warn_after,
             limit_opt,
+            backoff,
             move || {
                 try_it()
                     .timeout(timeout)
@@ -208,6 +232,7 @@ impl<I, E> RetryConfigNoTimeout<I, E> {
         let log_after = self.inner.log_after;
         let warn_after = self.inner.warn_after;
         let limit_opt = self.inner.limit.unwrap(&operation_name, "limit");
+        let backoff = self.inner.backoff;
 
         trace!(logger, "Run with retry: {}", operation_name);
 
@@ -218,6 +243,7 @@ impl<I, E> RetryConfigNoTimeout<I, E> {
             log_after,
             warn_after,
             limit_opt,
+            backoff,
             // No timeout, so all errors are inner errors
             move || try_it().map_err(TimeoutError::Inner),
         )
@@ -259,6 +285,7 @@ fn run_retry<I, E, F, R>(
     log_after: u64,
     warn_after: u64,
     limit_opt: Option<usize>,
+    backoff: Backoff,
     mut try_it_with_timeout: F,
 ) -> impl Future<Item = I, Error = TimeoutError<E>> + Send
 where
@@ -270,7 +297,7 @@ where
     let condition = Arc::new(condition);
 
     let mut attempt_count = 0;
-    Retry::spawn(retry_strategy(limit_opt), move || {
+    Retry::spawn(retry_strategy(limit_opt, backoff), move || {
         let operation_name = operation_name.clone();
         let logger = logger.clone();
         let condition = condition.clone();
@@ -345,12 +372,12 @@ where
     })
 }
 
-fn retry_strategy(limit_opt: Option<usize>) -> Box<dyn Iterator<Item = Duration> + Send> {
+fn retry_strategy(
+    limit_opt: Option<usize>,
+    backoff: Backoff,
+) -> Box<dyn Iterator<Item = Duration> + Send> {
     // Exponential backoff, but with a maximum
-    let max_delay_ms = 30_000;
-    let backoff = ExponentialBackoff::from_millis(2)
-        .max_delay(Duration::from_millis(max_delay_ms))
-        .map(jitter);
+    let backoff = backoff.delays();
 
     // Apply limit (maximum retry count)
     match limit_opt {
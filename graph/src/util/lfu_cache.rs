@@ -155,6 +155,13 @@ impl<K: Clone + Ord + Eq + Hash + Debug + CacheWeight, V: CacheWeight + Default>
             .is_some()
     }
 
+    /// Iterate over all entries in the cache, in no particular order
+    pub fn iter(&self) -> impl Iterator<Item = (&K, &V)> {
+        self.queue
+            .iter()
+            .map(|(entry, _)| (&entry.key, &entry.value))
+    }
+
     pub fn is_empty(&self) -> bool {
         self.queue.is_empty()
     }
@@ -163,6 +170,11 @@ impl<K: Clone + Ord + Eq + Hash + Debug + CacheWeight, V: CacheWeight + Default>
         self.queue.len()
     }
 
+    /// The total weight of all entries in the cache
+    pub fn total_weight(&self) -> usize {
+        self.total_weight
+    }
+
     /// Same as `evict_with_period(max_weight, STALE_PERIOD)`
     pub fn evict(&mut self, max_weight: usize) -> Option<(usize, usize, usize)> {
         self.evict_with_period(max_weight, STALE_PERIOD)
@@ -231,7 +243,7 @@ impl<K: Ord + Eq + Hash, V> Extend<(CacheEntry<K, V>, Priority)> for LfuCache<K,
 
 #[test]
 fn entity_lru_cache() {
-    #[derive(Default, Debug, PartialEq, Eq)]
+    #[derive(Default, Debug, PartialEq, Eq, PartialOrd, Ord)]
     struct Weight(usize);
 
     impl CacheWeight for Weight {
@@ -253,6 +265,10 @@ fn entity_lru_cache() {
     assert_eq!(cache.get(&"cow"), Some(&Weight(1)));
     assert_eq!(cache.get(&"panda"), Some(&Weight(2)));
 
+    let mut entries: Vec<_> = cache.iter().collect();
+    entries.sort();
+    assert_eq!(entries, vec![(&"cow", &Weight(1)), (&"panda", &Weight(2))]);
+
     // Nothing is evicted.
     cache.evict(panda_weight + cow_weight);
     assert_eq!(cache.len(), 2);
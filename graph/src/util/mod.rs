@@ -1,6 +1,9 @@
 /// Utilities for working with futures.
 pub mod futures;
 
+/// Retry policies for calls to outside services.
+pub mod retry_policy;
+
 /// Utils for working with ethereum data types
 pub mod ethereum;
 
@@ -9,6 +12,8 @@ pub mod security;
 
 pub mod lfu_cache;
 
+pub mod profiler;
+
 pub mod error;
 
 pub mod stats;
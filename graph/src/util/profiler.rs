@@ -0,0 +1,103 @@
+//! Execution profiles of subgraph mappings.
+//!
+//! When `GRAPH_WASM_PROFILER_INTERVAL` is set, the WASM runtime samples
+//! the stack of mapping functions while handlers run and adds the samples
+//! to the profile of the deployment. Profiles are kept in memory for as
+//! long as the process runs and are rendered in the 'folded stacks' format
+//! that flamegraph tools like `inferno-flamegraph` or `flamegraph.pl`
+//! understand.
+use lazy_static::lazy_static;
+use std::collections::HashMap;
+use std::env;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+
+use crate::prelude::SubgraphDeploymentId;
+
+lazy_static! {
+    /// How often to sample the stack of a running handler, in milliseconds.
+    /// Profiling is turned off if this is not set
+    pub static ref PROFILER_INTERVAL: Option<Duration> =
+        env::var("GRAPH_WASM_PROFILER_INTERVAL").ok().map(|s| {
+            Duration::from_millis(
+                s.parse::<u64>()
+                    .expect("invalid GRAPH_WASM_PROFILER_INTERVAL"),
+            )
+        });
+
+    /// The bearer token that requests for profiles must present. Profiles
+    /// can not be retrieved if this is not set
+    pub static ref PROFILER_TOKEN: Option<String> = env::var("GRAPH_WASM_PROFILER_TOKEN").ok();
+
+    static ref PROFILES: RwLock<HashMap<SubgraphDeploymentId, Arc<Profile>>> =
+        RwLock::new(HashMap::new());
+}
+
+/// The profile of one deployment. It maps stacks, written as the names of
+/// the functions on the stack from the outermost to the innermost function
+/// and separated by `;`, to the time spent in them
+#[derive(Default)]
+pub struct Profile {
+    stacks: Mutex<HashMap<String, Duration>>,
+}
+
+impl Profile {
+    /// Return the profile for `deployment`, creating an empty one if there
+    /// is none yet
+    pub fn for_deployment(deployment: &SubgraphDeploymentId) -> Arc<Profile> {
+        if let Some(profile) = PROFILES.read().unwrap().get(deployment) {
+            return profile.clone();
+        }
+        PROFILES
+            .write()
+            .unwrap()
+            .entry(deployment.clone())
+            .or_default()
+            .clone()
+    }
+
+    /// Return the profile for `deployment` if one has been recorded
+    pub fn get(deployment: &SubgraphDeploymentId) -> Option<Arc<Profile>> {
+        PROFILES.read().unwrap().get(deployment).cloned()
+    }
+
+    /// Forget the profile for `deployment`
+    pub fn remove(deployment: &SubgraphDeploymentId) {
+        PROFILES.write().unwrap().remove(deployment);
+    }
+
+    pub fn add(&self, stack: String, time: Duration) {
+        *self.stacks.lock().unwrap().entry(stack).or_default() += time;
+    }
+
+    /// Render the profile in folded format, i.e., one line per stack
+    /// followed by the number of microseconds spent in it
+    pub fn folded(&self) -> String {
+        let stacks = self.stacks.lock().unwrap();
+        let mut lines: Vec<_> = stacks
+            .iter()
+            .map(|(stack, time)| format!("{} {}\n", stack, time.as_micros()))
+            .collect();
+        lines.sort();
+        lines.concat()
+    }
+}
+
+#[test]
+fn folded_profile() {
+    let profile = Profile::default();
+    profile.add(
+        "handleTransfer;store.get".to_string(),
+        Duration::from_micros(7),
+    );
+    profile.add("handleTransfer".to_string(), Duration::from_micros(3));
+    profile.add(
+        "handleTransfer;store.get".to_string(),
+        Duration::from_micros(5),
+    );
+
+    assert_eq!(
+        "handleTransfer 3\nhandleTransfer;store.get 12\n",
+        profile.folded()
+    );
+}
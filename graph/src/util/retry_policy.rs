@@ -0,0 +1,267 @@
+//! How often and how long to retry calls to outside services.
+//!
+//! Every target, i.e., the Ethereum JSON-RPC endpoints, IPFS and
+//! Elasticsearch, has a `RetryPolicy` that determines how many attempts a
+//! call gets, how long each attempt may take and how long to wait between
+//! attempts. The policies start out with defaults for each target that can
+//! be changed for all targets with `GRAPH_RETRY_POLICY` and for individual
+//! targets with `GRAPH_RETRY_POLICY_ETHEREUM`, `GRAPH_RETRY_POLICY_IPFS`
+//! and `GRAPH_RETRY_POLICY_ELASTICSEARCH`, where the settings for a target
+//! take precedence over the ones for all targets. Each of these variables
+//! is a comma-separated list of `key=value` settings with the keys
+//!
+//! - `limit`: the number of attempts, or `none` for no limit
+//! - `timeout_secs`: how long an attempt may take
+//! - `backoff_base_ms`: the delay after the first failed attempt; the
+//!   delay grows exponentially with this as the base
+//! - `max_delay_ms`: the longest delay between attempts
+//! - `jitter`: `true` to wait a random fraction of the delay
+//!
+//! Call sites whose attempts must eventually succeed still use
+//! `no_limit()`, and some call sites set their own limit; everything else
+//! comes from the policy
+use anyhow::{anyhow, Error};
+use lazy_static::lazy_static;
+use std::env;
+use std::str::FromStr;
+use std::time::Duration;
+use tokio_retry::strategy::{jitter, ExponentialBackoff};
+
+lazy_static! {
+    static ref ALL_TARGETS: RetryOverrides = overrides_from_env("GRAPH_RETRY_POLICY");
+    static ref ETHEREUM: RetryPolicy = RetryTarget::Ethereum.load();
+    static ref IPFS: RetryPolicy = RetryTarget::Ipfs.load();
+    static ref ELASTICSEARCH: RetryPolicy = RetryTarget::Elasticsearch.load();
+}
+
+fn overrides_from_env(var: &str) -> RetryOverrides {
+    env::var(var)
+        .ok()
+        .map(|s| {
+            s.parse()
+                .unwrap_or_else(|e| panic!("invalid {}: {}", var, e))
+        })
+        .unwrap_or_default()
+}
+
+fn u64_from_env(var: &str, default: u64) -> u64 {
+    env::var(var)
+        .map(|s| s.parse().unwrap_or_else(|_| panic!("invalid {}", var)))
+        .unwrap_or(default)
+}
+
+/// The outside services that we make calls to
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RetryTarget {
+    Ethereum,
+    Ipfs,
+    Elasticsearch,
+}
+
+impl RetryTarget {
+    /// The policy for calls to this target
+    pub fn policy(&self) -> &'static RetryPolicy {
+        match self {
+            RetryTarget::Ethereum => &ETHEREUM,
+            RetryTarget::Ipfs => &IPFS,
+            RetryTarget::Elasticsearch => &ELASTICSEARCH,
+        }
+    }
+
+    fn env_var(&self) -> &'static str {
+        match self {
+            RetryTarget::Ethereum => "GRAPH_RETRY_POLICY_ETHEREUM",
+            RetryTarget::Ipfs => "GRAPH_RETRY_POLICY_IPFS",
+            RetryTarget::Elasticsearch => "GRAPH_RETRY_POLICY_ELASTICSEARCH",
+        }
+    }
+
+    /// The policy before any overrides. For Ethereum and IPFS, these
+    /// honor the environment variables that were used before retry
+    /// policies existed
+    fn defaults(&self) -> RetryPolicy {
+        match self {
+            RetryTarget::Ethereum => RetryPolicy {
+                limit: Some(u64_from_env("GRAPH_ETHEREUM_REQUEST_RETRIES", 10) as usize),
+                timeout: Duration::from_secs(u64_from_env("GRAPH_ETHEREUM_JSON_RPC_TIMEOUT", 180)),
+                backoff: Backoff::default(),
+            },
+            RetryTarget::Ipfs => RetryPolicy {
+                limit: None,
+                timeout: Duration::from_secs(u64_from_env("GRAPH_IPFS_TIMEOUT", 60)),
+                backoff: Backoff::default(),
+            },
+            RetryTarget::Elasticsearch => RetryPolicy {
+                limit: Some(3),
+                timeout: Duration::from_secs(60),
+                backoff: Backoff::default(),
+            },
+        }
+    }
+
+    fn load(&self) -> RetryPolicy {
+        let target = overrides_from_env(self.env_var());
+        let mut policy = self.defaults();
+        ALL_TARGETS.apply(&mut policy);
+        target.apply(&mut policy);
+        if policy.limit == Some(0) {
+            panic!("invalid {}: the limit must be at least 1", self.env_var());
+        }
+        policy
+    }
+}
+
+/// How long to wait between attempts
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Backoff {
+    pub base_millis: u64,
+    pub max_delay: Duration,
+    pub jitter: bool,
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Backoff {
+            base_millis: 2,
+            max_delay: Duration::from_secs(30),
+            jitter: true,
+        }
+    }
+}
+
+impl Backoff {
+    /// The delays between consecutive attempts
+    pub fn delays(&self) -> Box<dyn Iterator<Item = Duration> + Send> {
+        let backoff = ExponentialBackoff::from_millis(self.base_millis).max_delay(self.max_delay);
+        if self.jitter {
+            Box::new(backoff.map(jitter))
+        } else {
+            Box::new(backoff)
+        }
+    }
+}
+
+/// The number of attempts, the timeout for each attempt and the backoff
+/// between attempts for calls to one target
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// The number of attempts; `None` means retrying forever
+    pub limit: Option<usize>,
+    pub timeout: Duration,
+    pub backoff: Backoff,
+}
+
+/// The settings from one of the `GRAPH_RETRY_POLICY*` variables
+#[derive(Clone, Debug, Default, PartialEq)]
+struct RetryOverrides {
+    limit: Option<Option<usize>>,
+    timeout: Option<Duration>,
+    base_millis: Option<u64>,
+    max_delay: Option<Duration>,
+    jitter: Option<bool>,
+}
+
+impl RetryOverrides {
+    fn apply(&self, policy: &mut RetryPolicy) {
+        if let Some(limit) = self.limit {
+            policy.limit = limit;
+        }
+        if let Some(timeout) = self.timeout {
+            policy.timeout = timeout;
+        }
+        if let Some(base_millis) = self.base_millis {
+            policy.backoff.base_millis = base_millis;
+        }
+        if let Some(max_delay) = self.max_delay {
+            policy.backoff.max_delay = max_delay;
+        }
+        if let Some(jitter) = self.jitter {
+            policy.backoff.jitter = jitter;
+        }
+    }
+}
+
+impl FromStr for RetryOverrides {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        fn number(key: &str, value: &str) -> Result<u64, Error> {
+            value
+                .parse()
+                .map_err(|_| anyhow!("`{}` must be a number but is `{}`", key, value))
+        }
+
+        let mut overrides = RetryOverrides::default();
+        for setting in s.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+            let mut parts = setting.splitn(2, '=');
+            let key = parts.next().unwrap_or("").trim();
+            let value = parts
+                .next()
+                .ok_or_else(|| anyhow!("settings must have the form `key=value`"))?
+                .trim();
+            match key {
+                "limit" if value == "none" => overrides.limit = Some(None),
+                "limit" => overrides.limit = Some(Some(number(key, value)? as usize)),
+                "timeout_secs" => {
+                    overrides.timeout = Some(Duration::from_secs(number(key, value)?))
+                }
+                "backoff_base_ms" => overrides.base_millis = Some(number(key, value)?),
+                "max_delay_ms" => {
+                    overrides.max_delay = Some(Duration::from_millis(number(key, value)?))
+                }
+                "jitter" => {
+                    overrides.jitter = Some(
+                        value
+                            .parse()
+                            .map_err(|_| anyhow!("`jitter` must be `true` or `false`"))?,
+                    )
+                }
+                _ => return Err(anyhow!("unknown setting `{}`", key)),
+            }
+        }
+        Ok(overrides)
+    }
+}
+
+#[test]
+fn retry_overrides() {
+    let mut policy = RetryPolicy {
+        limit: Some(10),
+        timeout: Duration::from_secs(180),
+        backoff: Backoff::default(),
+    };
+
+    let overrides: RetryOverrides = "limit=none, timeout_secs=5".parse().unwrap();
+    overrides.apply(&mut policy);
+    assert_eq!(None, policy.limit);
+    assert_eq!(Duration::from_secs(5), policy.timeout);
+    assert_eq!(Backoff::default(), policy.backoff);
+
+    let overrides: RetryOverrides = "limit=3,backoff_base_ms=100,max_delay_ms=1000,jitter=false"
+        .parse()
+        .unwrap();
+    overrides.apply(&mut policy);
+    assert_eq!(Some(3), policy.limit);
+    assert_eq!(Duration::from_secs(5), policy.timeout);
+    assert_eq!(
+        Backoff {
+            base_millis: 100,
+            max_delay: Duration::from_millis(1000),
+            jitter: false
+        },
+        policy.backoff
+    );
+    assert_eq!(
+        vec![100, 1000, 1000],
+        policy
+            .backoff
+            .delays()
+            .take(3)
+            .map(|delay| delay.as_millis())
+            .collect::<Vec<_>>()
+    );
+
+    for invalid in &["limit", "limit=many", "timeout=5", "jitter=yes"] {
+        assert!(invalid.parse::<RetryOverrides>().is_err(), "{}", invalid);
+    }
+}
@@ -1,10 +1,14 @@
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
 use std::sync::Arc;
 
+use graph::components::store::{EntityAccess, EntityType};
+use graph::mock::MockStore;
 use graph::prelude::{
-    Entity, EntityCache, EntityKey, EntityModification, SubgraphDeploymentId, Value,
+    BlockState, Entity, EntityCache, EntityCollection, EntityFilter, EntityKey, EntityModification,
+    EntityOrder, EntityQuery, NullsPlacement, SubgraphDeploymentId, Value, ValueType,
+    BLOCK_NUMBER_MAX,
 };
-use graph::{components::store::EntityType, mock::MockStore};
+use graph::util::lfu_cache::LfuCache;
 
 fn make_band(id: &'static str, data: Vec<(&str, Value)>) -> (EntityKey, Entity) {
     let subgraph_id = SubgraphDeploymentId::new("entity_cache").unwrap();
@@ -45,13 +49,15 @@ fn insert_modifications() {
         "mogwai",
         vec![("id", "mogwai".into()), ("name", "Mogwai".into())],
     );
-    cache.set(mogwai_key.clone(), mogwai_data.clone());
+    cache.set(mogwai_key.clone(), mogwai_data.clone()).unwrap();
 
     let (sigurros_key, sigurros_data) = make_band(
         "sigurros",
         vec![("id", "sigurros".into()), ("name", "Sigur Ros".into())],
     );
-    cache.set(sigurros_key.clone(), sigurros_data.clone());
+    cache
+        .set(sigurros_key.clone(), sigurros_data.clone())
+        .unwrap();
 
     let result = cache.as_modifications(&*store);
     assert_eq!(
@@ -108,7 +114,7 @@ fn overwrite_modifications() {
             ("founded", 1995.into()),
         ],
     );
-    cache.set(mogwai_key.clone(), mogwai_data.clone());
+    cache.set(mogwai_key.clone(), mogwai_data.clone()).unwrap();
 
     let (sigurros_key, sigurros_data) = make_band(
         "sigurros",
@@ -118,7 +124,9 @@ fn overwrite_modifications() {
             ("founded", 1994.into()),
         ],
     );
-    cache.set(sigurros_key.clone(), sigurros_data.clone());
+    cache
+        .set(sigurros_key.clone(), sigurros_data.clone())
+        .unwrap();
 
     let result = cache.as_modifications(&*store);
     assert_eq!(
@@ -175,14 +183,14 @@ fn consecutive_modifications() {
             ("label", "Rock Action Records".into()),
         ],
     );
-    cache.set(update_key.clone(), update_data.clone());
+    cache.set(update_key.clone(), update_data.clone()).unwrap();
 
     // Then, just reset the "label".
     let (update_key, update_data) = make_band(
         "mogwai",
         vec![("id", "mogwai".into()), ("label", Value::Null)],
     );
-    cache.set(update_key.clone(), update_data.clone());
+    cache.set(update_key.clone(), update_data.clone()).unwrap();
 
     // We expect a single overwrite modification for the above that leaves "id"
     // and "name" untouched, sets "founded" and removes the "label" field.
@@ -199,3 +207,341 @@ fn consecutive_modifications() {
         },])
     );
 }
+
+#[test]
+fn forked_modifications() {
+    let mut store = MockStore::new();
+    store
+        .expect_get_many_mock()
+        .returning(|_, _| Ok(BTreeMap::new()));
+    let store = Arc::new(store);
+
+    let (mogwai_key, mogwai_data) = make_band(
+        "mogwai",
+        vec![("id", "mogwai".into()), ("name", "Mogwai".into())],
+    );
+    let (sigurros_key, sigurros_data) = make_band(
+        "sigurros",
+        vec![("id", "sigurros".into()), ("name", "Sigur Ros".into())],
+    );
+
+    let mut state = BlockState::new(store.clone(), LfuCache::new());
+    state
+        .entity_cache
+        .set(mogwai_key.clone(), mogwai_data.clone())
+        .unwrap();
+
+    // Both forks change "mogwai"; the fork that is merged last wins, just
+    // like the later of two consecutive `set` calls
+    let mut forks = state.forks(2);
+    let (update_key, update_data) = make_band(
+        "mogwai",
+        vec![
+            ("id", "mogwai".into()),
+            ("label", "Rock Action Records".into()),
+        ],
+    );
+    forks[0]
+        .entity_cache
+        .set(update_key.clone(), update_data.clone())
+        .unwrap();
+    forks[0]
+        .entity_cache
+        .set(sigurros_key.clone(), sigurros_data.clone())
+        .unwrap();
+    let (update_key, update_data) = make_band(
+        "mogwai",
+        vec![("id", "mogwai".into()), ("label", "Wichita".into())],
+    );
+    forks[1]
+        .entity_cache
+        .set(update_key.clone(), update_data.clone())
+        .unwrap();
+
+    let first = forks[0].entity_cache.access().unwrap();
+    assert_eq!(2, first.writes.len());
+    assert!(first.reads.is_empty());
+
+    // A fork that read an entity that another fork changed needs to be
+    // reprocessed
+    let mut reader = EntityAccess::default();
+    reader.reads.insert(sigurros_key.clone());
+    assert!(reader.read_changes_of(first));
+    assert!(!reader.read_changes_of(forks[1].entity_cache.access().unwrap()));
+
+    state.entity_cache.track_access();
+    for fork in forks {
+        state.extend(fork);
+    }
+    assert_eq!(2, state.entity_cache.take_access().writes.len());
+
+    let result = state.entity_cache.as_modifications(&*store);
+    assert_eq!(
+        sort_by_entity_key(result.unwrap().modifications),
+        sort_by_entity_key(vec![
+            EntityModification::Insert {
+                key: mogwai_key,
+                data: Entity::from(vec![
+                    ("id", "mogwai".into()),
+                    ("name", "Mogwai".into()),
+                    ("label", "Wichita".into()),
+                ]),
+            },
+            EntityModification::Insert {
+                key: sigurros_key,
+                data: sigurros_data,
+            }
+        ])
+    );
+}
+
+#[test]
+fn increment_modifications() {
+    let mut store = MockStore::new();
+
+    // Entities that are only incremented must not be loaded
+    store.expect_get_many_mock().returning(|_, ids_for_type| {
+        let ids: Vec<&str> = ids_for_type.values().flatten().cloned().collect();
+        assert!(!ids.contains(&"sigurros"));
+
+        let mut map = BTreeMap::new();
+        map.insert(
+            EntityType::data("Band".to_string()),
+            vec![
+                make_band(
+                    "low",
+                    vec![
+                        ("id", "low".into()),
+                        ("name", "Low".into()),
+                        ("plays", 10.into()),
+                    ],
+                )
+                .1,
+            ],
+        );
+        Ok(map)
+    });
+
+    let store = Arc::new(store);
+    let mut cache = EntityCache::new(store.clone());
+
+    // Increments of an entity we know nothing about are accumulated
+    let (sigurros_key, _) = make_band("sigurros", vec![]);
+    cache.increment(sigurros_key.clone(), "plays".to_owned(), 1.into());
+    cache.increment(sigurros_key.clone(), "plays".to_owned(), 2.into());
+
+    // Increments of a field that was set are applied right away
+    let (mogwai_key, mogwai_data) = make_band(
+        "mogwai",
+        vec![
+            ("id", "mogwai".into()),
+            ("name", "Mogwai".into()),
+            ("plays", 5.into()),
+        ],
+    );
+    cache.set(mogwai_key.clone(), mogwai_data).unwrap();
+    cache.increment(mogwai_key.clone(), "plays".to_owned(), 3.into());
+
+    // Increments of an entity that is also set need the entity
+    let (low_key, low_data) = make_band(
+        "low",
+        vec![("id", "low".into()), ("name", "Low (US)".into())],
+    );
+    cache.increment(low_key.clone(), "plays".to_owned(), 1.into());
+    cache.set(low_key.clone(), low_data).unwrap();
+
+    let mut deltas = BTreeMap::new();
+    deltas.insert("plays".to_owned(), Value::from(3));
+    let result = cache.as_modifications(&*store);
+    assert_eq!(
+        sort_by_entity_key(result.unwrap().modifications),
+        sort_by_entity_key(vec![
+            EntityModification::Increment {
+                key: sigurros_key,
+                deltas,
+            },
+            EntityModification::Insert {
+                key: mogwai_key,
+                data: Entity::from(vec![
+                    ("id", "mogwai".into()),
+                    ("name", "Mogwai".into()),
+                    ("plays", 8.into()),
+                ]),
+            },
+            EntityModification::Overwrite {
+                key: low_key,
+                data: Entity::from(vec![
+                    ("id", "low".into()),
+                    ("name", "Low (US)".into()),
+                    ("plays", 11.into()),
+                ]),
+            },
+        ])
+    );
+}
+
+#[test]
+fn append_only_modifications() {
+    // The store has no expectations; looking anything up in it fails
+    let store = Arc::new(MockStore::new());
+    let mut append_only = BTreeSet::new();
+    append_only.insert(EntityType::data("Band".to_string()));
+    let mut cache = EntityCache::new(store.clone()).with_append_only_types(Arc::new(append_only));
+
+    let (mogwai_key, mogwai_data) = make_band(
+        "mogwai",
+        vec![("id", "mogwai".into()), ("name", "Mogwai".into())],
+    );
+    cache.set(mogwai_key.clone(), mogwai_data.clone()).unwrap();
+
+    let result = cache.as_modifications(&*store);
+    assert_eq!(
+        result.unwrap().modifications,
+        vec![EntityModification::Insert {
+            key: mogwai_key,
+            data: mogwai_data,
+        }]
+    );
+}
+
+#[test]
+fn prefetch() {
+    let mut store = MockStore::new();
+
+    // Prefetching looks all entities up at once, and `get` does not go
+    // to the store afterwards
+    store
+        .expect_get_many_mock()
+        .times(1)
+        .returning(|_, ids_for_type| {
+            let mut ids: Vec<&str> = ids_for_type.values().flatten().cloned().collect();
+            ids.sort();
+            assert_eq!(vec!["mogwai", "sigurros"], ids);
+
+            let mut map = BTreeMap::new();
+            map.insert(
+                EntityType::data("Band".to_string()),
+                vec![
+                    make_band(
+                        "mogwai",
+                        vec![("id", "mogwai".into()), ("name", "Mogwai".into())],
+                    )
+                    .1,
+                ],
+            );
+            Ok(map)
+        });
+
+    let store = Arc::new(store);
+    let mut cache = EntityCache::new(store.clone());
+
+    let (mogwai_key, mogwai_data) = make_band(
+        "mogwai",
+        vec![("id", "mogwai".into()), ("name", "Mogwai".into())],
+    );
+    let (sigurros_key, _) = make_band("sigurros", vec![]);
+    cache
+        .prefetch(vec![
+            mogwai_key.clone(),
+            sigurros_key.clone(),
+            mogwai_key.clone(),
+        ])
+        .unwrap();
+    // Everything is cached now
+    cache.prefetch(vec![sigurros_key.clone()]).unwrap();
+
+    assert_eq!(Some(mogwai_data), cache.get(&mogwai_key).unwrap());
+    assert_eq!(None, cache.get(&sigurros_key).unwrap());
+}
+
+#[test]
+fn find_with_pending_changes() {
+    let band = |id: &'static str, name: &str, genre: &str| {
+        make_band(
+            id,
+            vec![
+                ("id", id.into()),
+                ("name", name.into()),
+                ("genre", genre.into()),
+            ],
+        )
+    };
+    let (mogwai_key, mogwai_data) = band("mogwai", "Mogwai", "post-rock");
+    let (low_key, low_data) = band("low", "Low", "slowcore");
+    let (sigurros_key, sigurros_data) = band("sigurros", "Sigur Ros", "post-rock");
+
+    let mut store = MockStore::new();
+    let found = mogwai_data.clone();
+    store.expect_find_mock().returning(move |_| {
+        let mut entity = found.clone();
+        entity.set("__typename", "Band");
+        Ok(vec![entity])
+    });
+    let stored = vec![mogwai_data.clone(), low_data.clone()];
+    store
+        .expect_get_many_mock()
+        .returning(move |_, ids_for_type| {
+            let ids: Vec<&str> = ids_for_type.values().flatten().cloned().collect();
+            let mut map = BTreeMap::new();
+            map.insert(
+                EntityType::data("Band".to_string()),
+                stored
+                    .iter()
+                    .filter(|entity| ids.contains(&entity.id().unwrap().as_str()))
+                    .cloned()
+                    .collect(),
+            );
+            Ok(map)
+        });
+
+    let store = Arc::new(store);
+    let mut cache = EntityCache::new(store.clone());
+    let query = EntityQuery::new(
+        SubgraphDeploymentId::new("entity_cache").unwrap(),
+        BLOCK_NUMBER_MAX,
+        EntityCollection::All(vec!["Band".to_owned()]),
+    )
+    .filter(EntityFilter::new_equal("genre", "post-rock"))
+    .order(EntityOrder::Ascending(
+        "name".to_owned(),
+        ValueType::String,
+        NullsPlacement::Last,
+    ));
+
+    // Without changes, we get what the store has
+    assert_eq!(
+        vec![mogwai_data.clone()],
+        cache.find(query.clone()).unwrap()
+    );
+
+    let mut low_data = low_data;
+    low_data.set("genre", "post-rock");
+    cache.set(low_key, low_data.clone()).unwrap();
+    cache.set(sigurros_key, sigurros_data.clone()).unwrap();
+    cache.remove(mogwai_key);
+
+    assert_eq!(
+        vec![low_data.clone(), sigurros_data.clone()],
+        cache.find(query.clone()).unwrap()
+    );
+    assert_eq!(vec![sigurros_data], cache.find(query.skip(1)).unwrap());
+}
+
+#[test]
+fn reserved_field_names() {
+    let store = Arc::new(MockStore::new());
+    let mut cache = EntityCache::new(store.clone());
+
+    let (mogwai_key, mogwai_data) = make_band(
+        "mogwai",
+        vec![("id", "mogwai".into()), ("vid", Value::Int(7))],
+    );
+    let err = cache.set(mogwai_key, mogwai_data).unwrap_err();
+    assert_eq!(
+        "Entity Band[mogwai]: `vid` is a reserved field name and can not be set",
+        err.to_string()
+    );
+
+    let result = cache.as_modifications(&*store);
+    assert_eq!(result.unwrap().modifications, vec![]);
+}
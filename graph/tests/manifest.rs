@@ -6,8 +6,10 @@ use std::time::Duration;
 
 use graph::components::{
     link_resolver::{JsonValueStream, LinkResolver as LinkResolverTrait},
-    store::EntityType,
+    store::{EntityKey, EntityType},
 };
+use graph::data::subgraph::canary::OnMismatch;
+use graph::data::subgraph::limits::EntityLimits;
 use graph::prelude::{
     anyhow, Entity, Link, SubgraphDeploymentId, SubgraphManifest, SubgraphManifestValidationError,
     UnvalidatedSubgraphManifest,
@@ -50,6 +52,10 @@ impl LinkResolverTrait for TextResolver {
     ) -> Result<JsonValueStream, anyhow::Error> {
         unimplemented!()
     }
+
+    async fn add(&self, _logger: &Logger, _data: Vec<u8>) -> Result<String, anyhow::Error> {
+        unimplemented!()
+    }
 }
 
 const GQL_SCHEMA: &str = "type Thing @entity { id: ID! }";
@@ -58,6 +64,11 @@ const ABI: &str = "[{\"type\":\"function\", \"inputs\": [{\"name\": \"i\",\"type
 
 const MAPPING: &str = "export function handleGet(call: getCall): void {}";
 
+const CANARIES: &str = r#"[
+  { "block": 20, "entity": "Thing", "id": "t1", "field": "count", "value": "3" },
+  { "block": 10, "entity": "Thing", "id": "t2", "field": "count", "value": null }
+]"#;
+
 async fn resolve_manifest(text: &str) -> SubgraphManifest {
     let mut resolver = TextResolver::default();
     let link = Link::from("/ipfs/Qmmanifest".to_owned());
@@ -66,6 +77,7 @@ async fn resolve_manifest(text: &str) -> SubgraphManifest {
     resolver.add("/ipfs/Qmschema", GQL_SCHEMA);
     resolver.add("/ipfs/Qmabi", ABI);
     resolver.add("/ipfs/Qmmapping", MAPPING);
+    resolver.add("/ipfs/Qmcanaries", CANARIES);
 
     SubgraphManifest::resolve(link, &resolver, &LOGGER)
         .await
@@ -98,6 +110,100 @@ specVersion: 0.0.2
 
     assert_eq!("Qmmanifest", manifest.id.as_str());
     assert!(manifest.graft.is_none());
+    assert_eq!(0, manifest.confirmations);
+}
+
+#[tokio::test]
+async fn confirmations_manifest() {
+    const YAML: &str = "
+dataSources: []
+schema:
+  file:
+    /: /ipfs/Qmschema
+confirmations: 12
+specVersion: 0.0.2
+";
+
+    let manifest = resolve_manifest(YAML).await;
+
+    assert_eq!(12, manifest.confirmations);
+}
+
+#[tokio::test]
+async fn limits_manifest() {
+    const YAML: &str = "
+dataSources: []
+schema:
+  file:
+    /: /ipfs/Qmschema
+limits:
+  entityMaxValueSize: 4
+  entityMaxListLength: 2
+specVersion: 0.0.2
+";
+
+    let manifest = resolve_manifest(YAML).await;
+
+    let limits = manifest.limits;
+    assert_eq!(
+        EntityLimits {
+            entity_max_value_size: Some(4),
+            entity_max_list_length: Some(2),
+        },
+        limits
+    );
+
+    let key = EntityKey {
+        subgraph_id: manifest.id.clone(),
+        entity_type: EntityType::data("Thing".to_string()),
+        entity_id: "t1".to_string(),
+    };
+    let mut thing = Entity::new();
+    thing.set("name", "abcd");
+    thing.set("tags", vec!["ab", "cd"]);
+    limits
+        .check(&key, &thing)
+        .expect("values are within the limits");
+
+    thing.set("name", "abcde");
+    let err = limits.check(&key, &thing).unwrap_err();
+    assert_eq!(
+        "Entity Thing[t1]: the value for field `name` has 5 bytes, but values can have \
+         at most 4 bytes",
+        err.to_string()
+    );
+
+    thing.set("name", "abcd");
+    thing.set("tags", vec!["ab", "cd", "ef"]);
+    let err = limits.check(&key, &thing).unwrap_err();
+    assert_eq!(
+        "Entity Thing[t1]: the list for field `tags` has 3 elements, but lists can have \
+         at most 2 elements",
+        err.to_string()
+    );
+}
+
+#[tokio::test]
+async fn canaries_manifest() {
+    const YAML: &str = "
+dataSources: []
+schema:
+  file:
+    /: /ipfs/Qmschema
+canaries:
+  file:
+    /: /ipfs/Qmcanaries
+  onMismatch: warn
+specVersion: 0.0.2
+";
+
+    let manifest = resolve_manifest(YAML).await;
+
+    let canaries = manifest.canaries.expect("manifest has canaries");
+    assert_eq!(OnMismatch::Warn, canaries.on_mismatch);
+    let blocks: Vec<_> = canaries.assertions.iter().map(|a| a.block).collect();
+    assert_eq!(vec![10, 20], blocks);
+    assert_eq!("t2", canaries.assertions[0].id);
 }
 
 #[tokio::test]
@@ -1,7 +1,10 @@
 use super::cache::{QueryBlockCache, QueryCache};
 use crossbeam::atomic::AtomicCell;
 use graph::{
-    data::schema::META_FIELD_NAME,
+    data::schema::{
+        ENTITIES_FIELD_NAME, INDEXING_STATUS_FIELD, META_FIELD_NAME, META_FIELD_TYPE,
+        SERVICE_FIELD_NAME,
+    },
     prelude::{s, CheapClone},
     util::timed_rw_lock::TimedMutex,
 };
@@ -19,6 +22,7 @@ use graph::data::query::CacheStatus;
 use graph::prelude::*;
 use graph::util::lfu_cache::LfuCache;
 
+use super::federation::EntityLookup;
 use super::QueryHash;
 use crate::introspection::{
     is_introspection_field, INTROSPECTION_DOCUMENT, INTROSPECTION_QUERY_TYPE,
@@ -309,16 +313,29 @@ pub fn execute_root_selection_set_uncached(
         items: Vec::new(),
     };
     let mut meta_items = Vec::new();
+    let mut entity_lookups = Vec::new();
 
-    for (_, fields) in collect_fields(ctx, root_type, iter::once(selection_set)) {
+    for (response_key, fields) in collect_fields(ctx, root_type, iter::once(selection_set)) {
         let name = fields[0].name.clone();
-        let selections = fields.into_iter().map(|f| q::Selection::Field(f.clone()));
         // See if this is an introspection or data field. We don't worry about
         // non-existent fields; those will cause an error later when we execute
         // the data_set SelectionSet
+        if &name == ENTITIES_FIELD_NAME {
+            // Entity lookups for Apollo Federation turn into ordinary
+            // collection queries
+            entity_lookups.push(EntityLookup::new(
+                ctx,
+                root_type,
+                response_key,
+                &fields,
+                &mut data_set,
+            )?);
+            continue;
+        }
+        let selections = fields.into_iter().map(|f| q::Selection::Field(f.clone()));
         if is_introspection_field(&name) {
             intro_set.items.extend(selections)
-        } else if &name == META_FIELD_NAME {
+        } else if &name == META_FIELD_NAME || &name == SERVICE_FIELD_NAME {
             meta_items.extend(selections)
         } else {
             data_set.items.extend(selections)
@@ -333,6 +350,9 @@ pub fn execute_root_selection_set_uncached(
         data_set.items.extend(meta_items);
         execute_selection_set_to_map(&ctx, iter::once(&data_set), root_type, initial_data)?
     };
+    for lookup in entity_lookups {
+        lookup.finish(&mut values);
+    }
 
     // Resolve introspection fields, if there are any
     if !intro_set.items.is_empty() {
@@ -349,6 +369,27 @@ pub fn execute_root_selection_set_uncached(
     Ok(values)
 }
 
+/// Whether the query asks for the `indexing` field of `_meta`
+fn selects_indexing_status(
+    ctx: &ExecutionContext<impl Resolver>,
+    selection_set: &q::SelectionSet,
+    root_type: &s::ObjectType,
+) -> bool {
+    let meta_type = match sast::get_named_type(ctx.query.schema.document(), META_FIELD_TYPE) {
+        Some(s::TypeDefinition::Object(meta_type)) => meta_type,
+        _ => return false,
+    };
+    collect_fields(ctx, root_type, iter::once(selection_set))
+        .into_iter()
+        .flat_map(|(_, fields)| fields)
+        .filter(|field| field.name == META_FIELD_NAME)
+        .any(|field| {
+            collect_fields(ctx, meta_type, iter::once(&field.selection_set))
+                .into_iter()
+                .any(|(_, fields)| fields[0].name == INDEXING_STATUS_FIELD)
+        })
+}
+
 /// Executes the root selection set of a query.
 pub async fn execute_root_selection_set<R: Resolver>(
     ctx: Arc<ExecutionContext<R>>,
@@ -360,7 +401,12 @@ pub async fn execute_root_selection_set<R: Resolver>(
     // and once for insert.
     let mut key: Option<QueryHash> = None;
 
-    if R::CACHEABLE && (*CACHE_ALL || CACHED_SUBGRAPH_IDS.contains(ctx.query.schema.id())) {
+    // The indexing status changes independently of the blocks that the
+    // cache is organized by, and must therefore always be looked up
+    if R::CACHEABLE
+        && (*CACHE_ALL || CACHED_SUBGRAPH_IDS.contains(ctx.query.schema.id()))
+        && !selects_indexing_status(&ctx, &selection_set, &root_type)
+    {
         if let (Some(block_ptr), Some(network)) = (block_ptr, &ctx.query.network) {
             // JSONB and metadata queries use `BLOCK_NUMBER_MAX`. Ignore this case for two reasons:
             // - Metadata queries are not cacheable.
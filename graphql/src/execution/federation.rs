@@ -0,0 +1,225 @@
+//! Execution of the `_entities` field that Apollo Federation uses to look
+//! up entities by their representations, i.e., objects with the
+//! `__typename` and `id` of an entity. The lookups are turned into
+//! ordinary collection queries with an `id_in` filter that run together
+//! with the other fields of the query, and their results are then put
+//! back in the order of the representations
+use graphql_parser::Pos;
+use indexmap::IndexMap;
+use inflector::Inflector;
+use std::collections::{BTreeMap, HashMap};
+
+use graph::data::schema::{
+    ANY_SCALAR_TYPE, ENTITIES_FIELD_NAME, ENTITY_UNION_TYPE, KEY_DIRECTIVE, SERVICE_FIELD_NAME,
+    SERVICE_FIELD_TYPE,
+};
+use graph::prelude::{q, s, QueryExecutionError};
+
+use super::{coerce_argument_values, get_named_type, ExecutionContext, Resolver};
+use crate::schema::ast as sast;
+
+/// The response key under which the collection queries select the `id` of
+/// entities so that they can be matched to their representations
+const ID_RESPONSE_KEY: &str = "_entities_id_";
+
+/// The collection queries for one `_entities` field
+pub(crate) struct EntityLookup {
+    /// The response key of the `_entities` field
+    response_key: String,
+    /// The `(__typename, id)` of each representation
+    representations: Vec<(String, String)>,
+    /// The entity type and response key of each collection query
+    queries: Vec<(String, String)>,
+}
+
+impl EntityLookup {
+    /// Add collection queries for the entities that the `_entities` fields
+    /// `fields` with response key `response_key` look up to `data_set`
+    pub(crate) fn new(
+        ctx: &ExecutionContext<impl Resolver>,
+        root_type: &s::ObjectType,
+        response_key: &str,
+        fields: &[&q::Field],
+        data_set: &mut q::SelectionSet,
+    ) -> Result<Self, Vec<QueryExecutionError>> {
+        let field = fields[0];
+        let entity_types =
+            match get_named_type(ctx.query.schema.document(), &ENTITY_UNION_TYPE.to_owned()) {
+                Some(s::TypeDefinition::Union(union)) => union.types,
+                _ => vec![],
+            };
+
+        let invalid = |value: &q::Value| {
+            vec![QueryExecutionError::InvalidArgumentError(
+                field.position,
+                "representations".to_owned(),
+                value.clone(),
+            )]
+        };
+        let args = coerce_argument_values(&ctx.query, root_type, field)?;
+        let representations = match args.get(&"representations".to_owned()) {
+            Some(q::Value::List(representations)) => representations
+                .iter()
+                .map(|representation| {
+                    let (typename, id) = match representation {
+                        q::Value::Object(object) => (object.get("__typename"), object.get("id")),
+                        _ => (None, None),
+                    };
+                    match (typename, id) {
+                        (Some(q::Value::String(typename)), Some(q::Value::String(id)))
+                            if entity_types.contains(typename) =>
+                        {
+                            Ok((typename.clone(), id.clone()))
+                        }
+                        _ => Err(invalid(representation)),
+                    }
+                })
+                .collect::<Result<Vec<_>, _>>()?,
+            Some(value) => return Err(invalid(value)),
+            None => vec![],
+        };
+
+        let mut ids_by_type: IndexMap<&String, Vec<&String>> = IndexMap::new();
+        for (typename, id) in &representations {
+            let ids = ids_by_type.entry(typename).or_default();
+            if !ids.contains(&id) {
+                ids.push(id);
+            }
+        }
+
+        let mut selection_set = q::SelectionSet {
+            span: field.selection_set.span,
+            items: fields
+                .iter()
+                .flat_map(|field| field.selection_set.items.iter().cloned())
+                .collect(),
+        };
+        selection_set.items.push(q::Selection::Field(q::Field {
+            position: Pos::default(),
+            alias: Some(ID_RESPONSE_KEY.to_owned()),
+            name: "id".to_owned(),
+            arguments: vec![],
+            directives: vec![],
+            selection_set: q::SelectionSet {
+                span: (Pos::default(), Pos::default()),
+                items: vec![],
+            },
+        }));
+
+        let mut queries = vec![];
+        for (typename, ids) in ids_by_type {
+            for chunk in ids.chunks(ctx.max_first.max(1) as usize) {
+                let query_key = format!("{}:{}:{}", response_key, typename, queries.len());
+                let mut filter = BTreeMap::new();
+                filter.insert(
+                    "id_in".to_owned(),
+                    q::Value::List(
+                        chunk
+                            .iter()
+                            .map(|id| q::Value::String((*id).clone()))
+                            .collect(),
+                    ),
+                );
+                data_set.items.push(q::Selection::Field(q::Field {
+                    position: field.position,
+                    alias: Some(query_key.clone()),
+                    name: typename.to_plural().to_camel_case(),
+                    arguments: vec![
+                        ("where".to_owned(), q::Value::Object(filter)),
+                        (
+                            "first".to_owned(),
+                            q::Value::Int((chunk.len() as i32).into()),
+                        ),
+                    ],
+                    directives: vec![],
+                    selection_set: selection_set.clone(),
+                }));
+                queries.push((typename.clone(), query_key));
+            }
+        }
+
+        Ok(EntityLookup {
+            response_key: response_key.to_owned(),
+            representations,
+            queries,
+        })
+    }
+
+    /// Replace the results of the collection queries in `values` with the
+    /// result of the `_entities` field, which has an entry for each
+    /// representation, and `null` for entities that do not exist
+    pub(crate) fn finish(self, values: &mut BTreeMap<String, q::Value>) {
+        let mut entities = HashMap::new();
+        for (typename, query_key) in self.queries {
+            if let Some(q::Value::List(objects)) = values.remove(&query_key) {
+                for object in objects {
+                    if let q::Value::Object(mut object) = object {
+                        if let Some(q::Value::String(id)) = object.remove(ID_RESPONSE_KEY) {
+                            entities.insert((typename.clone(), id), q::Value::Object(object));
+                        }
+                    }
+                }
+            }
+        }
+
+        let result = self
+            .representations
+            .into_iter()
+            .map(|key| entities.get(&key).cloned().unwrap_or(q::Value::Null))
+            .collect();
+        values.insert(self.response_key, q::Value::List(result));
+    }
+}
+
+/// The schema that the `_service { sdl }` field returns: the API schema
+/// without the subscription type, the fields and types for Apollo
+/// Federation, and the `@subgraphId` directives that are only used
+/// internally
+pub(crate) fn service_sdl(api_schema: &s::Document) -> String {
+    let internal_types = [
+        ANY_SCALAR_TYPE,
+        SERVICE_FIELD_TYPE,
+        ENTITY_UNION_TYPE,
+        "Subscription",
+    ];
+    let internal_fields = [SERVICE_FIELD_NAME, ENTITIES_FIELD_NAME];
+
+    let mut schema = api_schema.clone();
+    schema.definitions.retain(|defn| match defn {
+        s::Definition::TypeDefinition(typedef) => {
+            !internal_types.contains(&sast::get_type_name(typedef).as_str())
+        }
+        s::Definition::DirectiveDefinition(directive) => directive.name != KEY_DIRECTIVE,
+        _ => true,
+    });
+    for defn in schema.definitions.iter_mut() {
+        let directives = match defn {
+            s::Definition::TypeDefinition(s::TypeDefinition::Object(object_type)) => {
+                if object_type.name == "Query" {
+                    object_type
+                        .fields
+                        .retain(|field| !internal_fields.contains(&field.name.as_str()));
+                }
+                &mut object_type.directives
+            }
+            s::Definition::TypeDefinition(s::TypeDefinition::Interface(interface_type)) => {
+                &mut interface_type.directives
+            }
+            s::Definition::TypeDefinition(s::TypeDefinition::Enum(enum_type)) => {
+                &mut enum_type.directives
+            }
+            s::Definition::TypeDefinition(s::TypeDefinition::Scalar(scalar_type)) => {
+                &mut scalar_type.directives
+            }
+            s::Definition::TypeDefinition(s::TypeDefinition::InputObject(input_object_type)) => {
+                &mut input_object_type.directives
+            }
+            s::Definition::TypeDefinition(s::TypeDefinition::Union(union_type)) => {
+                &mut union_type.directives
+            }
+            _ => continue,
+        };
+        directives.retain(|directive| directive.name != "subgraphId");
+    }
+    schema.to_string()
+}
@@ -1,6 +1,7 @@
 mod cache;
 /// Implementation of the GraphQL execution algorithm.
 mod execution;
+mod federation;
 mod query;
 /// Common trait for field resolvers used in the execution.
 mod resolver;
@@ -8,6 +9,7 @@ mod resolver;
 use stable_hash::{crypto::SetHasher, StableHasher};
 
 pub use self::execution::*;
+pub(crate) use self::federation::service_sdl;
 pub use self::query::Query;
 pub use self::resolver::Resolver;
 
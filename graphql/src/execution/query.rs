@@ -1,12 +1,16 @@
 use graphql_parser::Pos;
-use std::collections::HashMap;
+use lazy_static::lazy_static;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque};
+use std::env;
 use std::hash::{Hash, Hasher};
-use std::sync::Arc;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
 use std::time::Instant;
 use std::{collections::hash_map::DefaultHasher, convert::TryFrom};
 
 use graph::data::graphql::{
-    ext::{DocumentExt, TypeExt},
+    expression::Expression,
+    ext::{DirectiveExt, DirectiveFinder, DocumentExt, FieldExt, TypeExt, ValueExt},
     ObjectOrInterface,
 };
 use graph::data::query::{Query as GraphDataQuery, QueryVariables};
@@ -20,9 +24,72 @@ use crate::query::{ast as qast, ext::BlockConstraint};
 use crate::schema::ast as sast;
 use crate::{
     execution::{get_field, get_named_type, object_or_interface},
-    schema::api::ErrorPolicy,
+    schema::api::{ErrorPolicy, AGGREGATE_DIRECTIVE, COUNT_DIRECTIVE, DECIMAL_DIRECTIVE},
 };
 
+lazy_static! {
+    /// The number of queries that passed validation to remember. Setting
+    /// this to 0 turns the cache off
+    static ref QUERY_VALIDATION_CACHE_SIZE: usize =
+        env::var("GRAPH_QUERY_VALIDATION_CACHE_SIZE")
+            .unwrap_or("10000".into())
+            .parse::<usize>()
+            .expect("invalid GRAPH_QUERY_VALIDATION_CACHE_SIZE");
+    static ref VALIDATED_QUERIES: ValidationCache =
+        ValidationCache::with_capacity(*QUERY_VALIDATION_CACHE_SIZE);
+}
+
+/// The schema version, the shape hash, and the name of the operation that
+/// was validated
+type ValidationKey = (u64, u64, Option<String>);
+
+/// The `(schema version, shape hash, operation name)` of queries whose
+/// fields passed validation. Validating fields only looks at the names of
+/// fields, fragments and types, all of which go into the shape hash, so a
+/// query with the same shape as a query that passed validation against the
+/// same schema passes, too. The shape hash covers the whole document, and
+/// the operation name makes sure that we only skip validation for the
+/// operation that was validated. Queries that fail validation are not
+/// remembered since their errors mention positions in the query. When the
+/// cache is full, the oldest entries are evicted first
+struct ValidationCache {
+    capacity: usize,
+    inner: Mutex<(HashSet<ValidationKey>, VecDeque<ValidationKey>)>,
+}
+
+impl ValidationCache {
+    fn with_capacity(capacity: usize) -> Self {
+        ValidationCache {
+            capacity,
+            inner: Mutex::new((HashSet::new(), VecDeque::new())),
+        }
+    }
+
+    fn contains(&self, key: &ValidationKey) -> bool {
+        self.inner.lock().unwrap().0.contains(key)
+    }
+
+    fn insert(&self, key: ValidationKey) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        let mut inner = self.inner.lock().unwrap();
+        let (keys, order) = &mut *inner;
+        if keys.insert(key.clone()) {
+            order.push_back(key);
+        }
+        while keys.len() > self.capacity {
+            match order.pop_front() {
+                Some(oldest) => {
+                    keys.remove(&oldest);
+                }
+                None => break,
+            }
+        }
+    }
+}
+
 #[derive(Copy, Clone, Debug)]
 pub enum ComplexityError {
     TooDeep,
@@ -128,6 +195,13 @@ impl Query {
             }
         }
         let operation = operation.ok_or(QueryExecutionError::OperationNameRequired)?;
+        let roles = query.roles;
+        let operation_name = match &operation {
+            q::OperationDefinition::Query(q::Query { name, .. })
+            | q::OperationDefinition::Subscription(q::Subscription { name, .. })
+            | q::OperationDefinition::Mutation(q::Mutation { name, .. }) => name.clone(),
+            q::OperationDefinition::SelectionSet(_) => None,
+        };
 
         let variables = coerce_variables(schema.as_ref(), &operation, query.variables)?;
         let (kind, selection_set) = match operation {
@@ -174,8 +248,9 @@ impl Query {
             complexity: 0,
         };
 
-        query.validate_fields()?;
+        query.validate_fields(operation_name)?;
         query.check_complexity(max_complexity, max_depth)?;
+        query.check_access(&roles)?;
 
         Ok(Arc::new(query))
     }
@@ -364,12 +439,21 @@ impl Query {
         }
     }
 
-    fn validate_fields(&self) -> Result<(), Vec<QueryExecutionError>> {
+    fn validate_fields(
+        &self,
+        operation_name: Option<String>,
+    ) -> Result<(), Vec<QueryExecutionError>> {
+        let key = (self.schema.version(), self.shape_hash, operation_name);
+        if VALIDATED_QUERIES.contains(&key) {
+            return Ok(());
+        }
+
         let root_type = self.schema.document().get_root_query_type().unwrap();
 
         let errors =
             self.validate_fields_inner(&"Query".to_owned(), root_type.into(), &self.selection_set);
         if errors.len() == 0 {
+            VALIDATED_QUERIES.insert(key);
             Ok(())
         } else {
             Err(errors)
@@ -401,6 +485,12 @@ impl Query {
                                     ty,
                                     &field.selection_set,
                                 ))
+                            } else if let Some(s::TypeDefinition::Union(union)) =
+                                get_named_type(schema, base_type)
+                            {
+                                errors.extend(
+                                    self.validate_union_fields(&union, &field.selection_set),
+                                )
                             }
                         }
                         None => errors.push(QueryExecutionError::UnknownField(
@@ -452,6 +542,253 @@ impl Query {
             })
     }
 
+    // Selections on a union can only select `__typename` directly, and
+    // everything else through fragments on the types in the union
+    fn validate_union_fields(
+        &self,
+        union: &s::UnionType,
+        selection_set: &q::SelectionSet,
+    ) -> Vec<QueryExecutionError> {
+        selection_set
+            .items
+            .iter()
+            .fold(vec![], |mut errors, selection| {
+                match selection {
+                    q::Selection::Field(field) if field.name == "__typename" => {}
+                    q::Selection::Field(field) => errors.push(QueryExecutionError::UnknownField(
+                        field.position,
+                        union.name.clone(),
+                        field.name.clone(),
+                    )),
+                    q::Selection::InlineFragment(fragment) if fragment.type_condition.is_none() => {
+                        errors.extend(self.validate_union_fields(union, &fragment.selection_set))
+                    }
+                    // Fragments with a type condition are validated against
+                    // that type, no matter what type we pass in
+                    q::Selection::InlineFragment(_) | q::Selection::FragmentSpread(_) => errors
+                        .extend(self.validate_fields_inner(
+                            &union.name,
+                            self.schema.query_type.as_ref().into(),
+                            &q::SelectionSet {
+                                span: selection_set.span,
+                                items: vec![selection.clone()],
+                            },
+                        )),
+                }
+                errors
+            })
+    }
+
+    /// Check that `roles` may see everything that the query selects. Types
+    /// and fields with a `@restricted(roles: [..])` directive can only be
+    /// selected, and fields only be filtered, ordered, or aggregated by, if
+    /// the query is made on behalf of one of the roles in the directive
+    fn check_access(&self, roles: &BTreeSet<String>) -> Result<(), Vec<QueryExecutionError>> {
+        let root_type = self.schema.document().get_root_query_type().unwrap();
+
+        let mut errors = vec![];
+        self.check_access_inner(roles, root_type.into(), &self.selection_set, &mut errors);
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+
+    fn check_access_inner(
+        &self,
+        roles: &BTreeSet<String>,
+        ty: ObjectOrInterface<'_>,
+        selection_set: &q::SelectionSet,
+        errors: &mut Vec<QueryExecutionError>,
+    ) {
+        let schema = self.schema.document();
+
+        for selection in &selection_set.items {
+            match selection {
+                q::Selection::Field(field) => {
+                    // Fields that don't exist have already been reported
+                    // by `validate_fields`
+                    let s_field = match get_field(ty, &field.name) {
+                        Some(s_field) => s_field,
+                        None => continue,
+                    };
+                    let mut deny = |name: String| {
+                        errors.push(QueryExecutionError::AccessDenied(field.position, name))
+                    };
+
+                    for name in denied_fields(roles, ty, s_field) {
+                        deny(name);
+                    }
+
+                    // The entity types whose data the field returns, either
+                    // directly, or as a count or an aggregate
+                    let base_type = s_field.field_type.get_base_type();
+                    let return_type = object_or_interface(schema, base_type);
+                    let mut entities = vec![];
+                    entities.extend(return_type);
+                    entities.extend(
+                        Some(&s_field.directives)
+                            .into_iter()
+                            .chain(return_type.map(|ty| ty.directives()))
+                            .filter_map(|directives| {
+                                directives
+                                    .find_directive(COUNT_DIRECTIVE.to_string())
+                                    .or_else(|| {
+                                        directives.find_directive(AGGREGATE_DIRECTIVE.to_string())
+                                    })
+                            })
+                            .filter_map(|directive| directive.argument("entity"))
+                            .filter_map(|entity| entity.as_string())
+                            .filter_map(|entity| object_or_interface(schema, entity)),
+                    );
+
+                    let (referenced, filter) = self.referenced_fields(field);
+                    for entity in entities {
+                        let mut types = vec![entity];
+                        if entity.is_interface() {
+                            types.extend(
+                                entity
+                                    .object_types(self.schema.schema())
+                                    .unwrap_or_default()
+                                    .into_iter()
+                                    .map(ObjectOrInterface::from),
+                            );
+                        }
+                        for entity_type in types {
+                            if !is_allowed(roles, entity_type.directives().restricted_roles()) {
+                                deny(entity_type.name().to_owned());
+                            }
+                            let mut denied = vec![];
+                            for name in &referenced {
+                                let s_fields = entity_type
+                                    .fields()
+                                    .iter()
+                                    .filter(|s_field| *name == &s_field.name);
+                                for s_field in s_fields {
+                                    denied.extend(denied_fields(roles, entity_type, s_field));
+                                }
+                            }
+                            if let Some(filter) = filter {
+                                self.check_filter_access(roles, entity_type, filter, &mut denied);
+                            }
+                            for name in denied {
+                                deny(name);
+                            }
+                        }
+                    }
+
+                    if let Some(return_type) = return_type {
+                        self.check_access_inner(roles, return_type, &field.selection_set, errors);
+                    } else if let Some(s::TypeDefinition::Union(_)) =
+                        get_named_type(schema, base_type)
+                    {
+                        // Validation made sure that selections on unions
+                        // only go through fragments with a type condition,
+                        // which determine the type that gets checked
+                        self.check_access_inner(
+                            roles,
+                            self.schema.query_type.as_ref().into(),
+                            &field.selection_set,
+                            errors,
+                        );
+                    }
+                }
+                q::Selection::FragmentSpread(fragment) => {
+                    if let Some(frag) = self.fragments.get(&fragment.fragment_name) {
+                        let q::TypeCondition::On(type_name) = &frag.type_condition;
+                        if let Some(ty) = object_or_interface(schema, type_name) {
+                            self.check_access_inner(roles, ty, &frag.selection_set, errors);
+                        }
+                    }
+                }
+                q::Selection::InlineFragment(fragment) => {
+                    let ty = match &fragment.type_condition {
+                        Some(q::TypeCondition::On(type_name)) => {
+                            match object_or_interface(schema, type_name) {
+                                Some(ty) => ty,
+                                None => continue,
+                            }
+                        }
+                        None => ty,
+                    };
+                    self.check_access_inner(roles, ty, &fragment.selection_set, errors);
+                }
+            }
+        }
+    }
+
+    /// Add the names of the fields of `ty` that `filter` filters by and
+    /// that `roles` may not see to `denied`. Nested `<field>_` filters are
+    /// checked against the type of the children they filter
+    fn check_filter_access(
+        &self,
+        roles: &BTreeSet<String>,
+        ty: ObjectOrInterface<'_>,
+        filter: &BTreeMap<String, q::Value>,
+        denied: &mut Vec<String>,
+    ) {
+        let schema = self.schema.document();
+        for (key, value) in filter {
+            // The key still carries the suffix of the filter operator, like
+            // `_gt`
+            let s_fields = ty.fields().iter().filter(|s_field| {
+                key == &s_field.name || key.starts_with(&format!("{}_", s_field.name))
+            });
+            for s_field in s_fields {
+                denied.extend(denied_fields(roles, ty, s_field));
+
+                if key != &format!("{}_", s_field.name) {
+                    continue;
+                }
+                let child = object_or_interface(schema, s_field.field_type.get_base_type());
+                if let (Some(child), Some(q::Value::Object(child_filter))) =
+                    (child, self.resolve_variable(value))
+                {
+                    if !is_allowed(roles, child.directives().restricted_roles()) {
+                        denied.push(child.name().to_owned());
+                    }
+                    self.check_filter_access(roles, child, child_filter, denied);
+                }
+            }
+        }
+    }
+
+    /// The names of the entity fields that the arguments of `field` order
+    /// or aggregate by, and the filter in its `where` argument
+    fn referenced_fields<'a>(
+        &'a self,
+        field: &'a q::Field,
+    ) -> (Vec<&'a String>, Option<&'a BTreeMap<String, q::Value>>) {
+        let mut referenced = vec![];
+        let mut filter = None;
+        for (name, value) in &field.arguments {
+            let value = match self.resolve_variable(value) {
+                Some(value) => value,
+                None => continue,
+            };
+            match (name.as_str(), value) {
+                ("where", q::Value::Object(object)) => filter = Some(object),
+                ("orderBy", q::Value::Enum(attr))
+                | ("orderBy", q::Value::String(attr))
+                | ("field", q::Value::Enum(attr))
+                | ("field", q::Value::String(attr))
+                | ("groupBy", q::Value::Enum(attr))
+                | ("groupBy", q::Value::String(attr)) => referenced.push(attr),
+                _ => {}
+            }
+        }
+        (referenced, filter)
+    }
+
+    /// The value of the variable if `value` is one, and `value` otherwise
+    fn resolve_variable<'a>(&'a self, value: &'a q::Value) -> Option<&'a q::Value> {
+        match value {
+            q::Value::Variable(var) => self.variables.get(var),
+            value => Some(value),
+        }
+    }
+
     fn complexity_inner(
         &self,
         ty: &s::TypeDefinition,
@@ -611,3 +948,72 @@ fn coerce_variable(
         )]
     })
 }
+
+/// Whether `roles` may see something with a `@restricted` directive with
+/// the `restricted` roles
+fn is_allowed(roles: &BTreeSet<String>, restricted: Option<Vec<&String>>) -> bool {
+    restricted.map_or(true, |restricted| {
+        restricted.into_iter().any(|role| roles.contains(role))
+    })
+}
+
+/// The names of the fields that `roles` may not see but that `field` of
+/// `ty` reveals. Besides the field itself, a `<field>Decimal` field reveals
+/// the field it is computed from, and a field with a `@derivedExpression`
+/// the fields in its expression
+fn denied_fields(
+    roles: &BTreeSet<String>,
+    ty: ObjectOrInterface<'_>,
+    field: &s::Field,
+) -> Vec<String> {
+    let find = |name: &str| ty.fields().iter().find(|field| field.name == name);
+
+    let mut revealed = vec![field];
+    let amount = field
+        .find_directive(DECIMAL_DIRECTIVE.to_string())
+        .and_then(|decimal| decimal.argument("amount"))
+        .and_then(|amount| amount.as_string())
+        .and_then(|amount| find(amount.as_str()));
+    revealed.extend(amount);
+    for field in revealed.clone() {
+        let expression = field
+            .derived_expression()
+            .and_then(|expression| Expression::from_str(expression).ok());
+        if let Some(expression) = expression {
+            revealed.extend(
+                expression
+                    .fields()
+                    .into_iter()
+                    .filter_map(|name| find(name)),
+            );
+        }
+    }
+
+    revealed
+        .into_iter()
+        .filter(|field| !is_allowed(roles, field.restricted_roles()))
+        .map(|field| format!("{}.{}", ty.name(), field.name))
+        .collect()
+}
+
+#[test]
+fn validation_cache() {
+    let cache = ValidationCache::with_capacity(2);
+    cache.insert((1, 1, None));
+    cache.insert((1, 2, None));
+    assert!(cache.contains(&(1, 1, None)));
+    assert!(!cache.contains(&(2, 1, None)));
+
+    // The oldest entry is evicted first
+    cache.insert((2, 1, None));
+    assert!(!cache.contains(&(1, 1, None)));
+    assert!(cache.contains(&(1, 2, None)));
+    assert!(cache.contains(&(2, 1, None)));
+
+    // Other operations of the same document are not validated
+    assert!(!cache.contains(&(2, 1, Some("other".to_owned()))));
+
+    let cache = ValidationCache::with_capacity(0);
+    cache.insert((1, 1, None));
+    assert!(!cache.contains(&(1, 1, None)));
+}
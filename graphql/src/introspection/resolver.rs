@@ -74,7 +74,7 @@ fn type_definition_object(
                 object_type_object(schema, type_objects, object_type)
             }
             s::TypeDefinition::Scalar(scalar_type) => scalar_type_object(scalar_type),
-            s::TypeDefinition::Union(union_type) => union_type_object(schema, union_type),
+            s::TypeDefinition::Union(union_type) => union_type_object(union_type),
         };
 
         type_objects.insert(type_name.to_owned(), type_object.clone());
@@ -207,22 +207,16 @@ fn scalar_type_object(scalar_type: &s::ScalarType) -> q::Value {
     }
 }
 
-fn union_type_object(schema: &Schema, union_type: &s::UnionType) -> q::Value {
+fn union_type_object(union_type: &s::UnionType) -> q::Value {
     object! {
         name: union_type.name.to_owned(),
         kind: q::Value::Enum(String::from("UNION")),
         description: union_type.description.clone(),
-        possibleTypes:
-            sast::get_object_type_definitions(&schema.document)
-                .iter()
-                .filter(|object_type| {
-                    object_type
-                        .implements_interfaces
-                        .iter()
-                        .any(|implemented_name| implemented_name == &union_type.name)
-                })
-                .map(|object_type| q::Value::String(object_type.name.to_owned()))
-                .collect::<Vec<_>>(),
+        possibleTypes: union_type
+            .types
+            .iter()
+            .map(|type_name| q::Value::String(type_name.to_owned()))
+            .collect::<Vec<_>>(),
     }
 }
 
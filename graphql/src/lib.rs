@@ -38,7 +38,7 @@ pub mod prelude {
     pub use graph::prelude::s::ObjectType;
 }
 
-#[cfg(debug_assertions)]
+#[cfg(any(debug_assertions, feature = "testing"))]
 pub mod test_support {
     pub use super::runner::INITIAL_DEPLOYMENT_STATE_FOR_TESTS;
 }
@@ -64,7 +64,7 @@ lazy_static! {
         .unwrap_or(false);
 }
 
-#[cfg(debug_assertions)]
+#[cfg(any(debug_assertions, feature = "testing"))]
 lazy_static! {
     // Test only, see c435c25decbc4ad7bbbadf8e0ced0ff2
     pub static ref INITIAL_DEPLOYMENT_STATE_FOR_TESTS: std::sync::Mutex<Option<DeploymentState>> = std::sync::Mutex::new(None);
@@ -139,17 +139,20 @@ where
         // mix data from different block heights even if no reverts happen
         // while the query is running. `self.store` can not be used after this
         // point, and everything needs to go through the `store` we are
-        // setting up here
+        // setting up here. The store is also pinned to the block the
+        // deployment is at right now so that the fields of the query do not
+        // see different blocks when a block is written while the query runs
         let store = self
             .store
             .query_store(target, false)
-            .map_err(|e| QueryExecutionError::from(e))?;
+            .map_err(|e| QueryExecutionError::from(e))?
+            .pin_latest_block()?;
         let state = store.deployment_state()?;
         let network = Some(store.network_name().to_string());
         let schema = store.api_schema()?;
 
         // Test only, see c435c25decbc4ad7bbbadf8e0ced0ff2
-        #[cfg(debug_assertions)]
+        #[cfg(any(debug_assertions, feature = "testing"))]
         let state = INITIAL_DEPLOYMENT_STATE_FOR_TESTS
             .lock()
             .unwrap()
@@ -187,6 +190,7 @@ where
                 query.schema.id().clone(),
             )
             .await?;
+            state.check_block_available(resolver.block_number())?;
             max_block = max_block.max(resolver.block_number());
             let query_res = execute_query(
                 query.clone(),
@@ -265,6 +269,7 @@ where
         target: QueryTarget,
     ) -> Result<SubscriptionResult, SubscriptionError> {
         let store = self.store.query_store(target, true)?;
+        store.deployment_state()?.check_subscriptions_allowed()?;
         let schema = store.api_schema()?;
         let network = store.network_name().to_string();
 
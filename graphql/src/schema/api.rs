@@ -7,8 +7,13 @@ use lazy_static::lazy_static;
 use crate::schema::ast;
 
 use graph::data::{
-    graphql::ext::{DirectiveExt, DocumentExt, ValueExt},
-    schema::{META_FIELD_NAME, META_FIELD_TYPE},
+    graphql::ext::{DirectiveExt, DirectiveFinder, DocumentExt, FieldExt, ValueExt},
+    schema::{
+        ANY_SCALAR_TYPE, BLOCK_CHANGED_FILTER_TYPE, CHANGE_BLOCK_FILTER_FIELD,
+        CREATE_BLOCK_FILTER_FIELD, ENTITIES_FIELD_NAME, ENTITY_UNION_TYPE, FEDERATION_ENABLED,
+        KEY_DIRECTIVE, LAST_UPDATED_BLOCK_ENABLED, LAST_UPDATED_BLOCK_FIELD, META_FIELD_NAME,
+        META_FIELD_TYPE, SERVICE_FIELD_NAME, SERVICE_FIELD_TYPE,
+    },
     subgraph::SubgraphFeature,
 };
 use graph::prelude::s::{Value, *};
@@ -29,6 +34,21 @@ const BLOCK_HEIGHT: &str = "Block_height";
 
 const ERROR_POLICY_TYPE: &str = "_SubgraphErrorPolicy_";
 
+const AGGREGATE_FUNCTION_TYPE: &str = "AggregateFunction";
+
+/// The directive that marks the `<type>_aggregate` types, and names the
+/// entity type they aggregate
+pub(crate) const AGGREGATE_DIRECTIVE: &str = "aggregate";
+
+/// The directive that marks the `<plural>Count` fields on `Query`, and
+/// names the entity type they count
+pub(crate) const COUNT_DIRECTIVE: &str = "count";
+
+/// The directive that marks the `<field>Decimal` fields that are added for
+/// fields with a `@decimals` directive, and names the fields that their
+/// value is computed from
+pub(crate) const DECIMAL_DIRECTIVE: &str = "decimal";
+
 #[derive(Debug, PartialEq, Eq, Copy, Clone)]
 pub enum ErrorPolicy {
     Allow,
@@ -78,13 +98,23 @@ pub fn api_schema(
     add_directives(&mut schema);
     add_builtin_scalar_types(&mut schema)?;
     add_order_direction_enum(&mut schema);
+    add_aggregate_function_enum(&mut schema);
     add_block_height_type(&mut schema);
+    add_block_changed_filter_type(&mut schema);
     add_meta_field_type(&mut schema);
     add_types_for_object_types(&mut schema, &object_types)?;
     add_types_for_interface_types(&mut schema, &interface_types)?;
     add_field_arguments(&mut schema, &input_schema)?;
+    add_renamed_fields(&mut schema, &object_types, &interface_types);
+    add_decimal_fields(&mut schema, &object_types, &interface_types);
+    if *LAST_UPDATED_BLOCK_ENABLED {
+        add_last_updated_block_fields(&mut schema, &object_types, &interface_types);
+    }
     add_query_type(&mut schema, &object_types, &interface_types, features)?;
     add_subscription_type(&mut schema, &object_types, &interface_types, features)?;
+    if *FEDERATION_ENABLED {
+        add_federation_types(&mut schema, &object_types)?;
+    }
     Ok(schema)
 }
 
@@ -158,27 +188,190 @@ fn add_directives(schema: &mut Document) {
         locations: vec![DirectiveLocation::Object],
     });
 
+    let renamed = Definition::DirectiveDefinition(DirectiveDefinition {
+        position: Pos::default(),
+        description: None,
+        name: "renamed".to_owned(),
+        arguments: vec![InputValue {
+            position: Pos::default(),
+            description: None,
+            name: "from".to_owned(),
+            value_type: Type::NonNullType(Box::new(Type::NamedType("String".to_owned()))),
+            default_value: None,
+            directives: vec![],
+        }],
+        locations: vec![DirectiveLocation::FieldDefinition],
+    });
+
+    let aggregate = Definition::DirectiveDefinition(DirectiveDefinition {
+        position: Pos::default(),
+        description: None,
+        name: AGGREGATE_DIRECTIVE.to_owned(),
+        arguments: vec![InputValue {
+            position: Pos::default(),
+            description: None,
+            name: "entity".to_owned(),
+            value_type: Type::NonNullType(Box::new(Type::NamedType("String".to_owned()))),
+            default_value: None,
+            directives: vec![],
+        }],
+        locations: vec![DirectiveLocation::Object],
+    });
+
+    let count = Definition::DirectiveDefinition(DirectiveDefinition {
+        position: Pos::default(),
+        description: None,
+        name: COUNT_DIRECTIVE.to_owned(),
+        arguments: vec![InputValue {
+            position: Pos::default(),
+            description: None,
+            name: "entity".to_owned(),
+            value_type: Type::NonNullType(Box::new(Type::NamedType("String".to_owned()))),
+            default_value: None,
+            directives: vec![],
+        }],
+        locations: vec![DirectiveLocation::FieldDefinition],
+    });
+
+    let derived_expression = Definition::DirectiveDefinition(DirectiveDefinition {
+        position: Pos::default(),
+        description: None,
+        name: "derivedExpression".to_owned(),
+        arguments: vec![InputValue {
+            position: Pos::default(),
+            description: None,
+            name: "expression".to_owned(),
+            value_type: Type::NonNullType(Box::new(Type::NamedType("String".to_owned()))),
+            default_value: None,
+            directives: vec![],
+        }],
+        locations: vec![DirectiveLocation::FieldDefinition],
+    });
+
+    let decimals = Definition::DirectiveDefinition(DirectiveDefinition {
+        position: Pos::default(),
+        description: None,
+        name: "decimals".to_owned(),
+        arguments: vec![InputValue {
+            position: Pos::default(),
+            description: None,
+            name: "field".to_owned(),
+            value_type: Type::NamedType("String".to_owned()),
+            default_value: None,
+            directives: vec![],
+        }],
+        locations: vec![DirectiveLocation::FieldDefinition],
+    });
+
+    let decimal = Definition::DirectiveDefinition(DirectiveDefinition {
+        position: Pos::default(),
+        description: None,
+        name: DECIMAL_DIRECTIVE.to_owned(),
+        arguments: vec![
+            InputValue {
+                position: Pos::default(),
+                description: None,
+                name: "amount".to_owned(),
+                value_type: Type::NonNullType(Box::new(Type::NamedType("String".to_owned()))),
+                default_value: None,
+                directives: vec![],
+            },
+            InputValue {
+                position: Pos::default(),
+                description: None,
+                name: "decimals".to_owned(),
+                value_type: Type::NamedType("String".to_owned()),
+                default_value: None,
+                directives: vec![],
+            },
+        ],
+        locations: vec![DirectiveLocation::FieldDefinition],
+    });
+
+    let restricted = Definition::DirectiveDefinition(DirectiveDefinition {
+        position: Pos::default(),
+        description: None,
+        name: "restricted".to_owned(),
+        arguments: vec![InputValue {
+            position: Pos::default(),
+            description: None,
+            name: "roles".to_owned(),
+            value_type: Type::NonNullType(Box::new(Type::ListType(Box::new(Type::NonNullType(
+                Box::new(Type::NamedType("String".to_owned())),
+            ))))),
+            default_value: None,
+            directives: vec![],
+        }],
+        locations: vec![
+            DirectiveLocation::Object,
+            DirectiveLocation::FieldDefinition,
+        ],
+    });
+
     schema.definitions.push(entity);
     schema.definitions.push(derived_from);
     schema.definitions.push(subgraph_id);
+    schema.definitions.push(renamed);
+    schema.definitions.push(aggregate);
+    schema.definitions.push(count);
+    schema.definitions.push(derived_expression);
+    schema.definitions.push(decimals);
+    schema.definitions.push(decimal);
+    schema.definitions.push(restricted);
 }
 
-/// Adds a global `OrderDirection` type to the schema.
+/// Adds a global `OrderDirection` type to the schema. Besides `asc` and
+/// `desc`, which put nulls last, it has values that also say where nulls go
 fn add_order_direction_enum(schema: &mut Document) {
     let typedef = TypeDefinition::Enum(EnumType {
         position: Pos::default(),
         description: None,
         name: "OrderDirection".to_string(),
         directives: vec![],
-        values: ["asc", "desc"]
-            .iter()
-            .map(|name| EnumValue {
-                position: Pos::default(),
-                description: None,
-                name: name.to_string(),
-                directives: vec![],
-            })
-            .collect(),
+        values: [
+            "asc",
+            "desc",
+            "asc_nulls_first",
+            "asc_nulls_last",
+            "desc_nulls_first",
+            "desc_nulls_last",
+        ]
+        .iter()
+        .map(|name| EnumValue {
+            position: Pos::default(),
+            description: None,
+            name: name.to_string(),
+            directives: vec![],
+        })
+        .collect(),
+    });
+    let def = Definition::TypeDefinition(typedef);
+    schema.definitions.push(def);
+}
+
+/// Adds a global `AggregateFunction` type to the schema. The `function`
+/// argument of `*_aggregate` fields accepts values of this type
+fn add_aggregate_function_enum(schema: &mut Document) {
+    let typedef = TypeDefinition::Enum(EnumType {
+        position: Pos::default(),
+        description: None,
+        name: AGGREGATE_FUNCTION_TYPE.to_string(),
+        directives: vec![],
+        values: [
+            AggregateFunction::Count,
+            AggregateFunction::Sum,
+            AggregateFunction::Min,
+            AggregateFunction::Max,
+            AggregateFunction::Avg,
+        ]
+        .iter()
+        .map(|function| EnumValue {
+            position: Pos::default(),
+            description: None,
+            name: function.as_str().to_string(),
+            directives: vec![],
+        })
+        .collect(),
     });
     let def = Definition::TypeDefinition(typedef);
     schema.definitions.push(def);
@@ -205,7 +398,7 @@ fn add_block_height_type(schema: &mut Document) {
                 position: Pos::default(),
                 description: None,
                 name: "number".to_owned(),
-                value_type: Type::NamedType("Int".to_owned()),
+                value_type: Type::NamedType("BigInt".to_owned()),
                 default_value: None,
                 directives: vec![],
             },
@@ -215,6 +408,28 @@ fn add_block_height_type(schema: &mut Document) {
     schema.definitions.push(def);
 }
 
+/// Adds a global `BlockChangedFilter` type to the schema. The
+/// `_change_block` and `_create_block` entries of `where` filters accept
+/// values of this type
+fn add_block_changed_filter_type(schema: &mut Document) {
+    let typedef = TypeDefinition::InputObject(InputObjectType {
+        position: Pos::default(),
+        description: None,
+        name: BLOCK_CHANGED_FILTER_TYPE.to_string(),
+        directives: vec![],
+        fields: vec![InputValue {
+            position: Pos::default(),
+            description: None,
+            name: "number_gte".to_owned(),
+            value_type: Type::NonNullType(Box::new(Type::NamedType("BigInt".to_owned()))),
+            default_value: None,
+            directives: vec![],
+        }],
+    });
+    let def = Definition::TypeDefinition(typedef);
+    schema.definitions.push(def);
+}
+
 /// Adds a global `_Meta_` type to the schema. The `_meta` field
 /// accepts values of this type
 fn add_meta_field_type(schema: &mut Document) {
@@ -235,8 +450,15 @@ fn add_types_for_object_types(
     object_types: &Vec<&ObjectType>,
 ) -> Result<(), APISchemaError> {
     for object_type in object_types {
-        add_order_by_type(schema, &object_type.name, &object_type.fields)?;
-        add_filter_type(schema, &object_type.name, &object_type.fields)?;
+        // Fields with a `@derivedExpression` are not stored, and can
+        // therefore not be used for sorting or filtering
+        let fields: Vec<_> = with_renamed_fields(&object_type.fields)
+            .into_iter()
+            .filter(|field| field.derived_expression().is_none())
+            .collect();
+        add_order_by_type(schema, &object_type.name, &fields)?;
+        add_filter_type(schema, &object_type.name, &filter_fields(&fields))?;
+        add_aggregate_type(schema, &object_type.name)?;
     }
     Ok(())
 }
@@ -247,12 +469,193 @@ fn add_types_for_interface_types(
     interface_types: &[&InterfaceType],
 ) -> Result<(), APISchemaError> {
     for interface_type in interface_types {
-        add_order_by_type(schema, &interface_type.name, &interface_type.fields)?;
-        add_filter_type(schema, &interface_type.name, &interface_type.fields)?;
+        let fields = with_renamed_fields(&interface_type.fields);
+        add_order_by_type(schema, &interface_type.name, &fields)?;
+        add_filter_type(schema, &interface_type.name, &filter_fields(&fields))?;
     }
     Ok(())
 }
 
+/// A copy of `field` under the name it had before it was renamed with
+/// `@renamed(from: "..")`, or `None` if the field was not renamed
+fn renamed_field(field: &Field) -> Option<Field> {
+    field.renamed_from().map(|old_name| Field {
+        position: Pos::default(),
+        description: Some(format!("Deprecated: renamed to `{}`", field.name)),
+        name: old_name.to_owned(),
+        arguments: field.arguments.clone(),
+        field_type: field.field_type.clone(),
+        directives: field
+            .directives
+            .iter()
+            .filter(|directive| directive.name != "renamed")
+            .cloned()
+            .collect(),
+    })
+}
+
+/// The `fields` together with a copy of each renamed field under its
+/// old name
+fn with_renamed_fields(fields: &[Field]) -> Vec<Field> {
+    let mut all_fields = fields.to_vec();
+    all_fields.extend(fields.iter().filter_map(renamed_field));
+    all_fields
+}
+
+/// Adds a field under the old name for every field that was renamed with
+/// `@renamed(from: "..")` so that queries written before the rename
+/// continue to work. Both names refer to the same underlying data
+fn add_renamed_fields(
+    schema: &mut Document,
+    object_types: &[&ObjectType],
+    interface_types: &[&InterfaceType],
+) {
+    for object_type in object_types {
+        let object_type = ast::get_object_type_mut(schema, &object_type.name)
+            .expect("object type from input schema is missing in API schema");
+        let renamed: Vec<_> = object_type
+            .fields
+            .iter()
+            .filter_map(renamed_field)
+            .collect();
+        object_type.fields.extend(renamed);
+    }
+    for interface_type in interface_types {
+        let interface_type = ast::get_interface_type_mut(schema, &interface_type.name)
+            .expect("interface type from input schema is missing in API schema");
+        let renamed: Vec<_> = interface_type
+            .fields
+            .iter()
+            .filter_map(renamed_field)
+            .collect();
+        interface_type.fields.extend(renamed);
+    }
+}
+
+/// The `<name>Decimal` field for `field` if it has a `@decimals` directive.
+/// The field takes the number of decimals as an argument, which defaults to
+/// the value of the sibling field named in `@decimals(field: "..")`
+fn decimal_field(field: &Field) -> Option<Field> {
+    if !field.has_decimals() {
+        return None;
+    }
+    let decimals_field = field.decimals_field();
+    let decimals_type = Type::NamedType("Int".to_owned());
+    let (decimals_type, description) = match decimals_field {
+        Some(decimals_field) => (
+            decimals_type,
+            format!(
+                "`{}` with the number of decimals given by `{}` unless overridden",
+                field.name, decimals_field
+            ),
+        ),
+        None => (
+            Type::NonNullType(Box::new(decimals_type)),
+            format!("`{}` with the given number of decimals", field.name),
+        ),
+    };
+    let mut arguments = vec![("amount".to_owned(), Value::String(field.name.to_owned()))];
+    if let Some(decimals_field) = decimals_field {
+        arguments.push((
+            "decimals".to_owned(),
+            Value::String(decimals_field.to_owned()),
+        ));
+    }
+    Some(Field {
+        position: Pos::default(),
+        description: Some(description),
+        name: format!("{}Decimal", field.name),
+        arguments: vec![InputValue {
+            position: Pos::default(),
+            description: None,
+            name: "decimals".to_owned(),
+            value_type: decimals_type,
+            default_value: None,
+            directives: vec![],
+        }],
+        field_type: Type::NamedType("BigDecimal".to_owned()),
+        directives: vec![Directive {
+            position: Pos::default(),
+            name: DECIMAL_DIRECTIVE.to_owned(),
+            arguments,
+        }],
+    })
+}
+
+/// Adds a `<name>Decimal` field for every `BigInt` field with a `@decimals`
+/// directive. The value of these fields is computed at query time by
+/// dividing the amount by the appropriate power of ten
+fn add_decimal_fields(
+    schema: &mut Document,
+    object_types: &[&ObjectType],
+    interface_types: &[&InterfaceType],
+) {
+    for object_type in object_types {
+        let object_type = ast::get_object_type_mut(schema, &object_type.name)
+            .expect("object type from input schema is missing in API schema");
+        let decimals: Vec<_> = object_type
+            .fields
+            .iter()
+            .filter_map(decimal_field)
+            .collect();
+        object_type.fields.extend(decimals);
+    }
+    for interface_type in interface_types {
+        let interface_type = ast::get_interface_type_mut(schema, &interface_type.name)
+            .expect("interface type from input schema is missing in API schema");
+        let decimals: Vec<_> = interface_type
+            .fields
+            .iter()
+            .filter_map(decimal_field)
+            .collect();
+        interface_type.fields.extend(decimals);
+    }
+}
+
+/// The fields for which `*_filter` input values are generated; these are the
+/// fields of the type plus `_lastUpdatedBlock` if that is enabled
+fn filter_fields(fields: &[Field]) -> Vec<Field> {
+    let mut fields = fields.to_vec();
+    if *LAST_UPDATED_BLOCK_ENABLED {
+        fields.push(last_updated_block_field());
+    }
+    fields
+}
+
+/// The `_lastUpdatedBlock` field. Like all block numbers in the API, it is
+/// a `BigInt` since block numbers do not necessarily fit into an `Int`
+fn last_updated_block_field() -> Field {
+    Field {
+        position: Pos::default(),
+        description: Some("The block at which this entity was last changed".to_string()),
+        name: LAST_UPDATED_BLOCK_FIELD.to_string(),
+        arguments: vec![],
+        field_type: Type::NonNullType(Box::new(Type::NamedType("BigInt".to_string()))),
+        directives: vec![],
+    }
+}
+
+/// Adds the `_lastUpdatedBlock` system field to all entity types and
+/// interfaces
+fn add_last_updated_block_fields(
+    schema: &mut Document,
+    object_types: &[&ObjectType],
+    interface_types: &[&InterfaceType],
+) {
+    for object_type in object_types {
+        ast::get_object_type_mut(schema, &object_type.name)
+            .expect("object type from input schema is missing in API schema")
+            .fields
+            .push(last_updated_block_field());
+    }
+    for interface_type in interface_types {
+        ast::get_interface_type_mut(schema, &interface_type.name)
+            .expect("interface type from input schema is missing in API schema")
+            .fields
+            .push(last_updated_block_field());
+    }
+}
+
 /// Adds a `<type_name>_orderBy` enum type for the given fields to the schema.
 fn add_order_by_type(
     schema: &mut Document,
@@ -296,7 +699,7 @@ fn add_filter_type(
     let filter_type_name = format!("{}_filter", type_name).to_string();
     match ast::get_named_type(schema, &filter_type_name) {
         None => {
-            let input_values = field_input_values(schema, fields)?;
+            let mut input_values = field_input_values(schema, fields)?;
 
             // Don't generate an input object with no fields, this makes the JS
             // graphql library, which graphiql uses, very confused and graphiql
@@ -305,12 +708,22 @@ fn add_filter_type(
             if input_values.is_empty() {
                 return Ok(());
             }
+            for name in &[CHANGE_BLOCK_FILTER_FIELD, CREATE_BLOCK_FILTER_FIELD] {
+                input_values.push(InputValue {
+                    position: Pos::default(),
+                    description: None,
+                    name: name.to_string(),
+                    value_type: Type::NamedType(BLOCK_CHANGED_FILTER_TYPE.to_owned()),
+                    default_value: None,
+                    directives: vec![],
+                });
+            }
             let typedef = TypeDefinition::InputObject(InputObjectType {
                 position: Pos::default(),
                 description: None,
                 name: filter_type_name,
                 directives: vec![],
-                fields: field_input_values(schema, fields)?,
+                fields: input_values,
             });
             let def = Definition::TypeDefinition(typedef);
             schema.definitions.push(def);
@@ -321,6 +734,45 @@ fn add_filter_type(
     Ok(())
 }
 
+/// Adds a `<type_name>_aggregate` object type for the results of
+/// aggregating entities of type `type_name` to the schema. Since the type
+/// of the values depends on the function and the attribute that is
+/// aggregated, the `group` and the `value` are returned as strings
+fn add_aggregate_type(schema: &mut Document, type_name: &String) -> Result<(), APISchemaError> {
+    let aggregate_type_name = format!("{}_aggregate", type_name);
+    match ast::get_named_type(schema, &aggregate_type_name) {
+        None => {
+            let field = |name: &str, description: &str| Field {
+                position: Pos::default(),
+                description: Some(description.to_string()),
+                name: name.to_string(),
+                arguments: vec![],
+                field_type: Type::NamedType("String".to_string()),
+                directives: vec![],
+            };
+            let typedef = TypeDefinition::Object(ObjectType {
+                position: Pos::default(),
+                description: None,
+                name: aggregate_type_name,
+                implements_interfaces: vec![],
+                directives: vec![Directive {
+                    position: Pos::default(),
+                    name: AGGREGATE_DIRECTIVE.to_string(),
+                    arguments: vec![("entity".to_string(), Value::String(type_name.to_owned()))],
+                }],
+                fields: vec![
+                    field("group", "The value of the `groupBy` attribute"),
+                    field("value", "The aggregated value"),
+                ],
+            });
+            let def = Definition::TypeDefinition(typedef);
+            schema.definitions.push(def);
+        }
+        Some(_) => return Err(APISchemaError::TypeExists(aggregate_type_name)),
+    }
+    Ok(())
+}
+
 /// Generates `*_filter` input values for the given set of fields.
 fn field_input_values(
     schema: &Document,
@@ -333,6 +785,30 @@ fn field_input_values(
             &field,
             &field.field_type,
         )?);
+        // Nullable fields can be filtered by whether they are set, except
+        // for derived fields, which are never stored
+        if !matches!(field.field_type, Type::NonNullType(_))
+            && ast::get_derived_from_directive(field).is_none()
+        {
+            input_values.push(input_value(
+                &field.name,
+                "is_null",
+                Type::NamedType("Boolean".to_owned()),
+            ));
+        }
+        // Fields that refer to entities of an object type can be filtered
+        // by conditions on those entities with `<field>_: { .. }`
+        let child_type = ast::get_field_name(&field.field_type);
+        if let Some(TypeDefinition::Object(_)) = ast::get_named_type(schema, &child_type) {
+            input_values.push(InputValue {
+                position: Pos::default(),
+                description: None,
+                name: format!("{}_", field.name),
+                value_type: Type::NamedType(format!("{}_filter", child_type)),
+                default_value: None,
+                directives: vec![],
+            });
+        }
     }
     Ok(input_values)
 }
@@ -405,6 +881,14 @@ fn field_scalar_filter_input_values(
             "not_starts_with",
             "ends_with",
             "not_ends_with",
+            "nocase",
+            "not_nocase",
+            "contains_nocase",
+            "not_contains_nocase",
+            "starts_with_nocase",
+            "not_starts_with_nocase",
+            "ends_with_nocase",
+            "not_ends_with_nocase",
         ],
         _ => vec!["", "not"],
     }
@@ -479,6 +963,13 @@ fn field_list_filter_input_values(
                         )))),
                     )
                 })
+                .chain(
+                    vec!["length", "length_gt", "length_lt"]
+                        .into_iter()
+                        .map(|filter_type| {
+                            input_value(&field.name, filter_type, Type::NamedType("Int".to_owned()))
+                        }),
+                )
                 .collect(),
         )
     })
@@ -519,6 +1010,18 @@ fn add_query_type(
         .chain(interface_types.iter().map(|t| &t.name))
         .flat_map(|name| query_fields_for_type(schema, name, features))
         .collect::<Vec<Field>>();
+    let mut aggregate_fields = object_types
+        .iter()
+        .map(|t| aggregate_field_for_type(schema, &t.name, features))
+        .collect();
+    fields.append(&mut aggregate_fields);
+    let mut count_fields = object_types
+        .iter()
+        .map(|t| &t.name)
+        .chain(interface_types.iter().map(|t| &t.name))
+        .map(|name| count_field_for_type(schema, name, features))
+        .collect();
+    fields.append(&mut count_fields);
     let mut fulltext_fields = schema
         .get_fulltext_directives()
         .map_err(|_| APISchemaError::FulltextSearchNonDeterministic)?
@@ -541,6 +1044,134 @@ fn add_query_type(
     Ok(())
 }
 
+/// Adds what Apollo Federation needs to use the API as a subgraph of a
+/// supergraph: a `@key(fields: "id")` directive on every entity type that
+/// is not restricted, the `_Any` scalar, the `_Entity` union of these
+/// types, the `_Service` type, and the `_service` and `_entities` fields
+/// on `Query`
+fn add_federation_types(
+    schema: &mut Document,
+    object_types: &[&ObjectType],
+) -> Result<(), APISchemaError> {
+    for name in &[ANY_SCALAR_TYPE, SERVICE_FIELD_TYPE, ENTITY_UNION_TYPE] {
+        if ast::get_named_type(schema, &name.to_string()).is_some() {
+            return Err(APISchemaError::TypeExists(name.to_string()));
+        }
+    }
+
+    schema
+        .definitions
+        .push(Definition::DirectiveDefinition(DirectiveDefinition {
+            position: Pos::default(),
+            description: None,
+            name: KEY_DIRECTIVE.to_owned(),
+            arguments: vec![InputValue {
+                position: Pos::default(),
+                description: None,
+                name: "fields".to_owned(),
+                value_type: Type::NonNullType(Box::new(Type::NamedType("String".to_owned()))),
+                default_value: None,
+                directives: vec![],
+            }],
+            locations: vec![DirectiveLocation::Object, DirectiveLocation::Interface],
+        }));
+    schema
+        .definitions
+        .push(Definition::TypeDefinition(TypeDefinition::Scalar(
+            ScalarType {
+                position: Pos::default(),
+                description: None,
+                name: ANY_SCALAR_TYPE.to_owned(),
+                directives: vec![],
+            },
+        )));
+    schema
+        .definitions
+        .push(Definition::TypeDefinition(TypeDefinition::Object(
+            ObjectType {
+                position: Pos::default(),
+                description: None,
+                name: SERVICE_FIELD_TYPE.to_owned(),
+                implements_interfaces: vec![],
+                directives: vec![],
+                fields: vec![Field {
+                    position: Pos::default(),
+                    description: Some("The schema of this subgraph for the gateway".to_owned()),
+                    name: "sdl".to_owned(),
+                    arguments: vec![],
+                    field_type: Type::NamedType("String".to_owned()),
+                    directives: vec![],
+                }],
+            },
+        )));
+
+    let mut entity_types = vec![];
+    for object_type in object_types {
+        if object_type.restricted_roles().is_some() {
+            continue;
+        }
+        ast::get_object_type_mut(schema, &object_type.name)
+            .expect("object type from input schema is missing in API schema")
+            .directives
+            .push(Directive {
+                position: Pos::default(),
+                name: KEY_DIRECTIVE.to_owned(),
+                arguments: vec![("fields".to_owned(), Value::String("id".to_owned()))],
+            });
+        entity_types.push(object_type.name.clone());
+    }
+
+    let mut fields = vec![Field {
+        position: Pos::default(),
+        description: Some("The schema of this subgraph for Apollo Federation".to_owned()),
+        name: SERVICE_FIELD_NAME.to_owned(),
+        arguments: vec![],
+        field_type: Type::NonNullType(Box::new(Type::NamedType(SERVICE_FIELD_TYPE.to_owned()))),
+        directives: vec![],
+    }];
+    // A union needs at least one member
+    if !entity_types.is_empty() {
+        schema
+            .definitions
+            .push(Definition::TypeDefinition(TypeDefinition::Union(
+                UnionType {
+                    position: Pos::default(),
+                    description: None,
+                    name: ENTITY_UNION_TYPE.to_owned(),
+                    directives: vec![],
+                    types: entity_types,
+                },
+            )));
+        fields.push(Field {
+            position: Pos::default(),
+            description: Some(
+                "Look up entities by their `__typename` and `id` for Apollo Federation".to_owned(),
+            ),
+            name: ENTITIES_FIELD_NAME.to_owned(),
+            arguments: vec![InputValue {
+                position: Pos::default(),
+                description: None,
+                name: "representations".to_owned(),
+                value_type: Type::NonNullType(Box::new(Type::ListType(Box::new(
+                    Type::NonNullType(Box::new(Type::NamedType(ANY_SCALAR_TYPE.to_owned()))),
+                )))),
+                default_value: None,
+                directives: vec![],
+            }],
+            field_type: Type::NonNullType(Box::new(Type::ListType(Box::new(Type::NamedType(
+                ENTITY_UNION_TYPE.to_owned(),
+            ))))),
+            directives: vec![],
+        });
+    }
+
+    ast::get_object_type_mut(schema, &"Query".to_owned())
+        .ok_or_else(|| APISchemaError::TypeNotFound("Query".to_owned()))?
+        .fields
+        .extend(fields);
+    Ok(())
+}
+
 fn query_field_for_fulltext(
     fulltext: &Directive,
     features: &BTreeSet<SubgraphFeature>,
@@ -720,6 +1351,90 @@ fn query_fields_for_type(
     ]
 }
 
+/// The `<plural>_aggregate` field on `Query` for the object type
+/// `type_name`
+fn aggregate_field_for_type(
+    schema: &Document,
+    type_name: &String,
+    features: &BTreeSet<SubgraphFeature>,
+) -> Field {
+    let input_objects = ast::get_input_object_definitions(schema);
+    let attribute_type = Type::NamedType(format!("{}_orderBy", type_name));
+
+    let mut arguments = vec![
+        input_value(
+            &"function".to_string(),
+            "",
+            Type::NonNullType(Box::new(Type::NamedType(
+                AGGREGATE_FUNCTION_TYPE.to_string(),
+            ))),
+        ),
+        input_value(&"field".to_string(), "", attribute_type.clone()),
+        input_value(&"groupBy".to_string(), "", attribute_type),
+    ];
+    let filter_name = format!("{}_filter", type_name);
+    if input_objects.iter().any(|o| o.name == filter_name) {
+        arguments.push(input_value(
+            &"where".to_string(),
+            "",
+            Type::NamedType(filter_name),
+        ));
+    }
+    arguments.push(block_argument());
+    if features.contains(&SubgraphFeature::nonFatalErrors) {
+        arguments.push(subgraph_error_argument());
+    }
+
+    Field {
+        position: Pos::default(),
+        description: None,
+        name: format!("{}_aggregate", type_name.to_plural().to_camel_case()),
+        arguments,
+        field_type: Type::NonNullType(Box::new(Type::ListType(Box::new(Type::NonNullType(
+            Box::new(Type::NamedType(format!("{}_aggregate", type_name))),
+        ))))),
+        directives: vec![],
+    }
+}
+
+/// The `<plural>Count` field on `Query` for the object or interface type
+/// `type_name`. It returns the number of entities that match the filter in
+/// `where`
+fn count_field_for_type(
+    schema: &Document,
+    type_name: &String,
+    features: &BTreeSet<SubgraphFeature>,
+) -> Field {
+    let input_objects = ast::get_input_object_definitions(schema);
+
+    let mut arguments = vec![];
+    let filter_name = format!("{}_filter", type_name);
+    if input_objects.iter().any(|o| o.name == filter_name) {
+        arguments.push(input_value(
+            &"where".to_string(),
+            "",
+            Type::NamedType(filter_name),
+        ));
+    }
+    arguments.push(block_argument());
+    if features.contains(&SubgraphFeature::nonFatalErrors) {
+        arguments.push(subgraph_error_argument());
+    }
+
+    Field {
+        position: Pos::default(),
+        description: None,
+        name: format!("{}Count", type_name.to_plural().to_camel_case()),
+        arguments,
+        field_type: Type::NonNullType(Box::new(Type::NamedType("BigInt".to_string()))),
+        directives: vec![Directive {
+            position: Pos::default(),
+            name: COUNT_DIRECTIVE.to_string(),
+            arguments: vec![("entity".to_string(), Value::String(type_name.to_owned()))],
+        }],
+    }
+}
+
 fn meta_field() -> Field {
     lazy_static! {
         static ref META_FIELD: Field = Field {
@@ -873,7 +1588,7 @@ mod tests {
     use graph::data::subgraph::SubgraphFeature;
     use graphql_parser::schema::*;
 
-    use super::api_schema;
+    use super::{add_federation_types, api_schema};
     use crate::schema::ast;
 
     #[test]
@@ -909,8 +1624,22 @@ mod tests {
         }
         .expect("OrderDirection type is not an enum");
 
-        let values: Vec<&String> = enum_type.values.iter().map(|value| &value.name).collect();
-        assert_eq!(values, [&"asc".to_string(), &"desc".to_string()]);
+        let values: Vec<&str> = enum_type
+            .values
+            .iter()
+            .map(|value| value.name.as_str())
+            .collect();
+        assert_eq!(
+            values,
+            [
+                "asc",
+                "desc",
+                "asc_nulls_first",
+                "asc_nulls_last",
+                "desc_nulls_first",
+                "desc_nulls_last"
+            ]
+        );
     }
 
     #[test]
@@ -946,6 +1675,127 @@ mod tests {
         assert_eq!(values, [&"id".to_string(), &"name".to_string()]);
     }
 
+    #[test]
+    fn api_schema_contains_renamed_fields() {
+        let input_schema =
+            parse_schema(r#"type User { id: ID!, fullName: String! @renamed(from: "name") }"#)
+                .expect("Failed to parse input schema");
+        let schema =
+            api_schema(&input_schema, &BTreeSet::new()).expect("Failed to derive API schema");
+
+        let user_type = ast::get_object_type_definitions(&schema)
+            .into_iter()
+            .find(|t| t.name == "User")
+            .expect("User type is missing in derived API schema");
+        let name = ast::get_field(user_type, &"name".to_string())
+            .expect("renamed field is missing under its old name");
+        assert_eq!(
+            name.field_type,
+            Type::NonNullType(Box::new(Type::NamedType("String".to_string())))
+        );
+        assert!(name.directives.is_empty());
+
+        let user_order_by = ast::get_named_type(&schema, &"User_orderBy".to_string())
+            .expect("User_orderBy type is missing in derived API schema");
+        let values: Vec<_> = match user_order_by {
+            TypeDefinition::Enum(t) => t.values.iter().map(|value| value.name.as_str()).collect(),
+            _ => panic!("User_orderBy type is not an enum"),
+        };
+        assert_eq!(values, ["id", "fullName", "name"]);
+
+        let user_filter = ast::get_named_type(&schema, &"User_filter".to_string())
+            .expect("User_filter type is missing in derived API schema");
+        match user_filter {
+            TypeDefinition::InputObject(t) => {
+                assert!(t
+                    .fields
+                    .iter()
+                    .any(|field| field.name == "name_starts_with"))
+            }
+            _ => panic!("User_filter type is not an input object"),
+        }
+    }
+
+    #[test]
+    fn api_schema_contains_decimal_fields() {
+        let input_schema = parse_schema(
+            r#"type Transfer {
+                 id: ID!
+                 amount: BigInt! @decimals(field: "decimals")
+                 fee: BigInt @decimals
+                 decimals: Int!
+               }"#,
+        )
+        .expect("Failed to parse input schema");
+        let schema =
+            api_schema(&input_schema, &BTreeSet::new()).expect("Failed to derive API schema");
+
+        let transfer_type = ast::get_object_type_definitions(&schema)
+            .into_iter()
+            .find(|t| t.name == "Transfer")
+            .expect("Transfer type is missing in derived API schema");
+
+        let amount = ast::get_field(transfer_type, &"amountDecimal".to_string())
+            .expect("amountDecimal field is missing");
+        assert_eq!(amount.field_type, Type::NamedType("BigDecimal".to_string()));
+        assert_eq!(
+            amount.arguments[0].value_type,
+            Type::NamedType("Int".to_string())
+        );
+        assert_eq!(
+            amount.directives[0].arguments,
+            vec![
+                ("amount".to_string(), Value::String("amount".to_string())),
+                (
+                    "decimals".to_string(),
+                    Value::String("decimals".to_string())
+                )
+            ]
+        );
+
+        // Without a sibling field, the number of decimals must be passed
+        // in the query
+        let fee = ast::get_field(transfer_type, &"feeDecimal".to_string())
+            .expect("feeDecimal field is missing");
+        assert_eq!(
+            fee.arguments[0].value_type,
+            Type::NonNullType(Box::new(Type::NamedType("Int".to_string())))
+        );
+
+        assert!(ast::get_field(transfer_type, &"decimalsDecimal".to_string()).is_none());
+    }
+
+    #[test]
+    fn api_schema_does_not_order_or_filter_by_derived_expressions() {
+        let input_schema = parse_schema(
+            r#"type Item {
+                 id: ID!
+                 price: BigInt!
+                 total: BigInt! @derivedExpression(expression: "price * 2")
+               }"#,
+        )
+        .expect("Failed to parse input schema");
+        let schema =
+            api_schema(&input_schema, &BTreeSet::new()).expect("Failed to derive API schema");
+
+        let item_order_by = ast::get_named_type(&schema, &"Item_orderBy".to_string())
+            .expect("Item_orderBy type is missing in derived API schema");
+        let values: Vec<_> = match item_order_by {
+            TypeDefinition::Enum(t) => t.values.iter().map(|value| value.name.as_str()).collect(),
+            _ => panic!("Item_orderBy type is not an enum"),
+        };
+        assert_eq!(values, ["id", "price"]);
+
+        let item_filter = ast::get_named_type(&schema, &"Item_filter".to_string())
+            .expect("Item_filter type is missing in derived API schema");
+        match item_filter {
+            TypeDefinition::InputObject(t) => {
+                assert!(!t.fields.iter().any(|field| field.name.starts_with("total")))
+            }
+            _ => panic!("Item_filter type is not an input object"),
+        }
+    }
+
     #[test]
     fn api_schema_contains_object_type_filter_enum() {
         let input_schema = parse_schema(
@@ -1010,14 +1860,30 @@ mod tests {
                 "name_not_starts_with",
                 "name_ends_with",
                 "name_not_ends_with",
+                "name_nocase",
+                "name_not_nocase",
+                "name_contains_nocase",
+                "name_not_contains_nocase",
+                "name_starts_with_nocase",
+                "name_not_starts_with_nocase",
+                "name_ends_with_nocase",
+                "name_not_ends_with_nocase",
                 "favoritePetNames",
                 "favoritePetNames_not",
                 "favoritePetNames_contains",
                 "favoritePetNames_not_contains",
+                "favoritePetNames_length",
+                "favoritePetNames_length_gt",
+                "favoritePetNames_length_lt",
+                "favoritePetNames_is_null",
                 "pets",
                 "pets_not",
                 "pets_contains",
                 "pets_not_contains",
+                "pets_length",
+                "pets_length_gt",
+                "pets_length_lt",
+                "pets_",
                 "favoritePet",
                 "favoritePet_not",
                 "favoritePet_gt",
@@ -1032,6 +1898,19 @@ mod tests {
                 "favoritePet_not_starts_with",
                 "favoritePet_ends_with",
                 "favoritePet_not_ends_with",
+                "favoritePet_nocase",
+                "favoritePet_not_nocase",
+                "favoritePet_contains_nocase",
+                "favoritePet_not_contains_nocase",
+                "favoritePet_starts_with_nocase",
+                "favoritePet_not_starts_with_nocase",
+                "favoritePet_ends_with_nocase",
+                "favoritePet_not_ends_with_nocase",
+                "favoritePet_",
+                "leastFavoritePet_",
+                "mostFavoritePets_",
+                "_change_block",
+                "_create_block",
             ]
             .iter()
             .map(|name| name.to_string())
@@ -1241,4 +2120,140 @@ type Gravatar @entity {
         }
         .expect("\"metadata\" field is missing on Query type");
     }
+
+    #[test]
+    fn api_schema_contains_aggregate_fields_on_query_type() {
+        let input_schema = parse_schema(
+            "interface Node { id: ID! } type User implements Node { id: ID!, age: Int! }",
+        )
+        .expect("Failed to parse input schema");
+        let schema =
+            api_schema(&input_schema, &BTreeSet::new()).expect("Failed to derive API schema");
+
+        let query_type = ast::get_named_type(&schema, &"Query".to_string())
+            .expect("Query type is missing in derived API schema");
+        let aggregate_field = match query_type {
+            TypeDefinition::Object(t) => ast::get_field(t, &"users_aggregate".to_string()),
+            _ => None,
+        }
+        .expect("\"users_aggregate\" field is missing on Query type");
+
+        assert_eq!(
+            aggregate_field.field_type,
+            Type::NonNullType(Box::new(Type::ListType(Box::new(Type::NonNullType(
+                Box::new(Type::NamedType("User_aggregate".to_string()))
+            )))))
+        );
+        assert_eq!(
+            aggregate_field
+                .arguments
+                .iter()
+                .map(|input_value| input_value.name.to_owned())
+                .collect::<Vec<String>>(),
+            ["function", "field", "groupBy", "where", "block"]
+                .iter()
+                .map(|name| name.to_string())
+                .collect::<Vec<String>>()
+        );
+
+        match ast::get_named_type(&schema, &"User_aggregate".to_string()) {
+            Some(TypeDefinition::Object(t)) => {
+                assert_eq!(
+                    t.fields
+                        .iter()
+                        .map(|field| field.name.as_str())
+                        .collect::<Vec<_>>(),
+                    vec!["group", "value"]
+                );
+            }
+            _ => panic!("\"User_aggregate\" type is missing in derived API schema"),
+        }
+    }
+
+    #[test]
+    fn api_schema_contains_count_fields_on_query_type() {
+        let input_schema = parse_schema(
+            "interface Node { id: ID! } type User implements Node { id: ID!, age: Int! }",
+        )
+        .expect("Failed to parse input schema");
+        let schema =
+            api_schema(&input_schema, &BTreeSet::new()).expect("Failed to derive API schema");
+
+        let query_type = ast::get_named_type(&schema, &"Query".to_string())
+            .expect("Query type is missing in derived API schema");
+        let count_field = match query_type {
+            TypeDefinition::Object(t) => ast::get_field(t, &"usersCount".to_string()),
+            _ => None,
+        }
+        .expect("\"usersCount\" field is missing on Query type");
+
+        assert_eq!(
+            count_field.field_type,
+            Type::NonNullType(Box::new(Type::NamedType("BigInt".to_string())))
+        );
+        assert_eq!(
+            count_field
+                .arguments
+                .iter()
+                .map(|input_value| input_value.name.to_owned())
+                .collect::<Vec<String>>(),
+            ["where", "block"]
+                .iter()
+                .map(|name| name.to_string())
+                .collect::<Vec<String>>()
+        );
+
+        match query_type {
+            TypeDefinition::Object(t) => ast::get_field(t, &"nodesCount".to_string()),
+            _ => None,
+        }
+        .expect("\"nodesCount\" field is missing on Query type");
+
+        // Interfaces can not be aggregated
+        match query_type {
+            TypeDefinition::Object(t) => {
+                assert!(ast::get_field(t, &"nodes_aggregate".to_string()).is_none())
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn federation_types() {
+        let input_schema = parse_schema(
+            "type User @entity { id: ID!, name: String! } \
+             type Secret @entity @restricted(roles: [\"admin\"]) { id: ID! }",
+        )
+        .expect("Failed to parse input schema");
+        let mut schema =
+            api_schema(&input_schema, &BTreeSet::new()).expect("Failed to derive API schema");
+        let object_types = ast::get_object_type_definitions(&input_schema);
+        add_federation_types(&mut schema, &object_types).expect("Failed to add federation types");
+
+        let query_type = ast::get_object_type_mut(&mut schema, &"Query".to_string())
+            .expect("Query type is missing in derived API schema")
+            .clone();
+        assert!(ast::get_field(&query_type, &"_service".to_string()).is_some());
+        assert!(ast::get_field(&query_type, &"_entities".to_string()).is_some());
+
+        // Restricted types can not be looked up
+        match ast::get_named_type(&schema, &"_Entity".to_string()) {
+            Some(TypeDefinition::Union(union)) => assert_eq!(vec!["User"], union.types),
+            _ => panic!("\"_Entity\" union is missing"),
+        }
+        let user = ast::get_object_type_mut(&mut schema, &"User".to_string()).unwrap();
+        assert!(user
+            .directives
+            .iter()
+            .any(|directive| directive.name == "key"));
+
+        // The SDL for the gateway leaves out the fields and types that are
+        // only there for federation
+        let sdl = crate::execution::service_sdl(&schema);
+        assert!(sdl.contains("type User @entity @key(fields: \"id\")"));
+        assert!(sdl.contains("users("));
+        assert!(!sdl.contains("_entities"));
+        assert!(!sdl.contains("_Service"));
+        assert!(!sdl.contains("type Subscription"));
+    }
 }
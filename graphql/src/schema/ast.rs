@@ -25,11 +25,45 @@ pub(crate) enum FilterOp {
     EndsWith,
     NotEndsWith,
     Equal,
+    EqualNoCase,
+    NotEqualNoCase,
+    ContainsNoCase,
+    NotContainsNoCase,
+    StartsWithNoCase,
+    NotStartsWithNoCase,
+    EndsWithNoCase,
+    NotEndsWithNoCase,
+    IsNull,
+    Child,
+    LengthEqual,
+    LengthGreaterThan,
+    LengthLessThan,
 }
 
 /// Split a "name_eq" style name into an attribute ("name") and a filter op (`Equal`).
 pub(crate) fn parse_field_as_filter(key: &String) -> (String, FilterOp) {
     let (suffix, op) = match key {
+        k if k.ends_with("_is_null") => ("_is_null", FilterOp::IsNull),
+        k if k.ends_with("_length_gt") => ("_length_gt", FilterOp::LengthGreaterThan),
+        k if k.ends_with("_length_lt") => ("_length_lt", FilterOp::LengthLessThan),
+        k if k.ends_with("_length") => ("_length", FilterOp::LengthEqual),
+        k if k.ends_with("_") => ("_", FilterOp::Child),
+        k if k.ends_with("_not_contains_nocase") => {
+            ("_not_contains_nocase", FilterOp::NotContainsNoCase)
+        }
+        k if k.ends_with("_contains_nocase") => ("_contains_nocase", FilterOp::ContainsNoCase),
+        k if k.ends_with("_not_starts_with_nocase") => {
+            ("_not_starts_with_nocase", FilterOp::NotStartsWithNoCase)
+        }
+        k if k.ends_with("_not_ends_with_nocase") => {
+            ("_not_ends_with_nocase", FilterOp::NotEndsWithNoCase)
+        }
+        k if k.ends_with("_starts_with_nocase") => {
+            ("_starts_with_nocase", FilterOp::StartsWithNoCase)
+        }
+        k if k.ends_with("_ends_with_nocase") => ("_ends_with_nocase", FilterOp::EndsWithNoCase),
+        k if k.ends_with("_not_nocase") => ("_not_nocase", FilterOp::NotEqualNoCase),
+        k if k.ends_with("_nocase") => ("_nocase", FilterOp::EqualNoCase),
         k if k.ends_with("_not") => ("_not", FilterOp::Not),
         k if k.ends_with("_gt") => ("_gt", FilterOp::GreaterThan),
         k if k.ends_with("_lt") => ("_lt", FilterOp::LessThan),
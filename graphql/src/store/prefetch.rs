@@ -4,22 +4,26 @@
 use anyhow::{anyhow, Error};
 use indexmap::IndexMap;
 use lazy_static::lazy_static;
-use std::collections::{BTreeMap, HashMap, HashSet};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::iter::once;
 use std::rc::Rc;
+use std::str::FromStr;
 use std::time::Instant;
 
 use graph::data::graphql::*;
 use graph::prelude::{
-    q, s, ApiSchema, BlockNumber, ChildMultiplicity, EntityCollection, EntityFilter, EntityLink,
-    EntityOrder, EntityWindow, Logger, ParentLink, QueryExecutionError, QueryStore,
-    Value as StoreValue, WindowAttribute,
+    q, s, ApiSchema, BigDecimal, BigInt, BlockNumber, ChildMultiplicity, EntityCollection,
+    EntityFilter, EntityLink, EntityOrder, EntityWindow, Logger, ParentLink, QueryExecutionError,
+    QueryStore, Value as StoreValue, WindowAttribute,
 };
 
 use crate::execution::{ExecutionContext, Resolver};
 use crate::query::ast as qast;
-use crate::schema::ast as sast;
-use crate::store::{build_query, StoreResolver};
+use crate::schema::{
+    api::{AGGREGATE_DIRECTIVE, COUNT_DIRECTIVE, DECIMAL_DIRECTIVE},
+    ast as sast,
+};
+use crate::store::{build_aggregation_query, build_query, StoreResolver};
 
 lazy_static! {
     static ref ARG_FIRST: String = String::from("first");
@@ -424,9 +428,9 @@ pub fn run(
     ctx: &ExecutionContext<impl Resolver>,
     selection_set: &q::SelectionSet,
 ) -> Result<q::Value, Vec<QueryExecutionError>> {
+    let counts = execute_counts(resolver, ctx, selection_set)?;
     execute_root_selection_set(resolver, ctx, selection_set).map(|nodes| {
-        let map = BTreeMap::default();
-        q::Value::Object(nodes.into_iter().fold(map, |mut map, node| {
+        q::Value::Object(nodes.into_iter().fold(counts, |mut map, node| {
             // For root nodes, we only care about the children
             for (key, nodes) in node.children.into_iter() {
                 map.insert(format!("prefetch:{}", key), node_list_as_value(nodes));
@@ -436,6 +440,74 @@ pub fn run(
     })
 }
 
+/// Compute the `<plural>Count` fields in the root selection set. The
+/// result maps `prefetch:<response key>` to the count for each field
+fn execute_counts(
+    resolver: &StoreResolver,
+    ctx: &ExecutionContext<impl Resolver>,
+    selection_set: &q::SelectionSet,
+) -> Result<BTreeMap<String, q::Value>, Vec<QueryExecutionError>> {
+    let schema = &ctx.query.schema;
+    let query_type = schema.query_type.as_ref();
+
+    let mut counts = BTreeMap::new();
+    let fields = selection_set
+        .items
+        .iter()
+        .filter(|selection| !qast::skip_selection(selection, &ctx.query.variables))
+        .filter(|selection| qast::include_selection(selection, &ctx.query.variables))
+        .filter_map(|selection| match selection {
+            q::Selection::Field(field) => Some(field),
+            _ => None,
+        });
+    for field in fields {
+        let entity = match query_type
+            .field(&field.name)
+            .and_then(|field_def| counted_entity(schema.document(), field_def))
+        {
+            Some(entity) => entity,
+            None => continue,
+        };
+        let argument_values =
+            crate::execution::coerce_argument_values(&ctx.query, query_type.into(), field)?;
+        let query = build_query(
+            entity,
+            resolver.block_number(),
+            &argument_values,
+            schema.document(),
+            schema.types_for_interface(),
+            ctx.max_first,
+            ctx.max_skip,
+        )
+        .map_err(|e| vec![e])?;
+        let count = resolver.store.count(query).map_err(|e| vec![e])?;
+        counts.insert(
+            format!("prefetch:{}", qast::get_response_key(field)),
+            q::Value::String(count.to_string()),
+        );
+    }
+    Ok(counts)
+}
+
+/// The entity type that `field` counts, if it is one of the
+/// `<plural>Count` fields on `Query`
+fn counted_entity<'a>(schema: &'a s::Document, field: &s::Field) -> Option<ObjectOrInterface<'a>> {
+    field
+        .directives
+        .iter()
+        .find(|directive| directive.name == COUNT_DIRECTIVE)
+        .and_then(|directive| {
+            directive
+                .arguments
+                .iter()
+                .find(|(name, _)| name == "entity")
+        })
+        .and_then(|(_, value)| match value {
+            s::Value::String(entity) => schema.object_or_interface(entity),
+            _ => None,
+        })
+}
+
 /// Executes the root selection set of a query.
 fn execute_root_selection_set(
     resolver: &StoreResolver,
@@ -487,11 +559,40 @@ fn execute_selection_set<'a>(
             // Unwrap: The query was validated to contain only valid fields,
             // and `collect_fields` will skip introspection fields.
             let field = type_cond.field(&fields[0].name).unwrap();
+
+            // Decimal fields are computed from other fields of the parents,
+            // there is nothing to fetch for them
+            if let Some((amount, decimals)) = decimal_source(field) {
+                let field = &fields[0];
+                if let Err(mut e) = execute_decimal(
+                    ctx,
+                    type_cond,
+                    parents,
+                    field,
+                    amount,
+                    decimals,
+                    response_key,
+                ) {
+                    errors.append(&mut e);
+                }
+                continue;
+            }
+
             let child_type = schema
                 .document()
                 .object_or_interface(field.field_type.get_base_type())
                 .expect("we only collect fields that are objects or interfaces");
 
+            // Aggregates are computed in the database in one go; their
+            // results do not have any children we would need to fetch
+            if let Some(entity) = aggregated_entity(schema.document(), child_type) {
+                match execute_aggregate(resolver, ctx, type_cond, entity, child_type, &fields[0]) {
+                    Ok(children) => Join::perform(parents, children, response_key),
+                    Err(mut e) => errors.append(&mut e),
+                }
+                continue;
+            }
+
             let join = Join::new(
                 ctx.query.schema.as_ref(),
                 type_cond,
@@ -502,9 +603,11 @@ fn execute_selection_set<'a>(
             // Group fields with the same response key, so we can execute them together
             let grouped_field_set =
                 collect_fields(ctx, child_type, fields.iter().map(|f| &f.selection_set));
+            let selected =
+                selected_attributes(ctx, child_type, fields.iter().map(|f| &f.selection_set));
 
             match execute_field(
-                resolver, &ctx, type_cond, &parents, &join, &fields[0], field,
+                resolver, &ctx, type_cond, &parents, &join, &fields[0], field, selected,
             ) {
                 Ok(children) => {
                     match execute_selection_set(resolver, ctx, children, grouped_field_set) {
@@ -643,6 +746,13 @@ fn collect_fields_inner<'a>(
             .unwrap_or(false)
     }
 
+    fn is_decimal_field(object_type: ObjectOrInterface, field: &q::Field) -> bool {
+        object_type
+            .field(&field.name)
+            .and_then(decimal_source)
+            .is_some()
+    }
+
     fn collect_fragment<'a>(
         ctx: &'a ExecutionContext<impl Resolver>,
         outer_type_condition: ObjectOrInterface<'a>,
@@ -707,9 +817,12 @@ fn collect_fields_inner<'a>(
     for selection in selections {
         match selection {
             q::Selection::Field(ref field) => {
-                // Only consider fields that point to objects or interfaces, and
-                // ignore nonexistent fields
-                if is_reference_field(&ctx.query.schema.document(), type_condition, field) {
+                // Only consider fields that point to objects or interfaces or
+                // that are computed from other fields, and ignore nonexistent
+                // fields
+                if is_reference_field(&ctx.query.schema.document(), type_condition, field)
+                    || is_decimal_field(type_condition, field)
+                {
                     let response_key = qast::get_response_key(field);
                     output
                         .entry(response_key)
@@ -758,6 +871,7 @@ fn execute_field(
     join: &Join<'_>,
     field: &q::Field,
     field_definition: &s::Field,
+    selected: BTreeSet<String>,
 ) -> Result<Vec<Node>, Vec<QueryExecutionError>> {
     let argument_values = crate::execution::coerce_argument_values(&ctx.query, object_type, field)?;
 
@@ -773,15 +887,251 @@ fn execute_field(
         &join,
         argument_values,
         multiplicity,
+        ctx.query.schema.document(),
         ctx.query.schema.types_for_interface(),
         resolver.block_number(),
         ctx.max_first,
         ctx.max_skip,
         ctx.query.query_id.clone(),
+        ctx.deadline,
+        selected,
     )
     .map_err(|e| vec![e])
 }
 
+/// The names of all fields of `object_type` that `selection_sets` select
+/// directly or through fragments, regardless of their type condition,
+/// together with the fields that `<field>Decimal` fields are computed
+/// from. The store only has to return these attributes of the entities it
+/// fetches
+fn selected_attributes<'a>(
+    ctx: &'a ExecutionContext<impl Resolver>,
+    object_type: ObjectOrInterface<'a>,
+    selection_sets: impl Iterator<Item = &'a q::SelectionSet>,
+) -> BTreeSet<String> {
+    fn collect<'a>(
+        ctx: &'a ExecutionContext<impl Resolver>,
+        object_type: ObjectOrInterface<'a>,
+        selection_set: &'a q::SelectionSet,
+        visited_fragments: &mut HashSet<&'a String>,
+        output: &mut BTreeSet<String>,
+    ) {
+        let schema = ctx.query.schema.document();
+        let fragment_type = |type_condition: Option<&'a q::TypeCondition>| match type_condition {
+            Some(q::TypeCondition::On(name)) => {
+                schema.object_or_interface(name).unwrap_or(object_type)
+            }
+            None => object_type,
+        };
+
+        for selection in &selection_set.items {
+            match selection {
+                q::Selection::Field(field) => {
+                    output.insert(field.name.clone());
+                    // A decimal field might only be declared on the types
+                    // that implement an interface
+                    let implementations: Vec<&s::ObjectType> = match object_type {
+                        ObjectOrInterface::Interface(_) => ctx
+                            .query
+                            .schema
+                            .types_for_interface()
+                            .get(object_type.name())
+                            .map(|types| types.iter().collect())
+                            .unwrap_or_default(),
+                        ObjectOrInterface::Object(_) => vec![],
+                    };
+                    let field_defs = object_type.field(&field.name).into_iter().chain(
+                        implementations
+                            .into_iter()
+                            .filter_map(|ty| ty.field(&field.name)),
+                    );
+                    for (amount, decimals) in field_defs.filter_map(decimal_source) {
+                        output.insert(amount.to_owned());
+                        output.extend(decimals.map(str::to_owned));
+                    }
+                }
+                q::Selection::FragmentSpread(spread) => {
+                    if visited_fragments.insert(&spread.fragment_name) {
+                        let fragment = ctx.query.get_fragment(&spread.fragment_name);
+                        collect(
+                            ctx,
+                            fragment_type(Some(&fragment.type_condition)),
+                            &fragment.selection_set,
+                            visited_fragments,
+                            output,
+                        );
+                    }
+                }
+                q::Selection::InlineFragment(fragment) => {
+                    collect(
+                        ctx,
+                        fragment_type(fragment.type_condition.as_ref()),
+                        &fragment.selection_set,
+                        visited_fragments,
+                        output,
+                    );
+                }
+            }
+        }
+    }
+
+    let mut output = BTreeSet::new();
+    let mut visited_fragments = HashSet::new();
+    for selection_set in selection_sets {
+        collect(
+            ctx,
+            object_type,
+            selection_set,
+            &mut visited_fragments,
+            &mut output,
+        );
+    }
+    output
+}
+
+/// The entity type that `aggregate_type` holds aggregates for, if it is
+/// one of the `<type>_aggregate` types
+fn aggregated_entity<'a>(
+    schema: &'a s::Document,
+    aggregate_type: ObjectOrInterface<'_>,
+) -> Option<&'a s::ObjectType> {
+    aggregate_type
+        .directives()
+        .iter()
+        .find(|directive| directive.name == AGGREGATE_DIRECTIVE)
+        .and_then(|directive| {
+            directive
+                .arguments
+                .iter()
+                .find(|(name, _)| name == "entity")
+        })
+        .and_then(|(_, value)| match value {
+            s::Value::String(entity) => schema.get_object_type_definition(entity),
+            _ => None,
+        })
+}
+
+/// Compute the aggregate for a `<plural>_aggregate` field. Each row of the
+/// result becomes a child node with a `group` and a `value`
+fn execute_aggregate(
+    resolver: &StoreResolver,
+    ctx: &ExecutionContext<impl Resolver>,
+    object_type: ObjectOrInterface<'_>,
+    entity: &s::ObjectType,
+    aggregate_type: ObjectOrInterface<'_>,
+    field: &q::Field,
+) -> Result<Vec<Node>, Vec<QueryExecutionError>> {
+    let argument_values = crate::execution::coerce_argument_values(&ctx.query, object_type, field)?;
+    let query = build_aggregation_query(
+        entity,
+        resolver.block_number(),
+        &argument_values,
+        ctx.query.schema.document(),
+    )
+    .map_err(|e| vec![e])?;
+
+    let as_string = |value: StoreValue| match value {
+        StoreValue::Null => q::Value::Null,
+        value => q::Value::String(value.to_string()),
+    };
+    resolver
+        .store
+        .aggregate(query)
+        .map(|rows| {
+            rows.into_iter()
+                .map(|row| {
+                    let mut entity = BTreeMap::new();
+                    entity.insert(
+                        "group".to_string(),
+                        row.group.map(as_string).unwrap_or(q::Value::Null),
+                    );
+                    entity.insert("value".to_string(), as_string(row.value));
+                    entity.insert(
+                        "__typename".to_string(),
+                        q::Value::String(aggregate_type.name().to_owned()),
+                    );
+                    Node::from(entity)
+                })
+                .collect()
+        })
+        .map_err(|e| vec![e])
+}
+
+/// The fields that the `<field>Decimal` field `field` computes its value
+/// from: the `BigInt` amount and, optionally, the `Int` field that holds the
+/// number of decimals. Returns `None` if `field` is not one of these fields
+fn decimal_source(field: &s::Field) -> Option<(&str, Option<&str>)> {
+    let directive = field
+        .directives
+        .iter()
+        .find(|directive| directive.name == DECIMAL_DIRECTIVE)?;
+    let argument = |name: &str| {
+        directive
+            .arguments
+            .iter()
+            .find(|(arg, _)| arg == name)
+            .and_then(|(_, value)| match value {
+                s::Value::String(field) => Some(field.as_str()),
+                _ => None,
+            })
+    };
+    Some((argument("amount")?, argument("decimals")))
+}
+
+/// Compute the value of a `<field>Decimal` field for each of the `parents`
+/// by dividing their `amount` by ten to the power of the `decimals` argument
+/// or, if that is not given, the value of their `decimals` field. The value
+/// is stored under the response key like a prefetched child
+fn execute_decimal(
+    ctx: &ExecutionContext<impl Resolver>,
+    object_type: ObjectOrInterface<'_>,
+    parents: Vec<&mut Node>,
+    field: &q::Field,
+    amount: &str,
+    decimals: Option<&str>,
+    response_key: &str,
+) -> Result<(), Vec<QueryExecutionError>> {
+    let argument_values = crate::execution::coerce_argument_values(&ctx.query, object_type, field)?;
+    let argument = match argument_values.get(&"decimals".to_string()) {
+        Some(q::Value::Int(n)) => match n.as_i64() {
+            Some(n) if n >= 0 => Some(n),
+            _ => {
+                return Err(vec![QueryExecutionError::InvalidArgumentError(
+                    field.position.clone(),
+                    "decimals".to_owned(),
+                    q::Value::Int(n.clone()),
+                )])
+            }
+        },
+        _ => None,
+    };
+
+    for parent in parents {
+        let decimals = argument.or_else(|| {
+            decimals
+                .and_then(|decimals| parent.get(decimals))
+                .and_then(|decimals| match decimals {
+                    q::Value::Int(n) => n.as_i64(),
+                    _ => None,
+                })
+        });
+        let digits = parent
+            .get(amount)
+            .and_then(|amount| amount.as_str())
+            .and_then(|amount| BigInt::from_str(amount).ok());
+        let value = match (digits, decimals) {
+            (Some(digits), Some(decimals)) => {
+                q::Value::String(BigDecimal::new(digits, -decimals).to_string())
+            }
+            _ => q::Value::Null,
+        };
+        parent
+            .entity
+            .insert(format!("prefetch:{}", response_key), value);
+    }
+    Ok(())
+}
+
 /// Query child entities for `parents` from the store. The `join` indicates
 /// in which child field to look for the parent's id/join field. When
 /// `is_single` is `true`, there is at most one child per parent.
@@ -792,21 +1142,28 @@ fn fetch(
     join: &Join<'_>,
     arguments: HashMap<&String, q::Value>,
     multiplicity: ChildMultiplicity,
+    schema: &s::Document,
     types_for_interface: &BTreeMap<String, Vec<s::ObjectType>>,
     block: BlockNumber,
     max_first: u32,
     max_skip: u32,
     query_id: String,
+    deadline: Option<Instant>,
+    selected: BTreeSet<String>,
 ) -> Result<Vec<Node>, QueryExecutionError> {
     let mut query = build_query(
         join.child_type,
         block,
         &arguments,
+        schema,
         types_for_interface,
         max_first,
         max_skip,
     )?;
     query.query_id = Some(query_id);
+    // Make sure the database stops working on the query once the GraphQL
+    // query has timed out
+    query.timeout = deadline.map(|deadline| deadline.saturating_duration_since(Instant::now()));
 
     if multiplicity == ChildMultiplicity::Single {
         // Suppress 'order by' in lookups of scalar values since
@@ -833,6 +1190,6 @@ fn fetch(
     }
 
     store
-        .find_query_values(query)
+        .find_query_values(query, Some(selected))
         .map(|entities| entities.into_iter().map(|entity| entity.into()).collect())
 }
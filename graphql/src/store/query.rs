@@ -2,7 +2,14 @@ use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
 use std::mem::discriminant;
 
 use graph::prelude::*;
-use graph::{components::store::EntityType, data::graphql::ObjectOrInterface};
+use graph::{
+    components::store::EntityType,
+    data::graphql::{DocumentExt, ObjectOrInterface},
+    data::schema::{
+        CHANGE_BLOCK_FILTER_FIELD, CREATE_BLOCK_FILTER_FIELD, LAST_UPDATED_BLOCK_FIELD,
+    },
+    data::store::BIG_INT_SCALAR,
+};
 
 use crate::schema::ast as sast;
 
@@ -19,6 +26,7 @@ pub fn build_query<'a>(
     entity: impl Into<ObjectOrInterface<'a>>,
     block: BlockNumber,
     arguments: &HashMap<&String, q::Value>,
+    schema: &s::Document,
     types_for_interface: &BTreeMap<String, Vec<s::ObjectType>>,
     max_first: u32,
     max_skip: u32,
@@ -33,18 +41,18 @@ pub fn build_query<'a>(
     });
     let mut query = EntityQuery::new(parse_subgraph_id(entity)?, block, entity_types)
         .range(build_range(arguments, max_first, max_skip)?);
-    if let Some(filter) = build_filter(entity, arguments)? {
+    if let Some(filter) = build_filter(entity, arguments, schema)? {
         query = query.filter(filter);
     }
     let order = match (
         build_order_by(entity, arguments)?,
         build_order_direction(arguments)?,
     ) {
-        (Some((attr, value_type)), OrderDirection::Ascending) => {
-            EntityOrder::Ascending(attr, value_type)
+        (Some((attr, value_type)), (OrderDirection::Ascending, nulls)) => {
+            EntityOrder::Ascending(attr, value_type, nulls)
         }
-        (Some((attr, value_type)), OrderDirection::Descending) => {
-            EntityOrder::Descending(attr, value_type)
+        (Some((attr, value_type)), (OrderDirection::Descending, nulls)) => {
+            EntityOrder::Descending(attr, value_type, nulls)
         }
         (None, _) => EntityOrder::Default,
     };
@@ -52,6 +60,47 @@ pub fn build_query<'a>(
     Ok(query)
 }
 
+/// Builds an `EntityAggregationQuery` from the arguments of a
+/// `<plural>_aggregate` field for the object type `entity`
+pub fn build_aggregation_query(
+    entity: &s::ObjectType,
+    block: BlockNumber,
+    arguments: &HashMap<&String, q::Value>,
+    schema: &s::Document,
+) -> Result<EntityAggregationQuery, QueryExecutionError> {
+    let attribute = |name: &str| match arguments.get(&name.to_string()) {
+        Some(q::Value::Enum(attr)) => Some(attr.to_owned()),
+        _ => None,
+    };
+
+    let function = match arguments.get(&"function".to_string()) {
+        Some(q::Value::Enum(function)) => function
+            .parse::<AggregateFunction>()
+            .map_err(QueryExecutionError::NotSupported)?,
+        _ => {
+            return Err(QueryExecutionError::NotSupported(
+                "aggregates need a `function`".to_owned(),
+            ))
+        }
+    };
+    let mut query = EntityAggregationQuery::new(
+        parse_subgraph_id(entity)?,
+        block,
+        entity.name.clone(),
+        function,
+    );
+    if let Some(attr) = attribute("field") {
+        query = query.attribute(attr);
+    }
+    if let Some(attr) = attribute("groupBy") {
+        query = query.group_by(attr);
+    }
+    if let Some(filter) = build_filter(entity.into(), arguments, schema)? {
+        query = query.filter(filter);
+    }
+    Ok(query)
+}
+
 /// Parses GraphQL arguments into a EntityRange, if present.
 fn build_range(
     arguments: &HashMap<&String, q::Value>,
@@ -98,9 +147,10 @@ fn build_range(
 fn build_filter(
     entity: ObjectOrInterface,
     arguments: &HashMap<&String, q::Value>,
+    schema: &s::Document,
 ) -> Result<Option<EntityFilter>, QueryExecutionError> {
     match arguments.get(&"where".to_string()) {
-        Some(q::Value::Object(object)) => build_filter_from_object(entity, object),
+        Some(q::Value::Object(object)) => build_filter_from_object(entity, object, schema),
         Some(q::Value::Null) => Ok(None),
         None => match arguments.get(&"text".to_string()) {
             Some(q::Value::Object(filter)) => build_fulltext_filter_from_object(filter),
@@ -133,44 +183,184 @@ fn build_fulltext_filter_from_object(
 fn build_filter_from_object(
     entity: ObjectOrInterface,
     object: &BTreeMap<String, q::Value>,
+    schema: &s::Document,
 ) -> Result<Option<EntityFilter>, QueryExecutionError> {
-    Ok(Some(EntityFilter::And({
-        object
-            .iter()
-            .map(|(key, value)| {
-                use self::sast::FilterOp::*;
+    let filters = object
+        .iter()
+        .map(|(key, value)| {
+            use self::sast::FilterOp::*;
 
-                let (field_name, op) = sast::parse_field_as_filter(key);
+            if key == CHANGE_BLOCK_FILTER_FIELD {
+                return build_block_number_gte(value).map(EntityFilter::ChangedAtBlockGte);
+            }
+            if key == CREATE_BLOCK_FILTER_FIELD {
+                return build_block_number_gte(value).map(EntityFilter::CreatedAtBlockGte);
+            }
 
-                let field = sast::get_field(entity, &field_name).ok_or_else(|| {
-                    QueryExecutionError::EntityFieldError(
-                        entity.name().to_owned(),
-                        field_name.clone(),
+            let (field_name, op) = sast::parse_field_as_filter(key);
+
+            let field = sast::get_field(entity, &field_name).ok_or_else(|| {
+                QueryExecutionError::EntityFieldError(entity.name().to_owned(), field_name.clone())
+            })?;
+
+            // `_is_null` takes a boolean, no matter what the type of
+            // the field is
+            if let IsNull = op {
+                return match value {
+                    q::Value::Boolean(true) => Ok(EntityFilter::IsNull(field_name)),
+                    q::Value::Boolean(false) => Ok(EntityFilter::IsNotNull(field_name)),
+                    _ => Err(QueryExecutionError::AttributeTypeError(
+                        value.to_string(),
+                        "Boolean".to_string(),
+                    )),
+                };
+            }
+
+            // `_length` filters take the number of elements of a list,
+            // no matter what the type of the elements is
+            if let LengthEqual | LengthGreaterThan | LengthLessThan = op {
+                let length = match value {
+                    q::Value::Int(n) => n.as_i64().and_then(|n| u32::try_from(n).ok()),
+                    _ => None,
+                }
+                .ok_or_else(|| {
+                    QueryExecutionError::AttributeTypeError(
+                        value.to_string(),
+                        "non-negative Int".to_string(),
                     )
                 })?;
+                return Ok(match op {
+                    LengthEqual => EntityFilter::LengthEqual(field_name, length),
+                    LengthGreaterThan => EntityFilter::LengthGreaterThan(field_name, length),
+                    _ => EntityFilter::LengthLessThan(field_name, length),
+                });
+            }
 
-                let ty = &field.field_type;
-                let store_value = Value::from_query_value(value, &ty)?;
-
-                Ok(match op {
-                    Not => EntityFilter::Not(field_name, store_value),
-                    GreaterThan => EntityFilter::GreaterThan(field_name, store_value),
-                    LessThan => EntityFilter::LessThan(field_name, store_value),
-                    GreaterOrEqual => EntityFilter::GreaterOrEqual(field_name, store_value),
-                    LessOrEqual => EntityFilter::LessOrEqual(field_name, store_value),
-                    In => EntityFilter::In(field_name, list_values(store_value, "_in")?),
-                    NotIn => EntityFilter::NotIn(field_name, list_values(store_value, "_not_in")?),
-                    Contains => EntityFilter::Contains(field_name, store_value),
-                    NotContains => EntityFilter::NotContains(field_name, store_value),
-                    StartsWith => EntityFilter::StartsWith(field_name, store_value),
-                    NotStartsWith => EntityFilter::NotStartsWith(field_name, store_value),
-                    EndsWith => EntityFilter::EndsWith(field_name, store_value),
-                    NotEndsWith => EntityFilter::NotEndsWith(field_name, store_value),
-                    Equal => EntityFilter::Equal(field_name, store_value),
-                })
+            // `<field>_` filters on the children the field refers to
+            if let Child = op {
+                return build_child_filter(field, value, schema);
+            }
+
+            // `_lastUpdatedBlock` is not part of the input schema
+            let ty = if field_name == LAST_UPDATED_BLOCK_FIELD {
+                s::Type::NamedType(BIG_INT_SCALAR.to_owned())
+            } else {
+                field.field_type.clone()
+            };
+            let store_value = Value::from_query_value(value, &ty)?;
+
+            Ok(match op {
+                Not => EntityFilter::Not(field_name, store_value),
+                GreaterThan => EntityFilter::GreaterThan(field_name, store_value),
+                LessThan => EntityFilter::LessThan(field_name, store_value),
+                GreaterOrEqual => EntityFilter::GreaterOrEqual(field_name, store_value),
+                LessOrEqual => EntityFilter::LessOrEqual(field_name, store_value),
+                In => EntityFilter::In(field_name, list_values(store_value, "_in")?),
+                NotIn => EntityFilter::NotIn(field_name, list_values(store_value, "_not_in")?),
+                Contains => EntityFilter::Contains(field_name, store_value),
+                NotContains => EntityFilter::NotContains(field_name, store_value),
+                StartsWith => EntityFilter::StartsWith(field_name, store_value),
+                NotStartsWith => EntityFilter::NotStartsWith(field_name, store_value),
+                EndsWith => EntityFilter::EndsWith(field_name, store_value),
+                NotEndsWith => EntityFilter::NotEndsWith(field_name, store_value),
+                Equal => EntityFilter::Equal(field_name, store_value),
+                EqualNoCase => EntityFilter::EqualNoCase(field_name, store_value),
+                NotEqualNoCase => EntityFilter::NotEqualNoCase(field_name, store_value),
+                ContainsNoCase => EntityFilter::ContainsNoCase(field_name, store_value),
+                NotContainsNoCase => EntityFilter::NotContainsNoCase(field_name, store_value),
+                StartsWithNoCase => EntityFilter::StartsWithNoCase(field_name, store_value),
+                NotStartsWithNoCase => EntityFilter::NotStartsWithNoCase(field_name, store_value),
+                EndsWithNoCase => EntityFilter::EndsWithNoCase(field_name, store_value),
+                NotEndsWithNoCase => EntityFilter::NotEndsWithNoCase(field_name, store_value),
+                IsNull => unreachable!("`_is_null` filters were handled above"),
+                Child => unreachable!("`_` filters were handled above"),
+                LengthEqual | LengthGreaterThan | LengthLessThan => {
+                    unreachable!("`_length` filters were handled above")
+                }
             })
-            .collect::<Result<Vec<EntityFilter>, QueryExecutionError>>()?
-    })))
+        })
+        .collect::<Result<Vec<EntityFilter>, QueryExecutionError>>()?;
+    Ok(Some(EntityFilter::And(fuse_ranges(filters))))
+}
+
+/// Parses the value `{ number_gte: <block> }` of a `_change_block` or
+/// `_create_block` filter
+fn build_block_number_gte(value: &q::Value) -> Result<BlockNumber, QueryExecutionError> {
+    match value {
+        q::Value::Object(object) => match object.get("number_gte") {
+            // `BigInt`s are represented as `String`s
+            Some(q::Value::String(n)) => n.parse().ok(),
+            Some(q::Value::Int(n)) => n.as_i64(),
+            _ => None,
+        },
+        _ => None,
+    }
+    .ok_or_else(|| {
+        QueryExecutionError::AttributeTypeError(value.to_string(), "BlockChangedFilter".to_string())
+    })
+}
+
+/// Parses the filter `<field>_: { .. }` into a filter on the children
+/// that `field` refers to. Children must be of an object type
+fn build_child_filter(
+    field: &s::Field,
+    value: &q::Value,
+    schema: &s::Document,
+) -> Result<EntityFilter, QueryExecutionError> {
+    let child_type = sast::get_field_name(&field.field_type);
+    let child = match schema.get_named_type(&child_type) {
+        Some(s::TypeDefinition::Object(child)) => child,
+        _ => return Err(QueryExecutionError::InvalidFilterError),
+    };
+    let object = match value {
+        q::Value::Object(object) => object,
+        _ => return Err(QueryExecutionError::InvalidFilterError),
+    };
+
+    // Derived fields are stored on the child as the id of the parent
+    let link = match sast::get_derived_from_field(child, field) {
+        Some(derived_from) => ChildLink::Child(derived_from.name.clone()),
+        None => ChildLink::Parent(field.name.clone()),
+    };
+    let filter = build_filter_from_object(child.into(), object, schema)?
+        .unwrap_or_else(|| EntityFilter::And(vec![]));
+    Ok(EntityFilter::Child {
+        child_type,
+        filter: Box::new(filter),
+        link,
+    })
+}
+
+/// Replace a `GreaterOrEqual` and a `LessOrEqual` filter on the same
+/// attribute with one `Between` filter, which the database can answer with
+/// a single index scan
+fn fuse_ranges(filters: Vec<EntityFilter>) -> Vec<EntityFilter> {
+    let mut fused: Vec<EntityFilter> = Vec::with_capacity(filters.len());
+    for filter in filters {
+        let partner = fused.iter().position(|other| match (other, &filter) {
+            (EntityFilter::GreaterOrEqual(a, _), EntityFilter::LessOrEqual(b, _))
+            | (EntityFilter::LessOrEqual(a, _), EntityFilter::GreaterOrEqual(b, _)) => a == b,
+            _ => false,
+        });
+        match partner {
+            Some(pos) => {
+                let between = match (fused.remove(pos), filter) {
+                    (
+                        EntityFilter::GreaterOrEqual(attr, low),
+                        EntityFilter::LessOrEqual(_, high),
+                    )
+                    | (
+                        EntityFilter::LessOrEqual(attr, high),
+                        EntityFilter::GreaterOrEqual(_, low),
+                    ) => EntityFilter::Between(attr, low, high),
+                    _ => unreachable!("we only fuse `_gte` and `_lte` filters"),
+                };
+                fused.insert(pos, between);
+            }
+            None => fused.push(filter),
+        }
+    }
+    fused
 }
 
 /// Parses a list of GraphQL values into a vector of entity field values.
@@ -244,17 +434,27 @@ fn build_fulltext_order_by_from_object(
 }
 
 /// Parses GraphQL arguments into a EntityOrder, if present.
+/// Parses the `orderDirection` argument into the direction of the order and
+/// where nulls go. Plain `asc` and `desc` put nulls last
 fn build_order_direction(
     arguments: &HashMap<&String, q::Value>,
-) -> Result<OrderDirection, QueryExecutionError> {
+) -> Result<(OrderDirection, NullsPlacement), QueryExecutionError> {
+    use NullsPlacement::{First, Last};
+    use OrderDirection::{Ascending, Descending};
+
     Ok(arguments
         .get(&"orderDirection".to_string())
         .map(|value| match value {
-            q::Value::Enum(name) if name == "asc" => OrderDirection::Ascending,
-            q::Value::Enum(name) if name == "desc" => OrderDirection::Descending,
-            _ => OrderDirection::Ascending,
+            q::Value::Enum(name) => match name.as_str() {
+                "asc" | "asc_nulls_last" => (Ascending, Last),
+                "asc_nulls_first" => (Ascending, First),
+                "desc" | "desc_nulls_last" => (Descending, Last),
+                "desc_nulls_first" => (Descending, First),
+                _ => (Ascending, Last),
+            },
+            _ => (Ascending, Last),
         })
-        .unwrap_or(OrderDirection::Ascending))
+        .unwrap_or((Ascending, Last)))
 }
 
 /// Parses the subgraph ID from the ObjectType directives.
@@ -335,7 +535,10 @@ pub fn collect_entities_from_query_field(
 
 #[cfg(test)]
 mod tests {
-    use graph::prelude::s::{Directive, Field, InputValue, ObjectType, Type, Value as SchemaValue};
+    use graph::prelude::s::{
+        Definition, Directive, Document, Field, InputValue, ObjectType, Type, TypeDefinition,
+        Value as SchemaValue,
+    };
     use graphql_parser::Pos;
     use std::collections::{BTreeMap, HashMap};
 
@@ -395,6 +598,12 @@ mod tests {
         }
     }
 
+    fn empty_schema() -> Document {
+        Document {
+            definitions: vec![],
+        }
+    }
+
     fn field(name: &str, field_type: Type) -> Field {
         Field {
             position: Default::default(),
@@ -422,6 +631,7 @@ mod tests {
                 &object("Entity1"),
                 BLOCK_NUMBER_MAX,
                 &default_arguments(),
+                &empty_schema(),
                 &BTreeMap::new(),
                 std::u32::MAX,
                 std::u32::MAX
@@ -435,6 +645,7 @@ mod tests {
                 &object("Entity2"),
                 BLOCK_NUMBER_MAX,
                 &default_arguments(),
+                &empty_schema(),
                 &BTreeMap::new(),
                 std::u32::MAX,
                 std::u32::MAX
@@ -452,6 +663,7 @@ mod tests {
                 &default_object(),
                 BLOCK_NUMBER_MAX,
                 &default_arguments(),
+                &empty_schema(),
                 &BTreeMap::new(),
                 std::u32::MAX,
                 std::u32::MAX
@@ -472,13 +684,14 @@ mod tests {
                 &default_object(),
                 BLOCK_NUMBER_MAX,
                 &args,
+                &empty_schema(),
                 &BTreeMap::new(),
                 std::u32::MAX,
                 std::u32::MAX
             )
             .unwrap()
             .order,
-            EntityOrder::Ascending("name".to_string(), ValueType::String)
+            EntityOrder::Ascending("name".to_string(), ValueType::String, NullsPlacement::Last)
         );
 
         let mut args = default_arguments();
@@ -488,13 +701,14 @@ mod tests {
                 &default_object(),
                 BLOCK_NUMBER_MAX,
                 &args,
+                &empty_schema(),
                 &BTreeMap::new(),
                 std::u32::MAX,
                 std::u32::MAX
             )
             .unwrap()
             .order,
-            EntityOrder::Ascending("email".to_string(), ValueType::String)
+            EntityOrder::Ascending("email".to_string(), ValueType::String, NullsPlacement::Last)
         );
     }
 
@@ -508,6 +722,7 @@ mod tests {
                 &default_object(),
                 BLOCK_NUMBER_MAX,
                 &args,
+                &empty_schema(),
                 &BTreeMap::new(),
                 std::u32::MAX,
                 std::u32::MAX
@@ -524,6 +739,7 @@ mod tests {
                 &default_object(),
                 BLOCK_NUMBER_MAX,
                 &args,
+                &empty_schema(),
                 &BTreeMap::new(),
                 std::u32::MAX,
                 std::u32::MAX
@@ -546,13 +762,14 @@ mod tests {
                 &default_object(),
                 BLOCK_NUMBER_MAX,
                 &args,
+                &empty_schema(),
                 &BTreeMap::new(),
                 std::u32::MAX,
                 std::u32::MAX
             )
             .unwrap()
             .order,
-            EntityOrder::Ascending("name".to_string(), ValueType::String)
+            EntityOrder::Ascending("name".to_string(), ValueType::String, NullsPlacement::Last)
         );
 
         let mut args = default_arguments();
@@ -563,15 +780,61 @@ mod tests {
                 &default_object(),
                 BLOCK_NUMBER_MAX,
                 &args,
+                &empty_schema(),
                 &BTreeMap::new(),
                 std::u32::MAX,
                 std::u32::MAX
             )
             .unwrap()
             .order,
-            EntityOrder::Descending("name".to_string(), ValueType::String)
+            EntityOrder::Descending("name".to_string(), ValueType::String, NullsPlacement::Last)
         );
 
+        for (direction, order) in vec![
+            (
+                "asc_nulls_first",
+                EntityOrder::Ascending(
+                    "name".to_string(),
+                    ValueType::String,
+                    NullsPlacement::First,
+                ),
+            ),
+            (
+                "desc_nulls_first",
+                EntityOrder::Descending(
+                    "name".to_string(),
+                    ValueType::String,
+                    NullsPlacement::First,
+                ),
+            ),
+            (
+                "desc_nulls_last",
+                EntityOrder::Descending(
+                    "name".to_string(),
+                    ValueType::String,
+                    NullsPlacement::Last,
+                ),
+            ),
+        ] {
+            let mut args = default_arguments();
+            args.insert(&order_by, q::Value::Enum("name".to_string()));
+            args.insert(&order_direction, q::Value::Enum(direction.to_string()));
+            assert_eq!(
+                build_query(
+                    &default_object(),
+                    BLOCK_NUMBER_MAX,
+                    &args,
+                    &empty_schema(),
+                    &BTreeMap::new(),
+                    std::u32::MAX,
+                    std::u32::MAX
+                )
+                .unwrap()
+                .order,
+                order
+            );
+        }
+
         let mut args = default_arguments();
         args.insert(&order_by, q::Value::Enum("name".to_string()));
         args.insert(
@@ -583,13 +846,14 @@ mod tests {
                 &default_object(),
                 BLOCK_NUMBER_MAX,
                 &args,
+                &empty_schema(),
                 &BTreeMap::new(),
                 std::u32::MAX,
                 std::u32::MAX
             )
             .unwrap()
             .order,
-            EntityOrder::Ascending("name".to_string(), ValueType::String)
+            EntityOrder::Ascending("name".to_string(), ValueType::String, NullsPlacement::Last)
         );
 
         // No orderBy -> EntityOrder::Default
@@ -603,6 +867,7 @@ mod tests {
                 &default_object(),
                 BLOCK_NUMBER_MAX,
                 &args,
+                &empty_schema(),
                 &BTreeMap::new(),
                 std::u32::MAX,
                 std::u32::MAX
@@ -625,13 +890,14 @@ mod tests {
                 &default_object(),
                 BLOCK_NUMBER_MAX,
                 &args,
+                &empty_schema(),
                 &BTreeMap::new(),
                 std::u32::MAX,
                 std::u32::MAX
             )
             .unwrap()
             .order,
-            EntityOrder::Ascending("name".to_string(), ValueType::String)
+            EntityOrder::Ascending("name".to_string(), ValueType::String, NullsPlacement::Last)
         );
 
         let mut args = default_arguments();
@@ -642,13 +908,14 @@ mod tests {
                 &default_object(),
                 BLOCK_NUMBER_MAX,
                 &args,
+                &empty_schema(),
                 &BTreeMap::new(),
                 std::u32::MAX,
                 std::u32::MAX
             )
             .unwrap()
             .order,
-            EntityOrder::Ascending("name".to_string(), ValueType::String)
+            EntityOrder::Ascending("name".to_string(), ValueType::String, NullsPlacement::Last)
         );
     }
 
@@ -659,6 +926,7 @@ mod tests {
                 &default_object(),
                 BLOCK_NUMBER_MAX,
                 &default_arguments(),
+                &empty_schema(),
                 &BTreeMap::new(),
                 std::u32::MAX,
                 std::u32::MAX
@@ -679,6 +947,7 @@ mod tests {
                 &default_object(),
                 BLOCK_NUMBER_MAX,
                 &args,
+                &empty_schema(),
                 &BTreeMap::new(),
                 std::u32::MAX,
                 std::u32::MAX
@@ -711,6 +980,7 @@ mod tests {
                 },
                 BLOCK_NUMBER_MAX,
                 &args,
+                &empty_schema(),
                 &BTreeMap::new(),
                 std::u32::MAX,
                 std::u32::MAX,
@@ -723,4 +993,88 @@ mod tests {
             )]))
         )
     }
+
+    #[test]
+    fn build_query_yields_child_filters() {
+        let token = ObjectType {
+            fields: vec![field("symbol", Type::NamedType("String".to_owned()))],
+            ..object("Token")
+        };
+        let schema = Document {
+            definitions: vec![Definition::TypeDefinition(TypeDefinition::Object(
+                token.clone(),
+            ))],
+        };
+
+        let whre = "where".to_string();
+        let mut args = default_arguments();
+        args.insert(
+            &whre,
+            q::Value::Object(BTreeMap::from_iter(vec![(
+                "token_".to_string(),
+                q::Value::Object(BTreeMap::from_iter(vec![(
+                    "symbol".to_string(),
+                    q::Value::String("DAI".to_string()),
+                )])),
+            )])),
+        );
+        assert_eq!(
+            build_query(
+                &ObjectType {
+                    fields: vec![field("token", Type::NamedType("Token".to_owned()))],
+                    ..object("Pool")
+                },
+                BLOCK_NUMBER_MAX,
+                &args,
+                &schema,
+                &BTreeMap::new(),
+                std::u32::MAX,
+                std::u32::MAX,
+            )
+            .unwrap()
+            .filter,
+            Some(EntityFilter::And(vec![EntityFilter::Child {
+                child_type: "Token".to_string(),
+                filter: Box::new(EntityFilter::And(vec![EntityFilter::Equal(
+                    "symbol".to_string(),
+                    Value::String("DAI".to_string()),
+                )])),
+                link: ChildLink::Parent("token".to_string()),
+            }]))
+        )
+    }
+
+    #[test]
+    fn build_query_fuses_ranges() {
+        let whre = "where".to_string();
+        let mut args = default_arguments();
+        args.insert(
+            &whre,
+            q::Value::Object(BTreeMap::from_iter(vec![
+                ("age_gte".to_string(), q::Value::Int(18.into())),
+                ("age_lte".to_string(), q::Value::Int(65.into())),
+            ])),
+        );
+        assert_eq!(
+            build_query(
+                &ObjectType {
+                    fields: vec![field("age", Type::NamedType("Int".to_owned()))],
+                    ..default_object()
+                },
+                BLOCK_NUMBER_MAX,
+                &args,
+                &empty_schema(),
+                &BTreeMap::new(),
+                std::u32::MAX,
+                std::u32::MAX,
+            )
+            .unwrap()
+            .filter,
+            Some(EntityFilter::And(vec![EntityFilter::Between(
+                "age".to_string(),
+                Value::Int(18),
+                Value::Int(65),
+            )]))
+        )
+    }
 }
@@ -4,7 +4,8 @@ use std::sync::Arc;
 
 use graph::data::{
     graphql::{object, ObjectOrInterface},
-    schema::META_FIELD_TYPE,
+    schema::{INDEXING_ERROR_TYPE, INDEXING_STATUS_TYPE, META_FIELD_TYPE, SERVICE_FIELD_TYPE},
+    subgraph::{schema::SubgraphError, status},
 };
 use graph::prelude::*;
 use graph::{components::store::*, data::schema::BLOCK_FIELD_TYPE};
@@ -130,21 +131,16 @@ impl StoreResolver {
                         )))
                     }
                 }),
-            BlockConstraint::Hash(hash) => {
-                store
-                    .block_number(hash)
-                    .map_err(|e| e.into())
-                    .and_then(|number| {
-                        number
-                            .ok_or_else(|| {
-                                QueryExecutionError::ValueParseError(
-                                    "block.hash".to_owned(),
-                                    "no block with that hash found".to_owned(),
-                                )
-                            })
-                            .map(|number| EthereumBlockPointer::from((hash, number as u64)))
+            BlockConstraint::Hash(hash) => store.block_number(hash).and_then(|number| {
+                number
+                    .ok_or_else(|| {
+                        QueryExecutionError::ValueParseError(
+                            "block.hash".to_owned(),
+                            "no block with that hash found".to_owned(),
+                        )
                     })
-            }
+                    .map(|number| EthereumBlockPointer::from((hash, number as u64)))
+            }),
             BlockConstraint::Latest => store
                 .block_ptr(subgraph.clone())
                 .map_err(|e| StoreError::from(e).into())
@@ -180,7 +176,7 @@ impl StoreResolver {
                 .unwrap_or(q::Value::Null);
             let number = self
                 .block_ptr
-                .map(|ptr| q::Value::Int((ptr.number as i32).into()))
+                .map(|ptr| q::Value::String(ptr.number.to_string()))
                 .unwrap_or(q::Value::Null);
             let mut map = BTreeMap::new();
             let block = object! {
@@ -205,6 +201,92 @@ impl StoreResolver {
         }
         return Ok((prefetched_object, None));
     }
+
+    /// The value for the `indexing` field of `_meta`. Since getting the
+    /// status requires a few queries, we only do that when the field is
+    /// actually part of the query
+    fn indexing_status(&self) -> Result<q::Value, QueryExecutionError> {
+        fn block_value(ptr: Option<EthereumBlockPointer>) -> q::Value {
+            let blocks = ptr
+                .map(|ptr| {
+                    object! {
+                        hash: q::Value::String(format!("0x{:x}", ptr.hash)),
+                        number: q::Value::String(ptr.number.to_string()),
+                        __typename: BLOCK_FIELD_TYPE
+                    }
+                })
+                .into_iter()
+                .collect();
+            q::Value::List(blocks)
+        }
+
+        fn error_value(error: SubgraphError) -> q::Value {
+            let mut map = BTreeMap::new();
+            map.insert("prefetch:block".to_string(), block_value(error.block_ptr));
+            map.insert("message".to_string(), q::Value::String(error.message));
+            map.insert(
+                "handler".to_string(),
+                error.handler.map_or(q::Value::Null, q::Value::String),
+            );
+            map.insert(
+                "deterministic".to_string(),
+                q::Value::Boolean(error.deterministic),
+            );
+            map.insert(
+                "__typename".to_string(),
+                q::Value::String(INDEXING_ERROR_TYPE.to_string()),
+            );
+            q::Value::Object(map)
+        }
+
+        let status::Info {
+            synced,
+            health,
+            fatal_error,
+            non_fatal_errors,
+            chains,
+            entity_count,
+            ..
+        } = self.store.deployment_status()?;
+        let chain = chains.into_iter().next();
+        let (chain_head_block, latest_block) = chain
+            .map(|chain| {
+                (
+                    chain.chain_head_block.map(|block| block.to_ptr()),
+                    chain.latest_block.map(|block| block.to_ptr()),
+                )
+            })
+            .unwrap_or((None, None));
+
+        let mut map = BTreeMap::new();
+        map.insert("synced".to_string(), q::Value::Boolean(synced));
+        map.insert("health".to_string(), q::Value::from(health));
+        map.insert(
+            "prefetch:fatalError".to_string(),
+            q::Value::List(fatal_error.into_iter().map(error_value).collect()),
+        );
+        map.insert(
+            "prefetch:nonFatalErrors".to_string(),
+            q::Value::List(non_fatal_errors.into_iter().map(error_value).collect()),
+        );
+        map.insert(
+            "prefetch:chainHeadBlock".to_string(),
+            block_value(chain_head_block),
+        );
+        map.insert(
+            "prefetch:latestBlock".to_string(),
+            block_value(latest_block),
+        );
+        map.insert(
+            "entityCount".to_string(),
+            q::Value::String(entity_count.to_string()),
+        );
+        map.insert(
+            "__typename".to_string(),
+            q::Value::String(INDEXING_STATUS_TYPE.to_string()),
+        );
+        Ok(q::Value::Object(map))
+    }
 }
 
 impl Resolver for StoreResolver {
@@ -250,6 +332,16 @@ impl Resolver for StoreResolver {
         if let Some(meta) = meta {
             return Ok(meta);
         }
+        if object_type.name() == INDEXING_STATUS_TYPE {
+            return self.indexing_status();
+        }
+        if object_type.name() == SERVICE_FIELD_TYPE {
+            let schema = self.store.api_schema()?;
+            return Ok(object! {
+                sdl: crate::execution::service_sdl(schema.document()),
+                __typename: SERVICE_FIELD_TYPE
+            });
+        }
         if let Some(q::Value::List(children)) = prefetched_object {
             if children.len() > 1 {
                 let derived_from_field =
@@ -150,6 +150,7 @@ fn map_source_to_response_stream(
     let trigger_stream = futures03::stream::iter(vec![Ok(Arc::new(StoreEvent {
         tag: 0,
         changes: Default::default(),
+        summaries: vec![],
     }))]);
 
     let SubscriptionExecutionOptions {
@@ -201,6 +202,12 @@ async fn execute_subscription_event(
 ) -> Arc<QueryResult> {
     debug!(logger, "Execute subscription event"; "event" => format!("{:?}", event));
 
+    // Every execution of the subscription query sees one block
+    let store = match store.pin_latest_block() {
+        Ok(store) => store,
+        Err(e) => return Arc::new(e.into()),
+    };
+
     let resolver = match StoreResolver::at_block(
         &logger,
         store,
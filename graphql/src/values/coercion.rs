@@ -51,6 +51,8 @@ impl MaybeCoercible<ScalarType> for Value {
             ("BigInt", Value::Int(n)) => {
                 Ok(Value::String(n.as_i64().ok_or(Value::Int(n))?.to_string()))
             }
+            // The entity representations for Apollo Federation
+            ("_Any", v @ Value::Object(_)) => Ok(v),
             (_, v) => Err(v),
         }
     }
@@ -17,11 +17,12 @@ use graph::{
     },
     prelude::{
         async_trait, futures03::stream::StreamExt, futures03::FutureExt, futures03::TryFutureExt,
-        o, q, serde_json, slog, tokio, Entity, EntityKey, EntityOperation, EthereumBlockPointer,
-        FutureExtension, GraphQlRunner as _, Logger, NodeId, Query, QueryError,
-        QueryExecutionError, QueryLoadManager, QueryResult, QueryStoreManager, QueryVariables,
-        Schema, SubgraphDeploymentEntity, SubgraphDeploymentId, SubgraphManifest, SubgraphName,
-        SubgraphStore, SubgraphVersionSwitchingMode, Subscription, SubscriptionError, Value,
+        o, q, serde_json, slog, tokio, BigInt, Entity, EntityKey, EntityOperation,
+        EthereumBlockPointer, FutureExtension, GraphQlRunner as _, Logger, NodeId, Query,
+        QueryError, QueryExecutionError, QueryLoadManager, QueryResult, QueryStoreManager,
+        QueryVariables, Schema, SubgraphDeploymentEntity, SubgraphDeploymentId, SubgraphManifest,
+        SubgraphName, SubgraphStore, SubgraphVersionSwitchingMode, Subscription, SubscriptionError,
+        Value,
     },
 };
 use graph_graphql::{prelude::*, subscription::execute_subscription};
@@ -58,6 +59,9 @@ fn setup_with_features(id: &str, features: BTreeSet<SubgraphFeature>) -> Subgrap
         data_sources: vec![],
         graft: None,
         templates: vec![],
+        confirmations: 0,
+        canaries: None,
+        limits: Default::default(),
     };
 
     insert_test_entities(STORE.as_ref(), manifest);
@@ -94,6 +98,15 @@ fn test_schema(id: SubgraphDeploymentId) -> Schema {
                 id: ID!
                 song: Song @derivedFrom(field: \"id\")
                 played: Int!
+                revenue: BigInt @decimals(field: \"decimals\")
+                decimals: Int
+                royalties: Int @restricted(roles: [\"ops\"])
+                doubleRoyalties: Int @derivedExpression(expression: \"royalties * 2\")
+            }
+
+            type Release @entity {
+                id: ID!
+                stat: SongStat
             }
             ",
         id,
@@ -184,11 +197,15 @@ fn insert_test_entities(store: &impl SubgraphStore, manifest: SubgraphManifest)
             ("__typename", Value::from("SongStat")),
             ("id", Value::from("s1")),
             ("played", Value::from(10)),
+            ("revenue", Value::from(BigInt::from(1234567890123456789i64))),
+            ("decimals", Value::from(18)),
+            ("royalties", Value::from(7)),
         ]),
         Entity::from(vec![
             ("__typename", Value::from("SongStat")),
             ("id", Value::from("s2")),
             ("played", Value::from(15)),
+            ("revenue", Value::from(BigInt::from(2500))),
         ]),
     ];
 
@@ -244,6 +261,10 @@ async fn execute_query_document_with_variables(
     query: q::Document,
     variables: Option<QueryVariables>,
 ) -> QueryResult {
+    execute_query(id, Query::new(query, variables)).await
+}
+
+async fn execute_query(id: &SubgraphDeploymentId, query: Query) -> QueryResult {
     let runner = Arc::new(GraphQlRunner::new(
         &*LOGGER,
         STORE.clone(),
@@ -251,7 +272,6 @@ async fn execute_query_document_with_variables(
         LOAD_MANAGER.clone(),
     ));
     let target = QueryTarget::Deployment(id.clone());
-    let query = Query::new(query, variables);
 
     runner
         .run_query_with_complexity(query, target, None, None, None, None, false)
@@ -1225,6 +1245,321 @@ fn can_filter_by_relationship_fields() {
     })
 }
 
+#[test]
+fn can_filter_by_child_fields() {
+    run_test_sequentially(setup, |_, id| async move {
+        let result = execute_query_document(
+            &id,
+            graphql_parser::parse_query(
+                "
+        query {
+            musicians(orderBy: id, where: { mainBand_: { name: \"The Amateurs\" } }) {
+                id
+            }
+            bands(orderBy: id, where: { members_: { name: \"Lisa\" } }) {
+                id
+            }
+        }
+        ",
+            )
+            .expect("invalid test query")
+            .into_static(),
+        )
+        .await;
+
+        assert_eq!(
+            extract_data!(result),
+            Some(object_value(vec![
+                (
+                    "musicians",
+                    q::Value::List(vec![object_value(vec![(
+                        "id",
+                        q::Value::String(String::from("m3"))
+                    )])])
+                ),
+                (
+                    "bands",
+                    q::Value::List(vec![object_value(vec![(
+                        "id",
+                        q::Value::String(String::from("b1"))
+                    )])])
+                )
+            ]))
+        );
+    })
+}
+
+#[test]
+fn can_query_aggregates() {
+    run_test_sequentially(setup, |_, id| async move {
+        let result = execute_query_document(
+            &id,
+            graphql_parser::parse_query(
+                "
+        query {
+            songStats_aggregate(function: sum, field: played) {
+                value
+            }
+            musicians_aggregate(function: count, groupBy: mainBand, where: { name_not: \"Lisa\" }) {
+                group
+                value
+            }
+        }
+        ",
+            )
+            .expect("invalid test query")
+            .into_static(),
+        )
+        .await;
+
+        let row = |group: q::Value, value: &str| {
+            object_value(vec![
+                ("group", group),
+                ("value", q::Value::String(value.to_owned())),
+            ])
+        };
+        assert_eq!(
+            extract_data!(result),
+            Some(object_value(vec![
+                (
+                    "songStats_aggregate",
+                    q::Value::List(vec![object_value(vec![(
+                        "value",
+                        q::Value::String(String::from("25"))
+                    )])])
+                ),
+                (
+                    "musicians_aggregate",
+                    q::Value::List(vec![
+                        row(q::Value::String(String::from("b1")), "1"),
+                        row(q::Value::String(String::from("b2")), "1"),
+                        row(q::Value::Null, "1"),
+                    ])
+                )
+            ]))
+        );
+    })
+}
+
+#[test]
+fn can_query_counts() {
+    run_test_sequentially(setup, |_, id| async move {
+        let result = execute_query_document(
+            &id,
+            graphql_parser::parse_query(
+                "
+        query {
+            musiciansCount
+            notLisa: musiciansCount(where: { name_not: \"Lisa\" })
+            songsCount
+        }
+        ",
+            )
+            .expect("invalid test query")
+            .into_static(),
+        )
+        .await;
+
+        assert_eq!(
+            extract_data!(result),
+            Some(object_value(vec![
+                ("musiciansCount", q::Value::String(String::from("4"))),
+                ("notLisa", q::Value::String(String::from("3"))),
+                ("songsCount", q::Value::String(String::from("4"))),
+            ]))
+        );
+    })
+}
+
+#[test]
+fn can_query_decimal_fields() {
+    run_test_sequentially(setup, |_, id| async move {
+        let result = execute_query_document(
+            &id,
+            graphql_parser::parse_query(
+                "
+        query {
+            songStats(orderBy: id) {
+                id
+                revenueDecimal
+                cents: revenueDecimal(decimals: 2)
+            }
+        }
+        ",
+            )
+            .expect("invalid test query")
+            .into_static(),
+        )
+        .await;
+
+        assert_eq!(
+            extract_data!(result),
+            Some(object_value(vec![(
+                "songStats",
+                q::Value::List(vec![
+                    object_value(vec![
+                        ("id", q::Value::String(String::from("s1"))),
+                        (
+                            "revenueDecimal",
+                            q::Value::String(String::from("1.234567890123456789"))
+                        ),
+                        (
+                            "cents",
+                            q::Value::String(String::from("12345678901234567.89"))
+                        ),
+                    ]),
+                    object_value(vec![
+                        ("id", q::Value::String(String::from("s2"))),
+                        ("revenueDecimal", q::Value::Null),
+                        ("cents", q::Value::String(String::from("25"))),
+                    ]),
+                ])
+            )]))
+        );
+    })
+}
+
+#[test]
+fn can_choose_where_nulls_go_when_ordering() {
+    run_test_sequentially(setup, |_, id| async move {
+        let result = execute_query_document(
+            &id,
+            graphql_parser::parse_query(
+                "
+        query {
+            last: songStats(orderBy: decimals, orderDirection: desc) { id }
+            first: songStats(orderBy: decimals, orderDirection: desc_nulls_first) { id }
+        }
+        ",
+            )
+            .expect("invalid test query")
+            .into_static(),
+        )
+        .await;
+
+        let ids = |ids: &[&str]| {
+            q::Value::List(
+                ids.iter()
+                    .map(|id| object_value(vec![("id", q::Value::String(id.to_string()))]))
+                    .collect(),
+            )
+        };
+        assert_eq!(
+            extract_data!(result),
+            Some(object_value(vec![
+                ("last", ids(&["s1", "s2"])),
+                ("first", ids(&["s2", "s1"])),
+            ]))
+        );
+    })
+}
+
+#[test]
+fn can_filter_by_list_length() {
+    run_test_sequentially(setup, |_, id| async move {
+        let result = execute_query_document(
+            &id,
+            graphql_parser::parse_query(
+                "
+        query {
+            several: musicians(orderBy: id, where: { bands_length_gt: 1 }) { id }
+            none: musicians(orderBy: id, where: { bands_length: 0 }) { id }
+        }
+        ",
+            )
+            .expect("invalid test query")
+            .into_static(),
+        )
+        .await;
+
+        assert_eq!(
+            extract_data!(result),
+            Some(object_value(vec![
+                (
+                    "several",
+                    q::Value::List(vec![
+                        object_value(vec![("id", q::Value::String(String::from("m1")))]),
+                        object_value(vec![("id", q::Value::String(String::from("m3")))]),
+                    ])
+                ),
+                (
+                    "none",
+                    q::Value::List(vec![object_value(vec![(
+                        "id",
+                        q::Value::String(String::from("m4"))
+                    )])])
+                ),
+            ]))
+        );
+    })
+}
+
+#[test]
+fn restricted_fields_require_a_role() {
+    run_test_sequentially(setup, |_, id| async move {
+        let query = |text: &str, roles: &[&str]| {
+            let document = graphql_parser::parse_query(text)
+                .expect("invalid test query")
+                .into_static();
+            Query::new(document, None)
+                .with_roles(roles.iter().map(|role| role.to_string()).collect())
+        };
+        let assert_denied =
+            |result: QueryResult, what: &str| match &result.to_result().unwrap_err()[0] {
+                QueryError::ExecutionError(QueryExecutionError::AccessDenied(_, denied)) => {
+                    assert_eq!(denied, what);
+                }
+                e => panic!(format!("expected AccessDenied, got {}", e)),
+            };
+
+        let select = "query { songStats(orderBy: id) { id royalties } }";
+        let filter = "query { songStats(where: { royalties_gt: 5 }) { id } }";
+
+        let result = execute_query(&id, query(select, &[])).await;
+        assert_denied(result, "SongStat.royalties");
+        let result = execute_query(&id, query(filter, &["audit"])).await;
+        assert_denied(result, "SongStat.royalties");
+
+        // Fields that are computed from a restricted field reveal it, and
+        // so do filters on children
+        let derived = "query { songStats { id doubleRoyalties } }";
+        let result = execute_query(&id, query(derived, &[])).await;
+        assert_denied(result, "SongStat.royalties");
+        let nested = "query { releases(where: { stat_: { royalties_gt: 5 } }) { id } }";
+        let result = execute_query(&id, query(nested, &[])).await;
+        assert_denied(result, "SongStat.royalties");
+
+        let result = execute_query(&id, query(select, &["ops"])).await;
+        assert_eq!(
+            extract_data!(result),
+            Some(object_value(vec![(
+                "songStats",
+                q::Value::List(vec![
+                    object_value(vec![
+                        ("id", q::Value::String(String::from("s1"))),
+                        ("royalties", q::Value::Int(q::Number::from(7))),
+                    ]),
+                    object_value(vec![
+                        ("id", q::Value::String(String::from("s2"))),
+                        ("royalties", q::Value::Null),
+                    ]),
+                ])
+            )]))
+        );
+        let result = execute_query(&id, query(filter, &["ops"])).await;
+        assert_eq!(
+            extract_data!(result),
+            Some(object_value(vec![(
+                "songStats",
+                q::Value::List(vec![object_value(vec![(
+                    "id",
+                    q::Value::String(String::from("s1"))
+                )])])
+            )]))
+        );
+    })
+}
+
 #[test]
 fn cannot_filter_by_derved_relationship_fields() {
     run_test_sequentially(setup, |_, id| async move {
@@ -1457,8 +1792,27 @@ fn query_at_block() {
         const BLOCK_NOT_INDEXED: &str = "subgraph graphqlTestsQuery has only indexed \
          up to block number 1 and data for block number 7000 is therefore not yet available";
         const BLOCK_HASH_NOT_FOUND: &str = "no block with that hash found";
+        const BLOCK_HASH_NOT_INDEXED: &str = "subgraph graphqlTestsQuery has not indexed block \
+         f8ccbd3877eb98c958614f395dd351211afb9abba187bfc1fb4ac414b099c4a6 with number 2 yet";
+
+        const BLOCK_PAST_I32_NOT_INDEXED: &str = "subgraph graphqlTestsQuery has only indexed \
+         up to block number 1 and data for block number 5000000000 is therefore not yet available";
 
         musicians_at(&id, "number: 7000", Err(BLOCK_NOT_INDEXED), "n7000").await;
+        musicians_at(
+            &id,
+            "number: 5000000000",
+            Err(BLOCK_PAST_I32_NOT_INDEXED),
+            "n5000000000",
+        )
+        .await;
+        musicians_at(
+            &id,
+            "number: \"5000000000\"",
+            Err(BLOCK_PAST_I32_NOT_INDEXED),
+            "s5000000000",
+        )
+        .await;
         musicians_at(&id, "number: 0", Ok(vec!["m1", "m2"]), "n0").await;
         musicians_at(&id, "number: 1", Ok(vec!["m1", "m2", "m3", "m4"]), "n1").await;
 
@@ -1470,13 +1824,7 @@ fn query_at_block() {
             "h1",
         )
         .await;
-        musicians_at(
-            &id,
-            &hash(&*BLOCK_TWO),
-            Ok(vec!["m1", "m2", "m3", "m4"]),
-            "h2",
-        )
-        .await;
+        musicians_at(&id, &hash(&*BLOCK_TWO), Err(BLOCK_HASH_NOT_INDEXED), "h2").await;
         musicians_at(&id, &hash(&*BLOCK_THREE), Err(BLOCK_HASH_NOT_FOUND), "h3").await;
     })
 }
@@ -1490,12 +1838,14 @@ fn query_at_block_with_vars() {
 
         async fn musicians_at_nr(
             id: &SubgraphDeploymentId,
-            block: i32,
+            block: i64,
             expected: Result<Vec<&str>, &str>,
             qid: &str,
         ) {
-            let query = "query by_nr($block: Int!) { musicians(block: { number: $block }) { id } }";
-            let number = q::Value::Int(q::Number::from(block));
+            let query =
+                "query by_nr($block: BigInt!) { musicians(block: { number: $block }) { id } }";
+            // `BigInt`s are represented as `String`s
+            let number = q::Value::String(block.to_string());
             let var = Some(("block", number.clone()));
 
             check_musicians_at(id, query, var, expected.clone(), qid).await;
@@ -1525,14 +1875,26 @@ fn query_at_block_with_vars() {
         const BLOCK_NOT_INDEXED: &str = "subgraph graphqlTestsQuery has only indexed \
          up to block number 1 and data for block number 7000 is therefore not yet available";
         const BLOCK_HASH_NOT_FOUND: &str = "no block with that hash found";
+        const BLOCK_HASH_NOT_INDEXED: &str = "subgraph graphqlTestsQuery has not indexed block \
+         f8ccbd3877eb98c958614f395dd351211afb9abba187bfc1fb4ac414b099c4a6 with number 2 yet";
+
+        const BLOCK_PAST_I32_NOT_INDEXED: &str = "subgraph graphqlTestsQuery has only indexed \
+         up to block number 1 and data for block number 5000000000 is therefore not yet available";
 
         musicians_at_nr(&id, 7000, Err(BLOCK_NOT_INDEXED), "n7000").await;
+        musicians_at_nr(
+            &id,
+            5_000_000_000,
+            Err(BLOCK_PAST_I32_NOT_INDEXED),
+            "n5000000000",
+        )
+        .await;
         musicians_at_nr(&id, 0, Ok(vec!["m1", "m2"]), "n0").await;
         musicians_at_nr(&id, 1, Ok(vec!["m1", "m2", "m3", "m4"]), "n1").await;
 
         musicians_at_hash(&id, &GENESIS_BLOCK, Ok(vec!["m1", "m2"]), "h0").await;
         musicians_at_hash(&id, &BLOCK_ONE, Ok(vec!["m1", "m2", "m3", "m4"]), "h1").await;
-        musicians_at_hash(&id, &BLOCK_TWO, Ok(vec!["m1", "m2", "m3", "m4"]), "h2").await;
+        musicians_at_hash(&id, &BLOCK_TWO, Err(BLOCK_HASH_NOT_INDEXED), "h2").await;
         musicians_at_hash(&id, &BLOCK_THREE, Err(BLOCK_HASH_NOT_FOUND), "h3").await;
     })
 }
@@ -1607,7 +1969,7 @@ fn can_query_meta() {
             _meta: object! {
                 block: object! {
                     hash: "0x8511fa04b64657581e3f00e14543c1d522d5d7e771b54aa3060b662ade47da13",
-                    number: 1,
+                    number: "1",
                     __typename: "_Block_"
                 },
                 deployment: "graphqlTestsQuery",
@@ -1627,7 +1989,7 @@ fn can_query_meta() {
             _meta: object! {
                 block: object! {
                     hash: q::Value::Null,
-                    number: 0
+                    number: "0"
                 },
                 deployment: "graphqlTestsQuery"
             },
@@ -1646,7 +2008,7 @@ fn can_query_meta() {
             _meta: object! {
                 block: object! {
                     hash: "0xbd34884280958002c51d3f7b5f853e6febeba33de0f40d15b0363006533c924f",
-                    number: 0
+                    number: "0"
                 },
                 deployment: "graphqlTestsQuery"
             },
@@ -1664,6 +2026,36 @@ fn can_query_meta() {
     })
 }
 
+#[test]
+fn can_query_indexing_status() {
+    run_test_sequentially(setup, |_, id| async move {
+        // Data and indexing status come back from the same query, and the
+        // status describes the latest state even with a block constraint
+        let query = "query { musician(id: \"m1\") { id } \
+                       _meta(block: { number: 0 }) { \
+                         indexing { health fatalError { message } nonFatalErrors { message } \
+                                    latestBlock { number } __typename } } }";
+        let query = graphql_parser::parse_query(query)
+            .expect("invalid test query")
+            .into_static();
+
+        let result = execute_query_document(&id, query).await;
+        let exp = object! {
+            musician: object! { id: "m1" },
+            _meta: object! {
+                indexing: object! {
+                    health: q::Value::Enum("healthy".to_string()),
+                    fatalError: q::Value::Null,
+                    nonFatalErrors: Vec::<q::Value>::new(),
+                    latestBlock: object! { number: "1" },
+                    __typename: "_IndexingStatus_"
+                }
+            },
+        };
+        assert_eq!(extract_data!(result), Some(exp));
+    })
+}
+
 #[test]
 fn non_fatal_errors() {
     use serde_json::json;
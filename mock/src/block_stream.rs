@@ -57,7 +57,9 @@ impl BlockStreamBuilder for MockBlockStreamBuilder {
         _: EthereumCallFilter,
         _: EthereumBlockFilter,
         _: bool,
+        _: u64,
         _: Arc<BlockStreamMetrics>,
+        _: Arc<DeploymentMemory>,
     ) -> Self::Stream {
         MockBlockStream::new()
     }
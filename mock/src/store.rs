@@ -4,7 +4,8 @@ use std::collections::BTreeMap;
 
 use graph::components::store::EntityType;
 use graph::components::store::StoredDynamicDataSource;
-use graph::data::subgraph::schema::SubgraphError;
+use graph::components::subgraph::ProofOfIndexingVersion;
+use graph::data::subgraph::schema::{SkippedTrigger, SubgraphError};
 use graph::prelude::*;
 use web3::types::{Address, H256};
 
@@ -44,7 +45,11 @@ mock! {
             offset: u64,
         ) -> Result<Option<EthereumBlock>, Error>;
 
-        fn cleanup_cached_blocks(&self, ancestor_count: u64) -> Result<(BlockNumber, usize), Error>;
+        fn cleanup_cached_blocks(
+            &self,
+            ancestor_count: u64,
+            retention: BlockRetention,
+        ) -> Result<(BlockNumber, usize), Error>;
 
         fn block_hashes_by_block_number(&self, number: u64) -> Result<Vec<H256>, Error>;
 
@@ -71,6 +76,7 @@ impl SubgraphStore for MockStore {
         &self,
         _subgraph_id: &SubgraphDeploymentId,
         _ids_for_type: BTreeMap<&EntityType, Vec<&str>>,
+        _block: BlockNumber,
     ) -> Result<BTreeMap<EntityType, Vec<Entity>>, StoreError> {
         unimplemented!()
     }
@@ -82,11 +88,19 @@ impl SubgraphStore for MockStore {
         unimplemented!()
     }
 
+    fn proof_of_indexing_versions(
+        &self,
+        _subgraph_id: &SubgraphDeploymentId,
+    ) -> Result<Vec<ProofOfIndexingVersion>, StoreError> {
+        unimplemented!()
+    }
+
     fn get_proof_of_indexing<'a>(
         self: Arc<Self>,
         _subgraph_id: &'a SubgraphDeploymentId,
         _indexer: &'a Option<Address>,
         _block: EthereumBlockPointer,
+        _version: Option<ProofOfIndexingVersion>,
     ) -> DynTryFuture<'a, Option<[u8; 32]>> {
         unimplemented!()
     }
@@ -99,6 +113,21 @@ impl SubgraphStore for MockStore {
         unimplemented!()
     }
 
+    fn find_stream(&self, _query: EntityQuery) -> Result<EntityStream, QueryExecutionError> {
+        unimplemented!()
+    }
+
+    fn count(&self, _query: EntityQuery) -> Result<u64, QueryExecutionError> {
+        unimplemented!()
+    }
+
+    fn aggregate(
+        &self,
+        _query: EntityAggregationQuery,
+    ) -> Result<Vec<AggregateRow>, QueryExecutionError> {
+        unimplemented!()
+    }
+
     fn find_ens_name(&self, _hash: &str) -> Result<Option<String>, QueryExecutionError> {
         unimplemented!()
     }
@@ -110,6 +139,7 @@ impl SubgraphStore for MockStore {
         _mods: Vec<EntityModification>,
         _stopwatch: StopwatchMetrics,
         _deterministic_errors: Vec<SubgraphError>,
+        _skipped_triggers: Vec<SkippedTrigger>,
     ) -> Result<(), StoreError> {
         unimplemented!()
     }
@@ -135,6 +165,9 @@ impl SubgraphStore for MockStore {
             reorg_count: 0,
             max_reorg_depth: 0,
             latest_ethereum_block_number: 0,
+            history: None,
+            history_pruned_below: None,
+            subscriptions_disabled: false,
         })
     }
 
@@ -220,4 +253,78 @@ impl SubgraphStore for MockStore {
     fn network_name(&self, _: &SubgraphDeploymentId) -> Result<String, StoreError> {
         unimplemented!()
     }
+
+    fn skipped_triggers(
+        &self,
+        _: &SubgraphDeploymentId,
+    ) -> Result<Vec<SkippedTrigger>, StoreError> {
+        unimplemented!()
+    }
+
+    fn mark_skipped_triggers_replayed(
+        &self,
+        _: &SubgraphDeploymentId,
+        _: BlockNumber,
+        _: &SubgraphDeploymentId,
+    ) -> Result<usize, StoreError> {
+        unimplemented!()
+    }
+
+    fn set_subscriptions_disabled(
+        &self,
+        _: &SubgraphDeploymentId,
+        _: bool,
+    ) -> Result<(), StoreError> {
+        unimplemented!()
+    }
+
+    fn deployment_context(
+        &self,
+        _: &SubgraphDeploymentId,
+    ) -> Result<BTreeMap<String, String>, StoreError> {
+        unimplemented!()
+    }
+
+    fn set_deployment_context(
+        &self,
+        _: &SubgraphDeploymentId,
+        _: String,
+        _: Option<String>,
+    ) -> Result<(), StoreError> {
+        unimplemented!()
+    }
+
+    fn register_change_log_consumer(
+        &self,
+        _: &SubgraphDeploymentId,
+        _: &str,
+    ) -> Result<i64, StoreError> {
+        unimplemented!()
+    }
+
+    fn remove_change_log_consumer(
+        &self,
+        _: &SubgraphDeploymentId,
+        _: &str,
+    ) -> Result<(), StoreError> {
+        unimplemented!()
+    }
+
+    fn entity_changes(
+        &self,
+        _: &SubgraphDeploymentId,
+        _: &str,
+        _: usize,
+    ) -> Result<Vec<LoggedEntityChange>, StoreError> {
+        unimplemented!()
+    }
+
+    fn ack_entity_changes(
+        &self,
+        _: &SubgraphDeploymentId,
+        _: &str,
+        _: i64,
+    ) -> Result<(), StoreError> {
+        unimplemented!()
+    }
 }
@@ -77,8 +77,40 @@ pub enum Command {
     /// Record which deployments are unused with `record`, then remove them
     /// with `remove`
     Unused(UnusedCommand),
+    /// Shrink the history of a subgraph by regrafting it
+    ///
+    /// Deploys a new version of the subgraph that is grafted onto the
+    /// current version at `block`, deletes the history before that block
+    /// from the new deployment once it has copied the data of the current
+    /// version, waits until graph-node has made it the current version, and
+    /// finally records the old deployment as unused. graph-node should run
+    /// with `GRAPH_SUBGRAPH_VERSION_SWITCHING_MODE=synced` so that queries
+    /// go to the old deployment until the new one has synced. It is safe
+    /// to interrupt the command and run it again later
+    Regraft {
+        /// The name of the subgraph
+        name: String,
+        /// The block at which to graft (default: the latest block of the
+        /// current version minus the reorg threshold)
+        #[structopt(long)]
+        block: Option<i64>,
+        /// The IPFS node that stores the manifest of the subgraph
+        #[structopt(long, default_value = "http://localhost:5001")]
+        ipfs: String,
+        /// The JSON-RPC admin server of graph-node
+        #[structopt(long, default_value = "http://localhost:8020")]
+        admin: String,
+        /// A bearer token for the admin server
+        #[structopt(long)]
+        token: Option<String>,
+        /// The key for the namespace of the subgraph
+        #[structopt(long)]
+        key: Option<String>,
+    },
     /// Check the configuration file
     Check,
+    /// Manage the block cache of chains
+    Chain(ChainCommand),
 }
 
 #[derive(Clone, Debug, StructOpt)]
@@ -105,6 +137,59 @@ pub enum UnusedCommand {
     },
 }
 
+#[derive(Clone, Debug, StructOpt)]
+pub enum ChainCommand {
+    /// Import blocks into the block cache of a chain
+    ///
+    /// Blocks are read from a file with an export of the block cache of
+    /// another graph-node, or directly from the database of another
+    /// graph-node if `from` is a `postgresql://` URL. Blocks that are
+    /// already in the block cache are only replaced if the imported block
+    /// has transaction receipts
+    Import {
+        /// The name of the chain
+        network: String,
+        /// The file or database URL to import blocks from
+        from: String,
+        /// How many blocks to import in one transaction
+        #[structopt(long, default_value = "1000")]
+        batch_size: usize,
+    },
+    /// Export the block cache of a chain to a file
+    ///
+    /// The file has a header line followed by one block per line as JSON
+    /// and can be imported into another graph-node with `import`
+    Export {
+        /// The name of the chain
+        network: String,
+        /// The file to write the blocks to
+        file: String,
+        /// The number of the first block to export
+        #[structopt(long, default_value = "0")]
+        from: i64,
+        /// Only export blocks before this block number (default: all)
+        #[structopt(long)]
+        to: Option<i64>,
+    },
+    /// Move the block cache of a chain to a different shard
+    ///
+    /// The configuration file must already place the chain in the new
+    /// shard. Blocks are copied from the shard that stores the chain now,
+    /// and the chain is then removed from that shard; the call cache is
+    /// not copied. Running graph-node processes keep using the old shard
+    /// until they are restarted with the new configuration and should be
+    /// stopped while the chain is being moved
+    Move {
+        /// The name of the chain
+        network: String,
+        /// The shard to move the chain to
+        shard: String,
+        /// How many blocks to copy in one transaction
+        #[structopt(long, default_value = "1000")]
+        batch_size: usize,
+    },
+}
+
 impl From<Opt> for config::Opt {
     fn from(opt: Opt) -> Self {
         let mut config_opt = config::Opt::default();
@@ -190,6 +275,63 @@ async fn main() {
                 }
             }
         }
+        Chain(cmd) => {
+            use ChainCommand::*;
+
+            match cmd {
+                Import {
+                    network,
+                    from,
+                    batch_size,
+                } => {
+                    let builder = StoreBuilder::new(&logger, &config, make_registry(&logger));
+                    commands::chain::import(builder, network, from, batch_size)
+                }
+                Export {
+                    network,
+                    file,
+                    from,
+                    to,
+                } => {
+                    let pool = make_main_pool(&logger, &config);
+                    let builder = StoreBuilder::new(&logger, &config, make_registry(&logger));
+                    commands::chain::export(pool, builder, network, file, from, to)
+                }
+                Move {
+                    network,
+                    shard,
+                    batch_size,
+                } => {
+                    let pool = make_main_pool(&logger, &config);
+                    commands::chain::move_chain(
+                        &logger,
+                        &config,
+                        make_registry(&logger),
+                        pool,
+                        network,
+                        shard,
+                        batch_size,
+                    )
+                }
+            }
+        }
+        Regraft {
+            name,
+            block,
+            ipfs,
+            admin,
+            token,
+            key,
+        } => {
+            let store = make_store(&logger, &config);
+            let endpoints = commands::regraft::Endpoints {
+                ipfs,
+                admin,
+                token,
+                key,
+            };
+            commands::regraft::run(logger.clone(), store, endpoints, name, block).await
+        }
         Check => match config.to_json() {
             Ok(txt) => {
                 println!("{}", txt);
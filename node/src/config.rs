@@ -2,7 +2,9 @@ use graph::{
     components::ethereum::NodeCapabilities,
     prelude::{
         anyhow::{anyhow, Context, Result},
-        info, serde_json, Logger, NodeId,
+        info, serde_json,
+        web3::types::H256,
+        Logger, NodeId,
     },
 };
 use graph_chain_ethereum::CLEANUP_BLOCKS;
@@ -346,6 +348,7 @@ impl ChainSection {
                 };
                 let entry = chains.entry(name.to_string()).or_insert_with(|| Chain {
                     shard: PRIMARY_SHARD.to_string(),
+                    genesis_hash: None,
                     providers: vec![],
                 });
                 entry.providers.push(provider);
@@ -358,6 +361,10 @@ impl ChainSection {
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct Chain {
     pub shard: String,
+    /// The hash of the genesis block that all providers for the chain
+    /// must report
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub genesis_hash: Option<String>,
     #[serde(rename = "provider")]
     pub providers: Vec<Provider>,
 }
@@ -366,11 +373,27 @@ impl Chain {
     fn validate(&self) -> Result<()> {
         // `Config` validates that `self.shard` references a configured shard
 
+        if let Some(hash) = &self.genesis_hash {
+            hash.trim_start_matches("0x")
+                .parse::<H256>()
+                .map_err(|e| anyhow!("the genesis hash `{}` is invalid: {}", hash, e))?;
+        }
+
         for provider in &self.providers {
             provider.validate()?
         }
         Ok(())
     }
+
+    /// The expected hash of the genesis block. Only call this on a chain
+    /// that has been validated
+    pub fn genesis_block_hash(&self) -> Option<H256> {
+        self.genesis_hash.as_ref().map(|hash| {
+            hash.trim_start_matches("0x")
+                .parse()
+                .expect("a validated chain has a valid genesis hash")
+        })
+    }
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
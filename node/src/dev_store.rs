@@ -0,0 +1,166 @@
+//! A private database for development mode. When `--dev` is given without
+//! `--postgres-url` or `--config`, we run our own Postgres server in
+//! `GRAPH_DEV_DATA_DIR` so that no database has to be set up. The server
+//! only listens on a Unix socket in that directory, and keeps running
+//! after graph-node exits so that the next start does not have to wait for
+//! it; removing the directory after stopping the server with
+//! `pg_ctl -D <dir>/data stop` resets everything. The server is started
+//! with the `initdb` and `pg_ctl` programs that are on the `PATH` or in
+//! `GRAPH_DEV_POSTGRES_BIN`, and needs the `pg_trgm`, `btree_gist` and
+//! `postgres_fdw` extensions from Postgres' contrib package
+//!
+//! Anybody who can connect to the server is a superuser without a
+//! password, which makes it possible to run programs as the developer. The
+//! directory therefore has to belong to the developer and must not be
+//! accessible to anybody else, and so does the socket in it
+use std::env;
+use std::fs;
+use std::os::unix::fs::{DirBuilderExt, MetadataExt};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use graph::prelude::{
+    anyhow::{anyhow, Context},
+    info, Error, Logger,
+};
+use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
+
+/// The database user that owns everything in the development database
+const DEV_USER: &str = "graph";
+
+/// The directory that holds the data and the socket of the development
+/// database. Defaults to `graph-node-dev` in the user's data directory,
+/// i.e., in `$XDG_DATA_HOME` or `~/.local/share`
+fn data_dir() -> Result<PathBuf, Error> {
+    if let Some(dir) = env::var_os("GRAPH_DEV_DATA_DIR") {
+        return Ok(PathBuf::from(dir));
+    }
+    let data_home = match env::var_os("XDG_DATA_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => env::var_os("HOME")
+            .map(|home| Path::new(&home).join(".local").join("share"))
+            .ok_or_else(|| {
+                anyhow!("set GRAPH_DEV_DATA_DIR or HOME for the development database")
+            })?,
+    };
+    Ok(data_home.join("graph-node-dev"))
+}
+
+/// Create `dir` so that only the current user can access it, or make sure
+/// that an existing `dir` belongs to the current user and can not be
+/// accessed by anybody else
+fn private_dir(dir: &Path) -> Result<(), Error> {
+    if !dir.exists() {
+        if let Some(parent) = dir.parent() {
+            fs::create_dir_all(parent)
+                .with_context(|| format!("failed to create {}", parent.display()))?;
+        }
+        fs::DirBuilder::new()
+            .mode(0o700)
+            .create(dir)
+            .with_context(|| format!("failed to create {}", dir.display()))?;
+    }
+
+    // Do not follow symlinks, since whoever made the link could change
+    // where it points to
+    let meta = fs::symlink_metadata(dir)
+        .with_context(|| format!("failed to read the metadata of {}", dir.display()))?;
+    // Safe since `geteuid` can not fail and has no side effects
+    let uid = unsafe { libc::geteuid() };
+    if !meta.is_dir() || meta.uid() != uid {
+        return Err(anyhow!(
+            "{} must be a directory that belongs to the current user",
+            dir.display()
+        ));
+    }
+    if meta.mode() & 0o077 != 0 {
+        return Err(anyhow!(
+            "{} must only be accessible to the current user; fix that with `chmod 700 {}`",
+            dir.display(),
+            dir.display()
+        ));
+    }
+    Ok(())
+}
+
+/// The Postgres program `name`, from `GRAPH_DEV_POSTGRES_BIN` if that is
+/// set, and from the `PATH` otherwise
+fn program(name: &str) -> Command {
+    match env::var_os("GRAPH_DEV_POSTGRES_BIN") {
+        Some(dir) => Command::new(Path::new(&dir).join(name)),
+        None => Command::new(name),
+    }
+}
+
+/// Run `command` and fail with its error output if it does not succeed
+fn run(mut command: Command, what: &str) -> Result<(), Error> {
+    let output = command
+        .output()
+        .with_context(|| format!("failed to run {:?}", command))?;
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(anyhow!(
+            "{} failed: {}",
+            what,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ))
+    }
+}
+
+/// Create the development database if it does not exist yet and make sure
+/// its server is running. Returns the URL for connecting to it
+pub fn start(logger: &Logger) -> Result<String, Error> {
+    let dir = data_dir()?;
+    let data = dir.join("data");
+    let host = dir
+        .to_str()
+        .ok_or_else(|| anyhow!("{} is not valid UTF-8", dir.display()))?;
+
+    private_dir(&dir)?;
+    if !data.join("PG_VERSION").exists() {
+        info!(logger, "Creating development database"; "dir" => host);
+        let mut initdb = program("initdb");
+        initdb.arg("-D").arg(&data).args(&[
+            "-U",
+            DEV_USER,
+            "-A",
+            "trust",
+            "-E",
+            "UTF8",
+            "--locale=C",
+        ]);
+        run(initdb, "creating the development database")?;
+    }
+
+    // `pg_ctl status` exits with status 3 if the server is not running
+    let mut status = program("pg_ctl");
+    status.arg("-D").arg(&data).arg("status");
+    let running = status
+        .output()
+        .context("failed to run pg_ctl")?
+        .status
+        .success();
+    if !running {
+        info!(logger, "Starting development database"; "dir" => host);
+        let mut start = program("pg_ctl");
+        start
+            .arg("-D")
+            .arg(&data)
+            .arg("-l")
+            .arg(dir.join("postgres.log"))
+            .arg("-o")
+            .arg(format!(
+                "-c listen_addresses='' -c unix_socket_permissions=0700 -k '{}'",
+                host
+            ))
+            .args(&["-w", "start"]);
+        run(start, "starting the development database")?;
+    }
+
+    Ok(format!(
+        "postgresql:///postgres?host={}&user={}",
+        utf8_percent_encode(host, NON_ALPHANUMERIC),
+        DEV_USER
+    ))
+}
@@ -1,28 +1,31 @@
 use git_testament::{git_testament, render_testament};
 use ipfs_api::IpfsClient;
-use lazy_static::lazy_static;
 use prometheus::Registry;
+use std::collections::HashMap;
 use std::env;
 use std::io::{BufRead, BufReader};
 use std::path::Path;
-use std::str::FromStr;
 use std::time::Duration;
 use structopt::StructOpt;
 use tokio::sync::mpsc;
 
 use graph::components::forward;
 use graph::components::{
-    ethereum::{EthereumNetworks, NodeCapabilities},
-    store::BlockStore,
+    ethereum::{EthereumNetworks, NodeCapabilities, ANCESTOR_COUNT, REORG_THRESHOLD},
+    store::{BlockStore, StatusStore},
 };
 use graph::data::graphql::effort::LoadManager;
+use graph::data::subgraph::status::ShadowComparisons;
 use graph::log::logger;
-use graph::prelude::{IndexNodeServer as _, JsonRpcServer as _, *};
+use graph::prelude::web3::types::H256;
+use graph::prelude::{IndexNodeServer as _, JsonRpcServer as _, MetricsRegistry as _, *};
 use graph::util::security::SafeDisplay;
 use graph_chain_arweave::adapter::ArweaveAdapter;
-use graph_chain_ethereum::{network_indexer, BlockIngestor, BlockStreamBuilder, Transport};
+use graph_chain_ethereum::{
+    network_indexer, BlockIngestor, BlockStreamBuilder, ReorgAlerter, Transport,
+};
 use graph_core::{
-    three_box::ThreeBoxAdapter, LinkResolver, MetricsRegistry,
+    three_box::ThreeBoxAdapter, LinkResolver, MetricsRegistry, ShadowMonitor,
     SubgraphAssignmentProvider as IpfsSubgraphAssignmentProvider, SubgraphInstanceManager,
     SubgraphRegistrar as IpfsSubgraphRegistrar,
 };
@@ -36,28 +39,13 @@ use graph_server_websocket::SubscriptionServer as GraphQLSubscriptionServer;
 use graph_store_postgres::BlockStore as DieselBlockStore;
 
 mod config;
+mod dev_store;
 mod opt;
 mod store_builder;
 
 use config::Config;
 use store_builder::StoreBuilder;
 
-lazy_static! {
-    // Default to an Ethereum reorg threshold to 50 blocks
-    static ref REORG_THRESHOLD: u64 = env::var("ETHEREUM_REORG_THRESHOLD")
-        .ok()
-        .map(|s| u64::from_str(&s)
-            .unwrap_or_else(|_| panic!("failed to parse env var ETHEREUM_REORG_THRESHOLD")))
-        .unwrap_or(50);
-
-    // Default to an ancestor count of 50 blocks
-    static ref ANCESTOR_COUNT: u64 = env::var("ETHEREUM_ANCESTOR_COUNT")
-        .ok()
-        .map(|s| u64::from_str(&s)
-             .unwrap_or_else(|_| panic!("failed to parse env var ETHEREUM_ANCESTOR_COUNT")))
-        .unwrap_or(50);
-}
-
 git_testament!(TESTAMENT);
 
 fn read_expensive_queries() -> Result<Vec<Arc<q::Document>>, std::io::Error> {
@@ -100,7 +88,7 @@ async fn main() {
     #[cfg(debug_assertions)]
     std::mem::forget(fail::FailScenario::setup());
 
-    let opt = opt::Opt::from_args();
+    let mut opt = opt::Opt::from_args();
 
     // Set up logger
     let logger = logger(opt.debug);
@@ -112,6 +100,18 @@ async fn main() {
         render_testament!(TESTAMENT)
     );
 
+    // In development mode, use our own database unless we were told
+    // which one to use
+    if opt.dev && opt.postgres_url.is_none() && opt.config.is_none() {
+        match dev_store::start(&logger) {
+            Ok(url) => opt.postgres_url = Some(url),
+            Err(e) => {
+                eprintln!("development database error: {:#}", e);
+                std::process::exit(1);
+            }
+        }
+    }
+
     let config = match Config::load(&logger, &opt.clone().into()) {
         Err(e) => {
             eprintln!("configuration error: {}", e);
@@ -133,6 +133,7 @@ async fn main() {
 
     // Obtain subgraph related command-line arguments
     let subgraph = opt.subgraph.clone();
+    let dev = opt.dev;
 
     // Obtain ports to use for the GraphQL server(s)
     let http_port = opt.http_port;
@@ -173,7 +174,47 @@ async fn main() {
     let ipfs_clients: Vec<_> = create_ipfs_clients(&logger, &opt.ipfs);
 
     // Convert the client into a link resolver
-    let link_resolver = Arc::new(LinkResolver::from(ipfs_clients));
+    let link_resolver = LinkResolver::from(ipfs_clients);
+
+    // In development mode, `--subgraph` can point to a manifest on the
+    // local filesystem instead of a file on IPFS
+    let (link_resolver, subgraph) = match subgraph {
+        Some(subgraph) if dev => {
+            let (name, manifest) = match subgraph.find(':') {
+                Some(pos) => (&subgraph[..pos + 1], &subgraph[pos + 1..]),
+                None => ("", subgraph.as_str()),
+            };
+            if Path::new(manifest).is_file() {
+                let (link_resolver, id) = link_resolver
+                    .with_local_manifest(Path::new(manifest))
+                    .expect("Failed to read the subgraph manifest");
+                info!(logger, "Serving subgraph from local manifest";
+                      "manifest" => manifest, "deployment" => id.as_str());
+                let subgraph = format!("{}{}", name, id);
+                (link_resolver, Some(subgraph))
+            } else {
+                (link_resolver, Some(subgraph))
+            }
+        }
+        subgraph => (link_resolver, subgraph),
+    };
+    let link_resolver = Arc::new(link_resolver);
+
+    // Parse the `--subgraph` argument into a name and a deployment
+    let subgraph = subgraph.map(|subgraph| {
+        let (name, hash) = if subgraph.contains(':') {
+            let mut split = subgraph.split(':');
+            (split.next().unwrap(), split.next().unwrap().to_owned())
+        } else {
+            ("cli", subgraph.clone())
+        };
+
+        let name = SubgraphName::new(name)
+            .expect("Subgraph name must contain only a-z, A-Z, 0-9, '-' and '_'");
+        let subgraph_id =
+            SubgraphDeploymentId::new(hash).expect("Subgraph hash must be a valid IPFS hash");
+        (name, subgraph_id)
+    });
 
     // Set up Prometheus registry
     let prometheus_registry = Arc::new(Registry::new());
@@ -193,15 +234,20 @@ async fn main() {
 
     let stores_logger = logger.clone();
     let stores_error_logger = logger.clone();
-    let stores_eth_networks = eth_networks.clone();
     let contention_logger = logger.clone();
 
     let expensive_queries = read_expensive_queries().unwrap();
 
     let store_builder = StoreBuilder::new(&logger, &config, metrics_registry.cheap_clone());
+    let genesis_hashes: HashMap<_, _> = config
+        .chains
+        .chains
+        .iter()
+        .filter_map(|(name, chain)| chain.genesis_block_hash().map(|hash| (name.clone(), hash)))
+        .collect();
 
     graph::spawn(
-        futures::stream::FuturesOrdered::from_iter(stores_eth_networks.flatten().into_iter().map(
+        futures::stream::FuturesOrdered::from_iter(eth_networks.flatten().into_iter().map(
             |(network_name, capabilities, eth_adapter)| {
                 info!(
                     logger, "Connecting to Ethereum...";
@@ -210,7 +256,9 @@ async fn main() {
                 );
                 eth_adapter
                     .net_identifiers(&logger)
-                    .map(move |network_identifier| (network_name, capabilities, network_identifier))
+                    .map(move |network_identifier| {
+                        (network_name, capabilities, eth_adapter, network_identifier)
+                    })
                     .compat()
             },
         ))
@@ -219,18 +267,28 @@ async fn main() {
             error!(stores_error_logger, "Was a valid Ethereum node provided?");
             panic!("Failed to connect to Ethereum node: {}", e);
         })
-        .map(move |(network_name, capabilities, network_identifier)| {
-            info!(
-                stores_logger,
-                "Connected to Ethereum";
-                "network" => &network_name,
-                "network_version" => &network_identifier.net_version,
-                "capabilities" => &capabilities
-            );
-            (network_name, network_identifier)
-        })
+        .map(
+            move |(network_name, capabilities, eth_adapter, network_identifier)| {
+                info!(
+                    stores_logger,
+                    "Connected to Ethereum";
+                    "network" => &network_name,
+                    "network_version" => &network_identifier.net_version,
+                    "capabilities" => &capabilities
+                );
+                (network_name, capabilities, eth_adapter, network_identifier)
+            },
+        )
         .collect()
-        .and_then(move |networks| {
+        .and_then(move |providers| {
+            // Only use providers that are on the chain we expect
+            let (eth_networks, networks) = check_genesis_hashes(
+                &logger,
+                &genesis_hashes,
+                metrics_registry.as_ref(),
+                providers,
+            );
+
             let subscription_manager = store_builder.subscription_manager();
             let network_store = store_builder.network_store(networks);
             let load_manager = Arc::new(LoadManager::new(
@@ -257,10 +315,14 @@ async fn main() {
                 network_store.store(),
             );
 
+            let shadows = Arc::new(ShadowComparisons::default());
+            ShadowMonitor::new(&logger_factory, network_store.clone(), shadows.clone()).start();
+
             let mut index_node_server = IndexNodeServer::new(
                 &logger_factory,
                 graphql_runner.clone(),
                 network_store.clone(),
+                shadows,
             );
 
             // Spawn Ethereum network indexers for all networks that are to be indexed
@@ -308,6 +370,8 @@ async fn main() {
                     block_polling_interval,
                     &eth_networks,
                     network_store.block_store(),
+                    network_store.store(),
+                    metrics_registry.clone(),
                     &logger_factory,
                 );
             }
@@ -361,10 +425,17 @@ async fn main() {
                     .expect("invalid version switching mode"),
             );
 
+            // In development mode, always index the subgraph from `--subgraph`
+            // from scratch. This has to happen before the registrar starts
+            // the subgraphs that are assigned to this node
+            if let (true, Some((name, subgraph_id))) = (dev, &subgraph) {
+                reset_dev_subgraph(&logger, &network_store.store().store(), name, subgraph_id);
+            }
+
             // Create named subgraph provider for resolving subgraph name->ID mappings
             let subgraph_registrar = Arc::new(IpfsSubgraphRegistrar::new(
                 &logger_factory,
-                link_resolver,
+                link_resolver.clone(),
                 Arc::new(subgraph_provider),
                 network_store.store(),
                 subscription_manager,
@@ -386,6 +457,9 @@ async fn main() {
                 http_port,
                 ws_port,
                 subgraph_registrar.clone(),
+                network_store.clone(),
+                link_resolver,
+                graph_server_json_rpc::authorizer_from_env(),
                 node_id.clone(),
                 logger.clone(),
             )
@@ -395,19 +469,7 @@ async fn main() {
             std::mem::forget(json_rpc_server);
 
             // Add the CLI subgraph with a REST request to the admin server.
-            if let Some(subgraph) = subgraph {
-                let (name, hash) = if subgraph.contains(':') {
-                    let mut split = subgraph.split(':');
-                    (split.next().unwrap(), split.next().unwrap().to_owned())
-                } else {
-                    ("cli", subgraph)
-                };
-
-                let name = SubgraphName::new(name)
-                    .expect("Subgraph name must contain only a-z, A-Z, 0-9, '-' and '_'");
-                let subgraph_id = SubgraphDeploymentId::new(hash)
-                    .expect("Subgraph hash must be a valid IPFS hash");
-
+            if let Some((name, subgraph_id)) = subgraph {
                 graph::spawn(
                     async move {
                         subgraph_registrar.create_subgraph(name.clone()).await?;
@@ -533,6 +595,27 @@ async fn create_ethereum_networks(
     Ok(parsed_networks)
 }
 
+/// Remove the subgraph `name` and the data for `subgraph_id` so that the
+/// deployment gets indexed from the start again. Used in development mode
+fn reset_dev_subgraph(
+    logger: &Logger,
+    store: &graph_store_postgres::SubgraphStore,
+    name: &SubgraphName,
+    subgraph_id: &SubgraphDeploymentId,
+) {
+    store
+        .remove_subgraph(name.clone())
+        .expect("Failed to remove the development subgraph");
+    match store.remove_deployment(subgraph_id) {
+        Ok(()) => info!(logger, "Removed previous development deployment";
+                        "deployment" => subgraph_id.as_str()),
+        Err(StoreError::DeploymentNotFound(_)) => { /* nothing to reset */ }
+        Err(e) => warn!(logger, "Could not remove previous development deployment";
+                        "deployment" => subgraph_id.as_str(),
+                        "error" => e.to_string()),
+    }
+}
+
 fn create_ipfs_clients(logger: &Logger, ipfs_addresses: &Vec<String>) -> Vec<IpfsClient> {
     // Parse the IPFS URL from the `--ipfs` command line argument
     let ipfs_addresses: Vec<_> = ipfs_addresses
@@ -600,11 +683,65 @@ fn create_ipfs_clients(logger: &Logger, ipfs_addresses: &Vec<String>) -> Vec<Ipf
         .collect()
 }
 
+/// Check that each provider reports the genesis block that is configured
+/// for its chain. Providers that report a different genesis block are on
+/// the wrong chain; we log an error for them, set the
+/// `ethereum_genesis_mismatch` metric, and leave them out of the returned
+/// networks so that we never ingest blocks or make calls through them. A
+/// chain whose providers are all on the wrong chain does not get indexed
+fn check_genesis_hashes(
+    logger: &Logger,
+    genesis_hashes: &HashMap<String, H256>,
+    registry: &MetricsRegistry,
+    providers: Vec<(
+        String,
+        NodeCapabilities,
+        Arc<dyn EthereumAdapter>,
+        EthereumNetworkIdentifier,
+    )>,
+) -> (EthereumNetworks, Vec<(String, EthereumNetworkIdentifier)>) {
+    let mismatches = registry
+        .new_gauge_vec(
+            "ethereum_genesis_mismatch",
+            "Set to 1 for providers whose genesis block differs from the configured one",
+            vec![String::from("network"), String::from("provider")],
+        )
+        .expect("failed to create `ethereum_genesis_mismatch` gauge");
+
+    let mut eth_networks = EthereumNetworks::new();
+    let mut networks = Vec::new();
+    for (network_name, capabilities, eth_adapter, ident) in providers {
+        if let Some(expected) = genesis_hashes.get(&network_name) {
+            if expected != &ident.genesis_block_hash {
+                error!(
+                    logger,
+                    "Ethereum node reports a different genesis block than the one \
+                     configured for its network; not using it";
+                    "network" => &network_name,
+                    "provider" => eth_adapter.url_hostname(),
+                    "genesis_block_hash" => format!("{:x}", ident.genesis_block_hash),
+                    "expected_genesis_block_hash" => format!("{:x}", expected),
+                );
+                mismatches
+                    .with_label_values(&[&network_name, eth_adapter.url_hostname()])
+                    .set(1.0);
+                continue;
+            }
+        }
+        eth_networks.insert(network_name.clone(), capabilities, eth_adapter);
+        networks.push((network_name, ident));
+    }
+    eth_networks.sort();
+    (eth_networks, networks)
+}
+
 fn start_block_ingestor(
     logger: &Logger,
     block_polling_interval: Duration,
     eth_networks: &EthereumNetworks,
     block_store: Arc<DieselBlockStore>,
+    status_store: Arc<dyn StatusStore>,
+    registry: Arc<MetricsRegistry>,
     logger_factory: &LoggerFactory,
 ) {
     // BlockIngestor must be configured to keep at least REORG_THRESHOLD ancestors,
@@ -626,10 +763,22 @@ fn start_block_ingestor(
                 "network_name" => &network_name
             );
             let eth_adapter = eth_adapters.cheapest().unwrap(); //Safe to unwrap since it cannot be empty
+            let chain_store = block_store
+                .chain_store(network_name)
+                .expect("network with name");
+
+            // Watch the chain head updates of the ingestor for deep reorgs
+            let reorg_alerter = ReorgAlerter::new(
+                logger,
+                network_name.to_string(),
+                chain_store.clone(),
+                status_store.clone(),
+                registry.clone(),
+            );
+            graph::spawn(reorg_alerter.run());
+
             let block_ingestor = BlockIngestor::new(
-                block_store
-                    .chain_store(network_name)
-                    .expect("network with name"),
+                chain_store,
                 eth_adapter.clone(),
                 *ANCESTOR_COUNT,
                 network_name.to_string(),
@@ -0,0 +1,360 @@
+use diesel::sql_types::{BigInt, Nullable, Text};
+use diesel::{Connection, PgConnection, RunQueryDsl};
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Lines, Write};
+use std::str::FromStr;
+use std::time::Instant;
+
+use std::sync::Arc;
+
+use graph::components::store::BlockStore as _;
+use graph::prelude::{
+    anyhow::{anyhow, Error},
+    serde_json,
+    web3::types::H256,
+    BlockNumber, CheapClone, EthereumBlock, EthereumNetworkIdentifier, Logger, MetricsRegistry,
+};
+use graph_store_postgres::connection_pool::ConnectionPool;
+use graph_store_postgres::{BlockStore, ChainStore, Shard};
+
+use crate::config::Config;
+use crate::store_builder::StoreBuilder;
+
+/// The first line of a file with an export of the block cache of a chain;
+/// every following line contains one block
+#[derive(Serialize, Deserialize)]
+pub struct ExportHeader {
+    pub network: String,
+    pub net_version: String,
+    pub genesis_block_hash: String,
+}
+
+impl ExportHeader {
+    fn ident(&self) -> Result<EthereumNetworkIdentifier, Error> {
+        Ok(EthereumNetworkIdentifier {
+            net_version: self.net_version.clone(),
+            genesis_block_hash: H256::from_str(self.genesis_block_hash.trim_start_matches("0x"))?,
+        })
+    }
+}
+
+trait BlockSource {
+    /// Return the next batch of about `size` blocks; an empty batch means
+    /// that there are no more blocks
+    fn next_batch(&mut self, size: usize) -> Result<Vec<EthereumBlock>, Error>;
+}
+
+/// Read blocks from an export file
+struct FileSource {
+    lines: Lines<BufReader<File>>,
+}
+
+impl FileSource {
+    fn new(path: &str) -> Result<(EthereumNetworkIdentifier, Self), Error> {
+        let mut lines = BufReader::new(File::open(path)?).lines();
+        let header = lines
+            .next()
+            .ok_or_else(|| anyhow!("the file {} is empty", path))??;
+        let header: ExportHeader = serde_json::from_str(&header)
+            .map_err(|e| anyhow!("the file {} does not start with a header: {}", path, e))?;
+        Ok((header.ident()?, FileSource { lines }))
+    }
+}
+
+impl BlockSource for FileSource {
+    fn next_batch(&mut self, size: usize) -> Result<Vec<EthereumBlock>, Error> {
+        let mut blocks = Vec::new();
+        while blocks.len() < size {
+            match self.lines.next() {
+                Some(line) => blocks.push(serde_json::from_str(&line?)?),
+                None => break,
+            }
+        }
+        Ok(blocks)
+    }
+}
+
+/// Read blocks directly from the database of another graph-node
+struct PeerSource {
+    conn: PgConnection,
+    network: String,
+    /// The table holding the blocks, and whether it is shared between
+    /// networks
+    table: String,
+    shared: bool,
+    next: i64,
+    max: i64,
+}
+
+#[derive(QueryableByName)]
+struct Network {
+    #[sql_type = "Text"]
+    net_version: String,
+    #[sql_type = "Text"]
+    genesis_block_hash: String,
+    #[sql_type = "Text"]
+    namespace: String,
+}
+
+impl Network {
+    /// Look up how `network` is stored in the database `conn` points to
+    fn load(conn: &PgConnection, network: &str) -> Result<Option<Self>, Error> {
+        Ok(diesel::sql_query(
+            "select net_version, genesis_block_hash, namespace \
+               from ethereum_networks where name = $1",
+        )
+        .bind::<Text, _>(network)
+        .get_results::<Network>(conn)?
+        .pop())
+    }
+
+    fn header(&self, network: &str) -> ExportHeader {
+        ExportHeader {
+            network: network.to_string(),
+            net_version: self.net_version.clone(),
+            genesis_block_hash: self.genesis_block_hash.clone(),
+        }
+    }
+}
+
+#[derive(QueryableByName)]
+struct Bounds {
+    #[sql_type = "Nullable<BigInt>"]
+    min: Option<i64>,
+    #[sql_type = "Nullable<BigInt>"]
+    max: Option<i64>,
+}
+
+#[derive(QueryableByName)]
+struct BlockData {
+    #[sql_type = "Text"]
+    data: String,
+}
+
+impl PeerSource {
+    fn new(url: &str, network: &str) -> Result<(EthereumNetworkIdentifier, Self), Error> {
+        let conn = PgConnection::establish(url)?;
+        let info = Network::load(&conn, network)?
+            .ok_or_else(|| anyhow!("the other database does not have a chain {}", network))?;
+        let ident = info.header(network).ident()?;
+
+        let shared = info.namespace == "public";
+        let (table, bounds) = if shared {
+            let table = "public.ethereum_blocks".to_string();
+            let bounds = diesel::sql_query(format!(
+                "select min(number) as min, max(number) as max from {} where network_name = $1",
+                table
+            ))
+            .bind::<Text, _>(network)
+            .get_result::<Bounds>(&conn)?;
+            (table, bounds)
+        } else {
+            let table = format!("{}.blocks", info.namespace);
+            let bounds = diesel::sql_query(format!(
+                "select min(number) as min, max(number) as max from {}",
+                table
+            ))
+            .get_result::<Bounds>(&conn)?;
+            (table, bounds)
+        };
+
+        let source = PeerSource {
+            conn,
+            network: network.to_string(),
+            table,
+            shared,
+            next: bounds.min.unwrap_or(0),
+            max: bounds.max.unwrap_or(-1),
+        };
+        Ok((ident, source))
+    }
+}
+
+impl BlockSource for PeerSource {
+    fn next_batch(&mut self, size: usize) -> Result<Vec<EthereumBlock>, Error> {
+        // Batches are ranges of block numbers; there might be more than
+        // one block for a number, or none at all
+        while self.next <= self.max {
+            let lower = self.next;
+            let upper = lower + size as i64;
+            self.next = upper;
+
+            let rows = if self.shared {
+                diesel::sql_query(format!(
+                    "select data::text as data from {} \
+                      where network_name = $1 and number >= $2 and number < $3",
+                    self.table
+                ))
+                .bind::<Text, _>(&self.network)
+                .bind::<BigInt, _>(lower)
+                .bind::<BigInt, _>(upper)
+                .load::<BlockData>(&self.conn)?
+            } else {
+                diesel::sql_query(format!(
+                    "select data::text as data from {} where number >= $1 and number < $2",
+                    self.table
+                ))
+                .bind::<BigInt, _>(lower)
+                .bind::<BigInt, _>(upper)
+                .load::<BlockData>(&self.conn)?
+            };
+            if !rows.is_empty() {
+                return rows
+                    .into_iter()
+                    .map(|row| serde_json::from_str(&row.data).map_err(Error::from))
+                    .collect();
+            }
+        }
+        Ok(vec![])
+    }
+}
+
+/// Import blocks into the block cache of `network` from `from`, which is
+/// either the path of an export file or the URL of the database of
+/// another graph-node
+pub fn import(
+    builder: StoreBuilder,
+    network: String,
+    from: String,
+    batch_size: usize,
+) -> Result<(), Error> {
+    let (ident, mut source): (_, Box<dyn BlockSource>) =
+        if from.starts_with("postgres://") || from.starts_with("postgresql://") {
+            let (ident, source) = PeerSource::new(&from, &network)?;
+            (ident, Box::new(source))
+        } else {
+            let (ident, source) = FileSource::new(&from)?;
+            (ident, Box::new(source))
+        };
+
+    let start = Instant::now();
+    let count = copy_blocks(builder, &network, ident, source.as_mut(), batch_size)?;
+    println!(
+        "Imported {} blocks into the block cache for {} in {}s",
+        count,
+        network,
+        start.elapsed().as_secs()
+    );
+    Ok(())
+}
+
+/// Copy all blocks from `source` into the block cache of `network` and
+/// return how many blocks were copied
+fn copy_blocks(
+    builder: StoreBuilder,
+    network: &str,
+    ident: EthereumNetworkIdentifier,
+    source: &mut dyn BlockSource,
+    batch_size: usize,
+) -> Result<usize, Error> {
+    let store = builder.network_store(vec![(network.to_string(), ident)]);
+    let chain_store = store
+        .block_store()
+        .chain_store(network)
+        .ok_or_else(|| anyhow!("unknown chain {}", network))?;
+
+    let mut count = 0;
+    loop {
+        let blocks = source.next_batch(batch_size)?;
+        if blocks.is_empty() {
+            break;
+        }
+        count += blocks.len();
+        chain_store.import_blocks(blocks)?;
+        println!("imported {} blocks", count);
+    }
+    Ok(count)
+}
+
+/// Move the block cache of `network` from the shard that stores it now to
+/// `shard`. The configuration must already place the chain in `shard`.
+/// Blocks are copied into the new shard before the chain is removed from
+/// the old one; the call cache is not copied and fills up again as
+/// subgraphs make calls
+pub fn move_chain(
+    logger: &Logger,
+    config: &Config,
+    registry: Arc<dyn MetricsRegistry>,
+    primary: ConnectionPool,
+    network: String,
+    shard: String,
+    batch_size: usize,
+) -> Result<(), Error> {
+    let configured = config
+        .chains
+        .chains
+        .get(&network)
+        .map(|chain| chain.shard.as_str());
+    if configured != Some(shard.as_str()) {
+        return Err(anyhow!(
+            "the configuration must store chain {} in shard {} before it can be moved there",
+            network,
+            shard
+        ));
+    }
+    let target = Shard::new(shard.clone())?;
+    let current = BlockStore::chain_shard(&primary, &network)?
+        .ok_or_else(|| anyhow!("unknown chain {}", network))?;
+    let current_config = config
+        .stores
+        .get(current.as_str())
+        .ok_or_else(|| anyhow!("chain {} is stored in unknown shard {}", network, current))?;
+
+    // Connect to the old shard before changing anything so that we do
+    // not leave the chain half-moved if that fails
+    let (ident, mut source) = PeerSource::new(&current_config.connection, &network)?;
+    BlockStore::move_chain(&primary, &network, &target)?;
+
+    let start = Instant::now();
+    let builder = StoreBuilder::new(logger, config, registry.cheap_clone());
+    let count = copy_blocks(builder, &network, ident, &mut source, batch_size)?;
+
+    let old_pool = StoreBuilder::main_pool(logger, current.as_str(), current_config, registry);
+    ChainStore::drop_chain(&old_pool, &network)?;
+    println!(
+        "Moved chain {} with {} blocks from shard {} to shard {} in {}s",
+        network,
+        count,
+        current,
+        shard,
+        start.elapsed().as_secs()
+    );
+    Ok(())
+}
+
+/// Write the blocks with numbers in `from..to` from the block cache of
+/// `network` to the file `path` in the format that `import` reads
+pub fn export(
+    pool: ConnectionPool,
+    builder: StoreBuilder,
+    network: String,
+    path: String,
+    from: BlockNumber,
+    to: Option<BlockNumber>,
+) -> Result<(), Error> {
+    let header = Network::load(&*pool.get()?, &network)?
+        .ok_or_else(|| anyhow!("unknown chain {}", network))?
+        .header(&network);
+    let ident = header.ident()?;
+
+    let store = builder.network_store(vec![(network.clone(), ident)]);
+    let chain_store = store
+        .block_store()
+        .chain_store(&network)
+        .ok_or_else(|| anyhow!("unknown chain {}", network))?;
+
+    let start = Instant::now();
+    let mut writer = BufWriter::new(File::create(&path)?);
+    serde_json::to_writer(&mut writer, &header)?;
+    writer.write_all(b"\n")?;
+    let count = chain_store.export_blocks(from..to.unwrap_or(BlockNumber::MAX), &mut writer)?;
+    println!(
+        "Exported {} blocks from the block cache for {} to {} in {}s",
+        count,
+        network,
+        path,
+        start.elapsed().as_secs()
+    );
+    Ok(())
+}
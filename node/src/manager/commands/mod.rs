@@ -1,4 +1,6 @@
+pub mod chain;
 pub mod info;
 pub mod place;
+pub mod regraft;
 pub mod txn_speed;
 pub mod unused_deployments;
@@ -0,0 +1,293 @@
+//! Shrink the history of a subgraph without interrupting queries. The
+//! current version of the subgraph is replaced with a new deployment that
+//! is grafted onto it at a recent block and that only keeps the history
+//! after that block. Once the new deployment has caught up with the chain
+//! and has become the current version, the old deployment is recorded as
+//! unused so that it can be removed with `graphman unused remove`
+use std::io::Cursor;
+use std::sync::Arc;
+use std::time::Duration;
+
+use graph::data::subgraph::status::{self, DependencyKind, Filter};
+use graph::prelude::{
+    anyhow::{anyhow, Error},
+    reqwest,
+    serde_json::{self, json},
+    tokio, BlockNumber, Link, LinkResolver as LinkResolverTrait, Logger, SubgraphDeploymentId,
+};
+use graph_core::LinkResolver;
+use graph_store_postgres::SubgraphStore;
+use ipfs_api::IpfsClient;
+use serde_yaml::{Mapping, Value};
+
+/// How often to check on the progress of the new deployment
+const POLL_INTERVAL: Duration = Duration::from_secs(30);
+
+pub struct Endpoints {
+    /// The IPFS node from which to read the manifest of the current version
+    /// and to which to add the manifest of the new deployment
+    pub ipfs: String,
+    /// The JSON-RPC admin server of graph-node
+    pub admin: String,
+    /// A bearer token for the admin server
+    pub token: Option<String>,
+    /// The key for the namespace of the subgraph
+    pub key: Option<String>,
+}
+
+pub async fn run(
+    logger: Logger,
+    store: Arc<SubgraphStore>,
+    endpoints: Endpoints,
+    name: String,
+    block: Option<BlockNumber>,
+) -> Result<(), Error> {
+    let current = version(&store, &name, true)?
+        .ok_or_else(|| anyhow!("subgraph `{}` does not have a current version", name))?;
+    let old = current.subgraph.clone();
+
+    // A pending version that is grafted onto the current version was
+    // deployed by an earlier run that did not finish
+    let pending = version(&store, &name, false)?;
+    let (new, block) = match pending.as_ref().and_then(|info| graft_onto(info, &old)) {
+        Some((new, block)) => {
+            println!(
+                "continuing with the pending version {} that is grafted onto {} at block {}",
+                new, old, block
+            );
+            (new, block)
+        }
+        None => {
+            if let Some(pending) = pending {
+                return Err(anyhow!(
+                    "subgraph `{}` already has the pending version {}; wait for it to \
+                     become the current version before regrafting",
+                    name,
+                    pending.subgraph
+                ));
+            }
+            let block = graft_block(&current, block)?;
+            let new = deploy(&logger, &endpoints, &name, &current, block).await?;
+            println!("deployed {} grafted onto {} at block {}", new, old, block);
+
+            if version(&store, &name, true)?.map_or(false, |info| info.subgraph == new.as_str()) {
+                println!(
+                    "warning: {} became the current version right away and queries will \
+                     not see all data until it has copied the data of {}. Run graph-node \
+                     with GRAPH_SUBGRAPH_VERSION_SWITCHING_MODE=synced to keep the old \
+                     version current until the new one has synced",
+                    new, old
+                );
+            }
+            (new, block)
+        }
+    };
+
+    wait_for_switch(&store, &name, &old, &new, block).await?;
+    println!("{} is now the current version of `{}`", new, name);
+
+    let recorded = store.record_unused_deployments()?;
+    if recorded.iter().any(|detail| detail.id == old) {
+        println!(
+            "recorded {} as unused; remove it with `graphman unused remove --deployment {}`",
+            old, old
+        );
+    } else {
+        println!(
+            "{} is still used by other subgraphs and was not recorded as unused",
+            old
+        );
+    }
+    Ok(())
+}
+
+/// The status of the current (`current == true`) or pending version of the
+/// subgraph `name`
+fn version(
+    store: &SubgraphStore,
+    name: &str,
+    current: bool,
+) -> Result<Option<status::Info>, Error> {
+    let infos = store.status(Filter::SubgraphVersion(name.to_owned(), current))?;
+    Ok(infos.into_iter().next())
+}
+
+/// The latest block that the deployment described by `info` has processed
+fn latest_block(info: &status::Info) -> Option<BlockNumber> {
+    info.chains
+        .first()
+        .and_then(|chain| chain.latest_block.as_ref())
+        .map(|block| block.number() as BlockNumber)
+}
+
+/// The deployment for `info` and its graft block if it is grafted onto `base`
+fn graft_onto(info: &status::Info, base: &str) -> Option<(SubgraphDeploymentId, BlockNumber)> {
+    let block = info
+        .dependencies
+        .iter()
+        .find(|dep| dep.kind == DependencyKind::Graft && dep.deployment == base)?
+        .block
+        .as_ref()?
+        .number() as BlockNumber;
+    let id = SubgraphDeploymentId::new(info.subgraph.clone()).ok()?;
+    Some((id, block))
+}
+
+/// Check that we can graft onto the deployment described by `info` at
+/// `block`, or pick a block outside of its reorg window
+fn graft_block(info: &status::Info, block: Option<BlockNumber>) -> Result<BlockNumber, Error> {
+    if info.health.is_failed() {
+        return Err(anyhow!(
+            "the current version {} has failed and can not be regrafted",
+            info.subgraph
+        ));
+    }
+    let chain = info.chains.first().ok_or_else(|| {
+        anyhow!(
+            "the current version {} does not index a chain",
+            info.subgraph
+        )
+    })?;
+    let latest = latest_block(info).ok_or_else(|| {
+        anyhow!(
+            "the current version {} has not processed any blocks yet",
+            info.subgraph
+        )
+    })?;
+    let block = block.unwrap_or(latest - chain.reorg_threshold as BlockNumber);
+    if block <= 0 || block > latest {
+        return Err(anyhow!(
+            "the graft block must be between 1 and the latest block {} of {}, but is {}",
+            latest,
+            info.subgraph,
+            block
+        ));
+    }
+    Ok(block)
+}
+
+/// Add the manifest of the current version with a graft onto it at `block`
+/// to IPFS and deploy it as a new version of `name` on the node that
+/// indexes the current version
+async fn deploy(
+    logger: &Logger,
+    endpoints: &Endpoints,
+    name: &str,
+    current: &status::Info,
+    block: BlockNumber,
+) -> Result<SubgraphDeploymentId, Error> {
+    let client = IpfsClient::new_from_uri(&endpoints.ipfs)
+        .map_err(|e| anyhow!("invalid IPFS URL `{}`: {}", endpoints.ipfs, e))?;
+    let raw = LinkResolver::from(client.clone())
+        .cat(
+            logger,
+            &Link {
+                link: format!("/ipfs/{}", current.subgraph),
+            },
+        )
+        .await?;
+    let manifest = with_graft(&raw, &current.subgraph, block)?;
+    let hash = client
+        .add(Cursor::new(manifest))
+        .await
+        .map_err(|e| anyhow!("failed to add the new manifest to IPFS: {}", e))?
+        .hash;
+
+    let request = json!({
+        "jsonrpc": "2.0",
+        "id": "regraft",
+        "method": "subgraph_deploy",
+        "params": {
+            "name": name,
+            "ipfs_hash": hash,
+            "node_id": current.node,
+            "key": endpoints.key,
+        },
+    });
+    let mut builder = reqwest::Client::new()
+        .post(&endpoints.admin)
+        .header("Content-Type", "application/json")
+        .body(request.to_string());
+    if let Some(token) = &endpoints.token {
+        builder = builder.bearer_auth(token);
+    }
+    let response: serde_json::Value = serde_json::from_str(&builder.send().await?.text().await?)?;
+    if let Some(error) = response.get("error") {
+        return Err(anyhow!("deploying {} failed: {}", hash, error));
+    }
+
+    SubgraphDeploymentId::new(hash).map_err(|hash| anyhow!("invalid deployment id `{}`", hash))
+}
+
+/// The manifest `raw` with a graft onto `base` at `block`
+fn with_graft(raw: &[u8], base: &str, block: BlockNumber) -> Result<Vec<u8>, Error> {
+    let mut manifest: Mapping = serde_yaml::from_slice(raw)?;
+    let mut graft = Mapping::new();
+    graft.insert(Value::from("base"), Value::from(base));
+    graft.insert(Value::from("block"), Value::from(block));
+    manifest.insert(Value::from("graft"), Value::Mapping(graft));
+    Ok(serde_yaml::to_vec(&manifest)?)
+}
+
+/// Wait until `new` has become the current version of `name`, pruning its
+/// history before `block` once it has copied the data of `old`
+async fn wait_for_switch(
+    store: &SubgraphStore,
+    name: &str,
+    old: &str,
+    new: &SubgraphDeploymentId,
+    block: BlockNumber,
+) -> Result<(), Error> {
+    let mut pruned = false;
+    loop {
+        let info = store
+            .status(Filter::Deployments(vec![new.to_string()]))?
+            .into_iter()
+            .next()
+            .ok_or_else(|| anyhow!("deployment {} does not exist any more", new))?;
+        if info.health.is_failed() {
+            let message = info
+                .fatal_error
+                .map(|error| error.message)
+                .unwrap_or_default();
+            return Err(anyhow!(
+                "the new deployment {} failed and {} stays the current version: {}",
+                new,
+                old,
+                message
+            ));
+        }
+
+        // The graft has been copied once the new deployment is past the
+        // graft block, and it can never revert past that block
+        let latest = latest_block(&info);
+        if !pruned && latest.map_or(false, |latest| latest > block) {
+            let count = store.prune_history(new, block)?;
+            println!(
+                "pruned {} entity versions from {} that were replaced before block {}",
+                count, new, block
+            );
+            pruned = true;
+        }
+
+        match version(store, name, true)? {
+            Some(current) if current.subgraph == new.as_str() && pruned => return Ok(()),
+            Some(current) if current.subgraph == new.as_str() || current.subgraph == old => {}
+            Some(current) => {
+                return Err(anyhow!(
+                    "subgraph `{}` switched to the unrelated version {}",
+                    name,
+                    current.subgraph
+                ))
+            }
+            None => return Err(anyhow!("subgraph `{}` has no current version", name)),
+        }
+
+        println!(
+            "waiting for {} to sync; it is at block {}",
+            new,
+            latest.map_or("none".to_owned(), |latest| latest.to_string())
+        );
+        tokio::time::delay_for(POLL_INTERVAL).await;
+    }
+}
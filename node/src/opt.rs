@@ -4,6 +4,9 @@ use structopt::StructOpt;
 
 use crate::config;
 
+/// The Ethereum node that development mode uses if none is given
+const DEV_ETHEREUM_RPC: &str = "localhost:http://localhost:8545";
+
 git_testament!(TESTAMENT);
 lazy_static! {
     static ref RENDERED_TESTAMENT: String = render_testament!(TESTAMENT);
@@ -21,7 +24,7 @@ pub struct Opt {
         long,
         env = "GRAPH_NODE_CONFIG",
         conflicts_with_all = &["postgres-url", "postgres-secondary-hosts", "postgres-host-weights"],
-        required_unless = "postgres-url",
+        required_unless_one = &["postgres-url", "dev"],
         help = "the name of the configuration file",
     )]
     pub config: Option<String>,
@@ -39,7 +42,7 @@ pub struct Opt {
         value_name = "URL",
         env = "POSTGRES_URL",
         conflicts_with = "config",
-        required_unless = "config",
+        required_unless_one = &["config", "dev"],
         help = "Location of the Postgres database used for storing entities"
     )]
     pub postgres_url: Option<String>,
@@ -69,7 +72,7 @@ pub struct Opt {
     #[structopt(
         long,
         min_values=0,
-        required_unless_one = &["ethereum-ws", "ethereum-ipc", "config"],
+        required_unless_one = &["ethereum-ws", "ethereum-ipc", "config", "dev"],
         conflicts_with_all = &["ethereum-ws", "ethereum-ipc", "config"],
         value_name="NETWORK_NAME:URL",
         env="ETHEREUM_RPC",
@@ -77,7 +80,7 @@ pub struct Opt {
     )]
     pub ethereum_rpc: Vec<String>,
     #[structopt(long, min_values=0,
-        required_unless_one = &["ethereum-rpc", "ethereum-ipc", "config"],
+        required_unless_one = &["ethereum-rpc", "ethereum-ipc", "config", "dev"],
         conflicts_with_all = &["ethereum-rpc", "ethereum-ipc", "config"],
         value_name="NETWORK_NAME:URL",
         env="ETHEREUM_WS",
@@ -85,7 +88,7 @@ pub struct Opt {
     )]
     pub ethereum_ws: Vec<String>,
     #[structopt(long, min_values=0,
-        required_unless_one = &["ethereum-rpc", "ethereum-ws", "config"],
+        required_unless_one = &["ethereum-rpc", "ethereum-ws", "config", "dev"],
         conflicts_with_all = &["ethereum-rpc", "ethereum-ws", "config"],
         value_name="NETWORK_NAME:FILE",
         env="ETHEREUM_IPC",
@@ -144,6 +147,15 @@ pub struct Opt {
     pub node_id: String,
     #[structopt(long, help = "Enable debug logging")]
     pub debug: bool,
+    #[structopt(
+        long,
+        env = "GRAPH_DEV",
+        help = "Run in development mode: `--subgraph` can point to a local manifest, \
+                which is redeployed from scratch on every start, the Ethereum node \
+                defaults to a local chain at http://localhost:8545, and the database \
+                defaults to a private Postgres server that graph-node runs itself"
+    )]
+    pub dev: bool,
 
     #[structopt(
         long,
@@ -228,8 +240,21 @@ impl From<Opt> for config::Opt {
             ethereum_rpc,
             ethereum_ws,
             ethereum_ipc,
+            dev,
             ..
         } = opt;
+        // In development mode, default to a local chain like the ones that
+        // Hardhat or Anvil run
+        let ethereum_rpc = if dev
+            && config.is_none()
+            && ethereum_rpc.is_empty()
+            && ethereum_ws.is_empty()
+            && ethereum_ipc.is_empty()
+        {
+            vec![DEV_ETHEREUM_RPC.to_string()]
+        } else {
+            ethereum_rpc
+        };
         config::Opt {
             postgres_url,
             config,
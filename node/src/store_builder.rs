@@ -188,8 +188,13 @@ impl StoreBuilder {
             .collect();
 
         let block_store = Arc::new(
-            DieselBlockStore::new(networks, &self.pools, chain_head_update_listener.clone())
-                .expect("Creating the BlockStore works"),
+            DieselBlockStore::new(
+                networks,
+                &self.pools,
+                chain_head_update_listener.clone(),
+                self.registry.cheap_clone(),
+            )
+            .expect("Creating the BlockStore works"),
         );
 
         Arc::new(DieselStore::new(
@@ -204,7 +209,7 @@ impl StoreBuilder {
 
     // This is used in the test-store, but rustc keeps complaining that it
     // is not used
-    #[cfg(debug_assertions)]
+    #[cfg(any(debug_assertions, feature = "testing"))]
     #[allow(dead_code)]
     pub fn primary_pool(&self) -> ConnectionPool {
         self.pools.get(&*PRIMARY_SHARD).unwrap().clone()
@@ -476,6 +476,19 @@ pub(crate) struct AscEthereumBlock {
     pub size: AscPtr<AscBigInt>,
 }
 
+#[repr(C)]
+#[derive(AscType)]
+pub(crate) struct AscBlockFeeStats {
+    pub number: AscPtr<AscBigInt>,
+    pub hash: AscPtr<AscH256>,
+    pub gas_used: AscPtr<AscBigInt>,
+    pub gas_limit: AscPtr<AscBigInt>,
+    pub transaction_count: AscPtr<AscBigInt>,
+    pub min_gas_price: AscPtr<AscBigInt>,
+    pub median_gas_price: AscPtr<AscBigInt>,
+    pub max_gas_price: AscPtr<AscBigInt>,
+}
+
 #[repr(C)]
 #[derive(AscType)]
 pub(crate) struct AscEthereumTransaction {
@@ -1,4 +1,5 @@
 use std::cmp::PartialEq;
+use std::collections::BTreeMap;
 use std::str::FromStr;
 use std::time::{Duration, Instant};
 
@@ -6,7 +7,10 @@ use async_trait::async_trait;
 use ethabi::{LogParam, RawLog};
 use futures::sync::mpsc::Sender;
 use futures03::channel::oneshot::channel;
-use graph::{components::store::CallCache, ensure};
+use graph::{
+    components::store::{BlockStore, CallCache},
+    ensure,
+};
 use semver::{Version, VersionReq};
 use slog::{o, OwnedKV};
 use strum::AsStaticRef as _;
@@ -17,7 +21,7 @@ use graph::components::ethereum::*;
 use graph::components::store::SubgraphStore;
 use graph::components::subgraph::{MappingError, SharedProofOfIndexing};
 use graph::components::three_box::ThreeBoxAdapter;
-use graph::data::subgraph::{Mapping, Source};
+use graph::data::subgraph::{limits::EntityLimits, Mapping, Source};
 use graph::prelude::{
     RuntimeHost as RuntimeHostTrait, RuntimeHostBuilder as RuntimeHostBuilderTrait, *,
 };
@@ -49,6 +53,8 @@ struct RuntimeHostConfig {
     data_source_creation_block: Option<u64>,
     contract: Source,
     templates: Arc<Vec<DataSourceTemplate>>,
+    deployment_context: Arc<BTreeMap<String, String>>,
+    entity_limits: EntityLimits,
 }
 
 pub struct RuntimeHostBuilder<S, CC> {
@@ -63,7 +69,7 @@ pub struct RuntimeHostBuilder<S, CC> {
 impl<S, CC> Clone for RuntimeHostBuilder<S, CC>
 where
     S: SubgraphStore,
-    CC: CallCache,
+    CC: CallCache + BlockStore,
 {
     fn clone(&self) -> Self {
         RuntimeHostBuilder {
@@ -80,7 +86,7 @@ where
 impl<S, CC> RuntimeHostBuilder<S, CC>
 where
     S: SubgraphStore,
-    CC: CallCache,
+    CC: CallCache + BlockStore,
 {
     pub fn new(
         ethereum_networks: EthereumNetworks,
@@ -104,13 +110,14 @@ where
 impl<S, CC> RuntimeHostBuilderTrait for RuntimeHostBuilder<S, CC>
 where
     S: SubgraphStore,
-    CC: CallCache,
+    CC: CallCache + BlockStore,
 {
     type Host = RuntimeHost;
     type Req = MappingRequest;
 
     fn spawn_mapping(
         raw_module: Vec<u8>,
+        api_version: &Version,
         logger: Logger,
         subgraph_id: SubgraphDeploymentId,
         metrics: Arc<HostMetrics>,
@@ -122,6 +129,7 @@ where
         };
         crate::mapping::spawn_module(
             raw_module,
+            api_version,
             logger,
             subgraph_id,
             metrics,
@@ -137,6 +145,8 @@ where
         subgraph_id: SubgraphDeploymentId,
         data_source: DataSource,
         templates: Arc<Vec<DataSourceTemplate>>,
+        deployment_context: Arc<BTreeMap<String, String>>,
+        entity_limits: EntityLimits,
         mapping_request_sender: Sender<MappingRequest>,
         metrics: Arc<HostMetrics>,
     ) -> Result<Self::Host, Error> {
@@ -150,6 +160,13 @@ where
                 )
             })?;
 
+        let chain_store = self.caches.chain_store(&network_name).ok_or_else(|| {
+            anyhow!(
+                "No store found that matches subgraph network: \"{}\"",
+                &network_name
+            )
+        })?;
+
         let required_capabilities = data_source.mapping.required_capabilities();
 
         let ethereum_adapter = self
@@ -161,6 +178,7 @@ where
             self.link_resolver.clone(),
             self.store.clone(),
             cache,
+            chain_store,
             RuntimeHostConfig {
                 subgraph_id,
                 mapping: data_source.mapping,
@@ -170,6 +188,8 @@ where
                 data_source_creation_block: data_source.creation_block,
                 contract: data_source.source,
                 templates,
+                deployment_context,
+                entity_limits,
             },
             mapping_request_sender,
             metrics,
@@ -199,6 +219,7 @@ impl RuntimeHost {
         link_resolver: Arc<dyn LinkResolver>,
         store: Arc<dyn crate::RuntimeStore>,
         call_cache: Arc<dyn EthereumCallCache>,
+        chain_store: Arc<dyn ChainStore>,
         config: RuntimeHostConfig,
         mapping_request_sender: Sender<MappingRequest>,
         metrics: Arc<HostMetrics>,
@@ -206,9 +227,9 @@ impl RuntimeHost {
         three_box_adapter: Arc<dyn ThreeBoxAdapter>,
     ) -> Result<Self, Error> {
         let api_version = Version::parse(&config.mapping.api_version)?;
-        if !VersionReq::parse("<= 0.0.4").unwrap().matches(&api_version) {
+        if !VersionReq::parse("<= 0.0.5").unwrap().matches(&api_version) {
             return Err(anyhow!(
-                "This Graph Node only supports mapping API versions <= 0.0.4, but subgraph `{}` uses `{}`",
+                "This Graph Node only supports mapping API versions <= 0.0.5, but subgraph `{}` uses `{}`",
                 config.subgraph_id,
                 api_version
             ));
@@ -239,12 +260,15 @@ impl RuntimeHost {
             config.contract.address.clone(),
             config.data_source_network,
             config.data_source_context,
+            config.deployment_context,
+            config.entity_limits,
             config.templates,
             config.mapping.abis,
             ethereum_adapter,
             link_resolver,
             store,
             call_cache,
+            chain_store,
             arweave_adapter,
             three_box_adapter,
         ));
@@ -474,6 +498,18 @@ impl RuntimeHostTrait for RuntimeHost {
             && self.data_source_contract.start_block <= log.block_number.unwrap().as_u64()
     }
 
+    fn is_commutative_log(&self, log: &Log) -> bool {
+        let topic0 = match log.topics.iter().next() {
+            Some(topic0) => topic0,
+            None => return false,
+        };
+
+        self.data_source_event_handlers
+            .iter()
+            .filter(|handler| *topic0 == handler.topic0())
+            .all(|handler| handler.commutative)
+    }
+
     fn matches_call(&self, call: &EthereumCall) -> bool {
         self.matches_call_address(call)
             && self.matches_call_function(call)
@@ -6,16 +6,18 @@ use crate::{
 use bytes::Bytes;
 use ethabi::{Address, Token};
 use graph::components::ethereum::*;
-use graph::components::store::EntityKey;
+use graph::components::store::{check_reserved_fields, EntityKey};
 use graph::components::subgraph::{ProofOfIndexingEvent, SharedProofOfIndexing};
 use graph::components::three_box::ThreeBoxAdapter;
 use graph::components::{arweave::ArweaveAdapter, store::EntityType};
+use graph::data::graphql::{DocumentExt, FieldExt, ObjectTypeExt, TypeExt};
 use graph::data::store;
+use graph::data::subgraph::limits::EntityLimits;
 use graph::prelude::serde_json;
 use graph::prelude::{slog::b, slog::record_static, *};
 use never::Never;
 use semver::Version;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::ops::Deref;
 use std::str::FromStr;
 use std::time::{Duration, Instant};
@@ -23,16 +25,32 @@ use web3::types::H160;
 
 use graph::ensure;
 use graph_graphql::prelude::validate_entity;
+use graph_graphql::schema::ast::is_list_or_non_null_list_field;
 use wasmtime::Trap;
 
 use crate::module::{WasmInstance, WasmInstanceContext};
 
+lazy_static! {
+    /// The largest number of blocks that `ethereum.feeHistory` returns
+    /// statistics for in one call
+    static ref FEE_HISTORY_MAX_BLOCKS: u64 =
+        std::env::var("GRAPH_ETHEREUM_FEE_HISTORY_MAX_BLOCKS")
+            .unwrap_or("1000".into())
+            .parse::<u64>()
+            .expect("invalid GRAPH_ETHEREUM_FEE_HISTORY_MAX_BLOCKS");
+}
+
 pub(crate) enum EthereumCallError {
     /// We might have detected a reorg.
     PossibleReorg(anyhow::Error),
     Unknown(anyhow::Error),
 }
 
+/// The results of the calls that a handler prefetched with
+/// `ethereum.prefetchCalls`, keyed by contract address and encoded input.
+/// Calls that reverted map to `None`
+pub(crate) type PrefetchedCalls = HashMap<(Address, Vec<u8>), Option<Vec<Token>>>;
+
 impl From<anyhow::Error> for EthereumCallError {
     fn from(e: anyhow::Error) -> Self {
         EthereumCallError::Unknown(e)
@@ -75,6 +93,8 @@ pub(crate) struct HostExports {
     data_source_address: Option<Address>,
     data_source_network: String,
     data_source_context: Option<DataSourceContext>,
+    deployment_context: Arc<BTreeMap<String, String>>,
+    entity_limits: EntityLimits,
     /// Some data sources have indeterminism or different notions of time. These
     /// need to be each be stored separately to separate causality between them,
     /// and merge the results later. Right now, this is just the ethereum
@@ -85,6 +105,7 @@ pub(crate) struct HostExports {
     ethereum_adapter: Arc<dyn EthereumAdapter>,
     pub(crate) link_resolver: Arc<dyn LinkResolver>,
     call_cache: Arc<dyn EthereumCallCache>,
+    chain_store: Arc<dyn ChainStore>,
     store: Arc<dyn crate::RuntimeStore>,
     arweave_adapter: Arc<dyn ArweaveAdapter>,
     three_box_adapter: Arc<dyn ThreeBoxAdapter>,
@@ -105,12 +126,15 @@ impl HostExports {
         data_source_address: Option<Address>,
         data_source_network: String,
         data_source_context: Option<DataSourceContext>,
+        deployment_context: Arc<BTreeMap<String, String>>,
+        entity_limits: EntityLimits,
         templates: Arc<Vec<DataSourceTemplate>>,
         abis: Vec<MappingABI>,
         ethereum_adapter: Arc<dyn EthereumAdapter>,
         link_resolver: Arc<dyn LinkResolver>,
         store: Arc<dyn crate::RuntimeStore>,
         call_cache: Arc<dyn EthereumCallCache>,
+        chain_store: Arc<dyn ChainStore>,
         arweave_adapter: Arc<dyn ArweaveAdapter>,
         three_box_adapter: Arc<dyn ThreeBoxAdapter>,
     ) -> Self {
@@ -123,12 +147,15 @@ impl HostExports {
             data_source_address,
             data_source_network,
             data_source_context,
+            deployment_context,
+            entity_limits,
             causality_region,
             templates,
             abis,
             ethereum_adapter,
             link_resolver,
             call_cache,
+            chain_store,
             store,
             arweave_adapter,
             three_box_adapter,
@@ -172,7 +199,21 @@ impl HostExports {
         entity_type: String,
         entity_id: String,
         mut data: HashMap<String, Value>,
-    ) -> Result<(), anyhow::Error> {
+    ) -> Result<(), HostExportError> {
+        let key = EntityKey {
+            subgraph_id: self.subgraph_id.clone(),
+            entity_type: EntityType::data(entity_type.clone()),
+            entity_id: entity_id.clone(),
+        };
+
+        // Check the entity before it goes into the proof of indexing. The
+        // limits come from the manifest, so violating them is
+        // deterministic; the reserved fields are configured for each node
+        self.entity_limits
+            .check(&key, &data)
+            .map_err(HostExportError::Deterministic)?;
+        check_reserved_fields(&key, &data)?;
+
         if let Some(proof_of_indexing) = proof_of_indexing {
             let mut proof_of_indexing = proof_of_indexing.deref().borrow_mut();
             proof_of_indexing.write(
@@ -195,20 +236,16 @@ impl HostExports {
                     entity_type,
                     v,
                     entity_id,
-                ));
+                )
+                .into());
             }
             _ => (),
         }
 
-        let key = EntityKey {
-            subgraph_id: self.subgraph_id.clone(),
-            entity_type: EntityType::data(entity_type),
-            entity_id,
-        };
         let entity = Entity::from(data);
         let schema = self.store.input_schema(&self.subgraph_id)?;
         let is_valid = validate_entity(&schema.document, &key, &entity).is_ok();
-        state.entity_cache.set(key.clone(), entity);
+        state.entity_cache.set(key.clone(), entity)?;
 
         // Validate the changes against the subgraph schema.
         // If the set of fields we have is already valid, avoid hitting the DB.
@@ -223,6 +260,69 @@ impl HostExports {
         Ok(())
     }
 
+    pub(crate) fn store_increment(
+        &self,
+        logger: &Logger,
+        state: &mut BlockState,
+        proof_of_indexing: &SharedProofOfIndexing,
+        entity_type: String,
+        entity_id: String,
+        field: String,
+        delta: Value,
+    ) -> Result<(), anyhow::Error> {
+        // Only numeric fields can be incremented, and only by a number of
+        // the same type
+        let schema = self.store.input_schema(&self.subgraph_id)?;
+        let field_def = schema
+            .document
+            .get_object_type_definition(&entity_type)
+            .and_then(|object_type| object_type.field(&field))
+            .ok_or_else(|| {
+                anyhow!(
+                    "Entity {}[{}]: can not increment unknown field `{}`",
+                    entity_type,
+                    entity_id,
+                    field
+                )
+            })?;
+        if !delta.is_number()
+            || is_list_or_non_null_list_field(field_def)
+            || field_def.field_type.get_base_type() != &delta.type_name()
+        {
+            return Err(anyhow!(
+                "Entity {}[{}]: can not increment field `{}` of type {} by `{}` of type {}",
+                entity_type,
+                entity_id,
+                field,
+                field_def.field_type,
+                delta,
+                delta.type_name()
+            ));
+        }
+
+        if let Some(proof_of_indexing) = proof_of_indexing {
+            let mut proof_of_indexing = proof_of_indexing.deref().borrow_mut();
+            proof_of_indexing.write(
+                logger,
+                &self.causality_region,
+                &ProofOfIndexingEvent::IncrementEntity {
+                    entity_type: &entity_type,
+                    id: &entity_id,
+                    field: &field,
+                    delta: &delta,
+                },
+            );
+        }
+
+        let key = EntityKey {
+            subgraph_id: self.subgraph_id.clone(),
+            entity_type: EntityType::data(entity_type),
+            entity_id,
+        };
+        state.entity_cache.increment(key, field, delta);
+        Ok(())
+    }
+
     pub(crate) fn store_remove(
         &self,
         logger: &Logger,
@@ -267,15 +367,100 @@ impl HostExports {
         Ok(state.entity_cache.get(&store_key)?)
     }
 
-    /// Returns `Ok(None)` if the call was reverted.
-    pub(crate) fn ethereum_call(
+    /// Load the entities that the `@derivedFrom` field `field` of the
+    /// entity `entity_type[entity_id]` refers to, including changes that
+    /// have not been written to the store yet
+    pub(crate) fn store_load_related(
         &self,
-        logger: &Logger,
-        block: &LightEthereumBlock,
-        unresolved_call: UnresolvedContractCall,
-    ) -> Result<Option<Vec<Token>>, EthereumCallError> {
-        let start_time = Instant::now();
+        state: &mut BlockState,
+        entity_type: String,
+        entity_id: String,
+        field: String,
+    ) -> Result<Vec<Entity>, anyhow::Error> {
+        let schema = self.store.input_schema(&self.subgraph_id)?;
+        let field_def = schema
+            .document
+            .get_object_type_definition(&entity_type)
+            .and_then(|object_type| object_type.field(&field))
+            .ok_or_else(|| {
+                anyhow!(
+                    "Entity {}[{}]: can not load related entities for unknown field `{}`",
+                    entity_type,
+                    entity_id,
+                    field
+                )
+            })?;
+        let derived_from = field_def.derived_from().ok_or_else(|| {
+            anyhow!(
+                "Entity {}[{}]: field `{}` does not have a @derivedFrom directive",
+                entity_type,
+                entity_id,
+                field
+            )
+        })?;
+        let related_type = field_def.field_type.get_base_type();
+        let related_field = schema
+            .document
+            .get_object_type_definition(related_type)
+            .and_then(|object_type| object_type.field(derived_from))
+            .ok_or_else(|| {
+                anyhow!(
+                    "Entity {}[{}]: can not load related entities of type `{}` for field `{}`; \
+                     only object types are supported",
+                    entity_type,
+                    entity_id,
+                    related_type,
+                    field
+                )
+            })?;
 
+        let filter = match is_list_or_non_null_list_field(related_field) {
+            true => EntityFilter::Contains(
+                derived_from.clone(),
+                Value::List(vec![Value::String(entity_id)]),
+            ),
+            false => EntityFilter::new_equal(derived_from.clone(), entity_id),
+        };
+        let query = EntityQuery::new(
+            self.subgraph_id.clone(),
+            BLOCK_NUMBER_MAX,
+            EntityCollection::All(vec![related_type.clone()]),
+        )
+        .filter(filter)
+        .range(EntityRange {
+            first: None,
+            skip: 0,
+        });
+
+        Ok(state.entity_cache.find(query)?)
+    }
+
+    pub(crate) fn store_prefetch(
+        &self,
+        state: &mut BlockState,
+        entity_type: String,
+        entity_ids: Vec<String>,
+    ) -> Result<(), anyhow::Error> {
+        let entity_type = EntityType::data(entity_type);
+        let keys = entity_ids
+            .into_iter()
+            .map(|entity_id| EntityKey {
+                subgraph_id: self.subgraph_id.clone(),
+                entity_type: entity_type.clone(),
+                entity_id,
+            })
+            .collect();
+
+        Ok(state.entity_cache.prefetch(keys)?)
+    }
+
+    /// Look up the function for `unresolved_call` in the ABIs of the
+    /// subgraph and turn it into a call at `block`
+    fn resolve_call(
+        &self,
+        block: &LightEthereumBlock,
+        unresolved_call: &UnresolvedContractCall,
+    ) -> Result<EthereumContractCall, anyhow::Error> {
         // Obtain the path to the contract ABI
         let contract = self
             .abis
@@ -328,12 +513,34 @@ impl HostExports {
                 })?,
         };
 
-        let call = EthereumContractCall {
+        Ok(EthereumContractCall {
             address: unresolved_call.contract_address.clone(),
             block_ptr: block.into(),
             function: function.clone(),
             args: unresolved_call.function_args.clone(),
-        };
+        })
+    }
+
+    /// Returns `Ok(None)` if the call was reverted.
+    pub(crate) fn ethereum_call(
+        &self,
+        logger: &Logger,
+        block: &LightEthereumBlock,
+        unresolved_call: UnresolvedContractCall,
+        prefetched: &PrefetchedCalls,
+    ) -> Result<Option<Vec<Token>>, EthereumCallError> {
+        let start_time = Instant::now();
+
+        let call = self.resolve_call(block, &unresolved_call)?;
+
+        if let Some(output) = call
+            .function
+            .encode_input(&call.args)
+            .ok()
+            .and_then(|data| prefetched.get(&(call.address, data)))
+        {
+            return Ok(output.clone());
+        }
 
         // Run Ethereum call in tokio runtime
         let eth_adapter = self.ethereum_adapter.clone();
@@ -376,6 +583,134 @@ impl HostExports {
         result.map_err(Into::into)
     }
 
+    /// Make all `unresolved_calls` at `block` at once and add their results
+    /// to `prefetched`. The calls are looked up in the call cache with one
+    /// query and the ones that are not cached are sent to the Ethereum node
+    /// as one batch. Calls that fail for reasons other than a revert are
+    /// not added, so that `ethereum.call` reports their error
+    pub(crate) fn ethereum_prefetch_calls(
+        &self,
+        logger: &Logger,
+        block: &LightEthereumBlock,
+        unresolved_calls: Vec<UnresolvedContractCall>,
+        prefetched: &mut PrefetchedCalls,
+    ) -> Result<(), EthereumCallError> {
+        let start_time = Instant::now();
+
+        let calls = unresolved_calls
+            .iter()
+            .map(|unresolved_call| self.resolve_call(block, unresolved_call))
+            .collect::<Result<Vec<_>, _>>()?;
+        let count = calls.len();
+
+        let eth_adapter = self.ethereum_adapter.clone();
+        let logger1 = logger.clone();
+        let call_cache = self.call_cache.clone();
+        let calls1 = calls.clone();
+        let results = match block_on(future::lazy(move || {
+            eth_adapter.contract_calls(&logger1, calls1, call_cache)
+        })) {
+            Ok(results) => results,
+            // See `ethereum_call` for why we treat errors from the Ethereum
+            // node as a possible reorg
+            Err(EthereumContractCallError::Web3Error(e)) => {
+                return Err(EthereumCallError::PossibleReorg(anyhow::anyhow!(
+                    "Ethereum node returned an error when prefetching {} calls: {}",
+                    count,
+                    e
+                )))
+            }
+            Err(e) => {
+                return Err(EthereumCallError::Unknown(anyhow::anyhow!(
+                    "Failed to prefetch {} calls: {}",
+                    count,
+                    e
+                )))
+            }
+        };
+
+        for (call, result) in calls.into_iter().zip(results) {
+            let output = match result {
+                Ok(tokens) => Some(tokens),
+                Err(EthereumContractCallError::Revert(_)) => None,
+                Err(_) => continue,
+            };
+            if let Ok(data) = call.function.encode_input(&call.args) {
+                prefetched.insert((call.address, data), output);
+            }
+        }
+
+        debug!(logger, "Contract calls prefetched";
+              "calls" => count,
+              "time" => format!("{}ms", start_time.elapsed().as_millis()));
+        Ok(())
+    }
+
+    /// Gas statistics for the blocks from `from` to `to`, both inclusive.
+    /// Statistics that the block ingestor has not stored yet are fetched
+    /// from the Ethereum node and stored for later calls. The range must
+    /// not reach past `block`, the block that is being processed
+    pub(crate) fn fee_history(
+        &self,
+        logger: &Logger,
+        block: &LightEthereumBlock,
+        from: u64,
+        to: u64,
+    ) -> Result<Vec<BlockFeeStats>, HostExportError> {
+        if from > to {
+            return Err(HostExportError::Deterministic(anyhow!(
+                "invalid fee history range: start block {} is after end block {}",
+                from,
+                to
+            )));
+        }
+        if to > block.number() {
+            return Err(HostExportError::Deterministic(anyhow!(
+                "invalid fee history range: end block {} is after the current block {}",
+                to,
+                block.number()
+            )));
+        }
+        if to - from + 1 > *FEE_HISTORY_MAX_BLOCKS {
+            return Err(HostExportError::Deterministic(anyhow!(
+                "fee history range {}..{} covers more than {} blocks",
+                from,
+                to,
+                *FEE_HISTORY_MAX_BLOCKS
+            )));
+        }
+
+        let mut stats = self.chain_store.fee_history(from, to)?;
+
+        // The stored statistics for the current block might be for a
+        // block that has since been reorged out
+        stats.retain(|stats| stats.number != block.number());
+        if to == block.number() {
+            stats.push(BlockFeeStats::from(block));
+        }
+
+        let known: HashSet<u64> = stats.iter().map(|stats| stats.number).collect();
+        let mut fetched = Vec::new();
+        for number in (from..=to).filter(|number| !known.contains(number)) {
+            let missing = block_on(self.ethereum_adapter.block_by_number(logger, number))?
+                .ok_or_else(|| {
+                    anyhow!("block #{} is not available from the Ethereum node", number)
+                })?;
+            fetched.push(BlockFeeStats::from(&missing));
+        }
+        if !fetched.is_empty() {
+            debug!(logger, "Fetched gas statistics from the Ethereum node";
+                   "from" => from,
+                   "to" => to,
+                   "blocks" => fetched.len());
+            self.chain_store.upsert_fee_stats(fetched.clone())?;
+            stats.extend(fetched);
+        }
+
+        stats.sort_by_key(|stats| stats.number);
+        Ok(stats)
+    }
+
     /// Prints the module of `n` in hex.
     /// Integers are encoded using the least amount of digits (no leading zero digits).
     /// Their encoding may be of uneven length. The number zero encodes as "0x0".
@@ -716,6 +1051,14 @@ impl HostExports {
         self.data_source_context.clone().unwrap_or_default()
     }
 
+    pub(crate) fn deployment_context(&self) -> Entity {
+        let mut entity = Entity::new();
+        for (key, value) in self.deployment_context.iter() {
+            entity.set(key.as_str(), Value::String(value.clone()));
+        }
+        entity
+    }
+
     pub(crate) fn arweave_transaction_data(&self, tx_id: &str) -> Option<Bytes> {
         block_on03(self.arweave_adapter.tx_data(tx_id)).ok()
     }
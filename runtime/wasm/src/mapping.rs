@@ -5,16 +5,30 @@ use futures03::channel::oneshot::Sender;
 use graph::components::ethereum::*;
 use graph::components::subgraph::{MappingError, SharedProofOfIndexing};
 use graph::prelude::*;
+use semver::Version;
 use std::collections::BTreeMap;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 use std::thread;
 use std::time::Instant;
 use strum_macros::AsStaticStr;
 use web3::types::{Log, Transaction};
 
-/// Spawn a wasm module in its own thread.
+lazy_static! {
+    /// The number of threads that run the handlers of each mapping. Only
+    /// handlers that are declared `commutative` run concurrently, and
+    /// therefore only they benefit from more than one thread
+    static ref MAPPING_WORKERS: usize = std::env::var("GRAPH_MAPPING_WORKERS")
+        .unwrap_or("1".into())
+        .parse::<usize>()
+        .ok()
+        .filter(|workers| *workers > 0)
+        .expect("invalid GRAPH_MAPPING_WORKERS");
+}
+
+/// Spawn a wasm module in its own threads.
 pub fn spawn_module(
     raw_module: Vec<u8>,
+    api_version: &Version,
     logger: Logger,
     subgraph_id: SubgraphDeploymentId,
     host_metrics: Arc<HostMetrics>,
@@ -22,93 +36,127 @@ pub fn spawn_module(
     timeout: Option<Duration>,
     experimental_features: ExperimentalFeatures,
 ) -> Result<mpsc::Sender<MappingRequest>, anyhow::Error> {
-    let valid_module = Arc::new(ValidModule::new(&raw_module)?);
+    let valid_module = Arc::new(ValidModule::new(&raw_module, api_version)?);
 
     // Create channel for event handling requests
     let (mapping_request_sender, mapping_request_receiver) = mpsc::channel(100);
+    let mapping_request_receiver = Arc::new(Mutex::new(mapping_request_receiver));
 
     // wasmtime instances are not `Send` therefore they cannot be scheduled by
-    // the regular tokio executor, so we create a dedicated thread.
+    // the regular tokio executor, so we create dedicated threads.
     //
-    // In case of failure, this thread may panic or simply terminate,
-    // dropping the `mapping_request_receiver` which ultimately causes the
-    // subgraph to fail the next time it tries to handle an event.
-    let conf =
-        thread::Builder::new().name(format!("mapping-{}-{}", &subgraph_id, uuid::Uuid::new_v4()));
-    conf.spawn(move || {
-        runtime.enter(|| {
-            // Pass incoming triggers to the WASM module and return entity changes;
-            // Stop when canceled because all RuntimeHosts and their senders were dropped.
-            match mapping_request_receiver
-                .map_err(|()| unreachable!())
-                .for_each(move |request| {
-                    let MappingRequest {
-                        ctx,
-                        trigger,
-                        result_sender,
-                    } = request;
-
-                    // Start the WASM module runtime.
-                    let section = host_metrics.stopwatch.start_section("module_init");
-                    let module = WasmInstance::from_valid_module_with_ctx(
-                        valid_module.cheap_clone(),
-                        ctx,
-                        host_metrics.cheap_clone(),
-                        timeout,
-                        experimental_features.clone(),
-                    )?;
-                    section.end();
-
-                    let section = host_metrics.stopwatch.start_section("run_handler");
-                    let result = match trigger {
-                        MappingTrigger::Log {
-                            transaction,
-                            log,
-                            params,
-                            handler,
-                        } => module.handle_ethereum_log(
-                            handler.handler.as_str(),
-                            transaction,
-                            log,
-                            params,
-                        ),
-                        MappingTrigger::Call {
-                            transaction,
-                            call,
-                            inputs,
-                            outputs,
-                            handler,
-                        } => module.handle_ethereum_call(
-                            handler.handler.as_str(),
-                            transaction,
-                            call,
-                            inputs,
-                            outputs,
-                        ),
-                        MappingTrigger::Block { handler } => {
-                            module.handle_ethereum_block(handler.handler.as_str())
+    // In case of failure, a thread may panic or simply terminate. Once all
+    // threads are gone, the `mapping_request_receiver` is dropped which
+    // ultimately causes the subgraph to fail the next time it tries to
+    // handle an event.
+    for _ in 0..*MAPPING_WORKERS {
+        let mapping_request_receiver = mapping_request_receiver.clone();
+        let valid_module = valid_module.cheap_clone();
+        let logger = logger.clone();
+        let host_metrics = host_metrics.cheap_clone();
+        let runtime = runtime.clone();
+        let experimental_features = experimental_features.clone();
+
+        let conf = thread::Builder::new().name(format!(
+            "mapping-{}-{}",
+            &subgraph_id,
+            uuid::Uuid::new_v4()
+        ));
+        conf.spawn(move || {
+            runtime.enter(|| {
+                // Pass incoming triggers to the WASM module and return entity changes;
+                // Stop when canceled because all RuntimeHosts and their senders were dropped.
+                loop {
+                    // The threads take turns waiting for the next request
+                    let request = match mapping_request_receiver.lock() {
+                        Ok(mut receiver) => receiver.by_ref().wait().next(),
+                        Err(_) => None,
+                    };
+                    let request = match request {
+                        Some(Ok(request)) => request,
+                        Some(Err(())) => unreachable!(),
+                        None => {
+                            debug!(logger, "Subgraph stopped, WASM runtime thread terminated");
+                            break;
                         }
                     };
-                    section.end();
-
-                    result_sender
-                        .send((result, future::ok(Instant::now())))
-                        .map_err(|_| anyhow::anyhow!("WASM module result receiver dropped."))
-                })
-                .wait()
-            {
-                Ok(()) => debug!(logger, "Subgraph stopped, WASM runtime thread terminated"),
-                Err(e) => debug!(logger, "WASM runtime thread terminated abnormally";
-                                    "error" => e.to_string()),
-            }
+
+                    if let Err(e) = handle_request(
+                        request,
+                        &valid_module,
+                        &host_metrics,
+                        timeout,
+                        &experimental_features,
+                    ) {
+                        debug!(logger, "WASM runtime thread terminated abnormally";
+                                       "error" => e.to_string());
+                        break;
+                    }
+                }
+            })
         })
-    })
-    .map(|_| ())
-    .context("Spawning WASM runtime thread failed")?;
+        .map(|_| ())
+        .context("Spawning WASM runtime thread failed")?;
+    }
 
     Ok(mapping_request_sender)
 }
 
+/// Run the handler for one request in a new instance of the WASM module
+fn handle_request(
+    request: MappingRequest,
+    valid_module: &Arc<ValidModule>,
+    host_metrics: &Arc<HostMetrics>,
+    timeout: Option<Duration>,
+    experimental_features: &ExperimentalFeatures,
+) -> Result<(), Error> {
+    let MappingRequest {
+        ctx,
+        trigger,
+        result_sender,
+    } = request;
+
+    // Start the WASM module runtime.
+    let section = host_metrics.stopwatch.start_section("module_init");
+    let module = WasmInstance::from_valid_module_with_ctx(
+        valid_module.cheap_clone(),
+        ctx,
+        host_metrics.cheap_clone(),
+        timeout,
+        experimental_features.clone(),
+    )?;
+    section.end();
+
+    let section = host_metrics.stopwatch.start_section("run_handler");
+    let result = match trigger {
+        MappingTrigger::Log {
+            transaction,
+            log,
+            params,
+            handler,
+        } => module.handle_ethereum_log(handler.handler.as_str(), transaction, log, params),
+        MappingTrigger::Call {
+            transaction,
+            call,
+            inputs,
+            outputs,
+            handler,
+        } => module.handle_ethereum_call(
+            handler.handler.as_str(),
+            transaction,
+            call,
+            inputs,
+            outputs,
+        ),
+        MappingTrigger::Block { handler } => module.handle_ethereum_block(handler.handler.as_str()),
+    };
+    section.end();
+
+    result_sender
+        .send((result, future::ok(Instant::now())))
+        .map_err(|_| anyhow::anyhow!("WASM module result receiver dropped."))
+}
+
 #[derive(Debug, AsStaticStr)]
 pub(crate) enum MappingTrigger {
     Log {
@@ -178,16 +226,33 @@ pub(crate) struct ValidModule {
 }
 
 impl ValidModule {
-    /// Pre-process and validate the module.
-    pub fn new(raw_module: &[u8]) -> Result<Self, anyhow::Error> {
+    /// Pre-process and validate the module. Mappings with `api_version` 0.0.5 or later may use
+    /// the WASM SIMD and bulk memory proposals; modules for earlier versions that use them are
+    /// rejected.
+    pub fn new(raw_module: &[u8], api_version: &Version) -> Result<Self, anyhow::Error> {
+        let features = WasmFeatures::for_api_version(api_version);
+        if let Err(e) = features.validate(raw_module) {
+            if WasmFeatures::all().validate(raw_module).is_ok() {
+                return Err(anyhow!(
+                    "the mapping uses WASM SIMD or bulk memory operations, which require \
+                     apiVersion {} or later, but it has apiVersion {}",
+                    WasmFeatures::min_api_version(),
+                    api_version
+                ));
+            }
+            return Err(anyhow!("invalid WASM module: {}", e));
+        }
+
         // We currently use Cranelift as a compilation engine. Cranelift is an optimizing compiler,
         // but that should not cause determinism issues since it adheres to the Wasm spec. Still we
         // turn off optional optimizations to be conservative.
         let mut config = wasmtime::Config::new();
         config.strategy(wasmtime::Strategy::Cranelift).unwrap();
         config.interruptable(true); // For timeouts.
-        config.cranelift_nan_canonicalization(true); // For NaN determinism.
+        config.cranelift_nan_canonicalization(true); // For NaN determinism, also for SIMD lanes.
         config.cranelift_opt_level(wasmtime::OptLevel::None);
+        config.wasm_simd(features.simd);
+        config.wasm_bulk_memory(features.bulk_memory);
         let engine = &wasmtime::Engine::new(&config);
         let module = wasmtime::Module::from_binary(&engine, raw_module)?;
 
@@ -208,3 +273,43 @@ impl ValidModule {
         })
     }
 }
+
+/// The WASM proposals that a mapping may use. They are tied to the mapping's apiVersion so that
+/// all nodes agree on which modules are valid.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct WasmFeatures {
+    simd: bool,
+    bulk_memory: bool,
+}
+
+impl WasmFeatures {
+    /// The first apiVersion that enables SIMD and bulk memory operations
+    fn min_api_version() -> Version {
+        Version::new(0, 0, 5)
+    }
+
+    fn for_api_version(api_version: &Version) -> Self {
+        let enabled = api_version >= &Self::min_api_version();
+        WasmFeatures {
+            simd: enabled,
+            bulk_memory: enabled,
+        }
+    }
+
+    fn all() -> Self {
+        WasmFeatures {
+            simd: true,
+            bulk_memory: true,
+        }
+    }
+
+    fn validate(&self, raw_module: &[u8]) -> Result<(), wasmparser::BinaryReaderError> {
+        let mut validator = wasmparser::Validator::new();
+        validator.wasm_features(wasmparser::WasmFeatures {
+            simd: self.simd,
+            bulk_memory: self.bulk_memory,
+            ..wasmparser::WasmFeatures::default()
+        });
+        validator.validate_all(raw_module)
+    }
+}
@@ -24,14 +24,18 @@ use web3::types::{Log, Transaction, U256};
 use crate::asc_abi::asc_ptr::*;
 use crate::asc_abi::class::*;
 use crate::asc_abi::*;
-use crate::host_exports::{EthereumCallError, HostExports};
+use crate::host_exports::{EthereumCallError, HostExports, PrefetchedCalls};
 use crate::mapping::ValidModule;
 use crate::UnresolvedContractCall;
 
 mod into_wasm_ret;
+mod prng;
+mod profiler;
 mod stopwatch;
 
 use into_wasm_ret::IntoWasmRet;
+use prng::Prng;
+use profiler::Sampler;
 use stopwatch::TimeoutStopwatch;
 
 #[cfg(test)]
@@ -133,7 +137,12 @@ impl WasmInstance {
             .erase()
         };
 
-        self.invoke_handler(handler_name, event)
+        let trigger = log
+            .log_index
+            .unwrap_or(U256::zero())
+            .low_u64()
+            .to_be_bytes();
+        self.invoke_handler(handler_name, &trigger, event)
     }
 
     pub(crate) fn handle_ethereum_call(
@@ -144,6 +153,16 @@ impl WasmInstance {
         inputs: Vec<LogParam>,
         outputs: Vec<LogParam>,
     ) -> Result<BlockState, MappingError> {
+        // Several calls in one transaction can trigger the same handler;
+        // the trace address tells them apart
+        let mut trigger = transaction
+            .transaction_index
+            .map_or(0, |index| index.as_u64())
+            .to_be_bytes()
+            .to_vec();
+        for index in &call.trace_address {
+            trigger.extend_from_slice(&(*index as u64).to_be_bytes());
+        }
         let call = EthereumCallData {
             to: call.to,
             from: call.from,
@@ -158,7 +177,7 @@ impl WasmInstance {
             self.asc_new::<AscEthereumCall, _>(&call)?.erase()
         };
 
-        self.invoke_handler(handler_name, arg)
+        self.invoke_handler(handler_name, &trigger, arg)
     }
 
     pub(crate) fn handle_ethereum_block(
@@ -170,7 +189,9 @@ impl WasmInstance {
         // Prepare an EthereumBlock for the WASM runtime
         let arg = self.asc_new(&block)?;
 
-        self.invoke_handler(handler_name, arg)
+        // Each data source runs the block handler for the same block
+        let trigger = self.instance_ctx().ctx.host_exports.data_source_address();
+        self.invoke_handler(handler_name, trigger.as_bytes(), arg)
     }
 
     pub(crate) fn take_ctx(&mut self) -> WasmInstanceContext {
@@ -193,6 +214,7 @@ impl WasmInstance {
     fn invoke_handler<C>(
         &mut self,
         handler: &str,
+        trigger: &[u8],
         arg: AscPtr<C>,
     ) -> Result<BlockState, MappingError> {
         let func = self
@@ -203,8 +225,18 @@ impl WasmInstance {
         // Caution: Make sure all exit paths from this function call `exit_handler`.
         self.instance_ctx_mut().ctx.state.enter_handler();
 
+        let block_hash = self.instance_ctx().ctx.block.hash.unwrap_or_default();
+        self.instance_ctx_mut().prng = Some(Prng::new(block_hash, handler, trigger));
+
+        let sampler = Sampler::new(&self.instance_ctx().ctx.host_exports.subgraph_id, handler);
+        self.instance_ctx_mut().sampler = sampler;
+        let result = func.get1()?(arg.wasm_ptr());
+        if let Some(sampler) = self.instance_ctx_mut().sampler.take() {
+            sampler.finish();
+        }
+
         // This `match` will return early if there was a non-deterministic trap.
-        let deterministic_error: Option<Error> = match func.get1()?(arg.wasm_ptr()) {
+        let deterministic_error: Option<Error> = match result {
             Ok(()) => None,
             Err(trap) if self.instance_ctx().possible_reorg => {
                 self.instance_ctx_mut().ctx.state.exit_handler();
@@ -235,7 +267,9 @@ impl WasmInstance {
                     _ if self.instance_ctx().deterministic_host_trap => Some(e),
                     _ => {
                         self.instance_ctx_mut().ctx.state.exit_handler();
-                        return Err(MappingError::Unknown(e));
+                        return Err(MappingError::Unknown(
+                            e.context(format!("Handler '{}' failed", handler)),
+                        ));
                     }
                 }
             }
@@ -308,6 +342,15 @@ pub(crate) struct WasmInstanceContext {
     deterministic_host_trap: bool,
 
     pub(crate) experimental_features: ExperimentalFeatures,
+
+    // Samples the WASM stack for the profiler while a handler runs.
+    sampler: Option<Sampler>,
+
+    // The random numbers for `prng.next()`, set when a handler is invoked.
+    prng: Option<Prng>,
+
+    // The results of the calls prefetched with `ethereum.prefetchCalls`.
+    prefetched_calls: PrefetchedCalls,
 }
 
 impl WasmInstance {
@@ -390,6 +433,9 @@ impl WasmInstance {
                             }
 
                             let instance = instance.as_mut().unwrap();
+                            if let Some(sampler) = instance.sampler.as_mut() {
+                                sampler.sample($wasm_name);
+                            }
                             let _section = instance.host_metrics.stopwatch.start_section($section);
 
                             let result = instance.$rust_name(
@@ -450,6 +496,9 @@ impl WasmInstance {
                     }
 
                     let instance = instance.as_mut().unwrap();
+                    if let Some(sampler) = instance.sampler.as_mut() {
+                        sampler.sample("ethereum.call");
+                    }
                     let stopwatch = &instance.host_metrics.stopwatch;
                     let _section = stopwatch.start_section("host_export_ethereum_call");
 
@@ -487,7 +536,37 @@ impl WasmInstance {
 
         link!("abort", abort, message_ptr, file_name_ptr, line, column);
 
+        link!(
+            "ethereum.prefetchCalls",
+            ethereum_prefetch_calls,
+            "host_export_ethereum_prefetch_calls",
+            calls_ptr
+        );
+
+        link!(
+            "ethereum.feeHistory",
+            ethereum_fee_history,
+            "host_export_ethereum_fee_history",
+            from,
+            to
+        );
+
         link!("store.get", store_get, "host_export_store_get", entity, id);
+        link!(
+            "store.loadRelated",
+            store_load_related,
+            "host_export_store_load_related",
+            entity,
+            id,
+            field
+        );
+        link!(
+            "store.prefetch",
+            store_prefetch,
+            "host_export_store_prefetch",
+            entity,
+            ids
+        );
         link!(
             "store.set",
             store_set,
@@ -509,6 +588,14 @@ impl WasmInstance {
         );
 
         link!("store.remove", store_remove, entity_ptr, id_ptr);
+        link!(
+            "store.increment",
+            store_increment,
+            entity_ptr,
+            id_ptr,
+            field_ptr,
+            delta_ptr
+        );
 
         link!("typeConversion.bytesToString", bytes_to_string, ptr);
         link!("typeConversion.bytesToHex", bytes_to_hex, ptr);
@@ -553,11 +640,14 @@ impl WasmInstance {
         link!("dataSource.address", data_source_address,);
         link!("dataSource.network", data_source_network,);
         link!("dataSource.context", data_source_context,);
+        link!("deployment.context", deployment_context,);
 
         link!("ens.nameByHash", ens_name_by_hash, ptr);
 
         link!("log.log", log_log, level, msg_ptr);
 
+        link!("prng.next", prng_next,);
+
         link!("arweave.transactionData", arweave_transaction_data, ptr);
 
         link!("box.profile", box_profile, ptr);
@@ -686,6 +776,9 @@ impl WasmInstanceContext {
             possible_reorg: false,
             deterministic_host_trap: false,
             experimental_features,
+            sampler: None,
+            prng: None,
+            prefetched_calls: PrefetchedCalls::new(),
         })
     }
 
@@ -724,6 +817,9 @@ impl WasmInstanceContext {
             possible_reorg: false,
             deterministic_host_trap: false,
             experimental_features,
+            sampler: None,
+            prng: None,
+            prefetched_calls: PrefetchedCalls::new(),
         })
     }
 }
@@ -782,6 +878,30 @@ impl WasmInstanceContext {
         Ok(())
     }
 
+    /// function store.increment(entity: string, id: string, field: string, delta: Value): void
+    fn store_increment(
+        &mut self,
+        entity_ptr: AscPtr<AscString>,
+        id_ptr: AscPtr<AscString>,
+        field_ptr: AscPtr<AscString>,
+        delta_ptr: AscPtr<AscEnum<StoreValueKind>>,
+    ) -> Result<(), HostExportError> {
+        let entity = self.asc_get(entity_ptr)?;
+        let id = self.asc_get(id_ptr)?;
+        let field = self.asc_get(field_ptr)?;
+        let delta = self.try_asc_get(delta_ptr)?;
+        self.ctx.host_exports.store_increment(
+            &self.ctx.logger,
+            &mut self.ctx.state,
+            &self.ctx.proof_of_indexing,
+            entity,
+            id,
+            field,
+            delta,
+        )?;
+        Ok(())
+    }
+
     /// function store.remove(entity: string, id: string): void
     fn store_remove(
         &mut self,
@@ -830,15 +950,48 @@ impl WasmInstanceContext {
         Ok(ret)
     }
 
+    /// function store.loadRelated(entity: string, id: string, field: string): Array<Entity>
+    fn store_load_related(
+        &mut self,
+        entity_ptr: AscPtr<AscString>,
+        id_ptr: AscPtr<AscString>,
+        field_ptr: AscPtr<AscString>,
+    ) -> Result<AscPtr<Array<AscPtr<AscEntity>>>, HostExportError> {
+        let entity = self.asc_get(entity_ptr)?;
+        let id = self.asc_get(id_ptr)?;
+        let field = self.asc_get(field_ptr)?;
+        let entities =
+            self.ctx
+                .host_exports
+                .store_load_related(&mut self.ctx.state, entity, id, field)?;
+        Ok(self.asc_new(entities.as_slice())?)
+    }
+
+    /// function store.prefetch(entity: string, ids: Array<string>): void
+    fn store_prefetch(
+        &mut self,
+        entity_ptr: AscPtr<AscString>,
+        ids_ptr: AscPtr<Array<AscPtr<AscString>>>,
+    ) -> Result<(), HostExportError> {
+        let entity = self.asc_get(entity_ptr)?;
+        let ids: Vec<String> = self.asc_get(ids_ptr)?;
+        self.ctx
+            .host_exports
+            .store_prefetch(&mut self.ctx.state, entity, ids)?;
+        Ok(())
+    }
+
     /// function ethereum.call(call: SmartContractCall): Array<Token> | null
     fn ethereum_call(
         &mut self,
         call: UnresolvedContractCall,
     ) -> Result<AscEnumArray<EthereumValueKind>, HostExportError> {
-        let result = self
-            .ctx
-            .host_exports
-            .ethereum_call(&self.ctx.logger, &self.ctx.block, call);
+        let result = self.ctx.host_exports.ethereum_call(
+            &self.ctx.logger,
+            &self.ctx.block,
+            call,
+            &self.prefetched_calls,
+        );
         match result {
             Ok(Some(tokens)) => Ok(self.asc_new(tokens.as_slice())?),
             Ok(None) => Ok(AscPtr::null()),
@@ -850,6 +1003,46 @@ impl WasmInstanceContext {
         }
     }
 
+    /// function ethereum.prefetchCalls(calls: Array<SmartContractCall>): void
+    fn ethereum_prefetch_calls(&mut self, calls_ptr: u32) -> Result<(), HostExportError> {
+        // See `ethereum.call` for the difference between the API versions
+        let calls: Vec<UnresolvedContractCall> =
+            if self.ctx.host_exports.api_version >= Version::new(0, 0, 4) {
+                self.asc_get::<_, Array<AscPtr<AscUnresolvedContractCall_0_0_4>>>(calls_ptr.into())?
+            } else {
+                self.asc_get::<_, Array<AscPtr<AscUnresolvedContractCall>>>(calls_ptr.into())?
+            };
+        let result = self.ctx.host_exports.ethereum_prefetch_calls(
+            &self.ctx.logger,
+            &self.ctx.block,
+            calls,
+            &mut self.prefetched_calls,
+        );
+        match result {
+            Ok(()) => Ok(()),
+            Err(EthereumCallError::Unknown(e)) => Err(HostExportError::Unknown(e)),
+            Err(EthereumCallError::PossibleReorg(e)) => {
+                self.possible_reorg = true;
+                Err(HostExportError::Unknown(e))
+            }
+        }
+    }
+
+    /// function ethereum.feeHistory(from: i32, to: i32): Array<BlockFeeStats>
+    fn ethereum_fee_history(
+        &mut self,
+        from: u32,
+        to: u32,
+    ) -> Result<AscPtr<Array<AscPtr<AscBlockFeeStats>>>, HostExportError> {
+        let stats = self.ctx.host_exports.fee_history(
+            &self.ctx.logger,
+            &self.ctx.block,
+            from.into(),
+            to.into(),
+        )?;
+        Ok(self.asc_new(stats.as_slice())?)
+    }
+
     /// function typeConversion.bytesToString(bytes: Bytes): string
     fn bytes_to_string(
         &mut self,
@@ -1302,6 +1495,11 @@ impl WasmInstanceContext {
         self.asc_new(&self.ctx.host_exports.data_source_context())
     }
 
+    /// function deployment.context(): DataSourceContext
+    fn deployment_context(&mut self) -> Result<AscPtr<AscEntity>, DeterministicHostError> {
+        self.asc_new(&self.ctx.host_exports.deployment_context())
+    }
+
     fn ens_name_by_hash(
         &mut self,
         hash_ptr: AscPtr<AscString>,
@@ -1313,6 +1511,13 @@ impl WasmInstanceContext {
             .unwrap_or(Ok(AscPtr::null()))
     }
 
+    /// function prng.next(): u64
+    fn prng_next(&mut self) -> Result<u64, DeterministicHostError> {
+        self.prng.as_mut().map(Prng::next).ok_or_else(|| {
+            DeterministicHostError(anyhow!("`prng.next` can only be called from a handler"))
+        })
+    }
+
     fn log_log(
         &mut self,
         level: u32,
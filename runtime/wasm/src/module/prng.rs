@@ -0,0 +1,62 @@
+use graph::prelude::tiny_keccak::keccak256;
+use graph::prelude::web3::types::H256;
+
+/// The deterministic random numbers behind `prng.next()`. The numbers for
+/// one handler invocation only depend on the hash of the block, the name
+/// of the handler, and what triggered the handler in the block, so that
+/// indexing the same block again produces the same numbers. They are
+/// predictable for anybody who knows the block and must not be used for
+/// anything that needs to be hard to guess
+pub(crate) struct Prng {
+    seed: [u8; 32],
+    counter: u64,
+}
+
+impl Prng {
+    /// The random numbers for running `handler` for the `trigger` in the
+    /// block `block_hash`. The trigger is the log index for event handlers,
+    /// the transaction index and trace address of the call for call
+    /// handlers, and the address of the data source for block handlers
+    pub fn new(block_hash: H256, handler: &str, trigger: &[u8]) -> Self {
+        let mut input = Vec::with_capacity(32 + handler.len() + trigger.len());
+        input.extend_from_slice(block_hash.as_bytes());
+        input.extend_from_slice(handler.as_bytes());
+        input.extend_from_slice(trigger);
+        Prng {
+            seed: keccak256(&input),
+            counter: 0,
+        }
+    }
+
+    pub fn next(&mut self) -> u64 {
+        let mut input = [0u8; 40];
+        input[..32].copy_from_slice(&self.seed);
+        input[32..].copy_from_slice(&self.counter.to_be_bytes());
+        self.counter += 1;
+
+        let mut bytes = [0u8; 8];
+        bytes.copy_from_slice(&keccak256(&input)[..8]);
+        u64::from_be_bytes(bytes)
+    }
+}
+
+#[test]
+fn prng_is_deterministic() {
+    let hash = H256::from_low_u64_be(7);
+
+    let mut prng = Prng::new(hash, "handleTransfer", &[3]);
+    let first: Vec<_> = (0..4).map(|_| prng.next()).collect();
+    let mut prng = Prng::new(hash, "handleTransfer", &[3]);
+    let second: Vec<_> = (0..4).map(|_| prng.next()).collect();
+    assert_eq!(first, second);
+    assert_ne!(first[0], first[1]);
+
+    let other =
+        |block_hash, handler, trigger: &[u8]| Prng::new(block_hash, handler, trigger).next();
+    assert_ne!(
+        first[0],
+        other(H256::from_low_u64_be(8), "handleTransfer", &[3])
+    );
+    assert_ne!(first[0], other(hash, "handleApproval", &[3]));
+    assert_ne!(first[0], other(hash, "handleTransfer", &[4]));
+}
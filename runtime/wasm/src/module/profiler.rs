@@ -0,0 +1,67 @@
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use graph::prelude::SubgraphDeploymentId;
+use graph::util::profiler::{Profile, PROFILER_INTERVAL};
+use wasmtime::Trap;
+
+/// Samples the WASM stack while a handler runs and adds the samples to the
+/// profile of the deployment.
+///
+/// We can only get at the WASM stack from inside a host function, and
+/// therefore take a sample on the first call to a host function after
+/// `PROFILER_INTERVAL` has passed since the last sample. The sample is
+/// charged with all the time since the last sample. Code that rarely calls
+/// host functions will therefore be underrepresented in the profile, and
+/// the host functions it eventually calls overrepresented
+pub(crate) struct Sampler {
+    profile: Arc<Profile>,
+    interval: Duration,
+    handler: String,
+    last: Instant,
+}
+
+impl Sampler {
+    /// Return a sampler for running `handler`, or `None` if profiling is
+    /// turned off
+    pub fn new(deployment: &SubgraphDeploymentId, handler: &str) -> Option<Self> {
+        PROFILER_INTERVAL.map(|interval| Sampler {
+            profile: Profile::for_deployment(deployment),
+            interval,
+            handler: handler.to_string(),
+            last: Instant::now(),
+        })
+    }
+
+    /// Called on entry to the host function `host_fn`
+    pub fn sample(&mut self, host_fn: &str) {
+        let elapsed = self.last.elapsed();
+        if elapsed < self.interval {
+            return;
+        }
+
+        // Creating a trap captures the current WASM stack, with function
+        // names taken from the name section of the module
+        let trap = Trap::new("profiler sample");
+        let mut frames = vec![self.handler.clone()];
+        frames.extend(
+            trap.trace()
+                .iter()
+                .rev()
+                .map(|frame| match frame.func_name() {
+                    Some(name) => name.to_string(),
+                    None => format!("func[{}]", frame.func_index()),
+                }),
+        );
+        frames.push(host_fn.to_string());
+
+        self.profile.add(frames.join(";"), elapsed);
+        self.last = Instant::now();
+    }
+
+    /// Charge the time since the last sample to the handler itself since we
+    /// do not know where in the handler it was spent
+    pub fn finish(self) {
+        self.profile.add(self.handler, self.last.elapsed());
+    }
+}
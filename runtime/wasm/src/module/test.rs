@@ -7,7 +7,7 @@ use std::str::FromStr;
 use crate::host_exports::HostExports;
 use graph::components::store::*;
 use graph::data::store::scalar;
-use graph::data::subgraph::*;
+use graph::data::subgraph::{limits::EntityLimits, *};
 use graph::mock::MockEthereumAdapter;
 use graph_chain_arweave::adapter::ArweaveAdapter;
 use graph_core;
@@ -71,7 +71,13 @@ fn test_valid_module_and_store_with_timeout(
     };
 
     let module = WasmInstance::from_valid_module_with_ctx(
-        Arc::new(ValidModule::new(data_source.mapping.runtime.as_ref()).unwrap()),
+        Arc::new(
+            ValidModule::new(
+                data_source.mapping.runtime.as_ref(),
+                &Version::parse(&data_source.mapping.api_version).unwrap(),
+            )
+            .unwrap(),
+        ),
         mock_context(deployment_id, data_source, store.clone(), call_cache),
         host_metrics,
         timeout,
@@ -121,7 +127,7 @@ fn mock_host_exports(
     subgraph_id: SubgraphDeploymentId,
     data_source: DataSource,
     store: Arc<impl SubgraphStore>,
-    call_cache: Arc<impl EthereumCallCache>,
+    call_cache: Arc<impl EthereumCallCache + ChainStore>,
 ) -> HostExports {
     let mock_ethereum_adapter = Arc::new(MockEthereumAdapter::default());
     let arweave_adapter = Arc::new(ArweaveAdapter::new("https://arweave.net".to_string()));
@@ -157,6 +163,8 @@ fn mock_host_exports(
         data_source.source.address,
         data_source.network.unwrap(),
         data_source.context,
+        Arc::new(BTreeMap::new()),
+        EntityLimits::default(),
         Arc::new(templates),
         data_source.mapping.abis,
         mock_ethereum_adapter,
@@ -164,6 +172,7 @@ fn mock_host_exports(
             ipfs_api::IpfsClient::default(),
         )),
         store,
+        call_cache.clone(),
         call_cache,
         arweave_adapter,
         three_box_adapter,
@@ -174,7 +183,7 @@ fn mock_context(
     subgraph_id: SubgraphDeploymentId,
     data_source: DataSource,
     store: Arc<impl SubgraphStore>,
-    call_cache: Arc<impl EthereumCallCache>,
+    call_cache: Arc<impl EthereumCallCache + ChainStore>,
 ) -> MappingContext {
     let mut block = LightEthereumBlock::default();
     block.hash = Some(Default::default());
@@ -761,3 +770,24 @@ async fn detect_contract_calls() {
         true
     );
 }
+
+#[test]
+fn wasm_features_require_api_version() {
+    // A module with one function that runs `memory.fill` from the bulk
+    // memory proposal
+    let bulk_memory: &[u8] = &[
+        0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00, // header
+        0x01, 0x04, 0x01, 0x60, 0x00, 0x00, // type section: [] -> []
+        0x03, 0x02, 0x01, 0x00, // function section
+        0x05, 0x03, 0x01, 0x00, 0x01, // memory section: one page
+        0x0a, 0x0d, 0x01, 0x0b, 0x00, // code section, one function without locals
+        0x41, 0x00, 0x41, 0x00, 0x41, 0x00, // i32.const 0 (three times)
+        0xfc, 0x0b, 0x00, 0x0b, // memory.fill, end
+    ];
+
+    let err = ValidModule::new(bulk_memory, &Version::new(0, 0, 4))
+        .err()
+        .expect("bulk memory is not allowed for apiVersion 0.0.4");
+    assert!(err.to_string().contains("0.0.5"), "{}", err);
+    assert!(ValidModule::new(bulk_memory, &Version::new(0, 0, 5)).is_ok());
+}
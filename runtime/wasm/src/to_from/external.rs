@@ -2,7 +2,7 @@ use ethabi;
 use std::collections::HashMap;
 
 use graph::components::ethereum::{
-    EthereumBlockData, EthereumCallData, EthereumEventData, EthereumTransactionData,
+    BlockFeeStats, EthereumBlockData, EthereumCallData, EthereumEventData, EthereumTransactionData,
 };
 use graph::data::store;
 use graph::prelude::serde_json;
@@ -357,6 +357,24 @@ impl ToAscObj<AscEthereumBlock> for EthereumBlockData {
     }
 }
 
+impl ToAscObj<AscBlockFeeStats> for BlockFeeStats {
+    fn to_asc_obj<H: AscHeap>(
+        &self,
+        heap: &mut H,
+    ) -> Result<AscBlockFeeStats, DeterministicHostError> {
+        Ok(AscBlockFeeStats {
+            number: heap.asc_new(&BigInt::from(self.number))?,
+            hash: heap.asc_new(&self.hash)?,
+            gas_used: heap.asc_new(&BigInt::from_unsigned_u256(&self.gas_used))?,
+            gas_limit: heap.asc_new(&BigInt::from_unsigned_u256(&self.gas_limit))?,
+            transaction_count: heap.asc_new(&BigInt::from(self.transaction_count))?,
+            min_gas_price: heap.asc_new(&BigInt::from_unsigned_u256(&self.min_gas_price))?,
+            median_gas_price: heap.asc_new(&BigInt::from_unsigned_u256(&self.median_gas_price))?,
+            max_gas_price: heap.asc_new(&BigInt::from_unsigned_u256(&self.max_gas_price))?,
+        })
+    }
+}
+
 impl ToAscObj<AscEthereumTransaction> for EthereumTransactionData {
     fn to_asc_obj<H: AscHeap>(
         &self,
@@ -0,0 +1,114 @@
+//! Roles of the clients that send queries to the GraphQL server.
+//!
+//! Types and fields with a `@restricted(roles: [..])` directive can only be
+//! queried on behalf of one of the roles in the directive. Setting
+//! `GRAPH_GRAPHQL_TOKENS` to a comma-separated list of
+//! `<token>:<role>+<role>..` hands out these roles: a query that carries an
+//! `Authorization: Bearer <token>` header is made on behalf of the roles of
+//! that token. Queries without a token are anonymous and have no roles.
+use http::header::{HeaderMap, AUTHORIZATION};
+use std::collections::BTreeSet;
+use std::env;
+use std::str::FromStr;
+use subtle::ConstantTimeEq;
+
+use graph::components::server::query::GraphQLServerError;
+use graph::prelude::lazy_static;
+
+lazy_static! {
+    static ref TOKENS: Option<QueryRoles> = env::var("GRAPH_GRAPHQL_TOKENS")
+        .ok()
+        .map(|s| s.parse().expect("invalid GRAPH_GRAPHQL_TOKENS"));
+}
+
+/// Return the roles on behalf of which a request with `headers` is made,
+/// based on the tokens configured through the environment
+pub fn roles_from_headers(headers: &HeaderMap) -> Result<BTreeSet<String>, GraphQLServerError> {
+    let tokens = match TOKENS.as_ref() {
+        Some(tokens) => tokens,
+        None => return Ok(BTreeSet::new()),
+    };
+    let token = headers
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .map(str::trim);
+    tokens.roles(token).map_err(GraphQLServerError::ClientError)
+}
+
+/// Compare a secret in time that does not depend on where `given` differs
+/// from `known`, so that responses do not reveal how much of a guess was
+/// right
+fn secret_eq(known: &str, given: &str) -> bool {
+    known.as_bytes().ct_eq(given.as_bytes()).into()
+}
+
+/// The roles that each token grants
+#[derive(Clone, Debug)]
+pub struct QueryRoles {
+    tokens: Vec<(String, BTreeSet<String>)>,
+}
+
+impl QueryRoles {
+    /// The roles for `token`; anonymous queries have no roles, and unknown
+    /// tokens are rejected
+    pub fn roles(&self, token: Option<&str>) -> Result<BTreeSet<String>, String> {
+        let token = match token {
+            Some(token) => token,
+            None => return Ok(BTreeSet::new()),
+        };
+        // Compare with all tokens, even once we found a match, so that the
+        // time this takes does not reveal which token matched
+        let mut found = None;
+        for (known, roles) in &self.tokens {
+            if secret_eq(known, token) {
+                found.get_or_insert(roles);
+            }
+        }
+        found.cloned().ok_or_else(|| "unknown token".to_owned())
+    }
+}
+
+impl FromStr for QueryRoles {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let tokens = s
+            .split(',')
+            .map(str::trim)
+            .filter(|entry| !entry.is_empty())
+            .map(|entry| {
+                let mut parts = entry.splitn(2, ':');
+                let token = parts.next().unwrap_or("");
+                let roles = parts.next().unwrap_or("");
+                if token.is_empty() || roles.is_empty() {
+                    return Err("tokens must have the form `<token>:<role>+<role>..`".to_owned());
+                }
+                Ok((
+                    token.to_owned(),
+                    roles.split('+').map(str::to_owned).collect(),
+                ))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(QueryRoles { tokens })
+    }
+}
+
+#[test]
+fn token_roles() {
+    let tokens: QueryRoles = "t1:ops+audit, t2:ops".parse().unwrap();
+    let roles = |roles: &[&str]| {
+        roles
+            .iter()
+            .map(|role| role.to_string())
+            .collect::<BTreeSet<_>>()
+    };
+
+    assert_eq!(Ok(roles(&["audit", "ops"])), tokens.roles(Some("t1")));
+    assert_eq!(Ok(roles(&["ops"])), tokens.roles(Some("t2")));
+    assert_eq!(Ok(roles(&[])), tokens.roles(None));
+    assert!(tokens.roles(Some("t3")).is_err());
+
+    assert!("t1".parse::<QueryRoles>().is_err());
+    assert!("t1:".parse::<QueryRoles>().is_err());
+}
@@ -8,9 +8,11 @@ extern crate http;
 extern crate hyper;
 extern crate serde;
 
+mod auth;
 mod request;
 mod server;
 mod service;
+mod webhooks;
 
 pub use self::request::GraphQLRequest;
 pub use self::server::GraphQLServer;
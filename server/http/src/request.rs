@@ -1,10 +1,57 @@
 use graph::prelude::serde_json;
 use graphql_parser;
 use hyper::body::Bytes;
+use std::collections::{HashMap, VecDeque};
+use std::env;
+use std::sync::Mutex;
 
 use graph::components::server::query::GraphQLServerError;
 use graph::prelude::*;
 
+lazy_static! {
+    /// The number of parsed queries to remember by their text, so that
+    /// queries that are sent over and over are only parsed once. Setting
+    /// this to 0 turns the cache off
+    static ref QUERY_PARSE_CACHE_SIZE: usize = env::var("GRAPH_QUERY_PARSE_CACHE_SIZE")
+        .map(|s| s.parse::<usize>().expect("invalid GRAPH_QUERY_PARSE_CACHE_SIZE"))
+        .unwrap_or(1000);
+    static ref PARSED_QUERIES: Mutex<(HashMap<String, q::Document>, VecDeque<String>)> =
+        Mutex::new((HashMap::new(), VecDeque::new()));
+}
+
+/// Parse `text`, or return a copy of the document we got when we parsed
+/// the same text before. Only documents that parsed successfully are
+/// remembered; when the cache is full, the oldest ones are evicted first
+fn parse_query(text: &str) -> Result<q::Document, QueryError> {
+    if let Some(document) = PARSED_QUERIES.lock().unwrap().0.get(text) {
+        return Ok(document.clone());
+    }
+
+    let document = graphql_parser::parse_query(text)
+        .map_err(|e| QueryError::ParseError(Arc::new(e.into())))?
+        .into_static();
+
+    if *QUERY_PARSE_CACHE_SIZE > 0 {
+        let mut cache = PARSED_QUERIES.lock().unwrap();
+        let (documents, order) = &mut *cache;
+        if documents
+            .insert(text.to_owned(), document.clone())
+            .is_none()
+        {
+            order.push_back(text.to_owned());
+        }
+        while documents.len() > *QUERY_PARSE_CACHE_SIZE {
+            match order.pop_front() {
+                Some(oldest) => {
+                    documents.remove(&oldest);
+                }
+                None => break,
+            }
+        }
+    }
+    Ok(document)
+}
+
 /// Future for a query parsed from an HTTP request.
 pub struct GraphQLRequest {
     body: Bytes,
@@ -44,9 +91,7 @@ impl Future for GraphQLRequest {
         })?;
 
         // Parse the "query" field of the JSON body
-        let document = graphql_parser::parse_query(query_string)
-            .map_err(|e| GraphQLServerError::from(QueryError::ParseError(Arc::new(e.into()))))?
-            .into_static();
+        let document = parse_query(query_string).map_err(GraphQLServerError::from)?;
 
         // Parse the "variables" field of the JSON body, if present
         let variables = match obj.get("variables") {
@@ -5,6 +5,7 @@ use hyper::service::make_service_fn;
 use hyper::Server;
 
 use crate::service::{GraphQLService, GraphQLServiceMetrics};
+use crate::webhooks::Webhooks;
 use graph::prelude::{GraphQLServer as GraphQLServerTrait, *};
 use thiserror::Error;
 
@@ -27,9 +28,13 @@ pub struct GraphQLServer<Q> {
     metrics: Arc<GraphQLServiceMetrics>,
     graphql_runner: Arc<Q>,
     node_id: NodeId,
+    webhooks: Option<Arc<Webhooks<Q>>>,
 }
 
-impl<Q> GraphQLServer<Q> {
+impl<Q> GraphQLServer<Q>
+where
+    Q: GraphQlRunner,
+{
     /// Creates a new GraphQL server.
     pub fn new(
         logger_factory: &LoggerFactory,
@@ -46,11 +51,13 @@ impl<Q> GraphQLServer<Q> {
             }),
         );
         let metrics = Arc::new(GraphQLServiceMetrics::new(metrics_registry.clone()));
+        let webhooks = Webhooks::new(&logger, graphql_runner.clone()).map(Arc::new);
         GraphQLServer {
             logger,
             metrics,
             graphql_runner,
             node_id,
+            webhooks,
         }
     }
 }
@@ -81,6 +88,7 @@ where
         let graphql_runner = self.graphql_runner.clone();
         let metrics = self.metrics.clone();
         let node_id = self.node_id.clone();
+        let webhooks = self.webhooks.clone();
         let new_service = make_service_fn(move |_| {
             futures03::future::ok::<_, Error>(GraphQLService::new(
                 logger_for_service.clone(),
@@ -88,6 +96,7 @@ where
                 graphql_runner.clone(),
                 ws_port,
                 node_id.clone(),
+                webhooks.clone(),
             ))
         });
 
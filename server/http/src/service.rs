@@ -11,7 +11,9 @@ use http::header;
 use hyper::service::Service;
 use hyper::{Body, Method, Request, Response, StatusCode};
 
+use crate::auth::roles_from_headers;
 use crate::request::GraphQLRequest;
+use crate::webhooks::Webhooks;
 
 pub struct GraphQLServiceMetrics {
     query_execution_time: Box<HistogramVec>,
@@ -76,6 +78,7 @@ pub struct GraphQLService<Q> {
     graphql_runner: Arc<Q>,
     ws_port: u16,
     node_id: NodeId,
+    webhooks: Option<Arc<Webhooks<Q>>>,
 }
 
 impl<Q> Clone for GraphQLService<Q> {
@@ -86,6 +89,7 @@ impl<Q> Clone for GraphQLService<Q> {
             graphql_runner: self.graphql_runner.clone(),
             ws_port: self.ws_port,
             node_id: self.node_id.clone(),
+            webhooks: self.webhooks.clone(),
         }
     }
 }
@@ -101,6 +105,7 @@ where
         graphql_runner: Arc<Q>,
         ws_port: u16,
         node_id: NodeId,
+        webhooks: Option<Arc<Webhooks<Q>>>,
     ) -> Self {
         GraphQLService {
             logger,
@@ -108,6 +113,7 @@ where
             graphql_runner,
             ws_port,
             node_id,
+            webhooks,
         }
     }
 
@@ -161,7 +167,7 @@ where
             GraphQLServerError::ClientError(format!("Invalid subgraph name {:?}", subgraph_name))
         })?;
 
-        self.handle_graphql_query(subgraph_name.into(), request.into_body())
+        self.handle_graphql_query(subgraph_name.into(), request)
             .await
     }
 
@@ -174,25 +180,27 @@ where
             .map_err(|id| GraphQLServerError::ClientError(format!("Invalid subgraph id `{}`", id)));
         match res {
             Err(_) => self.handle_not_found(),
-            Ok(id) => self
-                .handle_graphql_query(id.into(), request.into_body())
-                .boxed(),
+            Ok(id) => self.handle_graphql_query(id.into(), request).boxed(),
         }
     }
 
     async fn handle_graphql_query(
         self,
         target: QueryTarget,
-        request_body: Body,
+        request: Request<Body>,
     ) -> GraphQLServiceResult {
         let service = self.clone();
         let service_metrics = self.metrics.clone();
 
         let start = Instant::now();
-        let body = hyper::body::to_bytes(request_body)
+        let roles = roles_from_headers(request.headers())?;
+        let body = hyper::body::to_bytes(request.into_body())
             .map_err(|_| GraphQLServerError::InternalError("Failed to read request body".into()))
             .await?;
-        let query = GraphQLRequest::new(body).compat().await;
+        let query = GraphQLRequest::new(body)
+            .compat()
+            .await
+            .map(|query| query.with_roles(roles));
 
         let result = match query {
             Ok(query) => service.graphql_runner.run_query(query, target, false).await,
@@ -208,13 +216,60 @@ where
         Ok(result.as_http_response())
     }
 
+    async fn handle_register_webhook(
+        self,
+        target: Result<QueryTarget, GraphQLServerError>,
+        request: Request<Body>,
+    ) -> GraphQLServiceResult {
+        let webhooks = match &self.webhooks {
+            Some(webhooks) => webhooks,
+            None => return self.handle_not_found().await,
+        };
+        webhooks.authorize(request.headers())?;
+        let target = target?;
+        let body = hyper::body::to_bytes(request.into_body())
+            .map_err(|_| GraphQLServerError::InternalError("Failed to read request body".into()))
+            .await?;
+
+        let id = webhooks.register(target, body).await?;
+        Ok(Response::builder()
+            .status(StatusCode::CREATED)
+            .header("Content-Type", "application/json")
+            .body(Body::from(serde_json::json!({ "id": id }).to_string()))
+            .unwrap())
+    }
+
+    async fn handle_remove_webhook(
+        self,
+        id: String,
+        request: Request<Body>,
+    ) -> GraphQLServiceResult {
+        let webhooks = match &self.webhooks {
+            Some(webhooks) => webhooks,
+            None => return self.handle_not_found().await,
+        };
+        webhooks.authorize(request.headers())?;
+
+        if webhooks.remove(&id) {
+            Ok(Response::builder()
+                .status(StatusCode::NO_CONTENT)
+                .body(Body::empty())
+                .unwrap())
+        } else {
+            self.handle_not_found().await
+        }
+    }
+
     // Handles OPTIONS requests
     fn handle_graphql_options(&self, _request: Request<Body>) -> GraphQLServiceResponse {
         async {
             Ok(Response::builder()
                 .status(200)
                 .header("Access-Control-Allow-Origin", "*")
-                .header("Access-Control-Allow-Headers", "Content-Type, User-Agent")
+                .header(
+                    "Access-Control-Allow-Headers",
+                    "Content-Type, User-Agent, Authorization",
+                )
                 .header("Access-Control-Allow-Methods", "GET, OPTIONS, POST")
                 .body(Body::from(""))
                 .unwrap())
@@ -304,6 +359,32 @@ where
                     .boxed()
             }
 
+            (Method::POST, &["subgraphs", "id", subgraph_id, "webhooks"]) => {
+                let target = SubgraphDeploymentId::new(subgraph_id)
+                    .map(QueryTarget::from)
+                    .map_err(|id| {
+                        GraphQLServerError::ClientError(format!("Invalid subgraph id `{}`", id))
+                    });
+                self.handle_register_webhook(target, req).boxed()
+            }
+            (Method::POST, ["subgraphs", "name", subgraph_name, "webhooks"]) => {
+                let target = subgraph_name_target(subgraph_name.to_string());
+                self.handle_register_webhook(target, req).boxed()
+            }
+            (
+                Method::POST,
+                ["subgraphs", "name", subgraph_name_part1, subgraph_name_part2, "webhooks"],
+            ) => {
+                let target = subgraph_name_target(format!(
+                    "{}/{}",
+                    subgraph_name_part1, subgraph_name_part2
+                ));
+                self.handle_register_webhook(target, req).boxed()
+            }
+            (Method::DELETE, &["webhooks", id]) => {
+                self.handle_remove_webhook(id.to_owned(), req).boxed()
+            }
+
             (Method::OPTIONS, ["subgraphs", "name", _])
             | (Method::OPTIONS, ["subgraphs", "name", _, _])
             | (Method::OPTIONS, ["subgraphs", "network", _, _]) => self.handle_graphql_options(req),
@@ -313,6 +394,14 @@ where
     }
 }
 
+fn subgraph_name_target(subgraph_name: String) -> Result<QueryTarget, GraphQLServerError> {
+    SubgraphName::new(subgraph_name.as_str())
+        .map(QueryTarget::from)
+        .map_err(|()| {
+            GraphQLServerError::ClientError(format!("Invalid subgraph name {:?}", subgraph_name))
+        })
+}
+
 impl<Q> Service<Request<Body>> for GraphQLService<Q>
 where
     Q: GraphQlRunner,
@@ -441,7 +530,7 @@ mod tests {
         let graphql_runner = Arc::new(TestGraphQlRunner);
 
         let node_id = NodeId::new("test").unwrap();
-        let mut service = GraphQLService::new(logger, metrics, graphql_runner, 8001, node_id);
+        let mut service = GraphQLService::new(logger, metrics, graphql_runner, 8001, node_id, None);
 
         let request = Request::builder()
             .method(Method::POST)
@@ -473,7 +562,7 @@ mod tests {
         let graphql_runner = Arc::new(TestGraphQlRunner);
 
         let node_id = NodeId::new("test").unwrap();
-        let mut service = GraphQLService::new(logger, metrics, graphql_runner, 8001, node_id);
+        let mut service = GraphQLService::new(logger, metrics, graphql_runner, 8001, node_id, None);
 
         let request = Request::builder()
             .method(Method::POST)
@@ -0,0 +1,252 @@
+//! Delivery of subscription results to HTTP callbacks.
+//!
+//! Setting `GRAPH_WEBHOOK_SECRET` lets clients register a subscription
+//! query together with a callback URL by POSTing `{ "query": .., "variables":
+//! .., "url": .. }` to `/subgraphs/id/<ID>/webhooks` or
+//! `/subgraphs/name/<NAME>/webhooks` with an `Authorization: Bearer <secret>`
+//! header. The node then runs the query whenever the subgraph changes and
+//! POSTs each result to the URL. Deliveries are retried until the callback
+//! answers with a success status, so that each result arrives at least
+//! once; callbacks can use the `X-Graph-Delivery` sequence number to detect
+//! duplicates, and the `X-Graph-Signature` header, an HMAC over the body
+//! with keccak256 and the secret as the key, to check where the request
+//! came from. Webhooks are removed with a `DELETE /webhooks/<id>` and only
+//! live in memory, i.e., they have to be registered again when the node
+//! restarts
+use std::collections::HashMap;
+use std::env;
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use graph::components::server::query::GraphQLServerError;
+use graph::prelude::*;
+use graph::util::retry_policy::Backoff;
+use graph::{data::query::QueryTarget, prelude::tiny_keccak::Keccak};
+use http::header::{HeaderMap, AUTHORIZATION};
+use hyper::body::Bytes;
+
+use crate::request::GraphQLRequest;
+
+lazy_static! {
+    static ref WEBHOOK_SECRET: Option<String> = env::var("GRAPH_WEBHOOK_SECRET").ok();
+}
+
+/// How long a single delivery attempt may take
+const DELIVERY_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The block size of keccak256 in bytes, needed for the HMAC
+const KECCAK256_BLOCK_SIZE: usize = 136;
+
+/// The webhooks registered with this node
+pub struct Webhooks<Q> {
+    logger: Logger,
+    graphql_runner: Arc<Q>,
+    secret: String,
+    client: reqwest::Client,
+    hooks: Mutex<HashMap<String, CancelGuard>>,
+    next_id: AtomicU64,
+}
+
+impl<Q> fmt::Debug for Webhooks<Q> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Webhooks {{ }}")
+    }
+}
+
+impl<Q> Webhooks<Q>
+where
+    Q: GraphQlRunner,
+{
+    /// Return `None` if webhooks are not turned on
+    pub fn new(logger: &Logger, graphql_runner: Arc<Q>) -> Option<Self> {
+        WEBHOOK_SECRET.as_ref().map(|secret| Webhooks {
+            logger: logger.new(o!("component" => "Webhooks")),
+            graphql_runner,
+            secret: secret.clone(),
+            client: reqwest::Client::new(),
+            hooks: Mutex::new(HashMap::new()),
+            next_id: AtomicU64::new(1),
+        })
+    }
+
+    /// Check that a request to register or remove webhooks carries the
+    /// secret as its bearer token
+    pub fn authorize(&self, headers: &HeaderMap) -> Result<(), GraphQLServerError> {
+        let token = headers
+            .get(AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .map(str::trim);
+        if token == Some(self.secret.as_str()) {
+            Ok(())
+        } else {
+            Err(GraphQLServerError::ClientError(
+                "webhooks require a valid token".to_owned(),
+            ))
+        }
+    }
+
+    /// Start the subscription in `body` against `target` and deliver its
+    /// results to the URL in `body`. Return the id of the new webhook
+    pub async fn register(
+        &self,
+        target: QueryTarget,
+        body: Bytes,
+    ) -> Result<String, GraphQLServerError> {
+        let json: serde_json::Value = serde_json::from_slice(&body)
+            .map_err(|e| GraphQLServerError::ClientError(format!("{}", e)))?;
+        let url = json
+            .get("url")
+            .and_then(|url| url.as_str())
+            .ok_or_else(|| {
+                GraphQLServerError::ClientError(String::from(
+                    "The \"url\" field is missing or not a string",
+                ))
+            })?;
+        let url = reqwest::Url::parse(url)
+            .ok()
+            .filter(|url| url.scheme() == "http" || url.scheme() == "https")
+            .ok_or_else(|| {
+                GraphQLServerError::ClientError(format!("Invalid webhook url `{}`", url))
+            })?;
+        let query = GraphQLRequest::new(body).compat().await?;
+
+        let results = self
+            .graphql_runner
+            .clone()
+            .run_subscription(Subscription { query }, target)
+            .await
+            .map_err(|e| GraphQLServerError::ClientError(e.to_string()))?;
+
+        let id = format!("{:x}", self.next_id.fetch_add(1, Ordering::SeqCst));
+        let logger = self.logger.new(o!("webhook" => id.clone()));
+        info!(logger, "Registered webhook"; "url" => url.as_str());
+
+        let delivery = Delivery {
+            logger: logger.clone(),
+            id: id.clone(),
+            url,
+            secret: self.secret.clone(),
+            client: self.client.clone(),
+        };
+        let guard = CancelGuard::new();
+        let task = Box::pin(delivery.run(results).map(Ok::<(), ()>))
+            .compat()
+            .cancelable(&guard, move || info!(logger, "Removed webhook"));
+        self.hooks.lock().unwrap().insert(id.clone(), guard);
+
+        graph::spawn_allow_panic(task.compat());
+        Ok(id)
+    }
+
+    /// Stop delivering results for the webhook `id`. Return `false` if
+    /// there is no such webhook
+    pub fn remove(&self, id: &str) -> bool {
+        self.hooks.lock().unwrap().remove(id).is_some()
+    }
+}
+
+/// Everything needed to deliver the results of one webhook
+struct Delivery {
+    logger: Logger,
+    id: String,
+    url: reqwest::Url,
+    secret: String,
+    client: reqwest::Client,
+}
+
+impl Delivery {
+    async fn run(self, mut results: SubscriptionResult) {
+        let mut sequence: u64 = 0;
+        while let Some(result) = results.next().await {
+            sequence += 1;
+            let body = serde_json::to_vec(&*result).expect("Failed to serialize query result");
+            let signature = sign(self.secret.as_bytes(), &body);
+
+            let mut delays = Backoff::default().delays();
+            let mut attempt = 1;
+            loop {
+                match self.post(sequence, &signature, body.clone()).await {
+                    Ok(()) => break,
+                    Err(e) => {
+                        warn!(self.logger, "Failed to deliver webhook, retrying";
+                              "sequence" => sequence,
+                              "attempt" => attempt,
+                              "error" => e.to_string());
+                        let delay = delays.next().unwrap_or(Duration::from_secs(30));
+                        tokio::time::delay_for(delay).await;
+                        attempt += 1;
+                    }
+                }
+            }
+        }
+        debug!(self.logger, "Subscription of webhook ended");
+    }
+
+    async fn post(&self, sequence: u64, signature: &str, body: Vec<u8>) -> Result<(), Error> {
+        let response = self
+            .client
+            .post(self.url.clone())
+            .timeout(DELIVERY_TIMEOUT)
+            .header("Content-Type", "application/json")
+            .header("X-Graph-Webhook", self.id.as_str())
+            .header("X-Graph-Delivery", sequence.to_string())
+            .header("X-Graph-Signature", signature)
+            .body(body)
+            .send()
+            .await?;
+        if response.status().is_success() {
+            Ok(())
+        } else {
+            Err(anyhow!(
+                "callback answered with status {}",
+                response.status()
+            ))
+        }
+    }
+}
+
+fn keccak256(parts: &[&[u8]]) -> [u8; 32] {
+    let mut hasher = Keccak::new_keccak256();
+    for part in parts {
+        hasher.update(part);
+    }
+    let mut output = [0u8; 32];
+    hasher.finalize(&mut output);
+    output
+}
+
+/// The hex-encoded HMAC of `body` with keccak256 as the hash function
+fn sign(secret: &[u8], body: &[u8]) -> String {
+    let mut key = [0u8; KECCAK256_BLOCK_SIZE];
+    if secret.len() > KECCAK256_BLOCK_SIZE {
+        key[..32].copy_from_slice(&keccak256(&[secret]));
+    } else {
+        key[..secret.len()].copy_from_slice(secret);
+    }
+
+    let inner_key: Vec<u8> = key.iter().map(|b| b ^ 0x36).collect();
+    let outer_key: Vec<u8> = key.iter().map(|b| b ^ 0x5c).collect();
+    let inner = keccak256(&[&inner_key, body]);
+    hex::encode(keccak256(&[&outer_key, &inner]))
+}
+
+#[test]
+fn signatures() {
+    let body = br#"{"data":{"tokens":[]}}"#;
+    let signature = sign(b"secret", body);
+
+    assert_eq!(64, signature.len());
+    assert_eq!(signature, sign(b"secret", body));
+    assert_ne!(signature, sign(b"secret2", body));
+    assert_ne!(signature, sign(b"secret", br#"{"data":{"tokens":null}}"#));
+
+    // Keys that are longer than a block are hashed first
+    let long_secret = [7u8; 200];
+    assert_eq!(
+        sign(&keccak256(&[&long_secret]), body),
+        sign(&long_secret, body)
+    );
+}
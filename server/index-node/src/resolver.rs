@@ -1,20 +1,43 @@
 use std::collections::HashMap;
 
-use graph::data::subgraph::status;
+use graph::data::subgraph::status::{self, ShadowComparisons};
 use graph::prelude::*;
 use graph::{
-    components::store::StatusStore,
+    components::{store::StatusStore, subgraph::ProofOfIndexingVersion},
     data::graphql::{IntoValue, ObjectOrInterface, ValueMap},
 };
 use graph_graphql::prelude::{ExecutionContext, Resolver};
-use std::convert::TryInto;
+use std::convert::{TryFrom, TryInto};
 use web3::types::{Address, H256};
 
+/// The maximum number of proofs of indexing that can be requested with one
+/// `proofsOfIndexing` query
+const MAX_POI_REQUESTS: u32 = 1000;
+
+/// Read the optional `version` argument of a proof of indexing request
+fn poi_version<V: ValueMap>(
+    values: &V,
+) -> Result<Option<ProofOfIndexingVersion>, QueryExecutionError> {
+    values
+        .get_optional::<u64>("version")
+        .map_err(|e| QueryExecutionError::ValueParseError("version".to_string(), e.to_string()))?
+        .map(|version| {
+            i32::try_from(version)
+                .map_err(anyhow::Error::from)
+                .and_then(ProofOfIndexingVersion::try_from)
+                .map_err(|e| {
+                    QueryExecutionError::ValueParseError("version".to_string(), e.to_string())
+                })
+        })
+        .transpose()
+}
+
 /// Resolver for the index node GraphQL API.
 pub struct IndexNodeResolver<R, S> {
     logger: Logger,
     graphql_runner: Arc<R>,
     store: Arc<S>,
+    shadows: Arc<ShadowComparisons>,
 }
 
 impl<R, S> IndexNodeResolver<R, S>
@@ -22,13 +45,29 @@ where
     R: GraphQlRunner,
     S: StatusStore,
 {
-    pub fn new(logger: &Logger, graphql_runner: Arc<R>, store: Arc<S>) -> Self {
+    pub fn new(
+        logger: &Logger,
+        graphql_runner: Arc<R>,
+        store: Arc<S>,
+        shadows: Arc<ShadowComparisons>,
+    ) -> Self {
         let logger = logger.new(o!("component" => "IndexNodeResolver"));
         Self {
             logger,
             graphql_runner,
             store,
+            shadows,
+        }
+    }
+
+    /// Get the indexing statuses matching `filter`, together with the
+    /// results of comparing shadow deployments
+    fn status(&self, filter: status::Filter) -> Result<Vec<status::Info>, QueryExecutionError> {
+        let mut infos = self.store.status(filter)?;
+        for info in &mut infos {
+            info.shadow = self.shadows.get(&info.subgraph);
         }
+        Ok(infos)
     }
 
     fn resolve_indexing_statuses(
@@ -49,9 +88,7 @@ where
             })
             .unwrap_or_else(|| Vec::new());
 
-        let infos = self
-            .store
-            .status(status::Filter::Deployments(deployments))?;
+        let infos = self.status(status::Filter::Deployments(deployments))?;
         Ok(infos.into_value())
     }
 
@@ -72,9 +109,7 @@ where
             "name" => &subgraph_name
         );
 
-        let infos = self
-            .store
-            .status(status::Filter::SubgraphName(subgraph_name))?;
+        let infos = self.status(status::Filter::SubgraphName(subgraph_name))?;
 
         Ok(infos.into_value())
     }
@@ -105,11 +140,98 @@ where
             .get_optional::<Address>("indexer")
             .expect("Invalid indexer");
 
-        let poi_fut = self
-            .store
-            .clone()
-            .get_proof_of_indexing(&deployment_id, &indexer, block);
-        let poi = match futures::executor::block_on(poi_fut) {
+        let version = poi_version(&argument_values)?;
+
+        let poi = futures::executor::block_on(self.proof_of_indexing(
+            &deployment_id,
+            &indexer,
+            block,
+            version,
+        ));
+
+        Ok(poi)
+    }
+
+    /// Resolve a batch of proof of indexing requests, each for a deployment
+    /// at a block and for an optional indexer. Requests are independent of
+    /// each other, which makes it possible to get the proofs for many
+    /// deployments and past blocks in one go
+    fn resolve_proofs_of_indexing(
+        &self,
+        argument_values: &HashMap<&String, q::Value>,
+    ) -> Result<q::Value, QueryExecutionError> {
+        let requests = argument_values
+            .get_required::<Vec<q::Value>>("requests")
+            .expect("Valid requests required");
+
+        if requests.len() > MAX_POI_REQUESTS as usize {
+            return Err(QueryExecutionError::RangeArgumentsError(
+                "requests",
+                MAX_POI_REQUESTS,
+                requests.len() as i64,
+            ));
+        }
+
+        let versions = requests
+            .iter()
+            .map(poi_version)
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let results = requests
+            .into_iter()
+            .zip(versions)
+            .map(|(request, version)| async move {
+                // The arguments have been validated against the schema, so all
+                // required fields are present and have the right type
+                let deployment_id = request
+                    .get_required::<SubgraphDeploymentId>("subgraph")
+                    .expect("Valid subgraph required");
+                let block_number = request
+                    .get_required::<u64>("blockNumber")
+                    .expect("Valid blockNumber required");
+                let block_hash = request
+                    .get_required::<H256>("blockHash")
+                    .expect("Valid blockHash required");
+                let indexer = request
+                    .get_optional::<Address>("indexer")
+                    .expect("Invalid indexer");
+
+                let block = EthereumBlockPointer::from((block_hash, block_number));
+                let poi = self
+                    .proof_of_indexing(&deployment_id, &indexer, block, version)
+                    .await;
+
+                let field = |name: &str| request.get_required::<q::Value>(name).unwrap();
+                object! {
+                    __typename: "ProofOfIndexingResult",
+                    subgraph: field("subgraph"),
+                    blockNumber: field("blockNumber"),
+                    blockHash: field("blockHash"),
+                    indexer: request.get_optional::<q::Value>("indexer").unwrap(),
+                    version: request.get_optional::<q::Value>("version").unwrap(),
+                    proofOfIndexing: poi,
+                }
+            });
+
+        Ok(q::Value::List(futures::executor::block_on(
+            futures::future::join_all(results),
+        )))
+    }
+
+    /// Get the proof of indexing for `deployment_id` at `block` as a GraphQL
+    /// value; errors are logged and turned into `null`
+    async fn proof_of_indexing(
+        &self,
+        deployment_id: &SubgraphDeploymentId,
+        indexer: &Option<Address>,
+        block: EthereumBlockPointer,
+        version: Option<ProofOfIndexingVersion>,
+    ) -> q::Value {
+        let poi_fut =
+            self.store
+                .clone()
+                .get_proof_of_indexing(deployment_id, indexer, block, version);
+        match poi_fut.await {
             Ok(Some(poi)) => q::Value::String(format!("0x{}", hex::encode(&poi))),
             Ok(None) => q::Value::Null,
             Err(e) => {
@@ -122,9 +244,57 @@ where
                 );
                 q::Value::Null
             }
+        }
+    }
+
+    fn resolve_block_by_number(
+        &self,
+        arguments: &HashMap<&String, q::Value>,
+    ) -> Result<q::Value, QueryExecutionError> {
+        // We can safely unwrap because the arguments are non-nullable and
+        // have been validated.
+        let network = arguments.get_required::<String>("network").unwrap();
+        let number = arguments.get_required::<u64>("blockNumber").unwrap();
+        let number = BlockNumber::try_from(number).map_err(|e| {
+            QueryExecutionError::ValueParseError("blockNumber".to_string(), e.to_string())
+        })?;
+
+        let block = match self.store.block_by_number(&network, number)? {
+            Some(block) => block.block,
+            None => return Ok(q::Value::Null),
         };
+        let hash_hex = |hash: H256| format!("{:#x}", hash);
+
+        Ok(object! {
+            __typename: "BlockData",
+            hash: block.hash.map(hash_hex),
+            number: block.number.map(|number| number.to_string()),
+            parentHash: hash_hex(block.parent_hash),
+            timestamp: block.timestamp.to_string(),
+            transactions: block
+                .transactions
+                .iter()
+                .map(|tx| hash_hex(tx.hash))
+                .collect::<Vec<_>>(),
+        })
+    }
 
-        Ok(poi)
+    fn resolve_call_cache_sizes(&self) -> Result<q::Value, QueryExecutionError> {
+        let sizes = self.store.call_cache_sizes()?;
+        Ok(q::Value::List(
+            sizes
+                .into_iter()
+                .map(|size| {
+                    object! {
+                        __typename: "CallCacheSize",
+                        network: size.network,
+                        shared: size.shared,
+                        entries: size.entries.to_string(),
+                        bytes: size.bytes.to_string(),
+                    }
+                })
+                .collect(),
+        ))
     }
 
     fn resolve_indexing_status_for_version(
@@ -144,7 +314,7 @@ where
             "current_version" => current_version,
         );
 
-        let infos = self.store.status(status::Filter::SubgraphVersion(
+        let infos = self.status(status::Filter::SubgraphVersion(
             subgraph_name,
             current_version,
         ))?;
@@ -167,6 +337,7 @@ where
             logger: self.logger.clone(),
             graphql_runner: self.graphql_runner.clone(),
             store: self.store.clone(),
+            shadows: self.shadows.clone(),
         }
     }
 }
@@ -229,6 +400,14 @@ where
                 self.resolve_indexing_statuses_for_subgraph_name(arguments)
             }
 
+            // The top-level `proofsOfIndexing` field
+            (None, "ProofOfIndexingResult", "proofsOfIndexing") => {
+                self.resolve_proofs_of_indexing(arguments)
+            }
+
+            // The top-level `callCacheSizes` field
+            (None, "CallCacheSize", "callCacheSizes") => self.resolve_call_cache_sizes(),
+
             // Resolve fields of `Object` values (e.g. the `chains` field of `ChainIndexingStatus`)
             (value, _, _) => Ok(value.unwrap_or(q::Value::Null)),
         }
@@ -253,6 +432,9 @@ where
                 self.resolve_indexing_status_for_version(arguments, false)
             }
 
+            // The top-level `blockByNumber` field
+            (None, "blockByNumber") => self.resolve_block_by_number(arguments),
+
             // Resolve fields of `Object` values (e.g. the `latestBlock` field of `EthereumBlock`)
             (value, _) => Ok(value.unwrap_or(q::Value::Null)),
         }
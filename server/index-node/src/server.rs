@@ -5,6 +5,7 @@ use std::net::{Ipv4Addr, SocketAddrV4};
 
 use graph::{
     components::store::StatusStore,
+    data::subgraph::status::ShadowComparisons,
     prelude::{IndexNodeServer as IndexNodeServerTrait, *},
 };
 
@@ -29,11 +30,17 @@ pub struct IndexNodeServer<Q, S> {
     logger: Logger,
     graphql_runner: Arc<Q>,
     store: Arc<S>,
+    shadows: Arc<ShadowComparisons>,
 }
 
 impl<Q, S> IndexNodeServer<Q, S> {
     /// Creates a new GraphQL server.
-    pub fn new(logger_factory: &LoggerFactory, graphql_runner: Arc<Q>, store: Arc<S>) -> Self {
+    pub fn new(
+        logger_factory: &LoggerFactory,
+        graphql_runner: Arc<Q>,
+        store: Arc<S>,
+        shadows: Arc<ShadowComparisons>,
+    ) -> Self {
         let logger = logger_factory.component_logger(
             "IndexNodeServer",
             Some(ComponentLoggerConfig {
@@ -47,6 +54,7 @@ impl<Q, S> IndexNodeServer<Q, S> {
             logger,
             graphql_runner,
             store,
+            shadows,
         }
     }
 }
@@ -80,6 +88,7 @@ where
             logger_for_service.clone(),
             graphql_runner.clone(),
             store.clone(),
+            self.shadows.clone(),
         );
         let new_service =
             make_service_fn(move |_| futures03::future::ok::<_, Error>(service.clone()));
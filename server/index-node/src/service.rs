@@ -4,8 +4,11 @@ use hyper::{Body, Method, Request, Response, StatusCode};
 use std::task::Context;
 use std::task::Poll;
 
+use graph::util::profiler::{Profile, PROFILER_TOKEN};
 use graph::{components::server::query::GraphQLServerError, data::query::QueryResults};
-use graph::{components::store::StatusStore, prelude::*};
+use graph::{
+    components::store::StatusStore, data::subgraph::status::ShadowComparisons, prelude::*,
+};
 use graph_graphql::prelude::{execute_query, Query as PreparedQuery, QueryExecutionOptions};
 
 use crate::explorer::Explorer;
@@ -13,6 +16,14 @@ use crate::request::IndexNodeRequest;
 use crate::resolver::IndexNodeResolver;
 use crate::schema::SCHEMA;
 
+lazy_static! {
+    /// The bearer token that requests for the storage layout of a
+    /// deployment must present. The layout can not be retrieved if this is
+    /// not set
+    static ref STORAGE_LAYOUT_TOKEN: Option<String> =
+        std::env::var("GRAPH_STORAGE_LAYOUT_TOKEN").ok();
+}
+
 /// An asynchronous response to a GraphQL request.
 pub type IndexNodeServiceResponse = DynTryFuture<'static, Response<Body>, GraphQLServerError>;
 
@@ -22,6 +33,7 @@ pub struct IndexNodeService<Q, S> {
     logger: Logger,
     graphql_runner: Arc<Q>,
     store: Arc<S>,
+    shadows: Arc<ShadowComparisons>,
     explorer: Arc<Explorer<S>>,
 }
 
@@ -31,6 +43,7 @@ impl<Q, S> Clone for IndexNodeService<Q, S> {
             logger: self.logger.clone(),
             graphql_runner: self.graphql_runner.clone(),
             store: self.store.clone(),
+            shadows: self.shadows.clone(),
             explorer: self.explorer.clone(),
         }
     }
@@ -44,13 +57,19 @@ where
     S: StatusStore,
 {
     /// Creates a new GraphQL service.
-    pub fn new(logger: Logger, graphql_runner: Arc<Q>, store: Arc<S>) -> Self {
+    pub fn new(
+        logger: Logger,
+        graphql_runner: Arc<Q>,
+        store: Arc<S>,
+        shadows: Arc<ShadowComparisons>,
+    ) -> Self {
         let explorer = Arc::new(Explorer::new(store.clone()));
 
         IndexNodeService {
             logger,
             graphql_runner,
             store,
+            shadows,
             explorer,
         }
     }
@@ -106,7 +125,12 @@ where
         let logger = self.logger.cheap_clone();
         let result = {
             let options = QueryExecutionOptions {
-                resolver: IndexNodeResolver::new(&logger, graphql_runner, store),
+                resolver: IndexNodeResolver::new(
+                    &logger,
+                    graphql_runner,
+                    store,
+                    self.shadows.clone(),
+                ),
                 deadline: None,
                 max_first: std::u32::MAX,
                 max_skip: std::u32::MAX,
@@ -158,6 +182,92 @@ where
             .unwrap()
     }
 
+    /// Check that `req` carries `token` as a bearer token. Returns the
+    /// response to send if it does not, treating an unset token as if the
+    /// endpoint did not exist
+    fn check_token(req: &Request<Body>, token: Option<&String>) -> Option<Response<Body>> {
+        let token = match token {
+            Some(token) => token,
+            None => return Some(Self::handle_not_found()),
+        };
+        let authorized = req
+            .headers()
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .map(|value| value == token)
+            .unwrap_or(false);
+        if authorized {
+            None
+        } else {
+            Some(
+                Response::builder()
+                    .status(StatusCode::UNAUTHORIZED)
+                    .header("Content-Type", "text/plain")
+                    .body(Body::from("Unauthorized\n"))
+                    .unwrap(),
+            )
+        }
+    }
+
+    /// Serves the profile of a deployment in folded format, or resets it
+    /// for `DELETE`. Requests must carry the token from
+    /// `GRAPH_WASM_PROFILER_TOKEN` as a bearer token
+    fn handle_profile(req: &Request<Body>, deployment: &str) -> Response<Body> {
+        if let Some(response) = Self::check_token(req, PROFILER_TOKEN.as_ref()) {
+            return response;
+        }
+
+        let deployment = match SubgraphDeploymentId::new(deployment) {
+            Ok(deployment) => deployment,
+            Err(_) => return Self::handle_not_found(),
+        };
+        if req.method() == Method::DELETE {
+            Profile::remove(&deployment);
+            return Self::index();
+        }
+        match Profile::get(&deployment) {
+            Some(profile) => Response::builder()
+                .status(200)
+                .header("Content-Type", "text/plain")
+                .body(Body::from(profile.folded()))
+                .unwrap(),
+            None => Self::handle_not_found(),
+        }
+    }
+
+    /// Serves a description of the tables, columns and indexes of a
+    /// deployment as JSON. Requests must carry the token from
+    /// `GRAPH_STORAGE_LAYOUT_TOKEN` as a bearer token
+    fn handle_storage_layout(&self, req: &Request<Body>, deployment: &str) -> Response<Body> {
+        if let Some(response) = Self::check_token(req, STORAGE_LAYOUT_TOKEN.as_ref()) {
+            return response;
+        }
+
+        let deployment = match SubgraphDeploymentId::new(deployment) {
+            Ok(deployment) => deployment,
+            Err(_) => return Self::handle_not_found(),
+        };
+        match self.store.storage_layout(&deployment) {
+            Ok(layout) => Response::builder()
+                .status(200)
+                .header("Content-Type", "application/json")
+                .body(Body::from(serde_json::to_string(&layout).unwrap()))
+                .unwrap(),
+            Err(StoreError::DeploymentNotFound(_)) => Self::handle_not_found(),
+            Err(e) => {
+                error!(self.logger, "Failed to get storage layout";
+                       "deployment" => deployment.as_str(),
+                       "error" => e.to_string());
+                Response::builder()
+                    .status(StatusCode::INTERNAL_SERVER_ERROR)
+                    .header("Content-Type", "text/plain")
+                    .body(Body::from("Internal server error\n"))
+                    .unwrap()
+            }
+        }
+    }
+
     async fn handle_call(self, req: Request<Body>) -> Result<Response<Body>, GraphQLServerError> {
         let method = req.method().clone();
 
@@ -191,6 +301,13 @@ where
 
             (Method::GET, ["explorer", rest @ ..]) => self.explorer.handle(&self.logger, rest),
 
+            (Method::GET, ["profile", deployment]) | (Method::DELETE, ["profile", deployment]) => {
+                Ok(Self::handle_profile(&req, deployment))
+            }
+            (Method::GET, ["storage", deployment]) => {
+                Ok(self.handle_storage_layout(&req, deployment))
+            }
+
             _ => Ok(Self::handle_not_found()),
         }
     }
@@ -0,0 +1,129 @@
+//! Authorization of requests to the JSON-RPC admin server.
+//!
+//! By default, anybody who can reach the server can call every method.
+//! Setting `GRAPH_JSON_RPC_TOKENS` to a comma-separated list of
+//! `<token>:<scope>+<scope>..` turns on token authentication: requests must
+//! then carry an `Authorization: Bearer <token>` header for a token whose
+//! scopes include the method they call. A scope is either the name of a
+//! method, like `subgraph_deploy`, or `*` for all methods.
+use lazy_static::lazy_static;
+use std::env;
+use std::str::FromStr;
+use std::sync::Arc;
+use subtle::ConstantTimeEq;
+
+use graph::prelude::{AdminAuthorizer, AdminCredentials};
+
+lazy_static! {
+    static ref TOKENS: Option<TokenAuthorizer> = env::var("GRAPH_JSON_RPC_TOKENS")
+        .ok()
+        .map(|s| s.parse().expect("invalid GRAPH_JSON_RPC_TOKENS"));
+}
+
+/// Return the authorizer configured through the environment
+pub fn authorizer_from_env() -> Arc<dyn AdminAuthorizer> {
+    match TOKENS.as_ref() {
+        Some(tokens) => Arc::new(tokens.clone()),
+        None => Arc::new(AllowAll),
+    }
+}
+
+/// Compare a secret in time that does not depend on where `given` differs
+/// from `known`, so that responses do not reveal how much of a guess was
+/// right
+pub(crate) fn secret_eq(known: &str, given: &str) -> bool {
+    known.as_bytes().ct_eq(given.as_bytes()).into()
+}
+
+/// Allows every request
+pub struct AllowAll;
+
+impl AdminAuthorizer for AllowAll {
+    fn authorize(&self, _: &AdminCredentials, _: &str) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+/// Allows requests with a bearer token whose scopes include the method
+#[derive(Clone, Debug)]
+pub struct TokenAuthorizer {
+    /// Each token with the methods it may call
+    tokens: Vec<(String, Vec<String>)>,
+}
+
+impl FromStr for TokenAuthorizer {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let tokens = s
+            .split(',')
+            .map(str::trim)
+            .filter(|entry| !entry.is_empty())
+            .map(|entry| {
+                let mut parts = entry.splitn(2, ':');
+                let token = parts.next().unwrap_or("");
+                let scopes = parts.next().unwrap_or("");
+                if token.is_empty() || scopes.is_empty() {
+                    return Err("tokens must have the form `<token>:<scope>+<scope>..`".to_owned());
+                }
+                Ok((
+                    token.to_owned(),
+                    scopes.split('+').map(str::to_owned).collect(),
+                ))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(TokenAuthorizer { tokens })
+    }
+}
+
+impl AdminAuthorizer for TokenAuthorizer {
+    fn authorize(&self, credentials: &AdminCredentials, method: &str) -> Result<(), String> {
+        let token = credentials
+            .token
+            .as_deref()
+            .ok_or_else(|| format!("a bearer token is needed to call `{}`", method))?;
+        // Compare with all tokens so that the time does not depend on
+        // which token matched
+        let scopes = self
+            .tokens
+            .iter()
+            .fold(None, |found, (known, scopes)| {
+                if secret_eq(known, token) {
+                    Some(scopes)
+                } else {
+                    found
+                }
+            })
+            .ok_or_else(|| "unknown token".to_owned())?;
+        if scopes.iter().any(|scope| scope == "*" || scope == method) {
+            Ok(())
+        } else {
+            Err(format!("the token does not allow calling `{}`", method))
+        }
+    }
+}
+
+#[test]
+fn token_scopes() {
+    let auth: TokenAuthorizer = "t1:subgraph_deploy+subgraph_remove, t2:*".parse().unwrap();
+    let creds = |token: Option<&str>| AdminCredentials {
+        token: token.map(str::to_owned),
+        ..Default::default()
+    };
+
+    assert!(auth
+        .authorize(&creds(Some("t1")), "subgraph_deploy")
+        .is_ok());
+    assert!(auth
+        .authorize(&creds(Some("t1")), "subgraph_create")
+        .is_err());
+    assert!(auth
+        .authorize(&creds(Some("t2")), "subgraph_create")
+        .is_ok());
+    assert!(auth
+        .authorize(&creds(Some("t3")), "subgraph_deploy")
+        .is_err());
+    assert!(auth.authorize(&creds(None), "subgraph_deploy").is_err());
+
+    assert!("t1".parse::<TokenAuthorizer>().is_err());
+}
@@ -0,0 +1,634 @@
+//! A REST interface for deploying subgraphs, so that CI pipelines can
+//! deploy a subgraph and wait until it is healthy without having to script
+//! JSON-RPC calls. The JSON-RPC admin server serves it under
+//! `/deployments/<subgraph name>`:
+//!
+//! - `PUT` deploys a manifest as a new version of the subgraph, and creates
+//!   the subgraph first if it does not exist yet. The body is either
+//!   `{"ipfs_hash": "Qm.."}` for a manifest that is already on IPFS, or a
+//!   manifest bundle `{"manifest": "..", "files": {"<path>": "<base64>"}}`
+//!   with the text of the manifest and the contents of the files that it
+//!   references as `file: <path>`. The server adds the files and the
+//!   manifest to IPFS, so that CI pipelines do not need access to the IPFS
+//!   node. Both forms take an optional `"node_id"`.
+//! - `GET` returns the status of the current version of the subgraph, or,
+//!   with `?deployment=Qm..`, of that version of it. With `?block=<number>`,
+//!   the request waits until the deployment is healthy and has processed
+//!   that block, for at most `?timeout=<seconds>`, which defaults to 60
+//!   seconds and is capped by `GRAPH_DEPLOYMENT_API_MAX_WAIT`.
+//! - `DELETE` removes the subgraph.
+//!
+//! A `GET` responds with `200` when the deployment is ready, with `500`
+//! when it has failed, and with `503` when the wait timed out, so that CI
+//! tools can gate on the status code. Requests need the same authorization
+//! as the JSON-RPC methods `subgraph_deploy` (and `subgraph_create` if the
+//! subgraph does not exist yet), `subgraph_remove`, and `subgraph_status`
+//! for `GET`, which are checked before the body is read. Bodies can be at
+//! most `GRAPH_DEPLOYMENT_API_MAX_BODY_SIZE` bytes. A key for namespace
+//! permissions is passed in the `X-Graph-Key` header.
+use lazy_static::lazy_static;
+use serde_yaml::{Mapping, Value as YamlValue};
+use std::collections::BTreeMap;
+use std::env;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+use graph::data::subgraph::schema::SubgraphHealth;
+use graph::data::subgraph::status;
+use graph::prelude::futures03::channel::mpsc;
+use graph::prelude::{serde_json, *};
+use jsonrpc_http_server::{
+    hyper::{self, header::CONTENT_TYPE, Body, Method, StatusCode},
+    RequestMiddlewareAction,
+};
+
+use crate::{tokio02_spawn, JsonRpcServer, Key, Operation, RequestMeta, Task};
+
+lazy_static! {
+    /// The longest time, in seconds, that a request may wait for a
+    /// deployment to become ready
+    static ref MAX_WAIT: Duration = Duration::from_secs(
+        env::var("GRAPH_DEPLOYMENT_API_MAX_WAIT")
+            .map(|s| s.parse::<u64>().expect("invalid GRAPH_DEPLOYMENT_API_MAX_WAIT"))
+            .unwrap_or(600)
+    );
+
+    /// The largest body, in bytes, that we accept for a request
+    static ref MAX_BODY_SIZE: usize = env::var("GRAPH_DEPLOYMENT_API_MAX_BODY_SIZE")
+        .map(|s| s.parse::<usize>().expect("invalid GRAPH_DEPLOYMENT_API_MAX_BODY_SIZE"))
+        .unwrap_or(50 * 1024 * 1024);
+}
+
+const PATH_PREFIX: &str = "/deployments/";
+const DEFAULT_WAIT: Duration = Duration::from_secs(60);
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+type ApiResult = Result<(StatusCode, serde_json::Value), (StatusCode, String)>;
+
+/// The query parameters of a `GET` request
+#[derive(Debug, PartialEq)]
+struct StatusParams {
+    deployment: Option<SubgraphDeploymentId>,
+    block: Option<u64>,
+    wait: Duration,
+}
+
+impl FromStr for StatusParams {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut params = StatusParams {
+            deployment: None,
+            block: None,
+            wait: DEFAULT_WAIT,
+        };
+        for param in s.split('&').filter(|param| !param.is_empty()) {
+            let mut parts = param.splitn(2, '=');
+            let name = parts.next().unwrap_or("");
+            let value = parts.next().unwrap_or("");
+            let invalid = || format!("invalid value for `{}`: `{}`", name, value);
+            match name {
+                "deployment" => {
+                    params.deployment =
+                        Some(SubgraphDeploymentId::new(value).map_err(|_| invalid())?)
+                }
+                "block" => params.block = Some(value.parse().map_err(|_| invalid())?),
+                "timeout" => {
+                    params.wait = Duration::from_secs(value.parse().map_err(|_| invalid())?)
+                }
+                _ => return Err(format!("unknown parameter `{}`", name)),
+            }
+        }
+        Ok(params)
+    }
+}
+
+#[derive(Deserialize)]
+struct DeployBody {
+    /// A manifest that is already on IPFS
+    ipfs_hash: Option<SubgraphDeploymentId>,
+    /// The text of a manifest that is not on IPFS yet
+    manifest: Option<String>,
+    /// The base64-encoded contents of the files that `manifest` references,
+    /// by their path in the manifest
+    #[serde(default)]
+    files: BTreeMap<String, String>,
+    node_id: Option<NodeId>,
+}
+
+impl std::fmt::Debug for DeployBody {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // The files are too large to log
+        f.debug_struct("DeployBody")
+            .field("ipfs_hash", &self.ipfs_hash)
+            .field("files", &self.files.keys().collect::<Vec<_>>())
+            .field("node_id", &self.node_id)
+            .finish()
+    }
+}
+
+pub(crate) struct DeploymentApi<R> {
+    server: Arc<JsonRpcServer<R>>,
+    tasks: mpsc::Sender<Task>,
+}
+
+impl<R: SubgraphRegistrar> DeploymentApi<R> {
+    pub fn new(server: Arc<JsonRpcServer<R>>, tasks: mpsc::Sender<Task>) -> Self {
+        DeploymentApi { server, tasks }
+    }
+
+    /// Handle requests for `/deployments/..` and pass all others on to the
+    /// JSON-RPC server
+    pub fn on_request(&self, request: hyper::Request<Body>) -> RequestMiddlewareAction {
+        let name = match request.uri().path().strip_prefix(PATH_PREFIX) {
+            Some(name) => name.trim_end_matches('/').to_owned(),
+            None => {
+                return RequestMiddlewareAction::Proceed {
+                    should_continue_on_invalid_cors: false,
+                    request,
+                }
+            }
+        };
+
+        // Check the credentials before reading the body so that we do not
+        // buffer bodies for requests that are not allowed anyway
+        let meta = RequestMeta::from_request(&request);
+        if let Err(e) = authorize(&self.server, request.method(), &meta) {
+            return RequestMiddlewareAction::Respond {
+                should_validate_hosts: true,
+                response: Box::new(future::ok(to_response(Err(e)))),
+            };
+        }
+
+        let server = self.server.clone();
+        let tasks = self.tasks.clone();
+        let (parts, body) = request.into_parts();
+        let response = Box::pin(tokio02_spawn(
+            tasks,
+            async move {
+                let result = match read_body(body).await {
+                    Ok(body) => {
+                        let request = hyper::Request::from_parts(parts, body);
+                        handle(&server, name, meta, request).await
+                    }
+                    Err(e) => Err(e),
+                };
+                Ok::<_, hyper::Error>(to_response(result))
+            }
+            .boxed(),
+        ))
+        .compat();
+        RequestMiddlewareAction::Respond {
+            should_validate_hosts: true,
+            response: Box::new(response),
+        }
+    }
+}
+
+/// Check that the request is authorized for the JSON-RPC method that
+/// corresponds to its HTTP method
+fn authorize<R: SubgraphRegistrar>(
+    server: &JsonRpcServer<R>,
+    method: &Method,
+    meta: &RequestMeta,
+) -> Result<(), (StatusCode, String)> {
+    let rpc_method = match method {
+        &Method::PUT => "subgraph_deploy",
+        &Method::GET => "subgraph_status",
+        &Method::DELETE => "subgraph_remove",
+        _ => {
+            return Err((
+                StatusCode::METHOD_NOT_ALLOWED,
+                "only PUT, GET and DELETE are supported".to_owned(),
+            ))
+        }
+    };
+    server
+        .authorize(rpc_method, meta)
+        .map_err(|e| (StatusCode::UNAUTHORIZED, e.message))
+}
+
+/// Read the body of a request, but no more than `MAX_BODY_SIZE` bytes
+async fn read_body(body: Body) -> Result<Vec<u8>, (StatusCode, String)> {
+    let mut chunks = body.compat();
+    let mut body = Vec::new();
+    while let Some(chunk) = chunks.next().await {
+        let chunk = chunk.map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()))?;
+        if body.len() + chunk.len() > *MAX_BODY_SIZE {
+            return Err((
+                StatusCode::PAYLOAD_TOO_LARGE,
+                format!("the body is larger than {} bytes", *MAX_BODY_SIZE),
+            ));
+        }
+        body.extend_from_slice(&chunk);
+    }
+    Ok(body)
+}
+
+async fn handle<R: SubgraphRegistrar>(
+    server: &JsonRpcServer<R>,
+    name: String,
+    meta: RequestMeta,
+    request: hyper::Request<Vec<u8>>,
+) -> ApiResult {
+    let name = SubgraphName::new(name.as_str()).map_err(|()| {
+        (
+            StatusCode::BAD_REQUEST,
+            format!("invalid subgraph name `{}`", name),
+        )
+    })?;
+    let key = meta
+        .0
+        .headers
+        .get("x-graph-key")
+        .map(|key| Key::from(key.clone()));
+    match request.method() {
+        &Method::PUT => {
+            let body: DeployBody = serde_json::from_slice(request.body())
+                .map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()))?;
+            deploy(server, name, body, &meta, &key).await
+        }
+        &Method::GET => wait_for_status(server, name, request.uri().query()).await,
+        &Method::DELETE => remove(server, name, &key).await,
+        _ => Err((
+            StatusCode::METHOD_NOT_ALLOWED,
+            "only PUT, GET and DELETE are supported".to_owned(),
+        )),
+    }
+}
+
+async fn deploy<R: SubgraphRegistrar>(
+    server: &JsonRpcServer<R>,
+    name: SubgraphName,
+    body: DeployBody,
+    meta: &RequestMeta,
+    key: &Option<Key>,
+) -> ApiResult {
+    server
+        .check_permission("subgraph_deploy", &name, key, Operation::Deploy)
+        .map_err(|e| (StatusCode::FORBIDDEN, e.message))?;
+
+    info!(server.logger, "Received deployment request";
+          "subgraph" => name.to_string(),
+          "params" => format!("{:?}", body));
+
+    let hash = match (&body.ipfs_hash, &body.manifest) {
+        (Some(hash), None) => hash.clone(),
+        (None, Some(manifest)) => add_bundle(server, manifest, &body.files).await?,
+        _ => {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                "the body must have either an `ipfs_hash` or a `manifest`".to_owned(),
+            ))
+        }
+    };
+
+    let node_id = body.node_id.clone().unwrap_or(server.node_id.clone());
+    let deploy = || {
+        server
+            .registrar
+            .create_subgraph_version(name.clone(), hash.clone(), node_id.clone())
+    };
+    let result = match deploy().await {
+        Err(SubgraphRegistrarError::NameNotFound(_)) => {
+            server
+                .authorize("subgraph_create", meta)
+                .map_err(|e| (StatusCode::UNAUTHORIZED, e.message))?;
+            server
+                .check_permission("subgraph_create", &name, key, Operation::Create)
+                .map_err(|e| (StatusCode::FORBIDDEN, e.message))?;
+            match server.registrar.create_subgraph(name.clone()).await {
+                // Somebody else created it in the meantime
+                Ok(_) | Err(SubgraphRegistrarError::NameExists(_)) => deploy().await,
+                Err(e) => Err(e),
+            }
+        }
+        result => result,
+    };
+    result.map_err(|e| registrar_error(&server.logger, "deploy", e))?;
+
+    Ok((
+        StatusCode::CREATED,
+        serde_json::json!({
+            "deployment": hash.to_string(),
+            "routes": crate::subgraph_routes(&name, server.http_port, server.ws_port),
+        }),
+    ))
+}
+
+/// Add the `files` of a manifest bundle to IPFS, and then `manifest` with
+/// its references to them replaced by links to IPFS. Return the id of the
+/// manifest on IPFS
+async fn add_bundle<R: SubgraphRegistrar>(
+    server: &JsonRpcServer<R>,
+    manifest: &str,
+    files: &BTreeMap<String, String>,
+) -> Result<SubgraphDeploymentId, (StatusCode, String)> {
+    let ipfs_error = |e: Error| {
+        error!(server.logger, "Failed to add manifest bundle to IPFS"; "error" => e.to_string());
+        (
+            StatusCode::BAD_GATEWAY,
+            "failed to add the manifest bundle to IPFS".to_owned(),
+        )
+    };
+
+    let mut manifest: YamlValue = serde_yaml::from_str(manifest)
+        .map_err(|e| (StatusCode::BAD_REQUEST, format!("invalid manifest: {}", e)))?;
+    let mut links = BTreeMap::new();
+    for (path, contents) in files {
+        let contents = base64::decode(contents).map_err(|e| {
+            (
+                StatusCode::BAD_REQUEST,
+                format!("file `{}` is not valid base64: {}", path, e),
+            )
+        })?;
+        let hash = server
+            .link_resolver
+            .add(&server.logger, contents)
+            .await
+            .map_err(ipfs_error)?;
+        links.insert(normalize_path(path), hash);
+    }
+    link_files(&mut manifest, &links).map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+
+    let manifest = serde_yaml::to_vec(&manifest).map_err(|e| ipfs_error(e.into()))?;
+    let hash = server
+        .link_resolver
+        .add(&server.logger, manifest)
+        .await
+        .map_err(ipfs_error)?;
+    SubgraphDeploymentId::new(hash).map_err(|hash| {
+        ipfs_error(anyhow!(
+            "IPFS returned `{}`, which is not a valid deployment id",
+            hash
+        ))
+    })
+}
+
+/// Replace all `file: <path>` entries in `value` with links to the IPFS
+/// hashes in `links`, which maps normalized paths to hashes
+fn link_files(value: &mut YamlValue, links: &BTreeMap<String, String>) -> Result<(), String> {
+    match value {
+        YamlValue::Mapping(map) => {
+            for (key, value) in map.iter_mut() {
+                match (key.as_str(), &*value) {
+                    (Some("file"), YamlValue::String(path)) => {
+                        let hash = links.get(&normalize_path(path)).ok_or_else(|| {
+                            format!(
+                                "the manifest references `{}`, which is not in `files`",
+                                path
+                            )
+                        })?;
+                        let mut link = Mapping::new();
+                        link.insert(
+                            YamlValue::from("/"),
+                            YamlValue::from(format!("/ipfs/{}", hash)),
+                        );
+                        *value = YamlValue::Mapping(link);
+                    }
+                    _ => link_files(value, links)?,
+                }
+            }
+            Ok(())
+        }
+        YamlValue::Sequence(values) => values
+            .iter_mut()
+            .map(|value| link_files(value, links))
+            .collect(),
+        _ => Ok(()),
+    }
+}
+
+/// Make `./schema.graphql` and `schema.graphql` refer to the same file
+fn normalize_path(path: &str) -> String {
+    path.trim_start_matches("./").to_owned()
+}
+
+async fn remove<R: SubgraphRegistrar>(
+    server: &JsonRpcServer<R>,
+    name: SubgraphName,
+    key: &Option<Key>,
+) -> ApiResult {
+    server
+        .check_permission("subgraph_remove", &name, key, Operation::Remove)
+        .map_err(|e| (StatusCode::FORBIDDEN, e.message))?;
+
+    info!(server.logger, "Received removal request"; "subgraph" => name.to_string());
+
+    server
+        .registrar
+        .remove_subgraph(name)
+        .await
+        .map_err(|e| registrar_error(&server.logger, "remove", e))?;
+    Ok((StatusCode::OK, serde_json::Value::Null))
+}
+
+/// Return the status of the deployment that the request asks about. If the
+/// request asks for a block, wait until the deployment is healthy and has
+/// processed that block, it has failed, or the wait timed out
+async fn wait_for_status<R: SubgraphRegistrar>(
+    server: &JsonRpcServer<R>,
+    name: SubgraphName,
+    query: Option<&str>,
+) -> ApiResult {
+    let StatusParams {
+        deployment,
+        block,
+        wait,
+    } = query
+        .unwrap_or("")
+        .parse()
+        .map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+    let deadline = Instant::now() + wait.min(*MAX_WAIT);
+
+    if let Some(id) = &deployment {
+        let is_version = server.is_version_of(&name, id).map_err(|e| {
+            error!(server.logger, "Failed to get subgraph versions"; "error" => e.to_string());
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "internal error".to_owned(),
+            )
+        })?;
+        if !is_version {
+            return Err((
+                StatusCode::NOT_FOUND,
+                format!("subgraph `{}` has no deployment {}", name, id),
+            ));
+        }
+    }
+
+    loop {
+        let filter = match &deployment {
+            Some(id) => status::Filter::Deployments(vec![id.to_string()]),
+            None => status::Filter::SubgraphVersion(name.to_string(), true),
+        };
+        let info = server
+            .statuses
+            .status(filter)
+            .map_err(|e| {
+                error!(server.logger, "Failed to get deployment status"; "error" => e.to_string());
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "internal error".to_owned(),
+                )
+            })?
+            .into_iter()
+            .next()
+            .ok_or_else(|| {
+                (
+                    StatusCode::NOT_FOUND,
+                    format!("subgraph `{}` has no such deployment", name),
+                )
+            })?;
+
+        let latest_block = info
+            .chains
+            .first()
+            .and_then(|chain| chain.latest_block.as_ref())
+            .map(|block| block.number());
+        let ready = match block {
+            None => true,
+            Some(block) => {
+                info.health == SubgraphHealth::Healthy
+                    && latest_block.map_or(false, |latest| latest >= block)
+            }
+        };
+
+        if ready {
+            return Ok((StatusCode::OK, status_to_value(info, latest_block)));
+        }
+        if info.health.is_failed() {
+            return Ok((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                status_to_value(info, latest_block),
+            ));
+        }
+        if Instant::now() >= deadline {
+            return Ok((
+                StatusCode::SERVICE_UNAVAILABLE,
+                status_to_value(info, latest_block),
+            ));
+        }
+        tokio::time::delay_for(POLL_INTERVAL).await;
+    }
+}
+
+fn status_to_value(info: status::Info, latest_block: Option<u64>) -> serde_json::Value {
+    serde_json::json!({
+        "deployment": info.subgraph,
+        "synced": info.synced,
+        "health": info.health.as_str(),
+        "latestBlock": latest_block,
+        "fatalError": info.fatal_error.map(|e| e.message),
+        "node": info.node,
+    })
+}
+
+fn registrar_error(
+    logger: &Logger,
+    operation: &str,
+    e: SubgraphRegistrarError,
+) -> (StatusCode, String) {
+    use SubgraphRegistrarError::*;
+
+    error!(logger, "{} failed", operation; "error" => format!("{:?}", e));
+
+    let status = match &e {
+        NameNotFound(_) | DeploymentNotFound(_) => StatusCode::NOT_FOUND,
+        NameExists(_) => StatusCode::CONFLICT,
+        ResolveError(_)
+        | ManifestValidationError(_)
+        | NetworkNotSupported(_)
+        | SubgraphNetworkRequirementsNotSupported(_, _) => StatusCode::UNPROCESSABLE_ENTITY,
+        _ => StatusCode::INTERNAL_SERVER_ERROR,
+    };
+    let message = match e {
+        Unknown(_) => "internal error".to_owned(),
+        e => e.to_string(),
+    };
+    (status, message)
+}
+
+fn to_response(result: ApiResult) -> hyper::Response<Body> {
+    let (status, body) = match result {
+        Ok((status, value)) => (status, value),
+        Err((status, message)) => (status, serde_json::json!({ "error": message })),
+    };
+    hyper::Response::builder()
+        .status(status)
+        .header(CONTENT_TYPE, "application/json")
+        .body(Body::from(body.to_string()))
+        .unwrap()
+}
+
+#[test]
+fn link_bundle_files() {
+    let mut manifest: YamlValue = serde_yaml::from_str(
+        "
+schema:
+  file: ./schema.graphql
+dataSources:
+  - name: Token
+    mapping:
+      abis:
+        - name: Token
+          file: abis/Token.json
+      file: ./mapping.wasm
+",
+    )
+    .unwrap();
+    let links: BTreeMap<_, _> = vec![
+        ("schema.graphql", "QmSchema"),
+        ("abis/Token.json", "QmAbi"),
+        ("mapping.wasm", "QmMapping"),
+    ]
+    .into_iter()
+    .map(|(path, hash)| (path.to_owned(), hash.to_owned()))
+    .collect();
+    link_files(&mut manifest, &links).unwrap();
+
+    let linked: YamlValue = serde_yaml::from_str(
+        "
+schema:
+  file: { /: /ipfs/QmSchema }
+dataSources:
+  - name: Token
+    mapping:
+      abis:
+        - name: Token
+          file: { /: /ipfs/QmAbi }
+      file: { /: /ipfs/QmMapping }
+",
+    )
+    .unwrap();
+    assert_eq!(linked, manifest);
+
+    let mut manifest: YamlValue = serde_yaml::from_str("schema:\n  file: missing.graphql").unwrap();
+    assert!(link_files(&mut manifest, &links).is_err());
+}
+
+#[test]
+fn status_params() {
+    let params: StatusParams =
+        "deployment=QmXoypizjW3WknFiJnKLwHCnL72vedxjQkDDP1mXWo6uco&block=12&timeout=5"
+            .parse()
+            .unwrap();
+    assert_eq!(
+        StatusParams {
+            deployment: Some(
+                SubgraphDeploymentId::new("QmXoypizjW3WknFiJnKLwHCnL72vedxjQkDDP1mXWo6uco")
+                    .unwrap()
+            ),
+            block: Some(12),
+            wait: Duration::from_secs(5),
+        },
+        params
+    );
+
+    let params: StatusParams = "".parse().unwrap();
+    assert_eq!(None, params.block);
+    assert_eq!(DEFAULT_WAIT, params.wait);
+
+    assert!("block=latest".parse::<StatusParams>().is_err());
+    assert!("blocks=12".parse::<StatusParams>().is_err());
+}
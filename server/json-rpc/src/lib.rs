@@ -3,12 +3,16 @@ extern crate jsonrpc_http_server;
 extern crate lazy_static;
 extern crate serde;
 
+use graph::components::store::StatusStore;
+use graph::data::subgraph::schema::SkippedTrigger;
+use graph::data::subgraph::status;
 use graph::prelude::futures03::channel::{mpsc, oneshot};
 use graph::prelude::futures03::SinkExt;
 use graph::prelude::serde_json;
 use graph::prelude::{JsonRpcServer as JsonRpcServerTrait, *};
 use jsonrpc_http_server::{
-    jsonrpc_core::{self, Compatibility, IoHandler, Params, Value},
+    hyper,
+    jsonrpc_core::{self, Compatibility, MetaIoHandler, Metadata, Params, Value},
     RestApi, Server, ServerBuilder,
 };
 use lazy_static::lazy_static;
@@ -18,6 +22,14 @@ use std::env;
 use std::io;
 use std::net::{Ipv4Addr, SocketAddrV4};
 
+mod auth;
+mod deployments;
+mod namespaces;
+
+pub use auth::{authorizer_from_env, AllowAll, TokenAuthorizer};
+use deployments::DeploymentApi;
+use namespaces::{Key, Operation, NAMESPACE_PERMISSIONS};
+
 lazy_static! {
     static ref EXTERNAL_HTTP_BASE_URL: Option<String> = env::var_os("EXTERNAL_HTTP_BASE_URL")
         .map(|s| s.into_string().expect("invalid external HTTP base URL"));
@@ -29,10 +41,43 @@ const JSON_RPC_DEPLOY_ERROR: i64 = 0;
 const JSON_RPC_REMOVE_ERROR: i64 = 1;
 const JSON_RPC_CREATE_ERROR: i64 = 2;
 const JSON_RPC_REASSIGN_ERROR: i64 = 3;
+const JSON_RPC_SKIPPED_TRIGGERS_ERROR: i64 = 4;
+const JSON_RPC_REPLAY_ERROR: i64 = 5;
+const JSON_RPC_PERMISSION_ERROR: i64 = 6;
+const JSON_RPC_UNAUTHORIZED_ERROR: i64 = 7;
+const JSON_RPC_SUBSCRIPTIONS_ERROR: i64 = 8;
+const JSON_RPC_CONTEXT_ERROR: i64 = 9;
+
+/// The credentials of the sender of a request
+#[derive(Clone, Default)]
+struct RequestMeta(AdminCredentials);
+
+impl Metadata for RequestMeta {}
+
+impl RequestMeta {
+    fn from_request(req: &hyper::Request<hyper::Body>) -> Self {
+        let headers: BTreeMap<String, String> = req
+            .headers()
+            .iter()
+            .filter_map(|(name, value)| {
+                value
+                    .to_str()
+                    .ok()
+                    .map(|value| (name.as_str().to_owned(), value.to_owned()))
+            })
+            .collect();
+        let token = headers
+            .get("authorization")
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .map(|token| token.trim().to_owned());
+        RequestMeta(AdminCredentials { token, headers })
+    }
+}
 
 #[derive(Debug, Deserialize)]
 struct SubgraphCreateParams {
     name: SubgraphName,
+    key: Option<Key>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -40,21 +85,65 @@ struct SubgraphDeployParams {
     name: SubgraphName,
     ipfs_hash: SubgraphDeploymentId,
     node_id: Option<NodeId>,
+    key: Option<Key>,
 }
 
 #[derive(Debug, Deserialize)]
 struct SubgraphRemoveParams {
     name: SubgraphName,
+    key: Option<Key>,
 }
 
 #[derive(Debug, Deserialize)]
 struct SubgraphReassignParams {
     ipfs_hash: SubgraphDeploymentId,
     node_id: NodeId,
+    /// A subgraph that the deployment is a version of; needed when
+    /// per-namespace permissions are turned on
+    name: Option<SubgraphName>,
+    key: Option<Key>,
+}
+
+#[derive(Debug, Deserialize)]
+struct SubgraphSkippedTriggersParams {
+    name: SubgraphName,
+    ipfs_hash: SubgraphDeploymentId,
+    key: Option<Key>,
+}
+
+#[derive(Debug, Deserialize)]
+struct SubgraphDisableSubscriptionsParams {
+    name: SubgraphName,
+    ipfs_hash: SubgraphDeploymentId,
+    disabled: bool,
+    key: Option<Key>,
+}
+
+#[derive(Debug, Deserialize)]
+struct SubgraphSetContextParams {
+    name: SubgraphName,
+    ipfs_hash: SubgraphDeploymentId,
+    /// The key of the context entry, not a key for namespace permissions
+    key: String,
+    /// Removes the entry if it is missing or `null`
+    value: Option<String>,
+    /// The key for namespace permissions
+    permission_key: Option<Key>,
+}
+
+#[derive(Debug, Deserialize)]
+struct SubgraphReplaySkippedParams {
+    name: SubgraphName,
+    ipfs_hash: SubgraphDeploymentId,
+    node_id: Option<NodeId>,
+    key: Option<Key>,
 }
 
 pub struct JsonRpcServer<R> {
     registrar: Arc<R>,
+    statuses: Arc<dyn StatusStore>,
+    link_resolver: Arc<dyn LinkResolver>,
+    authorizer: Arc<dyn AdminAuthorizer>,
     http_port: u16,
     ws_port: u16,
     node_id: NodeId,
@@ -62,12 +151,125 @@ pub struct JsonRpcServer<R> {
 }
 
 impl<R: SubgraphRegistrar> JsonRpcServer<R> {
+    fn authorize(&self, method: &str, meta: &RequestMeta) -> Result<(), jsonrpc_core::Error> {
+        self.authorizer
+            .authorize(&meta.0, method)
+            .map_err(|message| {
+                warn!(self.logger, "Unauthorized call to {}", method; "error" => &message);
+                jsonrpc_core::Error {
+                    code: jsonrpc_core::ErrorCode::ServerError(JSON_RPC_UNAUTHORIZED_ERROR),
+                    message,
+                    data: None,
+                }
+            })
+    }
+
+    /// Check that `key` allows `op` on the subgraph `name` if per-namespace
+    /// permissions are turned on
+    fn check_permission(
+        &self,
+        method: &str,
+        name: &SubgraphName,
+        key: &Option<Key>,
+        op: Operation,
+    ) -> Result<(), jsonrpc_core::Error> {
+        let permissions = match NAMESPACE_PERMISSIONS.as_ref() {
+            Some(permissions) => permissions,
+            None => return Ok(()),
+        };
+        permissions
+            .check(name, key.as_ref().map(Key::as_str), op)
+            .map_err(|message| {
+                warn!(self.logger, "{} denied", method;
+                      "subgraph" => name.to_string(),
+                      "error" => &message);
+                jsonrpc_core::Error {
+                    code: jsonrpc_core::ErrorCode::ServerError(JSON_RPC_PERMISSION_ERROR),
+                    message,
+                    data: None,
+                }
+            })
+    }
+
+    /// Check that `key` allows `op` on the subgraph `name` like
+    /// `check_permission`, and that `hash` is a version of that subgraph so
+    /// that the permission also covers the deployment. Requests that do not
+    /// name a subgraph are denied if per-namespace permissions are on
+    fn check_deployment_permission(
+        &self,
+        method: &str,
+        name: Option<&SubgraphName>,
+        hash: &SubgraphDeploymentId,
+        key: &Option<Key>,
+        op: Operation,
+    ) -> Result<(), jsonrpc_core::Error> {
+        if let Some(permissions) = NAMESPACE_PERMISSIONS.as_ref() {
+            permissions
+                .check_deployment(name, key.as_ref().map(Key::as_str), op)
+                .map_err(|message| {
+                    warn!(self.logger, "{} denied", method;
+                          "deployment" => hash.to_string(),
+                          "error" => &message);
+                    jsonrpc_core::Error {
+                        code: jsonrpc_core::ErrorCode::ServerError(JSON_RPC_PERMISSION_ERROR),
+                        message,
+                        data: None,
+                    }
+                })?;
+        }
+        let name = match name {
+            Some(name) => name,
+            None => return Ok(()),
+        };
+
+        let is_version = self.is_version_of(name, hash).map_err(|e| {
+            error!(self.logger, "{} failed", method;
+                       "subgraph" => name.to_string(),
+                       "error" => e.to_string());
+            jsonrpc_core::Error::internal_error()
+        })?;
+        if is_version {
+            Ok(())
+        } else {
+            warn!(self.logger, "{} denied", method;
+                  "subgraph" => name.to_string(),
+                  "deployment" => hash.to_string());
+            Err(jsonrpc_core::Error {
+                code: jsonrpc_core::ErrorCode::ServerError(JSON_RPC_PERMISSION_ERROR),
+                message: format!(
+                    "deployment {} is not a version of subgraph `{}`",
+                    hash, name
+                ),
+                data: None,
+            })
+        }
+    }
+
+    /// Whether the deployment `hash` is a version of the subgraph `name`
+    fn is_version_of(
+        &self,
+        name: &SubgraphName,
+        hash: &SubgraphDeploymentId,
+    ) -> Result<bool, StoreError> {
+        Ok(self
+            .statuses
+            .status(status::Filter::SubgraphName(name.to_string()))?
+            .iter()
+            .any(|info| info.subgraph == hash.as_str()))
+    }
+
     /// Handler for the `subgraph_create` endpoint.
     async fn create_handler(
         &self,
         params: SubgraphCreateParams,
     ) -> Result<Value, jsonrpc_core::Error> {
         info!(&self.logger, "Received subgraph_create request"; "params" => format!("{:?}", params));
+        self.check_permission(
+            "subgraph_create",
+            &params.name,
+            &params.key,
+            Operation::Create,
+        )?;
 
         match self.registrar.create_subgraph(params.name.clone()).await {
             Ok(result) => {
@@ -89,6 +291,12 @@ impl<R: SubgraphRegistrar> JsonRpcServer<R> {
         params: SubgraphDeployParams,
     ) -> Result<Value, jsonrpc_core::Error> {
         info!(&self.logger, "Received subgraph_deploy request"; "params" => format!("{:?}", params));
+        self.check_permission(
+            "subgraph_deploy",
+            &params.name,
+            &params.key,
+            Operation::Deploy,
+        )?;
 
         let node_id = params.node_id.clone().unwrap_or(self.node_id.clone());
         let routes = subgraph_routes(&params.name, self.http_port, self.ws_port);
@@ -114,6 +322,12 @@ impl<R: SubgraphRegistrar> JsonRpcServer<R> {
         params: SubgraphRemoveParams,
     ) -> Result<Value, jsonrpc_core::Error> {
         info!(&self.logger, "Received subgraph_remove request"; "params" => format!("{:?}", params));
+        self.check_permission(
+            "subgraph_remove",
+            &params.name,
+            &params.key,
+            Operation::Remove,
+        )?;
 
         match self.registrar.remove_subgraph(params.name.clone()).await {
             Ok(_) => Ok(Value::Null),
@@ -135,6 +349,13 @@ impl<R: SubgraphRegistrar> JsonRpcServer<R> {
         let logger = self.logger.clone();
 
         info!(logger, "Received subgraph_reassignment request"; "params" => format!("{:?}", params));
+        self.check_deployment_permission(
+            "subgraph_reassign",
+            params.name.as_ref(),
+            &params.ipfs_hash,
+            &params.key,
+            Operation::Deploy,
+        )?;
 
         match self
             .registrar
@@ -151,6 +372,132 @@ impl<R: SubgraphRegistrar> JsonRpcServer<R> {
             )),
         }
     }
+
+    /// Handler for the `subgraph_skipped_triggers` endpoint.
+    async fn skipped_triggers_handler(
+        &self,
+        params: SubgraphSkippedTriggersParams,
+    ) -> Result<Value, jsonrpc_core::Error> {
+        info!(&self.logger, "Received subgraph_skipped_triggers request"; "params" => format!("{:?}", params));
+        self.check_deployment_permission(
+            "subgraph_skipped_triggers",
+            Some(&params.name),
+            &params.ipfs_hash,
+            &params.key,
+            Operation::Deploy,
+        )?;
+
+        match self
+            .registrar
+            .skipped_triggers(params.ipfs_hash.clone())
+            .await
+        {
+            Ok(triggers) => Ok(Value::Array(
+                triggers.iter().map(skipped_trigger_to_value).collect(),
+            )),
+            Err(e) => Err(json_rpc_error(
+                &self.logger,
+                "subgraph_skipped_triggers",
+                e,
+                JSON_RPC_SKIPPED_TRIGGERS_ERROR,
+                params,
+            )),
+        }
+    }
+
+    /// Handler for the `subgraph_replay_skipped` endpoint.
+    async fn replay_skipped_handler(
+        &self,
+        params: SubgraphReplaySkippedParams,
+    ) -> Result<Value, jsonrpc_core::Error> {
+        info!(&self.logger, "Received subgraph_replay_skipped request"; "params" => format!("{:?}", params));
+        self.check_permission(
+            "subgraph_replay_skipped",
+            &params.name,
+            &params.key,
+            Operation::Deploy,
+        )?;
+
+        let node_id = params.node_id.clone().unwrap_or(self.node_id.clone());
+        match self
+            .registrar
+            .replay_skipped_triggers(params.name.clone(), params.ipfs_hash.clone(), node_id)
+            .await
+        {
+            Ok(count) => Ok(Value::from(count)),
+            Err(e) => Err(json_rpc_error(
+                &self.logger,
+                "subgraph_replay_skipped",
+                e,
+                JSON_RPC_REPLAY_ERROR,
+                params,
+            )),
+        }
+    }
+
+    /// Handler for the `subgraph_disable_subscriptions` endpoint.
+    async fn disable_subscriptions_handler(
+        &self,
+        params: SubgraphDisableSubscriptionsParams,
+    ) -> Result<Value, jsonrpc_core::Error> {
+        info!(&self.logger, "Received subgraph_disable_subscriptions request"; "params" => format!("{:?}", params));
+        self.check_deployment_permission(
+            "subgraph_disable_subscriptions",
+            Some(&params.name),
+            &params.ipfs_hash,
+            &params.key,
+            Operation::Deploy,
+        )?;
+
+        match self
+            .registrar
+            .set_subscriptions_disabled(params.ipfs_hash.clone(), params.disabled)
+            .await
+        {
+            Ok(_) => Ok(Value::Null),
+            Err(e) => Err(json_rpc_error(
+                &self.logger,
+                "subgraph_disable_subscriptions",
+                e,
+                JSON_RPC_SUBSCRIPTIONS_ERROR,
+                params,
+            )),
+        }
+    }
+
+    /// Handler for the `subgraph_set_context` endpoint.
+    async fn set_context_handler(
+        &self,
+        params: SubgraphSetContextParams,
+    ) -> Result<Value, jsonrpc_core::Error> {
+        info!(&self.logger, "Received subgraph_set_context request"; "params" => format!("{:?}", params));
+        self.check_deployment_permission(
+            "subgraph_set_context",
+            Some(&params.name),
+            &params.ipfs_hash,
+            &params.permission_key,
+            Operation::Deploy,
+        )?;
+
+        match self
+            .registrar
+            .set_deployment_context(
+                params.ipfs_hash.clone(),
+                params.key.clone(),
+                params.value.clone(),
+            )
+            .await
+        {
+            Ok(_) => Ok(Value::Null),
+            Err(e) => Err(json_rpc_error(
+                &self.logger,
+                "subgraph_set_context",
+                e,
+                JSON_RPC_CONTEXT_ERROR,
+                params,
+            )),
+        }
+    }
 }
 
 impl<R> JsonRpcServerTrait<R> for JsonRpcServer<R>
@@ -164,6 +511,9 @@ where
         http_port: u16,
         ws_port: u16,
         registrar: Arc<R>,
+        statuses: Arc<dyn StatusStore>,
+        link_resolver: Arc<dyn LinkResolver>,
+        authorizer: Arc<dyn AdminAuthorizer>,
         node_id: NodeId,
         logger: Logger,
     ) -> Result<Self::Server, io::Error> {
@@ -176,18 +526,20 @@ where
 
         let addr = SocketAddrV4::new(Ipv4Addr::new(0, 0, 0, 0), port);
 
-        let mut handler = IoHandler::with_compatibility(Compatibility::Both);
+        let mut handler = MetaIoHandler::with_compatibility(Compatibility::Both);
 
         let arc_self = Arc::new(JsonRpcServer {
             registrar,
+            statuses,
+            link_resolver,
+            authorizer,
             http_port,
             ws_port,
             node_id,
             logger,
         });
 
-        let (task_sender, task_receiver) =
-            mpsc::channel::<Box<dyn std::future::Future<Output = ()> + Send + Unpin>>(100);
+        let (task_sender, task_receiver) = mpsc::channel::<Task>(100);
         graph::spawn(task_receiver.for_each(|f| {
             async {
                 // Blocking due to store interactions. Won't be blocking after #905.
@@ -195,95 +547,193 @@ where
             }
         }));
 
-        // This is a hack required because the json-rpc crate is not updated to tokio 0.2.
-        // We should watch the `jsonrpsee` crate and switch to that once it's ready.
-        async fn tokio02_spawn<I: Send + 'static, ER: Send + 'static>(
-            mut task_sink: mpsc::Sender<Box<dyn std::future::Future<Output = ()> + Send + Unpin>>,
-            future: impl std::future::Future<Output = Result<I, ER>> + Send + Unpin + 'static,
-        ) -> Result<I, ER>
-        where
-            I: Debug,
-            ER: Debug,
-        {
-            let (return_sender, return_receiver) = oneshot::channel();
-            task_sink
-                .send(Box::new(future.map(move |res| {
-                    return_sender.send(res).expect("`return_receiver` dropped");
-                })))
-                .await
-                .expect("task receiver dropped");
-            return_receiver.await.expect("`return_sender` dropped")
-        }
+        let me = arc_self.clone();
+        let sender = task_sender.clone();
+        handler.add_method_with_meta(
+            "subgraph_create",
+            move |params: Params, meta: RequestMeta| {
+                let me = me.clone();
+                Box::pin(tokio02_spawn(
+                    sender.clone(),
+                    async move {
+                        me.authorize("subgraph_create", &meta)?;
+                        let params = params.parse()?;
+                        me.create_handler(params).await
+                    }
+                    .boxed(),
+                ))
+                .compat()
+            },
+        );
 
         let me = arc_self.clone();
         let sender = task_sender.clone();
-        handler.add_method("subgraph_create", move |params: Params| {
-            let me = me.clone();
-            Box::pin(tokio02_spawn(
-                sender.clone(),
-                async move {
-                    let params = params.parse()?;
-                    me.create_handler(params).await
-                }
-                .boxed(),
-            ))
-            .compat()
-        });
+
+        handler.add_method_with_meta(
+            "subgraph_deploy",
+            move |params: Params, meta: RequestMeta| {
+                let me = me.clone();
+                Box::pin(tokio02_spawn(
+                    sender.clone(),
+                    async move {
+                        me.authorize("subgraph_deploy", &meta)?;
+                        let params = params.parse()?;
+                        me.deploy_handler(params).await
+                    }
+                    .boxed(),
+                ))
+                .compat()
+            },
+        );
 
         let me = arc_self.clone();
         let sender = task_sender.clone();
+        handler.add_method_with_meta(
+            "subgraph_remove",
+            move |params: Params, meta: RequestMeta| {
+                let me = me.clone();
+                Box::pin(tokio02_spawn(
+                    sender.clone(),
+                    async move {
+                        me.authorize("subgraph_remove", &meta)?;
+                        let params = params.parse()?;
+                        me.remove_handler(params).await
+                    }
+                    .boxed(),
+                ))
+                .compat()
+            },
+        );
 
-        handler.add_method("subgraph_deploy", move |params: Params| {
-            let me = me.clone();
-            Box::pin(tokio02_spawn(
-                sender.clone(),
-                async move {
-                    let params = params.parse()?;
-                    me.deploy_handler(params).await
-                }
-                .boxed(),
-            ))
-            .compat()
-        });
+        let me = arc_self.clone();
+        let sender = task_sender.clone();
+        handler.add_method_with_meta(
+            "subgraph_reassign",
+            move |params: Params, meta: RequestMeta| {
+                let me = me.clone();
+                Box::pin(tokio02_spawn(
+                    sender.clone(),
+                    async move {
+                        me.authorize("subgraph_reassign", &meta)?;
+                        let params = params.parse()?;
+                        me.reassign_handler(params).await
+                    }
+                    .boxed(),
+                ))
+                .compat()
+            },
+        );
 
         let me = arc_self.clone();
         let sender = task_sender.clone();
-        handler.add_method("subgraph_remove", move |params: Params| {
-            let me = me.clone();
-            Box::pin(tokio02_spawn(
-                sender.clone(),
-                async move {
-                    let params = params.parse()?;
-                    me.remove_handler(params).await
-                }
-                .boxed(),
-            ))
-            .compat()
-        });
+        handler.add_method_with_meta(
+            "subgraph_skipped_triggers",
+            move |params: Params, meta: RequestMeta| {
+                let me = me.clone();
+                Box::pin(tokio02_spawn(
+                    sender.clone(),
+                    async move {
+                        me.authorize("subgraph_skipped_triggers", &meta)?;
+                        let params = params.parse()?;
+                        me.skipped_triggers_handler(params).await
+                    }
+                    .boxed(),
+                ))
+                .compat()
+            },
+        );
 
         let me = arc_self.clone();
         let sender = task_sender.clone();
-        handler.add_method("subgraph_reassign", move |params: Params| {
-            let me = me.clone();
-            Box::pin(tokio02_spawn(
-                sender.clone(),
-                async move {
-                    let params = params.parse()?;
-                    me.reassign_handler(params).await
-                }
-                .boxed(),
-            ))
-            .compat()
-        });
+        handler.add_method_with_meta(
+            "subgraph_replay_skipped",
+            move |params: Params, meta: RequestMeta| {
+                let me = me.clone();
+                Box::pin(tokio02_spawn(
+                    sender.clone(),
+                    async move {
+                        me.authorize("subgraph_replay_skipped", &meta)?;
+                        let params = params.parse()?;
+                        me.replay_skipped_handler(params).await
+                    }
+                    .boxed(),
+                ))
+                .compat()
+            },
+        );
+
+        let me = arc_self.clone();
+        let sender = task_sender.clone();
+        handler.add_method_with_meta(
+            "subgraph_disable_subscriptions",
+            move |params: Params, meta: RequestMeta| {
+                let me = me.clone();
+                Box::pin(tokio02_spawn(
+                    sender.clone(),
+                    async move {
+                        me.authorize("subgraph_disable_subscriptions", &meta)?;
+                        let params = params.parse()?;
+                        me.disable_subscriptions_handler(params).await
+                    }
+                    .boxed(),
+                ))
+                .compat()
+            },
+        );
+
+        let me = arc_self.clone();
+        let sender = task_sender.clone();
+        handler.add_method_with_meta(
+            "subgraph_set_context",
+            move |params: Params, meta: RequestMeta| {
+                let me = me.clone();
+                Box::pin(tokio02_spawn(
+                    sender.clone(),
+                    async move {
+                        me.authorize("subgraph_set_context", &meta)?;
+                        let params = params.parse()?;
+                        me.set_context_handler(params).await
+                    }
+                    .boxed(),
+                ))
+                .compat()
+            },
+        );
+
+        let deployments = DeploymentApi::new(arc_self.clone(), task_sender.clone());
 
-        ServerBuilder::new(handler)
+        ServerBuilder::with_meta_extractor(handler, RequestMeta::from_request)
             // Enable REST API:
             // POST /<method>/<param1>/<param2>
             .rest_api(RestApi::Secure)
+            // PUT, GET and DELETE /deployments/<subgraph name>
+            .request_middleware(move |request| deployments.on_request(request))
             .start_http(&addr.into())
     }
 }
 
+type Task = Box<dyn std::future::Future<Output = ()> + Send + Unpin>;
+
+// This is a hack required because the json-rpc crate is not updated to tokio 0.2.
+// We should watch the `jsonrpsee` crate and switch to that once it's ready.
+async fn tokio02_spawn<I: Send + 'static, ER: Send + 'static>(
+    mut task_sink: mpsc::Sender<Task>,
+    future: impl std::future::Future<Output = Result<I, ER>> + Send + Unpin + 'static,
+) -> Result<I, ER>
+where
+    I: Debug,
+    ER: Debug,
+{
+    let (return_sender, return_receiver) = oneshot::channel();
+    task_sink
+        .send(Box::new(future.map(move |res| {
+            return_sender.send(res).expect("`return_receiver` dropped");
+        })))
+        .await
+        .expect("task receiver dropped");
+    return_receiver.await.expect("`return_sender` dropped")
+}
+
 fn json_rpc_error(
     logger: &Logger,
     operation: &str,
@@ -320,6 +770,47 @@ pub fn parse_response(response: Value) -> Result<(), jsonrpc_core::Error> {
     }
 }
 
+fn skipped_trigger_to_value(trigger: &SkippedTrigger) -> Value {
+    let mut map = serde_json::Map::new();
+    map.insert(
+        "blockHash".to_owned(),
+        Value::from(format!("{:x}", trigger.block_ptr.hash)),
+    );
+    map.insert(
+        "blockNumber".to_owned(),
+        Value::from(trigger.block_ptr.number),
+    );
+    map.insert(
+        "transactionHash".to_owned(),
+        trigger
+            .transaction_hash
+            .map(|hash| Value::from(format!("{:x}", hash)))
+            .unwrap_or(Value::Null),
+    );
+    map.insert(
+        "logIndex".to_owned(),
+        trigger.log_index.map(Value::from).unwrap_or(Value::Null),
+    );
+    map.insert(
+        "handler".to_owned(),
+        trigger
+            .handler
+            .clone()
+            .map(Value::from)
+            .unwrap_or(Value::Null),
+    );
+    map.insert("error".to_owned(), Value::from(trigger.message.clone()));
+    map.insert(
+        "replayedBy".to_owned(),
+        trigger
+            .replayed_by
+            .as_ref()
+            .map(|id| Value::from(id.to_string()))
+            .unwrap_or(Value::Null),
+    );
+    Value::Object(map)
+}
+
 fn subgraph_routes(name: &SubgraphName, http_port: u16, ws_port: u16) -> Value {
     let http_base_url = EXTERNAL_HTTP_BASE_URL
         .clone()
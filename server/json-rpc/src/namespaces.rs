@@ -0,0 +1,212 @@
+//! Per-namespace permissions for modifying subgraphs through the JSON-RPC
+//! server.
+//!
+//! Permissions are turned on by setting `GRAPH_JSON_RPC_NAMESPACE_KEYS` to
+//! a comma-separated list of grants of the form
+//! `<namespace>:<key>[:<operation>+<operation>..]`, for example
+//! `team-a:s3cr3t,team-b:0th3r:deploy`. A request that creates, deploys or
+//! removes the subgraph `team-a/tokens` must then pass `s3cr3t` as its
+//! `key` parameter. Operations are `create`, `deploy` and `remove`; a grant
+//! without operations allows all of them. Requests that work with one
+//! deployment of a subgraph, like setting its context or listing its
+//! skipped triggers, need the `deploy` operation for the subgraph, and the
+//! deployment must be a version of that subgraph; they are denied if they do
+//! not name a subgraph. The namespace `*` grants access to all subgraphs,
+//! including those whose name is not in any namespace.
+//! When the variable is not set, anybody who can reach the JSON-RPC server
+//! can modify any subgraph.
+use lazy_static::lazy_static;
+use std::env;
+use std::str::FromStr;
+
+use graph::prelude::{Deserialize, SubgraphName};
+
+use crate::auth::secret_eq;
+
+lazy_static! {
+    pub(crate) static ref NAMESPACE_PERMISSIONS: Option<NamespacePermissions> =
+        env::var("GRAPH_JSON_RPC_NAMESPACE_KEYS")
+            .ok()
+            .map(|s| s.parse().expect("invalid GRAPH_JSON_RPC_NAMESPACE_KEYS"));
+}
+
+/// The key that a request passes to prove that it may modify a subgraph.
+/// It is not shown in logs
+#[derive(Deserialize)]
+pub(crate) struct Key(String);
+
+impl std::fmt::Debug for Key {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Key(..)")
+    }
+}
+
+impl From<String> for Key {
+    fn from(key: String) -> Self {
+        Key(key)
+    }
+}
+
+impl Key {
+    pub fn as_str(&self) -> &str {
+        self.0.as_str()
+    }
+}
+
+/// The operations that can be restricted per namespace
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Operation {
+    Create,
+    Deploy,
+    Remove,
+}
+
+impl Operation {
+    const ALL: [Operation; 3] = [Operation::Create, Operation::Deploy, Operation::Remove];
+}
+
+impl FromStr for Operation {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "create" => Ok(Operation::Create),
+            "deploy" => Ok(Operation::Deploy),
+            "remove" => Ok(Operation::Remove),
+            _ => Err(format!("unknown operation `{}`", s)),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Grant {
+    /// The namespace, or `*` for all subgraphs
+    namespace: String,
+    key: String,
+    operations: Vec<Operation>,
+}
+
+#[derive(Debug)]
+pub(crate) struct NamespacePermissions {
+    grants: Vec<Grant>,
+}
+
+impl FromStr for NamespacePermissions {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let grants = s
+            .split(',')
+            .map(str::trim)
+            .filter(|grant| !grant.is_empty())
+            .map(|grant| {
+                let mut parts = grant.splitn(3, ':');
+                let namespace = parts.next().unwrap_or("");
+                let key = parts.next().unwrap_or("");
+                if namespace.is_empty() || key.is_empty() {
+                    return Err(format!(
+                        "grant `{}` must have the form `<namespace>:<key>`",
+                        grant
+                    ));
+                }
+                let operations = match parts.next() {
+                    Some(ops) => ops
+                        .split('+')
+                        .map(Operation::from_str)
+                        .collect::<Result<Vec<_>, _>>()?,
+                    None => Operation::ALL.to_vec(),
+                };
+                Ok(Grant {
+                    namespace: namespace.to_owned(),
+                    key: key.to_owned(),
+                    operations,
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(NamespacePermissions { grants })
+    }
+}
+
+impl NamespacePermissions {
+    /// Check that `key` allows performing `op` on the subgraph `name`
+    pub fn check(
+        &self,
+        name: &SubgraphName,
+        key: Option<&str>,
+        op: Operation,
+    ) -> Result<(), String> {
+        let key = key.ok_or_else(|| format!("a key is needed to modify subgraph `{}`", name))?;
+        let allowed = self.grants.iter().any(|grant| {
+            secret_eq(&grant.key, key)
+                && grant.operations.contains(&op)
+                && (grant.namespace == "*" || Some(grant.namespace.as_str()) == name.namespace())
+        });
+        if allowed {
+            Ok(())
+        } else {
+            Err(format!(
+                "the key does not allow the operation `{:?}` on subgraph `{}`",
+                op, name
+            ))
+        }
+    }
+
+    /// Check that `key` allows performing `op` on a deployment of the
+    /// subgraph `name`. Without a name, we can not tell which namespace the
+    /// deployment belongs to, and deny the operation
+    pub fn check_deployment(
+        &self,
+        name: Option<&SubgraphName>,
+        key: Option<&str>,
+        op: Operation,
+    ) -> Result<(), String> {
+        match name {
+            Some(name) => self.check(name, key, op),
+            None => Err(
+                "the name of a subgraph that the deployment is a version of is needed \
+                 to modify it"
+                    .to_owned(),
+            ),
+        }
+    }
+}
+
+#[test]
+fn namespace_permissions() {
+    use Operation::*;
+
+    let perms: NamespacePermissions = "team-a:ka, team-b:kb:deploy, *:admin".parse().unwrap();
+    let name = |s: &str| SubgraphName::new(s).unwrap();
+
+    assert!(perms.check(&name("team-a/x"), Some("ka"), Create).is_ok());
+    assert!(perms.check(&name("team-a/x"), Some("ka"), Remove).is_ok());
+    assert!(perms.check(&name("team-a/x"), Some("kb"), Deploy).is_err());
+    assert!(perms.check(&name("team-a/x"), None, Deploy).is_err());
+    assert!(perms.check(&name("team-b/x"), Some("kb"), Deploy).is_ok());
+    assert!(perms.check(&name("team-b/x"), Some("kb"), Remove).is_err());
+    assert!(perms.check(&name("team-a"), Some("ka"), Create).is_err());
+    assert!(perms.check(&name("team-a"), Some("admin"), Create).is_ok());
+    assert!(perms
+        .check(&name("team-b/x"), Some("admin"), Remove)
+        .is_ok());
+
+    assert!("team-a".parse::<NamespacePermissions>().is_err());
+    assert!("team-a:ka:publish".parse::<NamespacePermissions>().is_err());
+}
+
+#[test]
+fn deployment_permissions() {
+    use Operation::*;
+
+    let perms: NamespacePermissions = "team-a:ka, *:admin".parse().unwrap();
+    let name = SubgraphName::new("team-a/x").unwrap();
+
+    assert!(perms
+        .check_deployment(Some(&name), Some("ka"), Deploy)
+        .is_ok());
+    assert!(perms.check_deployment(Some(&name), None, Deploy).is_err());
+    // Reassigning a deployment without naming its subgraph is never
+    // allowed, not even with a key for all namespaces
+    assert!(perms.check_deployment(None, Some("ka"), Deploy).is_err());
+    assert!(perms.check_deployment(None, Some("admin"), Deploy).is_err());
+}
@@ -247,7 +247,7 @@ fn print_diesel_tables(layout: &Layout) {
                 diesel_type(column)
             );
         }
-        println!("            block_range -> Range<Integer>,");
+        println!("            block_range -> Range<BigInt>,");
         println!("        }}");
         println!("    }}\n")
     }
@@ -264,7 +264,7 @@ fn print_diesel_tables(layout: &Layout) {
                 rust_type(column)
             );
         }
-        println!("        pub block_range: (Bound<i32>, Bound<i32>),");
+        println!("        pub block_range: (Bound<i64>, Bound<i64>),");
         println!("    }}\n")
     }
 }
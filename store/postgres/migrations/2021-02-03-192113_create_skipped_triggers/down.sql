@@ -0,0 +1 @@
+drop table subgraphs.skipped_trigger;
@@ -0,0 +1,14 @@
+create table subgraphs.skipped_trigger (
+    vid              bigserial primary key,
+    subgraph_id      text not null,
+    block_hash       bytea not null,
+    block_number     int4 not null,
+    transaction_hash bytea,
+    log_index        int8,
+    handler          text,
+    message          text not null,
+    replayed_by      text
+);
+
+create index skipped_trigger_subgraph_id_block_number
+    on subgraphs.skipped_trigger(subgraph_id, block_number);
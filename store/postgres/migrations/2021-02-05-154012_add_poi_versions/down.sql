@@ -0,0 +1,3 @@
+alter table subgraphs.subgraph_deployment
+  drop column poi_version,
+  drop column poi_transition_version;
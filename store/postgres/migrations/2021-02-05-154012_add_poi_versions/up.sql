@@ -0,0 +1,3 @@
+alter table subgraphs.subgraph_deployment
+  add column poi_version int4 not null default 1,
+  add column poi_transition_version int4;
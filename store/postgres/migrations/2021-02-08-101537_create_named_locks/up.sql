@@ -0,0 +1,9 @@
+-- Locks that external tools can take through the store to coordinate
+-- with graph-node. A lock that is past its expires_at can be taken over
+-- by anybody
+create table public.named_locks (
+    name        text primary key,
+    holder      text not null,
+    acquired_at timestamptz not null default now(),
+    expires_at  timestamptz not null
+);
@@ -0,0 +1,15 @@
+do $$
+declare
+    nsp text;
+begin
+    for nsp in
+        select namespace from ethereum_networks
+         where namespace != 'public'
+           and to_regnamespace(namespace) is not null
+    loop
+        execute format('drop table if exists %I.fee_history', nsp);
+    end loop;
+end;
+$$;
+
+drop table public.eth_fee_history;
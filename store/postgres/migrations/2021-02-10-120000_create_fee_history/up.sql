@@ -0,0 +1,38 @@
+-- Gas statistics per block for chains whose data lives in shared tables
+create table public.eth_fee_history (
+    network_name      text    not null,
+    number            int8    not null,
+    hash              bytea   not null,
+    gas_used          numeric not null,
+    gas_limit         numeric not null,
+    transaction_count int8    not null,
+    min_gas_price     numeric not null,
+    median_gas_price  numeric not null,
+    max_gas_price     numeric not null,
+    primary key(network_name, number)
+);
+
+-- Chains with their own namespace get their own table
+do $$
+declare
+    nsp text;
+begin
+    for nsp in
+        select namespace from ethereum_networks
+         where namespace != 'public'
+           and to_regnamespace(namespace) is not null
+    loop
+        execute format('
+            create table if not exists %I.fee_history (
+                number            int8    not null primary key,
+                hash              bytea   not null,
+                gas_used          numeric not null,
+                gas_limit         numeric not null,
+                transaction_count int8    not null,
+                min_gas_price     numeric not null,
+                median_gas_price  numeric not null,
+                max_gas_price     numeric not null
+            )', nsp);
+    end loop;
+end;
+$$;
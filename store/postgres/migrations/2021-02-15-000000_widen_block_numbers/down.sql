@@ -0,0 +1,35 @@
+-- This fails if any block number does not fit into an int4
+alter table public.unused_deployments
+    alter column latest_ethereum_block_number type int4;
+alter table subgraphs.skipped_trigger
+    alter column block_number type int4;
+alter table public.eth_call_cache
+    alter column block_number type int4;
+
+do $$
+declare
+    nsp text;
+begin
+    for nsp in
+        select namespace from ethereum_networks
+         where namespace != 'public'
+           and to_regclass(namespace || '.call_cache') is not null
+    loop
+        execute format('alter table %I.call_cache
+                          alter column block_number type int4', nsp);
+    end loop;
+
+    perform change_block_range_type('subgraphs', 'int8range', 'int4range',
+                               '2147483647');
+    for nsp in
+        select s.name
+          from deployment_schemas s, pg_namespace n
+         where s.name = n.nspname
+    loop
+        perform change_block_range_type(nsp, 'int8range', 'int4range',
+                                   '2147483647');
+    end loop;
+end;
+$$;
+
+drop function change_block_range_type(text, text, text, text);
@@ -0,0 +1,84 @@
+-- Block numbers are stored as int8 so that chains whose block numbers do
+-- not fit into an int4 can be indexed
+create or replace function
+    change_block_range_type(nsp text, from_type text, to_type text, block_max text)
+returns void language plpgsql as $$
+declare
+    tbl text;
+    has_brin boolean;
+    has_closed boolean;
+begin
+    for tbl in
+        select c.relname
+          from pg_attribute a, pg_class c, pg_namespace n
+         where a.attrelid = c.oid
+           and c.relnamespace = n.oid
+           and n.nspname = nsp
+           and c.relkind = 'r'
+           and a.attname = 'block_range'
+           and a.atttypid = from_type::regtype
+           and not a.attisdropped
+    loop
+        -- The BRIN index and the index for closed ranges mention the
+        -- largest block number, and have to be recreated with the new one
+        select count(*) > 0 into has_brin
+          from pg_indexes
+         where schemaname = nsp and indexname = 'brin_' || tbl;
+        select count(*) > 0 into has_closed
+          from pg_indexes
+         where schemaname = nsp and indexname = tbl || '_block_range_closed';
+        execute format('drop index if exists %I.%I', nsp, 'brin_' || tbl);
+        execute format('drop index if exists %I.%I', nsp, tbl || '_block_range_closed');
+
+        execute format('alter table %I.%I alter column block_range type %s
+                          using %s(lower(block_range), upper(block_range))',
+                       nsp, tbl, to_type, to_type);
+
+        if has_brin then
+            execute format('create index %I on %I.%I
+                              using brin(lower(block_range),
+                                         coalesce(upper(block_range), %s), vid)',
+                           'brin_' || tbl, nsp, tbl, block_max);
+        end if;
+        if has_closed then
+            execute format('create index %I on %I.%I(coalesce(upper(block_range), %s))
+                             where coalesce(upper(block_range), %s) < %s',
+                           tbl || '_block_range_closed', nsp, tbl,
+                           block_max, block_max, block_max);
+        end if;
+    end loop;
+end;
+$$;
+
+do $$
+declare
+    nsp text;
+begin
+    perform change_block_range_type('subgraphs', 'int4range', 'int8range',
+                               '9223372036854775807');
+    for nsp in
+        select s.name
+          from deployment_schemas s, pg_namespace n
+         where s.name = n.nspname
+    loop
+        perform change_block_range_type(nsp, 'int4range', 'int8range',
+                                   '9223372036854775807');
+    end loop;
+
+    for nsp in
+        select namespace from ethereum_networks
+         where namespace != 'public'
+           and to_regclass(namespace || '.call_cache') is not null
+    loop
+        execute format('alter table %I.call_cache
+                          alter column block_number type int8', nsp);
+    end loop;
+end;
+$$;
+
+alter table public.eth_call_cache
+    alter column block_number type int8;
+alter table subgraphs.skipped_trigger
+    alter column block_number type int8;
+alter table public.unused_deployments
+    alter column latest_ethereum_block_number type int8;
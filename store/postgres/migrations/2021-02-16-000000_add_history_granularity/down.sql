@@ -0,0 +1,3 @@
+alter table subgraphs.subgraph_deployment
+  drop column history_granularity,
+  drop column history_coarse_below;
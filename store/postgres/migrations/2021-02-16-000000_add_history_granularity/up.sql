@@ -0,0 +1,3 @@
+alter table subgraphs.subgraph_deployment
+  add column history_granularity int4,
+  add column history_coarse_below numeric;
@@ -0,0 +1,27 @@
+-- The hash of the block on the main chain for each block number, for
+-- chains whose data lives in shared tables
+create table public.eth_canonical_blocks (
+    network_name text    not null,
+    number       int8    not null,
+    hash         varchar not null,
+    primary key(network_name, number)
+);
+
+-- Chains with their own namespace get their own table
+do $$
+declare
+    nsp text;
+begin
+    for nsp in
+        select namespace from ethereum_networks
+         where namespace != 'public'
+           and to_regnamespace(namespace) is not null
+    loop
+        execute format('
+            create table if not exists %I.canonical_blocks (
+                number int8  not null primary key,
+                hash   bytea not null
+            )', nsp);
+    end loop;
+end;
+$$;
@@ -0,0 +1,36 @@
+-- Transaction receipts for chains whose data lives in shared tables
+create table public.eth_transaction_receipts (
+    network_name      text  not null,
+    block_hash        bytea not null,
+    block_number      int8  not null,
+    transaction_index int8  not null,
+    data              jsonb not null,
+    primary key(block_hash, transaction_index)
+);
+create index eth_transaction_receipts_block_number
+    on public.eth_transaction_receipts(network_name, block_number);
+
+-- Chains with their own namespace get their own table
+do $$
+declare
+    nsp text;
+begin
+    for nsp in
+        select namespace from ethereum_networks
+         where namespace != 'public'
+           and to_regnamespace(namespace) is not null
+    loop
+        execute format('
+            create table if not exists %I.transaction_receipts (
+                block_hash        bytea not null,
+                block_number      int8  not null,
+                transaction_index int8  not null,
+                data              jsonb not null,
+                primary key(block_hash, transaction_index)
+            )', nsp);
+        execute format('
+            create index if not exists transaction_receipts_block_number
+                on %I.transaction_receipts(block_number)', nsp);
+    end loop;
+end;
+$$;
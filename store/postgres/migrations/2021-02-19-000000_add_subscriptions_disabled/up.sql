@@ -0,0 +1,2 @@
+alter table subgraphs.subgraph_deployment
+  add column subscriptions_disabled bool not null default false;
@@ -0,0 +1,33 @@
+-- The traces of blocks for chains whose data lives in shared tables. We
+-- store all traces of a block together
+create table public.eth_block_traces (
+    network_name text  not null,
+    block_hash   bytea not null primary key,
+    block_number int8  not null,
+    data         jsonb not null
+);
+create index eth_block_traces_block_number
+    on public.eth_block_traces(network_name, block_number);
+
+-- Chains with their own namespace get their own table
+do $$
+declare
+    nsp text;
+begin
+    for nsp in
+        select namespace from ethereum_networks
+         where namespace != 'public'
+           and to_regnamespace(namespace) is not null
+    loop
+        execute format('
+            create table if not exists %I.block_traces (
+                block_hash   bytea not null primary key,
+                block_number int8  not null,
+                data         jsonb not null
+            )', nsp);
+        execute format('
+            create index if not exists block_traces_block_number
+                on %I.block_traces(block_number)', nsp);
+    end loop;
+end;
+$$;
@@ -0,0 +1 @@
+alter table ethereum_networks drop column finalized_block_number;
@@ -0,0 +1,3 @@
+-- The finality watermark of each chain; blocks up to it that are not on
+-- the main chain have been removed from the block cache
+alter table ethereum_networks add column finalized_block_number int8;
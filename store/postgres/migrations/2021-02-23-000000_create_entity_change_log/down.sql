@@ -0,0 +1,2 @@
+drop table subgraphs.change_log_consumer;
+drop table subgraphs.entity_change_log;
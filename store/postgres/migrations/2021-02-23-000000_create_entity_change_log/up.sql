@@ -0,0 +1,21 @@
+create table subgraphs.entity_change_log (
+    seq          bigserial primary key,
+    subgraph_id  text not null,
+    block_number int8 not null,
+    entity_type  text not null,
+    entity_id    text not null,
+    operation    text not null
+);
+
+create index entity_change_log_subgraph_id_seq
+    on subgraphs.entity_change_log(subgraph_id, seq);
+
+create table subgraphs.change_log_consumer (
+    subgraph_id  text not null,
+    consumer     text not null,
+    acked_seq    int8 not null,
+    primary key(subgraph_id, consumer)
+);
+
+comment on table subgraphs.entity_change_log is
+  'Changes to the entities of deployments with registered consumers; entries are removed once all consumers have acknowledged them';
@@ -0,0 +1,15 @@
+do $$
+declare
+    nsp text;
+begin
+    for nsp in
+        select namespace from ethereum_networks
+         where namespace != 'public'
+           and to_regnamespace(namespace) is not null
+    loop
+        execute format('alter table %I.call_cache drop column if exists accessed_at', nsp);
+    end loop;
+end;
+$$;
+
+alter table public.eth_call_cache drop column accessed_at;
@@ -0,0 +1,25 @@
+-- Track when each cached call was last used so that the call cache can be
+-- cleaned by evicting the calls that were used the longest time ago
+alter table public.eth_call_cache
+  add column accessed_at date not null default current_date;
+create index eth_call_cache_accessed_at
+    on public.eth_call_cache(accessed_at, block_number);
+
+do $$
+declare
+    nsp text;
+begin
+    for nsp in
+        select namespace from ethereum_networks
+         where namespace != 'public'
+           and to_regnamespace(namespace) is not null
+    loop
+        execute format('
+            alter table %I.call_cache
+              add column if not exists accessed_at date not null default current_date', nsp);
+        execute format('
+            create index if not exists call_cache_accessed_at
+                on %I.call_cache(accessed_at, block_number)', nsp);
+    end loop;
+end;
+$$;
@@ -0,0 +1,19 @@
+-- Calls that revert deterministically are cached with the reason for the
+-- revert, and an empty return value
+alter table public.eth_call_cache add column revert_reason text;
+
+do $$
+declare
+    nsp text;
+begin
+    for nsp in
+        select namespace from ethereum_networks
+         where namespace != 'public'
+           and to_regnamespace(namespace) is not null
+    loop
+        execute format('
+            alter table %I.call_cache
+              add column if not exists revert_reason text', nsp);
+    end loop;
+end;
+$$;
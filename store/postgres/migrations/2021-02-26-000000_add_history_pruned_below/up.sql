@@ -0,0 +1,2 @@
+alter table subgraphs.subgraph_deployment
+  add column history_pruned_below numeric;
@@ -0,0 +1,259 @@
+//! The interface between the store and the physical storage of entities.
+//!
+//! Everything the store does with entities goes through the
+//! `entities::Connection`, which in turn only needs the operations in the
+//! `StorageBackend` trait to read and write entity data. The relational
+//! layout in `relational.rs`, together with the SQL that it generates, is
+//! the Postgres implementation of that trait. Other backends, e.g., a
+//! columnar store for analytics replicas or SQLite for local development,
+//! can be added by implementing `StorageBackend` for their own layout.
+//!
+//! Setting up and tearing down deployments, grafting and maintaining
+//! subgraph metadata like the entity count are still specific to Postgres
+//! and only available for `Layout`
+use diesel::Connection;
+use std::collections::BTreeMap;
+
+use graph::prelude::{
+    BlockNumber, Entity, EntityCollection, EntityFilter, EntityKey, EntityOrder, EntityRange,
+    HistogramVec, Logger, QueryExecutionError, StoreError, StoreEvent, SubgraphDeploymentId,
+};
+
+use crate::relational::{Layout, METADATA_LAYOUT};
+use crate::relational_queries::FromEntityData;
+
+/// The operations a backend must support to store the entities of one
+/// deployment. A backend value describes how the entities of one
+/// deployment are laid out; the data itself is accessed through
+/// connections of type `Conn`
+pub trait StorageBackend: Send + Sync + Sized + 'static {
+    /// The type of database connection the backend uses
+    type Conn: Connection + Send + 'static;
+
+    /// The backend that stores metadata entities, i.e., the entities in
+    /// the subgraph of subgraphs
+    fn metadata() -> &'static Self;
+
+    /// Find the entity of type `entity` with the given `id` as of `block`
+    fn find(
+        &self,
+        conn: &Self::Conn,
+        entity: &str,
+        id: &str,
+        block: BlockNumber,
+    ) -> Result<Option<Entity>, StoreError>;
+
+    /// Find several entities at once; the result maps entity types to the
+    /// entities that were found
+    fn find_many(
+        &self,
+        conn: &Self::Conn,
+        ids_for_type: BTreeMap<&str, &Vec<&str>>,
+        block: BlockNumber,
+    ) -> Result<BTreeMap<String, Vec<Entity>>, StoreError>;
+
+    /// Run a query for entities as of `block`
+    fn query<T: FromEntityData>(
+        &self,
+        logger: &Logger,
+        conn: &Self::Conn,
+        collection: EntityCollection,
+        filter: Option<EntityFilter>,
+        order: EntityOrder,
+        range: EntityRange,
+        distinct: bool,
+        block: BlockNumber,
+        query_id: Option<String>,
+        timing: Option<&HistogramVec>,
+    ) -> Result<Vec<T>, QueryExecutionError>;
+
+    /// Return the name of the first of `entities` that has an entity with
+    /// `entity_id`
+    fn conflicting_entity(
+        &self,
+        conn: &Self::Conn,
+        entity_id: &String,
+        entities: Vec<&String>,
+    ) -> Result<Option<String>, StoreError>;
+
+    fn insert(
+        &self,
+        conn: &Self::Conn,
+        key: &EntityKey,
+        entity: Entity,
+        block: BlockNumber,
+    ) -> Result<(), StoreError>;
+
+    /// Insert an entity that does not have any history. This is only used
+    /// for metadata
+    fn insert_unversioned(
+        &self,
+        conn: &Self::Conn,
+        key: &EntityKey,
+        entity: Entity,
+    ) -> Result<(), StoreError>;
+
+    /// Make `entity` the version of the entity for `key` starting at
+    /// `block`
+    fn update(
+        &self,
+        conn: &Self::Conn,
+        key: &EntityKey,
+        entity: Entity,
+        block: BlockNumber,
+    ) -> Result<(), StoreError>;
+
+    /// Replace an entity that does not have any history. This is only used
+    /// for metadata
+    fn overwrite_unversioned(
+        &self,
+        conn: &Self::Conn,
+        key: &EntityKey,
+        entity: Entity,
+    ) -> Result<usize, StoreError>;
+
+    /// Delete the entity for `key` as of `block`
+    fn delete(
+        &self,
+        conn: &Self::Conn,
+        key: &EntityKey,
+        block: BlockNumber,
+    ) -> Result<usize, StoreError>;
+
+    /// Undo all changes to entities at `block` and later, and return the
+    /// resulting changes and by how much the entity count changed
+    fn revert_block(
+        &self,
+        conn: &Self::Conn,
+        subgraph_id: &SubgraphDeploymentId,
+        block: BlockNumber,
+    ) -> Result<(StoreEvent, i32), StoreError>;
+
+    /// Undo the changes to the metadata of `subgraph` that need to be
+    /// reverted along with its data. Only called on `Self::metadata()`
+    fn revert_metadata(
+        &self,
+        conn: &Self::Conn,
+        subgraph: &SubgraphDeploymentId,
+        block: BlockNumber,
+    ) -> Result<(), StoreError>;
+}
+
+impl StorageBackend for Layout {
+    type Conn = diesel::pg::PgConnection;
+
+    fn metadata() -> &'static Self {
+        METADATA_LAYOUT.as_ref()
+    }
+
+    fn find(
+        &self,
+        conn: &Self::Conn,
+        entity: &str,
+        id: &str,
+        block: BlockNumber,
+    ) -> Result<Option<Entity>, StoreError> {
+        Layout::find(self, conn, entity, id, block)
+    }
+
+    fn find_many(
+        &self,
+        conn: &Self::Conn,
+        ids_for_type: BTreeMap<&str, &Vec<&str>>,
+        block: BlockNumber,
+    ) -> Result<BTreeMap<String, Vec<Entity>>, StoreError> {
+        Layout::find_many(self, conn, ids_for_type, block)
+    }
+
+    fn query<T: FromEntityData>(
+        &self,
+        logger: &Logger,
+        conn: &Self::Conn,
+        collection: EntityCollection,
+        filter: Option<EntityFilter>,
+        order: EntityOrder,
+        range: EntityRange,
+        distinct: bool,
+        block: BlockNumber,
+        query_id: Option<String>,
+        timing: Option<&HistogramVec>,
+    ) -> Result<Vec<T>, QueryExecutionError> {
+        Layout::query(
+            self, logger, conn, collection, filter, order, range, distinct, block, query_id, timing,
+        )
+    }
+
+    fn conflicting_entity(
+        &self,
+        conn: &Self::Conn,
+        entity_id: &String,
+        entities: Vec<&String>,
+    ) -> Result<Option<String>, StoreError> {
+        Layout::conflicting_entity(self, conn, entity_id, entities)
+    }
+
+    fn insert(
+        &self,
+        conn: &Self::Conn,
+        key: &EntityKey,
+        entity: Entity,
+        block: BlockNumber,
+    ) -> Result<(), StoreError> {
+        Layout::insert(self, conn, key, entity, block)
+    }
+
+    fn insert_unversioned(
+        &self,
+        conn: &Self::Conn,
+        key: &EntityKey,
+        entity: Entity,
+    ) -> Result<(), StoreError> {
+        Layout::insert_unversioned(self, conn, key, entity)
+    }
+
+    fn update(
+        &self,
+        conn: &Self::Conn,
+        key: &EntityKey,
+        entity: Entity,
+        block: BlockNumber,
+    ) -> Result<(), StoreError> {
+        Layout::update(self, conn, key, entity, block)
+    }
+
+    fn overwrite_unversioned(
+        &self,
+        conn: &Self::Conn,
+        key: &EntityKey,
+        entity: Entity,
+    ) -> Result<usize, StoreError> {
+        Layout::overwrite_unversioned(self, conn, key, entity)
+    }
+
+    fn delete(
+        &self,
+        conn: &Self::Conn,
+        key: &EntityKey,
+        block: BlockNumber,
+    ) -> Result<usize, StoreError> {
+        Layout::delete(self, conn, key, block)
+    }
+
+    fn revert_block(
+        &self,
+        conn: &Self::Conn,
+        subgraph_id: &SubgraphDeploymentId,
+        block: BlockNumber,
+    ) -> Result<(StoreEvent, i32), StoreError> {
+        Layout::revert_block(self, conn, subgraph_id, block)
+    }
+
+    fn revert_metadata(
+        &self,
+        conn: &Self::Conn,
+        subgraph: &SubgraphDeploymentId,
+        block: BlockNumber,
+    ) -> Result<(), StoreError> {
+        Layout::revert_metadata(self, conn, subgraph, block)
+    }
+}
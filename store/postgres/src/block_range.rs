@@ -3,7 +3,7 @@ use diesel::query_builder::{AstPass, QueryFragment};
 use diesel::result::QueryResult;
 ///! Utilities to deal with block numbers and block ranges
 use diesel::serialize::{Output, ToSql};
-use diesel::sql_types::{Integer, Range};
+use diesel::sql_types::{BigInt, Range};
 use std::io::Write;
 use std::ops::{Bound, RangeBounds, RangeFrom};
 
@@ -17,9 +17,9 @@ pub(crate) const BLOCK_RANGE_COLUMN: &str = "block_range";
 /// The SQL clause we use to check that an entity version is current;
 /// that version has an unbounded block range, but checking for
 /// `upper_inf(block_range)` is slow and can't use the exclusion
-/// index we have on entity tables; we therefore check if i32::MAX is
+/// index we have on entity tables; we therefore check if i64::MAX is
 /// in the range
-pub(crate) const BLOCK_RANGE_CURRENT: &str = "block_range @> 2147483647";
+pub(crate) const BLOCK_RANGE_CURRENT: &str = "block_range @> 9223372036854775807";
 
 /// Most subgraph metadata entities are not versioned. For such entities, we
 /// want two things:
@@ -29,9 +29,9 @@ pub(crate) const BLOCK_RANGE_CURRENT: &str = "block_range @> 2147483647";
 /// We therefore mark such entities with a block range `[-1,\infinity)`; we
 /// use `-1` as the lower bound to make it easier to identify such entities
 /// for troubleshooting/debugging
-pub(crate) const BLOCK_UNVERSIONED: i32 = -1;
+pub(crate) const BLOCK_UNVERSIONED: BlockNumber = -1;
 
-pub(crate) const UNVERSIONED_RANGE: (Bound<i32>, Bound<i32>) =
+pub(crate) const UNVERSIONED_RANGE: (Bound<BlockNumber>, Bound<BlockNumber>) =
     (Bound::Included(BLOCK_UNVERSIONED), Bound::Unbounded);
 
 /// The range of blocks for which an entity is valid. We need this struct
@@ -68,12 +68,12 @@ pub(crate) fn first_block_in_range(
 /// operation that does not record history, which should not happen
 /// with how we currently use relational schemas
 pub(crate) fn block_number(block_ptr: &EthereumBlockPointer) -> BlockNumber {
-    if block_ptr.number < std::i32::MAX as u64 {
-        block_ptr.number as i32
+    if block_ptr.number < std::i64::MAX as u64 {
+        block_ptr.number as BlockNumber
     } else {
         panic!(
             "Block numbers bigger than {} are not supported, but received block number {}",
-            std::i32::MAX,
+            std::i64::MAX,
             block_ptr.number
         )
     }
@@ -88,10 +88,10 @@ impl From<RangeFrom<BlockNumber>> for BlockRange {
     }
 }
 
-impl ToSql<Range<Integer>, Pg> for BlockRange {
+impl ToSql<Range<BigInt>, Pg> for BlockRange {
     fn to_sql<W: Write>(&self, out: &mut Output<W, Pg>) -> diesel::serialize::Result {
         let pair = (self.0, self.1);
-        ToSql::<Range<Integer>, Pg>::to_sql(&pair, out)
+        ToSql::<Range<BigInt>, Pg>::to_sql(&pair, out)
     }
 }
 
@@ -111,7 +111,7 @@ impl<'a> QueryFragment<Pg> for BlockRangeContainsClause<'a> {
         out.push_sql(self.table_prefix);
         out.push_identifier(BLOCK_RANGE_COLUMN)?;
         out.push_sql(" @> ");
-        out.push_bind_param::<Integer, _>(&self.block)?;
+        out.push_bind_param::<BigInt, _>(&self.block)?;
         if self.table.is_account_like && self.block < BLOCK_NUMBER_MAX {
             // When block is BLOCK_NUMBER_MAX, these checks would be wrong; we
             // don't worry about adding the equivalent in that case since
@@ -119,12 +119,12 @@ impl<'a> QueryFragment<Pg> for BlockRangeContainsClause<'a> {
             // queries where block ranges don't matter anyway
             out.push_sql(" and coalesce(upper(");
             out.push_identifier(BLOCK_RANGE_COLUMN)?;
-            out.push_sql("), 2147483647) > ");
-            out.push_bind_param::<Integer, _>(&self.block)?;
+            out.push_sql("), 9223372036854775807) > ");
+            out.push_bind_param::<BigInt, _>(&self.block)?;
             out.push_sql(" and lower(");
             out.push_identifier(BLOCK_RANGE_COLUMN)?;
             out.push_sql(") <= ");
-            out.push_bind_param::<Integer, _>(&self.block)
+            out.push_bind_param::<BigInt, _>(&self.block)
         } else {
             Ok(())
         }
@@ -132,9 +132,9 @@ impl<'a> QueryFragment<Pg> for BlockRangeContainsClause<'a> {
 }
 
 #[test]
-fn block_number_max_is_i32_max() {
-    // The code in this file embeds i32::MAX aka BLOCK_NUMBER_MAX in strings
+fn block_number_max_is_i64_max() {
+    // The code in this file embeds i64::MAX aka BLOCK_NUMBER_MAX in strings
     // for efficiency. This assertion makes sure that BLOCK_NUMBER_MAX still
     // is what we think it is
-    assert_eq!(2147483647, BLOCK_NUMBER_MAX);
+    assert_eq!(9223372036854775807, BLOCK_NUMBER_MAX);
 }
@@ -1,8 +1,9 @@
 use std::{collections::HashMap, sync::Arc};
 
+use graph::data::subgraph::status::CallCacheSize;
 use graph::{
     components::store::BlockStore as BlockStoreTrait,
-    prelude::{EthereumBlockPointer, EthereumNetworkIdentifier},
+    prelude::{EthereumBlockPointer, EthereumNetworkIdentifier, MetricsRegistry},
 };
 use graph::{components::store::CallCache as CallCacheTrait, prelude::StoreError};
 use graph::{
@@ -13,11 +14,11 @@ use graph::{
 use crate::{connection_pool::ConnectionPool, ChainHeadUpdateListener, ChainStore};
 use crate::{subgraph_store::PRIMARY_SHARD, Shard};
 
-#[cfg(debug_assertions)]
+#[cfg(any(debug_assertions, feature = "testing"))]
 pub const FAKE_NETWORK_SHARED: &str = "fake_network_shared";
 
 mod primary {
-    use diesel::{insert_into, ExpressionMethods, RunQueryDsl};
+    use diesel::{insert_into, update, ExpressionMethods, QueryDsl, RunQueryDsl};
     use graph::prelude::{EthereumNetworkIdentifier, StoreError};
 
     use crate::chain_store::Storage;
@@ -52,6 +53,15 @@ mod primary {
         Ok(chains::table.load(&conn)?)
     }
 
+    /// Record that the chain `name` is stored in `shard`
+    pub fn set_shard(pool: &ConnectionPool, name: &str, shard: &Shard) -> Result<(), StoreError> {
+        let conn = pool.get()?;
+        update(chains::table.filter(chains::name.eq(name)))
+            .set(chains::shard.eq(shard.as_str()))
+            .execute(&conn)?;
+        Ok(())
+    }
+
     pub fn add_chain(
         pool: &ConnectionPool,
         name: &str,
@@ -62,7 +72,7 @@ mod primary {
 
         // For tests, we want to have a chain that still uses the
         // shared `ethereum_blocks` table
-        #[cfg(debug_assertions)]
+        #[cfg(any(debug_assertions, feature = "testing"))]
         if name == super::FAKE_NETWORK_SHARED {
             return insert_into(chains::table)
                 .values((
@@ -101,6 +111,7 @@ impl BlockStore {
         // shard -> pool
         pools: &HashMap<Shard, ConnectionPool>,
         chain_head_update_listener: Arc<ChainHeadUpdateListener>,
+        registry: Arc<dyn MetricsRegistry>,
     ) -> Result<Self, StoreError> {
         let primary = pools
             .get(&PRIMARY_SHARD)
@@ -119,7 +130,8 @@ impl BlockStore {
                 Some(chain) => {
                     if chain.shard != shard {
                         return Err(StoreError::Unknown(anyhow!(
-                            "the chain {} is stored in shard {} but is configured for shard {}",
+                            "the chain {} is stored in shard {} but is configured for shard {}; \
+                             use `graphman chain move` to move it",
                             chain.name,
                             chain.shard,
                             shard
@@ -136,12 +148,47 @@ impl BlockStore {
                 ident.clone(),
                 chain_head_update_listener.clone(),
                 pool,
+                registry.cheap_clone(),
             );
             stores.insert(network.clone(), Arc::new(store));
         }
         Ok(Self { stores })
     }
 
+    /// Return the shard that currently stores the chain `network`, or
+    /// `None` if the chain is not known yet
+    pub fn chain_shard(
+        primary: &ConnectionPool,
+        network: &str,
+    ) -> Result<Option<Shard>, StoreError> {
+        Ok(primary::load_chains(primary)?
+            .into_iter()
+            .find(|chain| chain.name == network)
+            .map(|chain| chain.shard))
+    }
+
+    /// Record that the chain `network` is stored in `shard` from now on.
+    /// This does not copy any data; the next `BlockStore` that gets
+    /// created sets up storage for the chain in `shard`, and the caller
+    /// is responsible for copying blocks into it and for removing the
+    /// chain from the shard that stored it so far with
+    /// `ChainStore::drop_chain`
+    pub fn move_chain(
+        primary: &ConnectionPool,
+        network: &str,
+        shard: &Shard,
+    ) -> Result<(), StoreError> {
+        match Self::chain_shard(primary, network)? {
+            None => Err(constraint_violation!("unknown chain {}", network)),
+            Some(current) if &current == shard => Err(StoreError::Unknown(anyhow!(
+                "the chain {} is already stored in shard {}",
+                network,
+                shard
+            ))),
+            Some(_) => primary::set_shard(primary, network, shard),
+        }
+    }
+
     pub fn chain_head_pointers(&self) -> Result<HashMap<String, EthereumBlockPointer>, StoreError> {
         let mut map = HashMap::new();
         for store in self.stores.values() {
@@ -150,6 +197,17 @@ impl BlockStore {
         Ok(map)
     }
 
+    /// The size of the call cache of each chain, ordered by network name
+    pub fn call_cache_sizes(&self) -> Result<Vec<CallCacheSize>, StoreError> {
+        let mut sizes = self
+            .stores
+            .values()
+            .map(|store| store.call_cache_size())
+            .collect::<Result<Vec<_>, _>>()?;
+        sizes.sort_by(|a, b| a.network.cmp(&b.network));
+        Ok(sizes)
+    }
+
     pub fn chain_head_block(&self, network: &str) -> Result<Option<u64>, StoreError> {
         let store = self
             .stores
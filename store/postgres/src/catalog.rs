@@ -1,6 +1,6 @@
 use diesel::pg::PgConnection;
 use diesel::prelude::RunQueryDsl;
-use diesel::sql_types::Text;
+use diesel::sql_types::{BigInt, Bool, Text};
 use std::collections::{HashMap, HashSet};
 
 use graph::prelude::StoreError;
@@ -43,6 +43,22 @@ impl Catalog {
     }
 }
 
+/// Return `true` if the database schema `namespace` exists
+pub fn has_namespace(conn: &PgConnection, namespace: &Namespace) -> Result<bool, StoreError> {
+    #[derive(Debug, QueryableByName)]
+    struct Exists {
+        #[sql_type = "Bool"]
+        pub exists: bool,
+    }
+
+    let result = diesel::sql_query(
+        "select exists (select 1 from pg_namespace where nspname = $1) as exists",
+    )
+    .bind::<Text, _>(namespace.as_str())
+    .get_result::<Exists>(conn)?;
+    Ok(result.exists)
+}
+
 fn get_text_columns(
     conn: &PgConnection,
     namespace: &Namespace,
@@ -72,3 +88,72 @@ fn get_text_columns(
         });
     Ok(map)
 }
+
+/// Return the name and definition of all indexes in `namespace`, grouped
+/// by the name of the table they are on
+pub fn indexes(
+    conn: &PgConnection,
+    namespace: &Namespace,
+) -> Result<HashMap<String, Vec<(String, String)>>, StoreError> {
+    const QUERY: &str = "
+        select tablename as table_name, indexname as index_name, indexdef as definition
+          from pg_indexes
+         where schemaname = $1
+         order by tablename, indexname";
+
+    #[derive(Debug, QueryableByName)]
+    struct Index {
+        #[sql_type = "Text"]
+        pub table_name: String,
+        #[sql_type = "Text"]
+        pub index_name: String,
+        #[sql_type = "Text"]
+        pub definition: String,
+    }
+
+    let map = diesel::sql_query(QUERY)
+        .bind::<Text, _>(namespace.as_str())
+        .load::<Index>(conn)?
+        .into_iter()
+        .fold(HashMap::new(), |mut map: HashMap<_, Vec<_>>, index| {
+            map.entry(index.table_name)
+                .or_default()
+                .push((index.index_name, index.definition));
+            map
+        });
+    Ok(map)
+}
+
+/// Return the number of rows Postgres estimates each table in `namespace`
+/// to have, and the total size of each table in bytes, keyed by the name
+/// of the table
+pub fn table_sizes(
+    conn: &PgConnection,
+    namespace: &Namespace,
+) -> Result<HashMap<String, (i64, i64)>, StoreError> {
+    const QUERY: &str = "
+        select c.relname as table_name,
+               c.reltuples::int8 as row_estimate,
+               pg_total_relation_size(c.oid) as total_bytes
+          from pg_class c, pg_namespace n
+         where c.relnamespace = n.oid
+           and n.nspname = $1
+           and c.relkind = 'r'";
+
+    #[derive(Debug, QueryableByName)]
+    struct Size {
+        #[sql_type = "Text"]
+        pub table_name: String,
+        #[sql_type = "BigInt"]
+        pub row_estimate: i64,
+        #[sql_type = "BigInt"]
+        pub total_bytes: i64,
+    }
+
+    Ok(diesel::sql_query(QUERY)
+        .bind::<Text, _>(namespace.as_str())
+        .load::<Size>(conn)?
+        .into_iter()
+        .map(|size| (size.table_name, (size.row_estimate, size.total_bytes)))
+        .collect())
+}
@@ -1,5 +1,7 @@
 use diesel::{PgConnection, RunQueryDsl};
 use lazy_static::lazy_static;
+use std::collections::HashMap;
+use std::sync::RwLock;
 use tokio::sync::watch;
 use web3::types::H256;
 
@@ -24,6 +26,9 @@ pub struct ChainHeadUpdateListener {
     /// risk for collisions, and in particular sustained collisions is
     /// very low
     update_receiver: watch::Receiver<ChainHeadUpdate>,
+    /// The number of chain head updates received for each network. Unlike
+    /// the `update_receiver`, this never misses an update
+    generations: Arc<RwLock<HashMap<String, u64>>>,
     _listener: NotificationListener,
 }
 
@@ -39,12 +44,20 @@ impl ChainHeadUpdateListener {
             network_name: "none".to_owned(),
             head_block_hash: H256::zero(),
             head_block_number: 0,
+            reorg: None,
         };
         let (update_sender, update_receiver) = watch::channel(none_update);
-        Self::listen(ingestor_metrics, &mut listener, update_sender);
+        let generations = Arc::new(RwLock::new(HashMap::new()));
+        Self::listen(
+            ingestor_metrics,
+            &mut listener,
+            update_sender,
+            generations.clone(),
+        );
 
         ChainHeadUpdateListener {
             update_receiver,
+            generations,
 
             // We keep the listener around to tie its stream's lifetime to
             // that of the chain head update listener and prevent it from
@@ -57,6 +70,7 @@ impl ChainHeadUpdateListener {
         metrics: Arc<BlockIngestorMetrics>,
         listener: &mut NotificationListener,
         update_sender: watch::Sender<ChainHeadUpdate>,
+        generations: Arc<RwLock<HashMap<String, u64>>>,
     ) {
         // Process chain head updates in a dedicated task
         graph::spawn(
@@ -80,6 +94,11 @@ impl ChainHeadUpdateListener {
                         &update.network_name,
                         *&update.head_block_number as i64,
                     );
+                    *generations
+                        .write()
+                        .unwrap()
+                        .entry(update.network_name.clone())
+                        .or_insert(0) += 1;
                     futures03::future::ok(Some(update))
                 })
                 .try_for_each(move |update| {
@@ -91,18 +110,32 @@ impl ChainHeadUpdateListener {
         listener.start();
     }
 
+    /// A number that changes whenever we receive a chain head update for
+    /// `network_name`; anything derived from the chain head before the
+    /// number changed might be outdated
+    pub fn generation(&self, network_name: &str) -> u64 {
+        self.generations
+            .read()
+            .unwrap()
+            .get(network_name)
+            .cloned()
+            .unwrap_or(0)
+    }
+
     pub fn send(
         conn: &PgConnection,
         network_name: &str,
         hash: &str,
         number: i64,
+        reorg: Option<&ChainReorg>,
     ) -> Result<(), StoreError> {
         use crate::functions::pg_notify;
 
         let msg = json! ({
             "network_name": network_name,
             "head_block_hash": hash,
-            "head_block_number": number
+            "head_block_number": number,
+            "reorg": reorg,
         });
 
         diesel::select(pg_notify("chain_head_updates", &msg.to_string()))
@@ -116,7 +149,7 @@ impl ChainHeadUpdateListenerTrait for ChainHeadUpdateListener {
     fn subscribe(&self, network_name: String) -> ChainHeadUpdateStream {
         let f = move |update: ChainHeadUpdate| {
             if update.network_name == network_name {
-                futures03::future::ready(Some(()))
+                futures03::future::ready(Some(update))
             } else {
                 futures03::future::ready(None)
             }
@@ -1,6 +1,8 @@
 use graph::{
     constraint_violation,
-    prelude::{ethabi, ChainStore as ChainStoreTrait, EthereumCallCache, StoreError},
+    prelude::{
+        ethabi, ChainStore as ChainStoreTrait, EthereumCallCache, EthereumCallResult, StoreError,
+    },
 };
 
 use diesel::pg::PgConnection;
@@ -10,18 +12,96 @@ use diesel::sql_types::Text;
 use diesel::{insert_into, update};
 
 use graph::ensure;
-use std::sync::Arc;
-use std::{collections::HashMap, convert::TryFrom};
+use lazy_static::lazy_static;
+use std::io::Write;
+use std::ops::Range;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use std::{
+    collections::{HashMap, HashSet},
+    convert::TryFrom,
+};
 use std::{convert::TryInto, iter::FromIterator};
 
+use graph::components::ethereum::REORG_THRESHOLD;
+use graph::data::subgraph::status::CallCacheSize;
 use graph::prelude::{
-    web3::types::H256, BlockNumber, ChainHeadUpdateListener as _, ChainHeadUpdateStream, Error,
-    EthereumBlock, EthereumBlockPointer, EthereumNetworkIdentifier, Future, LightEthereumBlock,
-    Stream,
+    serde_json,
+    web3::types::{Trace, TransactionReceipt, H256},
+    BlockFeeStats, BlockNumber, BlockRetention, ChainHeadUpdateListener as _,
+    ChainHeadUpdateStream, ChainReorg, Counter, Error, EthereumBlock, EthereumBlockPointer,
+    EthereumNetworkIdentifier, Future, LightEthereumBlock, MetricsRegistry, Stream,
 };
+use graph::prelude::{tiny_keccak, CacheWeight};
+use graph::util::lfu_cache::LfuCache;
 
 use crate::{chain_head_listener::ChainHeadUpdateListener, connection_pool::ConnectionPool};
 
+lazy_static! {
+    /// The number of blocks that `upsert_blocks` writes with one statement
+    static ref UPSERT_BLOCKS_BATCH_SIZE: usize = std::env::var("GRAPH_UPSERT_BLOCKS_BATCH_SIZE")
+        .unwrap_or("100".into())
+        .parse::<usize>()
+        .ok()
+        .filter(|size| *size > 0)
+        .expect("invalid GRAPH_UPSERT_BLOCKS_BATCH_SIZE");
+
+    /// How many bytes of `eth_call` results each network keeps in memory
+    /// in front of the call cache in the database; `0` turns the in-memory
+    /// cache off
+    static ref CALL_CACHE_MEMORY: usize = std::env::var("GRAPH_ETH_CALL_CACHE_MEMORY")
+        .unwrap_or("10000000".into())
+        .parse::<usize>()
+        .expect("invalid GRAPH_ETH_CALL_CACHE_MEMORY");
+
+    /// The selectors of the functions listed in `GRAPH_ETH_CALL_IMMUTABLE`,
+    /// whose results do not change once a contract returns one. They are
+    /// cached once per contract instead of once per block
+    static ref IMMUTABLE_CALLS: HashSet<[u8; 4]> = std::env::var("GRAPH_ETH_CALL_IMMUTABLE")
+        .ok()
+        .map(|s| {
+            s.split(',')
+                .map(str::trim)
+                .filter(|signature| !signature.is_empty())
+                .map(|signature| {
+                    assert!(
+                        signature.contains('(') && signature.ends_with(')'),
+                        "invalid GRAPH_ETH_CALL_IMMUTABLE: `{}` is not a function signature",
+                        signature
+                    );
+                    let mut selector = [0u8; 4];
+                    selector.copy_from_slice(&tiny_keccak::keccak256(signature.as_bytes())[..4]);
+                    selector
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+}
+
+/// A call in the in-memory call cache. The result is valid for all blocks
+/// from `block_number` on; for calls that are not immutable, that is only
+/// the one block whose hash is part of the call id
+#[derive(Clone, Debug)]
+struct CachedCall {
+    block_number: BlockNumber,
+    result: EthereumCallResult,
+}
+
+impl CacheWeight for CachedCall {
+    fn indirect_weight(&self) -> usize {
+        self.result.indirect_weight()
+    }
+}
+
+/// How long we use a cached chain head without hearing about updates. The
+/// cache is invalidated by chain head updates; this only limits how long
+/// it can be outdated if an update gets lost, e.g., because the
+/// connection of the chain head update listener was interrupted
+const CHAIN_HEAD_CACHE_TTL: Duration = Duration::from_secs(10);
+
+/// How many block numbers `ChainStore::export_blocks` reads at once
+const EXPORT_BATCH_SIZE: BlockNumber = 1000;
+
 /// Tables in the 'public' database schema that store chain-specific data
 mod public {
     table! {
@@ -32,6 +112,7 @@ mod public {
             head_block_number -> Nullable<BigInt>,
             net_version -> Varchar,
             genesis_block_hash -> Varchar,
+            finalized_block_number -> Nullable<BigInt>,
         }
     }
 }
@@ -52,18 +133,21 @@ mod data {
     };
     use diesel::{prelude::*, sql_query};
     use diesel::{
-        sql_types::{BigInt, Bytea, Integer, Jsonb},
+        sql_types::{Array, BigInt, Bool, Bytea, Integer, Jsonb, Nullable},
         update,
     };
     use diesel_dynamic_schema as dds;
 
+    use std::collections::{HashMap, HashSet};
     use std::fmt;
     use std::iter::FromIterator;
+    use std::ops::Range;
     use std::{convert::TryFrom, io::Write};
 
     use graph::prelude::{
-        serde_json, web3::types::H256, BlockNumber, Error, EthereumBlock, EthereumBlockPointer,
-        LightEthereumBlock,
+        serde_json,
+        web3::types::{Trace, TransactionReceipt, H256, U256},
+        BlockFeeStats, BlockNumber, Error, EthereumBlock, EthereumBlockPointer, LightEthereumBlock,
     };
 
     mod public {
@@ -87,7 +171,9 @@ mod data {
                 id -> Bytea,
                 return_value -> Bytea,
                 contract_address -> Bytea,
-                block_number -> Integer,
+                block_number -> BigInt,
+                accessed_at -> Date,
+                revert_reason -> Nullable<Text>,
             }
         }
 
@@ -103,6 +189,33 @@ mod data {
         allow_tables_to_appear_in_same_query!(eth_call_cache, eth_call_meta);
     }
 
+    #[derive(QueryableByName)]
+    struct CallCount {
+        #[sql_type = "BigInt"]
+        count: i64,
+    }
+
+    // Helper for looking up several calls at once
+    #[derive(QueryableByName)]
+    struct CallRow {
+        #[sql_type = "Bytea"]
+        id: Vec<u8>,
+        #[sql_type = "Bytea"]
+        return_value: Vec<u8>,
+        #[sql_type = "Nullable<Text>"]
+        revert_reason: Option<String>,
+        #[sql_type = "Bool"]
+        stale: bool,
+    }
+
+    #[derive(QueryableByName)]
+    struct CallCacheSize {
+        #[sql_type = "BigInt"]
+        count: i64,
+        #[sql_type = "BigInt"]
+        bytes: i64,
+    }
+
     // Helper for literal SQL queries that look up a block hash
     #[derive(QueryableByName)]
     struct BlockHashText {
@@ -116,6 +229,114 @@ mod data {
         hash: Vec<u8>,
     }
 
+    // Helpers for walking the chain of ancestors of a block
+    #[derive(QueryableByName)]
+    struct AncestorText {
+        #[sql_type = "Text"]
+        hash: String,
+        #[sql_type = "BigInt"]
+        block_offset: i64,
+    }
+
+    #[derive(QueryableByName)]
+    struct AncestorBytea {
+        #[sql_type = "Bytea"]
+        hash: Vec<u8>,
+        #[sql_type = "BigInt"]
+        block_offset: i64,
+    }
+
+    // Helper for reading transaction receipts
+    #[derive(QueryableByName)]
+    struct ReceiptRow {
+        #[sql_type = "Jsonb"]
+        data: serde_json::Value,
+    }
+
+    // Helper for reading the traces of a block
+    #[derive(QueryableByName)]
+    struct TracesRow {
+        #[sql_type = "Bytea"]
+        block_hash: Vec<u8>,
+        #[sql_type = "Jsonb"]
+        data: serde_json::Value,
+    }
+
+    #[derive(QueryableByName)]
+    struct BlockNumberRow {
+        #[sql_type = "BigInt"]
+        number: i64,
+    }
+
+    #[derive(QueryableByName)]
+    struct BlockDataRow {
+        #[sql_type = "Jsonb"]
+        data: serde_json::Value,
+    }
+
+    #[derive(QueryableByName)]
+    struct BlockNumberBounds {
+        #[sql_type = "diesel::sql_types::Nullable<BigInt>"]
+        min: Option<i64>,
+        #[sql_type = "diesel::sql_types::Nullable<BigInt>"]
+        max: Option<i64>,
+    }
+
+    #[derive(QueryableByName)]
+    struct MaxBlockNumber {
+        #[sql_type = "diesel::sql_types::Nullable<BigInt>"]
+        number: Option<i64>,
+    }
+
+    /// The timestamp of the block in the `data` column as a number; block
+    /// timestamps are stored as hex strings like `0x5f5e1000`
+    const BLOCK_TIMESTAMP: &str = "('x' || lpad(substring(data->'block'->>'timestamp' from 3), \
+                                   16, '0'))::bit(64)::int8";
+
+    // Helper for reading gas statistics; numeric values are read as text
+    // since diesel has no mapping for U256
+    #[derive(QueryableByName)]
+    struct FeeStatsRow {
+        #[sql_type = "BigInt"]
+        number: i64,
+        #[sql_type = "Bytea"]
+        hash: Vec<u8>,
+        #[sql_type = "Text"]
+        gas_used: String,
+        #[sql_type = "Text"]
+        gas_limit: String,
+        #[sql_type = "BigInt"]
+        transaction_count: i64,
+        #[sql_type = "Text"]
+        min_gas_price: String,
+        #[sql_type = "Text"]
+        median_gas_price: String,
+        #[sql_type = "Text"]
+        max_gas_price: String,
+    }
+
+    impl TryFrom<FeeStatsRow> for BlockFeeStats {
+        type Error = StoreError;
+
+        fn try_from(row: FeeStatsRow) -> Result<Self, Self::Error> {
+            fn u256(value: &str) -> Result<U256, StoreError> {
+                U256::from_dec_str(value)
+                    .map_err(|e| constraint_violation!("invalid U256 value `{}`: {:?}", value, e))
+            }
+
+            Ok(BlockFeeStats {
+                number: row.number as u64,
+                hash: h256_from_bytes(&row.hash)?,
+                gas_used: u256(&row.gas_used)?,
+                gas_limit: u256(&row.gas_limit)?,
+                transaction_count: row.transaction_count as u64,
+                min_gas_price: u256(&row.min_gas_price)?,
+                median_gas_price: u256(&row.median_gas_price)?,
+                max_gas_price: u256(&row.max_gas_price)?,
+            })
+        }
+    }
+
     // Like H256::from_slice, but returns an error instead of panicking
     // when `bytes` does not have the right length
     fn h256_from_bytes(bytes: &[u8]) -> Result<H256, StoreError> {
@@ -171,10 +392,11 @@ mod data {
         }
     }
 
+    /// The table that records when calls to a contract were last cached.
+    /// We only ever access it with literal SQL
     #[derive(Clone, Debug)]
     struct CallMetaTable {
         qname: String,
-        table: DynTable,
     }
 
     impl CallMetaTable {
@@ -183,17 +405,8 @@ mod data {
         fn new(namespace: &str) -> Self {
             CallMetaTable {
                 qname: format!("{}.{}", namespace, Self::TABLE_NAME),
-                table: dds::schema(namespace.to_string()).table(Self::TABLE_NAME.to_string()),
             }
         }
-
-        fn table(&self) -> DynTable {
-            self.table.clone()
-        }
-
-        fn contract_address(&self) -> DynColumn<Bytea> {
-            self.table.column::<Bytea, _>("contract_address")
-        }
     }
 
     #[derive(Clone, Debug)]
@@ -224,8 +437,81 @@ mod data {
             self.table.column::<Bytea, _>("return_value")
         }
 
-        fn contract_address(&self) -> DynColumn<Bytea> {
-            self.table.column::<Bytea, _>("contract_address")
+        fn revert_reason(&self) -> DynColumn<Nullable<Text>> {
+            self.table.column::<Nullable<Text>, _>("revert_reason")
+        }
+
+        fn block_number(&self) -> DynColumn<BigInt> {
+            self.table.column::<BigInt, _>("block_number")
+        }
+    }
+
+    /// The table that holds gas statistics for blocks. We only ever access
+    /// it with literal SQL
+    #[derive(Clone, Debug)]
+    struct FeeHistoryTable {
+        qname: String,
+    }
+
+    impl FeeHistoryTable {
+        const TABLE_NAME: &'static str = "fee_history";
+
+        fn new(namespace: &str) -> Self {
+            FeeHistoryTable {
+                qname: format!("{}.{}", namespace, Self::TABLE_NAME),
+            }
+        }
+    }
+
+    /// The table that maps block numbers to the hashes of the blocks on
+    /// the chain that ends in the current chain head. We only ever access
+    /// it with literal SQL
+    #[derive(Clone, Debug)]
+    struct CanonicalBlocksTable {
+        qname: String,
+    }
+
+    impl CanonicalBlocksTable {
+        const TABLE_NAME: &'static str = "canonical_blocks";
+
+        fn new(namespace: &str) -> Self {
+            CanonicalBlocksTable {
+                qname: format!("{}.{}", namespace, Self::TABLE_NAME),
+            }
+        }
+    }
+
+    /// The table that holds the transaction receipts of blocks. We only
+    /// ever access it with literal SQL
+    #[derive(Clone, Debug)]
+    struct ReceiptsTable {
+        qname: String,
+    }
+
+    impl ReceiptsTable {
+        const TABLE_NAME: &'static str = "transaction_receipts";
+
+        fn new(namespace: &str) -> Self {
+            ReceiptsTable {
+                qname: format!("{}.{}", namespace, Self::TABLE_NAME),
+            }
+        }
+    }
+
+    /// The table that holds the traces of blocks, with one row for all
+    /// the traces of a block. We only ever access it with literal SQL
+    #[derive(Clone, Debug)]
+    struct TracesTable {
+        qname: String,
+    }
+
+    impl TracesTable {
+        const TABLE_NAME: &'static str = "block_traces";
+
+        fn new(namespace: &str) -> Self {
+            TracesTable {
+                qname: format!("{}.{}", namespace, Self::TABLE_NAME),
+            }
         }
     }
 
@@ -235,6 +521,10 @@ mod data {
         blocks: BlocksTable,
         call_meta: CallMetaTable,
         call_cache: CallCacheTable,
+        fee_history: FeeHistoryTable,
+        canonical_blocks: CanonicalBlocksTable,
+        receipts: ReceiptsTable,
+        traces: TracesTable,
     }
 
     impl Schema {
@@ -242,11 +532,19 @@ mod data {
             let blocks = BlocksTable::new(&name);
             let call_meta = CallMetaTable::new(&name);
             let call_cache = CallCacheTable::new(&name);
+            let fee_history = FeeHistoryTable::new(&name);
+            let canonical_blocks = CanonicalBlocksTable::new(&name);
+            let receipts = ReceiptsTable::new(&name);
+            let traces = TracesTable::new(&name);
             Self {
                 name,
                 blocks,
                 call_meta,
                 call_cache,
+                fee_history,
+                canonical_blocks,
+                receipts,
+                traces,
             }
         }
     }
@@ -285,6 +583,12 @@ mod data {
         }
     }
 
+    /// A SQL condition on the `data` of a row in a blocks table that is
+    /// true if the block lacks the receipts for some of its transactions,
+    /// i.e., if it was stored as a light block
+    const LIGHT_BLOCK: &str = "coalesce(jsonb_array_length(data -> 'transaction_receipts'), 0) \
+                               < jsonb_array_length(data -> 'block' -> 'transactions')";
+
     impl Storage {
         const PREFIX: &'static str = "chain";
         const PUBLIC: &'static str = "public";
@@ -329,13 +633,51 @@ mod data {
 	              id               bytea not null primary key,
 	              return_value     bytea not null,
 	              contract_address bytea not null,
-	              block_number     int4 not null
+	              block_number     int8 not null,
+	              accessed_at      date not null default current_date,
+	              revert_reason    text
                 );
+                create index call_cache_accessed_at
+                    on {nsp}.call_cache(accessed_at, block_number);
 
                 create table {nsp}.call_meta (
                     contract_address bytea not null primary key,
                     accessed_at      date  not null
                 );
+
+                create table {nsp}.fee_history (
+                    number            int8    not null primary key,
+                    hash              bytea   not null,
+                    gas_used          numeric not null,
+                    gas_limit         numeric not null,
+                    transaction_count int8    not null,
+                    min_gas_price     numeric not null,
+                    median_gas_price  numeric not null,
+                    max_gas_price     numeric not null
+                );
+
+                create table {nsp}.canonical_blocks (
+                    number int8  not null primary key,
+                    hash   bytea not null
+                );
+
+                create table {nsp}.transaction_receipts (
+                    block_hash        bytea not null,
+                    block_number      int8  not null,
+                    transaction_index int8  not null,
+                    data              jsonb not null,
+                    primary key(block_hash, transaction_index)
+                );
+                create index transaction_receipts_block_number
+                    on {nsp}.transaction_receipts(block_number);
+
+                create table {nsp}.block_traces (
+                    block_hash   bytea not null primary key,
+                    block_number int8  not null,
+                    data         jsonb not null
+                );
+                create index block_traces_block_number
+                    on {nsp}.block_traces(block_number);
             ",
                     nsp = nsp
                 )
@@ -350,6 +692,36 @@ mod data {
             }
         }
 
+        /// Remove all data for `network` from this storage. For
+        /// `Storage::Private`, that drops the namespace of the chain. The
+        /// shared call cache is not specific to a network and is left alone
+        pub(super) fn drop_storage(&self, conn: &PgConnection, network: &str) -> Result<(), Error> {
+            match self {
+                Storage::Shared => {
+                    use public::ethereum_blocks as b;
+
+                    for table in &[
+                        "eth_fee_history",
+                        "eth_canonical_blocks",
+                        "eth_transaction_receipts",
+                        "eth_block_traces",
+                    ] {
+                        sql_query(format!(
+                            "delete from public.{} where network_name = $1",
+                            table
+                        ))
+                        .bind::<Text, _>(network)
+                        .execute(conn)?;
+                    }
+                    diesel::delete(b::table.filter(b::network_name.eq(network))).execute(conn)?;
+                }
+                Storage::Private(Schema { name, .. }) => {
+                    conn.batch_execute(&format!("drop schema {} cascade", name))?;
+                }
+            }
+            Ok(())
+        }
+
         /// Insert a block. If the table already contains a block with the
         /// same hash, then overwrite that block since it may be adding
         /// transaction receipts.
@@ -404,6 +776,91 @@ mod data {
             result.map(|_| ()).map_err(Error::from)
         }
 
+        /// Insert `blocks` with one statement. Blocks that are already in
+        /// the table are overwritten in the same way as in `upsert_block`.
+        /// If `blocks` contains several blocks with the same hash, the last
+        /// one wins
+        pub(super) fn upsert_blocks(
+            &self,
+            conn: &PgConnection,
+            network: &str,
+            blocks: Vec<EthereumBlock>,
+        ) -> Result<(), Error> {
+            // Postgres refuses to update the same row twice in one
+            // statement
+            let mut seen = HashSet::new();
+            let blocks: Vec<_> = blocks
+                .into_iter()
+                .rev()
+                .filter(|block| seen.insert(block.block.hash.unwrap()))
+                .collect();
+
+            let numbers: Vec<i64> = blocks
+                .iter()
+                .map(|block| block.block.number.unwrap().as_u64() as i64)
+                .collect();
+            let data: Vec<serde_json::Value> = blocks
+                .iter()
+                .map(|block| serde_json::to_value(block).expect("Failed to serialize block"))
+                .collect();
+
+            let result = match self {
+                Storage::Shared => {
+                    let hashes: Vec<String> = blocks
+                        .iter()
+                        .map(|block| format!("{:x}", block.block.hash.unwrap()))
+                        .collect();
+                    let parent_hashes: Vec<String> = blocks
+                        .iter()
+                        .map(|block| format!("{:x}", block.block.parent_hash))
+                        .collect();
+                    let query = "\
+                        insert into ethereum_blocks(hash, number, parent_hash, network_name, data)
+                        select b.hash, b.number, b.parent_hash, $4, b.data
+                          from unnest($1::text[], $2::int8[], $3::text[], $5::jsonb[])
+                               as b(hash, number, parent_hash, data)
+                            on conflict(hash)
+                            do update set number = excluded.number,
+                                          parent_hash = excluded.parent_hash,
+                                          network_name = excluded.network_name,
+                                          data = excluded.data";
+                    sql_query(query)
+                        .bind::<Array<Text>, _>(hashes)
+                        .bind::<Array<BigInt>, _>(numbers)
+                        .bind::<Array<Text>, _>(parent_hashes)
+                        .bind::<Text, _>(network)
+                        .bind::<Array<Jsonb>, _>(data)
+                        .execute(conn)
+                }
+                Storage::Private(Schema { blocks: table, .. }) => {
+                    let hashes: Vec<&[u8]> = blocks
+                        .iter()
+                        .map(|block| block.block.hash.as_ref().unwrap().as_bytes())
+                        .collect();
+                    let parent_hashes: Vec<&[u8]> = blocks
+                        .iter()
+                        .map(|block| block.block.parent_hash.as_bytes())
+                        .collect();
+                    let query = format!(
+                        "insert into {}(hash, number, parent_hash, data) \
+                         select * from unnest($1::bytea[], $2::int8[], $3::bytea[], $4::jsonb[]) \
+                             on conflict(hash) \
+                             do update set number = excluded.number, \
+                                           parent_hash = excluded.parent_hash, \
+                                           data = excluded.data",
+                        table.qname,
+                    );
+                    sql_query(query)
+                        .bind::<Array<Bytea>, _>(hashes)
+                        .bind::<Array<BigInt>, _>(numbers)
+                        .bind::<Array<Bytea>, _>(parent_hashes)
+                        .bind::<Array<Jsonb>, _>(data)
+                        .execute(conn)
+                }
+            };
+            result.map(|_| ()).map_err(Error::from)
+        }
+
         /// Insert a light block. On conflict do nothing, since we
         /// do not want to erase transaction receipts that might already
         /// be there
@@ -496,6 +953,78 @@ mod data {
                 .collect()
         }
 
+        /// Return the blocks among `hashes` that are stored without the
+        /// receipts for all of their transactions
+        pub(super) fn light_blocks(
+            &self,
+            conn: &PgConnection,
+            network: &str,
+            hashes: &[H256],
+        ) -> Result<Vec<LightEthereumBlock>, Error> {
+            use diesel::dsl::any;
+
+            let blocks = match self {
+                Storage::Shared => {
+                    use public::ethereum_blocks as b;
+
+                    b::table
+                        .select(sql::<Jsonb>("data -> 'block'"))
+                        .filter(b::network_name.eq(network))
+                        .filter(b::hash.eq(any(Vec::from_iter(
+                            hashes.iter().map(|h| format!("{:x}", h)),
+                        ))))
+                        .filter(sql::<Bool>(LIGHT_BLOCK))
+                        .load::<serde_json::Value>(conn)?
+                }
+                Storage::Private(Schema { blocks, .. }) => blocks
+                    .table()
+                    .select(sql::<Jsonb>("data -> 'block'"))
+                    .filter(
+                        blocks
+                            .hash()
+                            .eq(any(Vec::from_iter(hashes.iter().map(|h| h.as_bytes())))),
+                    )
+                    .filter(sql::<Bool>(LIGHT_BLOCK))
+                    .load::<serde_json::Value>(conn)?,
+            };
+            blocks
+                .into_iter()
+                .map(|block| serde_json::from_value(block).map_err(Into::into))
+                .collect()
+        }
+
+        /// Replace the stored data for `block` with `block` if the stored
+        /// block lacks transaction receipts. Returns `true` if the stored
+        /// block was replaced
+        pub(super) fn hydrate_block(
+            &self,
+            conn: &PgConnection,
+            network: &str,
+            block: &EthereumBlock,
+        ) -> Result<bool, Error> {
+            let hash = block.block.hash.expect("block is missing block hash");
+            let data = serde_json::to_value(block)?;
+            let count = match self {
+                Storage::Shared => sql_query(format!(
+                    "update public.ethereum_blocks set data = $1 \
+                      where network_name = $2 and hash = $3 and {}",
+                    LIGHT_BLOCK
+                ))
+                .bind::<Jsonb, _>(data)
+                .bind::<Text, _>(network)
+                .bind::<Text, _>(format!("{:x}", hash))
+                .execute(conn)?,
+                Storage::Private(Schema { blocks, .. }) => sql_query(format!(
+                    "update {} set data = $1 where hash = $2 and {}",
+                    blocks.qname, LIGHT_BLOCK
+                ))
+                .bind::<Jsonb, _>(data)
+                .bind::<Bytea, _>(hash.as_bytes())
+                .execute(conn)?,
+            };
+            Ok(count > 0)
+        }
+
         pub(super) fn block_hashes_by_block_number(
             &self,
             conn: &PgConnection,
@@ -563,6 +1092,58 @@ mod data {
             }
         }
 
+        /// Remove the blocks with numbers in `(from, to]` that are not the
+        /// block that `canonical_blocks` records for their number. This has
+        /// the same effect as calling `confirm_block_hash` with the
+        /// canonical hash of each of these numbers; numbers that are not in
+        /// `canonical_blocks` are left alone
+        pub(super) fn confirm_canonical_blocks(
+            &self,
+            conn: &PgConnection,
+            network: &str,
+            from: BlockNumber,
+            to: BlockNumber,
+        ) -> Result<usize, Error> {
+            match self {
+                Storage::Shared => sql_query(
+                    "delete from ethereum_blocks b
+                      using eth_canonical_blocks c
+                      where b.network_name = $1
+                        and c.network_name = $1
+                        and c.number = b.number
+                        and c.hash != b.hash
+                        and b.number > $2
+                        and b.number <= $3",
+                )
+                .bind::<Text, _>(network)
+                .bind::<BigInt, _>(from)
+                .bind::<BigInt, _>(to)
+                .execute(conn)
+                .map_err(Error::from),
+                Storage::Private(Schema {
+                    blocks,
+                    canonical_blocks,
+                    ..
+                }) => {
+                    let query = format!(
+                        "delete from {blocks} b
+                          using {canonical} c
+                          where c.number = b.number
+                            and c.hash != b.hash
+                            and b.number > $1
+                            and b.number <= $2",
+                        blocks = blocks.qname,
+                        canonical = canonical_blocks.qname
+                    );
+                    sql_query(query)
+                        .bind::<BigInt, _>(from)
+                        .bind::<BigInt, _>(to)
+                        .execute(conn)
+                        .map_err(Error::from)
+                }
+            }
+        }
+
         pub(super) fn block_number(
             &self,
             conn: &PgConnection,
@@ -743,87 +1324,513 @@ mod data {
             }
         }
 
+        /// Find the ancestor of `block_ptr` that is `offset` blocks before
+        /// it. Following parent hashes gets slow for large offsets; since
+        /// the `canonical_blocks` record which block is on the main chain
+        /// for each number, we only follow parent hashes until we reach a
+        /// block on the main chain, and look the ancestor up by its number
+        /// from there. We only fall back to following parent hashes all the
+        /// way when the ancestor is older than what `canonical_blocks`
+        /// records
         pub(super) fn ancestor_block(
             &self,
             conn: &PgConnection,
+            network: &str,
             block_ptr: EthereumBlockPointer,
             offset: u64,
         ) -> Result<Option<EthereumBlock>, Error> {
-            let data = match self {
-                Storage::Shared => {
-                    const ANCESTOR_SQL: &str = "
-        with recursive ancestors(block_hash, block_offset) as (
-            values ($1, 0)
-            union all
-            select b.parent_hash, a.block_offset+1
-              from ancestors a, ethereum_blocks b
-             where a.block_hash = b.hash
-               and a.block_offset < $2
-        )
-        select a.block_hash as hash
-          from ancestors a
-         where a.block_offset = $2;";
+            let offset = offset as i64;
+
+            let hash = match self.walk_ancestors(conn, network, block_ptr.hash, offset, true)? {
+                Some((hash, walked)) if walked == offset => Some(hash),
+                Some((hash, walked)) => {
+                    match self.canonical_ancestor(conn, network, hash, offset - walked)? {
+                        Some(ancestor) => Some(ancestor),
+                        None => self
+                            .walk_ancestors(conn, network, hash, offset - walked, false)?
+                            .filter(|(_, rest)| *rest == offset - walked)
+                            .map(|(ancestor, _)| ancestor),
+                    }
+                }
+                None => None,
+            };
 
-                    let hash = sql_query(ANCESTOR_SQL)
-                        .bind::<Text, _>(block_ptr.hash_hex())
-                        .bind::<BigInt, _>(offset as i64)
-                        .get_result::<BlockHashText>(conn)
-                        .optional()?;
+            let data = match hash {
+                None => None,
+                Some(hash) => Some(self.block_data(conn, hash)?),
+            };
 
-                    use public::ethereum_blocks as b;
+            let block = data
+                .map(|data| serde_json::from_value::<EthereumBlock>(data))
+                .transpose()
+                .expect("Failed to deserialize block from database");
 
-                    match hash {
-                        None => None,
-                        Some(hash) => Some(
-                            b::table
-                                .filter(b::hash.eq(hash.hash))
-                                .select(b::data)
-                                .first::<serde_json::Value>(conn)?,
-                        ),
-                    }
-                }
-                Storage::Private(Schema { blocks, .. }) => {
-                    // Same as ANCESTOR_SQL except for the table name
-                    let query = format!(
-                        "
-        with recursive ancestors(block_hash, block_offset) as (
+            Ok(block)
+        }
+
+        /// Return the block with number `number` on the main chain. The
+        /// block is looked up through `canonical_blocks`; blocks that are
+        /// too old for that can only be found if they are the only block
+        /// with that number that we store, e.g., because its hash was
+        /// confirmed with `confirm_block_hash`
+        pub(super) fn block_by_number(
+            &self,
+            conn: &PgConnection,
+            network: &str,
+            number: BlockNumber,
+        ) -> Result<Option<EthereumBlock>, Error> {
+            let hash = match self.canonical_hash(conn, network, number)? {
+                Some(hash) => Some(hash),
+                None => {
+                    let mut hashes =
+                        self.block_hashes_by_block_number(conn, network, number as u64)?;
+                    if hashes.len() == 1 {
+                        hashes.pop()
+                    } else {
+                        None
+                    }
+                }
+            };
+
+            let data = match hash {
+                None => None,
+                Some(hash) => Some(self.block_data(conn, hash)?),
+            };
+
+            let block = data
+                .map(|data| serde_json::from_value::<EthereumBlock>(data))
+                .transpose()
+                .expect("Failed to deserialize block from database");
+
+            Ok(block)
+        }
+
+        /// The hash that `canonical_blocks` records for block `number`
+        fn canonical_hash(
+            &self,
+            conn: &PgConnection,
+            network: &str,
+            number: BlockNumber,
+        ) -> Result<Option<H256>, Error> {
+            match self {
+                Storage::Shared => sql_query(
+                    "select hash from eth_canonical_blocks
+                      where network_name = $1 and number = $2",
+                )
+                .bind::<Text, _>(network)
+                .bind::<BigInt, _>(number as i64)
+                .get_result::<BlockHashText>(conn)
+                .optional()?
+                .map(|block| block.hash.parse().map_err(Error::from))
+                .transpose(),
+                Storage::Private(Schema {
+                    canonical_blocks, ..
+                }) => {
+                    let query = format!(
+                        "select hash from {} where number = $1",
+                        canonical_blocks.qname
+                    );
+                    sql_query(query)
+                        .bind::<BigInt, _>(number as i64)
+                        .get_result::<BlockHashBytea>(conn)
+                        .optional()?
+                        .map(|block| h256_from_bytes(&block.hash).map_err(Error::from))
+                        .transpose()
+                }
+            }
+        }
+
+        /// Return the latest block in `canonical_blocks` that is
+        /// `block_ptr` or one of its at most `max_depth` closest ancestors
+        pub(super) fn canonical_ancestor(
+            &self,
+            conn: &PgConnection,
+            network: &str,
+            block_ptr: EthereumBlockPointer,
+            max_depth: i64,
+        ) -> Result<Option<EthereumBlockPointer>, Error> {
+            let (hash, steps) =
+                match self.walk_ancestors(conn, network, block_ptr.hash, max_depth, true)? {
+                    Some(ancestor) => ancestor,
+                    None => return Ok(None),
+                };
+            let number = block_ptr.number as i64 - steps;
+            if number < 0 {
+                return Ok(None);
+            }
+            match self.canonical_hash(conn, network, number as BlockNumber)? {
+                Some(canonical) if canonical == hash => Ok(Some((hash, number).into())),
+                _ => Ok(None),
+            }
+        }
+
+        /// Follow parent hashes from the block `hash` for at most `offset`
+        /// steps and return the last hash we reached together with the
+        /// number of steps it took. If `stop_at_canonical` is `true`, stop
+        /// at the first block that is in `canonical_blocks`
+        fn walk_ancestors(
+            &self,
+            conn: &PgConnection,
+            network: &str,
+            hash: H256,
+            offset: i64,
+            stop_at_canonical: bool,
+        ) -> Result<Option<(H256, i64)>, Error> {
+            match self {
+                Storage::Shared => {
+                    let stop = if stop_at_canonical {
+                        "and not exists (select 1 from eth_canonical_blocks c
+                                          where c.network_name = $3
+                                            and c.number = b.number
+                                            and c.hash = b.hash)"
+                    } else {
+                        ""
+                    };
+                    let query = format!(
+                        "
+        with recursive ancestors(block_hash, block_offset) as (
+            values ($1, 0)
+            union all
+            select b.parent_hash, a.block_offset+1
+              from ancestors a, ethereum_blocks b
+             where a.block_hash = b.hash
+               and a.block_offset < $2
+               {}
+        )
+        select a.block_hash as hash, a.block_offset
+          from ancestors a
+         order by a.block_offset desc
+         limit 1;",
+                        stop
+                    );
+
+                    let query = sql_query(query)
+                        .into_boxed::<Pg>()
+                        .bind::<Text, _>(format!("{:x}", hash))
+                        .bind::<BigInt, _>(offset);
+                    let query = if stop_at_canonical {
+                        query.bind::<Text, _>(network.to_owned())
+                    } else {
+                        query
+                    };
+                    query
+                        .get_result::<AncestorText>(conn)
+                        .optional()?
+                        .map(|ancestor| Ok((ancestor.hash.parse()?, ancestor.block_offset)))
+                        .transpose()
+                }
+                Storage::Private(Schema {
+                    blocks,
+                    canonical_blocks,
+                    ..
+                }) => {
+                    // Same as for `Storage::Shared` except for the table
+                    // names and that we do not need to filter by network
+                    let stop = if stop_at_canonical {
+                        format!(
+                            "and not exists (select 1 from {} c
+                                              where c.number = b.number
+                                                and c.hash = b.hash)",
+                            canonical_blocks.qname
+                        )
+                    } else {
+                        String::new()
+                    };
+                    let query = format!(
+                        "
+        with recursive ancestors(block_hash, block_offset) as (
             values ($1, 0)
             union all
             select b.parent_hash, a.block_offset+1
               from ancestors a, {} b
              where a.block_hash = b.hash
                and a.block_offset < $2
+               {}
         )
-        select a.block_hash as hash
+        select a.block_hash as hash, a.block_offset
           from ancestors a
-         where a.block_offset = $2;",
-                        blocks.qname
+         order by a.block_offset desc
+         limit 1;",
+                        blocks.qname, stop
                     );
 
-                    let hash = sql_query(query)
-                        .bind::<Bytea, _>(block_ptr.hash.as_bytes())
-                        .bind::<BigInt, _>(offset as i64)
+                    sql_query(query)
+                        .bind::<Bytea, _>(hash.as_bytes())
+                        .bind::<BigInt, _>(offset)
+                        .get_result::<AncestorBytea>(conn)
+                        .optional()?
+                        .map(|ancestor| {
+                            Ok((h256_from_bytes(&ancestor.hash)?, ancestor.block_offset))
+                        })
+                        .transpose()
+                }
+            }
+        }
+
+        /// If the block `hash` is in `canonical_blocks`, return the hash
+        /// of the block that `canonical_blocks` records `offset` blocks
+        /// before it
+        fn canonical_ancestor(
+            &self,
+            conn: &PgConnection,
+            network: &str,
+            hash: H256,
+            offset: i64,
+        ) -> Result<Option<H256>, Error> {
+            match self {
+                Storage::Shared => sql_query(
+                    "select a.hash
+                       from ethereum_blocks b,
+                            eth_canonical_blocks c,
+                            eth_canonical_blocks a
+                      where b.hash = $1
+                        and c.network_name = $2
+                        and c.number = b.number
+                        and c.hash = b.hash
+                        and a.network_name = $2
+                        and a.number = b.number - $3",
+                )
+                .bind::<Text, _>(format!("{:x}", hash))
+                .bind::<Text, _>(network)
+                .bind::<BigInt, _>(offset)
+                .get_result::<BlockHashText>(conn)
+                .optional()?
+                .map(|ancestor| ancestor.hash.parse().map_err(Error::from))
+                .transpose(),
+                Storage::Private(Schema {
+                    blocks,
+                    canonical_blocks,
+                    ..
+                }) => {
+                    let query = format!(
+                        "select a.hash
+                           from {blocks} b, {canonical} c, {canonical} a
+                          where b.hash = $1
+                            and c.number = b.number
+                            and c.hash = b.hash
+                            and a.number = b.number - $2",
+                        blocks = blocks.qname,
+                        canonical = canonical_blocks.qname
+                    );
+                    sql_query(query)
+                        .bind::<Bytea, _>(hash.as_bytes())
+                        .bind::<BigInt, _>(offset)
                         .get_result::<BlockHashBytea>(conn)
-                        .optional()?;
-                    match hash {
-                        None => None,
-                        Some(hash) => Some(
-                            blocks
-                                .table()
-                                .filter(blocks.hash().eq(hash.hash))
-                                .select(blocks.data())
-                                .first::<serde_json::Value>(conn)?,
-                        ),
-                    }
+                        .optional()?
+                        .map(|ancestor| h256_from_bytes(&ancestor.hash).map_err(Error::from))
+                        .transpose()
+                }
+            }
+        }
+
+        fn block_data(&self, conn: &PgConnection, hash: H256) -> Result<serde_json::Value, Error> {
+            match self {
+                Storage::Shared => {
+                    use public::ethereum_blocks as b;
+
+                    b::table
+                        .filter(b::hash.eq(format!("{:x}", hash)))
+                        .select(b::data)
+                        .first::<serde_json::Value>(conn)
+                        .map_err(Error::from)
+                }
+                Storage::Private(Schema { blocks, .. }) => blocks
+                    .table()
+                    .filter(blocks.hash().eq(hash.as_bytes()))
+                    .select(blocks.data())
+                    .first::<serde_json::Value>(conn)
+                    .map_err(Error::from),
+            }
+        }
+
+        /// The data of all blocks with a number in `range`, ordered by
+        /// block number
+        pub(super) fn blocks_in_range(
+            &self,
+            conn: &PgConnection,
+            network: &str,
+            range: Range<BlockNumber>,
+        ) -> Result<Vec<serde_json::Value>, Error> {
+            let rows = match self {
+                Storage::Shared => sql_query(
+                    "select data from ethereum_blocks \
+                      where network_name = $1 and number >= $2 and number < $3 \
+                      order by number",
+                )
+                .bind::<Text, _>(network)
+                .bind::<BigInt, _>(range.start)
+                .bind::<BigInt, _>(range.end)
+                .load::<BlockDataRow>(conn)?,
+                Storage::Private(Schema { blocks, .. }) => {
+                    let query = format!(
+                        "select data from {} where number >= $1 and number < $2 order by number",
+                        blocks.qname
+                    );
+                    sql_query(query)
+                        .bind::<BigInt, _>(range.start)
+                        .bind::<BigInt, _>(range.end)
+                        .load::<BlockDataRow>(conn)?
                 }
             };
+            Ok(rows.into_iter().map(|row| row.data).collect())
+        }
 
-            let block = data
-                .map(|data| serde_json::from_value::<EthereumBlock>(data))
-                .transpose()
-                .expect("Failed to deserialize block from database");
+        /// The smallest and largest number of a block in the cache, or
+        /// `None` if the cache is empty
+        pub(super) fn block_number_bounds(
+            &self,
+            conn: &PgConnection,
+            network: &str,
+        ) -> Result<Option<(BlockNumber, BlockNumber)>, Error> {
+            let bounds = match self {
+                Storage::Shared => sql_query(
+                    "select min(number) as min, max(number) as max \
+                       from ethereum_blocks where network_name = $1",
+                )
+                .bind::<Text, _>(network)
+                .get_result::<BlockNumberBounds>(conn)?,
+                Storage::Private(Schema { blocks, .. }) => {
+                    let query = format!(
+                        "select min(number) as min, max(number) as max from {}",
+                        blocks.qname
+                    );
+                    sql_query(query).get_result::<BlockNumberBounds>(conn)?
+                }
+            };
+            match (bounds.min, bounds.max) {
+                (Some(min), Some(max)) => Ok(Some((min, max))),
+                _ => Ok(None),
+            }
+        }
 
-            Ok(block)
+        /// Record the chain that ends in the new chain head `head` in
+        /// `canonical_blocks`, going back no further than `first_block`.
+        /// We stop at the first block that is already recorded, so that
+        /// usually only the new head needs to be written. If the blocks we
+        /// write do not connect to the ones that were already recorded,
+        /// e.g., after a reorg that was deeper than `first_block`, we
+        /// forget the older ones since they might belong to a different
+        /// fork
+        pub(super) fn update_canonical_blocks(
+            &self,
+            conn: &PgConnection,
+            network: &str,
+            head: EthereumBlockPointer,
+            first_block: i64,
+        ) -> Result<(), Error> {
+            let head_number = head.number as i64;
+
+            match self {
+                Storage::Shared => {
+                    let written = sql_query(
+                        "
+        with recursive chain(hash, number, parent_hash) as (
+            select b.hash, b.number, b.parent_hash
+              from ethereum_blocks b
+             where b.hash = $1
+            union all
+            select b.hash, b.number, b.parent_hash
+              from chain, ethereum_blocks b
+             where b.hash = chain.parent_hash
+               and b.number >= $2
+               and not exists (select 1 from eth_canonical_blocks c
+                                where c.network_name = $3
+                                  and c.number = b.number
+                                  and c.hash = b.hash)
+        )
+        insert into eth_canonical_blocks(network_name, number, hash)
+        select $3, chain.number, chain.hash
+          from chain
+            on conflict(network_name, number) do update set hash = excluded.hash
+        returning number;",
+                    )
+                    .bind::<Text, _>(head.hash_hex())
+                    .bind::<BigInt, _>(first_block)
+                    .bind::<Text, _>(network)
+                    .load::<BlockNumberRow>(conn)?;
+
+                    if let Some(lowest) = written.iter().map(|row| row.number).min() {
+                        sql_query(
+                            "
+        delete from eth_canonical_blocks c
+         where c.network_name = $1
+           and (c.number > $2
+                or (c.number < $3
+                    and not exists (select 1
+                                      from eth_canonical_blocks l,
+                                           ethereum_blocks b,
+                                           eth_canonical_blocks p
+                                     where l.network_name = $1
+                                       and l.number = $3
+                                       and b.hash = l.hash
+                                       and p.network_name = $1
+                                       and p.number = $3 - 1
+                                       and p.hash = b.parent_hash)))",
+                        )
+                        .bind::<Text, _>(network)
+                        .bind::<BigInt, _>(head_number)
+                        .bind::<BigInt, _>(lowest)
+                        .execute(conn)?;
+                    }
+                }
+                Storage::Private(Schema {
+                    blocks,
+                    canonical_blocks,
+                    ..
+                }) => {
+                    // Same as for `Storage::Shared` except for the table
+                    // names and that we do not need to filter by network
+                    let query = format!(
+                        "
+        with recursive chain(hash, number, parent_hash) as (
+            select b.hash, b.number, b.parent_hash
+              from {blocks} b
+             where b.hash = $1
+            union all
+            select b.hash, b.number, b.parent_hash
+              from chain, {blocks} b
+             where b.hash = chain.parent_hash
+               and b.number >= $2
+               and not exists (select 1 from {canonical} c
+                                where c.number = b.number
+                                  and c.hash = b.hash)
+        )
+        insert into {canonical}(number, hash)
+        select chain.number, chain.hash
+          from chain
+            on conflict(number) do update set hash = excluded.hash
+        returning number;",
+                        blocks = blocks.qname,
+                        canonical = canonical_blocks.qname
+                    );
+                    let written = sql_query(query)
+                        .bind::<Bytea, _>(head.hash.as_bytes())
+                        .bind::<BigInt, _>(first_block)
+                        .load::<BlockNumberRow>(conn)?;
+
+                    if let Some(lowest) = written.iter().map(|row| row.number).min() {
+                        let query = format!(
+                            "
+        delete from {canonical} c
+         where c.number > $1
+            or (c.number < $2
+                and not exists (select 1
+                                  from {canonical} l, {blocks} b, {canonical} p
+                                 where l.number = $2
+                                   and b.hash = l.hash
+                                   and p.number = $2 - 1
+                                   and p.hash = b.parent_hash))",
+                            blocks = blocks.qname,
+                            canonical = canonical_blocks.qname
+                        );
+                        sql_query(query)
+                            .bind::<BigInt, _>(head_number)
+                            .bind::<BigInt, _>(lowest)
+                            .execute(conn)?;
+                    }
+                }
+            }
+            Ok(())
         }
 
         pub(super) fn delete_blocks_before(
@@ -832,10 +1839,35 @@ mod data {
             network: &str,
             block: i64,
         ) -> Result<usize, Error> {
+            // The `canonical_blocks` must not point to blocks that are gone,
+            // and receipts and traces are only useful together with their
+            // block
             match self {
                 Storage::Shared => {
                     use public::ethereum_blocks as b;
 
+                    sql_query(
+                        "delete from eth_canonical_blocks \
+                          where network_name = $1 and number < $2 and number > 0",
+                    )
+                    .bind::<Text, _>(network)
+                    .bind::<BigInt, _>(block)
+                    .execute(conn)?;
+                    sql_query(
+                        "delete from eth_transaction_receipts \
+                          where network_name = $1 and block_number < $2 and block_number > 0",
+                    )
+                    .bind::<Text, _>(network)
+                    .bind::<BigInt, _>(block)
+                    .execute(conn)?;
+                    sql_query(
+                        "delete from eth_block_traces \
+                          where network_name = $1 and block_number < $2 and block_number > 0",
+                    )
+                    .bind::<Text, _>(network)
+                    .bind::<BigInt, _>(block)
+                    .execute(conn)?;
+
                     diesel::delete(b::table)
                         .filter(b::network_name.eq(network))
                         .filter(b::number.lt(block))
@@ -843,7 +1875,26 @@ mod data {
                         .execute(conn)
                         .map_err(Error::from)
                 }
-                Storage::Private(Schema { blocks, .. }) => {
+                Storage::Private(Schema {
+                    blocks,
+                    canonical_blocks,
+                    receipts,
+                    traces,
+                    ..
+                }) => {
+                    let query = format!(
+                        "delete from {} where number < $1 and number > 0",
+                        canonical_blocks.qname
+                    );
+                    sql_query(query).bind::<BigInt, _>(block).execute(conn)?;
+                    for table in &[&receipts.qname, &traces.qname] {
+                        let query = format!(
+                            "delete from {} where block_number < $1 and block_number > 0",
+                            table
+                        );
+                        sql_query(query).bind::<BigInt, _>(block).execute(conn)?;
+                    }
+
                     let query = format!(
                         "delete from {} where number < $1 and number > 0",
                         blocks.qname
@@ -856,42 +1907,73 @@ mod data {
             }
         }
 
+        /// Return the number of the latest block in the cache that has a
+        /// timestamp before `timestamp`, or `None` if there is no such block
+        pub(super) fn latest_block_before(
+            &self,
+            conn: &PgConnection,
+            network: &str,
+            timestamp: u64,
+        ) -> Result<Option<BlockNumber>, Error> {
+            let timestamp = i64::try_from(timestamp)?;
+            let row = match self {
+                Storage::Shared => {
+                    let query = format!(
+                        "select max(number) as number from ethereum_blocks \
+                          where network_name = $1 and {} < $2",
+                        BLOCK_TIMESTAMP
+                    );
+                    sql_query(query)
+                        .bind::<Text, _>(network)
+                        .bind::<BigInt, _>(timestamp)
+                        .get_result::<MaxBlockNumber>(conn)?
+                }
+                Storage::Private(Schema { blocks, .. }) => {
+                    let query = format!(
+                        "select max(number) as number from {} where {} < $1",
+                        blocks.qname, BLOCK_TIMESTAMP
+                    );
+                    sql_query(query)
+                        .bind::<BigInt, _>(timestamp)
+                        .get_result::<MaxBlockNumber>(conn)?
+                }
+            };
+            Ok(row.number)
+        }
+
+        /// Look up the call with `id` and return its return value, the
+        /// reason why it reverted if it did, and whether its `accessed_at`
+        /// needs to be updated because the call was not used yet today
         pub(super) fn get_call_and_access(
             &self,
             conn: &PgConnection,
             id: &[u8],
-        ) -> Result<Option<(Vec<u8>, bool)>, Error> {
+            block_number: BlockNumber,
+        ) -> Result<Option<(Vec<u8>, Option<String>, bool)>, Error> {
             match self {
                 Storage::Shared => {
                     use public::eth_call_cache as cache;
-                    use public::eth_call_meta as meta;
 
                     cache::table
                         .find(id.as_ref())
-                        .inner_join(meta::table)
+                        .filter(cache::block_number.le(block_number))
                         .select((
                             cache::return_value,
-                            sql("CURRENT_DATE > eth_call_meta.accessed_at"),
+                            cache::revert_reason,
+                            sql("CURRENT_DATE > eth_call_cache.accessed_at"),
                         ))
                         .get_result(conn)
                         .optional()
                         .map_err(Error::from)
                 }
-                Storage::Private(Schema {
-                    call_cache,
-                    call_meta,
-                    ..
-                }) => call_cache
+                Storage::Private(Schema { call_cache, .. }) => call_cache
                     .table()
-                    .inner_join(
-                        call_meta.table().on(call_meta
-                            .contract_address()
-                            .eq(call_cache.contract_address())),
-                    )
                     .filter(call_cache.id().eq(id))
+                    .filter(call_cache.block_number().le(block_number))
                     .select((
                         call_cache.return_value(),
-                        sql("CURRENT_DATE > eth_call_meta.accessed_at"),
+                        call_cache.revert_reason(),
+                        sql("CURRENT_DATE > call_cache.accessed_at"),
                     ))
                     .first(conn)
                     .optional()
@@ -899,55 +1981,183 @@ mod data {
             }
         }
 
+        /// Mark the call with `id` as used today
         pub(super) fn update_accessed_at(
             &self,
             conn: &PgConnection,
-            contract_address: &[u8],
+            id: &[u8],
         ) -> Result<(), Error> {
             let result = match self {
                 Storage::Shared => {
-                    use public::eth_call_meta as meta;
+                    use public::eth_call_cache as cache;
 
-                    update(meta::table.find(contract_address.as_ref()))
-                        .set(meta::accessed_at.eq(sql("CURRENT_DATE")))
+                    update(cache::table.find(id.as_ref()))
+                        .set(cache::accessed_at.eq(sql("CURRENT_DATE")))
                         .execute(conn)
                 }
-                Storage::Private(Schema { call_meta, .. }) => {
+                Storage::Private(Schema { call_cache, .. }) => {
                     let query = format!(
-                        "update {} set accessed_at = CURRENT_DATE where contract_address = $1",
-                        call_meta.qname
+                        "update {} set accessed_at = CURRENT_DATE where id = $1",
+                        call_cache.qname
                     );
-                    sql_query(query)
-                        .bind::<Bytea, _>(contract_address)
-                        .execute(conn)
+                    sql_query(query).bind::<Bytea, _>(id).execute(conn)
                 }
             };
             result.map(|_| ()).map_err(Error::from)
         }
 
+        fn call_cache_qname(&self) -> &str {
+            match self {
+                Storage::Shared => "public.eth_call_cache",
+                Storage::Private(Schema { call_cache, .. }) => &call_cache.qname,
+            }
+        }
+
+        fn call_meta_qname(&self) -> &str {
+            match self {
+                Storage::Shared => "public.eth_call_meta",
+                Storage::Private(Schema { call_meta, .. }) => &call_meta.qname,
+            }
+        }
+
+        /// Look up the calls with the given `ids` that were cached at or
+        /// before `block_number` and mark the ones that were not used today
+        /// yet as used today. Returns the return value and revert reason of
+        /// the calls that are cached, keyed by id
+        pub(super) fn get_calls_and_access(
+            &self,
+            conn: &PgConnection,
+            ids: &[Vec<u8>],
+            block_number: BlockNumber,
+        ) -> Result<HashMap<Vec<u8>, (Vec<u8>, Option<String>)>, Error> {
+            let table = self.call_cache_qname();
+            let query = format!(
+                "select id, return_value, revert_reason, \
+                        CURRENT_DATE > accessed_at as stale \
+                   from {} where id = any($1) and block_number <= $2",
+                table
+            );
+            let rows = sql_query(query)
+                .bind::<Array<Bytea>, _>(ids)
+                .bind::<BigInt, _>(block_number)
+                .load::<CallRow>(conn)?;
+
+            let stale: Vec<_> = rows
+                .iter()
+                .filter(|row| row.stale)
+                .map(|row| row.id.clone())
+                .collect();
+            if !stale.is_empty() {
+                let query = format!(
+                    "update {} set accessed_at = CURRENT_DATE where id = any($1)",
+                    table
+                );
+                sql_query(query)
+                    .bind::<Array<Bytea>, _>(stale)
+                    .execute(conn)?;
+            }
+
+            Ok(rows
+                .into_iter()
+                .map(|row| (row.id, (row.return_value, row.revert_reason)))
+                .collect())
+        }
+
+        /// Remove calls from the call cache that were last used more than
+        /// `max_age_days` ago, and then the calls that were used the
+        /// longest time ago until at most `max_entries` calls remain. The
+        /// shared call cache is not specific to a network, and the limits
+        /// apply to the calls of all networks that use it. Returns the
+        /// number of calls that were removed
+        pub(super) fn evict_calls(
+            &self,
+            conn: &PgConnection,
+            max_age_days: Option<u32>,
+            max_entries: Option<u64>,
+        ) -> Result<usize, Error> {
+            let table = self.call_cache_qname();
+            let mut evicted = 0;
+
+            if let Some(max_age_days) = max_age_days {
+                let query = format!(
+                    "delete from {} where accessed_at < CURRENT_DATE - $1",
+                    table
+                );
+                evicted += sql_query(query)
+                    .bind::<Integer, _>(i32::try_from(max_age_days).unwrap_or(i32::MAX))
+                    .execute(conn)?;
+            }
+
+            if let Some(max_entries) = max_entries {
+                let query = format!("select count(*) as count from {}", table);
+                let count = sql_query(query).get_result::<CallCount>(conn)?.count;
+                let excess = count - i64::try_from(max_entries).unwrap_or(i64::MAX);
+                if excess > 0 {
+                    // Of the calls that were last used on the same day, we
+                    // keep the ones for later blocks since they are more
+                    // likely to be used again
+                    let query = format!(
+                        "delete from {table} where id in \
+                           (select id from {table} \
+                             order by accessed_at, block_number \
+                             limit $1)",
+                        table = table
+                    );
+                    evicted += sql_query(query).bind::<BigInt, _>(excess).execute(conn)?;
+                }
+            }
+            Ok(evicted)
+        }
+
+        /// Return the number of calls in the call cache and the size of the
+        /// table that holds them, including its indexes, in bytes
+        pub(super) fn call_cache_size(&self, conn: &PgConnection) -> Result<(u64, u64), Error> {
+            let table = self.call_cache_qname();
+            let query = format!(
+                "select count(*) as count, pg_total_relation_size('{}') as bytes from {}",
+                table, table
+            );
+            let size = sql_query(query).get_result::<CallCacheSize>(conn)?;
+            Ok((size.count as u64, size.bytes as u64))
+        }
+
+        /// The `on conflict` clause for inserting into the call cache as
+        /// `cache`. Calls that are not immutable are keyed by block hash, and
+        /// their entry never changes. Immutable calls have one entry per
+        /// contract, and that entry should be valid from the earliest block
+        /// for which we know their result
+        const CALL_CACHE_CONFLICT: &str = "on conflict(id) do update \
+               set block_number = least(excluded.block_number, cache.block_number) \
+             where excluded.block_number < cache.block_number";
+
         pub(super) fn set_call(
             &self,
             conn: &PgConnection,
             id: &[u8],
             contract_address: &[u8],
-            block_number: i32,
+            block_number: BlockNumber,
             return_value: &[u8],
+            revert_reason: Option<&str>,
         ) -> Result<(), Error> {
+            let query = format!(
+                "insert into {} as cache(id, contract_address, block_number, \
+                                         return_value, revert_reason) \
+                 values ($1, $2, $3, $4, $5) {}",
+                self.call_cache_qname(),
+                Self::CALL_CACHE_CONFLICT
+            );
+            sql_query(query)
+                .bind::<Bytea, _>(id)
+                .bind::<Bytea, _>(contract_address)
+                .bind::<BigInt, _>(block_number)
+                .bind::<Bytea, _>(return_value)
+                .bind::<Nullable<Text>, _>(revert_reason)
+                .execute(conn)?;
+
             let result = match self {
                 Storage::Shared => {
-                    use public::eth_call_cache as cache;
                     use public::eth_call_meta as meta;
 
-                    insert_into(cache::table)
-                        .values((
-                            cache::id.eq(id),
-                            cache::contract_address.eq(contract_address),
-                            cache::block_number.eq(block_number),
-                            cache::return_value.eq(return_value),
-                        ))
-                        .on_conflict_do_nothing()
-                        .execute(conn)?;
-
                     let accessed_at = meta::accessed_at.eq(sql("CURRENT_DATE"));
                     insert_into(meta::table)
                         .values((
@@ -959,23 +2169,7 @@ mod data {
                         .set(accessed_at)
                         .execute(conn)
                 }
-                Storage::Private(Schema {
-                    call_cache,
-                    call_meta,
-                    ..
-                }) => {
-                    let query = format!(
-                        "insert into {}(id, contract_address, block_number, return_value) \
-                         values ($1, $2, $3, $4) on conflict do nothing",
-                        call_cache.qname
-                    );
-                    sql_query(query)
-                        .bind::<Bytea, _>(id)
-                        .bind::<Bytea, _>(contract_address)
-                        .bind::<Integer, _>(block_number)
-                        .bind::<Bytea, _>(return_value)
-                        .execute(conn)?;
-
+                Storage::Private(Schema { call_meta, .. }) => {
                     let query = format!(
                         "insert into {}(contract_address, accessed_at) \
                          values ($1, CURRENT_DATE) \
@@ -990,7 +2184,334 @@ mod data {
             result.map(|_| ()).map_err(Error::from)
         }
 
-        #[cfg(debug_assertions)]
+        /// Insert several calls at `block_number` with one statement. Each
+        /// call is given as `(id, contract_address, return_value,
+        /// revert_reason)`
+        pub(super) fn set_calls(
+            &self,
+            conn: &PgConnection,
+            block_number: BlockNumber,
+            calls: Vec<(Vec<u8>, Vec<u8>, Vec<u8>, Option<String>)>,
+        ) -> Result<(), Error> {
+            if calls.is_empty() {
+                return Ok(());
+            }
+
+            let mut ids = Vec::with_capacity(calls.len());
+            let mut addresses = Vec::with_capacity(calls.len());
+            let mut return_values = Vec::with_capacity(calls.len());
+            let mut revert_reasons = Vec::with_capacity(calls.len());
+            for (id, address, return_value, revert_reason) in calls {
+                ids.push(id);
+                addresses.push(address);
+                return_values.push(return_value);
+                revert_reasons.push(revert_reason);
+            }
+
+            let query = format!(
+                "insert into {} as cache(id, contract_address, block_number, \
+                                         return_value, revert_reason) \
+                 select id, contract_address, $3, return_value, revert_reason \
+                   from unnest($1, $2, $4, $5) \
+                        as c(id, contract_address, return_value, revert_reason) \
+                 {}",
+                self.call_cache_qname(),
+                Self::CALL_CACHE_CONFLICT
+            );
+            sql_query(query)
+                .bind::<Array<Bytea>, _>(&ids)
+                .bind::<Array<Bytea>, _>(&addresses)
+                .bind::<BigInt, _>(block_number)
+                .bind::<Array<Bytea>, _>(&return_values)
+                .bind::<Array<Nullable<Text>>, _>(&revert_reasons)
+                .execute(conn)?;
+
+            let query = format!(
+                "insert into {}(contract_address, accessed_at) \
+                 select distinct contract_address, CURRENT_DATE \
+                   from unnest($1) as c(contract_address) \
+                 on conflict(contract_address) do update set accessed_at = CURRENT_DATE",
+                self.call_meta_qname()
+            );
+            sql_query(query)
+                .bind::<Array<Bytea>, _>(&addresses)
+                .execute(conn)?;
+            Ok(())
+        }
+
+        /// Insert gas statistics, overwriting the statistics for blocks
+        /// with the same number since they might come from a block that
+        /// has since been reorged out
+        pub(super) fn upsert_fee_stats(
+            &self,
+            conn: &PgConnection,
+            network: &str,
+            stats: Vec<BlockFeeStats>,
+        ) -> Result<(), Error> {
+            const COLUMNS: &str = "number, hash, gas_used, gas_limit, transaction_count, \
+                                   min_gas_price, median_gas_price, max_gas_price";
+            const VALUES: &str = "$1, $2, $3::numeric, $4::numeric, $5, \
+                                  $6::numeric, $7::numeric, $8::numeric";
+            const UPDATE: &str = "hash = excluded.hash, gas_used = excluded.gas_used, \
+                                  gas_limit = excluded.gas_limit, \
+                                  transaction_count = excluded.transaction_count, \
+                                  min_gas_price = excluded.min_gas_price, \
+                                  median_gas_price = excluded.median_gas_price, \
+                                  max_gas_price = excluded.max_gas_price";
+
+            let (query, network) = match self {
+                Storage::Shared => (
+                    format!(
+                        "insert into public.eth_fee_history({}, network_name) \
+                         values ({}, $9) \
+                         on conflict(network_name, number) do update set {}",
+                        COLUMNS, VALUES, UPDATE
+                    ),
+                    Some(network),
+                ),
+                Storage::Private(Schema { fee_history, .. }) => (
+                    format!(
+                        "insert into {}({}) values ({}) \
+                         on conflict(number) do update set {}",
+                        fee_history.qname, COLUMNS, VALUES, UPDATE
+                    ),
+                    None,
+                ),
+            };
+
+            for stats in stats {
+                let insert = sql_query(&query)
+                    .into_boxed::<Pg>()
+                    .bind::<BigInt, _>(stats.number as i64)
+                    .bind::<Bytea, _>(stats.hash.as_bytes().to_vec())
+                    .bind::<Text, _>(stats.gas_used.to_string())
+                    .bind::<Text, _>(stats.gas_limit.to_string())
+                    .bind::<BigInt, _>(stats.transaction_count as i64)
+                    .bind::<Text, _>(stats.min_gas_price.to_string())
+                    .bind::<Text, _>(stats.median_gas_price.to_string())
+                    .bind::<Text, _>(stats.max_gas_price.to_string());
+                let insert = match network {
+                    Some(network) => insert.bind::<Text, _>(network.to_owned()),
+                    None => insert,
+                };
+                insert.execute(conn)?;
+            }
+            Ok(())
+        }
+
+        /// Insert transaction receipts, replacing receipts that are already
+        /// stored for the same transaction in the same block. Receipts
+        /// without a block hash or number are ignored
+        pub(super) fn upsert_receipts(
+            &self,
+            conn: &PgConnection,
+            network: &str,
+            receipts: Vec<TransactionReceipt>,
+        ) -> Result<(), Error> {
+            let (query, network) = match self {
+                Storage::Shared => (
+                    "insert into public.eth_transaction_receipts\
+                       (block_hash, block_number, transaction_index, data, network_name) \
+                     values ($1, $2, $3, $4, $5) \
+                     on conflict(block_hash, transaction_index) do update set data = excluded.data"
+                        .to_owned(),
+                    Some(network),
+                ),
+                Storage::Private(Schema { receipts, .. }) => (
+                    format!(
+                        "insert into {}(block_hash, block_number, transaction_index, data) \
+                         values ($1, $2, $3, $4) \
+                         on conflict(block_hash, transaction_index) do update set data = excluded.data",
+                        receipts.qname
+                    ),
+                    None,
+                ),
+            };
+
+            for receipt in receipts {
+                let (block_hash, block_number) = match (receipt.block_hash, receipt.block_number) {
+                    (Some(hash), Some(number)) => (hash, number),
+                    _ => continue,
+                };
+                let insert = sql_query(&query)
+                    .into_boxed::<Pg>()
+                    .bind::<Bytea, _>(block_hash.as_bytes().to_vec())
+                    .bind::<BigInt, _>(block_number.as_u64() as i64)
+                    .bind::<BigInt, _>(receipt.transaction_index.as_u64() as i64)
+                    .bind::<Jsonb, _>(serde_json::to_value(&receipt)?);
+                let insert = match network {
+                    Some(network) => insert.bind::<Text, _>(network.to_owned()),
+                    None => insert,
+                };
+                insert.execute(conn)?;
+            }
+            Ok(())
+        }
+
+        /// Return the receipts that are stored for the block `hash`,
+        /// ordered by their index in the block
+        pub(super) fn receipts_for_block(
+            &self,
+            conn: &PgConnection,
+            network: &str,
+            hash: H256,
+        ) -> Result<Vec<TransactionReceipt>, Error> {
+            let rows = match self {
+                Storage::Shared => sql_query(
+                    "select data from public.eth_transaction_receipts \
+                      where network_name = $1 and block_hash = $2 \
+                      order by transaction_index",
+                )
+                .bind::<Text, _>(network)
+                .bind::<Bytea, _>(hash.as_bytes())
+                .load::<ReceiptRow>(conn)?,
+                Storage::Private(Schema { receipts, .. }) => {
+                    let query = format!(
+                        "select data from {} where block_hash = $1 order by transaction_index",
+                        receipts.qname
+                    );
+                    sql_query(query)
+                        .bind::<Bytea, _>(hash.as_bytes())
+                        .load::<ReceiptRow>(conn)?
+                }
+            };
+            rows.into_iter()
+                .map(|row| serde_json::from_value(row.data).map_err(Error::from))
+                .collect()
+        }
+
+        /// Insert the traces of blocks, replacing the traces that are already
+        /// stored for them. Since we store all traces of a block together,
+        /// `traces` must contain every trace of the blocks it mentions
+        pub(super) fn upsert_traces(
+            &self,
+            conn: &PgConnection,
+            network: &str,
+            traces: Vec<Trace>,
+        ) -> Result<(), Error> {
+            let (query, network) = match self {
+                Storage::Shared => (
+                    "insert into public.eth_block_traces\
+                       (block_hash, block_number, data, network_name) \
+                     values ($1, $2, $3, $4) \
+                     on conflict(block_hash) do update set data = excluded.data"
+                        .to_owned(),
+                    Some(network),
+                ),
+                Storage::Private(Schema { traces, .. }) => (
+                    format!(
+                        "insert into {}(block_hash, block_number, data) \
+                         values ($1, $2, $3) \
+                         on conflict(block_hash) do update set data = excluded.data",
+                        traces.qname
+                    ),
+                    None,
+                ),
+            };
+
+            let mut blocks: Vec<(H256, u64, Vec<Trace>)> = Vec::new();
+            for trace in traces {
+                match blocks
+                    .iter_mut()
+                    .find(|(hash, _, _)| hash == &trace.block_hash)
+                {
+                    Some((_, _, block_traces)) => block_traces.push(trace),
+                    None => blocks.push((trace.block_hash, trace.block_number, vec![trace])),
+                }
+            }
+
+            for (block_hash, block_number, traces) in blocks {
+                let insert = sql_query(&query)
+                    .into_boxed::<Pg>()
+                    .bind::<Bytea, _>(block_hash.as_bytes().to_vec())
+                    .bind::<BigInt, _>(block_number as i64)
+                    .bind::<Jsonb, _>(serde_json::to_value(&traces)?);
+                let insert = match network {
+                    Some(network) => insert.bind::<Text, _>(network.to_owned()),
+                    None => insert,
+                };
+                insert.execute(conn)?;
+            }
+            Ok(())
+        }
+
+        /// Return the traces that are stored for the blocks `hashes`. Blocks
+        /// for which no traces are stored are not in the result
+        pub(super) fn traces_for_blocks(
+            &self,
+            conn: &PgConnection,
+            network: &str,
+            hashes: &[H256],
+        ) -> Result<HashMap<H256, Vec<Trace>>, Error> {
+            let hashes: Vec<_> = hashes.iter().map(|hash| hash.as_bytes()).collect();
+            let rows = match self {
+                Storage::Shared => sql_query(
+                    "select block_hash, data from public.eth_block_traces \
+                      where network_name = $1 and block_hash = any($2)",
+                )
+                .bind::<Text, _>(network)
+                .bind::<Array<Bytea>, _>(hashes)
+                .load::<TracesRow>(conn)?,
+                Storage::Private(Schema { traces, .. }) => {
+                    let query = format!(
+                        "select block_hash, data from {} where block_hash = any($1)",
+                        traces.qname
+                    );
+                    sql_query(query)
+                        .bind::<Array<Bytea>, _>(hashes)
+                        .load::<TracesRow>(conn)?
+                }
+            };
+            rows.into_iter()
+                .map(|row| {
+                    let traces = serde_json::from_value(row.data)?;
+                    Ok((H256::from_slice(&row.block_hash), traces))
+                })
+                .collect()
+        }
+
+        pub(super) fn fee_history(
+            &self,
+            conn: &PgConnection,
+            network: &str,
+            from: u64,
+            to: u64,
+        ) -> Result<Vec<BlockFeeStats>, Error> {
+            const SELECT: &str = "select number, hash, gas_used::text, gas_limit::text, \
+                                  transaction_count, min_gas_price::text, \
+                                  median_gas_price::text, max_gas_price::text";
+
+            let rows = match self {
+                Storage::Shared => {
+                    let query = format!(
+                        "{} from public.eth_fee_history \
+                         where network_name = $1 and number between $2 and $3 \
+                         order by number",
+                        SELECT
+                    );
+                    sql_query(query)
+                        .bind::<Text, _>(network)
+                        .bind::<BigInt, _>(from as i64)
+                        .bind::<BigInt, _>(to as i64)
+                        .load::<FeeStatsRow>(conn)?
+                }
+                Storage::Private(Schema { fee_history, .. }) => {
+                    let query = format!(
+                        "{} from {} where number between $1 and $2 order by number",
+                        SELECT, fee_history.qname
+                    );
+                    sql_query(query)
+                        .bind::<BigInt, _>(from as i64)
+                        .bind::<BigInt, _>(to as i64)
+                        .load::<FeeStatsRow>(conn)?
+                }
+            };
+            rows.into_iter()
+                .map(|row| BlockFeeStats::try_from(row).map_err(Error::from))
+                .collect()
+        }
+
+        #[cfg(any(debug_assertions, feature = "testing"))]
         // used by `super::set_chain` for test support
         pub(super) fn set_chain(
             &self,
@@ -1008,12 +2529,61 @@ mod data {
                     diesel::delete(b::table.filter(b::network_name.eq(network)))
                         .execute(conn)
                         .expect("Failed to delete ethereum_blocks");
+                    sql_query("delete from public.eth_fee_history where network_name = $1")
+                        .bind::<Text, _>(network)
+                        .execute(conn)
+                        .expect("Failed to delete eth_fee_history");
+                    sql_query("delete from public.eth_canonical_blocks where network_name = $1")
+                        .bind::<Text, _>(network)
+                        .execute(conn)
+                        .expect("Failed to delete eth_canonical_blocks");
+                    sql_query(
+                        "delete from public.eth_transaction_receipts where network_name = $1",
+                    )
+                    .bind::<Text, _>(network)
+                    .execute(conn)
+                    .expect("Failed to delete eth_transaction_receipts");
+                    sql_query("delete from public.eth_block_traces where network_name = $1")
+                        .bind::<Text, _>(network)
+                        .execute(conn)
+                        .expect("Failed to delete eth_block_traces");
+                    sql_query("delete from public.eth_call_cache")
+                        .execute(conn)
+                        .expect("Failed to delete eth_call_cache");
                 }
-                Storage::Private(Schema { blocks, .. }) => {
+                Storage::Private(Schema {
+                    blocks,
+                    fee_history,
+                    canonical_blocks,
+                    receipts,
+                    traces,
+                    call_cache,
+                    ..
+                }) => {
                     let query = format!("delete from {}", blocks.qname);
                     sql_query(query)
                         .execute(conn)
                         .expect(&format!("Failed to delete {}", blocks.qname));
+                    let query = format!("delete from {}", fee_history.qname);
+                    sql_query(query)
+                        .execute(conn)
+                        .expect(&format!("Failed to delete {}", fee_history.qname));
+                    let query = format!("delete from {}", canonical_blocks.qname);
+                    sql_query(query)
+                        .execute(conn)
+                        .expect(&format!("Failed to delete {}", canonical_blocks.qname));
+                    let query = format!("delete from {}", receipts.qname);
+                    sql_query(query)
+                        .execute(conn)
+                        .expect(&format!("Failed to delete {}", receipts.qname));
+                    let query = format!("delete from {}", traces.qname);
+                    sql_query(query)
+                        .execute(conn)
+                        .expect(&format!("Failed to delete {}", traces.qname));
+                    let query = format!("delete from {}", call_cache.qname);
+                    sql_query(query)
+                        .execute(conn)
+                        .expect(&format!("Failed to delete {}", call_cache.qname));
                 }
             }
 
@@ -1027,6 +2597,7 @@ mod data {
                     n::genesis_block_hash.eq(genesis_hash),
                     n::head_block_hash.eq::<Option<&str>>(None),
                     n::head_block_number.eq::<Option<i64>>(None),
+                    n::finalized_block_number.eq::<Option<i64>>(None),
                 ))
                 .execute(conn)
                 .unwrap();
@@ -1034,12 +2605,63 @@ mod data {
     }
 }
 
+/// Counters from which the throughput of writing blocks with
+/// `upsert_blocks` can be computed
+#[derive(Clone)]
+struct UpsertMetrics {
+    blocks: Counter,
+    seconds: Counter,
+}
+
+impl UpsertMetrics {
+    fn new(registry: &dyn MetricsRegistry, network: &str) -> Self {
+        let labels: HashMap<String, String> = vec![("network".to_owned(), network.to_owned())]
+            .into_iter()
+            .collect();
+        let blocks = registry
+            .global_counter(
+                "chain_store_upserted_blocks",
+                "The number of blocks written to the block cache by the block ingestor",
+                labels.clone(),
+            )
+            .expect("failed to create `chain_store_upserted_blocks` counter");
+        let seconds = registry
+            .global_counter(
+                "chain_store_upsert_blocks_secs",
+                "The time spent writing blocks to the block cache for the block ingestor",
+                labels,
+            )
+            .expect("failed to create `chain_store_upsert_blocks_secs` counter");
+        UpsertMetrics { blocks, seconds }
+    }
+}
+
+/// The chain head as we last read it from the database
+struct CachedChainHead {
+    /// The generation of the chain head update listener before we read
+    /// the chain head
+    generation: u64,
+    read_at: Instant,
+    ptr: Option<EthereumBlockPointer>,
+}
+
 pub struct ChainStore {
     conn: ConnectionPool,
     network: String,
     storage: data::Storage,
     genesis_block_ptr: EthereumBlockPointer,
     chain_head_update_listener: Arc<ChainHeadUpdateListener>,
+    upsert_metrics: UpsertMetrics,
+    chain_head_cache: Mutex<Option<CachedChainHead>>,
+    /// The number of times `chain_head_ptr` was answered from the cache
+    chain_head_cache_hits: Counter,
+    /// The most used `eth_call` results, keyed by call id. Since the id
+    /// includes the block hash or the call is immutable, entries never
+    /// become outdated
+    call_cache: Mutex<LfuCache<[u8; 32], Option<CachedCall>>>,
+    /// The number of calls that were found and not found in `call_cache`
+    call_cache_hits: Counter,
+    call_cache_misses: Counter,
 }
 
 impl ChainStore {
@@ -1049,13 +2671,49 @@ impl ChainStore {
         net_identifier: EthereumNetworkIdentifier,
         chain_head_update_listener: Arc<ChainHeadUpdateListener>,
         pool: ConnectionPool,
+        registry: Arc<dyn MetricsRegistry>,
     ) -> Self {
+        let upsert_metrics = UpsertMetrics::new(registry.as_ref(), &network);
+        let chain_head_cache_hits = registry
+            .global_counter(
+                "chain_store_chain_head_cache_hits",
+                "The number of chain head lookups that did not need to go to the database",
+                vec![("network".to_owned(), network.clone())]
+                    .into_iter()
+                    .collect(),
+            )
+            .expect("failed to create `chain_store_chain_head_cache_hits` counter");
+        let call_cache_counter = |name: &str, help: &str| {
+            registry
+                .global_counter(
+                    name,
+                    help,
+                    vec![("network".to_owned(), network.clone())]
+                        .into_iter()
+                        .collect(),
+                )
+                .unwrap_or_else(|_| panic!("failed to create `{}` counter", name))
+        };
+        let call_cache_hits = call_cache_counter(
+            "chain_store_call_cache_hits",
+            "The number of eth_call results that were found in the in-memory call cache",
+        );
+        let call_cache_misses = call_cache_counter(
+            "chain_store_call_cache_misses",
+            "The number of eth_call results that were not found in the in-memory call cache",
+        );
         let store = ChainStore {
             conn: pool,
             network,
             storage,
             genesis_block_ptr: (net_identifier.genesis_block_hash, 0 as u64).into(),
             chain_head_update_listener,
+            upsert_metrics,
+            chain_head_cache: Mutex::new(None),
+            chain_head_cache_hits,
+            call_cache: Mutex::new(LfuCache::new()),
+            call_cache_hits,
+            call_cache_misses,
         };
 
         // Add network to store and check network identifiers
@@ -1064,10 +2722,250 @@ impl ChainStore {
         store
     }
 
+    /// Remove the chain `network` and all its data from the shard that `pool`
+    /// connects to. This is only useful after the chain was moved to a
+    /// different shard; does nothing if the shard has no data for `network`
+    pub fn drop_chain(pool: &ConnectionPool, network: &str) -> Result<(), StoreError> {
+        use public::ethereum_networks as n;
+
+        let conn = pool.get()?;
+        conn.transaction(|| {
+            let storage = n::table
+                .select(n::namespace)
+                .filter(n::name.eq(network))
+                .first::<data::Storage>(&conn)
+                .optional()?;
+            if let Some(storage) = storage {
+                storage.drop_storage(&conn, network)?;
+                diesel::delete(n::table.filter(n::name.eq(network))).execute(&conn)?;
+            }
+            Ok(())
+        })
+    }
+
     fn get_conn(&self) -> Result<PooledConnection<ConnectionManager<PgConnection>>, Error> {
         self.conn.get().map_err(Error::from)
     }
 
+    /// Look up the call with id `id` at block `block_number` in the
+    /// in-memory call cache
+    fn cached_call(&self, id: &[u8; 32], block_number: BlockNumber) -> Option<EthereumCallResult> {
+        if *CALL_CACHE_MEMORY == 0 {
+            return None;
+        }
+        let result = self
+            .call_cache
+            .lock()
+            .unwrap()
+            .get(id)
+            .cloned()
+            .flatten()
+            .filter(|cached| cached.block_number <= block_number)
+            .map(|cached| cached.result);
+        match result {
+            Some(_) => self.call_cache_hits.inc(),
+            None => self.call_cache_misses.inc(),
+        }
+        result
+    }
+
+    /// Add calls to the in-memory call cache, evicting the least used
+    /// calls if it gets too big
+    fn cache_calls(
+        &self,
+        block_number: BlockNumber,
+        calls: impl IntoIterator<Item = ([u8; 32], EthereumCallResult)>,
+    ) {
+        if *CALL_CACHE_MEMORY == 0 {
+            return;
+        }
+        let mut cache = self.call_cache.lock().unwrap();
+        for (id, result) in calls {
+            // Keep the earliest block for which we know an immutable call
+            let block_number = match cache.get(&id) {
+                Some(Some(cached)) => cached.block_number.min(block_number),
+                _ => block_number,
+            };
+            cache.insert(
+                id,
+                Some(CachedCall {
+                    block_number,
+                    result,
+                }),
+            );
+        }
+        cache.evict(*CALL_CACHE_MEMORY);
+    }
+
+    /// Whether `block_number` is more than `REORG_THRESHOLD` blocks behind
+    /// the chain head. Immutable calls are only cached from such blocks,
+    /// since their entry is used for all later blocks, and a block that
+    /// is reorged out might be from before the contract was deployed
+    fn is_final(&self, block_number: BlockNumber) -> Result<bool, Error> {
+        Ok(self.chain_head_ptr()?.map_or(false, |head| {
+            block_number + *REORG_THRESHOLD as BlockNumber <= head.block_number()
+        }))
+    }
+
+    /// Remove all calls from the in-memory call cache
+    fn clear_call_cache(&self) {
+        *self.call_cache.lock().unwrap() = LfuCache::new();
+    }
+
+    /// Forget the cached chain head, e.g., because we just changed it
+    fn invalidate_chain_head_cache(&self) {
+        *self.chain_head_cache.lock().unwrap() = None;
+    }
+
+    fn load_chain_head_ptr(&self) -> Result<Option<EthereumBlockPointer>, Error> {
+        use public::ethereum_networks::dsl::*;
+
+        ethereum_networks
+            .select((head_block_hash, head_block_number))
+            .filter(name.eq(&self.network))
+            .load::<(Option<String>, Option<i64>)>(&*self.get_conn()?)
+            .map(|rows| {
+                rows.first()
+                    .map(|(hash_opt, number_opt)| match (hash_opt, number_opt) {
+                        (Some(hash), Some(number)) => Some((hash.parse().unwrap(), *number).into()),
+                        (None, None) => None,
+                        _ => unreachable!(),
+                    })
+                    .and_then(|opt| opt)
+            })
+            .map_err(Error::from)
+    }
+
+    /// The oldest block that the slowest active deployment on this network
+    /// that has not failed still needs, or `None` if there are no such
+    /// deployments
+    fn oldest_referenced_block(&self, conn: &PgConnection) -> Result<Option<BlockNumber>, Error> {
+        use diesel::sql_types::{BigInt, Nullable};
+
+        #[derive(QueryableByName)]
+        struct MinBlock {
+            #[sql_type = "Nullable<BigInt>"]
+            block: Option<i64>,
+        };
+
+        // We stay behind the slowest subgraph so that we do not interfere
+        // with its syncing activity.
+        //
+        // This assumes that subgraph metadata and blocks are stored in the
+        // same shard. We disallow setting GRAPH_ETHEREUM_CLEANUP_BLOCKS in
+        // graph_node::config so that we only run this query when we know
+        // it will work. Running this with a sharded store might remove
+        // blocks that are still needed by deployments in other shard
+        //
+        // See 8b6ad0c64e244023ac20ced7897fe666
+        let query = "
+            select min(d.latest_ethereum_block_number)::int8 as block
+              from subgraphs.subgraph_deployment d,
+                   subgraphs.subgraph_deployment_assignment a,
+                   subgraphs.ethereum_contract_data_source ds
+             where left(ds.id, 46) = d.id
+               and a.id = d.id
+               and not d.failed
+               and ds.network = $1";
+        Ok(diesel::sql_query(query)
+            .bind::<Text, _>(&self.network)
+            .get_result::<MinBlock>(conn)?
+            .block)
+    }
+
+    /// Blocks that were stored without receipts can use the receipts that
+    /// were stored separately if we have all of them
+    fn with_receipts(
+        &self,
+        conn: &PgConnection,
+        block: Option<EthereumBlock>,
+    ) -> Result<Option<EthereumBlock>, Error> {
+        match block {
+            Some(mut block)
+                if block.transaction_receipts.is_empty()
+                    && !block.block.transactions.is_empty() =>
+            {
+                let hash = block.block.hash.expect("stored blocks have a hash");
+                let receipts = self.storage.receipts_for_block(conn, &self.network, hash)?;
+                if receipts.len() == block.block.transactions.len() {
+                    block.transaction_receipts = receipts;
+                }
+                Ok(Some(block))
+            }
+            block => Ok(block),
+        }
+    }
+
+    /// Add `blocks` to the block cache in one transaction. A block that is
+    /// already in the cache is only replaced if the new block has
+    /// transaction receipts so that importing never loses receipts
+    pub fn import_blocks(&self, blocks: Vec<EthereumBlock>) -> Result<(), Error> {
+        let conn = self.get_conn()?;
+        conn.transaction(|| {
+            for block in blocks {
+                if block.transaction_receipts.is_empty() {
+                    self.storage
+                        .upsert_light_block(&conn, &self.network, block.block)?;
+                } else {
+                    self.storage.upsert_block(&conn, &self.network, block)?;
+                }
+            }
+            Ok(())
+        })
+    }
+
+    /// Write the blocks in the block cache whose numbers are in `range`
+    /// to `writer` as JSON, one block per line, in the format that
+    /// `import_blocks` accepts, and return how many blocks were written.
+    /// Receipts that were stored separately are included with their block
+    pub fn export_blocks(
+        &self,
+        range: Range<BlockNumber>,
+        writer: &mut dyn Write,
+    ) -> Result<usize, Error> {
+        let conn = self.get_conn()?;
+        let end = match self.storage.block_number_bounds(&conn, &self.network)? {
+            Some((_, max)) => range.end.min(max + 1),
+            None => return Ok(0),
+        };
+
+        let mut count = 0;
+        let mut lower = range.start.max(0);
+        while lower < end {
+            let upper = lower.saturating_add(EXPORT_BATCH_SIZE).min(end);
+            for data in self
+                .storage
+                .blocks_in_range(&conn, &self.network, lower..upper)?
+            {
+                let block = serde_json::from_value::<EthereumBlock>(data)?;
+                let block = self
+                    .with_receipts(&conn, Some(block))?
+                    .expect("a block stays a block");
+                serde_json::to_writer(&mut *writer, &block)?;
+                writer.write_all(b"\n")?;
+                count += 1;
+            }
+            lower = upper;
+        }
+        writer.flush()?;
+        Ok(count)
+    }
+
+    /// The identifier of the network as it was recorded when the network
+    /// was first added
+    pub fn network_identifier(&self) -> Result<EthereumNetworkIdentifier, Error> {
+        use public::ethereum_networks::dsl::*;
+
+        let (version, hash) = ethereum_networks
+            .select((net_version, genesis_block_hash))
+            .filter(name.eq(&self.network))
+            .first::<(String, String)>(&*self.get_conn()?)?;
+        Ok(EthereumNetworkIdentifier {
+            net_version: version,
+            genesis_block_hash: hash.trim_start_matches("0x").parse()?,
+        })
+    }
+
     fn add_network_if_missing(
         &self,
         new_net_identifiers: EthereumNetworkIdentifier,
@@ -1147,6 +3045,19 @@ impl ChainStore {
         Ok(HashMap::from_iter(pointers))
     }
 
+    /// The number of calls in the call cache of this chain and how much
+    /// space they take up
+    pub fn call_cache_size(&self) -> Result<CallCacheSize, StoreError> {
+        let conn = self.get_conn()?;
+        let (entries, bytes) = self.storage.call_cache_size(&conn)?;
+        Ok(CallCacheSize {
+            network: self.network.clone(),
+            shared: matches!(self.storage, data::Storage::Shared),
+            entries,
+            bytes,
+        })
+    }
+
     pub fn chain_head_block(&self, network: &str) -> Result<Option<u64>, StoreError> {
         use public::ethereum_networks as n;
 
@@ -1186,12 +3097,22 @@ impl ChainStoreTrait for ChainStore {
         let conn = self.conn.clone();
         let network = self.network.clone();
         let storage = self.storage.clone();
-        Box::new(blocks.for_each(move |block| {
-            let conn = conn.get().map_err(Error::from)?;
-            storage
-                .upsert_block(&conn, &network, block)
-                .map_err(E::from)
-        }))
+        let metrics = self.upsert_metrics.clone();
+        Box::new(
+            blocks
+                .chunks(*UPSERT_BLOCKS_BATCH_SIZE)
+                .for_each(move |blocks| {
+                    let conn = conn.get().map_err(Error::from)?;
+                    let start = Instant::now();
+                    let count = blocks.len();
+                    storage
+                        .upsert_blocks(&conn, &network, blocks)
+                        .map_err(E::from)?;
+                    metrics.blocks.inc_by(count as f64);
+                    metrics.seconds.inc_by(start.elapsed().as_secs_f64());
+                    Ok(())
+                }),
+        )
     }
 
     fn upsert_light_blocks(&self, blocks: Vec<LightEthereumBlock>) -> Result<(), Error> {
@@ -1207,7 +3128,7 @@ impl ChainStoreTrait for ChainStore {
         use public::ethereum_networks as n;
 
         let conn = self.get_conn()?;
-        conn.transaction(|| {
+        let missing = conn.transaction(|| {
             let candidate = self.storage.chain_head_candidate(&conn, &self.network)?;
             let (ptr, first_block) = match candidate {
                 None => return Ok(vec![]),
@@ -1225,6 +3146,12 @@ impl ChainStoreTrait for ChainStore {
                 return Ok(missing);
             }
 
+            let previous = n::table
+                .filter(n::name.eq(&self.network))
+                .select((n::head_block_hash, n::head_block_number))
+                .first::<(Option<String>, Option<i64>)>(&conn)
+                .optional()?;
+
             let hash = ptr.hash_hex();
             let number = ptr.number as i64;
             update(n::table.filter(n::name.eq(&self.network)))
@@ -1234,10 +3161,42 @@ impl ChainStoreTrait for ChainStore {
                 ))
                 .execute(&conn)?;
 
-            ChainHeadUpdateListener::send(&conn, &self.network, &hash, number)?;
+            self.storage
+                .update_canonical_blocks(&conn, &self.network, ptr, first_block as i64)?;
+
+            // The previous head is not on the main chain anymore if the new
+            // head is on a different branch
+            let reorg = match previous {
+                Some((Some(previous_hash), Some(previous_number))) => {
+                    let previous: EthereumBlockPointer =
+                        (previous_hash.parse::<H256>()?, previous_number).into();
+                    let ancestor = self.storage.canonical_ancestor(
+                        &conn,
+                        &self.network,
+                        previous,
+                        ancestor_count as i64,
+                    )?;
+                    if ancestor == Some(previous) {
+                        None
+                    } else {
+                        Some(ChainReorg {
+                            previous_head_block_hash: previous.hash,
+                            previous_head_block_number: previous.number,
+                            common_ancestor: ancestor.map(|ptr| (ptr.hash, ptr.number)),
+                        })
+                    }
+                }
+                _ => None,
+            };
+
+            ChainHeadUpdateListener::send(&conn, &self.network, &hash, number, reorg.as_ref())?;
 
             Ok(vec![])
-        })
+        })?;
+
+        // Do not wait for the notification about our own update
+        self.invalidate_chain_head_cache();
+        Ok(missing)
     }
 
     fn chain_head_updates(&self) -> ChainHeadUpdateStream {
@@ -1246,22 +3205,23 @@ impl ChainStoreTrait for ChainStore {
     }
 
     fn chain_head_ptr(&self) -> Result<Option<EthereumBlockPointer>, Error> {
-        use public::ethereum_networks::dsl::*;
+        // Remember the generation before reading from the database so that
+        // an update that arrives while we read makes the cached value stale
+        let generation = self.chain_head_update_listener.generation(&self.network);
+        if let Some(cached) = &*self.chain_head_cache.lock().unwrap() {
+            if cached.generation == generation && cached.read_at.elapsed() < CHAIN_HEAD_CACHE_TTL {
+                self.chain_head_cache_hits.inc();
+                return Ok(cached.ptr);
+            }
+        }
 
-        ethereum_networks
-            .select((head_block_hash, head_block_number))
-            .filter(name.eq(&self.network))
-            .load::<(Option<String>, Option<i64>)>(&*self.get_conn()?)
-            .map(|rows| {
-                rows.first()
-                    .map(|(hash_opt, number_opt)| match (hash_opt, number_opt) {
-                        (Some(hash), Some(number)) => Some((hash.parse().unwrap(), *number).into()),
-                        (None, None) => None,
-                        _ => unreachable!(),
-                    })
-                    .and_then(|opt| opt)
-            })
-            .map_err(Error::from)
+        let ptr = self.load_chain_head_ptr()?;
+        *self.chain_head_cache.lock().unwrap() = Some(CachedChainHead {
+            generation,
+            read_at: Instant::now(),
+            ptr,
+        });
+        Ok(ptr)
     }
 
     fn blocks(&self, hashes: Vec<H256>) -> Result<Vec<LightEthereumBlock>, Error> {
@@ -1282,72 +3242,79 @@ impl ChainStoreTrait for ChainStore {
         );
 
         let conn = self.get_conn()?;
-        self.storage.ancestor_block(&conn, block_ptr, offset)
+        let block = self
+            .storage
+            .ancestor_block(&conn, &self.network, block_ptr, offset)?;
+        self.with_receipts(&conn, block)
     }
 
-    fn cleanup_cached_blocks(&self, ancestor_count: u64) -> Result<(BlockNumber, usize), Error> {
-        use diesel::sql_types::Integer;
+    fn block_by_number(&self, number: BlockNumber) -> Result<Option<EthereumBlock>, Error> {
+        let conn = self.get_conn()?;
+        let block = self.storage.block_by_number(&conn, &self.network, number)?;
+        self.with_receipts(&conn, block)
+    }
 
-        #[derive(QueryableByName)]
-        struct MinBlock {
-            #[sql_type = "Integer"]
-            block: i32,
-        };
+    fn cleanup_cached_blocks(
+        &self,
+        ancestor_count: u64,
+        retention: BlockRetention,
+    ) -> Result<(BlockNumber, usize), Error> {
+        let conn = self.get_conn()?;
 
-        // Remove all blocks from the cache that are behind the slowest
-        // subgraph's head block, but retain the genesis block. We stay
-        // behind the slowest subgraph so that we do not interfere with its
-        // syncing activity.
-        // We also stay `ancestor_count` many blocks behind the head of the
-        // chain since the block ingestor consults these blocks frequently
-        //
-        // Only consider active subgraphs that have not failed
+        // We always stay `ancestor_count` many blocks behind the head of
+        // the chain since the block ingestor consults these blocks
+        // frequently
+        let head = match self.load_chain_head_ptr()? {
+            Some(head) => head.number as i64,
+            None => return Ok((0, 0)),
+        };
+        let ancestor_count = i64::try_from(ancestor_count)
+            .expect("ancestor_count fits into a signed 64 bit integer");
+        let limit = head - ancestor_count;
+
+        let block = match retention {
+            BlockRetention::KeepLast(count) => {
+                let count = i64::try_from(count).unwrap_or(i64::MAX);
+                (head - count).min(limit)
+            }
+            BlockRetention::KeepSince(timestamp) => {
+                match self
+                    .storage
+                    .latest_block_before(&conn, &self.network, timestamp)?
+                {
+                    Some(block) => (block + 1).min(limit),
+                    None => return Ok((0, 0)),
+                }
+            }
+            BlockRetention::KeepReferenced => match self.oldest_referenced_block(&conn)? {
+                Some(block) => block.min(limit),
+                None => limit,
+            },
+        };
 
-        // This assumes that subgraph metadata and blocks are stored in the
-        // same shard. We disallow setting GRAPH_ETHEREUM_CLEANUP_BLOCKS in
-        // graph_node::config so that we only run this query when we know
-        // it will work. Running this with a sharded store might remove
-        // blocks that are still needed by deployments in other shard
-        //
-        // See 8b6ad0c64e244023ac20ced7897fe666
+        // We guard against removing the genesis block
+        if block > 0 {
+            self.storage
+                .delete_blocks_before(&conn, &self.network, block)
+                .map(|rows| (block, rows))
+        } else {
+            Ok((0, 0))
+        }
+    }
 
+    fn cleanup_call_cache(
+        &self,
+        max_age_days: Option<u32>,
+        max_entries: Option<u64>,
+    ) -> Result<usize, Error> {
         let conn = self.get_conn()?;
-        let query = "
-            select coalesce(
-                   least(a.block,
-                        (select head_block_number::int - $1
-                           from ethereum_networks
-                          where name = $2)), -1)::int as block
-              from (
-                select min(d.latest_ethereum_block_number) as block
-                  from subgraphs.subgraph_deployment d,
-                       subgraphs.subgraph_deployment_assignment a,
-                       subgraphs.ethereum_contract_data_source ds
-                 where left(ds.id, 46) = d.id
-                   and a.id = d.id
-                   and not d.failed
-                   and ds.network = $2) a;";
-        let ancestor_count = i32::try_from(ancestor_count)
-            .expect("ancestor_count fits into a signed 32 bit integer");
-        diesel::sql_query(query)
-            .bind::<Integer, _>(ancestor_count)
-            .bind::<Text, _>(&self.network)
-            .load::<MinBlock>(&conn)?
-            .first()
-            .map(|MinBlock { block }| {
-                // If we could not determine a minimum block, the query
-                // returns -1, and we should not do anything. We also guard
-                // against removing the genesis block
-                if *block > 0 {
-                    self.storage
-                        .delete_blocks_before(&conn, &self.network, *block as i64)
-                        .map(|rows| (*block, rows))
-                } else {
-                    Ok((0, 0))
-                }
-            })
-            .unwrap_or(Ok((0, 0)))
-            .map_err(|e| e.into())
+        let count = self.storage.evict_calls(&conn, max_age_days, max_entries)?;
+        // Do not keep answering calls from memory that were just removed
+        // from the database
+        if count > 0 {
+            self.clear_call_cache();
+        }
+        Ok(count)
     }
 
     fn block_hashes_by_block_number(&self, number: u64) -> Result<Vec<H256>, Error> {
@@ -1362,6 +3329,51 @@ impl ChainStoreTrait for ChainStore {
             .confirm_block_hash(&conn, &self.network, number, hash)
     }
 
+    fn finalized_block_number(&self) -> Result<Option<BlockNumber>, Error> {
+        use public::ethereum_networks as n;
+
+        let conn = self.get_conn()?;
+        Ok(n::table
+            .filter(n::name.eq(&self.network))
+            .select(n::finalized_block_number)
+            .first::<Option<i64>>(&conn)
+            .optional()?
+            .flatten())
+    }
+
+    fn finalize_blocks(&self, confirmations: u64) -> Result<Option<(BlockNumber, usize)>, Error> {
+        use public::ethereum_networks as n;
+
+        let conn = self.get_conn()?;
+        conn.transaction(|| {
+            let (head, finalized) = match n::table
+                .filter(n::name.eq(&self.network))
+                .select((n::head_block_number, n::finalized_block_number))
+                .first::<(Option<i64>, Option<i64>)>(&conn)
+                .optional()?
+            {
+                Some((Some(head), finalized)) => (head, finalized.unwrap_or(0)),
+                _ => return Ok(None),
+            };
+
+            let watermark = head - confirmations as BlockNumber;
+            if watermark <= finalized {
+                return Ok(None);
+            }
+
+            let purged = self.storage.confirm_canonical_blocks(
+                &conn,
+                &self.network,
+                finalized,
+                watermark,
+            )?;
+            update(n::table.filter(n::name.eq(&self.network)))
+                .set(n::finalized_block_number.eq(watermark))
+                .execute(&conn)?;
+            Ok(Some((watermark, purged)))
+        })
+    }
+
     fn block_number(&self, hash: H256) -> Result<Option<(String, BlockNumber)>, StoreError> {
         let conn = self.get_conn()?;
         Ok(self
@@ -1369,6 +3381,78 @@ impl ChainStoreTrait for ChainStore {
             .block_number(&conn, hash)?
             .map(|number| (self.network.clone(), number)))
     }
+
+    fn upsert_fee_stats(&self, stats: Vec<BlockFeeStats>) -> Result<(), Error> {
+        let conn = self.get_conn()?;
+        conn.transaction(|| self.storage.upsert_fee_stats(&conn, &self.network, stats))
+    }
+
+    fn fee_history(&self, from: u64, to: u64) -> Result<Vec<BlockFeeStats>, Error> {
+        let conn = self.get_conn()?;
+        self.storage.fee_history(&conn, &self.network, from, to)
+    }
+
+    fn upsert_receipts(&self, receipts: Vec<TransactionReceipt>) -> Result<(), Error> {
+        let conn = self.get_conn()?;
+        conn.transaction(|| self.storage.upsert_receipts(&conn, &self.network, receipts))
+    }
+
+    fn receipts_for_block(&self, block_hash: H256) -> Result<Vec<TransactionReceipt>, Error> {
+        let conn = self.get_conn()?;
+        self.storage
+            .receipts_for_block(&conn, &self.network, block_hash)
+    }
+
+    fn upsert_traces(&self, traces: Vec<Trace>) -> Result<(), Error> {
+        let conn = self.get_conn()?;
+        conn.transaction(|| self.storage.upsert_traces(&conn, &self.network, traces))
+    }
+
+    fn traces_for_blocks(&self, hashes: Vec<H256>) -> Result<HashMap<H256, Vec<Trace>>, Error> {
+        let conn = self.get_conn()?;
+        self.storage
+            .traces_for_blocks(&conn, &self.network, &hashes)
+    }
+
+    fn light_blocks(&self, hashes: Vec<H256>) -> Result<Vec<LightEthereumBlock>, Error> {
+        let conn = self.get_conn()?;
+        let mut light = Vec::new();
+        for block in self.storage.light_blocks(&conn, &self.network, &hashes)? {
+            let block = self
+                .with_receipts(
+                    &conn,
+                    Some(EthereumBlock {
+                        block,
+                        transaction_receipts: Vec::new(),
+                    }),
+                )?
+                .expect("a block stays a block");
+            if block.transaction_receipts.is_empty() {
+                light.push(block.block);
+            } else {
+                // All receipts were stored separately; merge them into
+                // the block
+                self.storage.hydrate_block(&conn, &self.network, &block)?;
+            }
+        }
+        Ok(light)
+    }
+
+    fn hydrate_blocks(&self, blocks: Vec<EthereumBlock>) -> Result<usize, Error> {
+        let conn = self.get_conn()?;
+        conn.transaction(|| {
+            let mut count = 0;
+            for block in blocks
+                .iter()
+                .filter(|block| block.transaction_receipts.len() == block.block.transactions.len())
+            {
+                if self.storage.hydrate_block(&conn, &self.network, block)? {
+                    count += 1;
+                }
+            }
+            Ok(count)
+        })
+    }
 }
 
 impl EthereumCallCache for ChainStore {
@@ -1377,22 +3461,29 @@ impl EthereumCallCache for ChainStore {
         contract_address: ethabi::Address,
         encoded_call: &[u8],
         block: EthereumBlockPointer,
-    ) -> Result<Option<Vec<u8>>, Error> {
+    ) -> Result<Option<EthereumCallResult>, Error> {
         let id = contract_call_id(&contract_address, encoded_call, &block);
+        if let Some(result) = self.cached_call(&id, block.block_number()) {
+            return Ok(Some(result));
+        }
         let conn = &*self.get_conn()?;
         if let Some(call_output) = conn.transaction::<_, Error, _>(|| {
-            if let Some((return_value, update_accessed_at)) =
-                self.storage.get_call_and_access(conn, id.as_ref())?
+            if let Some((return_value, revert_reason, update_accessed_at)) = self
+                .storage
+                .get_call_and_access(conn, id.as_ref(), block.block_number())?
             {
                 if update_accessed_at {
-                    self.storage
-                        .update_accessed_at(conn, contract_address.as_ref())?;
+                    self.storage.update_accessed_at(conn, id.as_ref())?;
                 }
-                Ok(Some(return_value))
+                Ok(Some(match revert_reason {
+                    Some(reason) => EthereumCallResult::Revert(reason),
+                    None => EthereumCallResult::Return(return_value),
+                }))
             } else {
                 Ok(None)
             }
         })? {
+            self.cache_calls(block.block_number(), Some((id, call_output.clone())));
             Ok(Some(call_output))
         } else {
             Ok(None)
@@ -1404,23 +3495,147 @@ impl EthereumCallCache for ChainStore {
         contract_address: ethabi::Address,
         encoded_call: &[u8],
         block: EthereumBlockPointer,
-        return_value: &[u8],
+        result: &EthereumCallResult,
     ) -> Result<(), Error> {
+        if !is_cacheable(encoded_call, result)
+            || (is_immutable(encoded_call) && !self.is_final(block.block_number())?)
+        {
+            return Ok(());
+        }
         let id = contract_call_id(&contract_address, encoded_call, &block);
+        let (return_value, revert_reason) = match result {
+            EthereumCallResult::Return(return_value) => (return_value.as_slice(), None),
+            EthereumCallResult::Revert(reason) => (&[][..], Some(reason.as_str())),
+        };
         let conn = &*self.get_conn()?;
         conn.transaction(|| {
             self.storage.set_call(
                 conn,
                 id.as_ref(),
                 contract_address.as_ref(),
-                block.number as i32,
+                block.block_number(),
                 return_value,
+                revert_reason,
             )
-        })
+        })?;
+        self.cache_calls(block.block_number(), Some((id, result.clone())));
+        Ok(())
+    }
+
+    fn get_calls(
+        &self,
+        calls: &[(ethabi::Address, Vec<u8>)],
+        block: EthereumBlockPointer,
+    ) -> Result<Vec<Option<EthereumCallResult>>, Error> {
+        let ids: Vec<_> = calls
+            .iter()
+            .map(|(address, encoded_call)| contract_call_id(address, encoded_call, &block))
+            .collect();
+        let mut results: Vec<_> = ids
+            .iter()
+            .map(|id| self.cached_call(id, block.block_number()))
+            .collect();
+        let missing: Vec<_> = ids
+            .iter()
+            .zip(results.iter())
+            .filter(|(_, result)| result.is_none())
+            .map(|(id, _)| id.to_vec())
+            .collect();
+        if missing.is_empty() {
+            return Ok(results);
+        }
+
+        let conn = &*self.get_conn()?;
+        let mut found = conn.transaction(|| {
+            self.storage
+                .get_calls_and_access(conn, &missing, block.block_number())
+        })?;
+        let mut loaded = Vec::new();
+        for (id, result) in ids.iter().zip(results.iter_mut()) {
+            if result.is_some() {
+                continue;
+            }
+            *result =
+                found
+                    .remove(&id[..])
+                    .map(|(return_value, revert_reason)| match revert_reason {
+                        Some(reason) => EthereumCallResult::Revert(reason),
+                        None => EthereumCallResult::Return(return_value),
+                    });
+            if let Some(result) = result {
+                loaded.push((*id, result.clone()));
+            }
+        }
+        self.cache_calls(block.block_number(), loaded);
+        Ok(results)
+    }
+
+    fn set_calls(
+        &self,
+        calls: &[(ethabi::Address, Vec<u8>, EthereumCallResult)],
+        block: EthereumBlockPointer,
+    ) -> Result<(), Error> {
+        let is_final = self.is_final(block.block_number())?;
+        let calls: Vec<_> = calls
+            .iter()
+            .filter(|(_, encoded_call, result)| {
+                is_cacheable(encoded_call, result) && (is_final || !is_immutable(encoded_call))
+            })
+            .collect();
+        if calls.is_empty() {
+            return Ok(());
+        }
+        let ids: Vec<_> = calls
+            .iter()
+            .map(|(address, encoded_call, _)| contract_call_id(address, encoded_call, &block))
+            .collect();
+        let rows = calls
+            .iter()
+            .zip(ids.iter())
+            .map(|((address, _, result), id)| {
+                let id = id.to_vec();
+                let (return_value, revert_reason) = match result {
+                    EthereumCallResult::Return(return_value) => (return_value.clone(), None),
+                    EthereumCallResult::Revert(reason) => (vec![], Some(reason.clone())),
+                };
+                (id, address.as_ref().to_vec(), return_value, revert_reason)
+            })
+            .collect();
+        let conn = &*self.get_conn()?;
+        conn.transaction(|| self.storage.set_calls(conn, block.block_number(), rows))?;
+        self.cache_calls(
+            block.block_number(),
+            ids.into_iter()
+                .zip(calls.iter().map(|(_, _, result)| result.clone())),
+        );
+        Ok(())
+    }
+}
+
+/// Whether `encoded_call` calls one of the functions listed in
+/// `GRAPH_ETH_CALL_IMMUTABLE`
+fn is_immutable(encoded_call: &[u8]) -> bool {
+    encoded_call.len() >= 4 && {
+        let mut selector = [0u8; 4];
+        selector.copy_from_slice(&encoded_call[..4]);
+        IMMUTABLE_CALLS.contains(&selector)
+    }
+}
+
+/// Whether we can cache `result` for `encoded_call`. Immutable calls are
+/// cached for all later blocks, and we only do that once they return
+/// something, since before the contract is deployed they revert or return
+/// nothing
+fn is_cacheable(encoded_call: &[u8], result: &EthereumCallResult) -> bool {
+    match result {
+        EthereumCallResult::Return(output) => !output.is_empty() || !is_immutable(encoded_call),
+        EthereumCallResult::Revert(_) => !is_immutable(encoded_call),
     }
 }
 
 /// The id is the hashed encoded_call + contract_address + block hash to uniquely identify the call.
+/// For immutable calls, the id leaves out the block hash so that there is only one entry per
+/// contract, which is valid for all blocks from the `block_number` at which it was cached on.
 /// 256 bits of output, and therefore 128 bits of security against collisions, are needed since this
 /// could be targeted by a birthday attack.
 fn contract_call_id(
@@ -1431,12 +3646,14 @@ fn contract_call_id(
     let mut hash = blake3::Hasher::new();
     hash.update(encoded_call);
     hash.update(contract_address.as_ref());
-    hash.update(block.hash.as_ref());
+    if !is_immutable(encoded_call) {
+        hash.update(block.hash.as_ref());
+    }
     *hash.finalize().as_bytes()
 }
 
 /// Support for tests
-#[cfg(debug_assertions)]
+#[cfg(any(debug_assertions, feature = "testing"))]
 pub mod test_support {
     use std::str::FromStr;
 
@@ -1504,12 +3721,14 @@ pub mod test_support {
     }
 }
 
-#[cfg(debug_assertions)]
+#[cfg(any(debug_assertions, feature = "testing"))]
 impl test_support::SettableChainStore for ChainStore {
     fn set_chain(&self, genesis_hash: &str, chain: test_support::Chain) {
         let conn = self.conn.get().expect("can get a database connection");
 
         self.storage
             .set_chain(&conn, &self.network, genesis_hash, chain);
+        self.invalidate_chain_head_cache();
+        self.clear_call_cache();
     }
 }
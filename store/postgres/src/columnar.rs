@@ -0,0 +1,254 @@
+//! Mirror the entity versions of selected tables into tables that use a
+//! columnar storage format.
+//!
+//! Analytical queries that scan over the whole history of an entity type
+//! are slow against our row-oriented tables. For the tables listed in
+//! `GRAPH_COLUMNAR_TABLES`, we maintain a mirror table `<table>$columnar`
+//! in the deployment's schema that uses the table access method from
+//! `GRAPH_COLUMNAR_ACCESS_METHOD` (by default `columnar`, which requires
+//! the Citus columnar extension).
+//!
+//! Every version that gets written at a block is appended to the mirror
+//! when that block is processed, and the block ranges of the versions that
+//! the block closed are copied into the mirror, so that the mirror has the
+//! same block ranges as the table and can answer queries at any block.
+//! Reverting a block undoes both. Aggregation queries for a table with a
+//! mirror read from the mirror instead of the table; since a mirror is
+//! updated in the same transaction as its table, they see the same data.
+//!
+//! Mirrors are created and filled with the existing versions of a table
+//! when the first block for the deployment is processed after the table was
+//! added to `GRAPH_COLUMNAR_TABLES`, and get an index on `vid` so that
+//! updating them for a block does not need to scan them. The table access
+//! method has to support `update`, `delete` and indexes, which we check
+//! when the store starts. Grafting drops the mirrors of the new deployment
+//! so that they get recreated from the grafted data
+use diesel::connection::SimpleConnection;
+use diesel::result::Error as DieselError;
+use diesel::sql_types::{BigInt, Bool, Text};
+use diesel::{Connection, PgConnection, RunQueryDsl};
+use lazy_static::lazy_static;
+use std::collections::HashSet;
+use std::env;
+use std::sync::Mutex;
+
+use graph::prelude::{anyhow, BlockNumber, StoreError};
+
+use crate::block_range::BLOCK_RANGE_COLUMN;
+use crate::primary::Namespace;
+use crate::relational::{Layout, Table, COLUMNAR_TABLES};
+
+lazy_static! {
+    /// The table access method to use for mirror tables. Using `heap`
+    /// makes it possible to use mirrors without any extensions
+    static ref ACCESS_METHOD: String = env::var("GRAPH_COLUMNAR_ACCESS_METHOD")
+        .unwrap_or_else(|_| "columnar".to_string());
+
+    /// The mirror tables that we know exist, so that we do not need to
+    /// check for them for every block
+    static ref MIRRORS: Mutex<HashSet<String>> = Mutex::new(HashSet::new());
+}
+
+fn mirror_name(layout: &Layout, table: &Table) -> String {
+    format!(
+        "\"{}\".\"{}$columnar\"",
+        layout.catalog.namespace,
+        table.name.as_str()
+    )
+}
+
+/// Check that the table access method for mirrors supports everything
+/// that maintaining mirrors needs. Does nothing if no tables are mirrored
+pub(crate) fn check_access_method(conn: &PgConnection) -> Result<(), StoreError> {
+    if COLUMNAR_TABLES.is_empty() {
+        return Ok(());
+    }
+
+    let query = format!(
+        "create table __graph_node_columnar_check(vid int8) using {method};
+         create index on __graph_node_columnar_check(vid);
+         insert into __graph_node_columnar_check values (1);
+         update __graph_node_columnar_check set vid = 2;
+         delete from __graph_node_columnar_check;",
+        method = ACCESS_METHOD.as_str()
+    );
+    // Always roll back so that the table goes away again
+    let result = conn.transaction::<(), _, _>(|| {
+        conn.batch_execute(&query)?;
+        Err(DieselError::RollbackTransaction)
+    });
+    match result {
+        Ok(()) | Err(DieselError::RollbackTransaction) => Ok(()),
+        Err(e) => Err(StoreError::Unknown(anyhow!(
+            "the table access method `{}` from GRAPH_COLUMNAR_ACCESS_METHOD can not \
+             be used for columnar mirrors: {}",
+            ACCESS_METHOD.as_str(),
+            e
+        ))),
+    }
+}
+
+/// Forget which mirrors of the deployment in `namespace` exist, for
+/// example, because the deployment was dropped
+pub(crate) fn forget_mirrors(namespace: &Namespace) {
+    let prefix = format!("\"{}\".", namespace);
+    MIRRORS
+        .lock()
+        .unwrap()
+        .retain(|mirror| !mirror.starts_with(&prefix));
+}
+
+/// Drop the mirrors of all columnar tables in `layout` so that they get
+/// recreated from the tables when the next block is processed
+pub(crate) fn drop_mirrors(conn: &PgConnection, layout: &Layout) -> Result<(), StoreError> {
+    for table in layout.tables.values().filter(|table| table.is_columnar) {
+        let query = format!("drop table if exists {}", mirror_name(layout, table));
+        conn.batch_execute(&query)?;
+    }
+    forget_mirrors(&layout.catalog.namespace);
+    Ok(())
+}
+
+/// Make sure the mirror for `table` exists. If it does not, create it and
+/// copy all versions from `table` into it. Return `true` if the mirror was
+/// created
+fn ensure_mirror(conn: &PgConnection, mirror: &str, table: &Table) -> Result<bool, StoreError> {
+    #[derive(QueryableByName)]
+    struct Exists {
+        #[sql_type = "Bool"]
+        exists: bool,
+    }
+
+    if MIRRORS.lock().unwrap().contains(mirror) {
+        return Ok(false);
+    }
+
+    let exists = diesel::sql_query("select to_regclass($1) is not null as exists")
+        .bind::<Text, _>(mirror)
+        .get_result::<Exists>(conn)?
+        .exists;
+    if !exists {
+        let query = format!(
+            "create table {mirror} (like {table}) using {method}",
+            mirror = mirror,
+            table = table.qualified_name.as_str(),
+            method = ACCESS_METHOD.as_str()
+        );
+        diesel::sql_query(query).execute(conn)?;
+        let query = format!(
+            "insert into {mirror} select * from {table}",
+            mirror = mirror,
+            table = table.qualified_name.as_str()
+        );
+        diesel::sql_query(query).execute(conn)?;
+        // Updating and reverting blocks find versions in the mirror by `vid`
+        let query = format!("create index on {mirror}(vid)", mirror = mirror);
+        diesel::sql_query(query).execute(conn)?;
+    }
+    // Only remember mirrors that existed before; creating one might still
+    // get rolled back
+    if exists {
+        MIRRORS.lock().unwrap().insert(mirror.to_string());
+    }
+    Ok(!exists)
+}
+
+/// The mirror of `table` if it has one that we can read from
+pub fn mirror_for(
+    conn: &PgConnection,
+    layout: &Layout,
+    table: &Table,
+) -> Result<Option<String>, StoreError> {
+    #[derive(QueryableByName)]
+    struct Exists {
+        #[sql_type = "Bool"]
+        exists: bool,
+    }
+
+    if !table.is_columnar {
+        return Ok(None);
+    }
+    let mirror = mirror_name(layout, table);
+    if MIRRORS.lock().unwrap().contains(&mirror) {
+        return Ok(Some(mirror));
+    }
+    let exists = diesel::sql_query("select to_regclass($1) is not null as exists")
+        .bind::<Text, _>(&mirror)
+        .get_result::<Exists>(conn)?
+        .exists;
+    if exists {
+        MIRRORS.lock().unwrap().insert(mirror.clone());
+        Ok(Some(mirror))
+    } else {
+        Ok(None)
+    }
+}
+
+/// Append the versions of entities that were written at `block` to the
+/// mirrors of all columnar tables in `layout`, and copy the block ranges of
+/// the versions that were closed at `block`
+pub fn update(conn: &PgConnection, layout: &Layout, block: BlockNumber) -> Result<(), StoreError> {
+    for table in layout.tables.values().filter(|table| table.is_columnar) {
+        let mirror = mirror_name(layout, table);
+        if ensure_mirror(conn, &mirror, table)? {
+            // The mirror was just created from the table and already
+            // contains the versions for `block`
+            continue;
+        }
+        let query = format!(
+            "insert into {mirror} select * from {table} where lower({range}) = $1",
+            mirror = mirror,
+            table = table.qualified_name.as_str(),
+            range = BLOCK_RANGE_COLUMN
+        );
+        diesel::sql_query(query)
+            .bind::<BigInt, _>(block)
+            .execute(conn)?;
+
+        let query = format!(
+            "update {mirror} m set {range} = t.{range} \
+               from {table} t \
+              where m.vid = t.vid and upper_inf(m.{range}) and upper(t.{range}) = $1",
+            mirror = mirror,
+            table = table.qualified_name.as_str(),
+            range = BLOCK_RANGE_COLUMN
+        );
+        diesel::sql_query(query)
+            .bind::<BigInt, _>(block)
+            .execute(conn)?;
+    }
+    Ok(())
+}
+
+/// Remove all versions written at `block` or later from the mirrors of the
+/// columnar tables in `layout`, and reopen the versions that were closed at
+/// `block` or later. This must run after `block` was reverted in the tables
+pub fn revert(conn: &PgConnection, layout: &Layout, block: BlockNumber) -> Result<(), StoreError> {
+    for table in layout.tables.values().filter(|table| table.is_columnar) {
+        let mirror = mirror_name(layout, table);
+        if ensure_mirror(conn, &mirror, table)? {
+            continue;
+        }
+        let query = format!(
+            "delete from {mirror} where lower({range}) >= $1",
+            mirror = mirror,
+            range = BLOCK_RANGE_COLUMN
+        );
+        diesel::sql_query(query)
+            .bind::<BigInt, _>(block)
+            .execute(conn)?;
+
+        let query = format!(
+            "update {mirror} m set {range} = t.{range} \
+               from {table} t \
+              where m.vid = t.vid and upper(m.{range}) >= $1",
+            mirror = mirror,
+            table = table.qualified_name.as_str(),
+            range = BLOCK_RANGE_COLUMN
+        );
+        diesel::sql_query(query)
+            .bind::<BigInt, _>(block)
+            .execute(conn)?;
+    }
+    Ok(())
+}
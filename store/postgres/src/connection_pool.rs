@@ -1,17 +1,132 @@
 use diesel::pg::PgConnection;
 use diesel::r2d2::{self, event as e, ConnectionManager, HandleEvent, Pool};
+use diesel::result::{DatabaseErrorKind, Error as DieselError};
+use diesel::sql_types::Bool;
+use diesel::{sql_query, Connection, RunQueryDsl};
 
 use graph::prelude::*;
 
 use std::fmt;
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use std::{collections::HashMap, sync::RwLock};
 
+lazy_static::lazy_static! {
+    /// How long we keep retrying an operation that failed because the
+    /// database is failing over before we give up. Setting this to 0
+    /// turns retrying off
+    static ref FAILOVER_DEADLINE: Duration = std::env::var("GRAPH_STORE_FAILOVER_DEADLINE")
+        .ok()
+        .map(|s| {
+            Duration::from_secs(s.parse::<u64>().unwrap_or_else(|_| {
+                panic!("GRAPH_STORE_FAILOVER_DEADLINE must be a number, but is `{}`", s)
+            }))
+        })
+        .unwrap_or(Duration::from_secs(60));
+
+    /// How long operations on a shard fail immediately after we gave up
+    /// retrying an operation on that shard
+    static ref FAILOVER_COOLDOWN: Duration = std::env::var("GRAPH_STORE_FAILOVER_COOLDOWN")
+        .ok()
+        .map(|s| {
+            Duration::from_secs(s.parse::<u64>().unwrap_or_else(|_| {
+                panic!("GRAPH_STORE_FAILOVER_COOLDOWN must be a number, but is `{}`", s)
+            }))
+        })
+        .unwrap_or(Duration::from_secs(10));
+}
+
+/// How an error from a database operation relates to a failover
+#[derive(Debug, PartialEq)]
+enum Failover {
+    /// The error was certainly caused by the database going away
+    Yes,
+    /// The error has nothing to do with a failover
+    No,
+    /// The error might have been caused by a failover, e.g., because the
+    /// database refuses writes or terminated the connection, but we need
+    /// to check with the database to be sure
+    Maybe,
+}
+
+impl From<&DieselError> for Failover {
+    fn from(e: &DieselError) -> Self {
+        use DatabaseErrorKind::*;
+
+        match e {
+            DieselError::DatabaseError(UnableToSendCommand, _) => Failover::Yes,
+            DieselError::DatabaseError(UniqueViolation, _)
+            | DieselError::DatabaseError(ForeignKeyViolation, _)
+            | DieselError::DatabaseError(SerializationFailure, _) => Failover::No,
+            DieselError::DatabaseError(_, _) => Failover::Maybe,
+            _ => Failover::No,
+        }
+    }
+}
+
+/// Errors that can tell us about the underlying database error, if there
+/// is one
+pub(crate) trait AsDieselError {
+    fn as_diesel_error(&self) -> Option<&DieselError>;
+}
+
+impl AsDieselError for StoreError {
+    fn as_diesel_error(&self) -> Option<&DieselError> {
+        match self {
+            StoreError::Unknown(e) => e.downcast_ref::<DieselError>(),
+            _ => None,
+        }
+    }
+}
+
+impl AsDieselError for QueryExecutionError {
+    fn as_diesel_error(&self) -> Option<&DieselError> {
+        match self {
+            QueryExecutionError::StoreError(e) => e
+                .downcast_ref::<StoreError>()
+                .and_then(|e| e.as_diesel_error())
+                .or_else(|| e.downcast_ref::<DieselError>()),
+            _ => None,
+        }
+    }
+}
+
+/// Keeps track of whether a shard is considered unavailable. Once we gave
+/// up retrying an operation because of a failover, the breaker opens and
+/// all operations fail right away until the cooldown has passed, instead
+/// of each of them waiting for the full deadline
+#[derive(Default)]
+struct CircuitBreaker {
+    open_until: RwLock<Option<Instant>>,
+}
+
+impl CircuitBreaker {
+    fn is_open(&self) -> bool {
+        match *self.open_until.read().unwrap() {
+            Some(until) => Instant::now() < until,
+            None => false,
+        }
+    }
+
+    fn open(&self) {
+        *self.open_until.write().unwrap() = Some(Instant::now() + *FAILOVER_COOLDOWN);
+    }
+
+    fn close(&self) {
+        if self.open_until.read().unwrap().is_some() {
+            *self.open_until.write().unwrap() = None;
+        }
+    }
+}
+
 #[derive(Clone)]
 pub struct ConnectionPool {
     pool: Pool<ConnectionManager<PgConnection>>,
     pub(crate) wait_stats: PoolWaitStats,
+    shard: String,
+    pool_name: String,
+    postgres_url: String,
+    breaker: Arc<CircuitBreaker>,
 }
 
 struct ErrorHandler(Logger, Counter);
@@ -110,6 +225,10 @@ impl std::ops::Deref for ConnectionPool {
 }
 
 impl ConnectionPool {
+    pub(crate) fn shard(&self) -> &str {
+        &self.shard
+    }
+
     pub fn create(
         shard_name: &str,
         pool_name: &str,
@@ -165,6 +284,116 @@ impl ConnectionPool {
             .build(conn_manager)
             .unwrap();
         info!(logger_store, "Pool successfully connected to Postgres");
-        ConnectionPool { pool, wait_stats }
+        ConnectionPool {
+            pool,
+            wait_stats,
+            shard: shard_name.to_string(),
+            pool_name: pool_name.to_string(),
+            postgres_url,
+            breaker: Arc::new(CircuitBreaker::default()),
+        }
+    }
+
+    /// Check whether the database is failing over by connecting to it
+    /// afresh. The database is failing over if we can not connect to it,
+    /// or if this is the pool for the primary and the database is in
+    /// recovery, i.e., a standby that does not accept writes
+    fn is_failing_over(&self) -> bool {
+        #[derive(QueryableByName)]
+        struct Recovery {
+            #[sql_type = "Bool"]
+            in_recovery: bool,
+        }
+
+        let conn = match PgConnection::establish(&self.postgres_url) {
+            Ok(conn) => conn,
+            Err(_) => return true,
+        };
+        match sql_query("select pg_is_in_recovery() as in_recovery").get_result::<Recovery>(&conn) {
+            Ok(recovery) => self.pool_name == "main" && recovery.in_recovery,
+            Err(_) => true,
+        }
+    }
+
+    fn is_failover_error<E: AsDieselError>(&self, e: &E) -> bool {
+        match e.as_diesel_error().map(Failover::from) {
+            Some(Failover::Yes) => true,
+            Some(Failover::Maybe) => self.is_failing_over(),
+            Some(Failover::No) | None => false,
+        }
+    }
+
+    /// Run `f` and retry it with exponential backoff for as long as it
+    /// fails with an error that indicates that the database is failing
+    /// over, but no longer than `GRAPH_STORE_FAILOVER_DEADLINE`. Since `f`
+    /// might get run several times, it must be safe to do so, e.g., by
+    /// doing all its work in one transaction.
+    ///
+    /// Waiting between attempts blocks the current thread; this must
+    /// therefore only be called from blocking threads, e.g., inside
+    /// `graph::spawn_blocking_allow_panic`
+    pub(crate) fn with_failover_retry<T, E, F>(&self, logger: &Logger, mut f: F) -> Result<T, E>
+    where
+        E: fmt::Display + From<StoreError> + AsDieselError,
+        F: FnMut() -> Result<T, E>,
+    {
+        const MAX_BACKOFF: Duration = Duration::from_secs(5);
+
+        if self.breaker.is_open() {
+            return Err(StoreError::Unknown(anyhow!(
+                "database shard `{}` is unavailable since it is failing over",
+                self.shard
+            ))
+            .into());
+        }
+
+        let start = Instant::now();
+        let mut backoff = Duration::from_millis(100);
+        loop {
+            match f() {
+                Ok(value) => {
+                    self.breaker.close();
+                    return Ok(value);
+                }
+                Err(e) if self.is_failover_error(&e) => {
+                    if start.elapsed() + backoff > *FAILOVER_DEADLINE {
+                        self.breaker.open();
+                        return Err(e);
+                    }
+                    warn!(logger, "Database is failing over, retrying";
+                        "shard" => &self.shard,
+                        "error" => e.to_string(),
+                        "retry_ms" => backoff.as_millis());
+                    std::thread::sleep(backoff);
+                    backoff = (backoff * 2).min(MAX_BACKOFF);
+                }
+                Err(e) => return Err(e),
+            }
+        }
     }
 }
+
+#[test]
+fn failover_errors() {
+    use DatabaseErrorKind::*;
+
+    fn db_error(kind: DatabaseErrorKind) -> DieselError {
+        DieselError::DatabaseError(kind, Box::new(String::from("error")))
+    }
+
+    assert_eq!(
+        Failover::Yes,
+        Failover::from(&db_error(UnableToSendCommand))
+    );
+    assert_eq!(
+        Failover::Maybe,
+        Failover::from(&db_error(DatabaseErrorKind::__Unknown))
+    );
+    assert_eq!(Failover::No, Failover::from(&db_error(UniqueViolation)));
+    assert_eq!(Failover::No, Failover::from(&DieselError::NotFound));
+
+    let e = StoreError::from(db_error(UnableToSendCommand));
+    assert!(e.as_diesel_error().is_some());
+    let e = QueryExecutionError::from(db_error(UnableToSendCommand));
+    assert!(e.as_diesel_error().is_some());
+}
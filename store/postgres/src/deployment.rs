@@ -2,24 +2,32 @@
 //! into these methods must be for the shard that holds the actual
 //! deployment data and metadata
 use diesel::pg::PgConnection;
-use diesel::prelude::{ExpressionMethods, OptionalExtension, QueryDsl, RunQueryDsl};
+use diesel::prelude::{
+    BoolExpressionMethods, ExpressionMethods, OptionalExtension, QueryDsl, RunQueryDsl,
+};
 use diesel::{
-    dsl::{delete, insert_into, select, sql, update},
-    sql_types::Integer,
+    dsl::{delete, exists, insert_into, max, min, select, sql, update},
+    sql_types::BigInt,
 };
-use graph::data::subgraph::schema::SubgraphError;
+use graph::components::store::EntityType;
+use graph::components::subgraph::ProofOfIndexingVersion;
+use graph::data::subgraph::schema::{SkippedTrigger, SubgraphError};
 use graph::data::subgraph::{
     schema::{MetadataType, SubgraphManifestEntity},
     SubgraphFeature,
 };
 use graph::prelude::{
-    anyhow, bigdecimal::ToPrimitive, hex, web3::types::H256, BigDecimal, BlockNumber,
-    DeploymentState, EntityChange, EntityChangeOperation, EthereumBlockPointer, Schema, StoreError,
-    StoreEvent, SubgraphDeploymentId,
+    anyhow, bigdecimal::ToPrimitive, hex, serde_json, web3::types::H256, BigDecimal, BlockNumber,
+    DeploymentState, EntityChange, EntityChangeOperation, EthereumBlockPointer, HistoryGranularity,
+    LoggedEntityChange, Schema, StoreError, StoreEvent, SubgraphDeploymentId,
 };
 use stable_hash::crypto::SetHasher;
 use std::str::FromStr;
-use std::{collections::BTreeSet, convert::TryFrom, ops::Bound};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    convert::TryFrom,
+    ops::Bound,
+};
 
 use crate::block_range::BLOCK_RANGE_COLUMN;
 use graph::constraint_violation;
@@ -73,7 +81,14 @@ table! {
         reorg_count -> Integer,
         current_reorg_depth -> Integer,
         max_reorg_depth -> Integer,
-        block_range -> Range<Integer>,
+        block_range -> Range<BigInt>,
+        poi_version -> Integer,
+        poi_transition_version -> Nullable<Integer>,
+        history_granularity -> Nullable<Integer>,
+        history_coarse_below -> Nullable<Numeric>,
+        subscriptions_disabled -> Bool,
+        context -> Nullable<Text>,
+        history_pruned_below -> Nullable<Numeric>,
     }
 }
 
@@ -86,7 +101,7 @@ table! {
         block_hash -> Nullable<Binary>,
         handler -> Nullable<Text>,
         deterministic -> Bool,
-        block_range -> Range<Integer>,
+        block_range -> Range<BigInt>,
     }
 }
 
@@ -102,7 +117,7 @@ table! {
         ethereum_block_hash -> Binary,
         ethereum_block_number -> Numeric,
         deployment -> Text,
-        block_range -> Range<Integer>,
+        block_range -> Range<BigInt>,
     }
 }
 
@@ -117,7 +132,7 @@ table! {
         schema -> Text,
         data_sources -> Array<Text>,
         templates -> Nullable<Array<Text>>,
-        block_range -> Range<Integer>,
+        block_range -> Range<BigInt>,
     }
 }
 
@@ -130,7 +145,40 @@ table! {
         network -> Nullable<Text>,
         source -> Text,
         mapping -> Text,
-        block_range -> Range<Integer>,
+        block_range -> Range<BigInt>,
+    }
+}
+
+table! {
+    subgraphs.skipped_trigger (vid) {
+        vid -> BigInt,
+        subgraph_id -> Text,
+        block_hash -> Binary,
+        block_number -> BigInt,
+        transaction_hash -> Nullable<Binary>,
+        log_index -> Nullable<BigInt>,
+        handler -> Nullable<Text>,
+        message -> Text,
+        replayed_by -> Nullable<Text>,
+    }
+}
+
+table! {
+    subgraphs.entity_change_log (seq) {
+        seq -> BigInt,
+        subgraph_id -> Text,
+        block_number -> BigInt,
+        entity_type -> Text,
+        entity_id -> Text,
+        operation -> Text,
+    }
+}
+
+table! {
+    subgraphs.change_log_consumer (subgraph_id, consumer) {
+        subgraph_id -> Text,
+        consumer -> Text,
+        acked_seq -> BigInt,
     }
 }
 
@@ -228,6 +276,27 @@ pub fn manifest_info(
         .map(|schema| (schema, description, repository))
 }
 
+/// The versions of the proof of indexing that are computed for the
+/// deployment. The first entry is the version that is reported by default,
+/// the second one, if present, is the version the deployment is
+/// transitioning to
+pub fn poi_versions(
+    conn: &PgConnection,
+    id: &SubgraphDeploymentId,
+) -> Result<Vec<ProofOfIndexingVersion>, StoreError> {
+    use subgraph_deployment as d;
+
+    let (version, transition_version): (i32, Option<i32>) = d::table
+        .select((d::poi_version, d::poi_transition_version))
+        .filter(d::id.eq(id.as_str()))
+        .first(conn)?;
+    Some(version)
+        .into_iter()
+        .chain(transition_version)
+        .map(|version| ProofOfIndexingVersion::try_from(version).map_err(StoreError::from))
+        .collect()
+}
+
 pub fn features(
     conn: &PgConnection,
     id: &SubgraphDeploymentId,
@@ -277,6 +346,40 @@ pub fn forward_block_ptr(
         .map_err(|e| e.into())
 }
 
+/// Like `forward_block_ptr`, but only move the block pointer if it is
+/// before `ptr`. Returns `None` if the deployment has already processed the
+/// block. Since this is a single statement, it does not need to run in a
+/// transaction
+pub fn forward_block_ptr_if_behind(
+    conn: &PgConnection,
+    id: &SubgraphDeploymentId,
+    ptr: EthereumBlockPointer,
+) -> Result<Option<StoreEvent>, StoreError> {
+    use subgraph_deployment as d;
+
+    // Work around a Diesel issue with serializing BigDecimals to numeric
+    let number = format!("{}::numeric", ptr.number);
+
+    let count = update(
+        d::table.filter(d::id.eq(id.as_str())).filter(
+            d::latest_ethereum_block_number
+                .is_null()
+                .or(d::latest_ethereum_block_number.lt(sql(&number))),
+        ),
+    )
+    .set((
+        d::latest_ethereum_block_number.eq(sql(&number)),
+        d::latest_ethereum_block_hash.eq(ptr.hash.as_bytes()),
+        d::current_reorg_depth.eq(0),
+    ))
+    .execute(conn)?;
+    Ok(if count > 0 {
+        Some(block_ptr_store_event(id))
+    } else {
+        None
+    })
+}
+
 pub fn revert_block_ptr(
     conn: &PgConnection,
     id: &SubgraphDeploymentId,
@@ -335,7 +438,7 @@ fn convert_to_u32(number: Option<i32>, field: &str, subgraph: &str) -> Result<u3
 }
 
 /// Translate `latest` into a `BlockNumber`. If `latest` is `None` or does
-/// not represent an `i32`, return an error
+/// not represent an `i64`, return an error
 fn latest_as_block_number(
     latest: Option<BigDecimal>,
     subgraph: &str,
@@ -346,11 +449,11 @@ fn latest_as_block_number(
              a few blocks before querying it",
             subgraph
         ))),
-        Some(latest) => latest.to_i32().ok_or_else(|| {
+        Some(latest) => latest.to_i64().ok_or_else(|| {
             constraint_violation!(
                 "Subgraph `{}` has an \
                  invalid latest_ethereum_block_number `{:?}` that can not be \
-                 represented as an i32",
+                 represented as an i64",
                 subgraph,
                 latest
             )
@@ -368,31 +471,212 @@ pub fn state(conn: &PgConnection, id: SubgraphDeploymentId) -> Result<Deployment
             d::reorg_count,
             d::max_reorg_depth,
             d::latest_ethereum_block_number,
+            d::history_granularity,
+            d::history_coarse_below,
+            d::history_pruned_below,
+            d::subscriptions_disabled,
         ))
-        .first::<(String, i32, i32, Option<BigDecimal>)>(conn)
+        .first::<(
+            String,
+            i32,
+            i32,
+            Option<BigDecimal>,
+            Option<i32>,
+            Option<BigDecimal>,
+            Option<BigDecimal>,
+            bool,
+        )>(conn)
         .optional()?
     {
         None => Err(StoreError::QueryExecutionError(format!(
             "No data found for subgraph {}",
             id
         ))),
-        Some((_, reorg_count, max_reorg_depth, latest_ethereum_block_number)) => {
+        Some((
+            _,
+            reorg_count,
+            max_reorg_depth,
+            latest_ethereum_block_number,
+            history_granularity,
+            history_coarse_below,
+            history_pruned_below,
+            subscriptions_disabled,
+        )) => {
             let reorg_count = convert_to_u32(Some(reorg_count), "reorg_count", id.as_str())?;
             let max_reorg_depth =
                 convert_to_u32(Some(max_reorg_depth), "max_reorg_depth", id.as_str())?;
             let latest_ethereum_block_number =
                 latest_as_block_number(latest_ethereum_block_number, id.as_str())?;
+            let history =
+                history_granularity_from(history_granularity, history_coarse_below, id.as_str())?;
+            let history_pruned_below = history_pruned_below
+                .map(|block| {
+                    block.to_i64().ok_or_else(|| {
+                        constraint_violation!(
+                            "Subgraph `{}` has an invalid history_pruned_below `{}` \
+                             that can not be represented as an i64",
+                            id,
+                            block
+                        )
+                    })
+                })
+                .transpose()?;
 
             Ok(DeploymentState {
                 id,
                 reorg_count,
                 max_reorg_depth,
                 latest_ethereum_block_number,
+                history,
+                history_pruned_below,
+                subscriptions_disabled,
             })
         }
     }
 }
 
+fn history_granularity_from(
+    granularity: Option<i32>,
+    coarse_below: Option<BigDecimal>,
+    subgraph: &str,
+) -> Result<Option<HistoryGranularity>, StoreError> {
+    match (granularity, coarse_below) {
+        (Some(granularity), Some(coarse_below)) if granularity > 0 => {
+            let coarse_below = coarse_below.to_i64().ok_or_else(|| {
+                constraint_violation!(
+                    "Subgraph `{}` has an invalid history_coarse_below `{}` \
+                     that can not be represented as an i64",
+                    subgraph,
+                    coarse_below
+                )
+            })?;
+            Ok(Some(HistoryGranularity {
+                granularity: granularity as BlockNumber,
+                coarse_below,
+            }))
+        }
+        (None, None) => Ok(None),
+        (granularity, coarse_below) => Err(constraint_violation!(
+            "Subgraph `{}` has an invalid history granularity {:?} before block {:?}",
+            subgraph,
+            granularity,
+            coarse_below
+        )),
+    }
+}
+
+/// The coarser history that the deployment `id` keeps, if any
+pub fn history_granularity(
+    conn: &PgConnection,
+    id: &SubgraphDeploymentId,
+) -> Result<Option<HistoryGranularity>, StoreError> {
+    use subgraph_deployment as d;
+
+    let (granularity, coarse_below): (Option<i32>, Option<BigDecimal>) = d::table
+        .select((d::history_granularity, d::history_coarse_below))
+        .filter(d::id.eq(id.as_str()))
+        .first(conn)?;
+    history_granularity_from(granularity, coarse_below, id.as_str())
+}
+
+/// Record that the deployment `id` now keeps only the coarser history
+/// described by `history`
+pub fn set_history_granularity(
+    conn: &PgConnection,
+    id: &SubgraphDeploymentId,
+    history: HistoryGranularity,
+) -> Result<(), StoreError> {
+    use subgraph_deployment as d;
+
+    update(d::table.filter(d::id.eq(id.as_str())))
+        .set((
+            d::history_granularity.eq(history.granularity as i32),
+            d::history_coarse_below.eq(BigDecimal::from(history.coarse_below)),
+        ))
+        .execute(conn)?;
+    Ok(())
+}
+
+/// Record that versions of entities of the deployment `id` that were
+/// replaced before `block` have been deleted
+pub fn set_history_pruned_below(
+    conn: &PgConnection,
+    id: &SubgraphDeploymentId,
+    block: BlockNumber,
+) -> Result<(), StoreError> {
+    use subgraph_deployment as d;
+
+    update(d::table.filter(d::id.eq(id.as_str())))
+        .set(d::history_pruned_below.eq(BigDecimal::from(block)))
+        .execute(conn)?;
+    Ok(())
+}
+
+/// Turn new subscriptions to the deployment `id` away if `disabled` is
+/// `true`, and allow them again otherwise
+pub fn set_subscriptions_disabled(
+    conn: &PgConnection,
+    id: &SubgraphDeploymentId,
+    disabled: bool,
+) -> Result<(), StoreError> {
+    use subgraph_deployment as d;
+
+    let count = update(d::table.filter(d::id.eq(id.as_str())))
+        .set(d::subscriptions_disabled.eq(disabled))
+        .execute(conn)?;
+    if count == 0 {
+        return Err(StoreError::DeploymentNotFound(id.to_string()));
+    }
+    Ok(())
+}
+
+/// The key/value pairs that operators set for the deployment `id` and
+/// that its mappings can read
+pub fn context(
+    conn: &PgConnection,
+    id: &SubgraphDeploymentId,
+) -> Result<BTreeMap<String, String>, StoreError> {
+    use subgraph_deployment as d;
+
+    let context = d::table
+        .select(d::context)
+        .filter(d::id.eq(id.as_str()))
+        .first::<Option<String>>(conn)
+        .optional()?
+        .ok_or_else(|| StoreError::DeploymentNotFound(id.to_string()))?;
+    match context {
+        Some(context) => serde_json::from_str(&context)
+            .map_err(|e| constraint_violation!("invalid context for deployment {}: {}", id, e)),
+        None => Ok(BTreeMap::new()),
+    }
+}
+
+/// Set the context entry `key` of the deployment `id` to `value`, or
+/// remove it if `value` is `None`
+pub fn set_context_value(
+    conn: &PgConnection,
+    id: &SubgraphDeploymentId,
+    key: String,
+    value: Option<String>,
+) -> Result<(), StoreError> {
+    use subgraph_deployment as d;
+
+    let mut context = self::context(conn, id)?;
+    match value {
+        Some(value) => context.insert(key, value),
+        None => context.remove(&key),
+    };
+    let context = if context.is_empty() {
+        None
+    } else {
+        Some(serde_json::to_string(&context)?)
+    };
+    update(d::table.filter(d::id.eq(id.as_str())))
+        .set(d::context.eq(context))
+        .execute(conn)?;
+    Ok(())
+}
+
 /// Mark the deployment `id` as synced
 pub fn set_synced(conn: &PgConnection, id: &SubgraphDeploymentId) -> Result<(), StoreError> {
     use subgraph_deployment as d;
@@ -511,7 +795,7 @@ pub(crate) fn has_non_fatal_errors(
             .filter(
                 sql("block_range @> ")
                     .bind(block.single_value())
-                    .sql("::int"),
+                    .sql("::int8"),
             ),
     ))
     .get_result(conn)
@@ -558,7 +842,7 @@ pub(crate) fn insert_subgraph_errors(
     check_health(conn, id)
 }
 
-#[cfg(debug_assertions)]
+#[cfg(any(debug_assertions, feature = "testing"))]
 pub(crate) fn error_count(
     conn: &PgConnection,
     id: &SubgraphDeploymentId,
@@ -607,13 +891,341 @@ pub(crate) fn revert_subgraph_errors(
     delete(
         e::table
             .filter(e::subgraph_id.eq(id.as_str()))
-            .filter(sql(&lower_geq).bind::<Integer, _>(reverted_block)),
+            .filter(sql(&lower_geq).bind::<BigInt, _>(reverted_block)),
     )
     .execute(conn)?;
 
     check_health(conn, id)
 }
 
+/// Add the `triggers` to the dead-letter table
+pub(crate) fn insert_skipped_triggers(
+    conn: &PgConnection,
+    triggers: Vec<SkippedTrigger>,
+) -> Result<(), StoreError> {
+    use skipped_trigger as st;
+
+    let rows = triggers
+        .into_iter()
+        .map(|trigger| {
+            (
+                st::subgraph_id.eq(trigger.subgraph_id.to_string()),
+                st::block_hash.eq(trigger.block_ptr.hash.as_bytes().to_vec()),
+                st::block_number.eq(crate::block_range::block_number(&trigger.block_ptr)),
+                st::transaction_hash.eq(trigger
+                    .transaction_hash
+                    .map(|hash| hash.as_bytes().to_vec())),
+                st::log_index.eq(trigger.log_index.map(|index| index as i64)),
+                st::handler.eq(trigger.handler),
+                st::message.eq(trigger.message),
+            )
+        })
+        .collect::<Vec<_>>();
+
+    insert_into(st::table).values(rows).execute(conn)?;
+    Ok(())
+}
+
+/// Return all skipped triggers for the deployment `id`, ordered by the
+/// block in which they happened
+pub(crate) fn skipped_triggers(
+    conn: &PgConnection,
+    id: &SubgraphDeploymentId,
+) -> Result<Vec<SkippedTrigger>, StoreError> {
+    use skipped_trigger as st;
+
+    type Row = (
+        String,
+        Vec<u8>,
+        i64,
+        Option<Vec<u8>>,
+        Option<i64>,
+        Option<String>,
+        String,
+        Option<String>,
+    );
+
+    st::table
+        .filter(st::subgraph_id.eq(id.as_str()))
+        .order_by((st::block_number, st::vid))
+        .select((
+            st::subgraph_id,
+            st::block_hash,
+            st::block_number,
+            st::transaction_hash,
+            st::log_index,
+            st::handler,
+            st::message,
+            st::replayed_by,
+        ))
+        .load::<Row>(conn)?
+        .into_iter()
+        .map(
+            |(subgraph_id, hash, number, tx, log_index, handler, message, replayed_by)|
+             -> Result<_, StoreError> {
+                let replayed_by = replayed_by
+                    .map(SubgraphDeploymentId::new)
+                    .transpose()
+                    .map_err(|id| {
+                        constraint_violation!("invalid deployment id `{}` in replayed_by", id)
+                    })?;
+                Ok(SkippedTrigger {
+                    subgraph_id: SubgraphDeploymentId::new(subgraph_id).map_err(|id| {
+                        constraint_violation!("invalid deployment id `{}` in skipped_trigger", id)
+                    })?,
+                    block_ptr: EthereumBlockPointer::from((H256::from_slice(&hash), number)),
+                    transaction_hash: tx.map(|tx| H256::from_slice(&tx)),
+                    log_index: log_index.map(|index| index as u64),
+                    handler,
+                    message,
+                    replayed_by,
+                })
+            },
+        )
+        .collect()
+}
+
+/// Mark all skipped triggers of `id` after `graft_block` that have not been
+/// replayed yet as replayed by the deployment `replayed_by`. Returns the
+/// number of triggers that were marked
+pub(crate) fn mark_skipped_triggers_replayed(
+    conn: &PgConnection,
+    id: &SubgraphDeploymentId,
+    graft_block: BlockNumber,
+    replayed_by: &SubgraphDeploymentId,
+) -> Result<usize, StoreError> {
+    use skipped_trigger as st;
+
+    update(
+        st::table
+            .filter(st::subgraph_id.eq(id.as_str()))
+            .filter(st::block_number.gt(graft_block))
+            .filter(st::replayed_by.is_null()),
+    )
+    .set(st::replayed_by.eq(replayed_by.as_str()))
+    .execute(conn)
+    .map_err(|e| e.into())
+}
+
+/// Remove the skipped triggers for blocks that are being reverted
+pub(crate) fn revert_skipped_triggers(
+    conn: &PgConnection,
+    id: &SubgraphDeploymentId,
+    reverted_block: BlockNumber,
+) -> Result<(), StoreError> {
+    use skipped_trigger as st;
+
+    delete(
+        st::table
+            .filter(st::subgraph_id.eq(id.as_str()))
+            .filter(st::block_number.ge(reverted_block)),
+    )
+    .execute(conn)?;
+    Ok(())
+}
+
+/// Record the changes to data entities in `event` in the change log of
+/// `id` as having happened at `block`. Changes are only recorded if the
+/// deployment has consumers for its change log
+pub(crate) fn log_entity_changes(
+    conn: &PgConnection,
+    id: &SubgraphDeploymentId,
+    block: BlockNumber,
+    event: &StoreEvent,
+) -> Result<(), StoreError> {
+    use change_log_consumer as cc;
+    use entity_change_log as cl;
+
+    let has_consumers = select(exists(cc::table.filter(cc::subgraph_id.eq(id.as_str()))))
+        .get_result::<bool>(conn)?;
+    if !has_consumers {
+        return Ok(());
+    }
+
+    let rows = event
+        .changes
+        .iter()
+        .filter(|change| change.entity_type.is_data_type())
+        .map(|change| {
+            let operation = match change.operation {
+                EntityChangeOperation::Set => "set",
+                EntityChangeOperation::Removed => "removed",
+            };
+            (
+                cl::subgraph_id.eq(id.as_str()),
+                cl::block_number.eq(block),
+                cl::entity_type.eq(change.entity_type.as_str()),
+                cl::entity_id.eq(change.entity_id.as_str()),
+                cl::operation.eq(operation),
+            )
+        })
+        .collect::<Vec<_>>();
+    if !rows.is_empty() {
+        insert_into(cl::table).values(rows).execute(conn)?;
+    }
+    Ok(())
+}
+
+/// Register `consumer` for the change log of `id` and return the position
+/// up to which it has acknowledged changes. A new consumer starts at the
+/// end of the log
+pub(crate) fn register_change_log_consumer(
+    conn: &PgConnection,
+    id: &SubgraphDeploymentId,
+    consumer: &str,
+) -> Result<i64, StoreError> {
+    use change_log_consumer as cc;
+    use entity_change_log as cl;
+
+    if let Some(acked) = acked_seq(conn, id, consumer)? {
+        return Ok(acked);
+    }
+    let acked = cl::table
+        .filter(cl::subgraph_id.eq(id.as_str()))
+        .select(max(cl::seq))
+        .get_result::<Option<i64>>(conn)?
+        .unwrap_or(0);
+    insert_into(cc::table)
+        .values((
+            cc::subgraph_id.eq(id.as_str()),
+            cc::consumer.eq(consumer),
+            cc::acked_seq.eq(acked),
+        ))
+        .execute(conn)?;
+    Ok(acked)
+}
+
+/// Remove `consumer` from the consumers of the change log of `id`
+pub(crate) fn remove_change_log_consumer(
+    conn: &PgConnection,
+    id: &SubgraphDeploymentId,
+    consumer: &str,
+) -> Result<(), StoreError> {
+    use change_log_consumer as cc;
+
+    delete(
+        cc::table
+            .filter(cc::subgraph_id.eq(id.as_str()))
+            .filter(cc::consumer.eq(consumer)),
+    )
+    .execute(conn)?;
+    trim_change_log(conn, id)
+}
+
+fn acked_seq(
+    conn: &PgConnection,
+    id: &SubgraphDeploymentId,
+    consumer: &str,
+) -> Result<Option<i64>, StoreError> {
+    use change_log_consumer as cc;
+
+    cc::table
+        .filter(cc::subgraph_id.eq(id.as_str()))
+        .filter(cc::consumer.eq(consumer))
+        .select(cc::acked_seq)
+        .first::<i64>(conn)
+        .optional()
+        .map_err(|e| e.into())
+}
+
+fn unknown_consumer(id: &SubgraphDeploymentId, consumer: &str) -> StoreError {
+    StoreError::Unknown(anyhow!(
+        "`{}` is not a consumer of the change log of deployment `{}`",
+        consumer,
+        id
+    ))
+}
+
+/// Return up to `limit` changes in the change log of `id` that `consumer`
+/// has not acknowledged yet
+pub(crate) fn entity_changes(
+    conn: &PgConnection,
+    id: &SubgraphDeploymentId,
+    consumer: &str,
+    limit: usize,
+) -> Result<Vec<LoggedEntityChange>, StoreError> {
+    use entity_change_log as cl;
+
+    let acked = acked_seq(conn, id, consumer)?.ok_or_else(|| unknown_consumer(id, consumer))?;
+    cl::table
+        .filter(cl::subgraph_id.eq(id.as_str()))
+        .filter(cl::seq.gt(acked))
+        .order_by(cl::seq)
+        .limit(limit as i64)
+        .select((
+            cl::seq,
+            cl::block_number,
+            cl::entity_type,
+            cl::entity_id,
+            cl::operation,
+        ))
+        .load::<(i64, i64, String, String, String)>(conn)?
+        .into_iter()
+        .map(|(seq, block, entity_type, entity_id, operation)| {
+            let operation = match operation.as_str() {
+                "set" => EntityChangeOperation::Set,
+                "removed" => EntityChangeOperation::Removed,
+                _ => {
+                    return Err(constraint_violation!(
+                        "invalid operation `{}` in entity_change_log",
+                        operation
+                    ))
+                }
+            };
+            Ok(LoggedEntityChange {
+                seq,
+                block,
+                change: EntityChange {
+                    subgraph_id: id.clone(),
+                    entity_type: EntityType::data(entity_type),
+                    entity_id,
+                    operation,
+                },
+            })
+        })
+        .collect()
+}
+
+/// Mark the changes in the change log of `id` up to `seq` as acknowledged
+/// by `consumer`, and remove the changes that all consumers acknowledged
+pub(crate) fn ack_entity_changes(
+    conn: &PgConnection,
+    id: &SubgraphDeploymentId,
+    consumer: &str,
+    seq: i64,
+) -> Result<(), StoreError> {
+    use change_log_consumer as cc;
+
+    acked_seq(conn, id, consumer)?.ok_or_else(|| unknown_consumer(id, consumer))?;
+    update(
+        cc::table
+            .filter(cc::subgraph_id.eq(id.as_str()))
+            .filter(cc::consumer.eq(consumer))
+            .filter(cc::acked_seq.lt(seq)),
+    )
+    .set(cc::acked_seq.eq(seq))
+    .execute(conn)?;
+    trim_change_log(conn, id)
+}
+
+/// Remove the changes from the change log of `id` that all its consumers
+/// have acknowledged
+fn trim_change_log(conn: &PgConnection, id: &SubgraphDeploymentId) -> Result<(), StoreError> {
+    use change_log_consumer as cc;
+    use entity_change_log as cl;
+
+    let acked = cc::table
+        .filter(cc::subgraph_id.eq(id.as_str()))
+        .select(min(cc::acked_seq))
+        .get_result::<Option<i64>>(conn)?;
+    let changes = cl::table.filter(cl::subgraph_id.eq(id.as_str()));
+    match acked {
+        Some(acked) => delete(changes.filter(cl::seq.le(acked))).execute(conn)?,
+        None => delete(changes).execute(conn)?,
+    };
+    Ok(())
+}
+
 /// Drop the schema `namespace`. This deletes all data for the subgraph,
 /// and can not be reversed. It does not remove any of the metadata
 /// in the `subgraphs` schema for the deployment
@@ -3,7 +3,10 @@ use diesel::connection::SimpleConnection;
 use diesel::pg::PgConnection;
 use diesel::prelude::*;
 use diesel::r2d2::{ConnectionManager, PooledConnection};
+use futures::sync::mpsc::channel;
+use futures::{Future as _, Stream as _};
 use futures03::FutureExt as _;
+use graph::components::server::index_node::StorageLayout;
 use graph::components::store::{EntityType, StoredDynamicDataSource};
 use graph::data::subgraph::status;
 use graph::prelude::{
@@ -13,37 +16,48 @@ use graph::prelude::{
 use lazy_static::lazy_static;
 use lru_time_cache::LruCache;
 use rand::{seq::SliceRandom, thread_rng};
+use std::cell::{Cell, RefCell};
 use std::convert::TryInto;
 use std::iter::FromIterator;
 use std::ops::Deref;
 use std::sync::{atomic::AtomicUsize, Arc, Mutex};
 use std::time::Instant;
 use std::{
-    collections::{BTreeMap, HashMap},
+    collections::{BTreeMap, HashMap, HashSet},
+    fmt,
     time::Duration,
 };
 use tokio::sync::Semaphore;
 
+use graph::components::ethereum::REORG_THRESHOLD;
 use graph::components::store::EntityCollection;
-use graph::components::subgraph::ProofOfIndexingFinisher;
-use graph::data::subgraph::schema::{SubgraphError, POI_OBJECT};
+use graph::components::subgraph::{ProofOfIndexingFinisher, ProofOfIndexingVersion};
+use graph::data::schema::{SNAPSHOT_BLOCK_FIELD, SNAPSHOT_OF_FIELD};
+use graph::data::subgraph::schema::{SkippedTrigger, SubgraphError, POI_OBJECT};
 use graph::prelude::{
-    anyhow, debug, futures03, info, o, tokio, web3, ApiSchema, BlockNumber, CheapClone,
-    DeploymentState, DynTryFuture, Entity, EntityKey, EntityModification, EntityOrder, EntityQuery,
-    EntityRange, Error, EthereumBlockPointer, Logger, MetadataOperation, MetricsRegistry,
-    QueryExecutionError, Schema, StopwatchMetrics, StoreError, StoreEvent, SubgraphDeploymentId,
-    Value, BLOCK_NUMBER_MAX,
+    anyhow, debug, futures03, info, o, tokio, web3, AggregateRow, ApiSchema, BlockNumber,
+    CheapClone, DeploymentState, DynTryFuture, Entity, EntityAggregationQuery, EntityKey,
+    EntityModification, EntityOrder, EntityQuery, EntityRange, EntityStream, Error,
+    EthereumBlockPointer, HistogramOpts, HistogramVec, LoggedEntityChange, Logger,
+    MetadataOperation, MetricsRegistry, QueryExecutionError, QueryPlan, Schema, StopwatchMetrics,
+    StoreError, StoreEvent, SubgraphDeploymentId, Value, BLOCK_NUMBER_MAX,
 };
 
 use graph_graphql::prelude::api_schema;
 use web3::types::Address;
 
+use crate::block_range::block_number;
+use crate::connection_pool::{AsDieselError, ConnectionPool};
 use crate::primary::Site;
 use crate::relational::{Layout, METADATA_LAYOUT};
 use crate::relational_queries::FromEntityData;
-use crate::{connection_pool::ConnectionPool, detail, entities as e};
+use crate::{catalog, detail, entities as e};
 use crate::{deployment, primary::Namespace};
 
+/// The number of entities that `find_stream` reads from the database at a
+/// time
+const STREAM_BATCH_SIZE: usize = 1000;
+
 lazy_static! {
     static ref CONNECTION_LIMITER: Semaphore = {
         let db_conn_pool_size = std::env::var("STORE_CONNECTION_POOL_SIZE")
@@ -53,6 +67,79 @@ lazy_static! {
 
         Semaphore::new(db_conn_pool_size)
     };
+
+    /// How often, in blocks, to delete the entities with a `@ttl` that
+    /// have expired and to check the limits for the size of the history
+    static ref TTL_PRUNE_INTERVAL: BlockNumber = std::env::var("GRAPH_TTL_PRUNE_INTERVAL")
+        .unwrap_or("1000".into())
+        .parse::<BlockNumber>()
+        .ok()
+        .filter(|interval| *interval > 0)
+        .expect("invalid GRAPH_TTL_PRUNE_INTERVAL");
+
+    /// While a deployment is syncing, only write the block pointer of
+    /// blocks that change nothing to the database for every block whose
+    /// number is a multiple of this; the block pointer of other such
+    /// blocks is only kept in memory
+    static ref BLOCK_PTR_STRIDE: BlockNumber = std::env::var("GRAPH_BLOCK_PTR_STRIDE")
+        .unwrap_or("1".into())
+        .parse::<BlockNumber>()
+        .ok()
+        .filter(|stride| *stride > 0)
+        .expect("invalid GRAPH_BLOCK_PTR_STRIDE");
+
+    /// The longest that the SQL statement for an `EntityQuery` may run
+    /// before the database cancels it
+    static ref STATEMENT_TIMEOUT: Option<Duration> = std::env::var("GRAPH_SQL_STATEMENT_TIMEOUT")
+        .ok()
+        .map(|s| s.parse::<u64>().expect("invalid GRAPH_SQL_STATEMENT_TIMEOUT"))
+        .map(Duration::from_secs);
+
+    /// Statement timeouts for individual deployments that take the place
+    /// of `STATEMENT_TIMEOUT`
+    static ref DEPLOYMENT_STATEMENT_TIMEOUTS: HashMap<SubgraphDeploymentId, Duration> =
+        std::env::var("GRAPH_SQL_STATEMENT_TIMEOUT_DEPLOYMENTS")
+            .ok()
+            .map(|s| {
+                parse_statement_timeouts(&s).expect("invalid GRAPH_SQL_STATEMENT_TIMEOUT_DEPLOYMENTS")
+            })
+            .unwrap_or_default();
+}
+
+/// Parse a comma-separated list of `<deployment>=<seconds>` entries
+fn parse_statement_timeouts(s: &str) -> Result<HashMap<SubgraphDeploymentId, Duration>, String> {
+    s.split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .map(|entry| {
+            let mut parts = entry.splitn(2, '=');
+            let deployment = parts.next().unwrap_or("").trim();
+            let secs = parts.next().unwrap_or("").trim();
+            let deployment = SubgraphDeploymentId::new(deployment)
+                .map_err(|id| format!("invalid deployment id `{}`", id))?;
+            let secs = secs
+                .parse::<u64>()
+                .map_err(|_| format!("invalid timeout `{}` for {}", secs, deployment))?;
+            Ok((deployment, Duration::from_secs(secs)))
+        })
+        .collect()
+}
+
+/// The timeout for the SQL statement of a query against `deployment` that
+/// asks for `timeout`; the query gets the shorter of its own and the
+/// configured timeout
+fn statement_timeout(
+    deployment: &SubgraphDeploymentId,
+    timeout: Option<Duration>,
+) -> Option<Duration> {
+    let configured = DEPLOYMENT_STATEMENT_TIMEOUTS
+        .get(deployment)
+        .cloned()
+        .or(*STATEMENT_TIMEOUT);
+    match (timeout, configured) {
+        (Some(timeout), Some(configured)) => Some(timeout.min(configured)),
+        (timeout, configured) => timeout.or(configured),
+    }
 }
 
 embed_migrations!("./migrations");
@@ -172,6 +259,22 @@ pub struct StoreInner {
     pub(crate) layout_cache: e::LayoutCache,
 
     registry: Arc<dyn MetricsRegistry>,
+
+    /// Timing of entity queries by the fingerprint of the generated SQL
+    query_timing: Box<HistogramVec>,
+
+    /// The block pointers of deployments that have moved past the block
+    /// pointer in the database with blocks that changed nothing; see
+    /// `BLOCK_PTR_STRIDE`
+    pending_block_ptrs: Mutex<HashMap<SubgraphDeploymentId, PendingBlockPtr>>,
+}
+
+/// A block pointer that has not been written to the database yet. All
+/// blocks after `persisted` up to and including `ptr` changed nothing
+#[derive(Clone, Debug)]
+struct PendingBlockPtr {
+    persisted: Option<EthereumBlockPointer>,
+    ptr: EthereumBlockPointer,
 }
 
 /// Storage of the data for individual deployments. Each `DeploymentStore`
@@ -202,6 +305,11 @@ impl DeploymentStore {
         // Create the entities table (if necessary)
         initiate_schema(&logger, &pool.get().unwrap(), &pool.get().unwrap());
 
+        // Fail early if we can not maintain columnar mirrors
+        if let Err(e) = crate::columnar::check_access_method(&pool.get().unwrap()) {
+            panic!("Error setting up columnar mirrors: {}", e)
+        }
+
         // Create a list of replicas with repetitions according to the weights
         // and shuffle the resulting list. Any missing weights in the list
         // default to 1
@@ -223,6 +331,25 @@ impl DeploymentStore {
         replica_order.shuffle(&mut rng);
         debug!(logger, "Using postgres host order {:?}", replica_order);
 
+        let query_timing = {
+            let mut labels = HashMap::new();
+            labels.insert("shard".to_string(), pool.shard().to_string());
+            let opts = HistogramOpts::new(
+                "store_query_duration",
+                "Time spent running entity queries, by fingerprint of the generated SQL",
+            )
+            .const_labels(labels)
+            .buckets(vec![
+                0.001, 0.005, 0.01, 0.05, 0.1, 0.5, 1.0, 5.0, 10.0, 30.0,
+            ]);
+            let timing = Box::new(
+                HistogramVec::new(opts, &["namespace", "entity", "fingerprint"])
+                    .expect("failed to create `store_query_duration` histogram"),
+            );
+            registry.register("store_query_duration", timing.clone());
+            timing
+        };
+
         // Create the store
         let store = StoreInner {
             logger: logger.clone(),
@@ -233,6 +360,8 @@ impl DeploymentStore {
             subgraph_cache: Mutex::new(LruCache::with_capacity(100)),
             layout_cache: e::make_layout_cache(),
             registry,
+            query_timing,
+            pending_block_ptrs: Mutex::new(HashMap::new()),
         };
         let store = DeploymentStore(Arc::new(store));
 
@@ -259,6 +388,16 @@ impl DeploymentStore {
         );
         econn.transaction(|| -> Result<_, StoreError> {
             let exists = deployment::exists(&econn.conn, &site.deployment)?;
+            let has_schema = catalog::has_namespace(&econn.conn, &site.namespace)?;
+
+            // An earlier attempt to create the deployment might have failed
+            // half-way. A database schema without metadata can't have any
+            // data in it that we care about, and we start over with it
+            if has_schema && !exists {
+                econn
+                    .conn
+                    .batch_execute(&format!("drop schema {} cascade", site.namespace))?;
+            }
 
             let event = if replace || !exists {
                 let ops = deployment.create_operations(&site.deployment);
@@ -267,7 +406,7 @@ impl DeploymentStore {
                 StoreEvent::new(vec![])
             };
 
-            if !exists {
+            if !exists || !has_schema {
                 econn.create_schema(site.namespace.clone(), schema, graft_site)?;
             }
             Ok(event)
@@ -278,7 +417,13 @@ impl DeploymentStore {
     // is not reversible
     pub(crate) fn drop_deployment(&self, site: &Site) -> Result<(), StoreError> {
         let conn = self.get_conn()?;
-        conn.transaction(|| e::Connection::drop_deployment(&conn, site))
+        conn.transaction(|| e::Connection::drop_deployment(&conn, site))?;
+
+        // The deployment might get recreated with the same id, and cached
+        // information about it must not outlive it
+        self.layout_cache.lock().unwrap().remove(&site.deployment);
+        self.subgraph_cache.lock().unwrap().remove(&site.deployment);
+        Ok(())
     }
 
     /// Gets an entity from Postgres.
@@ -306,17 +451,38 @@ impl DeploymentStore {
         conn: &e::Connection,
         query: EntityQuery,
     ) -> Result<Vec<T>, QueryExecutionError> {
+        let timeout = statement_timeout(&query.subgraph_id, query.timeout);
+
         // Process results; deserialize JSON data
-        let logger = query.logger.unwrap_or(self.logger.clone());
-        conn.query(
-            &logger,
-            query.collection,
-            query.filter,
-            query.order,
-            query.range,
-            query.block,
-            query.query_id,
-        )
+        let logger = query.logger.clone().unwrap_or(self.logger.clone());
+        let run = || {
+            conn.query(
+                &logger,
+                query.collection,
+                query.filter,
+                query.order,
+                query.range,
+                query.distinct,
+                query.selected_attributes,
+                query.block,
+                query.query_id,
+                Some(&self.query_timing),
+            )
+        };
+        match timeout {
+            None => run(),
+            // Have the database cancel the statement when it runs too long
+            // so that it does not keep using resources after we stopped
+            // waiting for it. A `statement_timeout` of 0 turns the timeout
+            // off, and we therefore use at least 1ms
+            Some(timeout) => conn.transaction(|| {
+                conn.conn.batch_execute(&format!(
+                    "set local statement_timeout = {}",
+                    timeout.as_millis().max(1)
+                ))?;
+                run()
+            }),
+        }
     }
 
     fn check_interface_entity_uniqueness(
@@ -427,42 +593,69 @@ impl DeploymentStore {
             use EntityModification::*;
 
             let do_count = modification.entity_key().entity_type.is_data_type();
+            let entity_type = modification.entity_key().entity_type.to_string();
             let n = match modification {
                 Overwrite { key, data } => {
                     let section = stopwatch.start_section("check_interface_entity_uniqueness");
+                    let stage = stopwatch.start_stage("conflict_check", &entity_type);
                     self.check_interface_entity_uniqueness(conn, &key)?;
+                    stage.end();
                     section.end();
 
                     let _section = stopwatch.start_section("apply_entity_modifications_update");
-                    conn.update(&key, data, ptr).map(|_| 0)
+                    if key.entity_type.is_data_type() && ptr.is_some() {
+                        // This is what `conn.update` does, but with the
+                        // clamping of the old version and the insert of the
+                        // new version timed separately
+                        let stage = stopwatch.start_stage("clamp", &entity_type);
+                        conn.delete(&key, ptr)?;
+                        stage.end();
+
+                        let _stage = stopwatch.start_stage("insert", &entity_type);
+                        conn.insert(&key, data, ptr).map(|_| 0)
+                    } else {
+                        let _stage = stopwatch.start_stage("update", &entity_type);
+                        conn.update(&key, data, ptr).map(|_| 0)
+                    }
                 }
                 Insert { key, data } => {
                     let section = stopwatch.start_section("check_interface_entity_uniqueness");
+                    let stage = stopwatch.start_stage("conflict_check", &entity_type);
                     self.check_interface_entity_uniqueness(conn, &key)?;
+                    stage.end();
                     section.end();
 
                     let _section = stopwatch.start_section("apply_entity_modifications_insert");
+                    let _stage = stopwatch.start_stage("insert", &entity_type);
                     conn.insert(&key, data, ptr).map(|_| 1)
                 }
-                Remove { key } => conn
-                    .delete(&key, ptr)
-                    // This conversion is ok since n will only be 0 or 1
-                    .map(|n| -(n as i32))
-                    .map_err(|e| {
-                        anyhow!(
-                            "Failed to remove entity ({}, {}, {}): {}",
-                            key.subgraph_id,
-                            key.entity_type,
-                            key.entity_id,
-                            e
-                        )
-                        .into()
-                    }),
+                Increment { key, deltas } => {
+                    let _section = stopwatch.start_section("apply_entity_modifications_increment");
+                    let _stage = stopwatch.start_stage("increment", &entity_type);
+                    conn.increment(&key, &deltas, ptr).map(|_| 0)
+                }
+                Remove { key } => {
+                    let _stage = stopwatch.start_stage("clamp", &entity_type);
+                    conn.delete(&key, ptr)
+                        // This conversion is ok since n will only be 0 or 1
+                        .map(|n| -(n as i32))
+                        .map_err(|e| {
+                            anyhow!(
+                                "Failed to remove entity ({}, {}, {}): {}",
+                                key.subgraph_id,
+                                key.entity_type,
+                                key.entity_id,
+                                e
+                            )
+                            .into()
+                        })
+                }
             }?;
             if do_count {
                 count += n;
             }
         }
+        let _stage = stopwatch.start_stage("entity_count", "");
         conn.update_entity_count(count)?;
         Ok(())
     }
@@ -663,6 +856,20 @@ impl DeploymentStore {
         ))
     }
 
+    /// Run `f` and retry it while the database behind the pool for
+    /// `replica` is failing over. See `ConnectionPool::with_failover_retry`
+    pub(crate) fn with_failover_retry<T, E, F>(&self, replica: ReplicaId, f: F) -> Result<T, E>
+    where
+        E: fmt::Display + From<StoreError> + AsDieselError,
+        F: FnMut() -> Result<T, E>,
+    {
+        let pool = match replica {
+            ReplicaId::Main => &self.conn,
+            ReplicaId::ReadOnly(idx) => &self.read_only_pools[idx],
+        };
+        pool.with_failover_retry(&self.logger, f)
+    }
+
     pub(crate) fn wait_stats(&self, replica: ReplicaId) -> &PoolWaitStats {
         match replica {
             ReplicaId::Main => &self.conn.wait_stats,
@@ -712,7 +919,7 @@ impl DeploymentStore {
             deployment::manifest_info(&conn, subgraph_id.to_owned())?;
 
         let graft_block =
-            deployment::graft_point(&conn, &subgraph_id)?.map(|(_, ptr)| ptr.number as i32);
+            deployment::graft_point(&conn, &subgraph_id)?.map(|(_, ptr)| ptr.number as BlockNumber);
 
         let features = deployment::features(&conn, subgraph_id)?;
 
@@ -774,12 +981,20 @@ impl DeploymentStore {
             detail::deployment_statuses(&conn, sites)
         })
     }
+
+    pub(crate) fn graft_dependents(&self) -> Result<Vec<(String, status::Dependency)>, StoreError> {
+        let conn = self.get_conn()?;
+        detail::graft_dependents(&conn)
+    }
 }
 
 /// Methods that back the trait `graph::components::Store`, but have small
 /// variations in their signatures
 impl DeploymentStore {
     pub(crate) fn block_ptr(&self, site: &Site) -> Result<Option<EthereumBlockPointer>, Error> {
+        if let Some(pending) = self.pending_block_ptr(&site.deployment) {
+            return Ok(Some(pending.ptr));
+        }
         Self::block_ptr_with_conn(
             &site.deployment,
             &self
@@ -803,11 +1018,20 @@ impl DeploymentStore {
         .boxed()
     }
 
+    pub(crate) fn proof_of_indexing_versions(
+        &self,
+        site: &Site,
+    ) -> Result<Vec<ProofOfIndexingVersion>, StoreError> {
+        let conn = self.get_conn()?;
+        deployment::poi_versions(&conn, &site.deployment)
+    }
+
     pub(crate) fn get_proof_of_indexing<'a>(
         self: Arc<Self>,
         site: Arc<Site>,
         indexer: &'a Option<Address>,
         block: EthereumBlockPointer,
+        version: Option<ProofOfIndexingVersion>,
     ) -> DynTryFuture<'a, Option<[u8; 32]>> {
         let logger = self.logger.cheap_clone();
         let indexer = indexer.clone();
@@ -843,6 +1067,12 @@ impl DeploymentStore {
                             return Ok(None);
                         }
 
+                        let versions = deployment::poi_versions(&conn.conn, &site.deployment)?;
+                        let version = version.unwrap_or(versions[0]);
+                        if !versions.contains(&version) {
+                            return Ok(None);
+                        }
+
                         let entities = conn
                             .query::<Entity>(
                                 &logger,
@@ -853,27 +1083,43 @@ impl DeploymentStore {
                                     first: None,
                                     skip: 0,
                                 },
+                                false,
+                                None,
                                 block.number.try_into().unwrap(),
                                 None,
+                                None,
                             )
                             .map_err(anyhow::Error::from)?;
 
-                        Ok(Some(entities))
+                        Ok(Some((version, entities)))
                     })
                     .map_err(|e| e.into())
                 })
                 .await?;
 
-            let entities = if let Some(entities) = entities {
+            let (version, entities) = if let Some(entities) = entities {
                 entities
             } else {
                 return Ok(None);
             };
 
+            // Only use the digests for the requested version
             let mut by_causality_region = entities
                 .into_iter()
-                .map(|e| {
-                    let causality_region = e.id()?;
+                .filter_map(|e| {
+                    let digest_id = match e.id() {
+                        Ok(id) => id,
+                        Err(err) => return Some(Err(err)),
+                    };
+                    match ProofOfIndexingVersion::from_digest_id(&digest_id) {
+                        Ok((digest_version, causality_region)) if digest_version == version => {
+                            Some(Ok((causality_region.to_owned(), e)))
+                        }
+                        _ => None,
+                    }
+                })
+                .map(|res| {
+                    let (causality_region, e) = res?;
                     let digest = match e.get("digest") {
                         Some(Value::Bytes(b)) => Ok(b.to_owned()),
                         other => Err(anyhow::anyhow!(
@@ -901,24 +1147,29 @@ impl DeploymentStore {
         site: &Site,
         key: EntityKey,
     ) -> Result<Option<Entity>, QueryExecutionError> {
-        let conn = self
-            .get_entity_conn(site, ReplicaId::Main)
-            .map_err(|e| QueryExecutionError::StoreError(e.into()))?;
-        self.get_entity(&conn, &key)
+        self.with_failover_retry(ReplicaId::Main, || {
+            let conn = self
+                .get_entity_conn(site, ReplicaId::Main)
+                .map_err(|e| QueryExecutionError::StoreError(e.into()))?;
+            self.get_entity(&conn, &key)
+        })
     }
 
     pub(crate) fn get_many(
         &self,
         site: &Site,
         ids_for_type: BTreeMap<&EntityType, Vec<&str>>,
+        block: BlockNumber,
     ) -> Result<BTreeMap<EntityType, Vec<Entity>>, StoreError> {
         if ids_for_type.is_empty() {
             return Ok(BTreeMap::new());
         }
-        let conn = self
-            .get_entity_conn(site, ReplicaId::Main)
-            .map_err(|e| QueryExecutionError::StoreError(e.into()))?;
-        conn.find_many(ids_for_type, BLOCK_NUMBER_MAX)
+        self.with_failover_retry(ReplicaId::Main, || {
+            let conn = self
+                .get_entity_conn(site, ReplicaId::Main)
+                .map_err(|e| QueryExecutionError::StoreError(e.into()))?;
+            conn.find_many(ids_for_type.clone(), block)
+        })
     }
 
     pub(crate) fn find(
@@ -926,10 +1177,57 @@ impl DeploymentStore {
         site: &Site,
         query: EntityQuery,
     ) -> Result<Vec<Entity>, QueryExecutionError> {
+        self.with_failover_retry(ReplicaId::Main, || {
+            let conn = self
+                .get_entity_conn(site, ReplicaId::Main)
+                .map_err(|e| QueryExecutionError::StoreError(e.into()))?;
+            self.execute_query(&conn, query.clone())
+        })
+    }
+
+    /// Stream the entities that match `query`. The query runs on its own
+    /// connection in a blocking task that reads entities from a server-side
+    /// cursor and stays at most `STREAM_BATCH_SIZE` entities ahead of the
+    /// consumer of the stream
+    pub(crate) fn find_stream(
+        &self,
+        site: Arc<Site>,
+        query: EntityQuery,
+    ) -> Result<EntityStream, QueryExecutionError> {
         let conn = self
-            .get_entity_conn(site, ReplicaId::Main)
+            .get_conn()
             .map_err(|e| QueryExecutionError::StoreError(e.into()))?;
-        self.execute_query(&conn, query)
+        let layout = self.layout(&conn, &site.namespace, &site.deployment)?;
+        let conn: e::Connection<'static> =
+            e::Connection::new(conn.into(), layout, site.deployment.clone());
+
+        let (sender, receiver) = channel(STREAM_BATCH_SIZE);
+        graph::spawn_blocking_allow_panic(move || {
+            let result = conn
+                .transaction(|| -> Result<_, StoreError> {
+                    Ok(conn.query_stream(
+                        query.collection,
+                        query.filter,
+                        query.order,
+                        query.range,
+                        query.distinct,
+                        query.block,
+                        STREAM_BATCH_SIZE,
+                        // If sending fails, the stream was dropped and we
+                        // can stop reading entities
+                        |entity| sender.clone().send(Ok(entity)).wait().is_ok(),
+                    ))
+                })
+                .map_err(QueryExecutionError::from)
+                .and_then(|result| result);
+            if let Err(e) = result {
+                sender.send(Err(e)).wait().ok();
+            }
+        });
+
+        Ok(Box::new(receiver.then(|result| {
+            result.unwrap_or_else(|()| unreachable!("receiving from a channel never fails"))
+        })))
     }
 
     pub(crate) fn find_one(
@@ -939,17 +1237,205 @@ impl DeploymentStore {
     ) -> Result<Option<Entity>, QueryExecutionError> {
         query.range = EntityRange::first(1);
 
-        let conn = self
-            .get_entity_conn(site, ReplicaId::Main)
-            .map_err(|e| QueryExecutionError::StoreError(e.into()))?;
-
-        let mut results = self.execute_query(&conn, query)?;
+        let mut results = self.with_failover_retry(ReplicaId::Main, || {
+            let conn = self
+                .get_entity_conn(site, ReplicaId::Main)
+                .map_err(|e| QueryExecutionError::StoreError(e.into()))?;
+            self.execute_query(&conn, query.clone())
+        })?;
         match results.len() {
             0 | 1 => Ok(results.pop()),
             n => panic!("find_one query found {} results", n),
         }
     }
 
+    pub(crate) fn count(
+        &self,
+        site: &Site,
+        replica: ReplicaId,
+        query: EntityQuery,
+    ) -> Result<u64, QueryExecutionError> {
+        self.with_failover_retry(replica, || {
+            let conn = self
+                .get_entity_conn(site, replica)
+                .map_err(|e| QueryExecutionError::StoreError(e.into()))?;
+            conn.count(query.collection.clone(), query.filter.clone(), query.block)
+        })
+    }
+
+    pub(crate) fn aggregate(
+        &self,
+        site: &Site,
+        replica: ReplicaId,
+        query: EntityAggregationQuery,
+    ) -> Result<Vec<AggregateRow>, QueryExecutionError> {
+        self.with_failover_retry(replica, || {
+            let conn = self
+                .get_entity_conn(site, replica)
+                .map_err(|e| QueryExecutionError::StoreError(e.into()))?;
+            conn.aggregate(query.clone())
+        })
+    }
+
+    pub(crate) fn explain(
+        &self,
+        site: &Site,
+        replica: ReplicaId,
+        query: EntityQuery,
+    ) -> Result<QueryPlan, QueryExecutionError> {
+        self.with_failover_retry(replica, || {
+            let conn = self
+                .get_entity_conn(site, replica)
+                .map_err(|e| QueryExecutionError::StoreError(e.into()))?;
+            conn.explain(query.clone())
+        })
+    }
+
+    pub(crate) fn entity_digests(
+        &self,
+        site: &Site,
+        block: BlockNumber,
+    ) -> Result<BTreeMap<String, status::EntityDigest>, StoreError> {
+        let conn = self.get_entity_conn(site, ReplicaId::Main)?;
+        conn.entity_digests(block)
+    }
+
+    pub(crate) fn storage_layout(&self, site: &Site) -> Result<StorageLayout, StoreError> {
+        let conn = self.get_entity_conn(site, ReplicaId::Main)?;
+        Ok(StorageLayout {
+            deployment: site.deployment.to_string(),
+            shard: site.shard.as_str().to_owned(),
+            namespace: site.namespace.as_str().to_owned(),
+            tables: conn.table_storage()?,
+        })
+    }
+
+    /// Return the modifications needed to maintain the history entities of
+    /// types with a `@snapshot` directive. Every write to such an entity is
+    /// copied into the snapshot for the interval that contains `block`, so
+    /// that each snapshot reflects the entity as of the end of its interval
+    fn snapshot_modifications(
+        conn: &e::Connection,
+        intervals: &BTreeMap<String, BlockNumber>,
+        mods: &[EntityModification],
+        block: BlockNumber,
+    ) -> Result<Vec<EntityModification>, StoreError> {
+        let mut changed = Vec::new();
+        for modification in mods {
+            match modification {
+                EntityModification::Insert { key, data }
+                | EntityModification::Overwrite { key, data } => changed.push((key, data.clone())),
+                // The entity of an increment is not known; since the
+                // increment has not been applied yet, we load the entity
+                // and apply it ourselves
+                EntityModification::Increment { key, deltas }
+                    if intervals.contains_key(key.entity_type.as_str()) =>
+                {
+                    if let Some(mut data) = conn.find(key, BLOCK_NUMBER_MAX)? {
+                        for (field, delta) in deltas {
+                            let value = data
+                                .get(field.as_str())
+                                .unwrap_or(&Value::Null)
+                                .add_number(delta)
+                                .unwrap_or_else(|| delta.clone());
+                            data.set(field.as_str(), value);
+                        }
+                        changed.push((key, data));
+                    }
+                }
+                EntityModification::Increment { .. } | EntityModification::Remove { .. } => {}
+            }
+        }
+        let snapshots: Vec<(EntityKey, Entity)> = changed
+            .into_iter()
+            .filter_map(|(key, mut data)| {
+                let interval = intervals.get(key.entity_type.as_str())?;
+                let id = format!("{}-{}", key.entity_id, block / interval);
+                data.set("id", id.clone());
+                data.set(SNAPSHOT_OF_FIELD, key.entity_id.clone());
+                data.set(SNAPSHOT_BLOCK_FIELD, block);
+                let key = EntityKey::data(
+                    key.subgraph_id.clone(),
+                    Schema::snapshot_type_name(key.entity_type.as_str()),
+                    id,
+                );
+                Some((key, data))
+            })
+            .collect();
+        if snapshots.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        // Snapshots that already exist for the current interval get
+        // overwritten, all others are inserted
+        let mut ids_for_type: BTreeMap<&EntityType, Vec<&str>> = BTreeMap::new();
+        for (key, _) in &snapshots {
+            ids_for_type
+                .entry(&key.entity_type)
+                .or_default()
+                .push(&key.entity_id);
+        }
+        let existing: HashSet<(EntityType, String)> = conn
+            .find_many(ids_for_type, BLOCK_NUMBER_MAX)?
+            .into_iter()
+            .flat_map(|(entity_type, entities)| {
+                entities
+                    .into_iter()
+                    .filter_map(move |entity| entity.id().ok().map(|id| (entity_type.clone(), id)))
+            })
+            .collect();
+
+        Ok(snapshots
+            .into_iter()
+            .map(|(key, data)| {
+                if existing.contains(&(key.entity_type.clone(), key.entity_id.clone())) {
+                    EntityModification::Overwrite { key, data }
+                } else {
+                    EntityModification::Insert { key, data }
+                }
+            })
+            .collect())
+    }
+
+    /// Run `write` in a transaction that moves the block pointer of `site`
+    /// to `block_ptr_to`, and retry it while the database fails over. When
+    /// the connection fails while the transaction commits, we can not tell
+    /// whether it was committed. Rather than writing the block again, we
+    /// then only check whether the block pointer is at `block_ptr_to`
+    fn write_block<F>(
+        &self,
+        site: &Site,
+        block_ptr_to: &EthereumBlockPointer,
+        mut write: F,
+    ) -> Result<StoreEvent, StoreError>
+    where
+        F: FnMut(&e::Connection) -> Result<StoreEvent, StoreError>,
+    {
+        // The event of the transaction we tried to commit
+        let committing: RefCell<Option<StoreEvent>> = RefCell::new(None);
+        self.with_failover_retry(ReplicaId::Main, || {
+            let econn = self.get_entity_conn(site, ReplicaId::Main)?;
+            let committed = committing.borrow().clone();
+            if let Some(event) = committed {
+                return match Self::block_ptr_with_conn(&site.deployment, &econn)? {
+                    Some(ptr) if &ptr == block_ptr_to => Ok(event),
+                    _ => Err(StoreError::Unknown(anyhow!(
+                        "the connection to the database failed while committing \
+                         block {} for subgraph `{}`, and the block was not written",
+                        block_ptr_to.number,
+                        site.deployment
+                    ))),
+                };
+            }
+            econn.transaction(|| {
+                let event = write(&econn)?;
+                // All that is left to do is committing the transaction
+                committing.replace(Some(event.clone()));
+                Ok(event)
+            })
+        })
+    }
+
     pub(crate) fn transact_block_operations(
         &self,
         site: &Site,
@@ -957,6 +1443,7 @@ impl DeploymentStore {
         mods: Vec<EntityModification>,
         stopwatch: StopwatchMetrics,
         deterministic_errors: Vec<SubgraphError>,
+        skipped_triggers: Vec<SkippedTrigger>,
     ) -> Result<StoreEvent, StoreError> {
         // All operations should apply only to data or metadata for this subgraph
         if mods
@@ -970,9 +1457,61 @@ impl DeploymentStore {
             );
         }
 
-        let econn = self.get_entity_conn(site, ReplicaId::Main)?;
+        if let Some(pending) = self.pending_block_ptr(&site.deployment) {
+            if pending.ptr.number >= block_ptr_to.number {
+                return Err(StoreError::DuplicateBlockProcessing(
+                    site.deployment.clone(),
+                    block_ptr_to.number,
+                ));
+            }
+        }
 
-        let event = econn.transaction(|| -> Result<_, StoreError> {
+        // A block that changes nothing only moves the block pointer, which
+        // takes a single statement. Blocks at which expired entities get
+        // pruned and history gets coarsened still go through the full
+        // transaction
+        if mods.is_empty()
+            && deterministic_errors.is_empty()
+            && skipped_triggers.is_empty()
+            && block_number(&block_ptr_to) % *TTL_PRUNE_INTERVAL != 0
+        {
+            if block_number(&block_ptr_to) % *BLOCK_PTR_STRIDE != 0
+                && self.defer_block_ptr(site, &block_ptr_to)?
+            {
+                return Ok(StoreEvent::new(vec![]));
+            }
+            let attempted = Cell::new(false);
+            let event = self.with_failover_retry(ReplicaId::Main, || {
+                let retry = attempted.replace(true);
+                let conn = self.get_conn()?;
+                let _stage = stopwatch.start_stage("block_ptr", "");
+                match deployment::forward_block_ptr_if_behind(
+                    &conn,
+                    &site.deployment,
+                    block_ptr_to.clone(),
+                )? {
+                    Some(event) => Ok(event),
+                    // The connection might have failed after the previous
+                    // attempt moved the block pointer
+                    None if retry
+                        && deployment::block_ptr(&conn, &site.deployment)?
+                            == Some(block_ptr_to) =>
+                    {
+                        Ok(StoreEvent::new(vec![]))
+                    }
+                    None => Err(StoreError::DuplicateBlockProcessing(
+                        site.deployment.clone(),
+                        block_ptr_to.number,
+                    )),
+                }
+            })?;
+            self.clear_pending_block_ptr(&site.deployment);
+            return Ok(event);
+        }
+
+        // All changes happen in one transaction, which makes it safe to
+        // retry them if the database fails over
+        let event = self.write_block(site, &block_ptr_to, |econn| {
             let block_ptr_from = Self::block_ptr_with_conn(&site.deployment, &econn)?;
             if let Some(ref block_ptr_from) = block_ptr_from {
                 if block_ptr_from.number >= block_ptr_to.number {
@@ -983,41 +1522,179 @@ impl DeploymentStore {
                 }
             }
 
+            let mut mods = mods.clone();
+            let intervals = self
+                .subgraph_info_with_conn(&econn.conn, &site.deployment)?
+                .input
+                .snapshot_intervals();
+            if !intervals.is_empty() {
+                let snapshots = Self::snapshot_modifications(
+                    &econn,
+                    &intervals,
+                    &mods,
+                    block_number(&block_ptr_to),
+                )?;
+                mods.extend(snapshots);
+            }
+
             // Emit a store event for the changes we are about to make. We
             // wait with sending it until we have done all our other work
             // so that we do not hold a lock on the notification queue
             // for longer than we have to
+            let stage = stopwatch.start_stage("event", "");
             let event: StoreEvent = mods.iter().collect();
+            stage.end();
+
+            let stage = stopwatch.start_stage("change_log", "");
+            deployment::log_entity_changes(
+                &econn.conn,
+                &site.deployment,
+                block_number(&block_ptr_to),
+                &event,
+            )?;
+            stage.end();
 
             // Make the changes
             let section = stopwatch.start_section("apply_entity_modifications");
-            self.apply_entity_modifications(&econn, mods, Some(&block_ptr_to), stopwatch)?;
+            self.apply_entity_modifications(&econn, mods, Some(&block_ptr_to), stopwatch.clone())?;
             section.end();
 
+            let stage = stopwatch.start_stage("columnar_mirrors", "");
+            econn.update_columnar_mirrors(block_number(&block_ptr_to))?;
+            stage.end();
+
+            // Every so often, delete the entities with a `@ttl` that
+            // expired before the reorg window
+            let block = block_number(&block_ptr_to);
+            if block % *TTL_PRUNE_INTERVAL == 0 {
+                let stage = stopwatch.start_stage("prune_expired", "");
+                let pruned = econn.prune_expired(block - *REORG_THRESHOLD as BlockNumber)?;
+                stage.end();
+                if pruned > 0 {
+                    debug!(self.logger, "Pruned expired entities";
+                           "subgraph" => site.deployment.as_str(),
+                           "block" => block,
+                           "versions" => pruned);
+                }
+
+                let stage = stopwatch.start_stage("coarsen_history", "");
+                let coarsened = econn.coarsen_history(block - *REORG_THRESHOLD as BlockNumber)?;
+                stage.end();
+                if coarsened > 0 {
+                    info!(self.logger, "Coarsened history";
+                          "subgraph" => site.deployment.as_str(),
+                          "block" => block,
+                          "versions" => coarsened);
+                }
+            }
+
             if !deterministic_errors.is_empty() {
                 deployment::insert_subgraph_errors(
                     &econn.conn,
                     &site.deployment,
-                    deterministic_errors,
+                    deterministic_errors.clone(),
                 )?;
             }
 
+            if !skipped_triggers.is_empty() {
+                deployment::insert_skipped_triggers(&econn.conn, skipped_triggers.clone())?;
+            }
+
+            let _stage = stopwatch.start_stage("block_ptr", "");
             let metadata_event =
-                deployment::forward_block_ptr(&econn.conn, &site.deployment, block_ptr_to)?;
+                deployment::forward_block_ptr(&econn.conn, &site.deployment, block_ptr_to.clone())?;
             Ok(event.extend(metadata_event))
         })?;
+        self.clear_pending_block_ptr(&site.deployment);
 
         Ok(event)
     }
 
+    fn pending_block_ptr(&self, id: &SubgraphDeploymentId) -> Option<PendingBlockPtr> {
+        self.pending_block_ptrs.lock().unwrap().get(id).cloned()
+    }
+
+    fn clear_pending_block_ptr(&self, id: &SubgraphDeploymentId) {
+        self.pending_block_ptrs.lock().unwrap().remove(id);
+    }
+
+    /// Try to move the block pointer of the deployment to `block_ptr_to`
+    /// in memory only. That is only possible while the deployment is
+    /// syncing; returns `false` if the block pointer needs to be written
+    /// to the database. Since all blocks between the pointer in the
+    /// database and the pending pointer changed nothing, losing the
+    /// pending pointer, e.g., because of a restart, only means that these
+    /// blocks get processed again
+    fn defer_block_ptr(
+        &self,
+        site: &Site,
+        block_ptr_to: &EthereumBlockPointer,
+    ) -> Result<bool, StoreError> {
+        let persisted = match self.pending_block_ptr(&site.deployment) {
+            Some(pending) => pending.persisted,
+            None => {
+                let conn = self.get_conn()?;
+                if deployment::exists_and_synced(&conn, site.deployment.as_str())? {
+                    return Ok(false);
+                }
+                let persisted = deployment::block_ptr(&conn, &site.deployment)?;
+                if let Some(persisted) = &persisted {
+                    if persisted.number >= block_ptr_to.number {
+                        return Err(StoreError::DuplicateBlockProcessing(
+                            site.deployment.clone(),
+                            block_ptr_to.number,
+                        ));
+                    }
+                }
+                persisted
+            }
+        };
+        self.pending_block_ptrs.lock().unwrap().insert(
+            site.deployment.clone(),
+            PendingBlockPtr {
+                persisted,
+                ptr: block_ptr_to.clone(),
+            },
+        );
+        Ok(true)
+    }
+
+    /// Revert the pending block pointer of the deployment to
+    /// `block_ptr_to` if the block being reverted only exists in memory.
+    /// Since that block changed nothing, nothing needs to be changed in
+    /// the database. Returns `false` if the block needs to be reverted in
+    /// the database
+    fn revert_pending_block_ptr(&self, site: &Site, block_ptr_to: &EthereumBlockPointer) -> bool {
+        let mut pending_block_ptrs = self.pending_block_ptrs.lock().unwrap();
+        let pending = match pending_block_ptrs.get_mut(&site.deployment) {
+            Some(pending) => pending,
+            None => return false,
+        };
+        if pending.ptr.number != block_ptr_to.number + 1 {
+            panic!("revert_block_operations must revert a single block only");
+        }
+        let back_to_persisted = pending
+            .persisted
+            .as_ref()
+            .map_or(false, |persisted| persisted.number >= block_ptr_to.number);
+        if back_to_persisted {
+            pending_block_ptrs.remove(&site.deployment);
+        } else {
+            pending.ptr = block_ptr_to.clone();
+        }
+        true
+    }
+
     pub(crate) fn revert_block_operations(
         &self,
         site: &Site,
         block_ptr_to: EthereumBlockPointer,
     ) -> Result<StoreEvent, StoreError> {
-        let econn = self.get_entity_conn(site, ReplicaId::Main)?;
+        if self.revert_pending_block_ptr(site, &block_ptr_to) {
+            return Ok(StoreEvent::new(vec![]));
+        }
 
-        let event = econn.transaction(|| -> Result<_, StoreError> {
+        let event = self.write_block(site, &block_ptr_to, |econn| {
             // Unwrap: If we are reverting then the block ptr is not `None`.
             let block_ptr_from = Self::block_ptr_with_conn(&site.deployment, &econn)?.unwrap();
 
@@ -1043,10 +1720,17 @@ impl DeploymentStore {
             }
 
             let metadata_event =
-                deployment::revert_block_ptr(&econn.conn, &site.deployment, block_ptr_to)?;
+                deployment::revert_block_ptr(&econn.conn, &site.deployment, block_ptr_to.clone())?;
 
             let (event, count) = econn.revert_block(&block_ptr_from)?;
+            deployment::log_entity_changes(
+                &econn.conn,
+                &site.deployment,
+                block_number(&block_ptr_to),
+                &event,
+            )?;
             econn.update_entity_count(count)?;
+            econn.revert_columnar_mirrors(block_number(&block_ptr_from))?;
             Ok(event.extend(metadata_event))
         })?;
 
@@ -1126,6 +1810,9 @@ impl DeploymentStore {
         site: Arc<Site>,
         graft_base: Option<(Site, EthereumBlockPointer)>,
     ) -> Result<(), StoreError> {
+        // Start from the block pointer in the database
+        self.clear_pending_block_ptr(&site.deployment);
+
         let econn = self.get_entity_conn(&site, ReplicaId::Main)?;
         econn.transaction(|| {
             deployment::unfail(&econn.conn, &site.deployment)?;
@@ -1133,9 +1820,150 @@ impl DeploymentStore {
         })
     }
 
-    #[cfg(debug_assertions)]
+    /// Delete the versions of entities of the deployment that were
+    /// replaced before `block`. The deployment must not be able to revert
+    /// past `block`, which is the case if it was grafted at `block` or a
+    /// later block, or if `block` is outside of the reorg window
+    pub(crate) fn prune_history(
+        &self,
+        site: &Site,
+        block: BlockNumber,
+    ) -> Result<usize, StoreError> {
+        let econn = self.get_entity_conn(site, ReplicaId::Main)?;
+        econn.transaction(|| {
+            let id = &site.deployment;
+            if deployment::graft_pending(&econn.conn, id)?.is_some() {
+                return Err(constraint_violation!(
+                    "the history of {} can not be pruned before the data \
+                     for its graft has been copied",
+                    id
+                ));
+            }
+            let latest = deployment::block_ptr(&econn.conn, id)?
+                .map(|ptr| ptr.number as BlockNumber)
+                .unwrap_or(0);
+            let graft_block = deployment::graft_point(&econn.conn, id)?
+                .map(|(_, ptr)| ptr.number as BlockNumber)
+                .unwrap_or(0);
+            if block > graft_block && block > latest - *REORG_THRESHOLD as BlockNumber {
+                return Err(constraint_violation!(
+                    "the history of {} can not be pruned before block {} since the \
+                     deployment is at block {} and might still revert past it",
+                    id,
+                    block,
+                    latest
+                ));
+            }
+            econn.prune_history(block)
+        })
+    }
+
+    pub(crate) fn skipped_triggers(
+        &self,
+        id: &SubgraphDeploymentId,
+    ) -> Result<Vec<SkippedTrigger>, StoreError> {
+        let conn = self.get_conn()?;
+        deployment::skipped_triggers(&conn, id)
+    }
+
+    pub(crate) fn mark_skipped_triggers_replayed(
+        &self,
+        id: &SubgraphDeploymentId,
+        graft_block: BlockNumber,
+        replayed_by: &SubgraphDeploymentId,
+    ) -> Result<usize, StoreError> {
+        let conn = self.get_conn()?;
+        conn.transaction(|| {
+            deployment::mark_skipped_triggers_replayed(&conn, id, graft_block, replayed_by)
+        })
+    }
+
+    pub(crate) fn deployment_context(
+        &self,
+        id: &SubgraphDeploymentId,
+    ) -> Result<BTreeMap<String, String>, StoreError> {
+        let conn = self.get_conn()?;
+        deployment::context(&conn, id)
+    }
+
+    pub(crate) fn set_deployment_context(
+        &self,
+        id: &SubgraphDeploymentId,
+        key: String,
+        value: Option<String>,
+    ) -> Result<(), StoreError> {
+        let conn = self.get_conn()?;
+        conn.transaction(|| deployment::set_context_value(&conn, id, key, value))
+    }
+
+    pub(crate) fn register_change_log_consumer(
+        &self,
+        id: &SubgraphDeploymentId,
+        consumer: &str,
+    ) -> Result<i64, StoreError> {
+        let conn = self.get_conn()?;
+        conn.transaction(|| deployment::register_change_log_consumer(&conn, id, consumer))
+    }
+
+    pub(crate) fn remove_change_log_consumer(
+        &self,
+        id: &SubgraphDeploymentId,
+        consumer: &str,
+    ) -> Result<(), StoreError> {
+        let conn = self.get_conn()?;
+        conn.transaction(|| deployment::remove_change_log_consumer(&conn, id, consumer))
+    }
+
+    pub(crate) fn entity_changes(
+        &self,
+        id: &SubgraphDeploymentId,
+        consumer: &str,
+        limit: usize,
+    ) -> Result<Vec<LoggedEntityChange>, StoreError> {
+        let conn = self.get_conn()?;
+        deployment::entity_changes(&conn, id, consumer, limit)
+    }
+
+    pub(crate) fn ack_entity_changes(
+        &self,
+        id: &SubgraphDeploymentId,
+        consumer: &str,
+        seq: i64,
+    ) -> Result<(), StoreError> {
+        let conn = self.get_conn()?;
+        conn.transaction(|| deployment::ack_entity_changes(&conn, id, consumer, seq))
+    }
+
+    pub(crate) fn set_subscriptions_disabled(
+        &self,
+        id: &SubgraphDeploymentId,
+        disabled: bool,
+    ) -> Result<(), StoreError> {
+        let conn = self.get_conn()?;
+        deployment::set_subscriptions_disabled(&conn, id, disabled)
+    }
+
+    #[cfg(any(debug_assertions, feature = "testing"))]
     pub fn error_count(&self, id: &SubgraphDeploymentId) -> Result<usize, StoreError> {
         let conn = self.get_conn()?;
         deployment::error_count(&conn, id)
     }
 }
+
+#[test]
+fn statement_timeouts() {
+    let timeouts = parse_statement_timeouts("QmA=5, QmB = 30,").unwrap();
+    assert_eq!(2, timeouts.len());
+    assert_eq!(
+        Some(&Duration::from_secs(5)),
+        timeouts.get(&SubgraphDeploymentId::new("QmA").unwrap())
+    );
+    assert_eq!(
+        Some(&Duration::from_secs(30)),
+        timeouts.get(&SubgraphDeploymentId::new("QmB").unwrap())
+    );
+
+    assert!(parse_statement_timeouts("QmA").is_err());
+    assert!(parse_statement_timeouts("QmA=soon").is_err());
+    assert!(parse_statement_timeouts("not a deployment=5").is_err());
+}
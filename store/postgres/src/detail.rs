@@ -4,10 +4,12 @@ use diesel::prelude::{
     ExpressionMethods, JoinOnDsl, NullableExpressionMethods, QueryDsl, RunQueryDsl,
 };
 use graph::{
+    components::ethereum::{ANCESTOR_COUNT, REORG_THRESHOLD},
     constraint_violation,
     data::subgraph::schema::SubgraphError,
     prelude::{
-        bigdecimal::ToPrimitive, BigDecimal, EthereumBlockPointer, StoreError, SubgraphDeploymentId,
+        bigdecimal::ToPrimitive, BigDecimal, BlockNumber, EthereumBlockPointer, StoreError,
+        SubgraphDeploymentId,
     },
 };
 use graph::{data::subgraph::status, prelude::web3::types::H256};
@@ -46,7 +48,14 @@ pub struct DeploymentDetail {
     reorg_count: i32,
     current_reorg_depth: i32,
     max_reorg_depth: i32,
-    block_range: (Bound<i32>, Bound<i32>),
+    block_range: (Bound<BlockNumber>, Bound<BlockNumber>),
+    poi_version: i32,
+    poi_transition_version: Option<i32>,
+    history_granularity: Option<i32>,
+    history_coarse_below: Option<BigDecimal>,
+    subscriptions_disabled: bool,
+    context: Option<String>,
+    history_pruned_below: Option<BigDecimal>,
 }
 
 #[derive(Queryable, QueryableByName)]
@@ -62,7 +71,7 @@ struct ErrorDetail {
     block_hash: Option<Bytes>,
     handler: Option<String>,
     deterministic: bool,
-    block_range: (Bound<i32>, Bound<i32>),
+    block_range: (Bound<BlockNumber>, Bound<BlockNumber>),
 }
 
 struct DetailAndError<'a>(DeploymentDetail, Option<ErrorDetail>, &'a Vec<Arc<Site>>);
@@ -156,9 +165,9 @@ impl<'a> TryFrom<DetailAndError<'a>> for status::Info {
             latest_ethereum_block_hash,
             latest_ethereum_block_number,
             entity_count,
-            graft_base: _,
-            graft_block_hash: _,
-            graft_block_number: _,
+            graft_base,
+            graft_block_hash,
+            graft_block_number,
             ..
         } = detail;
 
@@ -182,12 +191,27 @@ impl<'a> TryFrom<DetailAndError<'a>> for status::Info {
             latest_ethereum_block_hash,
             latest_ethereum_block_number,
         )?;
+        let dependencies = graft_base
+            .map(|base| {
+                graft_dependency(
+                    &id,
+                    base,
+                    graft_block_hash,
+                    graft_block_number,
+                    latest_block.as_ref(),
+                )
+            })
+            .transpose()?
+            .into_iter()
+            .collect();
         let health = health.into();
         let chain = status::ChainInfo {
             network: site.network.clone(),
             chain_head_block,
             earliest_block,
             latest_block,
+            ancestor_count: *ANCESTOR_COUNT,
+            reorg_threshold: *REORG_THRESHOLD,
         };
         let entity_count = entity_count.to_u64().ok_or_else(|| {
             constraint_violation!("the entityCount for {} is not representable as a u64", id)
@@ -203,10 +227,76 @@ impl<'a> TryFrom<DetailAndError<'a>> for status::Info {
             chains: vec![chain],
             entity_count,
             node: None,
+            shadow: None,
+            dependencies,
+            dependents: vec![],
         })
     }
 }
 
+/// The dependency that the graft of the deployment `id` at the given block
+/// creates; `deployment` is the deployment that is listed in it. The graft
+/// is pending until the deployment has progressed past the graft block,
+/// since the data of the base is copied when the deployment starts
+fn graft_dependency(
+    id: &str,
+    deployment: String,
+    hash: Option<Bytes>,
+    number: Option<BigDecimal>,
+    latest_block: Option<&status::EthereumBlock>,
+) -> Result<status::Dependency, StoreError> {
+    let block = block(id, "graft_block", hash, number)?;
+    let pending = match (&block, latest_block) {
+        (Some(block), Some(latest)) => block.number() >= latest.number(),
+        (_, None) => true,
+        (None, Some(_)) => false,
+    };
+    Ok(status::Dependency {
+        deployment,
+        kind: status::DependencyKind::Graft,
+        block,
+        pending,
+    })
+}
+
+/// The grafts of all deployments in the shard as pairs of the id of the
+/// base and the dependency of the grafted deployment on it
+pub(crate) fn graft_dependents(
+    conn: &PgConnection,
+) -> Result<Vec<(String, status::Dependency)>, StoreError> {
+    use subgraph_deployment as d;
+
+    let grafts = d::table
+        .filter(d::graft_base.is_not_null())
+        .select((
+            d::id,
+            d::graft_base,
+            d::graft_block_hash,
+            d::graft_block_number,
+            d::latest_ethereum_block_hash,
+            d::latest_ethereum_block_number,
+        ))
+        .load::<(
+            String,
+            Option<String>,
+            Option<Bytes>,
+            Option<BigDecimal>,
+            Option<Bytes>,
+            Option<BigDecimal>,
+        )>(conn)?;
+
+    let mut dependents = Vec::new();
+    for (id, base, hash, number, latest_hash, latest_number) in grafts {
+        if let Some(base) = base {
+            let latest_block = block(&id, "latest_ethereum_block", latest_hash, latest_number)?;
+            let dependency =
+                graft_dependency(&id, id.clone(), hash, number, latest_block.as_ref())?;
+            dependents.push((base, dependency));
+        }
+    }
+    Ok(dependents)
+}
+
 /// Return the details for `deployments`
 pub(crate) fn deployment_details(
     conn: &PgConnection,
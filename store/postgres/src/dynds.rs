@@ -9,7 +9,7 @@ use graph::{
     components::store::StoredDynamicDataSource,
     constraint_violation,
     data::subgraph::Source,
-    prelude::{bigdecimal::ToPrimitive, web3::types::H160, BigDecimal, StoreError},
+    prelude::{bigdecimal::ToPrimitive, web3::types::H160, BigDecimal, BlockNumber, StoreError},
 };
 
 use crate::block_range::first_block_in_range;
@@ -34,7 +34,7 @@ table! {
         ethereum_block_number -> Numeric,
         deployment -> Text,
         context -> Nullable<Text>,
-        block_range -> Range<Integer>,
+        block_range -> Range<BigInt>,
     }
 }
 
@@ -45,7 +45,7 @@ table! {
         address -> Nullable<Binary>,
         abi -> Text,
         start_block -> Nullable<Numeric>,
-        block_range -> Range<Integer>,
+        block_range -> Range<BigInt>,
     }
 }
 
@@ -126,7 +126,7 @@ pub fn load(conn: &PgConnection, id: &str) -> Result<Vec<StoredDynamicDataSource
             String,
             Option<String>,
             (Option<Vec<u8>>, String, Option<BigDecimal>),
-            (Bound<i32>, Bound<i32>),
+            (Bound<BlockNumber>, Bound<BlockNumber>),
         )>(conn)?;
 
     let mut data_sources: Vec<StoredDynamicDataSource> = Vec::new();
@@ -26,23 +26,27 @@ use diesel::sql_types::{Integer, Text};
 use diesel::Connection as _;
 use diesel::RunQueryDsl;
 use maybe_owned::MaybeOwned;
-use std::collections::{BTreeMap, HashMap};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::convert::TryInto;
 use std::str::FromStr;
 use std::sync::{Arc, Mutex};
 use std::time::Instant;
 
+use graph::components::server::index_node::TableStorage;
 use graph::data::subgraph::schema::{MetadataType, POI_OBJECT, POI_TABLE};
+use graph::data::subgraph::status::EntityDigest;
 use graph::prelude::{
-    anyhow, info, BlockNumber, Entity, EntityCollection, EntityFilter, EntityKey, EntityOrder,
-    EntityRange, EthereumBlockPointer, Logger, QueryExecutionError, StoreError, StoreEvent,
-    SubgraphDeploymentId,
+    anyhow, info, AggregateRow, BlockNumber, Entity, EntityAggregationQuery, EntityCollection,
+    EntityFilter, EntityKey, EntityOrder, EntityQuery, EntityRange, EthereumBlockPointer,
+    HistogramVec, Logger, QueryExecutionError, QueryPlan, StoreError, StoreEvent,
+    SubgraphDeploymentId, Value,
 };
 use graph::{components::store::EntityType, data::schema::Schema as SubgraphSchema};
 
+use crate::backend::StorageBackend;
 use crate::deployment;
 use crate::primary::Site;
-use crate::relational::{Catalog, Layout, METADATA_LAYOUT};
+use crate::relational::{Catalog, Layout};
 use crate::{block_range::block_number, primary::Namespace};
 
 /// The size of string prefixes that we index. This is chosen so that we
@@ -72,17 +76,20 @@ pub(crate) fn make_layout_cache() -> LayoutCache {
 ///
 /// Instances of this struct must not be cached across transactions as it
 /// contains a database connection
+///
+/// Reading and writing entities works with any `StorageBackend`; managing
+/// deployments is only possible with the Postgres `Layout`
 #[derive(Constructor)]
-pub struct Connection<'a> {
-    pub conn: MaybeOwned<'a, PooledConnection<ConnectionManager<PgConnection>>>,
+pub struct Connection<'a, S: StorageBackend = Layout> {
+    pub conn: MaybeOwned<'a, PooledConnection<ConnectionManager<S::Conn>>>,
     /// The layout of the actual subgraph data; entities
     /// go into this
-    data: Arc<Layout>,
+    data: Arc<S>,
     /// The subgraph that is accessible through this connection
     subgraph: SubgraphDeploymentId,
 }
 
-impl Connection<'_> {
+impl<S: StorageBackend> Connection<'_, S> {
     /// Return the layout for `key`, which must refer either to the subgraph
     /// for this connection, or the metadata subgraph.
     ///
@@ -90,7 +97,7 @@ impl Connection<'_> {
     ///
     /// If `key` does not reference the connection's subgraph or the metadata
     /// subgraph
-    fn layout_for(&self, key: &EntityKey) -> &Layout {
+    fn layout_for(&self, key: &EntityKey) -> &S {
         if &key.subgraph_id != &self.subgraph {
             panic!(
                 "A connection can only be used with one subgraph and \
@@ -100,39 +107,11 @@ impl Connection<'_> {
             );
         }
         match &key.entity_type {
-            EntityType::Metadata(_) => METADATA_LAYOUT.as_ref(),
+            EntityType::Metadata(_) => S::metadata(),
             EntityType::Data(_) => self.data.as_ref(),
         }
     }
 
-    /// Do any cleanup to bring the subgraph into a known good state
-    pub(crate) fn start_subgraph(
-        &self,
-        logger: &Logger,
-        graft_base: Option<(Site, EthereumBlockPointer)>,
-    ) -> Result<(), StoreError> {
-        if let Some((base, block)) = graft_base {
-            let layout = &self.data;
-            let start = Instant::now();
-            let base_layout =
-                &Connection::layout(&self.conn, base.namespace.clone(), &base.deployment)?;
-            layout.copy_from(
-                logger,
-                &self.conn,
-                &self.subgraph,
-                &base_layout,
-                &base.deployment,
-                block,
-            )?;
-            // Set the block ptr to the graft point to signal that we successfully
-            // performed the graft
-            deployment::forward_block_ptr(&self.conn, &self.subgraph, block.clone())?;
-            info!(logger, "Subgraph successfully initialized";
-            "time_ms" => start.elapsed().as_millis());
-        }
-        Ok(())
-    }
-
     pub(crate) fn find(
         &self,
         key: &EntityKey,
@@ -143,7 +122,7 @@ impl Connection<'_> {
         match &key.entity_type {
             EntityType::Data(name) => self.data.find(&self.conn, name, &key.entity_id, block),
             EntityType::Metadata(typ) => {
-                METADATA_LAYOUT.find(&self.conn, typ.as_str(), &key.entity_id, block)
+                S::metadata().find(&self.conn, typ.as_str(), &key.entity_id, block)
             }
         }
     }
@@ -175,7 +154,7 @@ impl Connection<'_> {
             .into_iter()
             .map(|(name, entities)| (EntityType::data(name), entities))
             .collect();
-        let metadata: BTreeMap<EntityType, Vec<Entity>> = METADATA_LAYOUT
+        let metadata: BTreeMap<EntityType, Vec<Entity>> = S::metadata()
             .find_many(&self.conn, metadata, block)?
             .into_iter()
             .map(|(name, entities)| {
@@ -200,11 +179,15 @@ impl Connection<'_> {
         filter: Option<EntityFilter>,
         order: EntityOrder,
         range: EntityRange,
+        distinct: bool,
+        selected: Option<BTreeSet<String>>,
         block: BlockNumber,
         query_id: Option<String>,
+        timing: Option<&HistogramVec>,
     ) -> Result<Vec<T>, QueryExecutionError> {
         self.data.query(
-            logger, &self.conn, collection, filter, order, range, block, query_id,
+            logger, &self.conn, collection, filter, order, range, distinct, selected, block,
+            query_id, timing,
         )
     }
 
@@ -229,7 +212,7 @@ impl Connection<'_> {
         match (&key.entity_type, ptr) {
             (Data(_), Some(ptr)) => layout.insert(&self.conn, key, entity, block_number(ptr)),
             (Metadata(_), Some(ptr)) => {
-                METADATA_LAYOUT.insert(&self.conn, key, entity, block_number(ptr))
+                S::metadata().insert(&self.conn, key, entity, block_number(ptr))
             }
             (Metadata(_), None) => layout.insert_unversioned(&self.conn, key, entity),
             (Data(_), None) => unreachable!("data changes are always versioned"),
@@ -251,15 +234,33 @@ impl Connection<'_> {
         match (&key.entity_type, ptr) {
             (Data(_), Some(ptr)) => layout.update(&self.conn, key, entity, block_number(ptr)),
             (Metadata(_), Some(ptr)) => {
-                METADATA_LAYOUT.update(&self.conn, key, entity, block_number(ptr))
+                S::metadata().update(&self.conn, key, entity, block_number(ptr))
             }
-            (Metadata(_), None) => METADATA_LAYOUT
+            (Metadata(_), None) => S::metadata()
                 .overwrite_unversioned(&self.conn, key, entity)
                 .map(|_| ()),
             (Data(_), None) => unreachable!("data changes are always versioned"),
         }
     }
 
+    /// Add `deltas` to the attributes of an entity without reading it
+    /// first. Only data entities can be incremented
+    pub(crate) fn increment(
+        &self,
+        key: &EntityKey,
+        deltas: &BTreeMap<String, Value>,
+        ptr: Option<&EthereumBlockPointer>,
+    ) -> Result<usize, StoreError> {
+        use EntityType::*;
+
+        let layout = self.layout_for(key);
+        match (&key.entity_type, ptr) {
+            (Data(_), Some(ptr)) => layout.increment(&self.conn, key, deltas, block_number(ptr)),
+            (Metadata(_), _) => unreachable!("metadata is never incremented"),
+            (Data(_), None) => unreachable!("data changes are always versioned"),
+        }
+    }
+
     pub(crate) fn delete(
         &self,
         key: &EntityKey,
@@ -280,12 +281,10 @@ impl Connection<'_> {
         &self,
         block_ptr: &EthereumBlockPointer,
     ) -> Result<(StoreEvent, i32), StoreError> {
-        // At 1 block per 15 seconds, the maximum i32
-        // value affords just over 1020 years of blocks.
         let block = block_ptr
             .number
             .try_into()
-            .expect("block numbers fit into an i32");
+            .expect("block numbers fit into an i64");
 
         // Revert the block in the subgraph itself
         let (event, count) = self.data.revert_block(&self.conn, &self.subgraph, block)?;
@@ -294,10 +293,89 @@ impl Connection<'_> {
         // importantly creation of dynamic data sources. We ensure in the
         // rest of the code that we only record history for those meta data
         // changes that might need to be reverted
-        METADATA_LAYOUT.revert_metadata(&self.conn, &self.subgraph, block)?;
+        S::metadata().revert_metadata(&self.conn, &self.subgraph, block)?;
         Ok((event, count))
     }
 
+    pub(crate) fn transaction<T, E, F>(&self, f: F) -> Result<T, E>
+    where
+        F: FnOnce() -> Result<T, E>,
+        E: From<diesel::result::Error>,
+    {
+        self.conn.transaction(f)
+    }
+}
+
+/// Operations that are specific to Postgres
+impl Connection<'_, Layout> {
+    pub(crate) fn count(
+        &self,
+        collection: EntityCollection,
+        filter: Option<EntityFilter>,
+        block: BlockNumber,
+    ) -> Result<u64, QueryExecutionError> {
+        self.data.count(&self.conn, collection, filter, block)
+    }
+
+    pub(crate) fn aggregate(
+        &self,
+        query: EntityAggregationQuery,
+    ) -> Result<Vec<AggregateRow>, QueryExecutionError> {
+        self.data.aggregate(&self.conn, query)
+    }
+
+    pub(crate) fn explain(&self, query: EntityQuery) -> Result<QueryPlan, QueryExecutionError> {
+        self.data.explain(
+            &self.conn,
+            query.collection,
+            query.filter,
+            query.order,
+            query.range,
+            query.distinct,
+            query.block,
+        )
+    }
+
+    pub(crate) fn entity_digests(
+        &self,
+        block: BlockNumber,
+    ) -> Result<BTreeMap<String, EntityDigest>, StoreError> {
+        self.data.entity_digests(&self.conn, block)
+    }
+
+    pub(crate) fn table_storage(&self) -> Result<Vec<TableStorage>, StoreError> {
+        self.data.table_storage(&self.conn)
+    }
+
+    /// Do any cleanup to bring the subgraph into a known good state
+    pub(crate) fn start_subgraph(
+        &self,
+        logger: &Logger,
+        graft_base: Option<(Site, EthereumBlockPointer)>,
+    ) -> Result<(), StoreError> {
+        if let Some((base, block)) = graft_base {
+            let layout = &self.data;
+            let start = Instant::now();
+            let base_layout =
+                &Connection::layout(&self.conn, base.namespace.clone(), &base.deployment)?;
+            layout.copy_from(
+                logger,
+                &self.conn,
+                &self.subgraph,
+                &base_layout,
+                &base.deployment,
+                block,
+            )?;
+            crate::columnar::drop_mirrors(&self.conn, layout)?;
+            // Set the block ptr to the graft point to signal that we successfully
+            // performed the graft
+            deployment::forward_block_ptr(&self.conn, &self.subgraph, block.clone())?;
+            info!(logger, "Subgraph successfully initialized";
+            "time_ms" => start.elapsed().as_millis());
+        }
+        Ok(())
+    }
+
     pub(crate) fn update_entity_count(&self, count: i32) -> Result<(), StoreError> {
         if count == 0 {
             return Ok(());
@@ -337,14 +415,6 @@ impl Connection<'_> {
             .map(|_| ())?)
     }
 
-    pub(crate) fn transaction<T, E, F>(&self, f: F) -> Result<T, E>
-    where
-        F: FnOnce() -> Result<T, E>,
-        E: From<diesel::result::Error>,
-    {
-        self.conn.transaction(f)
-    }
-
     /// Create the database schema for a new subgraph, including all tables etc.
     ///
     /// It is an error if `deployment_schemas` already has an entry for this
@@ -387,9 +457,62 @@ impl Connection<'_> {
     /// for the subgraph
     pub(crate) fn drop_deployment(conn: &PgConnection, site: &Site) -> Result<(), StoreError> {
         crate::deployment::drop_schema(conn, &site.namespace)?;
+        crate::columnar::forget_mirrors(&site.namespace);
         Layout::drop_metadata(conn, &site.deployment)
     }
 
+    /// Copy the entity versions written at `block` into the columnar
+    /// mirrors of this deployment's tables
+    pub(crate) fn update_columnar_mirrors(&self, block: BlockNumber) -> Result<(), StoreError> {
+        crate::columnar::update(&self.conn, &self.data, block)
+    }
+
+    /// Remove the entity versions written at `block` and later from the
+    /// columnar mirrors of this deployment's tables
+    pub(crate) fn revert_columnar_mirrors(&self, block: BlockNumber) -> Result<(), StoreError> {
+        crate::columnar::revert(&self.conn, &self.data, block)
+    }
+
+    /// Pass the entities that match the query to `sink` as they are read
+    /// from the database. See `Layout::query_stream`
+    pub(crate) fn query_stream<F>(
+        &self,
+        collection: EntityCollection,
+        filter: Option<EntityFilter>,
+        order: EntityOrder,
+        range: EntityRange,
+        distinct: bool,
+        block: BlockNumber,
+        batch_size: usize,
+        sink: F,
+    ) -> Result<(), QueryExecutionError>
+    where
+        F: FnMut(Entity) -> bool,
+    {
+        self.data.query_stream(
+            &self.conn, collection, filter, order, range, distinct, block, batch_size, sink,
+        )
+    }
+
+    /// Delete the entities with a `@ttl` that expired before `block`
+    pub(crate) fn prune_expired(&self, block: BlockNumber) -> Result<usize, StoreError> {
+        self.data.prune_expired(&self.conn, block)
+    }
+
+    /// Keep only coarser history before `block` if the history of the
+    /// deployment grew beyond the limits in `GRAPH_HISTORY_LIMITS`
+    pub(crate) fn coarsen_history(&self, block: BlockNumber) -> Result<usize, StoreError> {
+        crate::history::coarsen(&self.conn, &self.data, &self.subgraph, block)
+    }
+
+    /// Delete the versions of entities that were replaced before `block`
+    /// and record that queries at earlier blocks are not possible any more
+    pub(crate) fn prune_history(&self, block: BlockNumber) -> Result<usize, StoreError> {
+        let count = self.data.prune_history(&self.conn, block)?;
+        crate::deployment::set_history_pruned_below(&self.conn, &self.subgraph, block)?;
+        Ok(count)
+    }
+
     pub(crate) fn supports_proof_of_indexing(&self) -> bool {
         self.data.tables.contains_key(POI_OBJECT)
     }
@@ -0,0 +1,160 @@
+//! Soft limits for the size of the history of deployments.
+//!
+//! Deployments keep every version of their entities so that they can be
+//! queried at any block. Setting `GRAPH_HISTORY_LIMITS` to a
+//! comma-separated list of `<versions>:<granularity>` makes deployments
+//! whose tables hold more than `versions` entity versions keep coarser
+//! history: versions that were replaced before the reorg window and that
+//! were not current at any block that is a multiple of `granularity` are
+//! deleted. Queries at these multiples still see the same data as before,
+//! and queries at other blocks before the reorg window fail. The
+//! granularity of the largest limit that a deployment exceeds applies, and
+//! each granularity must be a multiple of the granularities of smaller
+//! limits so that moving to a coarser granularity keeps the data that
+//! queries at the new multiples need. Deployments never go back to keeping
+//! finer history, and the granularity in effect is recorded in the
+//! deployment's metadata
+use diesel::pg::PgConnection;
+use lazy_static::lazy_static;
+use std::env;
+use std::str::FromStr;
+
+use graph::prelude::{
+    anyhow, BlockNumber, Error, HistoryGranularity, StoreError, SubgraphDeploymentId,
+};
+
+use crate::deployment;
+use crate::relational::Layout;
+
+lazy_static! {
+    static ref HISTORY_LIMITS: HistoryLimits = env::var("GRAPH_HISTORY_LIMITS")
+        .ok()
+        .map(|s| s.parse().expect("invalid GRAPH_HISTORY_LIMITS"))
+        .unwrap_or_default();
+}
+
+/// The granularity of history to keep once a deployment holds more than a
+/// certain number of entity versions
+#[derive(Clone, Debug, Default, PartialEq)]
+struct HistoryLimits {
+    /// Pairs of `(versions, granularity)`, sorted by `versions`
+    limits: Vec<(u64, BlockNumber)>,
+}
+
+impl HistoryLimits {
+    /// The granularity for a deployment that holds `versions` entity
+    /// versions, or `None` if it should keep all of its history
+    fn granularity(&self, versions: u64) -> Option<BlockNumber> {
+        self.limits
+            .iter()
+            .rev()
+            .find(|(limit, _)| versions > *limit)
+            .map(|(_, granularity)| *granularity)
+    }
+}
+
+impl FromStr for HistoryLimits {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut limits = s
+            .split(',')
+            .map(str::trim)
+            .filter(|entry| !entry.is_empty())
+            .map(|entry| {
+                let mut parts = entry.splitn(2, ':');
+                let versions = parts.next().unwrap_or("").trim().parse::<u64>();
+                let granularity = parts.next().unwrap_or("").trim().parse::<BlockNumber>();
+                match (versions, granularity) {
+                    (Ok(versions), Ok(granularity)) if granularity > 1 => {
+                        Ok((versions, granularity))
+                    }
+                    _ => Err(anyhow!(
+                        "limits must have the form `<versions>:<granularity>` \
+                         with a granularity of at least 2, but `{}` does not",
+                        entry
+                    )),
+                }
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        limits.sort();
+
+        for pair in limits.windows(2) {
+            let ((_, finer), (_, coarser)) = (pair[0], pair[1]);
+            if coarser <= finer || coarser % finer != 0 {
+                return Err(anyhow!(
+                    "the granularity {} must be a multiple of the granularity {} \
+                     for a smaller limit",
+                    coarser,
+                    finer
+                ));
+            }
+        }
+        Ok(HistoryLimits { limits })
+    }
+}
+
+/// Make the deployment `id` keep coarser history before `cutoff` if it
+/// grew beyond the limits in `GRAPH_HISTORY_LIMITS`. Since the versions
+/// that get deleted can not be reverted, `cutoff` must be outside of the
+/// reorg window. Return the number of deleted versions
+pub(crate) fn coarsen(
+    conn: &PgConnection,
+    layout: &Layout,
+    id: &SubgraphDeploymentId,
+    cutoff: BlockNumber,
+) -> Result<usize, StoreError> {
+    if HISTORY_LIMITS.limits.is_empty() || cutoff <= 0 {
+        return Ok(0);
+    }
+
+    let current = deployment::history_granularity(conn, id)?;
+    let versions = layout.estimated_versions(conn)?;
+    let granularity = match (current, HISTORY_LIMITS.granularity(versions)) {
+        (None, None) => return Ok(0),
+        (Some(current), None) => current.granularity,
+        (None, Some(granularity)) => granularity,
+        (Some(current), Some(granularity)) => current.granularity.max(granularity),
+    };
+
+    // With an unchanged granularity, only the versions that were replaced
+    // since the last time need to be looked at
+    let from = match current {
+        Some(current) if current.granularity == granularity => current.coarse_below,
+        _ => 0,
+    };
+    if from >= cutoff {
+        return Ok(0);
+    }
+
+    let count = layout.coarsen_history(conn, granularity, from, cutoff)?;
+    deployment::set_history_granularity(
+        conn,
+        id,
+        HistoryGranularity {
+            granularity,
+            coarse_below: cutoff,
+        },
+    )?;
+    Ok(count)
+}
+
+#[test]
+fn history_limits() {
+    let limits: HistoryLimits = "100000000:100, 1000000:10".parse().unwrap();
+    assert_eq!(vec![(1000000, 10), (100000000, 100)], limits.limits);
+    assert_eq!(None, limits.granularity(1000));
+    assert_eq!(None, limits.granularity(1000000));
+    assert_eq!(Some(10), limits.granularity(1000001));
+    assert_eq!(Some(100), limits.granularity(200000000));
+
+    for invalid in &[
+        "1000",
+        "1000:1",
+        "1000:ten",
+        "1000:10,2000:15",
+        "1000:10,2000:5",
+    ] {
+        assert!(invalid.parse::<HistoryLimits>().is_err(), "{}", invalid);
+    }
+}
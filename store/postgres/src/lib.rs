@@ -19,11 +19,13 @@ extern crate postgres;
 extern crate serde;
 extern crate uuid;
 
+mod backend;
 mod block_range;
 mod block_store;
 mod catalog;
 mod chain_head_listener;
 mod chain_store;
+mod columnar;
 pub mod connection_pool;
 mod deployment;
 mod deployment_store;
@@ -31,6 +33,7 @@ mod detail;
 mod dynds;
 mod entities;
 mod functions;
+mod history;
 mod jsonb;
 mod notification_listener;
 mod primary;
@@ -38,25 +41,31 @@ pub mod query_store;
 mod relational;
 mod relational_queries;
 mod sql_value;
+mod statement_cache;
 mod store;
 mod store_events;
 mod subgraph_store;
 
-#[cfg(debug_assertions)]
+#[cfg(any(debug_assertions, feature = "testing"))]
 pub mod layout_for_tests {
     pub use crate::block_range::*;
     pub use crate::block_store::FAKE_NETWORK_SHARED;
     pub use crate::chain_store::test_support as chain_support;
+    pub use crate::columnar::{
+        mirror_for as columnar_mirror, revert as revert_columnar, update as update_columnar,
+    };
     pub use crate::entities::STRING_PREFIX_SIZE;
     pub use crate::primary::{Connection, Namespace, EVENT_TAP, EVENT_TAP_ENABLED};
     pub use crate::relational::*;
 }
 
+pub use self::backend::StorageBackend;
 pub use self::block_store::BlockStore;
 pub use self::chain_head_listener::ChainHeadUpdateListener;
 pub use self::chain_store::ChainStore;
 pub use self::detail::DeploymentDetail;
-pub use self::primary::UnusedDeployment;
+pub use self::primary::{NamedLock, UnusedDeployment};
+pub use self::relational_queries::{FromColumnValue, FromEntityData};
 pub use self::store::Store;
 pub use self::store_events::SubscriptionManager;
 pub use self::subgraph_store::{unused, DeploymentPlacer, Shard, SubgraphStore, PRIMARY_SHARD};
@@ -6,7 +6,7 @@ use diesel::{
     dsl::{any, exists, not},
     pg::Pg,
     serialize::Output,
-    sql_types::{Array, Text},
+    sql_types::{Array, BigInt, Text},
     types::{FromSql, ToSql},
 };
 use diesel::{
@@ -38,7 +38,7 @@ use std::{
     convert::TryInto,
     fmt,
     io::Write,
-    time::{SystemTime, UNIX_EPOCH},
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
 
 use crate::{
@@ -48,9 +48,19 @@ use crate::{
     subgraph_store::{unused, Shard},
 };
 
-#[cfg(debug_assertions)]
+lazy_static::lazy_static! {
+    /// Store events with more changes than this are compacted into a count
+    /// of changes per entity type before they are sent to subscribers
+    static ref STORE_EVENT_COMPACTION_THRESHOLD: usize =
+        std::env::var("GRAPH_STORE_EVENT_COMPACTION_THRESHOLD")
+            .unwrap_or("10000".into())
+            .parse::<usize>()
+            .expect("invalid GRAPH_STORE_EVENT_COMPACTION_THRESHOLD");
+}
+
+#[cfg(any(debug_assertions, feature = "testing"))]
 use std::sync::Mutex;
-#[cfg(debug_assertions)]
+#[cfg(any(debug_assertions, feature = "testing"))]
 lazy_static::lazy_static! {
     /// Tests set this to true so that `send_store_event` will store a copy
     /// of each event sent in `EVENT_TAP`
@@ -72,7 +82,7 @@ table! {
         current_version -> Nullable<Text>,
         pending_version -> Nullable<Text>,
         created_at -> Numeric,
-        block_range -> Range<Integer>,
+        block_range -> Range<BigInt>,
     }
 }
 
@@ -83,7 +93,7 @@ table! {
         subgraph -> Text,
         deployment -> Text,
         created_at -> Numeric,
-        block_range -> Range<Integer>,
+        block_range -> Range<BigInt>,
     }
 }
 
@@ -93,7 +103,7 @@ table! {
         id -> Text,
         node_id -> Text,
         cost -> Numeric,
-        block_range -> Range<Integer>,
+        block_range -> Range<BigInt>,
     }
 }
 
@@ -146,12 +156,23 @@ table! {
         /// Data we fill in from the deployment's shard
         entity_count -> Integer,
         latest_ethereum_block_hash -> Nullable<Binary>,
-        latest_ethereum_block_number -> Nullable<Integer>,
+        latest_ethereum_block_number -> Nullable<BigInt>,
         failed -> Bool,
         synced -> Bool,
     }
 }
 
+table! {
+    /// Locks with a time-to-live that processes outside of graph-node can
+    /// use to coordinate with each other and with graph-node
+    named_locks(name) {
+        name -> Text,
+        holder -> Text,
+        acquired_at -> Timestamptz,
+        expires_at -> Timestamptz,
+    }
+}
+
 allow_tables_to_appear_in_same_query!(
     subgraph,
     subgraph_version,
@@ -188,11 +209,19 @@ pub struct UnusedDeployment {
     /// Data we fill in from the deployment's shard
     pub entity_count: i32,
     pub latest_ethereum_block_hash: Option<Vec<u8>>,
-    pub latest_ethereum_block_number: Option<i32>,
+    pub latest_ethereum_block_number: Option<i64>,
     pub failed: bool,
     pub synced: bool,
 }
 
+#[derive(Clone, Queryable, Debug)]
+pub struct NamedLock {
+    pub name: String,
+    pub holder: String,
+    pub acquired_at: PgTimestamp,
+    pub expires_at: PgTimestamp,
+}
+
 #[derive(Clone, Debug, PartialEq, Eq, Hash, AsExpression, FromSqlRow)]
 #[sql_type = "diesel::sql_types::Text"]
 /// A namespace (schema) in the database
@@ -770,8 +799,15 @@ impl Connection {
         if event.changes.is_empty() {
             return Ok(());
         }
+        let compacted;
+        let event = if event.changes.len() > *STORE_EVENT_COMPACTION_THRESHOLD {
+            compacted = event.clone().compact(*STORE_EVENT_COMPACTION_THRESHOLD);
+            &compacted
+        } else {
+            event
+        };
         let v = serde_json::to_value(event)?;
-        #[cfg(debug_assertions)]
+        #[cfg(any(debug_assertions, feature = "testing"))]
         {
             if *EVENT_TAP_ENABLED.lock().unwrap() {
                 EVENT_TAP.lock().unwrap().push(event.clone());
@@ -904,7 +940,7 @@ impl Connection {
         Ok(deployment.optional()?.flatten())
     }
 
-    #[cfg(debug_assertions)]
+    #[cfg(any(debug_assertions, feature = "testing"))]
     pub fn versions_for_subgraph(
         &self,
         name: &str,
@@ -919,7 +955,7 @@ impl Connection {
             .unwrap_or((None, None)))
     }
 
-    #[cfg(debug_assertions)]
+    #[cfg(any(debug_assertions, feature = "testing"))]
     pub fn deployment_for_version(&self, name: &str) -> Result<Option<String>, StoreError> {
         use subgraph_version as v;
 
@@ -1015,7 +1051,7 @@ impl Connection {
             .map(|ptr| {
                 (
                     Some(Vec::from(ptr.hash.as_bytes())),
-                    Some(ptr.number as i32),
+                    Some(ptr.number as i64),
                 )
             })
             .unwrap_or((None, None));
@@ -1070,6 +1106,61 @@ impl Connection {
             .load(&self.0)?)
     }
 
+    /// Try to take the lock `name` for `holder` for the next `ttl`. Return
+    /// `true` if `holder` now has the lock, either because nobody held it,
+    /// the previous holder's lock expired, or because `holder` already had
+    /// it, in which case its expiration is extended. Return `false` if
+    /// somebody else holds the lock
+    pub fn acquire_lock(
+        &self,
+        name: &str,
+        holder: &str,
+        ttl: Duration,
+    ) -> Result<bool, StoreError> {
+        const QUERY: &str = "
+    insert into public.named_locks as l(name, holder, expires_at)
+    values ($1, $2, now() + $3 * interval '1 millisecond')
+    on conflict(name) do update
+       set holder = excluded.holder,
+           acquired_at = case when l.holder = excluded.holder
+                              then l.acquired_at else now() end,
+           expires_at = excluded.expires_at
+     where l.holder = excluded.holder or l.expires_at <= now()";
+
+        let ttl = i64::try_from(ttl.as_millis())
+            .map_err(|_| constraint_violation!("lock ttl {:?} is too large", ttl))?;
+        let rows = diesel::sql_query(QUERY)
+            .bind::<Text, _>(name)
+            .bind::<Text, _>(holder)
+            .bind::<BigInt, _>(ttl)
+            .execute(&self.0)?;
+        Ok(rows > 0)
+    }
+
+    /// Release the lock `name` if it is held by `holder`. Return `true` if
+    /// `holder` held the lock, even if it had already expired
+    pub fn release_lock(&self, name: &str, holder: &str) -> Result<bool, StoreError> {
+        use named_locks as l;
+
+        let rows = delete(
+            l::table
+                .filter(l::name.eq(name))
+                .filter(l::holder.eq(holder)),
+        )
+        .execute(&self.0)?;
+        Ok(rows > 0)
+    }
+
+    /// List all locks that have not expired yet
+    pub fn named_locks(&self) -> Result<Vec<NamedLock>, StoreError> {
+        use named_locks as l;
+
+        Ok(l::table
+            .filter(l::expires_at.gt(sql("now()")))
+            .order_by(l::name)
+            .load(&self.0)?)
+    }
+
     pub fn find_ens_name(&self, hash: &str) -> Result<Option<String>, StoreError> {
         use ens_names as dsl;
 
@@ -1,18 +1,34 @@
-use std::collections::BTreeMap;
+use lazy_static::lazy_static;
+use std::collections::{BTreeMap, BTreeSet};
 
 use web3::types::H256;
 
 use crate::deployment_store::{DeploymentStore, ReplicaId};
+use graph::components::ethereum::REORG_THRESHOLD;
 use graph::components::store::QueryStore as QueryStoreTrait;
+use graph::data::subgraph::status;
 use graph::prelude::*;
 
 use crate::primary::Site;
 
+lazy_static! {
+    /// Whether `explain` may show query plans; query plans reveal how the
+    /// store is set up and should only be available to administrators
+    static ref ALLOW_QUERY_EXPLAIN: bool = std::env::var("GRAPH_ALLOW_QUERY_EXPLAIN")
+        .ok()
+        .map(|s| s.eq_ignore_ascii_case("true"))
+        .unwrap_or(false);
+}
+
+#[derive(Clone)]
 pub(crate) struct QueryStore {
     site: Arc<Site>,
     replica_id: ReplicaId,
     store: Arc<DeploymentStore>,
     chain_store: Arc<crate::ChainStore>,
+    /// The block that this store treats as the latest block of the
+    /// deployment, see `pin_latest_block`
+    pinned: Option<EthereumBlockPointer>,
 }
 
 impl QueryStore {
@@ -27,22 +43,105 @@ impl QueryStore {
             replica_id,
             store,
             chain_store,
+            pinned: None,
+        }
+    }
+
+    /// The latest block of the deployment, or the block that this store
+    /// is pinned to
+    fn head(&self) -> Result<Option<EthereumBlockPointer>, Error> {
+        match self.pinned {
+            Some(ptr) => Ok(Some(ptr)),
+            None => self.store.block_ptr(&self.site),
         }
     }
+
+    /// The block at which to run a query for `block`; queries for blocks
+    /// after the pinned block are run at the pinned block
+    fn pin(&self, block: BlockNumber) -> BlockNumber {
+        match self.pinned {
+            Some(ptr) => block.min(ptr.number as BlockNumber),
+            None => block,
+        }
+    }
+
+    /// Check whether the block `hash` with number `number` is on the chain
+    /// that ends in the deployment head `head`
+    fn is_on_chain(
+        &self,
+        head: EthereumBlockPointer,
+        hash: H256,
+        number: u64,
+    ) -> Result<bool, Error> {
+        if head.hash == hash {
+            return Ok(true);
+        }
+
+        // Blocks that are further back than the reorg threshold are final;
+        // if we only know of one block with that number, it must be the one
+        // on the chain
+        let offset = head.number - number;
+        let hashes = self.chain_store.block_hashes_by_block_number(number)?;
+        if offset > *REORG_THRESHOLD && hashes == [hash] {
+            return Ok(true);
+        }
+
+        // Otherwise, follow the parents of the head block. If the block
+        // cache is missing some of them, we can not tell whether the block
+        // is on the chain and treat it as if it was not
+        Ok(self
+            .chain_store
+            .ancestor_block(head, offset)?
+            .and_then(|ancestor| ancestor.block.hash)
+            .map_or(false, |ancestor| ancestor == hash))
+    }
 }
 
 #[async_trait]
 impl QueryStoreTrait for QueryStore {
     fn find_query_values(
         &self,
-        query: EntityQuery,
+        mut query: EntityQuery,
+        attributes: Option<BTreeSet<String>>,
     ) -> Result<Vec<BTreeMap<String, q::Value>>, QueryExecutionError> {
         assert_eq!(&self.site.deployment, &query.subgraph_id);
-        let conn = self
-            .store
-            .get_entity_conn(self.site.as_ref(), self.replica_id)
-            .map_err(|e| QueryExecutionError::StoreError(e.into()))?;
-        self.store.execute_query(&conn, query)
+        query.block = self.pin(query.block);
+        query.selected_attributes = attributes;
+        self.store.with_failover_retry(self.replica_id, || {
+            let conn = self
+                .store
+                .get_entity_conn(self.site.as_ref(), self.replica_id)
+                .map_err(|e| QueryExecutionError::StoreError(e.into()))?;
+            self.store.execute_query(&conn, query.clone())
+        })
+    }
+
+    fn count(&self, mut query: EntityQuery) -> Result<u64, QueryExecutionError> {
+        assert_eq!(&self.site.deployment, &query.subgraph_id);
+        query.block = self.pin(query.block);
+        self.store.count(self.site.as_ref(), self.replica_id, query)
+    }
+
+    fn aggregate(
+        &self,
+        mut query: EntityAggregationQuery,
+    ) -> Result<Vec<AggregateRow>, QueryExecutionError> {
+        assert_eq!(&self.site.deployment, &query.subgraph_id);
+        query.block = self.pin(query.block);
+        self.store
+            .aggregate(self.site.as_ref(), self.replica_id, query)
+    }
+
+    fn explain(&self, mut query: EntityQuery) -> Result<QueryPlan, QueryExecutionError> {
+        assert_eq!(&self.site.deployment, &query.subgraph_id);
+        query.block = self.pin(query.block);
+        if !*ALLOW_QUERY_EXPLAIN {
+            return Err(QueryExecutionError::NotSupported(
+                "explaining queries requires GRAPH_ALLOW_QUERY_EXPLAIN=true".to_owned(),
+            ));
+        }
+        self.store
+            .explain(self.site.as_ref(), self.replica_id, query)
     }
 
     /// Return true if the deployment with the given id is fully synced,
@@ -57,31 +156,52 @@ impl QueryStoreTrait for QueryStore {
         subgraph_id: SubgraphDeploymentId,
     ) -> Result<Option<EthereumBlockPointer>, Error> {
         assert_eq!(&self.site.deployment, &subgraph_id);
-        self.store.block_ptr(&self.site)
+        self.head()
     }
 
-    fn block_number(&self, block_hash: H256) -> Result<Option<BlockNumber>, StoreError> {
-        // We should also really check that the block with the given hash is
-        // on the chain starting at the subgraph's current head. That check is
-        // very expensive though with the data structures we have currently
-        // available. Ideally, we'd have the last REORG_THRESHOLD blocks in
-        // memory so that we can check against them, and then mark in the
-        // database the blocks on the main chain that we consider final
+    fn block_number(&self, block_hash: H256) -> Result<Option<BlockNumber>, QueryExecutionError> {
         let subgraph_network = self.network_name();
-        self.chain_store
-            .block_number(block_hash)?
-            .map(|(network_name, number)| {
-                if &network_name == subgraph_network {
-                    BlockNumber::try_from(number)
-                        .map_err(|e| StoreError::QueryExecutionError(e.to_string()))
-                } else {
-                    Err(StoreError::QueryExecutionError(format!(
+        let number = match self.chain_store.block_number(block_hash)? {
+            None => return Ok(None),
+            Some((network_name, number)) if &network_name == subgraph_network => number,
+            Some((network_name, _)) => {
+                return Err(QueryExecutionError::ValueParseError(
+                    "block.hash".to_owned(),
+                    format!(
                         "subgraph {} belongs to network {} but block {:x} belongs to network {}",
                         &self.site.deployment, subgraph_network, block_hash, network_name
-                    )))
-                }
-            })
-            .transpose()
+                    ),
+                ))
+            }
+        };
+
+        let head = self
+            .head()
+            .map_err(StoreError::from)?
+            .filter(|head| head.number >= number as u64)
+            .ok_or_else(|| {
+                QueryExecutionError::ValueParseError(
+                    "block.hash".to_owned(),
+                    format!(
+                        "subgraph {} has not indexed block {:x} with number {} yet",
+                        &self.site.deployment, block_hash, number
+                    ),
+                )
+            })?;
+
+        if !self
+            .is_on_chain(head, block_hash, number as u64)
+            .map_err(StoreError::from)?
+        {
+            return Err(QueryExecutionError::ValueParseError(
+                "block.hash".to_owned(),
+                format!(
+                    "block {:x} with number {} is not on the chain that subgraph {} has indexed",
+                    block_hash, number, &self.site.deployment
+                ),
+            ));
+        }
+        Ok(Some(number))
     }
 
     fn wait_stats(&self) -> &PoolWaitStats {
@@ -93,6 +213,10 @@ impl QueryStoreTrait for QueryStore {
         id: SubgraphDeploymentId,
         block: Option<BlockNumber>,
     ) -> Result<bool, StoreError> {
+        let block = match self.pinned {
+            Some(_) => Some(self.pin(block.unwrap_or(BLOCK_NUMBER_MAX))),
+            None => block,
+        };
         self.store
             .with_conn(move |conn, _| {
                 crate::deployment::has_non_fatal_errors(conn, &id, block).map_err(|e| e.into())
@@ -106,6 +230,22 @@ impl QueryStoreTrait for QueryStore {
             .deployment_state_from_id(self.site.deployment.clone())?)
     }
 
+    fn deployment_status(&self) -> Result<status::Info, QueryExecutionError> {
+        let mut info = self
+            .store
+            .deployment_statuses(&vec![self.site.clone()])?
+            .pop()
+            .ok_or_else(|| StoreError::DeploymentNotFound(self.site.deployment.to_string()))?;
+        let head = self
+            .chain_store
+            .chain_head_ptr()
+            .map_err(StoreError::from)?;
+        for chain in &mut info.chains {
+            chain.chain_head_block = head.map(|ptr| ptr.into());
+        }
+        Ok(info)
+    }
+
     fn api_schema(&self) -> Result<Arc<ApiSchema>, QueryExecutionError> {
         let info = self.store.subgraph_info(&self.site.deployment)?;
         Ok(info.api)
@@ -114,4 +254,14 @@ impl QueryStoreTrait for QueryStore {
     fn network_name(&self) -> &str {
         &self.site.network
     }
+
+    fn pin_latest_block(
+        &self,
+    ) -> Result<Arc<dyn QueryStoreTrait + Send + Sync>, QueryExecutionError> {
+        let pinned = self.head().map_err(StoreError::from)?;
+        Ok(Arc::new(QueryStore {
+            pinned,
+            ..self.clone()
+        }))
+    }
 }
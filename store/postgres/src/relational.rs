@@ -24,25 +24,33 @@ use std::time::{Duration, Instant};
 use crate::{
     primary::{Namespace, METADATA_NAMESPACE},
     relational_queries::{
-        self as rq, ClampRangeQuery, ConflictingEntityQuery, DeleteByPrefixQuery,
-        DeleteDynamicDataSourcesQuery, DeleteQuery, EntityData, FilterCollection, FilterQuery,
-        FindManyQuery, FindQuery, InsertQuery, RevertClampQuery, RevertRemoveQuery, UpdateQuery,
+        self as rq, AggregateData, AggregateQuery, ClampRangeQuery, CoarsenHistoryQuery,
+        ConflictingEntityQuery, CountData, CountQuery, DeclareCursorQuery, DeleteByPrefixQuery,
+        DeleteDynamicDataSourcesQuery, DeleteQuery, DigestData, DigestQuery, EntityData,
+        ExecutePreparedQuery, ExplainData, ExplainQuery, FilterCollection, FilterQuery,
+        FindManyQuery, FindQuery, FromColumnValue, IncrementQuery, InsertQuery, PruneExpiredQuery,
+        PruneHistoryQuery, QueryFilter, RevertClampQuery, RevertRemoveQuery, UpdateQuery,
     },
+    statement_cache,
 };
+use graph::components::server::index_node::{ColumnStorage, IndexStorage, TableStorage};
 use graph::components::store::EntityType;
 use graph::data::schema::{FulltextConfig, FulltextDefinition, Schema, SCHEMA_TYPE_NAME};
 use graph::data::store::BYTES_SCALAR;
 use graph::data::subgraph::schema::{
     DynamicEthereumContractDataSourceEntity, POI_OBJECT, POI_TABLE,
 };
+use graph::data::subgraph::status::EntityDigest;
 use graph::data::{
-    graphql::ext::{DocumentExt, ObjectTypeExt},
+    graphql::expression::Expression,
+    graphql::ext::{DocumentExt, FieldExt, ObjectTypeExt},
     subgraph::schema::MetadataType,
 };
 use graph::prelude::{
-    anyhow, info, BlockNumber, Entity, EntityChange, EntityChangeOperation, EntityCollection,
-    EntityFilter, EntityKey, EntityOrder, EntityRange, EthereumBlockPointer, Logger,
-    QueryExecutionError, StoreError, StoreEvent, SubgraphDeploymentId, Value, ValueType,
+    anyhow, info, serde_json, warn, AggregateFunction, AggregateRow, BlockNumber, Entity,
+    EntityAggregationQuery, EntityChange, EntityChangeOperation, EntityCollection, EntityFilter,
+    EntityKey, EntityOrder, EntityRange, EthereumBlockPointer, HistogramVec, Logger,
+    QueryExecutionError, QueryPlan, StoreError, StoreEvent, SubgraphDeploymentId, Value, ValueType,
     BLOCK_NUMBER_MAX,
 };
 
@@ -66,6 +74,18 @@ lazy_static! {
             .unwrap_or(HashSet::new())
     };
 
+    /// Experimental: a list of fully qualified table names whose entity
+    /// versions are mirrored into a columnar table for analytical queries.
+    /// See `columnar.rs` for details
+    ///
+    /// Example: GRAPH_COLUMNAR_TABLES=sgd21902.pair,sgd1708.things
+    pub(crate) static ref COLUMNAR_TABLES: HashSet<String> = {
+        env::var("GRAPH_COLUMNAR_TABLES")
+            .ok()
+            .map(|v| v.split(",").map(|s| s.to_owned()).collect())
+            .unwrap_or(HashSet::new())
+    };
+
     pub static ref METADATA_LAYOUT: Arc<Layout> = {
         const SUBGRAPHS_SCHEMA: &str = include_str!("subgraphs.graphql");
         // This is pretty awful: we need to have some deployment id so
@@ -352,6 +372,7 @@ impl Layout {
                     ))),
                     column_type: ColumnType::Bytes,
                     fulltext_fields: None,
+                    renamed_from: None,
                     is_reference: false,
                 },
                 Column {
@@ -362,6 +383,7 @@ impl Layout {
                     ))),
                     column_type: ColumnType::String,
                     fulltext_fields: None,
+                    renamed_from: None,
                     is_reference: false,
                 },
             ],
@@ -369,7 +391,10 @@ impl Layout {
             /// is really only needed for the tests to make the names of indexes
             /// predictable
             position: position as u32,
+            expression_columns: vec![],
             is_account_like: false,
+            is_columnar: false,
+            ttl: None,
         }
     }
 
@@ -448,7 +473,7 @@ impl Layout {
         let start = Instant::now();
         let block_to_revert: BlockNumber = (block.number + 1)
             .try_into()
-            .expect("block numbers fit into an i32");
+            .expect("block numbers fit into an i64");
         self.revert_block(conn, dest_subgraph, block_to_revert)?;
         METADATA_LAYOUT.revert_metadata(conn, dest_subgraph, block_to_revert)?;
         info!(logger, "Rewound subgraph to block {}", block.number;
@@ -606,7 +631,8 @@ impl Layout {
             .map(|data| data.entity))
     }
 
-    /// order is a tuple (attribute, value_type, direction)
+    /// order is a tuple (attribute, value_type, direction). If `selected` is
+    /// given, only these attributes and the `id` of entities are returned
     pub fn query<T: crate::relational_queries::FromEntityData>(
         &self,
         logger: &Logger,
@@ -615,8 +641,11 @@ impl Layout {
         filter: Option<EntityFilter>,
         order: EntityOrder,
         range: EntityRange,
+        distinct: bool,
+        selected: Option<BTreeSet<String>>,
         block: BlockNumber,
         query_id: Option<String>,
+        timing: Option<&HistogramVec>,
     ) -> Result<Vec<T>, QueryExecutionError> {
         fn log_query_timing(
             logger: &Logger,
@@ -639,35 +668,59 @@ impl Layout {
                 text.truncate(MAXLEN);
                 text.push_str(" ...");
             }
+            let fingerprint = query
+                .fingerprint()
+                .map(|fp| fp.hash.as_str())
+                .unwrap_or("none");
             info!(
                 logger,
                 "Query timing (SQL)";
                 "query" => text,
                 "time_ms" => elapsed.as_millis(),
-                "entity_count" => entity_count
+                "entity_count" => entity_count,
+                "fingerprint" => fingerprint
             );
         }
 
-        let filter_collection = FilterCollection::new(&self, collection, filter.as_ref())?;
+        let filter_collection = FilterCollection::new(&self, collection, filter.as_ref(), block)?;
         let query = FilterQuery::new(
             &filter_collection,
             filter.as_ref(),
             order,
             range,
+            distinct,
             block,
             query_id,
-        )?;
+        )?
+        .select(selected.as_ref())
+        .with_fingerprint(self.catalog.namespace.as_str())
+        .map_err(|e| QueryExecutionError::ResolveEntitiesError(e.to_string()))?;
         let query_clone = query.clone();
 
         let start = Instant::now();
-        let values = query.load::<EntityData>(conn).map_err(|e| {
+        let values = match self.prepare_query(logger, conn, &query) {
+            Some((name, bind_count)) => {
+                ExecutePreparedQuery::new(name, bind_count, query).load::<EntityData>(conn)
+            }
+            None => query.load::<EntityData>(conn),
+        }
+        .map_err(|e| {
+            if QueryExecutionError::is_statement_timeout(&e) {
+                return QueryExecutionError::Timeout;
+            }
             QueryExecutionError::ResolveEntitiesError(format!(
                 "{}, query = {:?}",
                 e,
                 debug_query(&query_clone).to_string()
             ))
         })?;
-        log_query_timing(logger, &query_clone, start.elapsed(), values.len());
+        let elapsed = start.elapsed();
+        if let (Some(timing), Some(fp)) = (timing, query_clone.fingerprint()) {
+            timing
+                .with_label_values(&[&fp.namespace, &fp.entity_types, &fp.hash])
+                .observe(elapsed.as_secs_f64());
+        }
+        log_query_timing(logger, &query_clone, elapsed, values.len());
         values
             .into_iter()
             .map(|entity_data| {
@@ -678,6 +731,334 @@ impl Layout {
             .collect()
     }
 
+    /// If reuse of prepared statements is turned on, make sure the shape of
+    /// `query` is prepared on `conn` and return the name of the statement
+    /// together with its number of bind parameters. Return `None` if the
+    /// query should be run as is
+    fn prepare_query(
+        &self,
+        logger: &Logger,
+        conn: &PgConnection,
+        query: &FilterQuery,
+    ) -> Option<(String, usize)> {
+        if !statement_cache::enabled() {
+            return None;
+        }
+        let fingerprint = query.fingerprint()?;
+        let name = statement_cache::statement_name(&fingerprint.hash);
+        let prepared = query.shape_sql().and_then(|sql| {
+            statement_cache::prepare(conn, &name, &sql)?;
+            Ok(statement_cache::bind_count(&sql))
+        });
+        match prepared {
+            Ok(bind_count) => Some((name, bind_count)),
+            Err(e) => {
+                warn!(logger, "Failed to prepare query, running it unprepared";
+                      "fingerprint" => &fingerprint.hash,
+                      "error" => e.to_string());
+                None
+            }
+        }
+    }
+
+    /// Like `query`, but instead of returning all matching entities at once,
+    /// read them in batches of `batch_size` from a server-side cursor and
+    /// pass each of them to `sink`. Stops early when `sink` returns `false`.
+    /// The cursor only lives until the end of the current transaction, and
+    /// this method must therefore be called inside one
+    pub fn query_stream<F>(
+        &self,
+        conn: &PgConnection,
+        collection: EntityCollection,
+        filter: Option<EntityFilter>,
+        order: EntityOrder,
+        range: EntityRange,
+        distinct: bool,
+        block: BlockNumber,
+        batch_size: usize,
+        mut sink: F,
+    ) -> Result<(), QueryExecutionError>
+    where
+        F: FnMut(Entity) -> bool,
+    {
+        const CURSOR: &str = "entity_stream";
+
+        let filter_collection = FilterCollection::new(&self, collection, filter.as_ref(), block)?;
+        let query = FilterQuery::new(
+            &filter_collection,
+            filter.as_ref(),
+            order,
+            range,
+            distinct,
+            block,
+            None,
+        )?;
+        let declare = DeclareCursorQuery::new(CURSOR, query);
+        declare.clone().execute(conn).map_err(|e| {
+            QueryExecutionError::ResolveEntitiesError(format!(
+                "{}, query = {:?}",
+                e,
+                debug_query(&declare).to_string()
+            ))
+        })?;
+
+        let fetch = format!("fetch forward {} from {}", batch_size, CURSOR);
+        'fetch: loop {
+            let batch = diesel::sql_query(&fetch)
+                .load::<EntityData>(conn)
+                .map_err(StoreError::from)?;
+            let done = batch.len() < batch_size;
+            for entity_data in batch {
+                if !sink(entity_data.deserialize_with_layout(self)?) {
+                    break 'fetch;
+                }
+            }
+            if done {
+                break;
+            }
+        }
+        diesel::sql_query(format!("close {}", CURSOR))
+            .execute(conn)
+            .map_err(StoreError::from)?;
+        Ok(())
+    }
+
+    /// Return the SQL that `query` would run for the same arguments and
+    /// the plan that Postgres would use for it, without running it
+    pub fn explain(
+        &self,
+        conn: &PgConnection,
+        collection: EntityCollection,
+        filter: Option<EntityFilter>,
+        order: EntityOrder,
+        range: EntityRange,
+        distinct: bool,
+        block: BlockNumber,
+    ) -> Result<QueryPlan, QueryExecutionError> {
+        let filter_collection = FilterCollection::new(&self, collection, filter.as_ref(), block)?;
+        let query = FilterQuery::new(
+            &filter_collection,
+            filter.as_ref(),
+            order,
+            range,
+            distinct,
+            block,
+            None,
+        )?;
+        let sql = debug_query(&query).to_string();
+        let explain = ExplainQuery::new(query);
+        let plan = explain
+            .clone()
+            .load::<ExplainData>(conn)
+            .map_err(|e| {
+                QueryExecutionError::ResolveEntitiesError(format!(
+                    "{}, query = {:?}",
+                    e,
+                    debug_query(&explain).to_string()
+                ))
+            })?
+            .pop()
+            .map(|data| data.plan)
+            .unwrap_or(serde_json::Value::Null);
+        Ok(QueryPlan { sql, plan })
+    }
+
+    /// Count the entities in `collection` that match `filter` as of
+    /// `block`. Only collections of all entities of some types can be
+    /// counted, not windows
+    pub fn count(
+        &self,
+        conn: &PgConnection,
+        collection: EntityCollection,
+        filter: Option<EntityFilter>,
+        block: BlockNumber,
+    ) -> Result<u64, QueryExecutionError> {
+        let filter_collection = FilterCollection::new(&self, collection, filter.as_ref(), block)?;
+        let query = CountQuery::new(&filter_collection, block)?;
+        let count = query
+            .clone()
+            .get_result::<CountData>(conn)
+            .map_err(|e| {
+                QueryExecutionError::ResolveEntitiesError(format!(
+                    "{}, query = {:?}",
+                    e,
+                    debug_query(&query).to_string()
+                ))
+            })?
+            .count;
+        Ok(count as u64)
+    }
+
+    pub fn aggregate(
+        &self,
+        conn: &PgConnection,
+        query: EntityAggregationQuery,
+    ) -> Result<Vec<AggregateRow>, QueryExecutionError> {
+        use AggregateFunction::*;
+
+        let table = self.table_for_entity(&query.entity_type)?.as_ref();
+        let column = query
+            .attribute
+            .as_ref()
+            .map(|attr| table.column_for_field(attr))
+            .transpose()?;
+        let group_by = query
+            .group_by
+            .as_ref()
+            .map(|attr| table.column_for_field(attr))
+            .transpose()?;
+
+        let not_supported = |column: &Column| {
+            QueryExecutionError::NotSupported(format!(
+                "can not compute `{}` of attribute `{}` of `{}`",
+                query.function.as_str(),
+                column.field,
+                query.entity_type
+            ))
+        };
+        // The type of the values that the aggregate function produces
+        let value_type = match (query.function, column) {
+            (Count, _) => ColumnType::Int,
+            (_, None) => {
+                return Err(QueryExecutionError::NotSupported(format!(
+                    "`{}` needs an attribute to aggregate",
+                    query.function.as_str()
+                )))
+            }
+            (_, Some(column)) if column.is_list() => return Err(not_supported(column)),
+            (Sum, Some(column)) | (Avg, Some(column)) => {
+                match (query.function, &column.column_type) {
+                    (Sum, ColumnType::Int) | (Sum, ColumnType::BigInt) => ColumnType::BigInt,
+                    (_, ColumnType::Int)
+                    | (_, ColumnType::BigInt)
+                    | (_, ColumnType::BigDecimal) => ColumnType::BigDecimal,
+                    _ => return Err(not_supported(column)),
+                }
+            }
+            (Min, Some(column)) | (Max, Some(column)) => match &column.column_type {
+                ColumnType::Boolean | ColumnType::TSVector(_) => return Err(not_supported(column)),
+                column_type => column_type.clone(),
+            },
+        };
+        if let Some(column) = group_by {
+            if let ColumnType::TSVector(_) = column.column_type {
+                return Err(QueryExecutionError::NotSupported(format!(
+                    "can not group by attribute `{}` of `{}`",
+                    column.field, query.entity_type
+                )));
+            }
+        }
+
+        let filter = query
+            .filter
+            .as_ref()
+            .map(|filter| QueryFilter::new(filter, self, table, query.block))
+            .transpose()?;
+        // Aggregations scan many rows and are faster on a columnar mirror
+        let mirror = crate::columnar::mirror_for(conn, self, table)?;
+        let aggregate_query = AggregateQuery {
+            table,
+            source: mirror
+                .as_deref()
+                .unwrap_or_else(|| table.qualified_name.as_str()),
+            function: query.function,
+            column,
+            group_by,
+            filter,
+            block: query.block,
+        };
+        let rows = aggregate_query
+            .clone()
+            .load::<AggregateData>(conn)
+            .map_err(|e| {
+                QueryExecutionError::ResolveEntitiesError(format!(
+                    "{}, query = {:?}",
+                    e,
+                    debug_query(&aggregate_query).to_string()
+                ))
+            })?;
+        rows.into_iter()
+            .map(|row| {
+                let group = match group_by {
+                    Some(column) => Some(Value::from_column_value(
+                        &column.column_type,
+                        row.group_value.unwrap_or(serde_json::Value::Null),
+                    )?),
+                    None => None,
+                };
+                let value = Value::from_column_value(
+                    &value_type,
+                    row.value.unwrap_or(serde_json::Value::Null),
+                )?;
+                Ok(AggregateRow { group, value })
+            })
+            .collect()
+    }
+
+    /// Compute a digest of the entities of each type as of `block`. The
+    /// result is keyed by the name of the entity type
+    pub fn entity_digests(
+        &self,
+        conn: &PgConnection,
+        block: BlockNumber,
+    ) -> Result<BTreeMap<String, EntityDigest>, StoreError> {
+        let mut digests = BTreeMap::new();
+        for table in self.tables.values() {
+            let data = DigestQuery::new(table, block).get_result::<DigestData>(conn)?;
+            digests.insert(
+                table.object.to_string(),
+                EntityDigest {
+                    count: data.count as u64,
+                    digest: data.digest,
+                },
+            );
+        }
+        Ok(digests)
+    }
+
+    /// Describe how the entities of each type are stored, sorted by the
+    /// name of the entity type
+    pub fn table_storage(&self, conn: &PgConnection) -> Result<Vec<TableStorage>, StoreError> {
+        let mut indexes = crate::catalog::indexes(conn, &self.catalog.namespace)?;
+        let sizes = crate::catalog::table_sizes(conn, &self.catalog.namespace)?;
+
+        let mut tables: Vec<_> = self
+            .tables
+            .values()
+            .map(|table| {
+                let columns = table
+                    .columns
+                    .iter()
+                    .map(|column| ColumnStorage {
+                        field: column.field.clone(),
+                        column: column.name.to_string(),
+                        sql_type: column.column_type.sql_type().to_owned(),
+                        nullable: column.is_nullable(),
+                        list: column.is_list(),
+                    })
+                    .collect();
+                let indexes = indexes
+                    .remove(table.name.as_str())
+                    .unwrap_or_default()
+                    .into_iter()
+                    .map(|(name, definition)| IndexStorage { name, definition })
+                    .collect();
+                let (row_estimate, total_bytes) =
+                    sizes.get(table.name.as_str()).cloned().unwrap_or((0, 0));
+                TableStorage {
+                    entity_type: table.object.clone(),
+                    table: table.qualified_name.to_string(),
+                    columns,
+                    indexes,
+                    row_estimate,
+                    total_bytes,
+                }
+            })
+            .collect();
+        tables.sort_by(|a, b| a.entity_type.cmp(&b.entity_type));
+        Ok(tables)
+    }
+
     pub fn update(
         &self,
         conn: &PgConnection,
@@ -692,6 +1073,21 @@ impl Layout {
         Ok(())
     }
 
+    /// Add `deltas` to the attributes of the entity in the store without
+    /// reading it. Returns the number of entities that were changed, i.e.,
+    /// 0 if the entity does not exist
+    pub fn increment(
+        &self,
+        conn: &PgConnection,
+        key: &EntityKey,
+        deltas: &BTreeMap<String, Value>,
+        block: BlockNumber,
+    ) -> Result<usize, StoreError> {
+        let table = self.table_for_entity(&key.entity_type.expect_data())?;
+        let query = IncrementQuery::new(table, key, deltas, block)?;
+        Ok(query.execute(conn)?)
+    }
+
     pub fn update_unversioned(
         &self,
         conn: &PgConnection,
@@ -740,6 +1136,72 @@ impl Layout {
         Ok(DeleteQuery::new(table, key).execute(conn)?)
     }
 
+    /// Delete all versions of entities with a `@ttl` that expired before
+    /// `block`, i.e., that have not been changed for `ttl` blocks before
+    /// it. Since pruned versions can not be reverted, `block` must be
+    /// outside of the reorg window. Return the number of deleted versions
+    pub fn prune_expired(
+        &self,
+        conn: &PgConnection,
+        block: BlockNumber,
+    ) -> Result<usize, StoreError> {
+        let mut count = 0;
+        for table in self.tables.values() {
+            let cutoff = match table.ttl {
+                Some(ttl) if block > ttl => block - ttl,
+                _ => continue,
+            };
+            count += PruneExpiredQuery::new(table, cutoff).execute(conn)?;
+        }
+        Ok(count)
+    }
+
+    /// Delete the versions of entities that were replaced at a block in
+    /// `[from, to)` and that queries at blocks that are multiples of
+    /// `granularity` do not see. Since deleted versions can not be
+    /// reverted, `to` must be outside of the reorg window. Return the
+    /// number of deleted versions
+    pub fn coarsen_history(
+        &self,
+        conn: &PgConnection,
+        granularity: BlockNumber,
+        from: BlockNumber,
+        to: BlockNumber,
+    ) -> Result<usize, StoreError> {
+        let mut count = 0;
+        for table in self.tables.values() {
+            count += CoarsenHistoryQuery::new(table, granularity, from, to).execute(conn)?;
+        }
+        Ok(count)
+    }
+
+    /// Delete the versions of entities that were replaced before `block`.
+    /// Since deleted versions can not be reverted, the deployment must
+    /// never revert past `block`. Return the number of deleted versions
+    pub fn prune_history(
+        &self,
+        conn: &PgConnection,
+        block: BlockNumber,
+    ) -> Result<usize, StoreError> {
+        let mut count = 0;
+        for table in self.tables.values() {
+            count += PruneHistoryQuery::new(table, block).execute(conn)?;
+        }
+        Ok(count)
+    }
+
+    /// The number of entity versions that Postgres estimates the tables
+    /// of this layout to hold
+    pub fn estimated_versions(&self, conn: &PgConnection) -> Result<u64, StoreError> {
+        let sizes = crate::catalog::table_sizes(conn, &self.catalog.namespace)?;
+        Ok(self
+            .tables
+            .values()
+            .filter_map(|table| sizes.get(table.name.as_str()))
+            .map(|(rows, _)| (*rows).max(0) as u64)
+            .sum())
+    }
+
     pub fn revert_block(
         &self,
         conn: &PgConnection,
@@ -846,6 +1308,7 @@ impl Layout {
         }
 
         crate::deployment::revert_subgraph_errors(conn, &subgraph, block)?;
+        crate::deployment::revert_skipped_triggers(conn, &subgraph, block)?;
 
         Ok(())
     }
@@ -1021,6 +1484,35 @@ impl ColumnType {
     }
 }
 
+/// A field with a `@derivedExpression` directive. Its value is not stored
+/// but computed from the other columns of the row whenever it is queried
+#[derive(Clone, Debug)]
+pub struct ExpressionColumn {
+    pub column: Column,
+    pub expression: Expression,
+}
+
+impl ExpressionColumn {
+    fn new(
+        table_name: &SqlName,
+        field: &s::Field,
+        expression: &str,
+        catalog: &Catalog,
+        enums: &EnumMap,
+        id_types: &IdTypeMap,
+    ) -> Result<ExpressionColumn, StoreError> {
+        let column = Column::new(table_name, field, catalog, enums, id_types)?;
+        let expression = Expression::from_str(expression).map_err(|e| {
+            StoreError::Unknown(anyhow!(
+                "invalid @derivedExpression for {}: {}",
+                field.name,
+                e
+            ))
+        })?;
+        Ok(ExpressionColumn { column, expression })
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct Column {
     pub name: SqlName,
@@ -1028,6 +1520,9 @@ pub struct Column {
     pub field_type: q::Type,
     pub column_type: ColumnType,
     pub fulltext_fields: Option<HashSet<String>>,
+    /// The name the field had before it was renamed with `@renamed`;
+    /// queries can still use that name to refer to this column
+    pub renamed_from: Option<String>,
     is_reference: bool,
 }
 
@@ -1063,6 +1558,7 @@ impl Column {
             column_type,
             field_type: field.field_type.clone(),
             fulltext_fields: None,
+            renamed_from: field.renamed_from().cloned(),
             is_reference,
         })
     }
@@ -1077,6 +1573,7 @@ impl Column {
             field_type: q::Type::NamedType(String::from("fulltext".to_string())),
             column_type: ColumnType::TSVector(def.config.clone()),
             fulltext_fields: Some(def.included_fields.clone()),
+            renamed_from: None,
             is_reference: false,
         })
     }
@@ -1202,11 +1699,23 @@ pub struct Table {
 
     pub columns: Vec<Column>,
 
+    /// The fields whose values are computed at query time from the
+    /// `columns` with a `@derivedExpression`
+    pub expression_columns: Vec<ExpressionColumn>,
+
     /// This kind of entity behaves like an account in that it has a low
     /// ratio of distinct entities to overall number of rows because
     /// entities are updated frequently on average
     pub is_account_like: bool,
 
+    /// Whether the versions of entities in this table are mirrored into a
+    /// columnar table
+    pub is_columnar: bool,
+
+    /// The number of blocks after their last change that entities in this
+    /// table expire, from the `@ttl` directive of the entity type
+    pub ttl: Option<BlockNumber>,
+
     /// The position of this table in all the tables for this layout; this
     /// is really only needed for the tests to make the names of indexes
     /// predictable
@@ -1228,18 +1737,32 @@ impl Table {
         let columns = defn
             .fields
             .iter()
-            .filter(|field| !derived_column(field))
+            .filter(|field| !derived_column(field) && field.derived_expression().is_none())
             .map(|field| Column::new(&table_name, field, catalog, enums, id_types))
             .chain(fulltexts.iter().map(|def| Column::new_fulltext(def)))
             .collect::<Result<Vec<Column>, StoreError>>()?;
+        let expression_columns = defn
+            .fields
+            .iter()
+            .filter_map(|field| {
+                field.derived_expression().map(|expression| {
+                    ExpressionColumn::new(&table_name, field, expression, catalog, enums, id_types)
+                })
+            })
+            .collect::<Result<Vec<_>, StoreError>>()?;
         let is_account_like =
             ACCOUNT_TABLES.contains(&format!("{}.{}", catalog.namespace, table_name));
+        let is_columnar =
+            COLUMNAR_TABLES.contains(&format!("{}.{}", catalog.namespace, table_name));
         let table = Table {
             object: defn.name.clone(),
             name: table_name.clone(),
             qualified_name: SqlName::qualified_name(&catalog.namespace, &table_name),
             is_account_like,
+            is_columnar,
+            ttl: Schema::ttl(defn),
             columns,
+            expression_columns,
             position,
         };
         Ok(table)
@@ -1257,12 +1780,27 @@ impl Table {
             .find(|column| &column.name == name)
     }
 
+    /// Find the expression column `name` in this table. The name must be
+    /// in snake case, i.e., use SQL conventions
+    pub fn expression_column(&self, name: &SqlName) -> Option<&Column> {
+        self.expression_columns
+            .iter()
+            .map(|expression_column| &expression_column.column)
+            .find(|column| &column.name == name)
+    }
+
     /// Find the column for `field` in this table. The name must be the
-    /// GraphQL name of an entity field
+    /// GraphQL name of an entity field, or the name it had before it was
+    /// renamed
     pub fn column_for_field(&self, field: &str) -> Result<&Column, StoreError> {
         self.columns
             .iter()
             .find(|column| &column.field == field)
+            .or_else(|| {
+                self.columns
+                    .iter()
+                    .find(|column| column.renamed_from.as_deref() == Some(field))
+            })
             .ok_or_else(|| StoreError::UnknownField(field.to_string()))
     }
 
@@ -1314,7 +1852,7 @@ impl Table {
         write!(
             out,
             "\n        {vid}                  bigserial primary key,\
-             \n        {block_range}          int4range not null,
+             \n        {block_range}          int8range not null,
         exclude using gist   (id with =, {block_range} with &&)\n);\n",
             vid = VID_COLUMN,
             block_range = BLOCK_RANGE_COLUMN
@@ -1602,15 +2140,15 @@ create table sgd0815.\"thing\" (
         \"big_thing\"          text not null,
 
         vid                  bigserial primary key,
-        block_range          int4range not null,
+        block_range          int8range not null,
         exclude using gist   (id with =, block_range with &&)
 );
 create index brin_thing
     on sgd0815.thing
- using brin(lower(block_range), coalesce(upper(block_range), 2147483647), vid);
+ using brin(lower(block_range), coalesce(upper(block_range), 9223372036854775807), vid);
 create index thing_block_range_closed
-    on sgd0815.thing(coalesce(upper(block_range), 2147483647))
- where coalesce(upper(block_range), 2147483647) < 2147483647;
+    on sgd0815.thing(coalesce(upper(block_range), 9223372036854775807))
+ where coalesce(upper(block_range), 9223372036854775807) < 9223372036854775807;
 create index attr_0_0_thing_id
     on sgd0815.\"thing\" using btree(\"id\");
 create index attr_0_1_thing_big_thing
@@ -1627,15 +2165,15 @@ create table sgd0815.\"scalar\" (
         \"color\"              \"sgd0815\".\"color\",
 
         vid                  bigserial primary key,
-        block_range          int4range not null,
+        block_range          int8range not null,
         exclude using gist   (id with =, block_range with &&)
 );
 create index brin_scalar
     on sgd0815.scalar
- using brin(lower(block_range), coalesce(upper(block_range), 2147483647), vid);
+ using brin(lower(block_range), coalesce(upper(block_range), 9223372036854775807), vid);
 create index scalar_block_range_closed
-    on sgd0815.scalar(coalesce(upper(block_range), 2147483647))
- where coalesce(upper(block_range), 2147483647) < 2147483647;
+    on sgd0815.scalar(coalesce(upper(block_range), 9223372036854775807))
+ where coalesce(upper(block_range), 9223372036854775807) < 9223372036854775807;
 create index attr_1_0_scalar_id
     on sgd0815.\"scalar\" using btree(\"id\");
 create index attr_1_1_scalar_bool
@@ -1689,15 +2227,15 @@ type SongStat @entity {
         \"bands\"              text[] not null,
 
         vid                  bigserial primary key,
-        block_range          int4range not null,
+        block_range          int8range not null,
         exclude using gist   (id with =, block_range with &&)
 );
 create index brin_musician
     on sgd0815.musician
- using brin(lower(block_range), coalesce(upper(block_range), 2147483647), vid);
+ using brin(lower(block_range), coalesce(upper(block_range), 9223372036854775807), vid);
 create index musician_block_range_closed
-    on sgd0815.musician(coalesce(upper(block_range), 2147483647))
- where coalesce(upper(block_range), 2147483647) < 2147483647;
+    on sgd0815.musician(coalesce(upper(block_range), 9223372036854775807))
+ where coalesce(upper(block_range), 9223372036854775807) < 9223372036854775807;
 create index attr_0_0_musician_id
     on sgd0815.\"musician\" using btree(\"id\");
 create index attr_0_1_musician_name
@@ -1713,15 +2251,15 @@ create table sgd0815.\"band\" (
         \"original_songs\"     text[] not null,
 
         vid                  bigserial primary key,
-        block_range          int4range not null,
+        block_range          int8range not null,
         exclude using gist   (id with =, block_range with &&)
 );
 create index brin_band
     on sgd0815.band
- using brin(lower(block_range), coalesce(upper(block_range), 2147483647), vid);
+ using brin(lower(block_range), coalesce(upper(block_range), 9223372036854775807), vid);
 create index band_block_range_closed
-    on sgd0815.band(coalesce(upper(block_range), 2147483647))
- where coalesce(upper(block_range), 2147483647) < 2147483647;
+    on sgd0815.band(coalesce(upper(block_range), 9223372036854775807))
+ where coalesce(upper(block_range), 9223372036854775807) < 9223372036854775807;
 create index attr_1_0_band_id
     on sgd0815.\"band\" using btree(\"id\");
 create index attr_1_1_band_name
@@ -1735,15 +2273,15 @@ create table sgd0815.\"song\" (
         \"written_by\"         text not null,
 
         vid                  bigserial primary key,
-        block_range          int4range not null,
+        block_range          int8range not null,
         exclude using gist   (id with =, block_range with &&)
 );
 create index brin_song
     on sgd0815.song
- using brin(lower(block_range), coalesce(upper(block_range), 2147483647), vid);
+ using brin(lower(block_range), coalesce(upper(block_range), 9223372036854775807), vid);
 create index song_block_range_closed
-    on sgd0815.song(coalesce(upper(block_range), 2147483647))
- where coalesce(upper(block_range), 2147483647) < 2147483647;
+    on sgd0815.song(coalesce(upper(block_range), 9223372036854775807))
+ where coalesce(upper(block_range), 9223372036854775807) < 9223372036854775807;
 create index attr_2_0_song_id
     on sgd0815.\"song\" using btree(\"id\");
 create index attr_2_1_song_title
@@ -1756,15 +2294,15 @@ create table sgd0815.\"song_stat\" (
         \"played\"             integer not null,
 
         vid                  bigserial primary key,
-        block_range          int4range not null,
+        block_range          int8range not null,
         exclude using gist   (id with =, block_range with &&)
 );
 create index brin_song_stat
     on sgd0815.song_stat
- using brin(lower(block_range), coalesce(upper(block_range), 2147483647), vid);
+ using brin(lower(block_range), coalesce(upper(block_range), 9223372036854775807), vid);
 create index song_stat_block_range_closed
-    on sgd0815.song_stat(coalesce(upper(block_range), 2147483647))
- where coalesce(upper(block_range), 2147483647) < 2147483647;
+    on sgd0815.song_stat(coalesce(upper(block_range), 9223372036854775807))
+ where coalesce(upper(block_range), 9223372036854775807) < 9223372036854775807;
 create index attr_3_0_song_stat_id
     on sgd0815.\"song_stat\" using btree(\"id\");
 create index attr_3_1_song_stat_played
@@ -1798,15 +2336,15 @@ type Habitat @entity {
         \"forest\"             text,
 
         vid                  bigserial primary key,
-        block_range          int4range not null,
+        block_range          int8range not null,
         exclude using gist   (id with =, block_range with &&)
 );
 create index brin_animal
     on sgd0815.animal
- using brin(lower(block_range), coalesce(upper(block_range), 2147483647), vid);
+ using brin(lower(block_range), coalesce(upper(block_range), 9223372036854775807), vid);
 create index animal_block_range_closed
-    on sgd0815.animal(coalesce(upper(block_range), 2147483647))
- where coalesce(upper(block_range), 2147483647) < 2147483647;
+    on sgd0815.animal(coalesce(upper(block_range), 9223372036854775807))
+ where coalesce(upper(block_range), 9223372036854775807) < 9223372036854775807;
 create index attr_0_0_animal_id
     on sgd0815.\"animal\" using btree(\"id\");
 create index attr_0_1_animal_forest
@@ -1816,15 +2354,15 @@ create table sgd0815.\"forest\" (
         \"id\"                 text not null,
 
         vid                  bigserial primary key,
-        block_range          int4range not null,
+        block_range          int8range not null,
         exclude using gist   (id with =, block_range with &&)
 );
 create index brin_forest
     on sgd0815.forest
- using brin(lower(block_range), coalesce(upper(block_range), 2147483647), vid);
+ using brin(lower(block_range), coalesce(upper(block_range), 9223372036854775807), vid);
 create index forest_block_range_closed
-    on sgd0815.forest(coalesce(upper(block_range), 2147483647))
- where coalesce(upper(block_range), 2147483647) < 2147483647;
+    on sgd0815.forest(coalesce(upper(block_range), 9223372036854775807))
+ where coalesce(upper(block_range), 9223372036854775807) < 9223372036854775807;
 create index attr_1_0_forest_id
     on sgd0815.\"forest\" using btree(\"id\");
 
@@ -1834,15 +2372,15 @@ create table sgd0815.\"habitat\" (
         \"dwellers\"           text[] not null,
 
         vid                  bigserial primary key,
-        block_range          int4range not null,
+        block_range          int8range not null,
         exclude using gist   (id with =, block_range with &&)
 );
 create index brin_habitat
     on sgd0815.habitat
- using brin(lower(block_range), coalesce(upper(block_range), 2147483647), vid);
+ using brin(lower(block_range), coalesce(upper(block_range), 9223372036854775807), vid);
 create index habitat_block_range_closed
-    on sgd0815.habitat(coalesce(upper(block_range), 2147483647))
- where coalesce(upper(block_range), 2147483647) < 2147483647;
+    on sgd0815.habitat(coalesce(upper(block_range), 9223372036854775807))
+ where coalesce(upper(block_range), 9223372036854775807) < 9223372036854775807;
 create index attr_2_0_habitat_id
     on sgd0815.\"habitat\" using btree(\"id\");
 create index attr_2_1_habitat_most_common
@@ -1890,15 +2428,15 @@ type Habitat @entity {
         \"search\"             tsvector,
 
         vid                  bigserial primary key,
-        block_range          int4range not null,
+        block_range          int8range not null,
         exclude using gist   (id with =, block_range with &&)
 );
 create index brin_animal
     on sgd0815.animal
- using brin(lower(block_range), coalesce(upper(block_range), 2147483647), vid);
+ using brin(lower(block_range), coalesce(upper(block_range), 9223372036854775807), vid);
 create index animal_block_range_closed
-    on sgd0815.animal(coalesce(upper(block_range), 2147483647))
- where coalesce(upper(block_range), 2147483647) < 2147483647;
+    on sgd0815.animal(coalesce(upper(block_range), 9223372036854775807))
+ where coalesce(upper(block_range), 9223372036854775807) < 9223372036854775807;
 create index attr_0_0_animal_id
     on sgd0815.\"animal\" using btree(\"id\");
 create index attr_0_1_animal_name
@@ -1914,15 +2452,15 @@ create table sgd0815.\"forest\" (
         \"id\"                 text not null,
 
         vid                  bigserial primary key,
-        block_range          int4range not null,
+        block_range          int8range not null,
         exclude using gist   (id with =, block_range with &&)
 );
 create index brin_forest
     on sgd0815.forest
- using brin(lower(block_range), coalesce(upper(block_range), 2147483647), vid);
+ using brin(lower(block_range), coalesce(upper(block_range), 9223372036854775807), vid);
 create index forest_block_range_closed
-    on sgd0815.forest(coalesce(upper(block_range), 2147483647))
- where coalesce(upper(block_range), 2147483647) < 2147483647;
+    on sgd0815.forest(coalesce(upper(block_range), 9223372036854775807))
+ where coalesce(upper(block_range), 9223372036854775807) < 9223372036854775807;
 create index attr_1_0_forest_id
     on sgd0815.\"forest\" using btree(\"id\");
 
@@ -1932,15 +2470,15 @@ create table sgd0815.\"habitat\" (
         \"dwellers\"           text[] not null,
 
         vid                  bigserial primary key,
-        block_range          int4range not null,
+        block_range          int8range not null,
         exclude using gist   (id with =, block_range with &&)
 );
 create index brin_habitat
     on sgd0815.habitat
- using brin(lower(block_range), coalesce(upper(block_range), 2147483647), vid);
+ using brin(lower(block_range), coalesce(upper(block_range), 9223372036854775807), vid);
 create index habitat_block_range_closed
-    on sgd0815.habitat(coalesce(upper(block_range), 2147483647))
- where coalesce(upper(block_range), 2147483647) < 2147483647;
+    on sgd0815.habitat(coalesce(upper(block_range), 9223372036854775807))
+ where coalesce(upper(block_range), 9223372036854775807) < 9223372036854775807;
 create index attr_2_0_habitat_id
     on sgd0815.\"habitat\" using btree(\"id\");
 create index attr_2_1_habitat_most_common
@@ -1968,15 +2506,15 @@ create table sgd0815.\"thing\" (
         \"orientation\"        \"sgd0815\".\"orientation\" not null,
 
         vid                  bigserial primary key,
-        block_range          int4range not null,
+        block_range          int8range not null,
         exclude using gist   (id with =, block_range with &&)
 );
 create index brin_thing
     on sgd0815.thing
- using brin(lower(block_range), coalesce(upper(block_range), 2147483647), vid);
+ using brin(lower(block_range), coalesce(upper(block_range), 9223372036854775807), vid);
 create index thing_block_range_closed
-    on sgd0815.thing(coalesce(upper(block_range), 2147483647))
- where coalesce(upper(block_range), 2147483647) < 2147483647;
+    on sgd0815.thing(coalesce(upper(block_range), 9223372036854775807))
+ where coalesce(upper(block_range), 9223372036854775807) < 9223372036854775807;
 create index attr_0_0_thing_id
     on sgd0815.\"thing\" using btree(\"id\");
 create index attr_0_1_thing_orientation
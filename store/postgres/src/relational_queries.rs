@@ -5,25 +5,34 @@
 ///!
 ///! Code in this module works very hard to minimize the number of allocations
 ///! that it performs
-use diesel::pg::{Pg, PgConnection};
-use diesel::query_builder::{AstPass, QueryFragment, QueryId};
+use diesel::deserialize::{self, QueryableByName};
+use diesel::pg::{Pg, PgConnection, PgQueryBuilder};
+use diesel::query_builder::{AstPass, QueryBuilder, QueryFragment, QueryId};
 use diesel::query_dsl::{LoadQuery, RunQueryDsl};
 use diesel::result::{Error as DieselError, QueryResult};
-use diesel::sql_types::{Array, Binary, Bool, Integer, Jsonb, Range, Text};
+use diesel::row::NamedRow;
+use diesel::sql_types::{Array, BigInt, Binary, Bool, Integer, Json, Jsonb, Nullable, Range, Text};
 use diesel::Connection;
 use lazy_static::lazy_static;
-use std::collections::{BTreeMap, HashSet};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, BTreeSet, HashSet};
 use std::convert::TryFrom;
 use std::env;
 use std::fmt::{self, Display};
+use std::hash::{Hash, Hasher};
 use std::iter::FromIterator;
 use std::str::FromStr;
 
-use graph::data::{schema::FulltextAlgorithm, store::scalar};
+use graph::data::{
+    graphql::expression::Expression,
+    schema::{FulltextAlgorithm, LAST_UPDATED_BLOCK_ENABLED, LAST_UPDATED_BLOCK_FIELD},
+    store::scalar,
+};
 use graph::prelude::{
-    anyhow, q, serde_json, Attribute, BlockNumber, ChildMultiplicity, Entity, EntityCollection,
-    EntityFilter, EntityKey, EntityLink, EntityOrder, EntityRange, EntityWindow, ParentLink,
-    QueryExecutionError, StoreError, Value,
+    anyhow, q, serde_json, AggregateFunction, Attribute, BlockNumber, ChildLink, ChildMultiplicity,
+    Entity, EntityCollection, EntityFilter, EntityKey, EntityLink, EntityOrder, EntityRange,
+    EntityWindow, NullsPlacement, ParentLink, QueryExecutionError, StoreError, Value,
+    BLOCK_NUMBER_MAX,
 };
 
 use crate::entities::STRING_PREFIX_SIZE;
@@ -220,6 +229,10 @@ impl ForeignKeyClauses for Column {
 pub trait FromEntityData: Default + From<Entity> {
     type Value: FromColumnValue;
 
+    /// Whether to add fields that are only needed for GraphQL queries, like
+    /// `_lastUpdatedBlock` or the old names of renamed fields
+    const WITH_SYSTEM_FIELDS: bool = false;
+
     fn insert_entity_data(&mut self, key: String, v: Self::Value);
 }
 
@@ -234,12 +247,14 @@ impl FromEntityData for Entity {
 impl FromEntityData for BTreeMap<String, q::Value> {
     type Value = q::Value;
 
+    const WITH_SYSTEM_FIELDS: bool = true;
+
     fn insert_entity_data(&mut self, key: String, v: Self::Value) {
         self.insert(key, v);
     }
 }
 
-pub trait FromColumnValue: Sized {
+pub trait FromColumnValue: Sized + Clone {
     fn is_null(&self) -> bool;
 
     fn null() -> Self;
@@ -250,6 +265,9 @@ pub trait FromColumnValue: Sized {
 
     fn from_i32(i: i32) -> Self;
 
+    /// A block number, which the GraphQL API represents as a `BigInt`
+    fn from_block_number(block: BlockNumber) -> Self;
+
     fn from_big_decimal(d: scalar::BigDecimal) -> Self;
 
     fn from_big_int(i: serde_json::Number) -> Result<Self, StoreError>;
@@ -341,6 +359,10 @@ impl FromColumnValue for q::Value {
         q::Value::Int(i.into())
     }
 
+    fn from_block_number(block: BlockNumber) -> Self {
+        q::Value::String(block.to_string())
+    }
+
     fn from_big_decimal(d: scalar::BigDecimal) -> Self {
         q::Value::String(d.to_string())
     }
@@ -379,6 +401,10 @@ impl FromColumnValue for graph::prelude::Value {
         graph::prelude::Value::Int(i)
     }
 
+    fn from_block_number(block: BlockNumber) -> Self {
+        graph::prelude::Value::BigInt(scalar::BigInt::from(block))
+    }
+
     fn from_big_decimal(d: scalar::BigDecimal) -> Self {
         graph::prelude::Value::BigDecimal(d)
     }
@@ -437,11 +463,31 @@ impl EntityData {
                     if key == "g$parent_id" {
                         let value = T::Value::from_column_value(&ColumnType::String, json)?;
                         out.insert_entity_data("g$parent_id".to_owned(), value);
-                    } else if let Some(column) = table.column(&SqlName::verbatim(key)) {
+                    } else if let Some(column) = table
+                        .column(&SqlName::verbatim(key.clone()))
+                        .or_else(|| table.expression_column(&SqlName::verbatim(key.clone())))
+                    {
                         let value = T::Value::from_column_value(&column.column_type, json)?;
                         if !value.is_null() {
+                            match &column.renamed_from {
+                                Some(old_name) if T::WITH_SYSTEM_FIELDS => {
+                                    out.insert_entity_data(old_name.clone(), value.clone());
+                                }
+                                _ => {}
+                            }
                             out.insert_entity_data(column.field.clone(), value);
                         }
+                    } else if key == BLOCK_RANGE_COLUMN
+                        && T::WITH_SYSTEM_FIELDS
+                        && *LAST_UPDATED_BLOCK_ENABLED
+                    {
+                        let block = json.as_str().and_then(block_range_start).ok_or_else(|| {
+                            StoreError::Unknown(anyhow!("invalid block range `{}`", json))
+                        })?;
+                        out.insert_entity_data(
+                            LAST_UPDATED_BLOCK_FIELD.to_owned(),
+                            T::Value::from_block_number(block),
+                        );
                     }
                 }
                 Ok(out)
@@ -453,6 +499,15 @@ impl EntityData {
     }
 }
 
+/// Extract the lower bound from the JSON representation of a block range,
+/// which looks like `[5,)` or `[5,10)`
+fn block_range_start(range: &str) -> Option<BlockNumber> {
+    range
+        .strip_prefix('[')
+        .and_then(|range| range.split(',').next())
+        .and_then(|start| start.parse().ok())
+}
+
 /// A `QueryValue` makes it possible to bind a `Value` into a SQL query
 /// using the metadata from Column
 struct QueryValue<'a>(&'a Value, &'a ColumnType);
@@ -717,28 +772,76 @@ impl<'a> QueryFragment<Pg> for PrefixComparison<'a> {
 /// the `where` clause of a SQL query. The attributes mentioned in
 /// the `filter` must all come from the given `table`, which is used to
 /// map GraphQL names to column names, and to determine the type of the
-/// column an attribute refers to
+/// column an attribute refers to. Attributes in `Child` filters come from
+/// the table of the child type, which we look up in `layout`
 #[derive(Debug, Clone)]
 pub struct QueryFilter<'a> {
     filter: &'a EntityFilter,
     table: &'a Table,
+    layout: &'a Layout,
+    block: BlockNumber,
+    /// How many `Child` filters this filter is nested in. The table for
+    /// the outermost filter is always aliased as `c`, and the tables for
+    /// children as `i1`, `i2`, etc. depending on how deeply they are nested
+    depth: usize,
 }
 
 impl<'a> QueryFilter<'a> {
-    pub fn new(filter: &'a EntityFilter, table: &'a Table) -> Result<Self, StoreError> {
-        Self::valid_attributes(filter, table)?;
-        Ok(QueryFilter { filter, table })
+    pub fn new(
+        filter: &'a EntityFilter,
+        layout: &'a Layout,
+        table: &'a Table,
+        block: BlockNumber,
+    ) -> Result<Self, StoreError> {
+        Self::valid_attributes(filter, layout, table)?;
+        Ok(QueryFilter {
+            filter,
+            table,
+            layout,
+            block,
+            depth: 0,
+        })
     }
 
-    fn valid_attributes(filter: &'a EntityFilter, table: &'a Table) -> Result<(), StoreError> {
+    fn valid_attributes(
+        filter: &'a EntityFilter,
+        layout: &'a Layout,
+        table: &'a Table,
+    ) -> Result<(), StoreError> {
         use EntityFilter::*;
         match filter {
             And(filters) | Or(filters) => {
                 for filter in filters {
-                    Self::valid_attributes(filter, table)?;
+                    Self::valid_attributes(filter, layout, table)?;
                 }
             }
 
+            Child {
+                child_type,
+                filter,
+                link,
+            } => {
+                let child = layout.table_for_entity(child_type)?;
+                match link {
+                    ChildLink::Parent(attr) => table.column_for_field(attr)?,
+                    ChildLink::Child(attr) => child.column_for_field(attr)?,
+                };
+                Self::valid_attributes(filter, layout, child)?;
+            }
+
+            // `_lastUpdatedBlock` is not backed by a column, and only
+            // supports comparisons
+            Equal(attr, _)
+            | Not(attr, _)
+            | GreaterThan(attr, _)
+            | LessThan(attr, _)
+            | GreaterOrEqual(attr, _)
+            | LessOrEqual(attr, _)
+            | In(attr, _)
+            | NotIn(attr, _)
+            | Between(attr, _, _)
+                if attr == LAST_UPDATED_BLOCK_FIELD => {}
+
             Contains(attr, _)
             | NotContains(attr, _)
             | Equal(attr, _)
@@ -752,9 +855,35 @@ impl<'a> QueryFilter<'a> {
             | StartsWith(attr, _)
             | NotStartsWith(attr, _)
             | EndsWith(attr, _)
-            | NotEndsWith(attr, _) => {
+            | NotEndsWith(attr, _)
+            | Match(attr, _)
+            | NotMatch(attr, _)
+            | EqualNoCase(attr, _)
+            | NotEqualNoCase(attr, _)
+            | ContainsNoCase(attr, _)
+            | NotContainsNoCase(attr, _)
+            | StartsWithNoCase(attr, _)
+            | NotStartsWithNoCase(attr, _)
+            | EndsWithNoCase(attr, _)
+            | NotEndsWithNoCase(attr, _)
+            | MatchNoCase(attr, _)
+            | NotMatchNoCase(attr, _)
+            | IsNull(attr)
+            | IsNotNull(attr)
+            | Between(attr, _, _) => {
                 table.column_for_field(attr)?;
             }
+
+            ChangedAtBlockGte(_) | CreatedAtBlockGte(_) => {}
+
+            LengthEqual(attr, _) | LengthGreaterThan(attr, _) | LengthLessThan(attr, _) => {
+                if !table.column_for_field(attr)?.is_list() {
+                    return Err(StoreError::QueryExecutionError(format!(
+                        "can not filter on the length of `{}` since it is not a list",
+                        attr
+                    )));
+                }
+            }
         }
         Ok(())
     }
@@ -763,6 +892,17 @@ impl<'a> QueryFilter<'a> {
         QueryFilter {
             filter,
             table: self.table,
+            layout: self.layout,
+            block: self.block,
+            depth: self.depth,
+        }
+    }
+
+    fn alias(depth: usize) -> String {
+        if depth == 0 {
+            "c".to_owned()
+        } else {
+            format!("i{}", depth)
         }
     }
 
@@ -859,6 +999,111 @@ impl<'a> QueryFilter<'a> {
         Ok(())
     }
 
+    /// Push `lower(block_range)`, which is the block at which an entity
+    /// was last updated
+    fn push_last_updated_block(out: &mut AstPass<Pg>) -> QueryResult<()> {
+        out.push_sql("lower(");
+        out.push_identifier(BLOCK_RANGE_COLUMN)?;
+        out.push_sql(")");
+        Ok(())
+    }
+
+    /// Generate a check that no version of the entity was written before
+    /// `block`:
+    ///
+    ///   not exists (select 1 from {table} v1
+    ///                where v1.id = c.id
+    ///                  and lower(v1.block_range) < {block})
+    fn created_at_block_gte(&self, block: BlockNumber, mut out: AstPass<Pg>) -> QueryResult<()> {
+        let entity = Self::alias(self.depth);
+        let version = format!("v{}", self.depth + 1);
+
+        out.push_sql("not exists (select 1 from ");
+        out.push_sql(self.table.qualified_name.as_str());
+        out.push_sql(" ");
+        out.push_sql(&version);
+        out.push_sql(" where ");
+        out.push_sql(&version);
+        out.push_sql(".");
+        out.push_identifier(PRIMARY_KEY_COLUMN)?;
+        out.push_sql(" = ");
+        out.push_sql(&entity);
+        out.push_sql(".");
+        out.push_identifier(PRIMARY_KEY_COLUMN)?;
+        out.push_sql(" and lower(");
+        out.push_sql(&version);
+        out.push_sql(".");
+        out.push_identifier(BLOCK_RANGE_COLUMN)?;
+        out.push_sql(") < ");
+        out.push_bind_param::<BigInt, _>(&block)?;
+        out.push_sql(")");
+        Ok(())
+    }
+
+    /// The block number that a filter on `_lastUpdatedBlock` compares
+    /// with. Block numbers do not necessarily fit into an `Int`, and the
+    /// GraphQL layer therefore passes them as `BigInt`
+    fn block_number(value: &Value) -> Option<BlockNumber> {
+        match value {
+            Value::Int(block) => Some(*block as BlockNumber),
+            Value::BigInt(block) => u64::try_from(block)
+                .ok()
+                .and_then(|block| BlockNumber::try_from(block).ok()),
+            _ => None,
+        }
+    }
+
+    fn last_updated_block(value: &Value, op: Comparison, mut out: AstPass<Pg>) -> QueryResult<()> {
+        match Self::block_number(value) {
+            Some(block) => {
+                Self::push_last_updated_block(&mut out)?;
+                out.push_sql(op.as_str());
+                out.push_bind_param::<BigInt, _>(&block)
+            }
+            None => Err(UnsupportedFilter {
+                filter: op.as_str().to_owned(),
+                value: value.clone(),
+            }
+            .into()),
+        }
+    }
+
+    fn last_updated_block_in(
+        values: &Vec<Value>,
+        negated: bool,
+        mut out: AstPass<Pg>,
+    ) -> QueryResult<()> {
+        if values.is_empty() {
+            out.push_sql("false");
+            return Ok(());
+        }
+
+        Self::push_last_updated_block(&mut out)?;
+        if negated {
+            out.push_sql(" not in (");
+        } else {
+            out.push_sql(" in (");
+        }
+        for (i, value) in values.iter().enumerate() {
+            if i > 0 {
+                out.push_sql(", ");
+            }
+            match Self::block_number(value) {
+                Some(block) => out.push_bind_param::<BigInt, _>(&block)?,
+                None => {
+                    let filter = if negated { "not_in" } else { "in" };
+                    return Err(UnsupportedFilter {
+                        filter: filter.to_owned(),
+                        value: value.clone(),
+                    }
+                    .into());
+                }
+            }
+        }
+        out.push_sql(")");
+        Ok(())
+    }
+
     fn equals(
         &self,
         attribute: &Attribute,
@@ -866,6 +1111,9 @@ impl<'a> QueryFilter<'a> {
         op: Comparison,
         mut out: AstPass<Pg>,
     ) -> QueryResult<()> {
+        if attribute == LAST_UPDATED_BLOCK_FIELD {
+            return Self::last_updated_block(value, op, out);
+        }
         let column = self.column(attribute);
 
         if column.is_text() && value.is_string() {
@@ -908,6 +1156,9 @@ impl<'a> QueryFilter<'a> {
         op: Comparison,
         mut out: AstPass<Pg>,
     ) -> QueryResult<()> {
+        if attribute == LAST_UPDATED_BLOCK_FIELD {
+            return Self::last_updated_block(value, op, out);
+        }
         let column = self.column(attribute);
 
         if column.is_text() && value.is_string() {
@@ -931,6 +1182,147 @@ impl<'a> QueryFilter<'a> {
         Ok(())
     }
 
+    /// Generate `attribute between low and high` so that the database can
+    /// answer range conditions with one index scan. Text columns are
+    /// compared through their prefix, which `between` can not express, and
+    /// we therefore fall back to two comparisons for them
+    fn between(
+        &self,
+        attribute: &Attribute,
+        low: &Value,
+        high: &Value,
+        mut out: AstPass<Pg>,
+    ) -> QueryResult<()> {
+        if attribute == LAST_UPDATED_BLOCK_FIELD {
+            return match (Self::block_number(low), Self::block_number(high)) {
+                (Some(low), Some(high)) => {
+                    Self::push_last_updated_block(&mut out)?;
+                    out.push_sql(" between ");
+                    out.push_bind_param::<BigInt, _>(&low)?;
+                    out.push_sql(" and ");
+                    out.push_bind_param::<BigInt, _>(&high)
+                }
+                (Some(_), None) => Err(UnsupportedFilter {
+                    filter: "between".to_owned(),
+                    value: high.clone(),
+                }
+                .into()),
+                (None, _) => Err(UnsupportedFilter {
+                    filter: "between".to_owned(),
+                    value: low.clone(),
+                }
+                .into()),
+            };
+        }
+        let column = self.column(attribute);
+
+        if column.is_text() && (low.is_string() || high.is_string()) {
+            out.push_sql("(");
+            self.compare(attribute, low, Comparison::GreaterOrEqual, out.reborrow())?;
+            out.push_sql(" and ");
+            self.compare(attribute, high, Comparison::LessOrEqual, out.reborrow())?;
+            out.push_sql(")");
+            return Ok(());
+        }
+
+        for value in &[low, high] {
+            match value {
+                Value::BigInt(_) | Value::BigDecimal(_) | Value::Int(_) | Value::String(_) => {}
+                Value::Bool(_) | Value::Bytes(_) | Value::List(_) | Value::Null => {
+                    return Err(UnsupportedFilter {
+                        filter: "between".to_owned(),
+                        value: (*value).clone(),
+                    }
+                    .into());
+                }
+            }
+        }
+        out.push_identifier(column.name.as_str())?;
+        out.push_sql(" between ");
+        QueryValue(low, &column.column_type).walk_ast(out.reborrow())?;
+        out.push_sql(" and ");
+        QueryValue(high, &column.column_type).walk_ast(out)
+    }
+
+    /// Generate a semi-join with the table for `child_type`:
+    ///
+    ///   exists (select 1 from {child_table} i1
+    ///            where {link between c and i1}
+    ///              and {i1 is visible at block}
+    ///              and {filter on i1})
+    ///
+    /// Columns in `filter` are not qualified with a table alias, and
+    /// therefore resolve to the child table
+    fn child(
+        &self,
+        child_type: &str,
+        filter: &EntityFilter,
+        link: &ChildLink,
+        mut out: AstPass<Pg>,
+    ) -> QueryResult<()> {
+        let child_table = self
+            .layout
+            .table_for_entity(child_type)
+            .expect("the constructor already checked that all child types are valid")
+            .as_ref();
+        let parent = Self::alias(self.depth);
+        let child = Self::alias(self.depth + 1);
+
+        out.push_sql("exists (select 1 from ");
+        out.push_sql(child_table.qualified_name.as_str());
+        out.push_sql(" ");
+        out.push_sql(&child);
+        out.push_sql(" where ");
+        match link {
+            ChildLink::Parent(attr) => {
+                let column = self.column(attr);
+                out.push_sql(&child);
+                out.push_sql(".");
+                out.push_identifier(PRIMARY_KEY_COLUMN)?;
+                if column.is_list() {
+                    out.push_sql(" = any(");
+                } else {
+                    out.push_sql(" = (");
+                }
+                out.push_sql(&parent);
+                out.push_sql(".");
+                out.push_identifier(column.name.as_str())?;
+                out.push_sql(")");
+            }
+            ChildLink::Child(attr) => {
+                let column = child_table
+                    .column_for_field(attr)
+                    .expect("the constructor already checked that all attribute names are valid");
+                out.push_sql(&parent);
+                out.push_sql(".");
+                out.push_identifier(PRIMARY_KEY_COLUMN)?;
+                if column.is_list() {
+                    out.push_sql(" = any(");
+                } else {
+                    out.push_sql(" = (");
+                }
+                out.push_sql(&child);
+                out.push_sql(".");
+                out.push_identifier(column.name.as_str())?;
+                out.push_sql(")");
+            }
+        }
+        out.push_sql(" and ");
+        let prefix = format!("{}.", child);
+        BlockRangeContainsClause::new(child_table, &prefix, self.block).walk_ast(out.reborrow())?;
+        out.push_sql(" and ");
+        QueryFilter {
+            filter,
+            table: child_table,
+            layout: self.layout,
+            block: self.block,
+            depth: self.depth + 1,
+        }
+        .walk_ast(out.reborrow())?;
+        out.push_sql(")");
+        Ok(())
+    }
+
     fn in_array(
         &self,
         attribute: &Attribute,
@@ -938,6 +1330,9 @@ impl<'a> QueryFilter<'a> {
         negated: bool,
         mut out: AstPass<Pg>,
     ) -> QueryResult<()> {
+        if attribute == LAST_UPDATED_BLOCK_FIELD {
+            return Self::last_updated_block_in(values, negated, out);
+        }
         let column = self.column(attribute);
 
         if values.is_empty() {
@@ -1056,6 +1451,103 @@ impl<'a> QueryFilter<'a> {
         }
         Ok(())
     }
+
+    /// Push `column` for a comparison with the string `value`, casting
+    /// enums to text. Return an error if either the column or the value
+    /// is not a string
+    fn push_text_column<'b>(
+        column: &Column,
+        filter: &str,
+        value: &'b Value,
+        out: &mut AstPass<Pg>,
+    ) -> QueryResult<&'b str> {
+        let unsupported = || UnsupportedFilter {
+            filter: filter.trim().to_owned(),
+            value: value.clone(),
+        };
+        let s = match value {
+            Value::String(s) if !column.is_list() => s,
+            _ => return Err(unsupported().into()),
+        };
+        match &column.column_type {
+            ColumnType::String => out.push_identifier(column.name.as_str())?,
+            ColumnType::Enum(_) => {
+                out.push_identifier(column.name.as_str())?;
+                out.push_sql("::text");
+            }
+            _ => return Err(unsupported().into()),
+        }
+        Ok(s)
+    }
+
+    fn regex_match(
+        &self,
+        attribute: &Attribute,
+        value: &Value,
+        op: &str,
+        mut out: AstPass<Pg>,
+    ) -> QueryResult<()> {
+        let column = self.column(attribute);
+
+        let s = Self::push_text_column(column, op, value, &mut out)?;
+        out.push_sql(op);
+        out.push_bind_param::<Text, _>(&s)
+    }
+
+    fn equals_no_case(
+        &self,
+        attribute: &Attribute,
+        value: &Value,
+        op: Comparison,
+        mut out: AstPass<Pg>,
+    ) -> QueryResult<()> {
+        let column = self.column(attribute);
+
+        out.push_sql("lower(");
+        let s = Self::push_text_column(column, op.as_str(), value, &mut out)?;
+        out.push_sql(")");
+        out.push_sql(op.as_str());
+        out.push_sql("lower(");
+        out.push_bind_param::<Text, _>(&s)?;
+        out.push_sql(")");
+        Ok(())
+    }
+
+    /// Generate `coalesce(array_length(column, 1), 0) {op} length`; a list
+    /// that is `null` has the same length as an empty list
+    fn length(
+        &self,
+        attribute: &Attribute,
+        op: &str,
+        length: u32,
+        mut out: AstPass<Pg>,
+    ) -> QueryResult<()> {
+        let column = self.column(attribute);
+        out.push_sql("coalesce(array_length(");
+        out.push_identifier(column.name.as_str())?;
+        out.push_sql(", 1), 0)");
+        out.push_sql(op);
+        out.push_bind_param::<Integer, _>(&(length.min(i32::MAX as u32) as i32))
+    }
+
+    /// Generate `column [not] ilike pattern` where `pattern` is `value`
+    /// with `%` added at the start, the end, or both
+    fn like_no_case(
+        &self,
+        attribute: &Attribute,
+        value: &Value,
+        negated: bool,
+        prefix: &str,
+        suffix: &str,
+        mut out: AstPass<Pg>,
+    ) -> QueryResult<()> {
+        let column = self.column(attribute);
+        let op = if negated { " not ilike " } else { " ilike " };
+
+        let s = Self::push_text_column(column, op, value, &mut out)?;
+        out.push_sql(op);
+        out.push_bind_param::<Text, _>(&format!("{}{}{}", prefix, s, suffix))
+    }
 }
 
 impl<'a> QueryFragment<Pg> for QueryFilter<'a> {
@@ -1092,6 +1584,47 @@ impl<'a> QueryFragment<Pg> for QueryFilter<'a> {
             NotEndsWith(attr, value) => {
                 self.starts_or_ends_with(attr, value, " not like ", false, out)?
             }
+            Match(attr, value) => self.regex_match(attr, value, " ~ ", out)?,
+            NotMatch(attr, value) => self.regex_match(attr, value, " !~ ", out)?,
+
+            EqualNoCase(attr, value) => self.equals_no_case(attr, value, c::Equal, out)?,
+            NotEqualNoCase(attr, value) => self.equals_no_case(attr, value, c::NotEqual, out)?,
+            ContainsNoCase(attr, value) => self.like_no_case(attr, value, false, "%", "%", out)?,
+            NotContainsNoCase(attr, value) => {
+                self.like_no_case(attr, value, true, "%", "%", out)?
+            }
+            StartsWithNoCase(attr, value) => self.like_no_case(attr, value, false, "", "%", out)?,
+            NotStartsWithNoCase(attr, value) => {
+                self.like_no_case(attr, value, true, "", "%", out)?
+            }
+            EndsWithNoCase(attr, value) => self.like_no_case(attr, value, false, "%", "", out)?,
+            NotEndsWithNoCase(attr, value) => self.like_no_case(attr, value, true, "%", "", out)?,
+            MatchNoCase(attr, value) => self.regex_match(attr, value, " ~* ", out)?,
+            NotMatchNoCase(attr, value) => self.regex_match(attr, value, " !~* ", out)?,
+
+            IsNull(attr) => {
+                out.push_identifier(self.column(attr).name.as_str())?;
+                out.push_sql(" is null");
+            }
+            IsNotNull(attr) => {
+                out.push_identifier(self.column(attr).name.as_str())?;
+                out.push_sql(" is not null");
+            }
+            Between(attr, low, high) => self.between(attr, low, high, out)?,
+            LengthEqual(attr, length) => self.length(attr, " = ", *length, out)?,
+            LengthGreaterThan(attr, length) => self.length(attr, " > ", *length, out)?,
+            LengthLessThan(attr, length) => self.length(attr, " < ", *length, out)?,
+            ChangedAtBlockGte(block) => {
+                Self::push_last_updated_block(&mut out)?;
+                out.push_sql(" >= ");
+                out.push_bind_param::<BigInt, _>(block)?;
+            }
+            CreatedAtBlockGte(block) => self.created_at_block_gte(*block, out)?,
+            Child {
+                child_type,
+                filter,
+                link,
+            } => self.child(child_type, filter, link, out)?,
         }
         Ok(())
     }
@@ -1137,50 +1670,201 @@ impl<'a> LoadQuery<PgConnection, EntityData> for FindQuery<'a> {
 
 impl<'a, Conn> RunQueryDsl<Conn> for FindQuery<'a> {}
 
-#[derive(Debug, Clone, Constructor)]
-pub struct FindManyQuery<'a> {
-    pub(crate) namespace: &'a Namespace,
-    pub(crate) tables: Vec<&'a Table>,
+/// One row of the result of an `AggregateQuery`. Like `EntityData`, the
+/// values are returned as Jsonb since their SQL type depends on the
+/// column that is aggregated
+#[derive(QueryableByName)]
+pub struct AggregateData {
+    #[sql_type = "Nullable<Jsonb>"]
+    pub group_value: Option<serde_json::Value>,
+    #[sql_type = "Nullable<Jsonb>"]
+    pub value: Option<serde_json::Value>,
+}
 
-    // Maps object name to ids.
-    pub(crate) ids_for_type: BTreeMap<&'a str, &'a Vec<&'a str>>,
+#[derive(Debug, Clone)]
+pub struct AggregateQuery<'a> {
+    pub(crate) table: &'a Table,
+    /// The qualified name of the table to read from; either the name of
+    /// `table` or the name of its columnar mirror
+    pub(crate) source: &'a str,
+    pub(crate) function: AggregateFunction,
+    /// The column to aggregate; `None` counts rows
+    pub(crate) column: Option<&'a Column>,
+    pub(crate) group_by: Option<&'a Column>,
+    pub(crate) filter: Option<QueryFilter<'a>>,
     pub(crate) block: BlockNumber,
 }
 
-impl<'a> QueryFragment<Pg> for FindManyQuery<'a> {
-    fn walk_ast(&self, mut out: AstPass<Pg>) -> QueryResult<()> {
-        out.unsafe_to_cache_prepared();
-
-        // Generate
-        //    select $object0 as entity, to_jsonb(e.*) as data
-        //      from schema.<table0> e where {id.is_in($ids0)}
-        //    union all
-        //    select $object1 as entity, to_jsonb(e.*) as data
-        //      from schema.<table1> e where {id.is_in($ids1))
-        //    union all
-        //    ...
-        for (i, table) in self.tables.iter().enumerate() {
-            if i > 0 {
-                out.push_sql("\nunion all\n");
+impl<'a> AggregateQuery<'a> {
+    fn aggregate(&self, out: &mut AstPass<Pg>) -> QueryResult<()> {
+        out.push_sql(self.function.as_str());
+        out.push_sql("(");
+        match self.column {
+            Some(column) => {
+                out.push_sql("c.");
+                out.push_identifier(column.name.as_str())?;
             }
-            out.push_sql("select ");
-            out.push_bind_param::<Text, _>(&table.object)?;
-            out.push_sql(" as entity, to_jsonb(e.*) as data\n");
-            out.push_sql("  from ");
-            out.push_sql(table.qualified_name.as_str());
-            out.push_sql(" e\n where ");
-            table
-                .primary_key()
-                .is_in(&self.ids_for_type[table.object.as_str()], &mut out)?;
-            out.push_sql(" and ");
-            BlockRangeContainsClause::new(&table, "e.", self.block).walk_ast(out.reborrow())?;
+            None => out.push_sql("*"),
         }
+        out.push_sql(")");
         Ok(())
     }
 }
 
-impl<'a> QueryId for FindManyQuery<'a> {
-    type QueryId = ();
+impl<'a> QueryFragment<Pg> for AggregateQuery<'a> {
+    fn walk_ast(&self, mut out: AstPass<Pg>) -> QueryResult<()> {
+        out.unsafe_to_cache_prepared();
+
+        // Generate
+        //    select to_jsonb(c.group) as group_value,
+        //           to_jsonb(function(c.column)) as value
+        //      from source c
+        //     where block_range @> $block
+        //       and filter
+        //     group by c.group
+        //     order by c.group
+        out.push_sql("select ");
+        match self.group_by {
+            Some(column) => {
+                out.push_sql("to_jsonb(c.");
+                out.push_identifier(column.name.as_str())?;
+                out.push_sql(")");
+            }
+            None => out.push_sql("null::jsonb"),
+        }
+        out.push_sql(" as group_value, to_jsonb(");
+        self.aggregate(&mut out)?;
+        out.push_sql(") as value\n");
+        out.push_sql("  from ");
+        out.push_sql(self.source);
+        out.push_sql(" c\n where ");
+        BlockRangeContainsClause::new(&self.table, "c.", self.block).walk_ast(out.reborrow())?;
+        if let Some(filter) = &self.filter {
+            out.push_sql(" and ");
+            filter.walk_ast(out.reborrow())?;
+        }
+        if let Some(column) = self.group_by {
+            out.push_sql("\n group by c.");
+            out.push_identifier(column.name.as_str())?;
+            out.push_sql("\n order by c.");
+            out.push_identifier(column.name.as_str())?;
+        }
+        Ok(())
+    }
+}
+
+impl<'a> QueryId for AggregateQuery<'a> {
+    type QueryId = ();
+
+    const HAS_STATIC_QUERY_ID: bool = false;
+}
+
+impl<'a> LoadQuery<PgConnection, AggregateData> for AggregateQuery<'a> {
+    fn internal_load(self, conn: &PgConnection) -> QueryResult<Vec<AggregateData>> {
+        conn.query_by_name(&self)
+    }
+}
+
+impl<'a, Conn> RunQueryDsl<Conn> for AggregateQuery<'a> {}
+
+#[derive(QueryableByName)]
+pub struct DigestData {
+    #[sql_type = "diesel::sql_types::BigInt"]
+    pub count: i64,
+    #[sql_type = "Text"]
+    pub digest: String,
+}
+
+/// Compute the number of entities in `table` at `block`, and a digest of
+/// their contents that does not depend on which blocks they were written
+/// at, or on the order in which they were written
+#[derive(Debug, Clone, Constructor)]
+pub struct DigestQuery<'a> {
+    table: &'a Table,
+    block: BlockNumber,
+}
+
+impl<'a> QueryFragment<Pg> for DigestQuery<'a> {
+    fn walk_ast(&self, mut out: AstPass<Pg>) -> QueryResult<()> {
+        out.unsafe_to_cache_prepared();
+
+        // Generate
+        //    select count(*) as count,
+        //           md5(coalesce(string_agg(md5((to_jsonb(e.*) - 'vid' - 'block_range')::text),
+        //                                   '' order by e.id), '')) as digest
+        //      from schema.table e
+        //     where block_range @> $block
+        out.push_sql("select count(*) as count,\n");
+        out.push_sql("       md5(coalesce(string_agg(md5((to_jsonb(e.*) - 'vid' - '");
+        out.push_sql(BLOCK_RANGE_COLUMN);
+        out.push_sql("')::text), '' order by e.");
+        out.push_identifier(PRIMARY_KEY_COLUMN)?;
+        out.push_sql("), '')) as digest\n");
+        out.push_sql("  from ");
+        out.push_sql(self.table.qualified_name.as_str());
+        out.push_sql(" e\n where ");
+        BlockRangeContainsClause::new(&self.table, "e.", self.block).walk_ast(out)
+    }
+}
+
+impl<'a> QueryId for DigestQuery<'a> {
+    type QueryId = ();
+
+    const HAS_STATIC_QUERY_ID: bool = false;
+}
+
+impl<'a> LoadQuery<PgConnection, DigestData> for DigestQuery<'a> {
+    fn internal_load(self, conn: &PgConnection) -> QueryResult<Vec<DigestData>> {
+        conn.query_by_name(&self)
+    }
+}
+
+impl<'a, Conn> RunQueryDsl<Conn> for DigestQuery<'a> {}
+
+#[derive(Debug, Clone, Constructor)]
+pub struct FindManyQuery<'a> {
+    pub(crate) namespace: &'a Namespace,
+    pub(crate) tables: Vec<&'a Table>,
+
+    // Maps object name to ids.
+    pub(crate) ids_for_type: BTreeMap<&'a str, &'a Vec<&'a str>>,
+    pub(crate) block: BlockNumber,
+}
+
+impl<'a> QueryFragment<Pg> for FindManyQuery<'a> {
+    fn walk_ast(&self, mut out: AstPass<Pg>) -> QueryResult<()> {
+        out.unsafe_to_cache_prepared();
+
+        // Generate
+        //    select $object0 as entity, to_jsonb(e.*) as data
+        //      from schema.<table0> e where {id.is_in($ids0)}
+        //    union all
+        //    select $object1 as entity, to_jsonb(e.*) as data
+        //      from schema.<table1> e where {id.is_in($ids1))
+        //    union all
+        //    ...
+        for (i, table) in self.tables.iter().enumerate() {
+            if i > 0 {
+                out.push_sql("\nunion all\n");
+            }
+            out.push_sql("select ");
+            out.push_bind_param::<Text, _>(&table.object)?;
+            out.push_sql(" as entity, to_jsonb(e.*) as data\n");
+            out.push_sql("  from ");
+            out.push_sql(table.qualified_name.as_str());
+            out.push_sql(" e\n where ");
+            table
+                .primary_key()
+                .is_in(&self.ids_for_type[table.object.as_str()], &mut out)?;
+            out.push_sql(" and ");
+            BlockRangeContainsClause::new(&table, "e.", self.block).walk_ast(out.reborrow())?;
+        }
+        Ok(())
+    }
+}
+
+impl<'a> QueryId for FindManyQuery<'a> {
+    type QueryId = ();
 
     const HAS_STATIC_QUERY_ID: bool = false;
 }
@@ -1270,7 +1954,7 @@ impl<'a> QueryFragment<Pg> for InsertQuery<'a> {
             }
         }
         let block_range: BlockRange = (self.block..).into();
-        out.push_bind_param::<Range<Integer>, _>(&block_range)?;
+        out.push_bind_param::<Range<BigInt>, _>(&block_range)?;
         out.push_sql(")");
         Ok(())
     }
@@ -1370,6 +2054,106 @@ impl<'a> QueryId for UpdateQuery<'a> {
 
 impl<'a, Conn> RunQueryDsl<Conn> for UpdateQuery<'a> {}
 
+/// Add deltas to attributes of an entity without reading it first. The
+/// current version of the entity is clamped at `block`, and a copy of it
+/// with the deltas added becomes the new version so that the history of
+/// the entity stays intact. Attributes that are `null` are treated as 0.
+/// Does nothing if the entity does not exist
+#[derive(Debug, Clone)]
+pub struct IncrementQuery<'a> {
+    table: &'a Table,
+    key: &'a EntityKey,
+    deltas: &'a BTreeMap<String, Value>,
+    block: BlockNumber,
+}
+
+impl<'a> IncrementQuery<'a> {
+    pub fn new(
+        table: &'a Table,
+        key: &'a EntityKey,
+        deltas: &'a BTreeMap<String, Value>,
+        block: BlockNumber,
+    ) -> Result<Self, StoreError> {
+        for field in deltas.keys() {
+            let column = table.column_for_field(field)?;
+            let is_number = match column.column_type {
+                ColumnType::Int | ColumnType::BigInt | ColumnType::BigDecimal => true,
+                _ => false,
+            };
+            if column.is_primary_key() || column.is_list() || !is_number {
+                return Err(StoreError::QueryExecutionError(format!(
+                    "can not increment attribute {} of entity {}[{}] since it is not a number",
+                    field, key.entity_type, key.entity_id
+                )));
+            }
+        }
+        Ok(IncrementQuery {
+            table,
+            key,
+            deltas,
+            block,
+        })
+    }
+}
+
+impl<'a> QueryFragment<Pg> for IncrementQuery<'a> {
+    fn walk_ast(&self, mut out: AstPass<Pg>) -> QueryResult<()> {
+        out.unsafe_to_cache_prepared();
+
+        // Construct a query
+        //   with clamped as (
+        //     update table
+        //        set block_range = int8range(lower(block_range), $block)
+        //      where id = $id
+        //        and block_range @> INTMAX
+        //     returning *)
+        //   insert into table(column, ..., block_range)
+        //   select column, ..., coalesce(counter, 0) + $delta, ...,
+        //          int8range($block, null)
+        //     from clamped
+        out.push_sql("with clamped as (\n");
+        ClampRangeQuery {
+            table: self.table,
+            key: self.key,
+            block: self.block,
+        }
+        .walk_ast(out.reborrow())?;
+        out.push_sql("\nreturning *)\ninsert into ");
+        out.push_sql(self.table.qualified_name.as_str());
+        out.push_sql("(");
+        for column in self.table.columns.iter() {
+            out.push_identifier(column.name.as_str())?;
+            out.push_sql(", ");
+        }
+        out.push_identifier(BLOCK_RANGE_COLUMN)?;
+        out.push_sql(")\nselect ");
+        for column in self.table.columns.iter() {
+            match self.deltas.get(&column.field) {
+                Some(delta) => {
+                    out.push_sql("coalesce(");
+                    out.push_identifier(column.name.as_str())?;
+                    out.push_sql(", 0) + ");
+                    QueryValue(delta, &column.column_type).walk_ast(out.reborrow())?;
+                }
+                None => out.push_identifier(column.name.as_str())?,
+            }
+            out.push_sql(", ");
+        }
+        out.push_sql("int8range(");
+        out.push_bind_param::<BigInt, _>(&self.block)?;
+        out.push_sql(", null)\n  from clamped");
+        Ok(())
+    }
+}
+
+impl<'a> QueryId for IncrementQuery<'a> {
+    type QueryId = ();
+
+    const HAS_STATIC_QUERY_ID: bool = false;
+}
+
+impl<'a, Conn> RunQueryDsl<Conn> for IncrementQuery<'a> {}
+
 /// A query that deletes all versions of an entity
 #[derive(Debug, Clone, Constructor)]
 pub struct DeleteQuery<'a> {
@@ -1609,6 +2393,7 @@ impl<'a> FilterWindow<'a> {
         layout: &'a Layout,
         window: EntityWindow,
         query_filter: Option<&'a EntityFilter>,
+        block: BlockNumber,
     ) -> Result<Self, QueryExecutionError> {
         let EntityWindow {
             child_type,
@@ -1617,7 +2402,7 @@ impl<'a> FilterWindow<'a> {
         } = window;
         let table = layout.table_for_entity(&child_type).map(|rc| rc.as_ref())?;
         let query_filter = query_filter
-            .map(|filter| QueryFilter::new(filter, table))
+            .map(|filter| QueryFilter::new(filter, layout, table, block))
             .transpose()?;
         let link = TableLink::new(table, link)?;
         Ok(FilterWindow {
@@ -1918,6 +2703,7 @@ impl<'a> FilterCollection<'a> {
         layout: &'a Layout,
         collection: EntityCollection,
         filter: Option<&'a EntityFilter>,
+        block: BlockNumber,
     ) -> Result<Self, QueryExecutionError> {
         match collection {
             EntityCollection::All(entities) => {
@@ -1933,7 +2719,7 @@ impl<'a> FilterCollection<'a> {
                             .map(|rc| rc.as_ref())
                             .and_then(|table| {
                                 filter
-                                    .map(|filter| QueryFilter::new(filter, table))
+                                    .map(|filter| QueryFilter::new(filter, layout, table, block))
                                     .transpose()
                                     .map(|filter| (table, filter))
                             })
@@ -1944,7 +2730,7 @@ impl<'a> FilterCollection<'a> {
             EntityCollection::Window(windows) => {
                 let windows = windows
                     .into_iter()
-                    .map(|window| FilterWindow::new(layout, window, filter))
+                    .map(|window| FilterWindow::new(layout, window, filter, block))
                     .collect::<Result<Vec<_>, _>>()?;
                 let collection = if windows.len() == 1 {
                     let mut windows = windows;
@@ -1975,6 +2761,37 @@ impl<'a> FilterCollection<'a> {
             FilterCollection::MultiWindow(windows, _) => windows.is_empty(),
         }
     }
+
+    /// The names of the entity types that the collection contains, sorted
+    /// and separated by commas
+    fn entity_types(&self) -> String {
+        let types: BTreeSet<&str> = match self {
+            FilterCollection::All(entities) => entities
+                .iter()
+                .map(|(table, _)| table.object.as_str())
+                .collect(),
+            FilterCollection::SingleWindow(window) => {
+                std::iter::once(window.table.object.as_str()).collect()
+            }
+            FilterCollection::MultiWindow(windows, _) => windows
+                .iter()
+                .map(|window| window.table.object.as_str())
+                .collect(),
+        };
+        types.into_iter().collect::<Vec<_>>().join(",")
+    }
+}
+
+/// Identifies the shape of a `FilterQuery` independent of the values that
+/// are bound in it. The fingerprint is put into a comment in the generated
+/// SQL so that statements in `pg_stat_statements` or in the Postgres logs
+/// can be traced back to the GraphQL queries that caused them
+#[derive(Debug, Clone)]
+pub struct QueryFingerprint {
+    /// A hash of the namespace and the generated SQL without bind values
+    pub hash: String,
+    pub namespace: String,
+    pub entity_types: String,
 }
 
 /// Convenience to pass the name of the column to order by around. If `name`
@@ -1987,6 +2804,7 @@ pub enum SortKey<'a> {
         column: &'a Column,
         value: Option<&'a str>,
         direction: &'static str,
+        nulls: NullsPlacement,
     },
 }
 
@@ -2004,6 +2822,7 @@ impl<'a> SortKey<'a> {
             attribute: String,
             filter: Option<&'a EntityFilter>,
             direction: &'static str,
+            nulls: NullsPlacement,
         ) -> Result<SortKey<'a>, QueryExecutionError> {
             let column = table.column_for_field(&attribute)?;
             if column.is_fulltext() {
@@ -2016,6 +2835,7 @@ impl<'a> SortKey<'a> {
                                 column,
                                 value: sort_value,
                                 direction,
+                                nulls,
                             })
                         }
                         _ => unreachable!(),
@@ -2027,13 +2847,14 @@ impl<'a> SortKey<'a> {
                     column,
                     value: None,
                     direction,
+                    nulls,
                 })
             }
         }
 
         match order {
-            EntityOrder::Ascending(attr, _) => with_key(table, attr, filter, ASC),
-            EntityOrder::Descending(attr, _) => with_key(table, attr, filter, DESC),
+            EntityOrder::Ascending(attr, _, nulls) => with_key(table, attr, filter, ASC, nulls),
+            EntityOrder::Descending(attr, _, nulls) => with_key(table, attr, filter, DESC, nulls),
             EntityOrder::Default => Ok(SortKey::Id),
             EntityOrder::Unordered => Ok(SortKey::None),
         }
@@ -2043,11 +2864,7 @@ impl<'a> SortKey<'a> {
     fn select(&self, out: &mut AstPass<Pg>) -> QueryResult<()> {
         match self {
             SortKey::None | SortKey::Id => Ok(()),
-            SortKey::Key {
-                column,
-                value: _,
-                direction: _,
-            } => {
+            SortKey::Key { column, .. } => {
                 let name = column.name.as_str();
                 if !column.is_primary_key() {
                     out.push_sql(", c.");
@@ -2071,9 +2888,10 @@ impl<'a> SortKey<'a> {
                 column,
                 value,
                 direction,
+                nulls,
             } => {
                 out.push_sql("order by ");
-                SortKey::sort_expr(column, value, direction, out)
+                SortKey::sort_expr(column, value, direction, *nulls, out)
             }
         }
     }
@@ -2091,21 +2909,27 @@ impl<'a> SortKey<'a> {
                 column,
                 value,
                 direction,
+                nulls,
             } => {
                 out.push_sql("order by g$parent_id, ");
-                SortKey::sort_expr(column, value, direction, out)
+                SortKey::sort_expr(column, value, direction, *nulls, out)
             }
         }
     }
 
     /// Generate
-    ///   [name direction,] id
+    ///   [name direction nulls {first|last},] id
     fn sort_expr(
         column: &Column,
         value: &Option<&str>,
         direction: &str,
+        nulls: NullsPlacement,
         out: &mut AstPass<Pg>,
     ) -> QueryResult<()> {
+        let nulls = match nulls {
+            NullsPlacement::First => " nulls first",
+            NullsPlacement::Last => " nulls last",
+        };
         match &column.column_type {
             ColumnType::TSVector(config) => {
                 let algorithm = match config.algorithm {
@@ -2120,7 +2944,7 @@ impl<'a> SortKey<'a> {
                 out.push_bind_param::<Text, _>(&String::from(value.unwrap()))?;
                 out.push_sql(")) ");
                 out.push_sql(direction);
-                out.push_sql(" nulls last");
+                out.push_sql(nulls);
                 if name != PRIMARY_KEY_COLUMN {
                     out.push_sql(", ");
                     out.push_identifier(PRIMARY_KEY_COLUMN)?;
@@ -2132,7 +2956,7 @@ impl<'a> SortKey<'a> {
                 out.push_identifier(name)?;
                 out.push_sql(" ");
                 out.push_sql(direction);
-                out.push_sql(" nulls last");
+                out.push_sql(nulls);
                 if name != PRIMARY_KEY_COLUMN {
                     out.push_sql(", ");
                     out.push_identifier(PRIMARY_KEY_COLUMN)?;
@@ -2162,6 +2986,133 @@ impl QueryFragment<Pg> for FilterRange {
     }
 }
 
+/// Generate the SQL for `expression`, referring to the columns of `table`
+/// through the alias `c`
+fn walk_expression(
+    table: &Table,
+    expression: &Expression,
+    out: &mut AstPass<Pg>,
+) -> QueryResult<()> {
+    match expression {
+        Expression::Field(name) => {
+            let column = table
+                .column_for_field(name)
+                .map_err(|e| DieselError::QueryBuilderError(Box::new(e)))?;
+            out.push_sql("c.");
+            out.push_identifier(column.name.as_str())
+        }
+        // The parser guarantees that numbers only consist of digits and
+        // at most one `.`
+        Expression::Number(number) => {
+            out.push_sql(number);
+            Ok(())
+        }
+        Expression::String(s) => {
+            out.push_bind_param::<Text, _>(s)?;
+            out.push_sql("::text");
+            Ok(())
+        }
+        Expression::Neg(expr) => {
+            out.push_sql("-(");
+            walk_expression(table, expr, out)?;
+            out.push_sql(")");
+            Ok(())
+        }
+        Expression::Binary(lhs, op, rhs) => {
+            out.push_sql("(");
+            walk_expression(table, lhs, out)?;
+            out.push_sql(" ");
+            out.push_sql(op.as_str());
+            out.push_sql(" ");
+            walk_expression(table, rhs, out)?;
+            out.push_sql(")");
+            Ok(())
+        }
+    }
+}
+
+/// Whether the attribute for `column` is in `selected`. Without a
+/// selection, all attributes are selected, and the `id` always is
+fn is_selected(column: &Column, selected: Option<&BTreeSet<String>>) -> bool {
+    match selected {
+        Some(selected) => {
+            column.is_primary_key()
+                || selected.contains(&column.field)
+                || column
+                    .renamed_from
+                    .as_ref()
+                    .map_or(false, |old_name| selected.contains(old_name))
+        }
+        None => true,
+    }
+}
+
+/// Generate the JSONB data for the rows of `table`, extended with the
+/// values of its expression columns. The value of each expression is cast
+/// to the type of its column; for `BigInt` that means dropping any
+/// fractional digits. Without a selection, that is `to_jsonb(c.*)`; with
+/// one, we only build an object from the selected columns so that
+/// Postgres does not have to read and send the others
+fn walk_entity_data(
+    table: &Table,
+    selected: Option<&BTreeSet<String>>,
+    out: &mut AstPass<Pg>,
+) -> QueryResult<()> {
+    // Postgres functions take at most 100 arguments, i.e., 50 key/value
+    // pairs for `jsonb_build_object`
+    const MAX_PAIRS: usize = 50;
+
+    match selected {
+        None => out.push_sql("to_jsonb(c.*)"),
+        Some(attributes) => {
+            let mut names: Vec<&str> = table
+                .columns
+                .iter()
+                .filter(|column| is_selected(column, selected))
+                .map(|column| column.name.as_str())
+                .collect();
+            if attributes.contains(LAST_UPDATED_BLOCK_FIELD) {
+                names.push(BLOCK_RANGE_COLUMN);
+            }
+            for (i, chunk) in names.chunks(MAX_PAIRS).enumerate() {
+                if i > 0 {
+                    out.push_sql(" || ");
+                }
+                out.push_sql("jsonb_build_object(");
+                for (j, name) in chunk.iter().enumerate() {
+                    if j > 0 {
+                        out.push_sql(", ");
+                    }
+                    out.push_sql("'");
+                    out.push_sql(name);
+                    out.push_sql("', c.");
+                    out.push_identifier(name)?;
+                }
+                out.push_sql(")");
+            }
+        }
+    }
+    for expression_column in &table.expression_columns {
+        let column = &expression_column.column;
+        if !is_selected(column, selected) {
+            continue;
+        }
+        out.push_sql(" || jsonb_build_object('");
+        out.push_sql(column.name.as_str());
+        out.push_sql("', ");
+        if column.column_type == ColumnType::BigInt {
+            out.push_sql("trunc(");
+        } else {
+            out.push_sql("(");
+        }
+        walk_expression(table, &expression_column.expression, out)?;
+        out.push_sql(")::");
+        out.push_sql(column.column_type.sql_type());
+        out.push_sql(")");
+    }
+    Ok(())
+}
+
 /// The parallel to `EntityQuery`.
 ///
 /// Details of how query generation for `FilterQuery` works can be found
@@ -2171,8 +3122,11 @@ pub struct FilterQuery<'a> {
     collection: &'a FilterCollection<'a>,
     sort_key: SortKey<'a>,
     range: FilterRange,
+    distinct: bool,
     block: BlockNumber,
     query_id: Option<String>,
+    fingerprint: Option<QueryFingerprint>,
+    selected: Option<&'a BTreeSet<String>>,
 }
 
 impl<'a> FilterQuery<'a> {
@@ -2181,6 +3135,7 @@ impl<'a> FilterQuery<'a> {
         filter: Option<&'a EntityFilter>,
         order: EntityOrder,
         range: EntityRange,
+        distinct: bool,
         block: BlockNumber,
         query_id: Option<String>,
     ) -> Result<Self, QueryExecutionError> {
@@ -2197,11 +3152,48 @@ impl<'a> FilterQuery<'a> {
             collection,
             sort_key,
             range: FilterRange(range),
+            distinct,
             block,
             query_id,
+            fingerprint: None,
+            selected: None,
         })
     }
 
+    /// Only return the attributes of entities that are in `selected`
+    pub fn select(mut self, selected: Option<&'a BTreeSet<String>>) -> Self {
+        self.selected = selected;
+        self
+    }
+
+    /// Compute the fingerprint of this query for the deployment in
+    /// `namespace` and mark the generated SQL with it
+    pub fn with_fingerprint(mut self, namespace: &str) -> QueryResult<Self> {
+        let mut hasher = DefaultHasher::new();
+        namespace.hash(&mut hasher);
+        self.shape_sql()?.hash(&mut hasher);
+        self.fingerprint = Some(QueryFingerprint {
+            hash: format!("{:016x}", hasher.finish()),
+            namespace: namespace.to_string(),
+            entity_types: self.collection.entity_types(),
+        });
+        Ok(self)
+    }
+
+    /// The SQL for this query without the query id, which differs for
+    /// every query. It is the same for all queries with the same shape
+    pub fn shape_sql(&self) -> QueryResult<String> {
+        let mut query = self.clone();
+        query.query_id = None;
+        let mut builder = PgQueryBuilder::new();
+        query.to_sql(&mut builder)?;
+        Ok(builder.finish())
+    }
+
+    pub fn fingerprint(&self) -> Option<&QueryFingerprint> {
+        self.fingerprint.as_ref()
+    }
+
     /// Generate
     ///     from schema.table c
     ///    where block_range @> $block
@@ -2227,10 +3219,13 @@ impl<'a> FilterQuery<'a> {
         Ok(())
     }
 
-    fn select_entity_and_data(table: &Table, out: &mut AstPass<Pg>) {
+    fn select_entity_and_data(&self, table: &Table, out: &mut AstPass<Pg>) -> QueryResult<()> {
         out.push_sql("select '");
         out.push_sql(&table.object);
-        out.push_sql("' as entity, to_jsonb(c.*) as data");
+        out.push_sql("' as entity, ");
+        walk_entity_data(table, self.selected, out)?;
+        out.push_sql(" as data");
+        Ok(())
     }
 
     /// Only one table/filter pair, and no window
@@ -2251,7 +3246,7 @@ impl<'a> FilterQuery<'a> {
         filter: &Option<QueryFilter>,
         mut out: AstPass<Pg>,
     ) -> QueryResult<()> {
-        Self::select_entity_and_data(table, &mut out);
+        self.select_entity_and_data(table, &mut out)?;
         out.push_sql(" from (select * ");
         self.filtered_rows(table, filter, out.reborrow())?;
         out.push_sql("\n ");
@@ -2273,7 +3268,7 @@ impl<'a> FilterQuery<'a> {
         window: &FilterWindow,
         mut out: AstPass<Pg>,
     ) -> QueryResult<()> {
-        Self::select_entity_and_data(&window.table, &mut out);
+        self.select_entity_and_data(&window.table, &mut out)?;
         out.push_sql(" from (\n");
         out.push_sql("select c.*, p.id::text as g$parent_id");
         window.children(
@@ -2343,7 +3338,9 @@ impl<'a> FilterQuery<'a> {
             if i > 0 {
                 out.push_sql("\nunion all\n");
             }
-            out.push_sql("select m.entity, to_jsonb(c.*) as data, c.id");
+            out.push_sql("select m.entity, ");
+            walk_entity_data(table, self.selected, &mut out)?;
+            out.push_sql(" as data, c.id");
             self.sort_key.select(&mut out)?;
             out.push_sql("\n  from ");
             out.push_sql(table.qualified_name.as_str());
@@ -2382,6 +3379,12 @@ impl<'a> FilterQuery<'a> {
         //             ... range over all windows ...
         //             order by c.{sort_key}
         //             limit $first skip $skip) c)
+        //
+        // If the query is distinct, the CTE only keeps the row for the
+        // first parent of each entity by selecting
+        //     distinct on (c.entity, c.id) c.* ...
+        //     order by c.entity, c.id, c.g$parent_id
+        //
         //   select m.entity, to_jsonb(c.*) as data, m.parent_id
         //     from matches m, {window.child_table} c
         //    where c.vid = m.vid and m.entity = '{window.child_type}'
@@ -2391,7 +3394,11 @@ impl<'a> FilterQuery<'a> {
 
         // Step 1: build matches CTE
         out.push_sql("with matches as (");
-        out.push_sql("select c.* from ");
+        if self.distinct {
+            out.push_sql("select distinct on (c.entity, c.id) c.* from ");
+        } else {
+            out.push_sql("select c.* from ");
+        }
         out.push_sql("unnest(");
         out.push_bind_param::<Array<Text>, _>(parent_ids)?;
         out.push_sql("::text[]) as q(id)\n");
@@ -2405,7 +3412,11 @@ impl<'a> FilterQuery<'a> {
         out.push_sql("\n");
         self.sort_key.order_by(&mut out)?;
         self.range.walk_ast(out.reborrow())?;
-        out.push_sql(") c)\n");
+        out.push_sql(") c");
+        if self.distinct {
+            out.push_sql("\n order by c.entity, c.id, c.g$parent_id");
+        }
+        out.push_sql(")\n");
 
         // Step 2: convert to JSONB
         // If the parent is an interface, each implementation might store its
@@ -2415,22 +3426,22 @@ impl<'a> FilterQuery<'a> {
         // duplicate entities in the result
         // We only use a table's qualified name and object to save ourselves
         // the hassle of making `Table` hashable
-        let unique_child_tables = windows
+        let mut unique_child_tables = HashSet::new();
+        let child_tables = windows
             .iter()
-            .map(|window| (&window.table.qualified_name, &window.table.object))
-            .collect::<HashSet<_>>();
-        for (i, (table_name, object)) in unique_child_tables.into_iter().enumerate() {
+            .map(|window| window.table)
+            .filter(|table| unique_child_tables.insert((&table.qualified_name, &table.object)));
+        for (i, table) in child_tables.enumerate() {
             if i > 0 {
                 out.push_sql("\nunion all\n");
             }
-            out.push_sql(
-                "select m.*, \
-                 to_jsonb(c.*) || jsonb_build_object('g$parent_id', m.g$parent_id) as data",
-            );
+            out.push_sql("select m.*, ");
+            walk_entity_data(table, self.selected, &mut out)?;
+            out.push_sql(" || jsonb_build_object('g$parent_id', m.g$parent_id) as data");
             out.push_sql("\n  from ");
-            out.push_sql(table_name.as_str());
+            out.push_sql(table.qualified_name.as_str());
             out.push_sql(" c, matches m\n where c.vid = m.vid and m.entity = '");
-            out.push_sql(object);
+            out.push_sql(&table.object);
             out.push_sql("'");
         }
         out.push_sql("\n ");
@@ -2450,6 +3461,15 @@ impl<'a> QueryFragment<Pg> for FilterQuery<'a> {
             out.push_sql(qid);
             out.push_sql(" */\n");
         }
+        if let Some(fp) = &self.fingerprint {
+            out.push_sql("/* fp: ");
+            out.push_sql(&fp.hash);
+            out.push_sql(", namespace: ");
+            out.push_sql(&fp.namespace);
+            out.push_sql(", entity: ");
+            out.push_sql(&fp.entity_types);
+            out.push_sql(" */\n");
+        }
         // We generate four different kinds of queries, depending on whether
         // we need to window and whether we query just one or multiple entity
         // types/windows; the most complex situation is windowing with multiple
@@ -2469,6 +3489,11 @@ impl<'a> QueryFragment<Pg> for FilterQuery<'a> {
                     self.query_no_window(entities, out)
                 }
             }
+            FilterCollection::SingleWindow(window) if self.distinct => {
+                let windows = vec![window.clone()];
+                let parent_ids = FilterWindow::collect_parents(&windows);
+                self.query_window(&windows, &parent_ids, out)
+            }
             FilterCollection::SingleWindow(window) => self.query_window_one_entity(window, out),
             FilterCollection::MultiWindow(windows, parent_ids) => {
                 self.query_window(windows, parent_ids, out)
@@ -2491,6 +3516,203 @@ impl<'a> LoadQuery<PgConnection, EntityData> for FilterQuery<'a> {
 
 impl<'a, Conn> RunQueryDsl<Conn> for FilterQuery<'a> {}
 
+/// A query that declares the cursor `name` for the entities that a
+/// `FilterQuery` returns, so that they can be fetched in batches
+#[derive(Debug, Clone, Constructor)]
+pub struct DeclareCursorQuery<'a> {
+    name: &'a str,
+    query: FilterQuery<'a>,
+}
+
+impl<'a> QueryFragment<Pg> for DeclareCursorQuery<'a> {
+    fn walk_ast(&self, mut out: AstPass<Pg>) -> QueryResult<()> {
+        out.unsafe_to_cache_prepared();
+
+        // Construct a query
+        //   declare name no scroll cursor for
+        //   <filter query>
+        out.push_sql("declare ");
+        out.push_identifier(self.name)?;
+        out.push_sql(" no scroll cursor for\n");
+        self.query.walk_ast(out.reborrow())
+    }
+}
+
+impl<'a> QueryId for DeclareCursorQuery<'a> {
+    type QueryId = ();
+
+    const HAS_STATIC_QUERY_ID: bool = false;
+}
+
+impl<'a, Conn> RunQueryDsl<Conn> for DeclareCursorQuery<'a> {}
+
+/// The plan that Postgres returns for `explain (format json)`, which is
+/// always in a column called `QUERY PLAN`
+pub struct ExplainData {
+    pub plan: serde_json::Value,
+}
+
+impl QueryableByName<Pg> for ExplainData {
+    fn build<R: NamedRow<Pg>>(row: &R) -> deserialize::Result<Self> {
+        Ok(ExplainData {
+            plan: row.get::<Json, _>("QUERY PLAN")?,
+        })
+    }
+}
+
+/// A query that asks Postgres for the plan it would use to run a
+/// `FilterQuery`, without running it
+#[derive(Debug, Clone, Constructor)]
+pub struct ExplainQuery<'a> {
+    query: FilterQuery<'a>,
+}
+
+impl<'a> QueryFragment<Pg> for ExplainQuery<'a> {
+    fn walk_ast(&self, mut out: AstPass<Pg>) -> QueryResult<()> {
+        out.unsafe_to_cache_prepared();
+
+        out.push_sql("explain (format json)\n");
+        self.query.walk_ast(out.reborrow())
+    }
+}
+
+impl<'a> QueryId for ExplainQuery<'a> {
+    type QueryId = ();
+
+    const HAS_STATIC_QUERY_ID: bool = false;
+}
+
+impl<'a> LoadQuery<PgConnection, ExplainData> for ExplainQuery<'a> {
+    fn internal_load(self, conn: &PgConnection) -> QueryResult<Vec<ExplainData>> {
+        conn.query_by_name(&self)
+    }
+}
+
+impl<'a, Conn> RunQueryDsl<Conn> for ExplainQuery<'a> {}
+
+/// A query that runs the statement `name`, which must have been prepared
+/// on the connection from the `shape_sql` of `query`, with the bind
+/// parameters of `query`
+#[derive(Debug, Clone, Constructor)]
+pub struct ExecutePreparedQuery<'a> {
+    name: String,
+    bind_count: usize,
+    query: FilterQuery<'a>,
+}
+
+impl<'a> QueryFragment<Pg> for ExecutePreparedQuery<'a> {
+    fn walk_ast(&self, out: AstPass<Pg>) -> QueryResult<()> {
+        // Only used to collect bind parameters; the SQL comes from `to_sql`
+        self.query.walk_ast(out)
+    }
+
+    fn to_sql(&self, out: &mut PgQueryBuilder) -> QueryResult<()> {
+        out.push_sql("execute ");
+        out.push_sql(&self.name);
+        if self.bind_count > 0 {
+            out.push_sql("(");
+            for i in 0..self.bind_count {
+                if i > 0 {
+                    out.push_sql(", ");
+                }
+                out.push_bind_param();
+            }
+            out.push_sql(")");
+        }
+        Ok(())
+    }
+
+    fn is_safe_to_cache_prepared(&self) -> QueryResult<bool> {
+        Ok(false)
+    }
+}
+
+impl<'a> QueryId for ExecutePreparedQuery<'a> {
+    type QueryId = ();
+
+    const HAS_STATIC_QUERY_ID: bool = false;
+}
+
+impl<'a> LoadQuery<PgConnection, EntityData> for ExecutePreparedQuery<'a> {
+    fn internal_load(self, conn: &PgConnection) -> QueryResult<Vec<EntityData>> {
+        conn.query_by_name(&self)
+    }
+}
+
+impl<'a, Conn> RunQueryDsl<Conn> for ExecutePreparedQuery<'a> {}
+
+#[derive(QueryableByName)]
+pub struct CountData {
+    #[sql_type = "diesel::sql_types::BigInt"]
+    pub count: i64,
+}
+
+/// Count the entities in a collection without retrieving them
+#[derive(Debug, Clone)]
+pub struct CountQuery<'a> {
+    entities: &'a Vec<(&'a Table, Option<QueryFilter<'a>>)>,
+    block: BlockNumber,
+}
+
+impl<'a> CountQuery<'a> {
+    pub fn new(
+        collection: &'a FilterCollection<'a>,
+        block: BlockNumber,
+    ) -> Result<Self, QueryExecutionError> {
+        match collection {
+            FilterCollection::All(entities) => Ok(CountQuery { entities, block }),
+            FilterCollection::SingleWindow(_) | FilterCollection::MultiWindow(_, _) => Err(
+                QueryExecutionError::NotSupported("counting entities in a window".to_string()),
+            ),
+        }
+    }
+}
+
+impl<'a> QueryFragment<Pg> for CountQuery<'a> {
+    fn walk_ast(&self, mut out: AstPass<Pg>) -> QueryResult<()> {
+        out.unsafe_to_cache_prepared();
+
+        // Generate
+        //    select count(*) as count from (
+        //      select c.vid
+        //        from schema.table1 c
+        //       where block_range @> $block
+        //         and query_filter
+        //      union all
+        //      ...) c
+        out.push_sql("select count(*) as count from (");
+        for (i, (table, filter)) in self.entities.iter().enumerate() {
+            if i > 0 {
+                out.push_sql("\nunion all\n");
+            }
+            out.push_sql("select c.vid\n  from ");
+            out.push_sql(table.qualified_name.as_str());
+            out.push_sql(" c\n where ");
+            BlockRangeContainsClause::new(&table, "c.", self.block).walk_ast(out.reborrow())?;
+            if let Some(filter) = filter {
+                out.push_sql(" and ");
+                filter.walk_ast(out.reborrow())?;
+            }
+        }
+        out.push_sql(") c");
+        Ok(())
+    }
+}
+
+impl<'a> QueryId for CountQuery<'a> {
+    type QueryId = ();
+
+    const HAS_STATIC_QUERY_ID: bool = false;
+}
+
+impl<'a> LoadQuery<PgConnection, CountData> for CountQuery<'a> {
+    fn internal_load(self, conn: &PgConnection) -> QueryResult<Vec<CountData>> {
+        conn.query_by_name(&self)
+    }
+}
+
+impl<'a, Conn> RunQueryDsl<Conn> for CountQuery<'a> {}
+
 /// Reduce the upper bound of the current entry's block range to `block` as
 /// long as that does not result in an empty block range
 #[derive(Debug, Clone, Constructor)]
@@ -2503,7 +3725,7 @@ pub struct ClampRangeQuery<'a> {
 impl<'a> QueryFragment<Pg> for ClampRangeQuery<'a> {
     fn walk_ast(&self, mut out: AstPass<Pg>) -> QueryResult<()> {
         // update table
-        //    set block_range = int4range(lower(block_range), $block)
+        //    set block_range = int8range(lower(block_range), $block)
         //  where id = $id
         //    and block_range @> INTMAX
         out.unsafe_to_cache_prepared();
@@ -2511,10 +3733,10 @@ impl<'a> QueryFragment<Pg> for ClampRangeQuery<'a> {
         out.push_sql(self.table.qualified_name.as_str());
         out.push_sql("\n   set ");
         out.push_identifier(BLOCK_RANGE_COLUMN)?;
-        out.push_sql(" = int4range(lower(");
+        out.push_sql(" = int8range(lower(");
         out.push_identifier(BLOCK_RANGE_COLUMN)?;
         out.push_sql("), ");
-        out.push_bind_param::<Integer, _>(&self.block)?;
+        out.push_bind_param::<BigInt, _>(&self.block)?;
         out.push_sql(")\n where ");
         self.table.primary_key().eq(&self.key.entity_id, &mut out)?;
         out.push_sql(" and (");
@@ -2577,7 +3799,7 @@ impl<'a> QueryFragment<Pg> for RevertRemoveQuery<'a> {
         out.push_sql("\n where lower(");
         out.push_identifier(BLOCK_RANGE_COLUMN)?;
         out.push_sql(") >= ");
-        out.push_bind_param::<Integer, _>(&self.block)?;
+        out.push_bind_param::<BigInt, _>(&self.block)?;
         out.push_sql("\nreturning ");
         out.push_sql(PRIMARY_KEY_COLUMN);
         out.push_sql("::text");
@@ -2600,6 +3822,157 @@ impl<'a> LoadQuery<PgConnection, RevertEntityData> for RevertRemoveQuery<'a> {
 
 impl<'a, Conn> RunQueryDsl<Conn> for RevertRemoveQuery<'a> {}
 
+/// A query that deletes all versions of the entities in `table` that were
+/// last changed before `cutoff`. Entities that were removed at or after
+/// `cutoff` are kept so that their removal can still be reverted
+#[derive(Debug, Clone, Constructor)]
+pub struct PruneExpiredQuery<'a> {
+    table: &'a Table,
+    cutoff: BlockNumber,
+}
+
+impl<'a> QueryFragment<Pg> for PruneExpiredQuery<'a> {
+    fn walk_ast(&self, mut out: AstPass<Pg>) -> QueryResult<()> {
+        out.unsafe_to_cache_prepared();
+
+        // Construct a query
+        //   delete from table
+        //    where id in (select id from table
+        //                  group by id
+        //                 having max(lower(block_range)) < $cutoff
+        //                    and coalesce(max(upper(block_range)), 0) < $cutoff)
+        out.push_sql("delete from ");
+        out.push_sql(self.table.qualified_name.as_str());
+        out.push_sql("\n where ");
+        out.push_identifier(PRIMARY_KEY_COLUMN)?;
+        out.push_sql(" in (select ");
+        out.push_identifier(PRIMARY_KEY_COLUMN)?;
+        out.push_sql(" from ");
+        out.push_sql(self.table.qualified_name.as_str());
+        out.push_sql("\n group by ");
+        out.push_identifier(PRIMARY_KEY_COLUMN)?;
+        out.push_sql("\nhaving max(lower(");
+        out.push_identifier(BLOCK_RANGE_COLUMN)?;
+        out.push_sql(")) < ");
+        out.push_bind_param::<BigInt, _>(&self.cutoff)?;
+        out.push_sql("\n   and coalesce(max(upper(");
+        out.push_identifier(BLOCK_RANGE_COLUMN)?;
+        out.push_sql(")), 0) < ");
+        out.push_bind_param::<BigInt, _>(&self.cutoff)?;
+        out.push_sql(")");
+        Ok(())
+    }
+}
+
+impl<'a> QueryId for PruneExpiredQuery<'a> {
+    type QueryId = ();
+
+    const HAS_STATIC_QUERY_ID: bool = false;
+}
+
+impl<'a, Conn> RunQueryDsl<Conn> for PruneExpiredQuery<'a> {}
+
+/// A query that deletes the versions of the entities in `table` that were
+/// replaced at a block in `[from, to)` and that were not current at any
+/// block that is a multiple of `granularity`. Queries at such multiples
+/// therefore see the same data as before
+#[derive(Debug, Clone, Constructor)]
+pub struct CoarsenHistoryQuery<'a> {
+    table: &'a Table,
+    granularity: BlockNumber,
+    from: BlockNumber,
+    to: BlockNumber,
+}
+
+impl<'a> CoarsenHistoryQuery<'a> {
+    /// The upper bound of the block range in the form in which it is
+    /// indexed by the index for closed block ranges
+    fn upper(&self, out: &mut AstPass<Pg>) -> QueryResult<()> {
+        out.push_sql("coalesce(upper(");
+        out.push_identifier(BLOCK_RANGE_COLUMN)?;
+        out.push_sql(&format!("), {})", BLOCK_NUMBER_MAX));
+        Ok(())
+    }
+}
+
+impl<'a> QueryFragment<Pg> for CoarsenHistoryQuery<'a> {
+    fn walk_ast(&self, mut out: AstPass<Pg>) -> QueryResult<()> {
+        out.unsafe_to_cache_prepared();
+
+        // Construct a query
+        //   delete from table
+        //    where coalesce(upper(block_range), $MAX) >= $from
+        //      and coalesce(upper(block_range), $MAX) < $to
+        //      and (coalesce(upper(block_range), $MAX) - 1)
+        //            / $granularity * $granularity < lower(block_range)
+        // The last condition checks that the largest multiple of
+        // $granularity that is smaller than the upper bound is not in the
+        // block range
+        out.push_sql("delete from ");
+        out.push_sql(self.table.qualified_name.as_str());
+        out.push_sql("\n where ");
+        self.upper(&mut out)?;
+        out.push_sql(" >= ");
+        out.push_bind_param::<BigInt, _>(&self.from)?;
+        out.push_sql("\n   and ");
+        self.upper(&mut out)?;
+        out.push_sql(" < ");
+        out.push_bind_param::<BigInt, _>(&self.to)?;
+        out.push_sql("\n   and (");
+        self.upper(&mut out)?;
+        out.push_sql(" - 1) / ");
+        out.push_bind_param::<BigInt, _>(&self.granularity)?;
+        out.push_sql(" * ");
+        out.push_bind_param::<BigInt, _>(&self.granularity)?;
+        out.push_sql(" < lower(");
+        out.push_identifier(BLOCK_RANGE_COLUMN)?;
+        out.push_sql(")");
+        Ok(())
+    }
+}
+
+impl<'a> QueryId for CoarsenHistoryQuery<'a> {
+    type QueryId = ();
+
+    const HAS_STATIC_QUERY_ID: bool = false;
+}
+
+impl<'a, Conn> RunQueryDsl<Conn> for CoarsenHistoryQuery<'a> {}
+
+/// A query that deletes the versions of the entities in `table` that were
+/// replaced before `block`. Queries at `block` or later therefore see the
+/// same data as before
+#[derive(Debug, Clone, Constructor)]
+pub struct PruneHistoryQuery<'a> {
+    table: &'a Table,
+    block: BlockNumber,
+}
+
+impl<'a> QueryFragment<Pg> for PruneHistoryQuery<'a> {
+    fn walk_ast(&self, mut out: AstPass<Pg>) -> QueryResult<()> {
+        out.unsafe_to_cache_prepared();
+
+        // Construct a query
+        //   delete from table
+        //    where coalesce(upper(block_range), $MAX) <= $block
+        out.push_sql("delete from ");
+        out.push_sql(self.table.qualified_name.as_str());
+        out.push_sql("\n where coalesce(upper(");
+        out.push_identifier(BLOCK_RANGE_COLUMN)?;
+        out.push_sql(&format!("), {}) <= ", BLOCK_NUMBER_MAX));
+        out.push_bind_param::<BigInt, _>(&self.block)?;
+        Ok(())
+    }
+}
+
+impl<'a> QueryId for PruneHistoryQuery<'a> {
+    type QueryId = ();
+
+    const HAS_STATIC_QUERY_ID: bool = false;
+}
+
+impl<'a, Conn> RunQueryDsl<Conn> for PruneHistoryQuery<'a> {}
+
 /// A query that unclamps the block range of all versions that contain
 /// `block` by setting the upper bound of the block range to infinity.
 #[derive(Debug, Clone, Constructor)]
@@ -2614,7 +3987,7 @@ impl<'a> QueryFragment<Pg> for RevertClampQuery<'a> {
 
         // Construct a query
         //   update table
-        //     set block_range = int4range(lower(block_range), null)
+        //     set block_range = int8range(lower(block_range), null)
         //   where block_range @> $block
         //     and not block_range @> INTMAX
         //     and lower(block_range) <= $block
@@ -2630,25 +4003,25 @@ impl<'a> QueryFragment<Pg> for RevertClampQuery<'a> {
         out.push_sql(self.table.qualified_name.as_str());
         out.push_sql("\n   set ");
         out.push_identifier(BLOCK_RANGE_COLUMN)?;
-        out.push_sql(" = int4range(lower(");
+        out.push_sql(" = int8range(lower(");
         out.push_identifier(BLOCK_RANGE_COLUMN)?;
         out.push_sql("), null)\n where ");
         out.push_identifier(BLOCK_RANGE_COLUMN)?;
         out.push_sql(" @> ");
-        out.push_bind_param::<Integer, _>(&self.block)?;
+        out.push_bind_param::<BigInt, _>(&self.block)?;
         out.push_sql(" and not ");
         out.push_sql(BLOCK_RANGE_CURRENT);
         out.push_sql(" and lower(");
         out.push_sql(BLOCK_RANGE_COLUMN);
         out.push_sql(") <= ");
-        out.push_bind_param::<Integer, _>(&self.block)?;
+        out.push_bind_param::<BigInt, _>(&self.block)?;
         out.push_sql(" and coalesce(upper(");
         out.push_sql(BLOCK_RANGE_COLUMN);
-        out.push_sql("), 2147483647) > ");
-        out.push_bind_param::<Integer, _>(&self.block)?;
+        out.push_sql("), 9223372036854775807) > ");
+        out.push_bind_param::<BigInt, _>(&self.block)?;
         out.push_sql(" and coalesce(upper(");
         out.push_sql(BLOCK_RANGE_COLUMN);
-        out.push_sql("), 2147483647) < 2147483647");
+        out.push_sql("), 9223372036854775807) < 9223372036854775807");
         out.push_sql("\nreturning ");
         out.push_sql(PRIMARY_KEY_COLUMN);
         out.push_sql("::text");
@@ -2672,11 +4045,11 @@ impl<'a> LoadQuery<PgConnection, RevertEntityData> for RevertClampQuery<'a> {
 impl<'a, Conn> RunQueryDsl<Conn> for RevertClampQuery<'a> {}
 
 #[test]
-fn block_number_max_is_i32_max() {
-    // The code in RevertClampQuery::walk_ast embeds i32::MAX
+fn block_number_max_is_i64_max() {
+    // The code in RevertClampQuery::walk_ast embeds i64::MAX
     // aka BLOCK_NUMBER_MAX in strings for efficiency. This assertion
     // makes sure that BLOCK_NUMBER_MAX still is what we think it is
-    assert_eq!(2147483647, graph::prelude::BLOCK_NUMBER_MAX);
+    assert_eq!(9223372036854775807, graph::prelude::BLOCK_NUMBER_MAX);
 }
 
 /// A query that removes all dynamic data sources for a given subgraph
@@ -2703,7 +4076,7 @@ impl<'a> QueryFragment<Pg> for DeleteDynamicDataSourcesQuery<'a> {
             out.push_sql(" lower(");
             out.push_identifier(BLOCK_RANGE_COLUMN)?;
             out.push_sql(") >= ");
-            out.push_bind_param::<Integer, _>(&self.block)?;
+            out.push_bind_param::<BigInt, _>(&self.block)?;
             out.push_sql(" and");
         }
         out.push_sql(" deployment = ");
@@ -0,0 +1,125 @@
+//! Reuse of prepared statements for entity queries. Popular GraphQL
+//! queries lead to the same parameterized SQL being run thousands of times
+//! a minute, and Postgres plans it from scratch every time. Setting
+//! `GRAPH_STORE_PREPARED_STATEMENTS` to a positive number makes us prepare
+//! the SQL for each deployment and query shape as a named statement on the
+//! connection that runs it, and run it with `execute` after that. Since
+//! Postgres keeps prepared statements around for the lifetime of the
+//! connection, we keep at most that many of them on each connection and
+//! deallocate the ones that were prepared the longest time ago to make
+//! room for new ones
+use diesel::connection::SimpleConnection;
+use diesel::pg::PgConnection;
+use diesel::sql_types::{BigInt, Text};
+use diesel::{sql_query, Connection, RunQueryDsl};
+use lazy_static::lazy_static;
+
+lazy_static! {
+    /// The maximum number of statements that we keep prepared on each
+    /// connection; `0` turns reuse of prepared statements off
+    static ref MAX_PREPARED_STATEMENTS: usize =
+        std::env::var("GRAPH_STORE_PREPARED_STATEMENTS")
+            .ok()
+            .map(|s| s.parse().expect("invalid GRAPH_STORE_PREPARED_STATEMENTS"))
+            .unwrap_or(0);
+}
+
+/// The prefix of the names of the statements that we prepare. It keeps
+/// them apart from the statements that Diesel prepares
+const PREFIX: &str = "gq_";
+
+#[derive(QueryableByName)]
+struct PreparedCount {
+    #[sql_type = "BigInt"]
+    found: i64,
+    #[sql_type = "BigInt"]
+    total: i64,
+}
+
+#[derive(QueryableByName)]
+struct PreparedName {
+    #[sql_type = "Text"]
+    name: String,
+}
+
+/// Whether queries should be run as prepared statements
+pub(crate) fn enabled() -> bool {
+    *MAX_PREPARED_STATEMENTS > 0
+}
+
+/// The name of the prepared statement for queries whose fingerprint has
+/// the hash `hash`
+pub(crate) fn statement_name(hash: &str) -> String {
+    format!("{}{}", PREFIX, hash)
+}
+
+/// Make sure that `sql` is prepared as the statement `name` on `conn`,
+/// deallocating older statements if the connection has too many. This
+/// runs in a savepoint if `conn` is in a transaction, so that a failure
+/// to prepare the statement does not abort that transaction
+pub(crate) fn prepare(
+    conn: &PgConnection,
+    name: &str,
+    sql: &str,
+) -> Result<(), diesel::result::Error> {
+    conn.transaction(|| {
+        // `pg_prepared_statements` only lists the statements of the
+        // current connection
+        let counts = sql_query(
+            "select count(*) filter (where name = $1) as found, count(*) as total \
+               from pg_prepared_statements \
+              where name like 'gq\\_%'",
+        )
+        .bind::<Text, _>(name)
+        .get_result::<PreparedCount>(conn)?;
+        if counts.found > 0 {
+            return Ok(());
+        }
+
+        let excess = counts.total + 1 - *MAX_PREPARED_STATEMENTS as i64;
+        if excess > 0 {
+            let evicted = sql_query(
+                "select name from pg_prepared_statements \
+                  where name like 'gq\\_%' \
+                  order by prepare_time \
+                  limit $1",
+            )
+            .bind::<BigInt, _>(excess)
+            .load::<PreparedName>(conn)?;
+            for statement in evicted {
+                conn.batch_execute(&format!("deallocate {}", statement.name))?;
+            }
+        }
+
+        conn.batch_execute(&format!("prepare {} as {}", name, sql))
+    })
+}
+
+/// The number of bind parameters in `sql`, i.e., the highest `$<n>` in it
+pub(crate) fn bind_count(sql: &str) -> usize {
+    let mut count = 0;
+    let mut previous = ' ';
+    let mut chars = sql.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c == '$' && !(previous.is_alphanumeric() || previous == '_') {
+            let mut n = 0;
+            while let Some(digit) = chars.peek().and_then(|d| d.to_digit(10)) {
+                n = n * 10 + digit as usize;
+                chars.next();
+            }
+            count = count.max(n);
+        }
+        previous = c;
+    }
+    count
+}
+
+#[test]
+fn bind_params() {
+    assert_eq!(0, bind_count("select 1"));
+    assert_eq!(
+        3,
+        bind_count("select m.g$parent_id from t where a = $1 and b = any($3) and c = $2")
+    );
+    assert_eq!(12, bind_count("select $12, $2"));
+}
@@ -1,20 +1,24 @@
+use std::collections::BTreeMap;
 use std::sync::Arc;
 
 use graph::{
     components::{
-        server::index_node::VersionInfo,
+        server::index_node::{StorageLayout, VersionInfo},
         store::{
-            BlockStore as BlockStoreTrait, EntityType, QueryStoreManager, StatusStore,
-            StoredDynamicDataSource, SubgraphStore as SubgraphStoreTrait,
+            BlockStore as BlockStoreTrait, ChainStore as ChainStoreTrait, EntityType,
+            QueryStoreManager, StatusStore, StoredDynamicDataSource,
+            SubgraphStore as SubgraphStoreTrait,
         },
+        subgraph::ProofOfIndexingVersion,
     },
     constraint_violation,
-    data::subgraph::schema::SubgraphError,
+    data::subgraph::schema::{SkippedTrigger, SubgraphError},
     data::subgraph::status,
     prelude::{
-        web3::types::Address, CheapClone, Error, EthereumBlockPointer, NodeId, QueryExecutionError,
-        QueryStore as QueryStoreTrait, Schema, StoreError, SubgraphDeploymentEntity,
-        SubgraphDeploymentId, SubgraphName, SubgraphVersionSwitchingMode,
+        web3::types::Address, AggregateRow, BlockNumber, CheapClone, EntityAggregationQuery,
+        EntityStream, Error, EthereumBlock, EthereumBlockPointer, LoggedEntityChange, NodeId,
+        QueryExecutionError, QueryStore as QueryStoreTrait, Schema, StoreError,
+        SubgraphDeploymentEntity, SubgraphDeploymentId, SubgraphName, SubgraphVersionSwitchingMode,
     },
 };
 
@@ -42,7 +46,7 @@ impl Store {
 
     // Only for tests to simplify their handling of test fixtures, so that
     // tests can reset the block pointer of a subgraph by recreating it
-    #[cfg(debug_assertions)]
+    #[cfg(any(debug_assertions, feature = "testing"))]
     pub fn create_deployment_replace(
         &self,
         name: SubgraphName,
@@ -56,7 +60,7 @@ impl Store {
             .create_deployment_replace(name, schema, deployment, node_id, network_name, mode)
     }
 
-    #[cfg(debug_assertions)]
+    #[cfg(any(debug_assertions, feature = "testing"))]
     pub fn delete_all_entities_for_test_use_only(&self) -> Result<(), StoreError> {
         self.store.delete_all_entities_for_test_use_only()
     }
@@ -78,15 +82,23 @@ impl SubgraphStoreTrait for Store {
         self.store.clone().supports_proof_of_indexing(subgraph_id)
     }
 
+    fn proof_of_indexing_versions(
+        &self,
+        subgraph_id: &graph::prelude::SubgraphDeploymentId,
+    ) -> Result<Vec<ProofOfIndexingVersion>, StoreError> {
+        self.store.proof_of_indexing_versions(subgraph_id)
+    }
+
     fn get_proof_of_indexing<'a>(
         self: Arc<Self>,
         subgraph_id: &'a graph::prelude::SubgraphDeploymentId,
         indexer: &'a Option<Address>,
         block: EthereumBlockPointer,
+        version: Option<ProofOfIndexingVersion>,
     ) -> graph::prelude::DynTryFuture<'a, Option<[u8; 32]>> {
         self.store
             .clone()
-            .get_proof_of_indexing(subgraph_id, indexer, block)
+            .get_proof_of_indexing(subgraph_id, indexer, block, version)
     }
 
     fn get(
@@ -100,11 +112,12 @@ impl SubgraphStoreTrait for Store {
         &self,
         subgraph_id: &graph::prelude::SubgraphDeploymentId,
         ids_for_type: std::collections::BTreeMap<&EntityType, Vec<&str>>,
+        block: BlockNumber,
     ) -> Result<
         std::collections::BTreeMap<EntityType, Vec<graph::prelude::Entity>>,
         graph::prelude::StoreError,
     > {
-        self.store.get_many(subgraph_id, ids_for_type)
+        self.store.get_many(subgraph_id, ids_for_type, block)
     }
 
     fn find(
@@ -121,6 +134,24 @@ impl SubgraphStoreTrait for Store {
         self.store.find_one(query)
     }
 
+    fn find_stream(
+        &self,
+        query: graph::prelude::EntityQuery,
+    ) -> Result<EntityStream, QueryExecutionError> {
+        self.store.find_stream(query)
+    }
+
+    fn count(&self, query: graph::prelude::EntityQuery) -> Result<u64, QueryExecutionError> {
+        self.store.count(query)
+    }
+
+    fn aggregate(
+        &self,
+        query: EntityAggregationQuery,
+    ) -> Result<Vec<AggregateRow>, QueryExecutionError> {
+        self.store.aggregate(query)
+    }
+
     fn find_ens_name(&self, hash: &str) -> Result<Option<String>, QueryExecutionError> {
         self.store.find_ens_name(hash)
     }
@@ -132,6 +163,7 @@ impl SubgraphStoreTrait for Store {
         mods: Vec<graph::prelude::EntityModification>,
         stopwatch: graph::prelude::StopwatchMetrics,
         deterministic_errors: Vec<SubgraphError>,
+        skipped_triggers: Vec<SkippedTrigger>,
     ) -> Result<(), graph::prelude::StoreError> {
         self.store.transact_block_operations(
             subgraph_id,
@@ -139,6 +171,7 @@ impl SubgraphStoreTrait for Store {
             mods,
             stopwatch,
             deterministic_errors,
+            skipped_triggers,
         )
     }
 
@@ -261,6 +294,81 @@ impl SubgraphStoreTrait for Store {
     ) -> Result<String, StoreError> {
         self.store.network_name(subgraph_id)
     }
+
+    fn skipped_triggers(
+        &self,
+        id: &SubgraphDeploymentId,
+    ) -> Result<Vec<SkippedTrigger>, StoreError> {
+        self.store.skipped_triggers(id)
+    }
+
+    fn mark_skipped_triggers_replayed(
+        &self,
+        id: &SubgraphDeploymentId,
+        graft_block: BlockNumber,
+        replayed_by: &SubgraphDeploymentId,
+    ) -> Result<usize, StoreError> {
+        self.store
+            .mark_skipped_triggers_replayed(id, graft_block, replayed_by)
+    }
+
+    fn set_subscriptions_disabled(
+        &self,
+        id: &SubgraphDeploymentId,
+        disabled: bool,
+    ) -> Result<(), StoreError> {
+        self.store.set_subscriptions_disabled(id, disabled)
+    }
+
+    fn deployment_context(
+        &self,
+        id: &SubgraphDeploymentId,
+    ) -> Result<BTreeMap<String, String>, StoreError> {
+        self.store.deployment_context(id)
+    }
+
+    fn set_deployment_context(
+        &self,
+        id: &SubgraphDeploymentId,
+        key: String,
+        value: Option<String>,
+    ) -> Result<(), StoreError> {
+        self.store.set_deployment_context(id, key, value)
+    }
+
+    fn register_change_log_consumer(
+        &self,
+        id: &SubgraphDeploymentId,
+        consumer: &str,
+    ) -> Result<i64, StoreError> {
+        self.store.register_change_log_consumer(id, consumer)
+    }
+
+    fn remove_change_log_consumer(
+        &self,
+        id: &SubgraphDeploymentId,
+        consumer: &str,
+    ) -> Result<(), StoreError> {
+        self.store.remove_change_log_consumer(id, consumer)
+    }
+
+    fn entity_changes(
+        &self,
+        id: &SubgraphDeploymentId,
+        consumer: &str,
+        limit: usize,
+    ) -> Result<Vec<LoggedEntityChange>, StoreError> {
+        self.store.entity_changes(id, consumer, limit)
+    }
+
+    fn ack_entity_changes(
+        &self,
+        id: &SubgraphDeploymentId,
+        consumer: &str,
+        seq: i64,
+    ) -> Result<(), StoreError> {
+        self.store.ack_entity_changes(id, consumer, seq)
+    }
 }
 
 impl QueryStoreManager for Store {
@@ -302,6 +410,13 @@ impl StatusStore for Store {
         Ok(info)
     }
 
+    fn storage_layout(
+        &self,
+        subgraph_id: &SubgraphDeploymentId,
+    ) -> Result<StorageLayout, StoreError> {
+        self.store.storage_layout(subgraph_id)
+    }
+
     fn versions_for_subgraph_id(
         &self,
         subgraph_id: &str,
@@ -321,9 +436,33 @@ impl StatusStore for Store {
         subgraph_id: &'a SubgraphDeploymentId,
         indexer: &'a Option<Address>,
         block: EthereumBlockPointer,
+        version: Option<ProofOfIndexingVersion>,
     ) -> graph::prelude::DynTryFuture<'a, Option<[u8; 32]>> {
         self.store
             .clone()
-            .get_proof_of_indexing(subgraph_id, indexer, block)
+            .get_proof_of_indexing(subgraph_id, indexer, block, version)
+    }
+
+    fn entity_digests(
+        &self,
+        subgraph_id: &SubgraphDeploymentId,
+        block: BlockNumber,
+    ) -> Result<BTreeMap<String, status::EntityDigest>, StoreError> {
+        self.store.entity_digests(subgraph_id, block)
+    }
+
+    fn block_by_number(
+        &self,
+        network: &str,
+        number: BlockNumber,
+    ) -> Result<Option<EthereumBlock>, StoreError> {
+        match self.block_store.chain_store(network) {
+            Some(chain_store) => Ok(chain_store.block_by_number(number)?),
+            None => Ok(None),
+        }
+    }
+
+    fn call_cache_sizes(&self) -> Result<Vec<status::CallCacheSize>, StoreError> {
+        self.block_store.call_cache_sizes()
     }
 }
@@ -12,22 +12,24 @@ use std::{fmt, io::Write};
 
 use graph::{
     components::{
-        server::index_node::VersionInfo,
+        server::index_node::{StorageLayout, VersionInfo},
         store::{self, EntityType},
+        subgraph::ProofOfIndexingVersion,
     },
     constraint_violation,
     data::query::QueryTarget,
     data::subgraph::schema::MetadataType,
-    data::subgraph::schema::SubgraphError,
+    data::subgraph::schema::{SkippedTrigger, SubgraphError},
     data::subgraph::status,
     prelude::StoreEvent,
     prelude::SubgraphDeploymentEntity,
     prelude::{
-        lazy_static, o, web3::types::Address, ApiSchema, CheapClone, DeploymentState, DynTryFuture,
-        Entity, EntityKey, EntityModification, EntityQuery, Error, EthereumBlockPointer, Logger,
-        MetadataOperation, MetricsRegistry, NodeId, QueryExecutionError, Schema, StopwatchMetrics,
-        StoreError, SubgraphDeploymentId, SubgraphName, SubgraphStore as SubgraphStoreTrait,
-        SubgraphVersionSwitchingMode,
+        lazy_static, o, web3::types::Address, AggregateRow, ApiSchema, BlockNumber, CheapClone,
+        DeploymentState, Duration, DynTryFuture, Entity, EntityAggregationQuery, EntityKey,
+        EntityModification, EntityQuery, EntityStream, Error, EthereumBlockPointer,
+        LoggedEntityChange, Logger, MetadataOperation, MetricsRegistry, NodeId,
+        QueryExecutionError, Schema, StopwatchMetrics, StoreError, SubgraphDeploymentId,
+        SubgraphName, SubgraphStore as SubgraphStoreTrait, SubgraphVersionSwitchingMode,
     },
 };
 use store::StoredDynamicDataSource;
@@ -36,7 +38,7 @@ use crate::{connection_pool::ConnectionPool, deployment, primary, primary::Site}
 use crate::{
     deployment_store::{DeploymentStore, ReplicaId},
     detail::DeploymentDetail,
-    primary::UnusedDeployment,
+    primary::{NamedLock, UnusedDeployment},
 };
 
 /// The name of a database shard; valid names must match `[a-z0-9_]+`
@@ -178,7 +180,7 @@ impl SubgraphStore {
     }
 
     // Only needed for tests
-    #[cfg(debug_assertions)]
+    #[cfg(any(debug_assertions, feature = "testing"))]
     pub(crate) fn clear_caches(&self) {
         for store in self.stores.values() {
             store.layout_cache.lock().unwrap().clear();
@@ -271,15 +273,18 @@ impl SubgraphStore {
         // be 'false'
         replace: bool,
     ) -> Result<(), StoreError> {
-        #[cfg(not(debug_assertions))]
+        #[cfg(not(any(debug_assertions, feature = "testing")))]
         assert!(!replace);
 
         let (shard, node_id) = self.place(&name, &network_name, node_id)?;
 
-        // TODO: Check this for behavior on failure
+        // If an earlier attempt to create this deployment failed after
+        // allocating a site, we get that site back and have to keep using
+        // its shard, even if placement would now choose a different one
         let site = self
             .primary_conn()?
-            .allocate_site(shard.clone(), &schema.id, network_name)?;
+            .allocate_site(shard, &schema.id, network_name)?;
+        let shard = site.shard.clone();
 
         let graft_site = deployment
             .graft_base
@@ -327,7 +332,7 @@ impl SubgraphStore {
 
     // Only for tests to simplify their handling of test fixtures, so that
     // tests can reset the block pointer of a subgraph by recreating it
-    #[cfg(debug_assertions)]
+    #[cfg(any(debug_assertions, feature = "testing"))]
     pub fn create_deployment_replace(
         &self,
         name: SubgraphName,
@@ -372,7 +377,7 @@ impl SubgraphStore {
     /// Delete all entities. This function exists solely for integration tests
     /// and should never be called from any other code. Unfortunately, Rust makes
     /// it very hard to export items just for testing
-    #[cfg(debug_assertions)]
+    #[cfg(any(debug_assertions, feature = "testing"))]
     pub fn delete_all_entities_for_test_use_only(&self) -> Result<(), StoreError> {
         use diesel::connection::SimpleConnection;
 
@@ -491,6 +496,30 @@ impl SubgraphStore {
         self.primary_conn()?.list_unused_deployments(filter)
     }
 
+    /// Try to take the named lock `name` on behalf of `holder` for `ttl`.
+    /// Locks are stored in the primary so that tools outside of graph-node
+    /// can coordinate work with each other and with graph-node. A holder
+    /// that needs the lock for longer than `ttl` must call this again
+    /// before the lock expires to extend it
+    pub fn acquire_lock(
+        &self,
+        name: &str,
+        holder: &str,
+        ttl: Duration,
+    ) -> Result<bool, StoreError> {
+        self.primary_conn()?.acquire_lock(name, holder, ttl)
+    }
+
+    /// Release the named lock `name` if it is held by `holder`
+    pub fn release_lock(&self, name: &str, holder: &str) -> Result<bool, StoreError> {
+        self.primary_conn()?.release_lock(name, holder)
+    }
+
+    /// List all named locks that are currently held
+    pub fn named_locks(&self) -> Result<Vec<NamedLock>, StoreError> {
+        self.primary_conn()?.named_locks()
+    }
+
     /// Remove a deployment, i.e., all its data and metadata. This is only permissible
     /// if the deployment is unused in the sense that it is neither the current nor
     /// pending version of any subgraph, and is not currently assigned to any node
@@ -523,11 +552,24 @@ impl SubgraphStore {
         store.drop_deployment(&site)?;
 
         self.primary_conn()?.drop_site(&site.deployment)?;
+        self.sites.write().unwrap().remove(&site.deployment);
 
         Ok(())
     }
 
-    pub(crate) fn status(&self, filter: status::Filter) -> Result<Vec<status::Info>, StoreError> {
+    /// Delete the versions of entities of the deployment `id` that were
+    /// replaced before `block`, making queries at earlier blocks
+    /// impossible. Return the number of deleted versions
+    pub fn prune_history(
+        &self,
+        id: &SubgraphDeploymentId,
+        block: BlockNumber,
+    ) -> Result<usize, StoreError> {
+        let (store, site) = self.store(id)?;
+        store.prune_history(&site, block)
+    }
+
+    pub fn status(&self, filter: status::Filter) -> Result<Vec<status::Info>, StoreError> {
         let deployments = match filter {
             status::Filter::SubgraphName(name) => {
                 let deployments = self.primary_conn()?.deployments_for_subgraph(name)?;
@@ -559,7 +601,19 @@ impl SubgraphStore {
                 .ok_or(StoreError::UnknownShard(shard.to_string()))?;
             infos.extend(store.deployment_statuses(&sites)?);
         }
-        let infos = self.primary_conn()?.fill_assignments(infos)?;
+        let mut infos = self.primary_conn()?.fill_assignments(infos)?;
+
+        // Deployments can be grafted onto deployments in other shards, and
+        // we need to look in all of them for the dependents of a deployment
+        let mut dependents: HashMap<String, Vec<status::Dependency>> = HashMap::new();
+        for store in self.stores.values() {
+            for (base, dependency) in store.graft_dependents()? {
+                dependents.entry(base).or_default().push(dependency);
+            }
+        }
+        for info in &mut infos {
+            info.dependents = dependents.remove(&info.subgraph).unwrap_or_default();
+        }
         Ok(infos)
     }
 
@@ -599,6 +653,23 @@ impl SubgraphStore {
         }
     }
 
+    pub(crate) fn entity_digests(
+        &self,
+        id: &SubgraphDeploymentId,
+        block: BlockNumber,
+    ) -> Result<BTreeMap<String, status::EntityDigest>, StoreError> {
+        let (store, site) = self.store(id)?;
+        store.entity_digests(site.as_ref(), block)
+    }
+
+    pub(crate) fn storage_layout(
+        &self,
+        id: &SubgraphDeploymentId,
+    ) -> Result<StorageLayout, StoreError> {
+        let (store, site) = self.store(id)?;
+        store.storage_layout(site.as_ref())
+    }
+
     pub(crate) fn versions_for_subgraph_id(
         &self,
         subgraph_id: &str,
@@ -608,7 +679,7 @@ impl SubgraphStore {
         primary.versions_for_subgraph_id(subgraph_id)
     }
 
-    #[cfg(debug_assertions)]
+    #[cfg(any(debug_assertions, feature = "testing"))]
     pub fn error_count(&self, id: &SubgraphDeploymentId) -> Result<usize, StoreError> {
         let (store, _) = self.store(id)?;
         store.error_count(id)
@@ -630,14 +701,25 @@ impl SubgraphStoreTrait for SubgraphStore {
         store.clone().supports_proof_of_indexing(site)
     }
 
+    fn proof_of_indexing_versions(
+        &self,
+        id: &SubgraphDeploymentId,
+    ) -> Result<Vec<ProofOfIndexingVersion>, StoreError> {
+        let (store, site) = self.store(&id)?;
+        store.proof_of_indexing_versions(site.as_ref())
+    }
+
     fn get_proof_of_indexing<'a>(
         self: Arc<Self>,
         id: &'a SubgraphDeploymentId,
         indexer: &'a Option<Address>,
         block: EthereumBlockPointer,
+        version: Option<ProofOfIndexingVersion>,
     ) -> DynTryFuture<'a, Option<[u8; 32]>> {
         let (store, site) = self.store(&id).unwrap();
-        store.clone().get_proof_of_indexing(site, indexer, block)
+        store
+            .clone()
+            .get_proof_of_indexing(site, indexer, block, version)
     }
 
     fn get(&self, key: EntityKey) -> Result<Option<Entity>, QueryExecutionError> {
@@ -649,9 +731,10 @@ impl SubgraphStoreTrait for SubgraphStore {
         &self,
         id: &SubgraphDeploymentId,
         ids_for_type: BTreeMap<&EntityType, Vec<&str>>,
+        block: BlockNumber,
     ) -> Result<BTreeMap<EntityType, Vec<Entity>>, StoreError> {
         let (store, site) = self.store(&id)?;
-        store.get_many(site.as_ref(), ids_for_type)
+        store.get_many(site.as_ref(), ids_for_type, block)
     }
 
     fn find(&self, query: EntityQuery) -> Result<Vec<Entity>, QueryExecutionError> {
@@ -664,6 +747,24 @@ impl SubgraphStoreTrait for SubgraphStore {
         store.find_one(site.as_ref(), query)
     }
 
+    fn find_stream(&self, query: EntityQuery) -> Result<EntityStream, QueryExecutionError> {
+        let (store, site) = self.store(&query.subgraph_id)?;
+        store.find_stream(site, query)
+    }
+
+    fn count(&self, query: EntityQuery) -> Result<u64, QueryExecutionError> {
+        let (store, site) = self.store(&query.subgraph_id)?;
+        store.count(site.as_ref(), ReplicaId::Main, query)
+    }
+
+    fn aggregate(
+        &self,
+        query: EntityAggregationQuery,
+    ) -> Result<Vec<AggregateRow>, QueryExecutionError> {
+        let (store, site) = self.store(&query.subgraph_id)?;
+        store.aggregate(site.as_ref(), ReplicaId::Main, query)
+    }
+
     fn find_ens_name(&self, hash: &str) -> Result<Option<String>, QueryExecutionError> {
         Ok(self.primary_conn()?.find_ens_name(hash)?)
     }
@@ -675,6 +776,7 @@ impl SubgraphStoreTrait for SubgraphStore {
         mods: Vec<EntityModification>,
         stopwatch: StopwatchMetrics,
         deterministic_errors: Vec<SubgraphError>,
+        skipped_triggers: Vec<SkippedTrigger>,
     ) -> Result<(), StoreError> {
         assert!(
             mods.in_shard(&id),
@@ -687,6 +789,7 @@ impl SubgraphStoreTrait for SubgraphStore {
             mods,
             stopwatch,
             deterministic_errors,
+            skipped_triggers,
         )?;
         self.send_store_event(&event)
     }
@@ -859,6 +962,89 @@ impl SubgraphStoreTrait for SubgraphStore {
         let (_, site) = self.store(&id)?;
         Ok(site.network.to_string())
     }
+
+    fn skipped_triggers(
+        &self,
+        id: &SubgraphDeploymentId,
+    ) -> Result<Vec<SkippedTrigger>, StoreError> {
+        let (store, _) = self.store(&id)?;
+        store.skipped_triggers(id)
+    }
+
+    fn mark_skipped_triggers_replayed(
+        &self,
+        id: &SubgraphDeploymentId,
+        graft_block: BlockNumber,
+        replayed_by: &SubgraphDeploymentId,
+    ) -> Result<usize, StoreError> {
+        let (store, _) = self.store(&id)?;
+        store.mark_skipped_triggers_replayed(id, graft_block, replayed_by)
+    }
+
+    fn set_subscriptions_disabled(
+        &self,
+        id: &SubgraphDeploymentId,
+        disabled: bool,
+    ) -> Result<(), StoreError> {
+        let (store, _) = self.store(&id)?;
+        store.set_subscriptions_disabled(id, disabled)
+    }
+
+    fn deployment_context(
+        &self,
+        id: &SubgraphDeploymentId,
+    ) -> Result<BTreeMap<String, String>, StoreError> {
+        let (store, _) = self.store(&id)?;
+        store.deployment_context(id)
+    }
+
+    fn set_deployment_context(
+        &self,
+        id: &SubgraphDeploymentId,
+        key: String,
+        value: Option<String>,
+    ) -> Result<(), StoreError> {
+        let (store, _) = self.store(&id)?;
+        store.set_deployment_context(id, key, value)
+    }
+
+    fn register_change_log_consumer(
+        &self,
+        id: &SubgraphDeploymentId,
+        consumer: &str,
+    ) -> Result<i64, StoreError> {
+        let (store, _) = self.store(&id)?;
+        store.register_change_log_consumer(id, consumer)
+    }
+
+    fn remove_change_log_consumer(
+        &self,
+        id: &SubgraphDeploymentId,
+        consumer: &str,
+    ) -> Result<(), StoreError> {
+        let (store, _) = self.store(&id)?;
+        store.remove_change_log_consumer(id, consumer)
+    }
+
+    fn entity_changes(
+        &self,
+        id: &SubgraphDeploymentId,
+        consumer: &str,
+        limit: usize,
+    ) -> Result<Vec<LoggedEntityChange>, StoreError> {
+        let (store, _) = self.store(&id)?;
+        store.entity_changes(id, consumer, limit)
+    }
+
+    fn ack_entity_changes(
+        &self,
+        id: &SubgraphDeploymentId,
+        consumer: &str,
+        seq: i64,
+    ) -> Result<(), StoreError> {
+        let (store, _) = self.store(&id)?;
+        store.ack_entity_changes(id, consumer, seq)
+    }
 }
 
 trait ShardData {
@@ -2,13 +2,17 @@
 //! the chain head pointer gets updated in various situations
 
 use futures::future::IntoFuture;
+use futures::{stream, Future as _};
 use std::fmt::Debug;
 use std::sync::Arc;
 
 use graph::prelude::anyhow;
 use graph::{
     components::store::BlockStore as _,
-    prelude::{anyhow::Error, Future01CompatExt, SubgraphDeploymentId},
+    prelude::{
+        anyhow::Error, serde_json, BlockFeeStats, BlockNumber, EthereumBlock, Future01CompatExt,
+        SubgraphDeploymentId,
+    },
 };
 use graph::{components::store::ChainStore as _, prelude::QueryStoreManager};
 use graph_store_postgres::Store as DieselStore;
@@ -157,11 +161,24 @@ fn long_chain_with_uncles() {
 
 #[test]
 fn block_number() {
-    let chain = vec![&*GENESIS_BLOCK, &*BLOCK_ONE, &*BLOCK_TWO];
+    let chain = vec![
+        &*GENESIS_BLOCK,
+        &*BLOCK_ONE,
+        &*BLOCK_ONE_SIBLING,
+        &*BLOCK_TWO,
+        &*BLOCK_THREE,
+    ];
     let subgraph = SubgraphDeploymentId::new("nonExistentSubgraph").unwrap();
 
     run_test(chain, move |_, subgraph_store| -> Result<(), ()> {
         create_test_subgraph(&subgraph, "type Dummy @entity { id: ID! }");
+        transact_entity_operations(
+            &subgraph_store,
+            subgraph.clone(),
+            BLOCK_TWO.block_ptr(),
+            vec![],
+        )
+        .unwrap();
 
         let query_store = subgraph_store
             .query_store(subgraph.clone().into(), false)
@@ -177,10 +194,23 @@ fn block_number() {
         assert_eq!(Some(1), block);
 
         let block = query_store
-            .block_number(BLOCK_THREE.block_hash())
-            .expect("Looked for block 3");
+            .block_number(BLOCK_TWO.block_hash())
+            .expect("Found block 2");
+        assert_eq!(Some(2), block);
+
+        let block = query_store
+            .block_number(BLOCK_FOUR.block_hash())
+            .expect("Looked for block 4");
         assert!(block.is_none());
 
+        // The sibling of block 1 is not on the chain the subgraph indexed
+        assert!(query_store
+            .block_number(BLOCK_ONE_SIBLING.block_hash())
+            .is_err());
+
+        // Block 3 is on the chain, but the subgraph has not indexed it yet
+        assert!(query_store.block_number(BLOCK_THREE.block_hash()).is_err());
+
         Ok(())
     })
 }
@@ -230,6 +260,31 @@ fn block_hashes_by_number() {
     })
 }
 
+#[test]
+fn upsert_blocks() {
+    let chain = vec![&*GENESIS_BLOCK];
+    run_test(chain, move |store, _| {
+        // Writing the same block twice in one batch works
+        let blocks = vec![
+            BLOCK_ONE.as_ethereum_block(),
+            BLOCK_TWO.as_ethereum_block(),
+            BLOCK_TWO_NO_PARENT.as_ethereum_block(),
+            BLOCK_ONE.as_ethereum_block(),
+        ];
+        store
+            .upsert_blocks(stream::iter_ok::<_, Error>(blocks))
+            .map(move |()| {
+                let hashes = store.block_hashes_by_block_number(1).unwrap();
+                assert_eq!(vec![BLOCK_ONE.block_hash()], hashes);
+
+                let hashes = store.block_hashes_by_block_number(2).unwrap();
+                assert_eq!(2, hashes.len());
+                assert!(hashes.contains(&BLOCK_TWO.block_hash()));
+                assert!(hashes.contains(&BLOCK_TWO_NO_PARENT.block_hash()));
+            })
+    })
+}
+
 #[track_caller]
 fn check_ancestor(
     store: &Arc<DieselChainStore>,
@@ -302,3 +357,469 @@ fn ancestor_block_ommers() {
         Ok(())
     });
 }
+
+#[test]
+fn ancestor_block_canonical() {
+    let chain = vec![
+        &*GENESIS_BLOCK,
+        &*BLOCK_ONE,
+        &*BLOCK_ONE_SIBLING,
+        &*BLOCK_TWO,
+        &*BLOCK_THREE,
+        &*BLOCK_FOUR,
+        &*BLOCK_FIVE,
+    ];
+
+    run_test(chain, move |store, _| -> Result<(), Error> {
+        // Updating the chain head records the last ANCESTOR_COUNT blocks
+        // as canonical, and ancestors are looked up by number from there
+        let missing = store.attempt_chain_head_update(ANCESTOR_COUNT)?;
+        assert!(missing.is_empty());
+
+        check_ancestor(&store, &*BLOCK_FIVE, 1, &*BLOCK_FOUR)?;
+        check_ancestor(&store, &*BLOCK_FIVE, 2, &*BLOCK_THREE)?;
+        check_ancestor(&store, &*BLOCK_FIVE, 3, &*BLOCK_TWO)?;
+        // Ancestors that are older than the canonical blocks
+        check_ancestor(&store, &*BLOCK_FIVE, 4, &*BLOCK_ONE)?;
+        check_ancestor(&store, &*BLOCK_FIVE, 5, &*GENESIS_BLOCK)?;
+        // Blocks that are not on the main chain
+        check_ancestor(&store, &*BLOCK_ONE_SIBLING, 1, &*GENESIS_BLOCK)?;
+
+        let block = store.ancestor_block(BLOCK_TWO_NO_PARENT.block_ptr(), 1)?;
+        assert!(block.is_none());
+        Ok(())
+    });
+}
+
+#[test]
+fn block_by_number() {
+    let chain = vec![
+        &*GENESIS_BLOCK,
+        &*BLOCK_ONE,
+        &*BLOCK_ONE_SIBLING,
+        &*BLOCK_TWO,
+        &*BLOCK_THREE,
+        &*BLOCK_FOUR,
+        &*BLOCK_FIVE,
+    ];
+
+    run_test(chain, move |store, _| -> Result<(), Error> {
+        let hash = |number| -> Result<Option<String>, Error> {
+            Ok(store
+                .block_by_number(number)?
+                .map(|block| format!("{:x}", block.block.hash.unwrap())))
+        };
+
+        // Without a chain head, only blocks whose number is unique in the
+        // store can be found
+        assert_eq!(Some(BLOCK_THREE.hash.clone()), hash(3)?);
+        assert_eq!(None, hash(1)?);
+
+        let missing = store.attempt_chain_head_update(ANCESTOR_COUNT)?;
+        assert!(missing.is_empty());
+        assert_eq!(Some(BLOCK_FIVE.hash.clone()), hash(5)?);
+        assert_eq!(Some(BLOCK_TWO.hash.clone()), hash(2)?);
+        assert_eq!(None, hash(6)?);
+
+        // Block one is older than the canonical blocks and can only be
+        // found once its hash has been confirmed
+        assert_eq!(None, hash(1)?);
+        store.confirm_block_hash(1, &BLOCK_ONE.block_hash())?;
+        assert_eq!(Some(BLOCK_ONE.hash.clone()), hash(1)?);
+        Ok(())
+    });
+}
+
+#[test]
+fn finalize_blocks() {
+    let chain = vec![
+        &*GENESIS_BLOCK,
+        &*BLOCK_ONE,
+        &*BLOCK_ONE_SIBLING,
+        &*BLOCK_TWO,
+        &*BLOCK_TWO_NO_PARENT,
+        &*BLOCK_THREE,
+        &*BLOCK_THREE_NO_PARENT,
+        &*BLOCK_FOUR,
+        &*BLOCK_FIVE,
+    ];
+
+    run_test(chain, move |store, _| -> Result<(), Error> {
+        // Nothing can be finalized without a chain head
+        assert_eq!(None, store.finalize_blocks(2)?);
+        assert_eq!(None, store.finalized_block_number()?);
+
+        let missing = store.attempt_chain_head_update(ANCESTOR_COUNT)?;
+        assert!(missing.is_empty());
+
+        // Blocks two and three are final; the blocks at these heights that
+        // are not on the main chain are purged. Block one is older than
+        // the canonical blocks and its sibling is left alone
+        assert_eq!(Some((3, 2)), store.finalize_blocks(2)?);
+        assert_eq!(Some(3), store.finalized_block_number()?);
+        assert_eq!(
+            vec![BLOCK_TWO.block_hash()],
+            store.block_hashes_by_block_number(2)?
+        );
+        assert_eq!(
+            vec![BLOCK_THREE.block_hash()],
+            store.block_hashes_by_block_number(3)?
+        );
+        assert_eq!(2, store.block_hashes_by_block_number(1)?.len());
+
+        // The watermark only moves forward
+        assert_eq!(None, store.finalize_blocks(2)?);
+        assert_eq!(None, store.finalize_blocks(4)?);
+        assert_eq!(Some(3), store.finalized_block_number()?);
+        Ok(())
+    });
+}
+
+#[test]
+fn export_blocks() {
+    let chain = vec![
+        &*GENESIS_BLOCK,
+        &*BLOCK_ONE,
+        &*BLOCK_ONE_SIBLING,
+        &*BLOCK_TWO,
+        &*BLOCK_THREE,
+    ];
+
+    run_test(chain, move |store, _| -> Result<(), Error> {
+        let export = |range: std::ops::Range<BlockNumber>| -> Result<Vec<u64>, Error> {
+            let mut out = Vec::new();
+            let count = store.export_blocks(range, &mut out)?;
+            let numbers = String::from_utf8(out)?
+                .lines()
+                .map(|line| {
+                    serde_json::from_str::<EthereumBlock>(line)
+                        .map(|block| block.block.number.unwrap().as_u64())
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            assert_eq!(count, numbers.len());
+            Ok(numbers)
+        };
+
+        assert_eq!(vec![0, 1, 1, 2, 3], export(0..BlockNumber::MAX)?);
+        assert_eq!(vec![1, 1, 2], export(1..3)?);
+        assert!(export(10..20)?.is_empty());
+        Ok(())
+    });
+}
+
+#[test]
+fn fee_history() {
+    fn stats(block: &FakeBlock, gas_used: u64, max_gas_price: u64) -> BlockFeeStats {
+        let mut stats = BlockFeeStats::from(&block.as_ethereum_block().block);
+        stats.gas_used = gas_used.into();
+        stats.transaction_count = 2;
+        stats.min_gas_price = 1.into();
+        stats.median_gas_price = 1.into();
+        stats.max_gas_price = max_gas_price.into();
+        stats
+    }
+
+    let chain = vec![&*GENESIS_BLOCK, &*BLOCK_ONE, &*BLOCK_TWO];
+    run_test(chain, move |store, _| -> Result<(), Error> {
+        assert!(store.fee_history(0, 10)?.is_empty());
+
+        let genesis = stats(&*GENESIS_BLOCK, 0, 1);
+        let one = stats(&*BLOCK_ONE, 21000, 7);
+        let two = stats(&*BLOCK_TWO, 42000, u64::MAX);
+        store.upsert_fee_stats(vec![two.clone(), genesis.clone(), one.clone()])?;
+
+        assert_eq!(
+            vec![genesis, one.clone(), two.clone()],
+            store.fee_history(0, 10)?
+        );
+        assert_eq!(vec![one.clone()], store.fee_history(1, 1)?);
+        assert!(store.fee_history(3, 10)?.is_empty());
+
+        // Statistics for a block with the same number replace the old ones
+        let sibling = stats(&*BLOCK_ONE_SIBLING, 63000, 9);
+        store.upsert_fee_stats(vec![sibling.clone()])?;
+        assert_eq!(vec![sibling, two], store.fee_history(1, 2)?);
+        Ok(())
+    });
+}
+
+#[test]
+fn transaction_receipts() {
+    use graph::prelude::{serde_json, web3::types::TransactionReceipt};
+
+    fn receipt(block: &FakeBlock, index: u64, gas_used: u64) -> TransactionReceipt {
+        serde_json::from_value(serde_json::json!({
+            "transactionHash": format!("0x{:064x}", block.number * 100 + index),
+            "transactionIndex": format!("0x{:x}", index),
+            "blockHash": format!("0x{}", block.hash),
+            "blockNumber": format!("0x{:x}", block.number),
+            "cumulativeGasUsed": format!("0x{:x}", gas_used),
+            "gasUsed": format!("0x{:x}", gas_used),
+            "contractAddress": null,
+            "logs": [],
+            "status": "0x1",
+            "logsBloom": format!("0x{}", "0".repeat(512)),
+        }))
+        .expect("valid receipt")
+    }
+
+    let chain = vec![&*GENESIS_BLOCK, &*BLOCK_ONE, &*BLOCK_TWO];
+    run_test(chain, move |store, _| -> Result<(), Error> {
+        let one = BLOCK_ONE.block_ptr().hash;
+        assert!(store.receipts_for_block(one)?.is_empty());
+
+        let first = receipt(&*BLOCK_ONE, 0, 21000);
+        let second = receipt(&*BLOCK_ONE, 1, 42000);
+        store.upsert_receipts(vec![second.clone(), first.clone()])?;
+        assert_eq!(
+            vec![first.clone(), second.clone()],
+            store.receipts_for_block(one)?
+        );
+        assert!(store
+            .receipts_for_block(BLOCK_TWO.block_ptr().hash)?
+            .is_empty());
+
+        // Receipts for the same transaction in the same block replace the
+        // old ones
+        let replaced = receipt(&*BLOCK_ONE, 1, 63000);
+        store.upsert_receipts(vec![replaced.clone()])?;
+        assert_eq!(vec![first, replaced], store.receipts_for_block(one)?);
+        Ok(())
+    });
+}
+
+#[test]
+fn block_traces() {
+    use graph::prelude::{serde_json, web3::types::Trace};
+
+    fn trace(block: &FakeBlock, position: u64, gas: u64) -> Trace {
+        serde_json::from_value(serde_json::json!({
+            "action": {
+                "callType": "call",
+                "from": format!("0x{:040x}", 1),
+                "to": format!("0x{:040x}", 2),
+                "gas": format!("0x{:x}", gas),
+                "input": "0x",
+                "value": "0x0",
+            },
+            "result": { "gasUsed": format!("0x{:x}", gas), "output": "0x" },
+            "traceAddress": [],
+            "subtraces": 0,
+            "transactionPosition": position,
+            "transactionHash": format!("0x{:064x}", block.number * 100 + position),
+            "blockNumber": block.number,
+            "blockHash": format!("0x{}", block.hash),
+            "type": "call",
+        }))
+        .expect("valid trace")
+    }
+
+    let chain = vec![&*GENESIS_BLOCK, &*BLOCK_ONE, &*BLOCK_TWO];
+    run_test(chain, move |store, _| -> Result<(), Error> {
+        let one = BLOCK_ONE.block_ptr().hash;
+        let two = BLOCK_TWO.block_ptr().hash;
+        assert!(store.traces_for_blocks(vec![one, two])?.is_empty());
+
+        let first = trace(&*BLOCK_ONE, 0, 21000);
+        let second = trace(&*BLOCK_ONE, 1, 42000);
+        let third = trace(&*BLOCK_TWO, 0, 21000);
+        store.upsert_traces(vec![first.clone(), second.clone(), third.clone()])?;
+        let traces = store.traces_for_blocks(vec![one, two])?;
+        assert_eq!(2, traces.len());
+        assert_eq!(Some(&vec![first.clone(), second]), traces.get(&one));
+        assert_eq!(Some(&vec![third.clone()]), traces.get(&two));
+
+        // Storing the traces of a block again replaces all its traces
+        store.upsert_traces(vec![first.clone()])?;
+        let traces = store.traces_for_blocks(vec![one])?;
+        assert_eq!(1, traces.len());
+        assert_eq!(Some(&vec![first]), traces.get(&one));
+        Ok(())
+    });
+}
+
+#[test]
+fn hydrate_blocks() {
+    use graph::prelude::{web3::types::TransactionReceipt, LightEthereumBlock};
+
+    fn light_block(block: &FakeBlock) -> LightEthereumBlock {
+        let mut light = block.as_ethereum_block().block;
+        light.transactions.push(
+            serde_json::from_value(serde_json::json!({
+                "hash": format!("0x{:064x}", block.number * 100),
+                "nonce": "0x0",
+                "blockHash": format!("0x{}", block.hash),
+                "blockNumber": format!("0x{:x}", block.number),
+                "transactionIndex": "0x0",
+                "from": format!("0x{:040x}", 1),
+                "to": format!("0x{:040x}", 2),
+                "value": "0x0",
+                "gasPrice": "0x1",
+                "gas": "0x5208",
+                "input": "0x",
+            }))
+            .expect("valid transaction"),
+        );
+        light
+    }
+
+    fn receipt(block: &FakeBlock) -> TransactionReceipt {
+        serde_json::from_value(serde_json::json!({
+            "transactionHash": format!("0x{:064x}", block.number * 100),
+            "transactionIndex": "0x0",
+            "blockHash": format!("0x{}", block.hash),
+            "blockNumber": format!("0x{:x}", block.number),
+            "cumulativeGasUsed": "0x5208",
+            "gasUsed": "0x5208",
+            "contractAddress": null,
+            "logs": [],
+            "status": "0x1",
+            "logsBloom": format!("0x{}", "0".repeat(512)),
+        }))
+        .expect("valid receipt")
+    }
+
+    let chain = vec![&*GENESIS_BLOCK, &*BLOCK_ONE, &*BLOCK_TWO];
+    run_test(chain, move |store, _| -> Result<(), Error> {
+        let three = BLOCK_THREE.block_ptr().hash;
+        let four = BLOCK_FOUR.block_ptr().hash;
+        let five = BLOCK_FIVE.block_ptr().hash;
+        store.upsert_light_blocks(vec![light_block(&*BLOCK_THREE), light_block(&*BLOCK_FOUR)])?;
+
+        // Blocks without transactions and blocks that are not in the
+        // cache are never light
+        let hashes = vec![BLOCK_ONE.block_ptr().hash, three, four, five];
+        let light = store.light_blocks(hashes.clone())?;
+        assert_eq!(2, light.len());
+
+        // Hydrating a block makes it full; blocks that are not in the
+        // cache and incomplete blocks are ignored
+        let full = EthereumBlock {
+            block: light_block(&*BLOCK_THREE),
+            transaction_receipts: vec![receipt(&*BLOCK_THREE)],
+        };
+        let not_cached = EthereumBlock {
+            block: light_block(&*BLOCK_FIVE),
+            transaction_receipts: vec![receipt(&*BLOCK_FIVE)],
+        };
+        let incomplete = EthereumBlock {
+            block: light_block(&*BLOCK_FOUR),
+            transaction_receipts: vec![],
+        };
+        assert_eq!(
+            1,
+            store.hydrate_blocks(vec![full.clone(), not_cached, incomplete])?
+        );
+        assert_eq!(0, store.hydrate_blocks(vec![full])?);
+        let light = store.light_blocks(hashes.clone())?;
+        assert_eq!(
+            vec![Some(four)],
+            light.iter().map(|b| b.hash).collect::<Vec<_>>()
+        );
+
+        // Receipts that were stored separately get merged into the block
+        store.upsert_receipts(vec![receipt(&*BLOCK_FOUR)])?;
+        assert!(store.light_blocks(hashes)?.is_empty());
+        Ok(())
+    });
+}
+
+#[test]
+fn cleanup_call_cache() {
+    use graph::prelude::{web3::types::Address, EthereumCallCache, EthereumCallResult};
+
+    let chain = vec![&*GENESIS_BLOCK, &*BLOCK_ONE, &*BLOCK_TWO, &*BLOCK_THREE];
+    run_test(chain, move |store, _| -> Result<(), Error> {
+        let address = Address::from_low_u64_be(1);
+        let blocks = vec![
+            BLOCK_ONE.block_ptr(),
+            BLOCK_TWO.block_ptr(),
+            BLOCK_THREE.block_ptr(),
+        ];
+        let result = EthereumCallResult::Return(vec![4, 5, 6]);
+        for block in &blocks {
+            store.set_call(address, &[1, 2, 3], *block, &result)?;
+        }
+        assert_eq!(3, store.call_cache_size()?.entries);
+
+        // All calls were used today
+        assert_eq!(0, store.cleanup_call_cache(Some(0), None)?);
+        assert_eq!(0, store.cleanup_call_cache(None, Some(3))?);
+
+        // Of calls that were used on the same day, the ones for the
+        // earliest blocks go first
+        assert_eq!(1, store.cleanup_call_cache(None, Some(2))?);
+        assert_eq!(2, store.call_cache_size()?.entries);
+        assert_eq!(None, store.get_call(address, &[1, 2, 3], blocks[0])?);
+        for block in &blocks[1..] {
+            assert_eq!(
+                Some(result.clone()),
+                store.get_call(address, &[1, 2, 3], *block)?
+            );
+        }
+        Ok(())
+    });
+}
+
+#[test]
+fn call_cache_reverts() {
+    use graph::prelude::{web3::types::Address, EthereumCallCache, EthereumCallResult};
+
+    let chain = vec![&*GENESIS_BLOCK, &*BLOCK_ONE];
+    run_test(chain, move |store, _| -> Result<(), Error> {
+        let address = Address::from_low_u64_be(1);
+        let block = BLOCK_ONE.block_ptr();
+        let returned = EthereumCallResult::Return(vec![]);
+        let reverted = EthereumCallResult::Revert("execution reverted".to_owned());
+
+        // An empty return value is not a revert
+        store.set_call(address, &[1], block, &returned)?;
+        store.set_call(address, &[2], block, &reverted)?;
+        assert_eq!(Some(returned), store.get_call(address, &[1], block)?);
+        assert_eq!(Some(reverted), store.get_call(address, &[2], block)?);
+        assert_eq!(None, store.get_call(address, &[3], block)?);
+        Ok(())
+    });
+}
+
+#[test]
+fn call_cache_batches() {
+    use graph::prelude::{web3::types::Address, EthereumCallCache, EthereumCallResult};
+
+    let chain = vec![&*GENESIS_BLOCK, &*BLOCK_ONE];
+    run_test(chain, move |store, _| -> Result<(), Error> {
+        let one = Address::from_low_u64_be(1);
+        let two = Address::from_low_u64_be(2);
+        let block = BLOCK_ONE.block_ptr();
+        let returned = EthereumCallResult::Return(vec![7, 8]);
+        let reverted = EthereumCallResult::Revert("execution reverted".to_owned());
+
+        store.set_calls(
+            &[
+                (one, vec![1], returned.clone()),
+                (two, vec![1], reverted.clone()),
+            ],
+            block,
+        )?;
+        // Calls cached one at a time and in a batch are the same
+        store.set_call(one, &[2], block, &returned)?;
+        assert_eq!(Some(reverted.clone()), store.get_call(two, &[1], block)?);
+
+        let calls = vec![
+            (one, vec![2]),
+            (one, vec![3]),
+            (two, vec![1]),
+            (one, vec![1]),
+        ];
+        assert_eq!(
+            vec![Some(returned.clone()), None, Some(reverted), Some(returned)],
+            store.get_calls(&calls, block)?
+        );
+        // The calls are cached for a specific block
+        assert_eq!(
+            vec![None, None],
+            store.get_calls(&calls[..2], GENESIS_BLOCK.block_ptr())?
+        );
+        Ok(())
+    });
+}
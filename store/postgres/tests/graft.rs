@@ -78,6 +78,10 @@ lazy_static! {
             "b98fb783b49de5652097a989414c767824dff7e7fd765a63b493772511db81c1",
             2
         ),
+        block_pointer!(
+            "7347afe69254df06729e123610b00b8b11f15cfae3241f9366fb113aec07489c",
+            3
+        ),
     ];
 }
 
@@ -127,6 +131,9 @@ fn insert_test_data(store: Arc<DieselStore>) {
         data_sources: vec![],
         graft: None,
         templates: vec![],
+        confirmations: 0,
+        canaries: None,
+        limits: Default::default(),
     };
 
     // Create SubgraphDeploymentEntity
@@ -279,6 +286,7 @@ fn graft() {
         .order(EntityOrder::Descending(
             "name".to_string(),
             ValueType::String,
+            NullsPlacement::Last,
         ));
 
         let entities = store
@@ -318,3 +326,108 @@ fn graft() {
         Ok(())
     })
 }
+
+#[test]
+fn graft_dependencies() {
+    use graph::components::store::StatusStore;
+    use graph::data::subgraph::status::{self, DependencyKind};
+
+    run_test(move |store| -> Result<(), ()> {
+        const SUBGRAPH: &str = "grafted";
+        let subgraph_id = SubgraphDeploymentId::new(SUBGRAPH).unwrap();
+        test_store::create_grafted_subgraph(
+            &subgraph_id,
+            GRAFT_GQL,
+            TEST_SUBGRAPH_ID.as_str(),
+            BLOCKS[1],
+        )
+        .expect("can create grafted subgraph");
+        transact_entity_operations(&store, subgraph_id.clone(), BLOCKS[2], vec![]).unwrap();
+
+        let infos = store
+            .status(status::Filter::Deployments(vec![
+                SUBGRAPH.to_owned(),
+                TEST_SUBGRAPH_ID.to_string(),
+            ]))
+            .expect("can get statuses");
+        let info = |id: &str| {
+            infos
+                .iter()
+                .find(|info| info.subgraph == id)
+                .expect("status for deployment")
+        };
+
+        let grafted = info(SUBGRAPH);
+        assert_eq!(1, grafted.dependencies.len());
+        let dependency = &grafted.dependencies[0];
+        assert_eq!(TEST_SUBGRAPH_ID.as_str(), dependency.deployment);
+        assert_eq!(DependencyKind::Graft, dependency.kind);
+        assert_eq!(
+            Some(BLOCKS[1]),
+            dependency.block.clone().map(|block| block.to_ptr())
+        );
+        // The grafted deployment is past the graft block and does not
+        // need the base any more
+        assert!(!dependency.pending);
+        assert!(grafted.dependents.is_empty());
+
+        let base = info(TEST_SUBGRAPH_ID.as_str());
+        assert!(base.dependencies.is_empty());
+        let dependents: Vec<_> = base
+            .dependents
+            .iter()
+            .map(|dependent| dependent.deployment.as_str())
+            .collect();
+        assert_eq!(vec![SUBGRAPH], dependents);
+
+        Ok(())
+    })
+}
+
+#[test]
+fn prune_history() {
+    run_test(move |store| -> Result<(), ()> {
+        const SUBGRAPH: &str = "grafted";
+        let subgraph_id = SubgraphDeploymentId::new(SUBGRAPH).unwrap();
+        test_store::create_grafted_subgraph(
+            &subgraph_id,
+            GRAFT_GQL,
+            TEST_SUBGRAPH_ID.as_str(),
+            BLOCKS[2],
+        )
+        .expect("can create grafted subgraph");
+
+        // The history can only be pruned once the graft has been copied
+        store
+            .store()
+            .prune_history(&subgraph_id, BLOCKS[2].number as BlockNumber)
+            .expect_err("pruning a pending graft fails");
+        transact_entity_operations(&store, subgraph_id.clone(), BLOCKS[3], vec![]).unwrap();
+
+        // The version of Shaqueena from block 1 was replaced at block 2
+        let pruned = store
+            .store()
+            .prune_history(&subgraph_id, BLOCKS[2].number as BlockNumber)
+            .expect("can prune history before the graft block");
+        assert_eq!(1, pruned);
+
+        let state = store
+            .deployment_state_from_id(subgraph_id.clone())
+            .expect("can get deployment state");
+        assert_eq!(Some(2), state.history_pruned_below);
+        assert!(state.check_block_available(1).is_err());
+        assert!(state.check_block_available(2).is_ok());
+
+        let key = EntityKey::data(subgraph_id.clone(), USER.to_owned(), "3".to_owned());
+        let shaq = store.get(key).unwrap().expect("Shaqueena is still there");
+        assert_eq!(Some(&Value::from("teeko@email.com")), shaq.get("email"));
+
+        // The base might still revert past block 2
+        store
+            .store()
+            .prune_history(&TEST_SUBGRAPH_ID, BLOCKS[2].number as BlockNumber)
+            .expect_err("pruning inside the reorg window fails");
+
+        Ok(())
+    })
+}
@@ -1,16 +1,23 @@
 //! Test mapping of GraphQL schema to a relational schema
 use diesel::connection::SimpleConnection as _;
 use diesel::pg::PgConnection;
+use diesel::Connection as _;
 use hex_literal::hex;
 use lazy_static::lazy_static;
+use std::collections::{BTreeMap, BTreeSet};
 use std::str::FromStr;
+use std::sync::Arc;
 
 use graph::data::store::scalar::{BigDecimal, BigInt, Bytes};
 use graph::prelude::{
-    web3::types::H256, Entity, EntityCollection, EntityFilter, EntityKey, EntityOrder, EntityQuery,
-    EntityRange, Schema, SubgraphDeploymentId, Value, ValueType, BLOCK_NUMBER_MAX,
+    web3::types::H256, AggregateFunction, AggregateRow, BlockNumber, Entity,
+    EntityAggregationQuery, EntityCollection, EntityFilter, EntityKey, EntityOrder, EntityQuery,
+    EntityRange, NullsPlacement, QueryPlan, Schema, SubgraphDeploymentId, Value, ValueType,
+    BLOCK_NUMBER_MAX,
+};
+use graph_store_postgres::layout_for_tests::{
+    columnar_mirror, revert_columnar, update_columnar, Layout, Namespace, STRING_PREFIX_SIZE,
 };
-use graph_store_postgres::layout_for_tests::{Layout, Namespace, STRING_PREFIX_SIZE};
 
 use test_store::*;
 
@@ -105,6 +112,20 @@ const THINGS_GQL: &str = r#"
         description: String,
         test: String
     }
+
+    type Order @entity @ttl(blocks: 10) {
+        id: ID!,
+        amount: Int!
+    }
+
+    type LineItem @entity {
+        id: ID!,
+        price: BigInt!,
+        quantity: Int!,
+        name: String,
+        total: BigInt! @derivedExpression(expression: "price * quantity / 2"),
+        label: String @derivedExpression(expression: "name || ' x' || quantity")
+    }
 "#;
 
 lazy_static! {
@@ -476,6 +497,158 @@ fn update() {
     });
 }
 
+#[test]
+fn block_numbers_past_i32() {
+    run_test(|conn, layout| {
+        const BLOCK: BlockNumber = std::i32::MAX as BlockNumber + 10;
+
+        let key = EntityKey::data(
+            THINGS_SUBGRAPH_ID.clone(),
+            "Scalar".to_owned(),
+            "one".to_owned(),
+        );
+        layout
+            .insert(&conn, &key, SCALAR_ENTITY.clone(), BLOCK)
+            .expect("Failed to insert");
+        let mut entity = SCALAR_ENTITY.clone();
+        entity.set("string", "updated");
+        layout
+            .update(&conn, &key, entity.clone(), BLOCK + 1)
+            .expect("Failed to update");
+
+        let actual = layout
+            .find(conn, "Scalar", "one", BLOCK - 1)
+            .expect("Failed to read Scalar[one]");
+        assert!(actual.is_none());
+        let actual = layout
+            .find(conn, "Scalar", "one", BLOCK)
+            .expect("Failed to read Scalar[one]")
+            .unwrap();
+        assert_entity_eq!(scrub(&*SCALAR_ENTITY), actual);
+        let actual = layout
+            .find(conn, "Scalar", "one", BLOCK + 1)
+            .expect("Failed to read Scalar[one]")
+            .unwrap();
+        assert_entity_eq!(scrub(&entity), actual);
+
+        let last_updated = |op: fn(String, Value) -> EntityFilter, block: BlockNumber| {
+            query(vec!["Scalar"]).filter(op(
+                "_lastUpdatedBlock".to_owned(),
+                Value::BigInt(BigInt::from(block)),
+            ))
+        };
+        QueryChecker::new(conn, layout)
+            .check(vec!["one"], last_updated(EntityFilter::Equal, BLOCK + 1))
+            .check(vec![], last_updated(EntityFilter::GreaterThan, BLOCK + 1))
+            .check(
+                vec!["one"],
+                last_updated(EntityFilter::GreaterOrEqual, BLOCK),
+            );
+    });
+}
+
+/// The migration that widened block numbers converts `int4range` block
+/// ranges to `int8range` and recreates the indexes that mention the largest
+/// block number
+#[test]
+fn migrate_block_ranges_to_int8() {
+    run_test(|conn, _| {
+        let query = format!(
+            "create table {nsp}.narrow(vid int8 primary key, block_range int4range not null);
+             create index brin_narrow on {nsp}.narrow
+                using brin(lower(block_range), coalesce(upper(block_range), 2147483647), vid);
+             create index narrow_block_range_closed
+                 on {nsp}.narrow(coalesce(upper(block_range), 2147483647))
+              where coalesce(upper(block_range), 2147483647) < 2147483647;
+             insert into {nsp}.narrow values (1, '[1,5)'), (2, '[5,)');
+
+             select change_block_range_type('{nsp}', 'int4range', 'int8range',
+                                            '9223372036854775807');
+
+             insert into {nsp}.narrow values (3, '[5000000000,)');
+             do $$ begin
+               assert (select atttypid::regtype::text from pg_attribute
+                        where attrelid = '{nsp}.narrow'::regclass
+                          and attname = 'block_range') = 'int8range';
+               assert (select count(*) from {nsp}.narrow
+                        where (vid = 1 and block_range = '[1,5)'::int8range)
+                           or (vid = 2 and block_range = '[5,)'::int8range)) = 2;
+               assert (select count(*) from pg_indexes
+                        where schemaname = '{nsp}'
+                          and indexname in ('brin_narrow', 'narrow_block_range_closed')
+                          and indexdef like '%9223372036854775807%') = 2;
+             end $$;",
+            nsp = NAMESPACE.as_str()
+        );
+        conn.batch_execute(&query)
+            .expect("Failed to widen block ranges");
+    });
+}
+
+#[test]
+fn columnar_mirrors() {
+    // The test database does not have the columnar extension
+    std::env::set_var("GRAPH_COLUMNAR_ACCESS_METHOD", "heap");
+
+    fn assert_mirror(conn: &PgConnection, ranges: &str) {
+        let query = format!(
+            "do $$ begin
+               assert (select string_agg(id || block_range::text, ' ' order by vid)
+                         from {nsp}.\"cat$columnar\") = '{ranges}';
+             end $$;",
+            nsp = NAMESPACE.as_str(),
+            ranges = ranges
+        );
+        conn.batch_execute(&query)
+            .expect("The mirror has the wrong versions");
+    }
+
+    run_test(|conn, layout| {
+        let mut layout = layout.clone();
+        let cat = layout.tables.get_mut("Cat").unwrap();
+        Arc::make_mut(cat).is_columnar = true;
+        let cat = layout.tables.get("Cat").unwrap().clone();
+        assert_eq!(None, columnar_mirror(conn, &layout, &cat).unwrap());
+
+        // Processing a block creates the mirror from the table
+        insert_pet(conn, &layout, "Cat", "garfield", "Garfield");
+        update_columnar(conn, &layout, 0).expect("Failed to create mirror");
+        assert!(columnar_mirror(conn, &layout, &cat).unwrap().is_some());
+        assert_mirror(conn, "garfield[0,)");
+        let query = format!(
+            "do $$ begin
+               assert (select count(*) from pg_indexes
+                        where schemaname = '{nsp}'
+                          and tablename = 'cat$columnar'
+                          and indexdef like '%(vid)%') = 1;
+             end $$;",
+            nsp = NAMESPACE.as_str()
+        );
+        conn.batch_execute(&query)
+            .expect("The mirror has no index on vid");
+
+        // Updating copies the new version and the closed block range
+        let mut garfield = Entity::new();
+        garfield.set("id", "garfield");
+        garfield.set("name", "Garfield the Cat");
+        let key = EntityKey::data(
+            THINGS_SUBGRAPH_ID.clone(),
+            "Cat".to_owned(),
+            "garfield".to_owned(),
+        );
+        layout.update(conn, &key, garfield, 1).unwrap();
+        update_columnar(conn, &layout, 1).expect("Failed to update mirror");
+        assert_mirror(conn, "garfield[0,1) garfield[1,)");
+
+        // Reverting removes the new version and reopens the old one
+        layout
+            .revert_block(conn, &THINGS_SUBGRAPH_ID, 1)
+            .expect("Failed to revert block");
+        revert_columnar(conn, &layout, 1).expect("Failed to revert mirror");
+        assert_mirror(conn, "garfield[0,)");
+    });
+}
+
 /// Test that we properly handle BigDecimal values with a negative scale.
 #[test]
 fn serialize_bigdecimal() {
@@ -524,8 +697,11 @@ fn count_scalar_entities(conn: &PgConnection, layout: &Layout) -> usize {
                 first: None,
                 skip: 0,
             },
+            false,
+            None,
             BLOCK_NUMBER_MAX,
             None,
+            None,
         )
         .expect("Count query failed")
         .len()
@@ -557,6 +733,56 @@ fn delete() {
     });
 }
 
+#[test]
+fn increment() {
+    run_test(|conn, layout| {
+        insert_entity(&conn, &layout, "Scalar", SCALAR_ENTITY.clone());
+
+        let mut deltas = BTreeMap::new();
+        deltas.insert("int".to_owned(), Value::Int(-2));
+        deltas.insert("bigInt".to_owned(), Value::BigInt(BigInt::from(7)));
+        let mut key = EntityKey::data(
+            THINGS_SUBGRAPH_ID.clone(),
+            "Scalar".to_owned(),
+            "one".to_owned(),
+        );
+        let count = layout
+            .increment(&conn, &key, &deltas, 1)
+            .expect("Failed to increment");
+        assert_eq!(1, count);
+
+        let mut entity = SCALAR_ENTITY.clone();
+        for (field, delta) in &deltas {
+            let value = entity.get(field).unwrap().add_number(delta).unwrap();
+            entity.set(field.as_str(), value);
+        }
+        let actual = layout
+            .find(conn, "Scalar", "one", BLOCK_NUMBER_MAX)
+            .expect("Failed to read Scalar[one]")
+            .unwrap();
+        assert_entity_eq!(scrub(&entity), actual);
+
+        // The entity before the increment is still there
+        let actual = layout
+            .find(conn, "Scalar", "one", 0)
+            .expect("Failed to read Scalar[one]")
+            .unwrap();
+        assert_entity_eq!(scrub(&*SCALAR_ENTITY), actual);
+
+        // Incrementing an entity that does not exist does nothing
+        key.entity_id = "no such entity".to_owned();
+        let count = layout
+            .increment(&conn, &key, &deltas, 1)
+            .expect("Failed to increment");
+        assert_eq!(0, count);
+
+        // Only numbers can be incremented
+        let mut deltas = BTreeMap::new();
+        deltas.insert("string".to_owned(), Value::Int(1));
+        assert!(layout.increment(&conn, &key, &deltas, 2).is_err());
+    });
+}
+
 #[test]
 fn conflicting_entity() {
     run_test(|conn, layout| {
@@ -627,8 +853,11 @@ impl<'a> QueryChecker<'a> {
                 query.filter,
                 query.order,
                 query.range,
+                query.distinct,
+                None,
                 BLOCK_NUMBER_MAX,
                 None,
+                None,
             )
             .expect("layout.query failed to execute query");
 
@@ -654,6 +883,459 @@ impl<'a> QueryChecker<'a> {
     }
 }
 
+#[test]
+fn aggregate() {
+    fn aggregate(
+        conn: &PgConnection,
+        layout: &Layout,
+        function: AggregateFunction,
+        attribute: Option<&str>,
+        group_by: Option<&str>,
+        filter: Option<EntityFilter>,
+    ) -> Vec<(Option<Value>, Value)> {
+        let mut query = EntityAggregationQuery::new(
+            THINGS_SUBGRAPH_ID.clone(),
+            BLOCK_NUMBER_MAX,
+            "User".to_owned(),
+            function,
+        );
+        query.attribute = attribute.map(str::to_owned);
+        query.group_by = group_by.map(str::to_owned);
+        query.filter = filter;
+        layout
+            .aggregate(conn, query)
+            .expect("aggregate query failed")
+            .into_iter()
+            .map(|AggregateRow { group, value }| (group, value))
+            .collect()
+    }
+
+    run_test(|conn, layout| {
+        use AggregateFunction::*;
+
+        insert_users(conn, layout);
+        let single = |function, attribute| aggregate(conn, layout, function, attribute, None, None);
+
+        assert_eq!(vec![(None, Value::Int(3))], single(Count, None));
+        // `count` of an attribute skips entities where it is not set
+        assert_eq!(
+            vec![(None, Value::Int(2))],
+            single(Count, Some("favorite_color"))
+        );
+        assert_eq!(
+            vec![(None, Value::BigInt(BigInt::from(138)))],
+            single(Sum, Some("age"))
+        );
+        assert_eq!(vec![(None, Value::Int(28))], single(Min, Some("age")));
+        assert_eq!(
+            vec![(None, Value::String("Shaqueeena".to_owned()))],
+            single(Max, Some("name"))
+        );
+        assert_eq!(
+            vec![(None, Value::BigDecimal(BigDecimal::from(46)))],
+            single(Avg, Some("age"))
+        );
+
+        assert_eq!(
+            vec![
+                (Some(Value::Bool(false)), Value::BigInt(BigInt::from(95))),
+                (Some(Value::Bool(true)), Value::BigInt(BigInt::from(43)))
+            ],
+            aggregate(conn, layout, Sum, Some("age"), Some("coffee"), None)
+        );
+        assert_eq!(
+            vec![(None, Value::Int(2))],
+            aggregate(
+                conn,
+                layout,
+                Count,
+                None,
+                None,
+                Some(EntityFilter::GreaterThan("age".to_owned(), Value::Int(30)))
+            )
+        );
+
+        // Aggregates that do not make sense for an attribute are rejected
+        let mut query = EntityAggregationQuery::new(
+            THINGS_SUBGRAPH_ID.clone(),
+            BLOCK_NUMBER_MAX,
+            "User".to_owned(),
+            Sum,
+        );
+        assert!(layout.aggregate(conn, query.clone()).is_err());
+        query.attribute = Some("name".to_owned());
+        assert!(layout.aggregate(conn, query).is_err());
+    });
+}
+
+#[test]
+fn entity_digests() {
+    run_test(|conn, layout| {
+        insert_users(conn, layout);
+        let before = layout
+            .entity_digests(conn, 0)
+            .expect("digests can be computed");
+        assert_eq!(3, before["User"].count);
+        assert_eq!(0, before["Cat"].count);
+
+        // Writing the same data again at a later block does not change
+        // the digest
+        update_user_entity(
+            conn,
+            layout,
+            "3",
+            "User",
+            "Shaqueeena",
+            "teeko@email.com",
+            28 as i32,
+            111.7,
+            false,
+            None,
+            Some(vec!["coffee", "tea"]),
+        );
+        let after = layout
+            .entity_digests(conn, 1)
+            .expect("digests can be computed");
+        assert_eq!(before, after);
+
+        update_user_entity(
+            conn,
+            layout,
+            "1",
+            "User",
+            "Jono",
+            "achangedemail@email.com",
+            67 as i32,
+            184.4,
+            false,
+            Some("yellow"),
+            None,
+        );
+        let after = layout
+            .entity_digests(conn, 1)
+            .expect("digests can be computed");
+        assert_eq!(3, after["User"].count);
+        assert_ne!(before["User"], after["User"]);
+        assert_eq!(before["Cat"], after["Cat"]);
+        assert_eq!(
+            before,
+            layout
+                .entity_digests(conn, 0)
+                .expect("digests can be computed")
+        );
+    });
+}
+
+#[test]
+fn count() {
+    run_test(|conn, layout| {
+        insert_users(conn, layout);
+        let count = |entity_types: Vec<&str>, filter: Option<EntityFilter>| {
+            let collection =
+                EntityCollection::All(entity_types.into_iter().map(str::to_owned).collect());
+            layout
+                .count(conn, collection, filter, BLOCK_NUMBER_MAX)
+                .expect("entities can be counted")
+        };
+
+        assert_eq!(3, count(vec!["User"], None));
+        assert_eq!(
+            2,
+            count(
+                vec!["User"],
+                Some(EntityFilter::GreaterThan("age".to_owned(), Value::Int(30)))
+            )
+        );
+        assert_eq!(
+            0,
+            count(
+                vec!["User", "Cat"],
+                Some(EntityFilter::Equal("id".to_owned(), Value::from("none")))
+            )
+        );
+        assert_eq!(3, count(vec!["User", "Cat"], None));
+    });
+}
+
+#[test]
+fn explain() {
+    run_test(|conn, layout| {
+        insert_users(conn, layout);
+
+        let QueryPlan { sql, plan } = layout
+            .explain(
+                conn,
+                EntityCollection::All(vec!["User".to_owned()]),
+                Some(EntityFilter::GreaterThan("age".to_owned(), Value::Int(30))),
+                EntityOrder::Default,
+                EntityRange::first(10),
+                false,
+                BLOCK_NUMBER_MAX,
+            )
+            .expect("queries can be explained");
+
+        assert!(sql.contains("\"user\""), "{}", sql);
+        assert!(sql.contains("30"), "{}", sql);
+        assert!(plan[0]["Plan"]["Node Type"].is_string(), "{}", plan);
+
+        // Explaining a query does not return or change any entities
+        assert_eq!(
+            3,
+            layout
+                .count(
+                    conn,
+                    EntityCollection::All(vec!["User".to_owned()]),
+                    None,
+                    BLOCK_NUMBER_MAX
+                )
+                .unwrap()
+        );
+    });
+}
+
+#[test]
+fn query_stream() {
+    // Stream users ordered by name in batches of two, stopping after
+    // `limit` of them
+    fn names(conn: &PgConnection, layout: &Layout, limit: usize) -> Vec<String> {
+        let mut names = Vec::new();
+        conn.transaction(|| -> Result<_, diesel::result::Error> {
+            Ok(layout.query_stream(
+                conn,
+                EntityCollection::All(vec!["User".to_owned()]),
+                None,
+                EntityOrder::Ascending("name".to_owned(), ValueType::String, NullsPlacement::Last),
+                EntityRange {
+                    first: None,
+                    skip: 0,
+                },
+                false,
+                BLOCK_NUMBER_MAX,
+                2,
+                |user| {
+                    names.push(user.get("name").unwrap().as_str().unwrap().to_owned());
+                    names.len() < limit
+                },
+            ))
+        })
+        .expect("the transaction succeeds")
+        .expect("entities can be streamed");
+        names
+    }
+
+    run_test(|conn, layout| {
+        insert_users(conn, layout);
+
+        assert_eq!(
+            vec!["Cindini", "Johnton", "Shaqueeena"],
+            names(conn, layout, 10)
+        );
+        assert_eq!(vec!["Cindini"], names(conn, layout, 1));
+    });
+}
+
+#[test]
+fn selected_attributes() {
+    run_test(|conn, layout| {
+        insert_users(conn, layout);
+
+        let selected: BTreeSet<_> = vec!["name".to_owned(), "age".to_owned()]
+            .into_iter()
+            .collect();
+        let users = layout
+            .query::<Entity>(
+                &*LOGGER,
+                conn,
+                EntityCollection::All(vec!["User".to_owned()]),
+                None,
+                EntityOrder::Ascending("name".to_owned(), ValueType::String, NullsPlacement::Last),
+                EntityRange::first(1),
+                false,
+                Some(selected),
+                BLOCK_NUMBER_MAX,
+                None,
+                None,
+            )
+            .expect("users can be queried");
+        let user = users.first().expect("there is a user");
+
+        // Only the selected attributes and the id are returned
+        assert_eq!(Some(&Value::from("2")), user.get("id"));
+        assert_eq!(Some(&Value::from("Cindini")), user.get("name"));
+        assert_eq!(Some(&Value::Int(43)), user.get("age"));
+        for omitted in &[
+            "email",
+            "bin_name",
+            "favorite_color",
+            "drinks",
+            "seconds_age",
+            "weight",
+            "coffee",
+        ] {
+            assert_eq!(None, user.get(omitted), "{}", omitted);
+        }
+    });
+}
+
+#[test]
+fn prune_expired() {
+    run_test(|conn, layout| {
+        let key = |id: &str| {
+            EntityKey::data(
+                THINGS_SUBGRAPH_ID.clone(),
+                "Order".to_owned(),
+                id.to_owned(),
+            )
+        };
+        let order = |id: &str, amount: i32| {
+            Entity::from(vec![
+                ("id", Value::from(id)),
+                ("amount", Value::from(amount)),
+            ])
+        };
+        let exists = |id: &str| {
+            layout
+                .find(conn, "Order", id, BLOCK_NUMBER_MAX)
+                .expect("orders can be found")
+                .is_some()
+        };
+
+        insert_users(conn, layout);
+        for id in &["o1", "o2", "o3"] {
+            layout
+                .insert(conn, &key(id), order(id, 1), 0)
+                .expect("orders can be inserted");
+        }
+        layout
+            .update(conn, &key("o2"), order("o2", 2), 8)
+            .expect("orders can be updated");
+        layout
+            .delete(conn, &key("o3"), 12)
+            .expect("orders can be deleted");
+
+        // `o1` was last changed at block 0 and expired at block 10. `o2`
+        // was changed at block 8, and the removal of `o3` at block 12 could
+        // still be reverted
+        assert_eq!(1, layout.prune_expired(conn, 15).expect("pruning works"));
+        assert!(!exists("o1"));
+        assert!(exists("o2"));
+
+        // Both versions of `o2` and the removed `o3` get deleted
+        assert_eq!(3, layout.prune_expired(conn, 25).expect("pruning works"));
+        assert!(!exists("o2"));
+
+        // Entities without a `@ttl` never expire
+        assert!(layout
+            .find(conn, "User", "1", BLOCK_NUMBER_MAX)
+            .expect("users can be found")
+            .is_some());
+    });
+}
+
+#[test]
+fn derived_expressions() {
+    run_test(|conn, layout| {
+        let line_item = |id: &str, price: i32, quantity: i32, name: Option<&str>| {
+            let mut entity = Entity::from(vec![
+                ("id", Value::from(id)),
+                ("price", Value::from(BigInt::from(price))),
+                ("quantity", Value::from(quantity)),
+            ]);
+            if let Some(name) = name {
+                entity.set("name", name);
+            }
+            let key = EntityKey::data(
+                THINGS_SUBGRAPH_ID.clone(),
+                "LineItem".to_owned(),
+                id.to_owned(),
+            );
+            layout
+                .insert(conn, &key, entity, 0)
+                .expect("line items can be inserted");
+        };
+        line_item("li1", 7, 3, Some("widget"));
+        line_item("li2", 10, 1, None);
+
+        let items = layout
+            .query::<Entity>(
+                &*LOGGER,
+                conn,
+                EntityCollection::All(vec!["LineItem".to_owned()]),
+                None,
+                EntityOrder::Ascending("id".to_owned(), ValueType::String, NullsPlacement::Last),
+                EntityRange {
+                    first: None,
+                    skip: 0,
+                },
+                false,
+                None,
+                BLOCK_NUMBER_MAX,
+                None,
+                None,
+            )
+            .expect("line items can be queried");
+        let computed: Vec<_> = items
+            .iter()
+            .map(|item| (item.get("total").cloned(), item.get("label").cloned()))
+            .collect();
+        assert_eq!(
+            vec![
+                (
+                    Some(Value::from(BigInt::from(10))),
+                    Some(Value::from("widget x3"))
+                ),
+                (Some(Value::from(BigInt::from(5))), None),
+            ],
+            computed
+        );
+
+        // The computed values are not stored
+        let item = layout
+            .find(conn, "LineItem", "li1", BLOCK_NUMBER_MAX)
+            .expect("line items can be found")
+            .expect("the line item exists");
+        assert_eq!(None, item.get("total"));
+    });
+}
+
+#[test]
+fn table_storage() {
+    run_test(|conn, layout| {
+        let tables = layout
+            .table_storage(conn)
+            .expect("storage can be described");
+        let user = tables
+            .iter()
+            .find(|table| table.entity_type == "User")
+            .expect("there is a table for User");
+        assert_eq!("\"sgd0815\".\"user\"", user.table);
+
+        let drinks = user
+            .columns
+            .iter()
+            .find(|column| column.field == "drinks")
+            .expect("there is a column for drinks");
+        assert_eq!("drinks", drinks.column);
+        assert_eq!("text", drinks.sql_type);
+        assert!(drinks.nullable);
+        assert!(drinks.list);
+
+        let age = user
+            .columns
+            .iter()
+            .find(|column| column.field == "age")
+            .expect("there is a column for age");
+        assert_eq!("integer", age.sql_type);
+        assert!(!age.nullable);
+        assert!(!age.list);
+
+        assert!(user
+            .indexes
+            .iter()
+            .any(|index| index.definition.contains("(age)")));
+    });
+}
+
 fn query(entity_types: Vec<&str>) -> EntityQuery {
     EntityQuery::new(
         THINGS_SUBGRAPH_ID.clone(),
@@ -675,12 +1357,20 @@ trait EasyOrder {
 impl EasyOrder for EntityQuery {
     fn asc(self, attr: &str) -> Self {
         // The ValueType doesn't matter since relational layouts ignore it
-        self.order(EntityOrder::Ascending(attr.to_owned(), ValueType::String))
+        self.order(EntityOrder::Ascending(
+            attr.to_owned(),
+            ValueType::String,
+            NullsPlacement::Last,
+        ))
     }
 
     fn desc(self, attr: &str) -> Self {
         // The ValueType doesn't matter since relational layouts ignore it
-        self.order(EntityOrder::Descending(attr.to_owned(), ValueType::String))
+        self.order(EntityOrder::Descending(
+            attr.to_owned(),
+            ValueType::String,
+            NullsPlacement::Last,
+        ))
     }
 
     fn unordered(self) -> Self {
@@ -764,6 +1454,45 @@ fn check_find() {
                 )),
             );
 
+        // list length; user 1 has no drinks at all
+        let checker = checker
+            .check(
+                vec!["2", "3"],
+                user_query().filter(EntityFilter::LengthEqual("drinks".into(), 2)),
+            )
+            .check(
+                vec!["1"],
+                user_query().filter(EntityFilter::LengthEqual("drinks".into(), 0)),
+            )
+            .check(
+                vec!["2", "3"],
+                user_query().filter(EntityFilter::LengthGreaterThan("drinks".into(), 1)),
+            )
+            .check(
+                vec!["1"],
+                user_query().filter(EntityFilter::LengthLessThan("drinks".into(), 2)),
+            );
+
+        // change and creation blocks; all users are created at block 0 and
+        // user 1 is updated at block 1
+        let checker = checker
+            .check(
+                vec!["1"],
+                user_query().filter(EntityFilter::ChangedAtBlockGte(1)),
+            )
+            .check(
+                vec!["1", "2", "3"],
+                user_query().filter(EntityFilter::ChangedAtBlockGte(0)),
+            )
+            .check(
+                vec!["1", "2", "3"],
+                user_query().filter(EntityFilter::CreatedAtBlockGte(0)),
+            )
+            .check(
+                vec![],
+                user_query().filter(EntityFilter::CreatedAtBlockGte(1)),
+            );
+
         // string attributes
         let checker = checker
             .check(
@@ -832,6 +1561,58 @@ fn check_find() {
                     .filter(EntityFilter::NotEndsWith("name".to_owned(), "ini".into()))
                     .desc("name"),
             )
+            .check(
+                vec!["3", "2"],
+                user_query()
+                    .filter(EntityFilter::Match("name".to_owned(), "^[CS].*n".into()))
+                    .desc("name"),
+            )
+            .check(
+                vec!["1"],
+                user_query()
+                    .filter(EntityFilter::NotMatch("name".to_owned(), "^[CS].*n".into()))
+                    .desc("name"),
+            )
+            .check(
+                vec!["1"],
+                user_query().filter(EntityFilter::EqualNoCase(
+                    "name".to_owned(),
+                    "johnTON".into(),
+                )),
+            )
+            .check(
+                vec!["2"],
+                user_query()
+                    .filter(EntityFilter::ContainsNoCase(
+                        "name".to_owned(),
+                        "DIN".into(),
+                    ))
+                    .desc("name"),
+            )
+            .check(
+                vec!["3"],
+                user_query()
+                    .filter(EntityFilter::StartsWithNoCase(
+                        "name".to_owned(),
+                        "sha".into(),
+                    ))
+                    .desc("name"),
+            )
+            .check(
+                vec!["3", "1"],
+                user_query()
+                    .filter(EntityFilter::NotEndsWithNoCase(
+                        "name".to_owned(),
+                        "INI".into(),
+                    ))
+                    .desc("name"),
+            )
+            .check(
+                vec!["2"],
+                user_query()
+                    .filter(EntityFilter::MatchNoCase("name".to_owned(), "^c".into()))
+                    .desc("name"),
+            )
             .check(
                 vec!["1"],
                 user_query()
@@ -992,6 +1773,16 @@ fn check_find() {
                     ))
                     .desc("name"),
             )
+            .check(
+                vec!["2", "3"],
+                user_query()
+                    .filter(EntityFilter::Between(
+                        "age".to_owned(),
+                        Value::Int(28 as i32),
+                        Value::Int(43 as i32),
+                    ))
+                    .asc("name"),
+            )
             .check(
                 vec!["2"],
                 user_query()
@@ -1084,6 +1875,18 @@ fn check_find() {
                     .filter(EntityFilter::Not("favorite_color".to_owned(), Value::Null))
                     .desc("name"),
             )
+            .check(
+                vec!["3"],
+                user_query()
+                    .filter(EntityFilter::IsNull("favorite_color".to_owned()))
+                    .desc("name"),
+            )
+            .check(
+                vec!["3", "2"],
+                user_query()
+                    .filter(EntityFilter::IsNotNull("drinks".to_owned()))
+                    .desc("name"),
+            )
             .check(
                 vec!["1", "2"],
                 user_query()
@@ -1112,6 +1915,24 @@ fn check_find() {
             .check(vec!["1", "2", "3"], user_query().desc("age"))
             .check(vec!["2", "1", "3"], user_query().asc("name"))
             .check(vec!["3", "1", "2"], user_query().desc("name"))
+            .check(vec!["1", "2", "3"], user_query().asc("favorite_color"))
+            .check(vec!["2", "1", "3"], user_query().desc("favorite_color"))
+            .check(
+                vec!["3", "1", "2"],
+                user_query().order(EntityOrder::Ascending(
+                    "favorite_color".to_owned(),
+                    ValueType::String,
+                    NullsPlacement::First,
+                )),
+            )
+            .check(
+                vec!["3", "2", "1"],
+                user_query().order(EntityOrder::Descending(
+                    "favorite_color".to_owned(),
+                    ValueType::String,
+                    NullsPlacement::First,
+                )),
+            )
             .check(
                 vec!["1", "2"],
                 user_query()
@@ -1217,8 +2038,11 @@ fn text_find(expected_entity_ids: Vec<&str>, filter: EntityFilter) {
                 query.filter,
                 query.order,
                 query.range,
+                query.distinct,
+                None,
                 BLOCK_NUMBER_MAX,
                 None,
+                None,
             )
             .expect("layout.query failed to execute query");
 
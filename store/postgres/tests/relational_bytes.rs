@@ -356,6 +356,15 @@ fn make_thing_tree(conn: &PgConnection, layout: &Layout) -> (Entity, Entity, Ent
 #[test]
 fn query() {
     fn fetch(conn: &PgConnection, layout: &Layout, coll: EntityCollection) -> Vec<String> {
+        fetch_distinct(conn, layout, coll, false)
+    }
+
+    fn fetch_distinct(
+        conn: &PgConnection,
+        layout: &Layout,
+        coll: EntityCollection,
+        distinct: bool,
+    ) -> Vec<String> {
         layout
             .query::<Entity>(
                 &*LOGGER,
@@ -364,8 +373,11 @@ fn query() {
                 None,
                 EntityOrder::Default,
                 EntityRange::first(10),
+                distinct,
+                None,
                 BLOCK_NUMBER_MAX,
                 None,
+                None,
             )
             .expect("the query succeeds")
             .into_iter()
@@ -464,7 +476,11 @@ fn query() {
             ids: vec![CHILD1.to_owned(), CHILD2.to_owned()],
             link: EntityLink::Parent(ParentLink::Scalar(vec![ROOT.to_owned(), ROOT.to_owned()])),
         }]);
-        let things = fetch(conn, layout, coll);
+        let things = fetch(conn, layout, coll.clone());
         assert_eq!(vec![ROOT, ROOT], things);
+
+        // The same query, but returning each parent only once
+        let things = fetch_distinct(conn, layout, coll, true);
+        assert_eq!(vec![ROOT], things);
     });
 }
@@ -3,7 +3,10 @@ use hex_literal::hex;
 use lazy_static::lazy_static;
 use std::str::FromStr;
 use std::time::Duration;
-use std::{collections::HashSet, sync::Mutex};
+use std::{
+    collections::{BTreeMap, HashSet},
+    sync::Mutex,
+};
 use test_store::*;
 
 use graph::components::store::{
@@ -153,6 +156,9 @@ fn insert_test_data(store: Arc<DieselStore>) {
         data_sources: vec![],
         graft: None,
         templates: vec![],
+        confirmations: 0,
+        canaries: None,
+        limits: Default::default(),
     };
 
     // Create SubgraphDeploymentEntity
@@ -552,12 +558,20 @@ trait EasyOrder {
 impl EasyOrder for EntityQuery {
     fn asc(self, attr: &str) -> Self {
         // The ValueType doesn't matter since relational layouts ignore it
-        self.order(EntityOrder::Ascending(attr.to_owned(), ValueType::String))
+        self.order(EntityOrder::Ascending(
+            attr.to_owned(),
+            ValueType::String,
+            NullsPlacement::Last,
+        ))
     }
 
     fn desc(self, attr: &str) -> Self {
         // The ValueType doesn't matter since relational layouts ignore it
-        self.order(EntityOrder::Descending(attr.to_owned(), ValueType::String))
+        self.order(EntityOrder::Descending(
+            attr.to_owned(),
+            ValueType::String,
+            NullsPlacement::Last,
+        ))
     }
 }
 
@@ -1269,6 +1283,7 @@ fn revert_block_with_dynamic_data_source_operations() {
                 }]
                 .into_iter(),
             ),
+            summaries: vec![],
         }];
         check_events(subscription, expected_events).await
     })
@@ -1291,6 +1306,9 @@ fn entity_changes_are_fired_and_forwarded_to_subscriptions() {
             data_sources: vec![],
             graft: None,
             templates: vec![],
+            confirmations: 0,
+            canaries: None,
+            limits: Default::default(),
         };
 
         // Create SubgraphDeploymentEntity
@@ -1487,6 +1505,51 @@ fn throttle_subscription_throttles() {
     })
 }
 
+#[test]
+fn pinned_query_store_ignores_later_blocks() {
+    run_test(|store| async move {
+        let query_store = store
+            .clone()
+            .query_store(TEST_SUBGRAPH_ID.clone().into(), false)
+            .unwrap();
+        let pinned = query_store.pin_latest_block().unwrap();
+        let head = pinned.block_ptr(TEST_SUBGRAPH_ID.clone()).unwrap();
+
+        let user4 = create_test_entity(
+            "4",
+            USER,
+            "Steve",
+            "nieve@email.com",
+            72 as i32,
+            120.7,
+            false,
+            None,
+        );
+        transact_entity_operations(
+            &store,
+            TEST_SUBGRAPH_ID.clone(),
+            *TEST_BLOCK_3_PTR,
+            vec![user4],
+        )
+        .unwrap();
+
+        // The pinned store still sees the block from before the write
+        let query = user_query().filter(EntityFilter::Equal("name".to_owned(), "Steve".into()));
+        assert_eq!(head, pinned.block_ptr(TEST_SUBGRAPH_ID.clone()).unwrap());
+        assert_eq!(
+            0,
+            pinned.find_query_values(query.clone(), None).unwrap().len()
+        );
+        assert_eq!(0, pinned.count(query.clone()).unwrap());
+
+        assert_eq!(
+            Some(*TEST_BLOCK_3_PTR),
+            query_store.block_ptr(TEST_SUBGRAPH_ID.clone()).unwrap()
+        );
+        assert_eq!(1, query_store.find_query_values(query, None).unwrap().len());
+    })
+}
+
 #[test]
 fn subgraph_schema_types_have_subgraph_id_directive() {
     run_test(|store| async move {
@@ -1570,6 +1633,7 @@ fn handle_large_string_with_index() {
                 ],
                 stopwatch_metrics,
                 Vec::new(),
+                Vec::new(),
             )
             .expect("Failed to insert large text");
 
@@ -1663,16 +1727,22 @@ impl WindowQuery {
 
     fn asc(self, attr: &str) -> Self {
         WindowQuery(
-            self.0
-                .order(EntityOrder::Ascending(attr.to_owned(), ValueType::String)),
+            self.0.order(EntityOrder::Ascending(
+                attr.to_owned(),
+                ValueType::String,
+                NullsPlacement::Last,
+            )),
             self.1,
         )
     }
 
     fn desc(self, attr: &str) -> Self {
         WindowQuery(
-            self.0
-                .order(EntityOrder::Descending(attr.to_owned(), ValueType::String)),
+            self.0.order(EntityOrder::Descending(
+                attr.to_owned(),
+                ValueType::String,
+                NullsPlacement::Last,
+            )),
             self.1,
         )
     }
@@ -1844,6 +1914,43 @@ fn find_at_block() {
     shaqueeena_at_block(7000, "teeko@email.com");
 }
 
+#[test]
+fn get_many_at_block() {
+    fn emails_at_block(block: BlockNumber, expected: Vec<&'static str>) {
+        run_test(move |store| async move {
+            let user_type = EntityType::data(USER.to_owned());
+            let mut ids_for_type = BTreeMap::new();
+            ids_for_type.insert(&user_type, vec!["1", "2", "3"]);
+
+            let mut entities = store
+                .get_many(&TEST_SUBGRAPH_ID, ids_for_type, block)
+                .expect("store.get_many failed")
+                .remove(&user_type)
+                .unwrap_or_default();
+            entities.sort_by_key(|entity| entity.id().unwrap());
+            let emails: Vec<_> = entities
+                .iter()
+                .map(|entity| entity.get("email").unwrap().as_str().unwrap())
+                .collect();
+            assert_eq!(expected, emails);
+        })
+    }
+
+    emails_at_block(0, vec!["tonofjohn@email.com"]);
+    emails_at_block(
+        1,
+        vec![
+            "tonofjohn@email.com",
+            "dinici@email.com",
+            "queensha@email.com",
+        ],
+    );
+    emails_at_block(
+        BLOCK_NUMBER_MAX,
+        vec!["tonofjohn@email.com", "dinici@email.com", "teeko@email.com"],
+    );
+}
+
 #[test]
 fn cleanup_cached_blocks() {
     run_test(|store| async move {
@@ -1855,10 +1962,16 @@ fn cleanup_cached_blocks() {
             .block_store()
             .chain_store(NETWORK_NAME)
             .expect("fake chain store");
-        let cleaned = chain_store
-            .cleanup_cached_blocks(10)
-            .expect("cleanup succeeds");
-        assert_eq!((0, 0), cleaned);
+        for retention in vec![
+            BlockRetention::KeepReferenced,
+            BlockRetention::KeepLast(100),
+            BlockRetention::KeepSince(0),
+        ] {
+            let cleaned = chain_store
+                .cleanup_cached_blocks(10, retention)
+                .expect("cleanup succeeds");
+            assert_eq!((0, 0), cleaned, "{:?}", retention);
+        }
     })
 }
 
@@ -1959,3 +2072,39 @@ fn reorg_tracking() {
         check_state!(store, 5, 3, 2);
     })
 }
+
+#[test]
+fn noop_blocks() {
+    run_test(|store| async move {
+        let block_number = |store: &Arc<DieselStore>| {
+            store
+                .block_ptr(&TEST_SUBGRAPH_ID)
+                .unwrap()
+                .map(|ptr| ptr.number)
+        };
+
+        // A block without changes only moves the block pointer
+        transact_entity_operations(
+            &store,
+            TEST_SUBGRAPH_ID.clone(),
+            TEST_BLOCK_3_PTR.clone(),
+            vec![],
+        )
+        .unwrap();
+        assert_eq!(Some(3), block_number(&store));
+
+        // Processing the same block again is rejected
+        let err = transact_entity_operations(
+            &store,
+            TEST_SUBGRAPH_ID.clone(),
+            TEST_BLOCK_3_PTR.clone(),
+            vec![],
+        )
+        .unwrap_err();
+        match err {
+            StoreError::DuplicateBlockProcessing(_, 3) => (),
+            _ => panic!("unexpected error: {}", err),
+        }
+        assert_eq!(Some(3), block_number(&store));
+    })
+}
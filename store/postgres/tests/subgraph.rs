@@ -1,10 +1,14 @@
 use graph::{
     components::store::StatusStore,
     data::subgraph::schema::MetadataType,
+    data::subgraph::schema::SkippedTrigger,
     data::subgraph::schema::SubgraphError,
     data::subgraph::schema::SubgraphHealth,
+    prelude::Entity,
     prelude::EntityChange,
     prelude::EntityChangeOperation,
+    prelude::EntityKey,
+    prelude::EntityOperation,
     prelude::QueryStoreManager,
     prelude::Schema,
     prelude::StoreEvent,
@@ -12,6 +16,7 @@ use graph::{
     prelude::SubgraphManifest,
     prelude::SubgraphName,
     prelude::SubgraphVersionSwitchingMode,
+    prelude::Value,
     prelude::{CheapClone, NodeId, SubgraphDeploymentId, SubgraphStore as _},
 };
 use graph_store_postgres::layout_for_tests::Connection as Primary;
@@ -131,6 +136,9 @@ fn create_subgraph() {
             data_sources: vec![],
             graft: None,
             templates: vec![],
+            confirmations: 0,
+            canaries: None,
+            limits: Default::default(),
         };
         let deployment = SubgraphDeploymentEntity::new(&manifest, false, None);
         let node_id = NodeId::new("left").unwrap();
@@ -310,6 +318,31 @@ fn create_subgraph() {
         let (current, pending) = subgraph_deployments(&primary);
         assert_eq!(Some(ID2), current.as_deref());
         assert_eq!(Some(ID2), pending.as_deref());
+    });
+
+    // Retrying a deployment whose subgraph version was never created, for
+    // example because the primary was unavailable, finishes the deployment
+    run_test_sequentially(remove_subgraphs, |store, _| async move {
+        const MODE: SubgraphVersionSwitchingMode = SubgraphVersionSwitchingMode::Instant;
+        const ID: &str = "retried";
+
+        let primary = primary_connection();
+        let name = SubgraphName::new(SUBGRAPH_NAME.to_string()).unwrap();
+
+        deploy(store.as_ref(), ID, MODE);
+        store.remove_subgraph(name).unwrap();
+        let (current, pending) = subgraph_deployments(&primary);
+        assert!(current.is_none());
+        assert!(pending.is_none());
+
+        deploy(store.as_ref(), ID, MODE);
+        let (current, pending) = subgraph_deployments(&primary);
+        assert_eq!(Some(ID), current.as_deref());
+        assert!(pending.is_none());
+        assert!(store
+            .assigned_node(&SubgraphDeploymentId::new(ID).unwrap())
+            .unwrap()
+            .is_some());
     })
 }
 
@@ -505,6 +538,245 @@ fn subgraph_error() {
     )
 }
 
+#[test]
+fn skipped_triggers() {
+    test_store::run_test_sequentially(
+        || (),
+        |store, _| async move {
+            let subgraph_id = SubgraphDeploymentId::new("skippedTriggers").unwrap();
+            let replay_id = SubgraphDeploymentId::new("skippedTriggersReplay").unwrap();
+            test_store::create_test_subgraph(&subgraph_id, "type Foo { id: ID! }");
+
+            let error = |block| SubgraphError {
+                subgraph_id: subgraph_id.clone(),
+                message: "test".to_string(),
+                block_ptr: Some(BLOCKS[block]),
+                handler: Some("handleFoo".to_string()),
+                deterministic: true,
+            };
+            let trigger = |block| SkippedTrigger::new(&error(block), None, Some(3)).unwrap();
+
+            transact_skipped_triggers(
+                &store,
+                subgraph_id.clone(),
+                BLOCKS[1],
+                vec![error(1)],
+                vec![trigger(1)],
+            )
+            .unwrap();
+            transact_skipped_triggers(
+                &store,
+                subgraph_id.clone(),
+                BLOCKS[2],
+                vec![error(2)],
+                vec![trigger(2)],
+            )
+            .unwrap();
+            assert_eq!(
+                vec![trigger(1), trigger(2)],
+                store.skipped_triggers(&subgraph_id).unwrap()
+            );
+
+            // Reverting a block removes the triggers skipped in it
+            store
+                .revert_block_operations(subgraph_id.clone(), BLOCKS[1])
+                .unwrap();
+            assert_eq!(
+                vec![trigger(1)],
+                store.skipped_triggers(&subgraph_id).unwrap()
+            );
+
+            // Only triggers after the graft block get marked as replayed
+            assert_eq!(
+                0,
+                store
+                    .mark_skipped_triggers_replayed(&subgraph_id, 1, &replay_id)
+                    .unwrap()
+            );
+            assert_eq!(
+                1,
+                store
+                    .mark_skipped_triggers_replayed(&subgraph_id, 0, &replay_id)
+                    .unwrap()
+            );
+            let triggers = store.skipped_triggers(&subgraph_id).unwrap();
+            assert_eq!(Some(replay_id.clone()), triggers[0].replayed_by);
+
+            test_store::remove_subgraph(&subgraph_id);
+        },
+    )
+}
+
+#[test]
+fn subscriptions_disabled() {
+    test_store::run_test_sequentially(
+        || (),
+        |store, _| async move {
+            let subgraph_id = SubgraphDeploymentId::new("subscriptionsDisabled").unwrap();
+            test_store::create_test_subgraph(&subgraph_id, "type Foo { id: ID! }");
+
+            let state = || store.deployment_state_from_id(subgraph_id.clone()).unwrap();
+            assert!(!state().subscriptions_disabled);
+            assert!(state().check_subscriptions_allowed().is_ok());
+
+            store
+                .set_subscriptions_disabled(&subgraph_id, true)
+                .unwrap();
+            assert!(state().subscriptions_disabled);
+            assert!(state().check_subscriptions_allowed().is_err());
+
+            store
+                .set_subscriptions_disabled(&subgraph_id, false)
+                .unwrap();
+            assert!(!state().subscriptions_disabled);
+
+            let unknown = SubgraphDeploymentId::new("subscriptionsDisabledUnknown").unwrap();
+            assert!(store.set_subscriptions_disabled(&unknown, true).is_err());
+
+            test_store::remove_subgraph(&subgraph_id);
+        },
+    )
+}
+
+#[test]
+fn deployment_context() {
+    test_store::run_test_sequentially(
+        || (),
+        |store, _| async move {
+            let subgraph_id = SubgraphDeploymentId::new("deploymentContext").unwrap();
+            test_store::create_test_subgraph(&subgraph_id, "type Foo { id: ID! }");
+
+            let context = || store.deployment_context(&subgraph_id).unwrap();
+            assert!(context().is_empty());
+
+            store
+                .set_deployment_context(&subgraph_id, "feeTo".to_owned(), Some("0x01".to_owned()))
+                .unwrap();
+            store
+                .set_deployment_context(&subgraph_id, "env".to_owned(), Some("test".to_owned()))
+                .unwrap();
+            assert_eq!(Some(&"0x01".to_owned()), context().get("feeTo"));
+            assert_eq!(2, context().len());
+
+            store
+                .set_deployment_context(&subgraph_id, "feeTo".to_owned(), None)
+                .unwrap();
+            assert_eq!(None, context().get("feeTo"));
+            assert_eq!(Some(&"test".to_owned()), context().get("env"));
+
+            let unknown = SubgraphDeploymentId::new("deploymentContextUnknown").unwrap();
+            assert!(store.deployment_context(&unknown).is_err());
+
+            test_store::remove_subgraph(&subgraph_id);
+        },
+    )
+}
+
+#[test]
+fn change_log() {
+    test_store::run_test_sequentially(
+        || (),
+        |store, _| async move {
+            let subgraph_id = SubgraphDeploymentId::new("changeLog").unwrap();
+            test_store::create_test_subgraph(&subgraph_id, SUBGRAPH_GQL);
+
+            let key =
+                |id: &str| EntityKey::data(subgraph_id.clone(), "User".to_owned(), id.to_owned());
+            let user = |id: &str| EntityOperation::Set {
+                key: key(id),
+                data: Entity::from(vec![("id", Value::from(id)), ("name", Value::from("Ann"))]),
+            };
+            let changes = |consumer: &str| -> HashSet<(i64, String, EntityChangeOperation)> {
+                store
+                    .entity_changes(&subgraph_id, consumer, 100)
+                    .unwrap()
+                    .into_iter()
+                    .map(|logged| {
+                        (
+                            logged.block,
+                            logged.change.entity_id,
+                            logged.change.operation,
+                        )
+                    })
+                    .collect()
+            };
+            let expected = |changes: Vec<(i64, &str, EntityChangeOperation)>| {
+                changes
+                    .into_iter()
+                    .map(|(block, id, op)| (block, id.to_owned(), op))
+                    .collect::<HashSet<_>>()
+            };
+
+            // Without consumers, nothing gets logged
+            transact_entity_operations(&store, subgraph_id.clone(), BLOCKS[1], vec![user("1")])
+                .unwrap();
+            assert_eq!(
+                0,
+                store
+                    .register_change_log_consumer(&subgraph_id, "search")
+                    .unwrap()
+            );
+            assert!(changes("search").is_empty());
+
+            transact_entity_operations(
+                &store,
+                subgraph_id.clone(),
+                BLOCKS[2],
+                vec![user("2"), EntityOperation::Remove { key: key("1") }],
+            )
+            .unwrap();
+            let block2 = expected(vec![
+                (2, "2", EntityChangeOperation::Set),
+                (2, "1", EntityChangeOperation::Removed),
+            ]);
+            assert_eq!(block2, changes("search"));
+            // Reading changes does not acknowledge them
+            assert_eq!(block2, changes("search"));
+
+            // A new consumer starts at the end of the log
+            store
+                .register_change_log_consumer(&subgraph_id, "cache")
+                .unwrap();
+            assert!(changes("cache").is_empty());
+
+            // Reverts are logged at the block the deployment reverts to
+            store
+                .revert_block_operations(subgraph_id.clone(), BLOCKS[1])
+                .unwrap();
+            let revert = expected(vec![
+                (1, "2", EntityChangeOperation::Removed),
+                (1, "1", EntityChangeOperation::Set),
+            ]);
+            assert_eq!(revert, changes("cache"));
+            assert_eq!(4, changes("search").len());
+
+            // Acknowledging changes hides them from that consumer only
+            let logged = store.entity_changes(&subgraph_id, "search", 2).unwrap();
+            assert_eq!(2, logged.len());
+            store
+                .ack_entity_changes(&subgraph_id, "search", logged[1].seq)
+                .unwrap();
+            assert_eq!(revert, changes("search"));
+            assert_eq!(revert, changes("cache"));
+
+            assert!(store.entity_changes(&subgraph_id, "unknown", 10).is_err());
+            assert!(store
+                .ack_entity_changes(&subgraph_id, "unknown", 1)
+                .is_err());
+
+            store
+                .remove_change_log_consumer(&subgraph_id, "search")
+                .unwrap();
+            store
+                .remove_change_log_consumer(&subgraph_id, "cache")
+                .unwrap();
+            assert!(store.entity_changes(&subgraph_id, "cache", 10).is_err());
+
+            test_store::remove_subgraph(&subgraph_id);
+        },
+    )
+}
+
 #[test]
 fn fatal_vs_non_fatal() {
     fn setup() -> SubgraphDeploymentId {
@@ -540,3 +812,41 @@ fn fatal_vs_non_fatal() {
             .unwrap());
     })
 }
+
+#[test]
+fn named_locks() {
+    test_store::run_test_sequentially(
+        || (),
+        |store, _| async move {
+            let store = store.store();
+            let ttl = std::time::Duration::from_secs(60);
+
+            store.release_lock("pruner", "tool1").unwrap();
+            store.release_lock("pruner", "tool2").unwrap();
+
+            assert!(store.acquire_lock("pruner", "tool1", ttl).unwrap());
+            // Acquiring again extends the lock
+            assert!(store.acquire_lock("pruner", "tool1", ttl).unwrap());
+            assert!(!store.acquire_lock("pruner", "tool2", ttl).unwrap());
+            let holders: Vec<_> = store
+                .named_locks()
+                .unwrap()
+                .into_iter()
+                .filter(|lock| lock.name == "pruner")
+                .map(|lock| lock.holder)
+                .collect();
+            assert_eq!(vec!["tool1".to_string()], holders);
+
+            // Only the holder can release the lock
+            assert!(!store.release_lock("pruner", "tool2").unwrap());
+            assert!(store.release_lock("pruner", "tool1").unwrap());
+            assert!(store.acquire_lock("pruner", "tool2", ttl).unwrap());
+
+            // An expired lock can be taken over by somebody else
+            let expired = std::time::Duration::from_millis(0);
+            assert!(store.acquire_lock("pruner", "tool2", expired).unwrap());
+            assert!(store.acquire_lock("pruner", "tool1", ttl).unwrap());
+            assert!(store.release_lock("pruner", "tool1").unwrap());
+        },
+    )
+}
@@ -1,9 +1,13 @@
-#[cfg(debug_assertions)]
+//! A store for tests that need a database, together with helpers to set up
+//! fake chains and deployments in it. The store connects to the database
+//! from `THEGRAPH_STORE_POSTGRES_DIESEL_URL` or uses the configuration in
+//! `GRAPH_NODE_TEST_CONFIG`. This crate turns on the
+//! `testing` feature of `graph-store-postgres`, `graph-graphql` and
+//! `graph-node`, which makes the test support in those crates available
+//! in release builds, too, so that crates outside of this repository can
+//! use it for their own tests
 extern crate diesel;
 
-#[cfg(debug_assertions)]
 pub mod block_store;
-#[cfg(debug_assertions)]
 pub mod store;
-#[cfg(debug_assertions)]
 pub use crate::store::*;
@@ -2,7 +2,7 @@ use diesel::{self, PgConnection};
 use graph::data::graphql::effort::LoadManager;
 use graph::data::query::QueryResults;
 use graph::data::query::QueryTarget;
-use graph::data::subgraph::schema::SubgraphError;
+use graph::data::subgraph::schema::{SkippedTrigger, SubgraphError};
 use graph::log;
 use graph::prelude::{QueryStoreManager as _, SubgraphStore as _, *};
 use graph::{components::store::EntityType, prelude::NodeId};
@@ -154,6 +154,9 @@ fn create_subgraph(
         data_sources: vec![],
         graft: None,
         templates: vec![],
+        confirmations: 0,
+        canaries: None,
+        limits: Default::default(),
     };
 
     let deployment = SubgraphDeploymentEntity::new(&manifest, false, None).graft(base);
@@ -203,6 +206,16 @@ pub fn transact_errors(
     subgraph_id: SubgraphDeploymentId,
     block_ptr_to: EthereumBlockPointer,
     errs: Vec<SubgraphError>,
+) -> Result<(), StoreError> {
+    transact_skipped_triggers(store, subgraph_id, block_ptr_to, errs, Vec::new())
+}
+
+pub fn transact_skipped_triggers(
+    store: &Arc<Store>,
+    subgraph_id: SubgraphDeploymentId,
+    block_ptr_to: EthereumBlockPointer,
+    errs: Vec<SubgraphError>,
+    skipped_triggers: Vec<SkippedTrigger>,
 ) -> Result<(), StoreError> {
     let metrics_registry = Arc::new(MockMetricsRegistry::new());
     let stopwatch_metrics = StopwatchMetrics::new(
@@ -216,6 +229,7 @@ pub fn transact_errors(
         Vec::new(),
         stopwatch_metrics,
         errs,
+        skipped_triggers,
     )
 }
 
@@ -244,6 +258,7 @@ pub fn transact_entity_operations(
         mods,
         stopwatch_metrics,
         Vec::new(),
+        Vec::new(),
     )
 }
 